@@ -9,9 +9,11 @@ function setup() {
     cargo build --release
 
     # Generate 10 years worth of data for an extremely active user who does one
-    # entry every minute.
+    # entry every minute. The seed and start date are fixed so that every run
+    # benchmarks exactly the same file.
     rm /tmp/out
-    target/release/hmmdg --path /tmp/out --num-days 3650 --entries-per-day 1440
+    target/release/hmmdg --path /tmp/out --num-days 3650 --entries-per-day 1440 \
+      --seed 1 --start 2015-01-01T00:00:00+00:00
 }
 
 setup
@@ -32,3 +34,7 @@ hyperfine \
   'target/release/hmmq --path /tmp/out --start 2019 --end 2020 --count' \
   'target/release/hmmq --path /tmp/out --start 2019-01 --end 2019-06 --contains lorum' \
   'target/release/hmmq --path /tmp/out --start 2019 --end 2020 --regex "(lorum|ipsum)"' \
+  'target/release/hmmq --path /tmp/out --mmap --last 10' \
+  'target/release/hmmq --path /tmp/out --mmap --end 2019 --last 10' \
+  'target/release/hmmq --path /tmp/out --mmap --start 2019-01 --end 2019-02' \
+  'target/release/hmmq --path /tmp/out --mmap --start 2019 --end 2020 --count' \
@@ -0,0 +1,19 @@
+// Generates the header for the C interface in src/ffi.rs in to OUT_DIR when
+// building with the ffi feature. The copy in include/hmm.h is checked against
+// it by the ffi tests, rather than being written here, so that building never
+// changes the source tree.
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out = std::env::var("OUT_DIR").unwrap();
+        cbindgen::generate(&dir)
+            .expect("couldn't generate hmm.h")
+            .write_to_file(std::path::Path::new(&out).join("hmm.h"));
+    }
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,77 @@
+/* Generated by cbindgen from src/ffi.rs, don't edit this file by hand. */
+
+#ifndef HMM_H
+#define HMM_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Appends an entry with `message` to the .hmm file at `path`, creating the
+ * file if it doesn't exist. If hmm's default config file has an
+ * encrypt_command the message is encrypted with it, as hmm would, but none of
+ * the hooks in it are run.
+ *
+ * # Safety
+ *
+ * `path` and `message` must be NUL-terminated strings.
+ */
+int hmm_append(const char *path, const char *message);
+
+/**
+ * Returns the entries in the .hmm file at `path` as a JSON array of objects
+ * with "datetime" and "message" fields, and "metadata" if an entry has any,
+ * the same as hmmserve's /entries. `start`, `end` and `contains` work like
+ * hmmq's --start, --end and --contains, and any of them can be NULL. As with
+ * hmmq, encrypted messages are decrypted before they're matched if hmm's
+ * default config file has a decrypt_command. Returns NULL if there's an
+ * error.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string, and `start`, `end` and `contains`
+ * must each be NULL or a NUL-terminated string.
+ */
+char *hmm_query_json(const char *path, const char *start, const char *end, const char *contains);
+
+/**
+ * Counts the entries in the .hmm file at `path` between `start`, inclusive,
+ * and `end`, exclusive, either of which can be NULL, and stores the count in
+ * `count`.
+ *
+ * # Safety
+ *
+ * `path` must be a NUL-terminated string, `start` and `end` must each be NULL
+ * or a NUL-terminated string, and `count` must point to a uint64_t.
+ */
+int hmm_count(const char *path, const char *start, const char *end, uint64_t *count);
+
+/**
+ * Returns a description of the last error on this thread, or NULL if there
+ * hasn't been one. The string belongs to the library and is only valid until
+ * the next call that fails.
+ */
+const char *hmm_last_error(void);
+
+/**
+ * Frees a string returned by the library. Does nothing if `s` is NULL.
+ *
+ * # Safety
+ *
+ * `s` must be NULL or a string returned by the library that hasn't already
+ * been freed.
+ */
+void hmm_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* HMM_H */
@@ -1,28 +1,126 @@
 use chrono::prelude::*;
-use fs2::FileExt;
-use hmmcli::{entries::Entries, entry::Entry, Result};
+#[cfg(feature = "object-storage")]
+use hmmcli::storage::object::ObjectStore;
+use hmmcli::{
+    config::{expand_tilde, Config},
+    crypt, date, draft,
+    entries::Entries,
+    entry::{Entry, Metadata},
+    entry_template,
+    error::{Error, ErrorFormat},
+    front_matter::{self, FrontMatter},
+    hook,
+    journal::{self, AppendOptions, SkewPolicy},
+    location, lock, mood,
+    remote::Remote,
+    storage::Storage,
+    text, track, Result,
+};
 use human_panic::setup_panic;
+use std::env;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{exit, Command};
+use std::time::Duration;
 use structopt::StructOpt;
 use tempfile::NamedTempFile;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "hmm", about = "Command line note taking")]
 struct Opt {
-    /// Path to your hmm file, defaults to your default configuration directory,
-    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
-    #[structopt(long = "path")]
-    path: Option<PathBuf>,
+    #[structopt(flatten)]
+    write: WriteOpt,
 
     /// If you call hmm with no arguments, it will attempt to open an editor to
-    /// compose an entry. It will use this value, or the EDITOR environment
-    /// variable.
-    #[structopt(long = "editor", env)]
+    /// compose an entry. It will use this value, or the HMM_EDITOR environment
+    /// variable, or the EDITOR environment variable, in that order.
+    #[structopt(long = "editor", env = "HMM_EDITOR")]
     editor: Option<String>,
 
+    /// A file to fill your editor with when composing an entry, overriding
+    /// the template setting in your config file. If it's a directory, one of
+    /// the files in it is picked each day. Lines starting with # are removed
+    /// from the entry.
+    #[structopt(long = "template", env = "HMM_TEMPLATE")]
+    template: Option<PathBuf>,
+
+    /// Write the entry even if you leave your editor without writing anything
+    /// in it. By default nothing is written.
+    #[structopt(long = "allow-empty")]
+    allow_empty: bool,
+
+    /// Write the contents of this file as the entry, instead of a message or
+    /// composing one in your editor.
+    #[structopt(long = "file", conflicts_with = "message")]
+    file: Option<PathBuf>,
+
+    /// Write what's on the clipboard as the entry. A message given as well is
+    /// put before it, which is handy for saying why you saved a link.
+    #[structopt(long = "clipboard", conflicts_with = "file")]
+    clipboard: bool,
+
+    /// Write each line read from stdin as its own entry, as soon as it's read,
+    /// for writing the output of other programs to your hmm file.
+    #[structopt(long = "stdin-lines", conflicts_with_all = &["message", "file", "clipboard"])]
+    stdin_lines: bool,
+
+    /// With --stdin-lines, use the timestamp at the start of each line, like
+    /// "2024-01-01T12:00:00Z" or "2024-01-01 12:00:00", as the time of its
+    /// entry. Lines without one are written at the time they're read.
+    #[structopt(long = "parse-ts", requires = "stdin-lines")]
+    parse_ts: bool,
+
+    /// How you're feeling, from 1 (awful) to 5 (great), stored alongside the
+    /// entry. hmms mood charts it over time.
+    #[structopt(long = "mood", possible_values = &["1", "2", "3", "4", "5"], conflicts_with = "stdin-lines")]
+    mood: Option<u8>,
+
+    /// Delete the file given with --file once it's been written.
+    #[structopt(long = "delete", requires = "file", conflicts_with = "archive")]
+    delete: bool,
+
+    /// Move the file given with --file in to this directory once it's been
+    /// written.
+    #[structopt(long = "archive", requires = "file")]
+    archive: Option<PathBuf>,
+
+    /// Start tracking time on a project, given as a tag like "garden" or
+    /// "work/hmm", stopping the one that's running. A message is written
+    /// along with it as a note.
+    #[structopt(long = "start", conflicts_with_all = &["stop", "file", "clipboard", "stdin-lines", "mood", "allow-empty"])]
+    start: Option<String>,
+
+    /// Stop tracking time on the project that's running. A message is written
+    /// along with it as a note.
+    #[structopt(long = "stop", conflicts_with_all = &["file", "clipboard", "stdin-lines", "mood", "allow-empty"])]
+    stop: bool,
+
+    /// Write an entry from one of the entry_templates in your config file,
+    /// filled in from the flags after the template's name, like -y "..." for
+    /// {y} or --blockers "..." for {blockers}. Everything after the template's
+    /// name is taken as its fields, so hmm's own flags go before --new.
+    #[structopt(long = "new", value_name = "template", min_values = 1, allow_hyphen_values = true, conflicts_with_all = &["message", "start", "stop", "file", "clipboard", "stdin-lines", "mood", "allow-empty"])]
+    new: Vec<String>,
+
+    /// List the drafts kept when entries couldn't be written, or write the
+    /// ones named after it. Drafts are deleted once they've been written.
+    #[structopt(long = "recover", value_name = "draft", conflicts_with_all = &["message", "start", "stop", "new", "file", "clipboard", "stdin-lines", "mood", "allow-empty"])]
+    recover: Option<Vec<String>>,
+
+    /// With --recover, write every draft, oldest first.
+    #[structopt(long = "all", requires = "recover")]
+    all: bool,
+
+    /// With --recover, delete the drafts instead of writing them.
+    #[structopt(long = "discard", requires = "recover")]
+    discard: bool,
+
+    /// Show a random entry from your hmm file before writing a new one, to
+    /// give you something to write about.
+    #[structopt(long = "prompt", conflicts_with_all = &["start", "stop", "new", "recover", "stdin-lines"])]
+    prompt: bool,
+
     /// Message to add to your hmm journal. Feel free to use quotes or not, but
     /// be wary of how your shell interprets strings. For example, # is often the
     /// beginning of a comment, so anything after it is likely to be ignored.
@@ -31,81 +129,777 @@ struct Opt {
     message: Vec<String>,
 }
 
+/// Flags for how entries are written.
+#[derive(Debug, StructOpt)]
+struct WriteOpt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows. A path like
+    /// ssh://host/~/.hmm writes to a journal on another machine by running hmm
+    /// there over SSH.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// How many seconds to wait for another hmm process to finish writing to
+    /// your hmm file before giving up.
+    #[structopt(
+        long = "lock-timeout",
+        env = "HMM_LOCK_TIMEOUT",
+        default_value = "10",
+        parse(try_from_str = lock::parse_timeout)
+    )]
+    lock_timeout: Duration,
+
+    /// Don't lock your hmm file while writing to it. Only use this if your hmm
+    /// file lives on a filesystem that doesn't support locking, as concurrent
+    /// writes could corrupt your file.
+    #[structopt(long = "no-lock")]
+    no_lock: bool,
+
+    /// What to do if the last entry in your hmm file is later than the current
+    /// time: "error" refuses to write, "wait" waits a few seconds for the
+    /// clock to catch up, and "clamp" writes the entry a nanosecond after the
+    /// last one. Overrides the skew setting in your config file, defaults to
+    /// "error".
+    #[structopt(long = "skew", env = "HMM_SKEW", possible_values = &["error", "wait", "clamp"])]
+    skew: Option<SkewPolicy>,
+
+    /// Don't run the pre_append and post_append hooks, the webhook or the
+    /// validate_command from your config file.
+    #[structopt(long = "no-hooks")]
+    no_hooks: bool,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+impl WriteOpt {
+    fn path(&self) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"))
+    }
+}
+
 fn main() {
     setup_panic!();
 
-    if let Err(e) = app(Opt::from_args()) {
-        eprintln!("{}", e);
-        exit(1);
+    let opt = Opt::from_args();
+    let errors = opt.write.errors;
+    if let Err(e) = app(opt) {
+        errors.print(&e);
+        exit(e.exit_code());
     }
 }
 
 fn app(opt: Opt) -> Result<()> {
-    let path = opt
-        .path
-        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
-
-    let mut fopts = std::fs::OpenOptions::new();
-    fopts.create(true);
-    fopts.read(true);
-    fopts.write(true);
-    fopts.append(true);
-
-    let mut f = match fopts.open(&path) {
-        Ok(f) => f,
-        Err(e) => {
-            return Err(format!(
-                "Couldn't open or create file at {}: {}",
-                path.to_string_lossy(),
-                e
-            )
-            .into())
+    if let Some(ref project) = opt.start {
+        let msg = track::start_message(project, &opt.message.join(" "))?;
+        return write_one(&opt.write, &msg);
+    }
+    if opt.stop {
+        return write_one(&opt.write, &track::stop_message(&opt.message.join(" ")));
+    }
+    if let Some((template, values)) = opt.new.split_first() {
+        return new_entry(&opt.write, template, values);
+    }
+    if let Some(ref drafts) = opt.recover {
+        return recover(&opt, drafts);
+    }
+
+    let config = Config::load_or_default(opt.write.config.as_deref())?;
+    let path = opt.write.path();
+    let validate_command = config
+        .validate_command
+        .as_deref()
+        .filter(|_| !opt.write.no_hooks);
+
+    let template = opt
+        .template
+        .clone()
+        .or_else(|| config.template.clone())
+        .map(|path| Template {
+            path: expand_tilde(&path),
+            comment_prefix: config
+                .comment_prefix
+                .clone()
+                .unwrap_or_else(|| "#".to_owned()),
+        });
+
+    if let Some(remote) = Remote::parse(&path)? {
+        if opt.prompt {
+            remote.run("hmmq", &["--random".to_owned()])?;
+            wait_for_enter()?;
+        }
+        if opt.stdin_lines {
+            let mut args = vec!["--stdin-lines".to_owned()];
+            if opt.parse_ts {
+                args.push("--parse-ts".to_owned());
+            }
+            match append_remote(&remote, &opt.write, &args)? {
+                0 => return Ok(()),
+                code => exit(code),
+            }
+        }
+
+        let (msg, front_matter) = message(&opt, template.as_ref(), validate_command, &path)?;
+        let mut metadata = opt.metadata();
+        metadata.extend(front_matter.metadata);
+        let mood = metadata.remove(mood::FIELD);
+        if front_matter.date.is_some() || !metadata.is_empty() {
+            keep_draft(&msg);
+            return Err(Error::InvalidArgument(format!(
+                "{} is on another machine, so only tags and mood can be set in front matter",
+                path.display()
+            )));
+        }
+        let mut args = Vec::new();
+        if let Some(mood) = mood {
+            args.extend(["--mood".to_owned(), mood.to_string()]);
+        }
+        args.extend(["--".to_owned(), msg.clone()]);
+        let code = append_remote(&remote, &opt.write, &args);
+        // There's no telling whether the entry was written when hmm fails on
+        // the other machine, so the draft is kept just in case.
+        if !matches!(code, Ok(0)) && opt.composed() {
+            keep_draft(&msg);
         }
+        match code? {
+            0 => return done_with_file(&opt),
+            // Errors on the other machine have already been printed there.
+            code => exit(code),
+        }
+    }
+
+    let journal = Journal::open(&path)?;
+    if opt.stdin_lines {
+        return write_lines(&opt, &config, &path, &journal);
+    }
+    if opt.prompt {
+        show_inspiration(&journal, config.decrypt_command.as_deref())?;
+    }
+    let (msg, front_matter) = message(&opt, template.as_ref(), validate_command, &path)?;
+    let mut metadata = opt.metadata();
+    metadata.extend(front_matter.metadata);
+
+    let result = write(
+        &opt.write,
+        &config,
+        &path,
+        &journal,
+        &msg,
+        &metadata,
+        front_matter.date,
+    );
+    // Entries composed in an editor can be long, so rather than losing one
+    // that couldn't be written it's saved for hmm --recover. A failing
+    // post_append hook or webhook means it was written.
+    if let Err(e) = &result {
+        if opt.composed() && !matches!(e, Error::HookFailed(_) | Error::WebhookFailed(_)) {
+            keep_draft(&msg);
+        }
+    }
+    result?;
+    done_with_file(&opt)
+}
+
+/// Deletes or archives the file given with --file, once it's been written.
+fn done_with_file(opt: &Opt) -> Result<()> {
+    let file = match &opt.file {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    if opt.delete {
+        std::fs::remove_file(file)?;
+    }
+
+    if let Some(dir) = &opt.archive {
+        std::fs::create_dir_all(dir)?;
+        let name = file
+            .file_name()
+            .ok_or_else(|| Error::InvalidArgument(format!("{} isn't a file", file.display())))?;
+        let stem = Path::new(name)
+            .file_stem()
+            .unwrap_or(name)
+            .to_string_lossy();
+        let ext = Path::new(name)
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+
+        // Files that have already been archived are never overwritten.
+        let mut to = dir.join(name);
+        let mut n = 1;
+        while to.exists() {
+            to = dir.join(format!("{}-{}{}", stem, n, ext));
+            n += 1;
+        }
+
+        // Renaming doesn't work across filesystems.
+        if std::fs::rename(file, &to).is_err() {
+            std::fs::copy(file, &to).map_err(|source| Error::CreateFile {
+                path: to.clone(),
+                source,
+            })?;
+            std::fs::remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `msg` to `journal`, running hooks and encrypting it as set up in
+/// `config`. It's written at the current time unless it's given one.
+/// Metadata is stored as it is, even when the message is encrypted.
+fn write(
+    opt: &WriteOpt,
+    config: &Config,
+    path: &Path,
+    journal: &Journal,
+    msg: &str,
+    metadata: &Metadata,
+    at: Option<DateTime<FixedOffset>>,
+) -> Result<()> {
+    let append_opts = AppendOptions {
+        lock: !opt.no_lock,
+        lock_timeout: opt.lock_timeout,
+        skew: opt.skew.or(config.skew).unwrap_or_default(),
+    };
+
+    let (pre_append, post_append, webhook) = match opt.no_hooks {
+        true => (None, None, None),
+        false => (
+            config.pre_append.as_ref(),
+            config.post_append.as_ref(),
+            config.webhook.as_ref(),
+        ),
+    };
+
+    if let Some(command) = pre_append {
+        hook::pre_append(command, path, msg)?;
+    }
+
+    // Where you are is only worth storing for entries written now, and not
+    // being able to find out isn't worth losing the entry over.
+    let mut metadata = metadata.clone();
+    if let Some(command) = &config.location_command {
+        if at.is_none() && !metadata.contains_key(location::FIELD) {
+            match location::locate(command) {
+                Ok(here) => {
+                    metadata.insert(location::FIELD.to_owned(), here.to_json());
+                }
+                Err(e) => opt.errors.warn(command, &e),
+            }
+        }
+    }
+
+    let (stored, encrypted) = crypt::encrypt_with(config.encrypt_command.as_deref(), msg)?;
+    let mut stored_metadata = metadata.clone();
+    stored_metadata.extend(encrypted);
+    let datetime = match at {
+        Some(at) => {
+            let entry = Entry::new(at, stored).with_metadata(stored_metadata);
+            journal.insert(entry, &append_opts)?;
+            at
+        }
+        None => *journal
+            .append(&stored, &stored_metadata, &append_opts)?
+            .datetime(),
     };
 
-    let mut msg = itertools::join(opt.message, " ");
-    if msg.is_empty() {
-        if opt.editor.is_none() {
-            return Err("Unable to find an editor, set your EDITOR environment variable".into());
+    // Hooks always see the message in plain text.
+    let entry = Entry::new(datetime, msg.to_owned()).with_metadata(metadata);
+    if let Some(command) = post_append {
+        hook::post_append(command, path, &entry)?;
+    }
+    if let Some(url) = webhook {
+        hook::webhook(url, path, &entry)?;
+    }
+    Ok(())
+}
+
+/// Writes each line of stdin as its own entry as soon as it's read, so that
+/// hmm can be at the end of a pipe from something that logs as it goes. Blank
+/// lines are skipped, and whitespace around each line is trimmed.
+fn write_lines(opt: &Opt, config: &Config, path: &Path, journal: &Journal) -> Result<()> {
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let (at, msg) = match opt.parse_ts {
+            true => match date::split_timestamp(&line) {
+                Some((at, msg)) => (Some(at), msg),
+                None => (None, line.as_str()),
+            },
+            false => (None, line.as_str()),
+        };
+        let msg = msg.trim();
+        if !msg.is_empty() {
+            write(&opt.write, config, path, journal, msg, &Metadata::new(), at)?;
         }
-        msg = compose_entry(&opt.editor.unwrap())?;
     }
+    Ok(())
+}
 
-    f.lock_exclusive()?;
+fn keep_draft(msg: &str) {
+    let saved = draft::dir()
+        .ok_or_else(|| Error::InvalidArgument("couldn't find where to keep drafts".to_owned()))
+        .and_then(|dir| draft::save(&dir, msg));
+    match saved {
+        Ok(draft) => eprintln!(
+            "your entry was saved to {}, run `hmm --recover {}` to try writing it again",
+            draft.path().display(),
+            draft.name()
+        ),
+        Err(e) => eprintln!("your entry couldn't be saved as a draft: {}", e),
+    }
+}
 
-    let mut entries = Entries::new(BufReader::new(&mut f));
+/// Writes a single entry that hmm made up itself, rather than one written by
+/// hand, so there's nothing to keep as a draft if it can't be written.
+fn write_one(opt: &WriteOpt, msg: &str) -> Result<()> {
+    let config = Config::load_or_default(opt.config.as_deref())?;
+    let path = opt.path();
+    if let Some(remote) = Remote::parse(&path)? {
+        return match append_remote(&remote, opt, &["--".to_owned(), msg.to_owned()])? {
+            0 => Ok(()),
+            code => exit(code),
+        };
+    }
+    write(
+        opt,
+        &config,
+        &path,
+        &Journal::open(&path)?,
+        msg,
+        &Metadata::new(),
+        None,
+    )
+}
 
-    if entries.len()? > 0 {
-        entries.seek_to_end()?;
-        let entry = entries.prev_entry()?.unwrap();
+/// Writes an entry from one of the entry templates in the config file, filled
+/// in from the command line.
+fn new_entry(opt: &WriteOpt, name: &str, values: &[String]) -> Result<()> {
+    let config = Config::load_or_default(opt.config.as_deref())?;
+    let template = match config.entry_templates.get(name) {
+        Some(template) => template,
+        None if config.entry_templates.is_empty() => {
+            return Err(Error::InvalidArgument(format!(
+            "there's no entry template called {}, add one to entry_templates in your config file",
+            name
+        )))
+        }
+        None => {
+            let names: Vec<&str> = config.entry_templates.keys().map(String::as_str).collect();
+            return Err(Error::InvalidArgument(format!(
+                "there's no entry template called {}, there's {}",
+                name,
+                names.join(", ")
+            )));
+        }
+    };
 
-        if entry.datetime() > &Utc::now() {
-            return Err("clock skew detected, writing an entry now would break the ordering of your hmm file, please try again in a moment".into());
+    let values = entry_template::parse_values(values)?;
+    let msg = template.fill(&values).map_err(|e| match e {
+        Error::InvalidArgument(reason) => {
+            Error::InvalidArgument(reason.replacen("the template", name, 1))
         }
+        e => e,
+    })?;
+    write_one(opt, &msg)
+}
 
-        entries.seek_to_end()?;
+/// Lists drafts saved when entries couldn't be written, or writes them.
+/// Drafts are deleted once they've been written.
+fn recover(opt: &Opt, names: &[String]) -> Result<()> {
+    let dir = draft::dir()
+        .ok_or_else(|| Error::InvalidArgument("couldn't find where drafts are kept".to_owned()))?;
+    let mut drafts = draft::list(&dir)?;
 
-        // Because the seek_to_end function seeks in such a way that reading the previous
-        // entry reads the last entry, if we attempted to write to the underlying file at
-        // this point there would be a single null byte in between the last entry and the
-        // new entry. For this reason, we need to read the previous entry to make sure we
-        // aren't leaving any gaps.
-        entries.prev_entry()?;
+    if opt.all && !names.is_empty() {
+        return Err(Error::InvalidArgument(
+            "--all writes every draft, so it can't be given the names of drafts too".to_owned(),
+        ));
     }
+    if !opt.all {
+        if names.is_empty() {
+            for draft in drafts {
+                let first_line = draft.message().trim().lines().next().unwrap_or_default();
+                println!("{}  {}", draft.name(), first_line);
+            }
+            return Ok(());
+        }
+
+        let mut chosen = Vec::new();
+        for name in names {
+            let i = drafts
+                .iter()
+                .position(|d| d.name() == name || d.path() == Path::new(name))
+                .ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "there's no draft called \"{}\", run hmm --recover to see them",
+                        name
+                    ))
+                })?;
+            chosen.push(drafts.remove(i));
+        }
+        drafts = chosen;
+    }
+
+    if opt.discard {
+        for draft in drafts {
+            draft.remove()?;
+        }
+        return Ok(());
+    }
+
+    let config = Config::load_or_default(opt.write.config.as_deref())?;
+    let path = opt.write.path();
+    let remote = Remote::parse(&path)?;
+    let journal = match remote {
+        Some(_) => None,
+        None => Some(Journal::open(&path)?),
+    };
 
-    let res = Entry::with_message(&msg).write(BufWriter::new(&f));
-    f.unlock()?;
-    res
+    for draft in drafts {
+        match (&remote, &journal) {
+            (Some(remote), _) => match append_remote(
+                remote,
+                &opt.write,
+                &["--".to_owned(), draft.message().to_owned()],
+            )? {
+                0 => {}
+                code => exit(code),
+            },
+            (None, Some(journal)) => write(
+                &opt.write,
+                &config,
+                &path,
+                journal,
+                draft.message(),
+                &Metadata::new(),
+                None,
+            )?,
+            (None, None) => unreachable!(),
+        }
+        draft.remove()?;
+        eprintln!("wrote {} to {}", draft.name(), path.display());
+    }
+    Ok(())
 }
 
-fn compose_entry(editor: &str) -> Result<String> {
-    let f = NamedTempFile::new()?;
+/// Shows a random entry from `journal`, to give you something to write about.
+fn show_inspiration(journal: &Journal, decrypt_command: Option<&str>) -> Result<()> {
+    let entry = match journal.random_entry()? {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    let entry = match decrypt_command {
+        Some(command) => crypt::decrypt_entry(command, &entry)?,
+        None => entry,
+    };
+
+    println!(
+        "On {}, you wrote:\n\n{}\n",
+        entry.datetime().format("%A %-d %B %Y"),
+        textwrap::indent(entry.message().trim_end(), "    ")
+    );
+    wait_for_enter()
+}
+
+// Terminal editors take over the screen, so give people a chance to read the
+// entry they've been shown before opening one.
+fn wait_for_enter() -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    eprint!("Press enter to start writing...");
+    std::io::stdin().read_line(&mut String::new())?;
+    Ok(())
+}
+
+/// Where an entry is being written to.
+enum Journal {
+    // The file is opened again for everything that's done with it, as
+    // inserting an entry before the last one, here or in another process,
+    // replaces the file with a new copy.
+    File(PathBuf),
+    #[cfg(feature = "object-storage")]
+    Object(ObjectStore),
+}
+
+impl Journal {
+    /// Opens the journal at `path`, creating it if it's a file that doesn't
+    /// exist yet, so that a journal that can't be written to is found out
+    /// before there's an entry to write.
+    fn open(path: &Path) -> Result<Journal> {
+        match Storage::parse(path)? {
+            Storage::File(path) => {
+                open_file(&path)?;
+                Ok(Journal::File(path))
+            }
+            #[cfg(feature = "object-storage")]
+            Storage::Object(store) => Ok(Journal::Object(store)),
+        }
+    }
+
+    fn append(&self, message: &str, metadata: &Metadata, opts: &AppendOptions) -> Result<Entry> {
+        match self {
+            Journal::File(path) => {
+                journal::append_with_metadata(&open_file(path)?, message, metadata, opts)
+            }
+            #[cfg(feature = "object-storage")]
+            Journal::Object(store) => store.append_with_metadata(message, metadata, opts),
+        }
+    }
+
+    /// Writes `entry` where it belongs among the others by its timestamp, even
+    /// if there's one with the same time and message already.
+    fn insert(&self, entry: Entry, opts: &AppendOptions) -> Result<usize> {
+        match self {
+            Journal::File(path) => {
+                journal::insert_keeping_duplicates(&open_file(path)?, path, vec![entry], opts)
+            }
+            #[cfg(feature = "object-storage")]
+            Journal::Object(store) => Err(Error::InvalidArgument(format!(
+                "{} is in object storage, which entries with their own timestamps can't be written to",
+                store
+            ))),
+        }
+    }
+
+    fn random_entry(&self) -> Result<Option<Entry>> {
+        match self {
+            Journal::File(path) => Entries::new(BufReader::new(open_file(path)?)).rand_entry(),
+            #[cfg(feature = "object-storage")]
+            Journal::Object(store) => Entries::new(BufReader::new(store.reader()?)).rand_entry(),
+        }
+    }
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .map_err(|source| Error::OpenFile {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+/// What to fill the editor with when composing an entry.
+struct Template {
+    path: PathBuf,
+    comment_prefix: String,
+}
+
+impl Template {
+    // Picking by the day means the same file is used all day, and they're all
+    // used in turn.
+    fn load(&self, today: NaiveDate) -> Result<String> {
+        let path = match self.path.is_dir() {
+            true => {
+                let mut files = Vec::new();
+                for file in std::fs::read_dir(&self.path)? {
+                    let path = file?.path();
+                    let hidden = path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+                    if path.is_file() && !hidden {
+                        files.push(path);
+                    }
+                }
+                files.sort();
+                if files.is_empty() {
+                    return Err(Error::InvalidArgument(format!(
+                        "there are no templates in {}",
+                        self.path.display()
+                    )));
+                }
+                files.swap_remove(today.num_days_from_ce() as usize % files.len())
+            }
+            false => self.path.clone(),
+        };
+
+        std::fs::read_to_string(&path).map_err(|source| Error::OpenFile { path, source })
+    }
+}
+
+impl Opt {
+    /// The metadata to store with the entry.
+    fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new();
+        if let Some(mood) = self.mood {
+            metadata.insert(mood::FIELD.to_owned(), mood.into());
+        }
+        metadata
+    }
+
+    /// Whether the entry is composed in an editor.
+    fn composed(&self) -> bool {
+        self.message.is_empty() && self.file.is_none() && !self.clipboard && !self.stdin_lines
+    }
+}
+
+/// The entry to write, along with any front matter that was at the top of it
+/// if it was composed in an editor. Composed entries are checked with
+/// `validate_command` if there is one.
+fn message(
+    opt: &Opt,
+    template: Option<&Template>,
+    validate_command: Option<&str>,
+    path: &Path,
+) -> Result<(String, FrontMatter)> {
+    let msg = itertools::join(&opt.message, " ");
+    if opt.clipboard {
+        let text = clipboard_text()?;
+        if text.trim().is_empty() && !opt.allow_empty {
+            return Err(Error::Clipboard("there's no text on it".to_owned()));
+        }
+        return Ok((with_prefix(&msg, &text), FrontMatter::default()));
+    }
+    if !msg.is_empty() {
+        return Ok((msg, FrontMatter::default()));
+    }
+
+    let (msg, front_matter) = match &opt.file {
+        Some(file) => (
+            std::fs::read_to_string(file).map_err(|source| Error::OpenFile {
+                path: file.clone(),
+                source,
+            })?,
+            FrontMatter::default(),
+        ),
+        None => {
+            let editor = opt
+                .editor
+                .clone()
+                .or_else(|| env::var("EDITOR").ok())
+                .ok_or(Error::NoEditor)?;
+
+            let mut prefill = match template {
+                Some(template) => template.load(Local::now().date_naive())?,
+                None => String::new(),
+            };
+            loop {
+                let composed = compose_entry(&editor, &prefill)?;
+                // Front matter comes before comments are stripped, as org-mode
+                // keywords start with the same # that comments usually do.
+                let (front_matter, rest) =
+                    front_matter::split(&composed).inspect_err(|_| keep_draft(&composed))?;
+                let rest = match template {
+                    Some(template) => text::strip_comments(rest, &template.comment_prefix),
+                    None => rest.to_owned(),
+                };
+                if let Some(command) = validate_command.filter(|_| !rest.trim().is_empty()) {
+                    if !validated(command, path, &rest).inspect_err(|_| keep_draft(&composed))? {
+                        prefill = composed;
+                        continue;
+                    }
+                }
+                break (rest, front_matter);
+            }
+        }
+    };
+
+    // Leaving the editor without writing anything is how you change your
+    // mind, like it is with git commit. Empty files are most likely a mistake
+    // too.
+    if msg.trim().is_empty() && !opt.allow_empty {
+        return Err(Error::EmptyEntry);
+    }
+    Ok((front_matter.tag(&msg), front_matter))
+}
+
+/// Runs `command` on an entry composed in an editor, asking whether to edit it
+/// again if it finds problems with it. Returns whether to write the entry as
+/// it is, or to edit it again.
+fn validated(command: &str, path: &Path, msg: &str) -> Result<bool> {
+    let problems = match hook::validate(command, path, msg)? {
+        Some(problems) => problems,
+        None => return Ok(true),
+    };
+    if !std::io::stdin().is_terminal() {
+        return Err(Error::ValidateFailed(problems.trim().to_owned()));
+    }
+
+    eprintln!("{}", problems.trim_end());
+    loop {
+        eprint!("Edit the entry again (e), write it anyway (w) or give up (q)? [e] ");
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            answer = "q".to_owned();
+        }
+        match answer.trim() {
+            "" | "e" => return Ok(false),
+            "w" => return Ok(true),
+            "q" => return Err(Error::ValidateFailed(problems.trim().to_owned())),
+            _ => {}
+        }
+    }
+}
+
+fn clipboard_text() -> Result<String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| Error::Clipboard(e.to_string()))?;
+    match clipboard.get_text() {
+        Ok(text) => Ok(text),
+        Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+        Err(e) => Err(Error::Clipboard(e.to_string())),
+    }
+}
+
+// Prefixes go on the same line as things like links, and on a line of their
+// own before anything longer.
+fn with_prefix(prefix: &str, text: &str) -> String {
+    let text = text.trim();
+    match (prefix, text.contains('\n')) {
+        ("", _) => text.to_owned(),
+        (prefix, false) => format!("{} {}", prefix, text),
+        (prefix, true) => format!("{}\n\n{}", prefix, text),
+    }
+}
+
+/// Writes to a journal on another machine by running hmm there with `rest`
+/// after the flags for writing entries, returning the status it exited with. The entry is composed here, so that
+/// you get your own editor, but everything else, including the config file and
+/// its hooks, is up to the other machine.
+fn append_remote(remote: &Remote, opt: &WriteOpt, rest: &[String]) -> Result<i32> {
+    let mut args = vec![
+        "--lock-timeout".to_owned(),
+        opt.lock_timeout.as_secs_f64().to_string(),
+    ];
+    if opt.no_lock {
+        args.push("--no-lock".to_owned());
+    }
+    if let Some(skew) = opt.skew {
+        args.extend(["--skew".to_owned(), skew.name().to_owned()]);
+    }
+    if opt.no_hooks {
+        args.push("--no-hooks".to_owned());
+    }
+    if opt.errors == ErrorFormat::Json {
+        args.extend(["--errors".to_owned(), "json".to_owned()]);
+    }
+    args.extend_from_slice(rest);
+
+    remote.run("hmm", &args)
+}
+
+fn compose_entry(editor: &str, prefill: &str) -> Result<String> {
+    let mut f = NamedTempFile::new()?;
+    f.write_all(prefill.as_bytes())?;
     let path = f.into_temp_path();
 
-    let args = shellwords::split(editor).map_err(|_| "mismatched quotes in editor command")?;
+    let args = shellwords::split(editor)
+        .map_err(|_| Error::Editor("mismatched quotes in editor command".to_owned()))?;
 
     let mut cmd = match args.as_slice() {
-        [] => return Err("no editor specified".into()),
+        [] => return Err(Error::Editor("no editor specified".to_owned())),
         [cmd] => {
             let mut c = Command::new(cmd);
             c.arg(&path);
@@ -121,7 +915,9 @@ fn compose_entry(editor: &str) -> Result<String> {
     let status = cmd.status()?;
 
     if !status.success() {
-        return Err("something went wrong composing entry, please try again".into());
+        return Err(Error::Editor(
+            "something went wrong composing entry, please try again".to_owned(),
+        ));
     }
 
     let mut s = String::new();
@@ -131,10 +927,12 @@ fn compose_entry(editor: &str) -> Result<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
     use assert_cmd::{assert::Assert, prelude::*};
+    use chrono::prelude::*;
     use escargot::{CargoBuild, CargoRun};
+    use fs2::FileExt;
     use hmmcli::entries::Entries;
+    use hmmcli::entry::Entry;
     use lazy_static::lazy_static;
     use std::io::BufReader;
     use std::path::PathBuf;
@@ -163,11 +961,17 @@ mod tests {
         NamedTempFile::new().unwrap().keep().unwrap().1
     }
 
+    fn new_tempfile(content: &str) -> PathBuf {
+        let path = new_tempfile_path();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
     #[test_case(vec!["hello world"]               => "hello world"   ; "single argument, single line entry")]
     #[test_case(vec!["hello", "world"]            => "hello world"   ; "multiple argument, single line entry")]
     #[test_case(vec!["hello\nworld"]              => "hello\nworld"  ; "single argument, multiple line entry")]
     #[test_case(vec!["hello\n", "world"]          => "hello\n world" ; "multiple argument, multiple line entry")]
-    #[test_case(vec!["--editor", "cat"]           => ""              ; "the editor argument works")]
+    #[test_case(vec!["--editor", "cat", "--allow-empty"] => ""       ; "the editor argument works")]
     #[test_case(vec!["--editor", "perl -e \"my $f = $ARGV[0]; open(my $fh, '>', $f) or die 'could not open file'; print $fh 'hello world'\""]  => "hello world" ; "the editor argument actually creates entries")]
     fn test_hmm_single_invocation(args: Vec<&str>) -> String {
         let path = new_tempfile_path();
@@ -212,9 +1016,860 @@ mod tests {
         messages
     }
 
+    #[test]
+    fn test_hmm_concurrent_writers() {
+        let path = new_tempfile_path();
+        let writers = 32;
+
+        let children: Vec<_> = (0..writers)
+            .map(|i| {
+                HMM.command()
+                    .arg("--path")
+                    .arg(path.as_os_str())
+                    .arg(format!("writer {}", i))
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+
+        let entries: Vec<_> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|result| result.unwrap())
+            .collect();
+        assert_eq!(entries.len(), writers);
+        assert!(entries
+            .windows(2)
+            .all(|w| w[0].datetime() <= w[1].datetime()));
+
+        let mut messages: Vec<_> = entries.iter().map(|e| e.message().to_owned()).collect();
+        messages.sort();
+        let mut expected: Vec<_> = (0..writers).map(|i| format!("writer {}", i)).collect();
+        expected.sort();
+        assert_eq!(messages, expected);
+    }
+
+    #[test_case(vec![],                   None                         => vec!["future"]        ; "defaults to error")]
+    #[test_case(vec!["--skew", "clamp"], None                         => vec!["future", "now"] ; "flag")]
+    #[test_case(vec![],                   Some("skew = \"clamp\"")    => vec!["future", "now"] ; "config")]
+    #[test_case(vec!["--skew", "error"], Some("skew = \"clamp\"")    => vec!["future"]        ; "flag overrides config")]
+    fn test_hmm_skew(args: Vec<&str>, config: Option<&str>) -> Vec<String> {
+        let path = new_tempfile_path();
+        std::fs::write(&path, "9999-01-01T00:00:00+00:00,\"\"\"future\"\"\"\n").unwrap();
+
+        let config_path = new_tempfile_path();
+        std::fs::write(&config_path, config.unwrap_or("")).unwrap();
+
+        let mut cmd = HMM.command();
+        cmd.arg("--path")
+            .arg(path.as_os_str())
+            .arg("--config")
+            .arg(config_path.as_os_str())
+            .args(args)
+            .arg("now");
+        let assert = cmd.assert();
+
+        let messages: Vec<String> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|e| e.unwrap().message().to_owned())
+            .collect();
+        if messages.len() == 1 {
+            assert.code(75);
+        } else {
+            assert.success();
+        }
+        messages
+    }
+
+    #[test]
+    fn test_hmm_lock_timeout() {
+        let path = new_tempfile_path();
+        let holder = File::open(&path).unwrap();
+        holder.lock_exclusive().unwrap();
+
+        let assert = run_with_path(&path, vec!["--lock-timeout", "0.1", "hello"]);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(
+            stderr.contains("another hmm process is writing to your hmm file"),
+            "unexpected stderr: {}",
+            stderr
+        );
+        assert.code(75);
+
+        run_with_path(&path, vec!["--no-lock", "hello"]).success();
+        drop(holder);
+
+        let mut entries = Entries::new(BufReader::new(File::open(&path).unwrap()));
+        assert_eq!(entries.next_entry().unwrap().unwrap().message(), "hello");
+        assert!(entries.next_entry().unwrap().is_none());
+    }
+
+    // Writes its argument in to the file it's given, for use as an editor.
+    fn editor_writing(s: &str) -> String {
+        format!(
+            "perl -e \"open(my $fh, '>', $ARGV[0]) or die; print $fh '{}'\"",
+            s
+        )
+    }
+
+    #[test_case(vec![("HMM_EDITOR", editor_writing("hmm editor")), ("EDITOR", editor_writing("editor"))], vec![]                                  => "hmm editor" ; "HMM_EDITOR before EDITOR")]
+    #[test_case(vec![("EDITOR", editor_writing("editor"))],                                               vec![]                                  => "editor"     ; "EDITOR")]
+    #[test_case(vec![("HMM_EDITOR", editor_writing("hmm editor"))],                                       vec!["--editor", &editor_writing("flag")] => "flag"       ; "flag before HMM_EDITOR")]
+    #[test_case(vec![("HMM_LOCK_TIMEOUT", "5".to_owned())],                                               vec!["hello"]                           => "hello"      ; "HMM_LOCK_TIMEOUT")]
+    fn test_hmm_env(env: Vec<(&str, String)>, args: Vec<&str>) -> String {
+        let path = new_tempfile_path();
+        let mut cmd = HMM.command();
+        cmd.env_remove("EDITOR").env("HMM_PATH", &path).args(args);
+        for (k, v) in env {
+            cmd.env(k, v);
+        }
+        cmd.assert().success();
+
+        let mut entries = Entries::new(BufReader::new(File::open(&path).unwrap()));
+        entries.next_entry().unwrap().unwrap().message().to_owned()
+    }
+
+    #[test]
+    fn test_hmm_env_config_and_skew() {
+        let path = new_tempfile_path();
+        std::fs::write(&path, "9999-01-01T00:00:00+00:00,\"\"\"future\"\"\"\n").unwrap();
+        let config_path = new_tempfile_path();
+        std::fs::write(&config_path, "skew = \"clamp\"").unwrap();
+
+        let run = |env: Vec<(&str, &std::ffi::OsStr)>| {
+            let mut cmd = HMM.command();
+            cmd.env("HMM_PATH", &path).arg("now");
+            for (k, v) in env {
+                cmd.env(k, v);
+            }
+            cmd.assert()
+        };
+
+        // HMM_SKEW takes precedence over the config file.
+        run(vec![
+            ("HMM_CONFIG", config_path.as_os_str()),
+            ("HMM_SKEW", "error".as_ref()),
+        ])
+        .code(75);
+        run(vec![("HMM_CONFIG", config_path.as_os_str())]).success();
+    }
+
+    // Runs hmm with a config file containing `config`, returning the messages
+    // in the hmm file afterwards along with the result.
+    fn run_with_config(config: &str, args: Vec<&str>) -> (Vec<String>, Assert) {
+        let path = new_tempfile_path();
+        let config_path = new_tempfile_path();
+        std::fs::write(&config_path, config).unwrap();
+
+        let assert = HMM
+            .command()
+            .arg("--path")
+            .arg(path.as_os_str())
+            .arg("--config")
+            .arg(config_path.as_os_str())
+            .args(args)
+            .assert();
+
+        let messages = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|e| e.unwrap().message().to_owned())
+            .collect();
+        (messages, assert)
+    }
+
+    #[test_case("pre_append = \"true\"",                                         vec!["hello"]               => (vec!["hello".to_owned()], 0)  ; "pre_append allows")]
+    #[test_case("pre_append = \"test \\\"$HMM_ENTRY_MESSAGE\\\" != secret\"", vec!["secret"]              => (vec![], 77)                   ; "pre_append vetoes")]
+    #[test_case("pre_append = \"false\"",                                        vec!["--no-hooks", "hello"] => (vec!["hello".to_owned()], 0)  ; "no hooks")]
+    #[test_case("post_append = \"false\"",                                       vec!["hello"]               => (vec!["hello".to_owned()], 70) ; "post_append failure keeps the entry")]
+    #[test_case("pre_append = \"/this/does/not/exist\"",                         vec!["hello"]               => (vec![], 77)                   ; "missing pre_append command")]
+    fn test_hmm_hooks(config: &str, args: Vec<&str>) -> (Vec<String>, i32) {
+        let (messages, assert) = run_with_config(config, args);
+        let code = assert.get_output().status.code().unwrap();
+        (messages, code)
+    }
+
+    #[test]
+    fn test_hmm_post_append_sees_entry() {
+        let out = new_tempfile_path();
+        let config = format!(
+            "post_append = \"cat > {0}; echo \\\"|$HMM_ENTRY_DATETIME\\\" >> {0}\"",
+            out.display()
+        );
+        let (messages, assert) = run_with_config(&config, vec!["hello", "world"]);
+        assert.success();
+        assert_eq!(messages, vec!["hello world"]);
+
+        let written = std::fs::read_to_string(&out).unwrap();
+        let (message, datetime) = written.trim_end().split_once('|').unwrap();
+        assert_eq!(message, "hello world");
+        assert!(
+            DateTime::parse_from_rfc3339(datetime).is_ok(),
+            "{}",
+            datetime
+        );
+    }
+
+    #[test_case(200, vec!["hello"]               => (vec!["hello".to_owned()], 0, 1)  ; "sent")]
+    #[test_case(404, vec!["hello"]               => (vec!["hello".to_owned()], 70, 1) ; "failure keeps the entry")]
+    #[test_case(404, vec!["--no-hooks", "hello"] => (vec!["hello".to_owned()], 0, 0)  ; "no hooks")]
+    fn test_hmm_webhook(status: u16, args: Vec<&str>) -> (Vec<String>, i32, usize) {
+        let server = std::sync::Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}/hook", server.server_addr().to_ip().unwrap());
+        let shared = server.clone();
+        let requests = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for mut req in shared.incoming_requests() {
+                let mut body = String::new();
+                req.as_reader().read_to_string(&mut body).unwrap();
+                bodies.push(body);
+                req.respond(tiny_http::Response::empty(status)).unwrap();
+            }
+            bodies
+        });
+
+        let (messages, assert) = run_with_config(&format!("webhook = \"{}\"", url), args);
+        let code = assert.get_output().status.code().unwrap();
+        server.unblock();
+        let bodies = requests.join().unwrap();
+        for body in &bodies {
+            let json: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(json["text"], "hello");
+        }
+        (messages, code, bodies.len())
+    }
+
+    #[cfg(unix)]
+    #[test_case("echo 51.5007,-0.1246"   => Some(serde_json::json!({"lat": 51.5007, "lon": -0.1246})) ; "located")]
+    #[test_case("echo nowhere"           => None                                                       ; "not located")]
+    fn test_hmm_location_command(command: &str) -> Option<serde_json::Value> {
+        let path = new_tempfile_path();
+        let config = new_tempfile_path();
+        std::fs::write(&config, format!("location_command = \"{}\"", command)).unwrap();
+        HMM.command()
+            .arg("--path")
+            .arg(&path)
+            .arg("--config")
+            .arg(&config)
+            .arg("hello")
+            .assert()
+            .success();
+
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(entries[0].message(), "hello");
+        entries[0].metadata().get("location").cloned()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hmm_encrypt_command() {
+        let out = new_tempfile_path();
+        let config = format!(
+            "encrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"\npost_append = \"cat > {}\"",
+            out.display()
+        );
+        let (messages, assert) = run_with_config(&config, vec!["hello"]);
+        assert.success();
+        assert_eq!(messages, vec!["hmm-encrypted:dXJ5eWI="]);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_hmm_encrypt_command_fails() {
+        let (messages, assert) = run_with_config("encrypt_command = \"exit 1\"", vec!["hello"]);
+        assert.code(70);
+        assert!(messages.is_empty());
+    }
+
+    // Stands in for ssh by running the remote command here, or failing like
+    // ssh does when it can't connect if the host is "down".
+    #[cfg(unix)]
+    const FAKE_SSH: &str = "while [ \"$1\" != -- ]; do shift; done\n[ \"$2\" = down ] && exit 255\nexec sh -c \"$3\"\n";
+
+    #[cfg(unix)]
+    fn remote_command(bin: &CargoRun, host: &str, path: &Path) -> std::process::Command {
+        let ssh = new_tempfile_path();
+        std::fs::write(&ssh, FAKE_SSH).unwrap();
+        let paths = std::env::var_os("PATH").unwrap_or_default();
+        let paths = std::iter::once(bin.path().parent().unwrap().to_owned())
+            .chain(std::env::split_paths(&paths));
+
+        let mut cmd = bin.command();
+        cmd.env("HMM_SSH", format!("sh {}", ssh.display()))
+            .env("PATH", std::env::join_paths(paths).unwrap())
+            .arg("--path")
+            .arg(format!("ssh://{}{}", host, path.display()));
+        cmd
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hmm_remote() {
+        let path = new_tempfile_path();
+        remote_command(&HMM, "box", &path)
+            .args(["--", "hello", "--world"])
+            .assert()
+            .success();
+        remote_command(&HMM, "box", &path)
+            .args(["--skew", "error", "it's"])
+            .assert()
+            .success();
+
+        let messages: Vec<String> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|e| e.unwrap().message().to_owned())
+            .collect();
+        assert_eq!(messages, vec!["hello --world", "it's"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hmm_remote_errors() {
+        remote_command(&HMM, "box", Path::new("/this/path/does/not/exist"))
+            .arg("hello")
+            .assert()
+            .code(66);
+
+        let assert = remote_command(&HMM, "down", &new_tempfile_path())
+            .args(["--errors", "json", "hello"])
+            .assert();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("\"error\":\"remote\""), "{}", stderr);
+        assert.code(69);
+    }
+
+    fn read_messages(path: &Path) -> Vec<String> {
+        Entries::new(BufReader::new(File::open(path).unwrap()))
+            .map(|e| e.unwrap().message().to_owned())
+            .collect()
+    }
+
+    #[test_case(vec!["start", "something"]        => "start something"  ; "start")]
+    #[test_case(vec!["find", "a", "dentist"]      => "find a dentist"   ; "find")]
+    #[test_case(vec!["review", "the", "PR"]       => "review the PR"    ; "review")]
+    #[test_case(vec!["--", "sync", "later"]       => "sync later"       ; "after dashes")]
+    fn test_hmm_command_names_as_message(args: Vec<&str>) -> String {
+        let path = new_tempfile_path();
+        run_with_path(&path, args).success();
+        read_messages(&path).join("\n")
+    }
+
+    #[test]
+    fn test_hmm_prompt() {
+        let path = new_tempfile_path();
+        std::fs::write(&path, "2020-01-01T00:00:00+00:00,\"\"\"past me\"\"\"\n").unwrap();
+
+        let assert = HMM
+            .command()
+            .args([
+                "--prompt",
+                "--editor",
+                "sh -c 'echo present me > $0'",
+                "--path",
+            ])
+            .arg(&path)
+            .assert()
+            .success();
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert_eq!(
+            stdout,
+            "On Wednesday 1 January 2020, you wrote:\n\n    past me\n\n"
+        );
+        assert_eq!(read_messages(&path), vec!["past me", "present me"]);
+    }
+
+    #[test]
+    fn test_hmm_prompt_empty_journal() {
+        let path = new_tempfile_path();
+        let assert = HMM
+            .command()
+            .args(["--prompt", "--path"])
+            .arg(&path)
+            .arg("first")
+            .assert()
+            .success();
+        assert!(assert.get_output().stdout.is_empty());
+        assert_eq!(read_messages(&path), vec!["first"]);
+    }
+
+    #[test]
+    fn test_hmm_template() {
+        let template = new_tempfile_path();
+        std::fs::write(
+            &template,
+            "# Lines starting with # are removed.\nWhat went well?\n",
+        )
+        .unwrap();
+        let config = format!("template = {:?}", template);
+
+        let (messages, assert) = run_with_config(
+            &config,
+            vec!["--editor", "sh -c 'echo \"# not me\nit all did\" >> $0'"],
+        );
+        assert.success();
+        assert_eq!(messages, vec!["What went well?\nit all did"]);
+    }
+
+    #[test]
+    fn test_hmm_template_comment_prefix() {
+        let template = new_tempfile_path();
+        std::fs::write(&template, "; notes\n# Heading\n").unwrap();
+        let config = format!("template = {:?}\ncomment_prefix = \";\"", template);
+
+        let (messages, assert) = run_with_config(&config, vec!["--editor", "true"]);
+        assert.success();
+        assert_eq!(messages, vec!["# Heading"]);
+    }
+
+    #[test_case(None,                      vec!["--editor", "true"]                  => (vec![], 65)                    ; "empty")]
+    #[test_case(None,                      vec!["--editor", "sh -c 'echo > $0'"]     => (vec![], 65)                    ; "whitespace")]
+    #[test_case(Some("# just comments\n"), vec!["--editor", "true"]                  => (vec![], 65)                    ; "comments")]
+    #[test_case(None,                      vec!["--editor", "true", "--allow-empty"] => (vec!["".to_owned()], 0)        ; "allowed")]
+    fn test_hmm_empty_entry(template: Option<&str>, args: Vec<&str>) -> (Vec<String>, i32) {
+        let path = new_tempfile_path();
+        std::fs::write(&path, template.unwrap_or("")).unwrap();
+        let config = match template {
+            Some(_) => format!("template = {:?}", path),
+            None => String::new(),
+        };
+
+        let (messages, assert) = run_with_config(&config, args);
+        let output = assert.get_output();
+        let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+        if messages.is_empty() {
+            assert!(stderr.contains("--allow-empty"), "{}", stderr);
+        }
+        (messages, output.status.code().unwrap())
+    }
+
+    #[test]
+    fn test_hmm_template_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a"), "first prompt").unwrap();
+        std::fs::write(dir.path().join("b"), "second prompt").unwrap();
+        std::fs::write(dir.path().join(".hidden"), "never").unwrap();
+
+        let path = new_tempfile_path();
+        HMM.command()
+            .arg("--path")
+            .arg(&path)
+            .arg("--template")
+            .arg(dir.path())
+            .args(["--editor", "true"])
+            .assert()
+            .success();
+        let messages = read_messages(&path);
+        assert!(
+            messages == ["first prompt"] || messages == ["second prompt"],
+            "{:?}",
+            messages
+        );
+    }
+
+    #[test]
+    fn test_hmm_template_not_used_with_message() {
+        let (messages, assert) =
+            run_with_config("template = \"/this/path/does/not/exist\"", vec!["hello"]);
+        assert.success();
+        assert_eq!(messages, vec!["hello"]);
+
+        let (messages, assert) = run_with_config(
+            "template = \"/this/path/does/not/exist\"",
+            vec!["--editor", "true"],
+        );
+        assert.code(66);
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn test_hmm_start_and_stop() {
+        let path = new_tempfile_path();
+        let run = |args: Vec<&str>| HMM.command().args(args).arg("--path").arg(&path).assert();
+
+        run(vec!["--start", "garden", "weeding", "the", "beds"]).success();
+        run(vec!["--start", "#work"]).success();
+        run(vec!["--stop"]).success();
+        run(vec!["--stop", "done", "for", "the", "day"]).success();
+        run(vec!["--start", "not a tag"]).code(64);
+        run(vec!["--start"]).failure();
+        run(vec!["--start", "garden", "--stop"]).failure();
+
+        assert_eq!(
+            read_messages(&path),
+            vec![
+                "#start #garden weeding the beds",
+                "#start #work",
+                "#stop",
+                "#stop done for the day"
+            ]
+        );
+    }
+
+    const ENTRY_TEMPLATES: &str = "[entry_templates]
+standup = \"yesterday: {y}\\ntoday: {t}\\nblockers: {b|none}\"
+";
+
+    #[test_case(vec!["--new", "standup", "-y", "fixed bugs", "-t", "more bugs"] => Ok("yesterday: fixed bugs\ntoday: more bugs\nblockers: none".to_owned()) ; "defaults")]
+    #[test_case(vec!["--new", "standup", "-y=-", "-t", "-b", "--b", "#ops"] => Ok("yesterday: -\ntoday: -b\nblockers: #ops".to_owned()) ; "flags as values")]
+    #[test_case(vec!["--skew", "clamp", "--new", "standup", "-y", "a", "-t", "b"] => Ok("yesterday: a\ntoday: b\nblockers: none".to_owned()) ; "hmm flags")]
+    #[test_case(vec!["--new", "standup", "-y", "a", "-t", "b", "--skew", "clamp"] => Err(64) ; "hmm flags after the template")]
+    #[test_case(vec!["--new", "standup", "-y", "a"] => Err(64) ; "missing field")]
+    #[test_case(vec!["--new", "standup", "-y", "a", "-t", "b", "-x", "c"] => Err(64) ; "unknown field")]
+    #[test_case(vec!["--new", "standup", "a"] => Err(64) ; "value without a flag")]
+    #[test_case(vec!["--new", "retro"] => Err(64) ; "unknown template")]
+    #[test_case(vec!["--new"] => Err(1) ; "no template")]
+    #[test_case(vec!["hello", "--new", "standup"] => Err(1) ; "message")]
+    fn test_hmm_new(args: Vec<&str>) -> Result<String, i32> {
+        let path = new_tempfile_path();
+        let assert = HMM
+            .command()
+            .arg("--path")
+            .arg(&path)
+            .arg("--config")
+            .arg(new_tempfile(ENTRY_TEMPLATES))
+            .args(args)
+            .assert();
+        match assert.get_output().status.code().unwrap() {
+            0 => Ok(read_messages(&path).join("\n---\n")),
+            code => Err(code),
+        }
+    }
+
+    #[test]
+    fn test_hmm_recover() {
+        let data = tempfile::TempDir::new().unwrap();
+        let path = new_tempfile_path();
+        std::fs::write(&path, "9999-01-01T00:00:00+00:00,\"\"\"future\"\"\"\n").unwrap();
+        let hmm = |args: &[&str]| {
+            let mut cmd = HMM.command();
+            cmd.env("XDG_DATA_HOME", data.path())
+                .args(args)
+                .arg("--path")
+                .arg(&path);
+            cmd.assert()
+        };
+        let stderr =
+            |assert: &Assert| String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+
+        // Messages given on the command line aren't saved.
+        hmm(&["not saved"]).code(75);
+        let assert = hmm(&["--editor", &editor_writing("a long entry")]).code(75);
+        assert!(
+            stderr(&assert).contains("run `hmm --recover "),
+            "{}",
+            stderr(&assert)
+        );
+
+        let assert = hmm(&["--recover"]).success();
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        let (name, first_line) = stdout.trim_end().split_once("  ").unwrap();
+        assert_eq!(first_line, "a long entry");
+
+        hmm(&["--all", "--recover", name]).code(64);
+        hmm(&["--skew", "clamp", "--recover", name]).success();
+        assert_eq!(read_messages(&path), vec!["future", "a long entry"]);
+        assert!(hmm(&["--recover"]).get_output().stdout.is_empty());
+        hmm(&["--recover", name]).code(64);
+    }
+
+    #[test]
+    fn test_hmm_recover_all_and_discard() {
+        let data = tempfile::TempDir::new().unwrap();
+        let drafts = data.path().join("hmm").join("drafts");
+        std::fs::create_dir_all(&drafts).unwrap();
+        std::fs::write(drafts.join("2020-01-01-000000-a.txt"), "first").unwrap();
+        std::fs::write(drafts.join("2020-01-02-000000-b.txt"), "second").unwrap();
+        std::fs::write(drafts.join("2020-01-03-000000-c.txt"), "third").unwrap();
+
+        let path = new_tempfile_path();
+        let hmm = |args: &[&str]| {
+            let mut cmd = HMM.command();
+            cmd.env("XDG_DATA_HOME", data.path())
+                .args(args)
+                .arg("--path")
+                .arg(&path);
+            cmd.assert().success()
+        };
+
+        hmm(&["--discard", "--recover", "2020-01-02-000000-b.txt"]);
+        hmm(&["--all", "--recover"]);
+        assert_eq!(read_messages(&path), vec!["first", "third"]);
+        assert_eq!(std::fs::read_dir(&drafts).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_hmm_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let notes = dir.path().join("meeting-notes.md");
+        let path = new_tempfile_path();
+
+        std::fs::write(&notes, "# Standup\n\n- shipped it\n").unwrap();
+        run_with_path(&path, vec!["--file", notes.to_str().unwrap()]).success();
+        assert!(notes.exists());
+
+        let archive = dir.path().join("archive");
+        std::fs::create_dir(&archive).unwrap();
+        std::fs::write(archive.join("meeting-notes.md"), "already archived").unwrap();
+        run_with_path(
+            &path,
+            vec![
+                "--file",
+                notes.to_str().unwrap(),
+                "--archive",
+                archive.to_str().unwrap(),
+            ],
+        )
+        .success();
+        assert!(!notes.exists());
+        assert_eq!(
+            std::fs::read_to_string(archive.join("meeting-notes-1.md")).unwrap(),
+            "# Standup\n\n- shipped it\n"
+        );
+
+        std::fs::write(&notes, "again").unwrap();
+        run_with_path(&path, vec!["--file", notes.to_str().unwrap(), "--delete"]).success();
+        assert!(!notes.exists());
+
+        assert_eq!(
+            read_messages(&path),
+            vec![
+                "# Standup\n\n- shipped it",
+                "# Standup\n\n- shipped it",
+                "again"
+            ]
+        );
+    }
+
+    #[test_case(vec!["--file", "/this/path/does/not/exist"]          => 66 ; "missing file")]
+    #[test_case(vec!["--file", "EMPTY"]                                => 65 ; "empty file")]
+    #[test_case(vec!["--file", "EMPTY", "--delete", "--archive", "x"] => 1  ; "delete and archive")]
+    #[test_case(vec!["--delete", "hello"]                              => 1  ; "delete without file")]
+    #[test_case(vec!["--file", "EMPTY", "hello"]                       => 1  ; "file and message")]
+    fn test_hmm_file_errors(args: Vec<&str>) -> i32 {
+        let empty = new_tempfile_path();
+        let args = args
+            .into_iter()
+            .map(|arg| match arg {
+                "EMPTY" => empty.to_str().unwrap(),
+                arg => arg,
+            })
+            .collect();
+        let path = new_tempfile_path();
+        let code = run_with_path(&path, args)
+            .get_output()
+            .status
+            .code()
+            .unwrap();
+        assert!(read_messages(&path).is_empty());
+        assert!(empty.exists());
+        code
+    }
+
+    #[test_case("",          "https://example.com\n" => "https://example.com"                 ; "no prefix")]
+    #[test_case("read this", "https://example.com"   => "read this https://example.com"       ; "link")]
+    #[test_case("snippet:",  "fn main() {\n}\n"     => "snippet:\n\nfn main() {\n}"          ; "multiple lines")]
+    fn test_with_prefix(prefix: &str, text: &str) -> String {
+        super::with_prefix(prefix, text)
+    }
+
+    // There's no clipboard to read without a display.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_hmm_clipboard_unavailable() {
+        let path = new_tempfile_path();
+        let assert = HMM
+            .command()
+            .env_remove("DISPLAY")
+            .env_remove("WAYLAND_DISPLAY")
+            .arg("--path")
+            .arg(&path)
+            .args(["--clipboard", "--errors", "json"])
+            .assert()
+            .code(69);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("\"error\":\"clipboard\""), "{}", stderr);
+        assert!(read_messages(&path).is_empty());
+    }
+
+    #[test]
+    fn test_hmm_stdin_lines() {
+        let path = new_tempfile_path();
+        assert_cmd::Command::from_std(HMM.command())
+            .arg("--path")
+            .arg(&path)
+            .arg("--stdin-lines")
+            .write_stdin("first\n\n  \n  second line \n")
+            .assert()
+            .success();
+        assert_eq!(read_messages(&path), vec!["first", "second line"]);
+    }
+
+    #[test]
+    fn test_hmm_stdin_lines_parse_ts() {
+        let path = new_tempfile_path();
+        assert_cmd::Command::from_std(HMM.command())
+            .arg("--path")
+            .arg(&path)
+            .args(["--stdin-lines", "--parse-ts"])
+            .write_stdin(
+                "2020-01-02T09:00:00Z started\n\
+                 2020-01-01 10:00:00+00:00 out of order\n\
+                 no timestamp\n\
+                 2020-01-02T09:00:00Z started\n",
+            )
+            .assert()
+            .success();
+
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|e| e.unwrap())
+            .collect();
+        let messages: Vec<&str> = entries.iter().map(|e| e.message()).collect();
+        assert_eq!(
+            messages,
+            vec!["out of order", "started", "started", "no timestamp"]
+        );
+        assert_eq!(
+            entries[0].datetime().to_rfc3339(),
+            "2020-01-01T10:00:00+00:00"
+        );
+    }
+
+    #[test_case(vec!["--stdin-lines", "hello"] ; "message")]
+    #[test_case(vec!["--stdin-lines", "--clipboard"] ; "clipboard")]
+    #[test_case(vec!["--parse-ts", "hello"] ; "parse-ts without stdin-lines")]
+    fn test_hmm_stdin_lines_errors(args: Vec<&str>) {
+        let path = new_tempfile_path();
+        run_with_path(&path, args).failure();
+        assert!(read_messages(&path).is_empty());
+    }
+
+    #[test]
+    fn test_hmm_mood() {
+        let path = new_tempfile_path();
+        run_with_path(&path, vec!["--mood", "4", "good day"]).success();
+        run_with_path(&path, vec!["no mood"]).success();
+
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(entries[0].message(), "good day");
+        assert_eq!(entries[0].metadata().get("mood"), Some(&4.into()));
+        assert!(entries[1].metadata().is_empty());
+    }
+
+    #[test]
+    fn test_hmm_front_matter() {
+        let path = new_tempfile_path();
+        let composed = "---\ntags: work, ideas\ndate: 2024-05-13T09:30\nmood: 4\nweather: sunny\n---\nthe idea\nmore about it\n";
+        run_with_path(&path, vec!["--editor", &editor_writing(composed)]).success();
+        let composed = "#+mood: 5\n#+tags: later\nlater";
+        run_with_path(
+            &path,
+            vec!["--mood", "2", "--editor", &editor_writing(composed)],
+        )
+        .success();
+        let composed = "---\nnot front matter\n---";
+        run_with_path(&path, vec!["--editor", &editor_writing(composed)]).success();
+        run_with_path(
+            &path,
+            vec!["--", "---\ntags: not\n---\nfrom the command line"],
+        )
+        .success();
+
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(
+            entries[0].message(),
+            "the idea\nmore about it\n\n#work #ideas"
+        );
+        assert_eq!(
+            entries[0].datetime().naive_local(),
+            NaiveDate::from_ymd_opt(2024, 5, 13)
+                .unwrap()
+                .and_hms_opt(9, 30, 0)
+                .unwrap()
+        );
+        assert_eq!(entries[0].metadata().get("mood"), Some(&4.into()));
+        assert_eq!(entries[0].metadata().get("weather"), Some(&"sunny".into()));
+        assert_eq!(entries[1].message(), "later #later");
+        assert_eq!(entries[1].metadata().get("mood"), Some(&5.into()));
+        assert_eq!(entries[2].message(), "---\nnot front matter\n---");
+        assert_eq!(
+            entries[3].message(),
+            "---\ntags: not\n---\nfrom the command line"
+        );
+    }
+
+    #[test_case("true", vec![] => (0, vec!["speling".to_owned()]) ; "passes")]
+    #[test_case("grep -q speling && exit 1", vec![] => (77, vec![]) ; "fails")]
+    #[test_case("exit 1", vec!["--no-hooks"] => (0, vec!["speling".to_owned()]) ; "no hooks")]
+    #[test_case("exit 1", vec!["not composed"] => (0, vec!["not composed".to_owned()]) ; "not composed")]
+    fn test_hmm_validate_command(command: &str, args: Vec<&str>) -> (i32, Vec<String>) {
+        let data = tempfile::TempDir::new().unwrap();
+        let path = new_tempfile_path();
+        let config = new_tempfile(&format!("validate_command = {:?}\n", command));
+        let assert = HMM
+            .command()
+            .env("XDG_DATA_HOME", data.path())
+            .arg("--path")
+            .arg(&path)
+            .arg("--config")
+            .arg(&config)
+            .arg("--editor")
+            .arg(editor_writing("speling"))
+            .args(args)
+            .assert();
+        let code = assert.get_output().status.code().unwrap();
+        if code != 0 {
+            let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+            assert!(stderr.contains("hmm --recover"), "{}", stderr);
+        }
+        (code, read_messages(&path))
+    }
+
+    #[test]
+    fn test_hmm_front_matter_errors() {
+        let data = tempfile::TempDir::new().unwrap();
+        let path = new_tempfile_path();
+        let assert = HMM
+            .command()
+            .env("XDG_DATA_HOME", data.path())
+            .arg("--path")
+            .arg(&path)
+            .arg("--editor")
+            .arg(editor_writing("---\nmood: 9\n---\nhello"))
+            .assert()
+            .code(64);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("hmm --recover"), "{}", stderr);
+        assert!(read_messages(&path).is_empty());
+    }
+
+    #[test_case(vec!["--mood", "6", "hello"] ; "too high")]
+    #[test_case(vec!["--mood", "great", "hello"] ; "not a number")]
+    #[test_case(vec!["--mood", "3", "--stdin-lines"] ; "stdin lines")]
+    fn test_hmm_mood_errors(args: Vec<&str>) {
+        let path = new_tempfile_path();
+        run_with_path(&path, args).failure();
+        assert!(read_messages(&path).is_empty());
+    }
+
+    #[test]
+    fn test_hmm_env_errors() {
+        let assert = HMM
+            .command()
+            .env("HMM_ERRORS", "json")
+            .args(["--path", "/this/path/does/not/exist", "hi"])
+            .assert();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("\"error\":\"open_file\""), "{}", stderr);
+        assert.code(66);
+    }
+
     #[test_case(vec!["--path", "/this/path/does/not/exist"],        "Couldn't open or create file at")]
     #[test_case(vec!["--path", "something", "--path", "something"], "The argument '--path <path>' was provided more than once")]
     #[test_case(vec!["--nonexistent"], "Found argument '--nonexistent' which wasn't expected")]
+    #[test_case(vec!["--path", "/this/path/does/not/exist", "--errors", "json", "hi"], "\"error\":\"open_file\"")]
     fn test_hmm_errors(args: Vec<&str>, error: &str) {
         let assert = HMM.command().args(args).assert();
         let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
@@ -0,0 +1,452 @@
+use chrono::prelude::*;
+use hmmcli::{
+    entries::Entries,
+    error::{Error, ErrorFormat},
+    journal::{self, AppendOptions, SkewPolicy},
+    scan, Result,
+};
+use human_panic::setup_panic;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hmmb",
+    about = "Benchmark common operations against a .hmm file"
+)]
+struct Opt {
+    /// Path to the .hmm file to benchmark against. It isn't modified, the append
+    /// benchmark writes to a temporary file that starts with a copy of this
+    /// file's last entry.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: PathBuf,
+
+    /// Which operations to benchmark, in the order given. "append" writes new
+    /// entries, "seek" finds the first entry after random dates, "last" reads
+    /// the last 100 entries and "regex" scans the whole file with --regex.
+    /// Defaults to all of them.
+    #[structopt(long = "op", possible_values = &["append", "seek", "last", "regex"])]
+    ops: Vec<Op>,
+
+    /// How many times to run each of the fast operations.
+    #[structopt(long = "iterations", default_value = "100")]
+    iterations: usize,
+
+    /// How many times to run operations that scan the whole file, which take a
+    /// lot longer than the others.
+    #[structopt(long = "scan-iterations", default_value = "5")]
+    scan_iterations: usize,
+
+    /// The regular expression used by the regex benchmark.
+    #[structopt(long = "regex", default_value = "(lorem|ipsum)")]
+    regex: String,
+
+    /// Seed for picking random dates, so that runs can be compared like for
+    /// like.
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// Read the file using mmap, the same as hmmq --mmap.
+    #[structopt(long = "mmap")]
+    mmap: bool,
+
+    /// Print results as one JSON object per operation instead of a table.
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Append,
+    Seek,
+    Last,
+    Regex,
+}
+
+const ALL_OPS: &[Op] = &[Op::Append, Op::Seek, Op::Last, Op::Regex];
+
+impl Op {
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Append => "append",
+            Op::Seek => "seek",
+            Op::Last => "last",
+            Op::Regex => "regex",
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ALL_OPS
+            .iter()
+            .find(|op| op.name() == s)
+            .copied()
+            .ok_or_else(|| Error::InvalidArgument(format!("unknown operation \"{}\"", s)))
+    }
+}
+
+// How many entries the "last" benchmark reads, the same as hmmq --last 100.
+const LAST_N: u64 = 100;
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+fn app(opt: &Opt) -> Result<()> {
+    let (first, last) = match (open(opt)?.next(), open(opt)?.next_back()) {
+        (Some(first), Some(last)) => (first?, last?),
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "{} has no entries to benchmark against",
+                opt.path.to_string_lossy()
+            )))
+        }
+    };
+
+    let mut rng = match opt.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let ops = if opt.ops.is_empty() {
+        ALL_OPS
+    } else {
+        &opt.ops[..]
+    };
+
+    if !opt.json {
+        println!(
+            "{:<8} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "op", "runs", "min", "p50", "p90", "p99", "max"
+        );
+    }
+
+    for op in ops {
+        let times = match op {
+            Op::Append => bench_append(opt, &last.to_csv_row()?)?,
+            Op::Seek => {
+                let (first, last) = (first.datetime().timestamp(), last.datetime().timestamp());
+                let mut dates = (0..opt.iterations).map(|_| {
+                    let t = rng.gen_range(first..=last);
+                    Utc.timestamp_opt(t, 0).unwrap().into()
+                });
+                if opt.mmap {
+                    bench_seek(opt, Entries::from_mmap(&opt.path)?, &mut dates)?
+                } else {
+                    bench_seek(opt, open(opt)?, &mut dates)?
+                }
+            }
+            Op::Last => {
+                if opt.mmap {
+                    bench_last(opt, Entries::from_mmap(&opt.path)?)?
+                } else {
+                    bench_last(opt, open(opt)?)?
+                }
+            }
+            Op::Regex => bench_regex(opt)?,
+        };
+
+        let stats = Stats::new(times);
+        if opt.json {
+            println!("{}", stats.to_json(op.name()));
+        } else {
+            println!("{}", stats.to_row(op.name()));
+        }
+    }
+
+    Ok(())
+}
+
+fn open(opt: &Opt) -> Result<Entries<BufReader<File>>> {
+    let f = File::open(&opt.path).map_err(|source| Error::OpenFile {
+        path: opt.path.clone(),
+        source,
+    })?;
+    Ok(Entries::new(BufReader::new(f)))
+}
+
+/// Times `f`, which is run `n` times.
+fn time(n: usize, mut f: impl FnMut() -> Result<()>) -> Result<Vec<Duration>> {
+    let mut times = Vec::with_capacity(n);
+    for _ in 0..n {
+        let start = Instant::now();
+        f()?;
+        times.push(start.elapsed());
+    }
+    Ok(times)
+}
+
+fn bench_append(opt: &Opt, last_row: &str) -> Result<Vec<Duration>> {
+    // Appending only ever looks at the end of the file, so a file holding just
+    // the last entry behaves the same as the real thing without us having to
+    // write to it.
+    let mut tmp = tempfile::NamedTempFile::new()?;
+    tmp.write_all(last_row.as_bytes())?;
+    let f = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open(tmp.path())?;
+
+    // The last entry is likely to be in the future if the file was generated
+    // by hmmdg, so clamp rather than fail.
+    let opts = AppendOptions {
+        skew: SkewPolicy::Clamp,
+        ..AppendOptions::default()
+    };
+    time(opt.iterations, || {
+        journal::append(&f, "benchmark entry", &opts)?;
+        Ok(())
+    })
+}
+
+fn bench_seek<T: Seek + Read + BufRead>(
+    opt: &Opt,
+    mut entries: Entries<T>,
+    dates: &mut impl Iterator<Item = DateTime<FixedOffset>>,
+) -> Result<Vec<Duration>> {
+    time(opt.iterations, || {
+        let date = dates.next().unwrap();
+        entries.seek_to_first(&date)?;
+        entries.next_entry()?;
+        Ok(())
+    })
+}
+
+fn bench_last<T: Seek + Read + BufRead>(
+    opt: &Opt,
+    mut entries: Entries<T>,
+) -> Result<Vec<Duration>> {
+    time(opt.iterations, || {
+        let len = entries.len_bytes()?;
+        entries.at(len)?;
+        for _ in 0..LAST_N {
+            entries.seek_to_prev()?;
+        }
+        for _ in 0..LAST_N {
+            entries.next_entry()?;
+        }
+        Ok(())
+    })
+}
+
+fn bench_regex(opt: &Opt) -> Result<Vec<Duration>> {
+    let re = regex::Regex::new(&opt.regex)?;
+    time(opt.scan_iterations, || {
+        scan::par_filter(&opt.path, None, None, |e| re.is_match(e.message()))?;
+        Ok(())
+    })
+}
+
+/// Latency percentiles for a set of timings.
+#[derive(Debug, PartialEq)]
+struct Stats {
+    runs: usize,
+    min: Duration,
+    p50: Duration,
+    p90: Duration,
+    p99: Duration,
+    max: Duration,
+}
+
+impl Stats {
+    fn new(mut times: Vec<Duration>) -> Self {
+        times.sort();
+        Stats {
+            runs: times.len(),
+            min: percentile(&times, 0.0),
+            p50: percentile(&times, 50.0),
+            p90: percentile(&times, 90.0),
+            p99: percentile(&times, 99.0),
+            max: percentile(&times, 100.0),
+        }
+    }
+
+    fn to_row(&self, name: &str) -> String {
+        format!(
+            "{:<8} {:>6} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            name,
+            self.runs,
+            human(self.min),
+            human(self.p50),
+            human(self.p90),
+            human(self.p99),
+            human(self.max)
+        )
+    }
+
+    fn to_json(&self, name: &str) -> String {
+        let micros = |d: Duration| d.as_micros() as u64;
+        serde_json::json!({
+            "op": name,
+            "runs": self.runs,
+            "min_us": micros(self.min),
+            "p50_us": micros(self.p50),
+            "p90_us": micros(self.p90),
+            "p99_us": micros(self.p99),
+            "max_us": micros(self.max),
+        })
+        .to_string()
+    }
+}
+
+/// The nearest-rank percentile `p` of `sorted`, which must be sorted.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn human(d: Duration) -> String {
+    let micros = d.as_secs_f64() * 1_000_000.0;
+    if micros < 1000.0 {
+        format!("{:.1}µs", micros)
+    } else if micros < 1_000_000.0 {
+        format!("{:.2}ms", micros / 1000.0)
+    } else {
+        format!("{:.2}s", micros / 1_000_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_cmd::assert::Assert;
+    use escargot::{CargoBuild, CargoRun};
+    use lazy_static::lazy_static;
+    use std::fs;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMB: CargoRun = CargoBuild::new()
+            .bin("hmmb")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    fn testdata() -> NamedTempFile {
+        let mut f = NamedTempFile::new().unwrap();
+        for day in 1..=28 {
+            for hour in 0..24 {
+                writeln!(
+                    f,
+                    "2020-02-{:02}T{:02}:00:00+00:00,\"\"\"lorem {}\"\"\"",
+                    day, hour, hour
+                )
+                .unwrap();
+            }
+        }
+        f
+    }
+
+    fn run(args: &[&str]) -> Assert {
+        assert_cmd::Command::from_std(HMMB.command())
+            .args(["--iterations", "5", "--scan-iterations", "2"])
+            .args(args)
+            .assert()
+    }
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test_case(vec![5],                  0.0   => ms(5)  ; "single")]
+    #[test_case(vec![1, 2, 3, 4],         50.0  => ms(2)  ; "median")]
+    #[test_case(vec![1, 2, 3, 4],         100.0 => ms(4)  ; "max")]
+    #[test_case(vec![1, 2, 3, 4],         0.0   => ms(1)  ; "min")]
+    #[test_case((1..=100).collect(),      99.0  => ms(99) ; "p99")]
+    #[test_case(vec![],                   50.0  => ms(0)  ; "empty")]
+    fn test_percentile(times: Vec<u64>, p: f64) -> Duration {
+        let times: Vec<Duration> = times.into_iter().map(ms).collect();
+        percentile(&times, p)
+    }
+
+    #[test]
+    fn test_hmmb() {
+        let f = testdata();
+        let before = fs::read_to_string(f.path()).unwrap();
+
+        let assert = run(&["--path", f.path().to_str().unwrap()]).success();
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+
+        let ops: Vec<&str> = stdout
+            .lines()
+            .skip(1)
+            .map(|l| l.split_whitespace().next().unwrap())
+            .collect();
+        assert_eq!(ops, vec!["append", "seek", "last", "regex"]);
+        assert_eq!(fs::read_to_string(f.path()).unwrap(), before);
+    }
+
+    #[test_case("DATA",                      vec![]                   => true  ; "HMM_PATH")]
+    #[test_case("/this/path/does/not/exist", vec!["--path", "DATA"] => true  ; "flag before HMM_PATH")]
+    #[test_case("DATA",                      vec!["--path", "/this/path/does/not/exist"] => false ; "missing flag path before HMM_PATH")]
+    fn test_hmmb_env(env: &str, args: Vec<&str>) -> bool {
+        let f = testdata();
+        let data = f.path().to_str().unwrap();
+        let args: Vec<String> = args.iter().map(|a| a.replace("DATA", data)).collect();
+        assert_cmd::Command::from_std(HMMB.command())
+            .env("HMM_PATH", env.replace("DATA", data))
+            .args(["--op", "last", "--iterations", "1"])
+            .args(args)
+            .assert()
+            .get_output()
+            .status
+            .success()
+    }
+
+    #[test]
+    fn test_hmmb_json() {
+        let f = testdata();
+        let assert = run(&[
+            "--path",
+            f.path().to_str().unwrap(),
+            "--op",
+            "seek",
+            "--op",
+            "last",
+            "--mmap",
+            "--json",
+        ])
+        .success();
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+
+        let results: Vec<serde_json::Value> = stdout
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["op"], "seek");
+        assert_eq!(results[1]["op"], "last");
+        assert_eq!(results[1]["runs"], 5);
+    }
+
+    #[test]
+    fn test_hmmb_empty_file() {
+        let f = NamedTempFile::new().unwrap();
+        run(&["--path", f.path().to_str().unwrap()]).code(64);
+    }
+}
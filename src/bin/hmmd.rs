@@ -0,0 +1,617 @@
+use hmmcli::{
+    config::Config,
+    error::{Error, ErrorFormat},
+    journal::{AppendOptions, SkewPolicy},
+    lock, Result,
+};
+use human_panic::setup_panic;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hmmd",
+    about = "A daemon that appends lines sent to a socket to your hmm file"
+)]
+struct Opt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Path of the Unix socket to listen on, defaults to hmmd.sock in your
+    /// runtime directory ($XDG_RUNTIME_DIR), or ~/.hmmd.sock if you don't have
+    /// one.
+    #[structopt(long = "socket", env = "HMM_SOCKET")]
+    socket: Option<PathBuf>,
+
+    /// The most entries to write to your hmm file at once. Lines that arrive
+    /// while a batch is being written are written together in the next one.
+    #[structopt(long = "batch-size", default_value = "1000")]
+    batch_size: usize,
+
+    /// How many seconds to wait for another hmm process to finish writing to
+    /// your hmm file before giving up on a batch.
+    #[structopt(
+        long = "lock-timeout",
+        env = "HMM_LOCK_TIMEOUT",
+        default_value = "10",
+        parse(try_from_str = lock::parse_timeout)
+    )]
+    lock_timeout: Duration,
+
+    /// Don't lock your hmm file while writing to it. Only use this if your hmm
+    /// file lives on a filesystem that doesn't support locking, as concurrent
+    /// writes could corrupt your file.
+    #[structopt(long = "no-lock")]
+    no_lock: bool,
+
+    /// What to do if the last entry in your hmm file is later than the current
+    /// time, as in hmm's --skew. Overrides the skew setting in your config
+    /// file, defaults to "clamp", as there's nobody around to try again.
+    #[structopt(long = "skew", env = "HMM_SKEW", possible_values = &["error", "wait", "clamp"])]
+    skew: Option<SkewPolicy>,
+
+    /// Don't run the pre_append and post_append hooks or the webhook from your
+    /// config file.
+    #[structopt(long = "no-hooks")]
+    no_hooks: bool,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+fn append_options(opt: &Opt, config: &Config) -> AppendOptions {
+    AppendOptions {
+        lock: !opt.no_lock,
+        lock_timeout: opt.lock_timeout,
+        skew: opt.skew.or(config.skew).unwrap_or(SkewPolicy::Clamp),
+    }
+}
+
+#[cfg(not(unix))]
+fn app(_: &Opt) -> Result<()> {
+    Err(Error::InvalidArgument(
+        "hmmd only supports Unix sockets, so doesn't run on this platform yet".to_owned(),
+    ))
+}
+
+#[cfg(unix)]
+fn app(opt: &Opt) -> Result<()> {
+    use hmmcli::{crypt, entry::Entry, hook, journal};
+    use std::fs::OpenOptions;
+    use std::sync::mpsc;
+    use std::thread;
+
+    if opt.batch_size == 0 {
+        return Err(Error::InvalidArgument(
+            "--batch-size must be greater than 0".to_owned(),
+        ));
+    }
+
+    let config = Config::load_or_default(opt.config.as_deref())?;
+    let append_opts = append_options(opt, &config);
+    let (pre_append, post_append, webhook) = match opt.no_hooks {
+        true => (None, None, None),
+        false => (
+            config.pre_append.as_ref(),
+            config.post_append.as_ref(),
+            config.webhook.as_ref(),
+        ),
+    };
+
+    let path = opt
+        .path
+        .clone()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+    let open = |path: &PathBuf| {
+        OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(|source| Error::OpenFile {
+                path: path.clone(),
+                source,
+            })
+    };
+    let mut f = open(&path)?;
+
+    let socket = opt.socket.clone().unwrap_or_else(unix::default_socket);
+    let listener = unix::bind(&socket)?;
+    eprintln!("hmmd: listening on {}", socket.display());
+
+    let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    let errors = opt.errors;
+    thread::spawn(move || unix::accept(listener, tx, errors));
+
+    // There's nobody to report errors to, so the best we can do is say what
+    // happened and carry on with the next batch.
+    let source = path.to_string_lossy().into_owned();
+    let mut batch: Vec<String> = Vec::with_capacity(opt.batch_size);
+    while let Ok(line) = rx.recv() {
+        batch.clear();
+        batch.push(line);
+        while batch.len() < opt.batch_size {
+            match rx.try_recv() {
+                Ok(line) => batch.push(line),
+                Err(_) => break,
+            }
+        }
+
+        if let Some(command) = pre_append {
+            batch.retain(|line| match hook::pre_append(command, &path, line) {
+                Ok(()) => true,
+                Err(e) => {
+                    opt.errors.warn(command, &e);
+                    false
+                }
+            });
+            if batch.is_empty() {
+                continue;
+            }
+        }
+
+        let written = batch
+            .iter()
+            .map(|line| crypt::encrypt_with(config.encrypt_command.as_deref(), line))
+            .collect::<Result<Vec<_>>>()
+            .and_then(|stored| {
+                // Either every message is encrypted or none of them are, so
+                // they all have the same metadata.
+                let metadata = stored[0].1.clone();
+                let stored: Vec<String> = stored.into_iter().map(|(s, _)| s).collect();
+
+                // The journal stays open between batches, so it's the old copy
+                // if something has replaced it since, like hmmi importing
+                // entries in to the middle of it.
+                match journal::append_all_with_metadata(&f, &stored, &metadata, &append_opts) {
+                    Err(Error::Replaced) => {
+                        f = open(&path)?;
+                        journal::append_all_with_metadata(&f, &stored, &metadata, &append_opts)
+                    }
+                    written => written,
+                }
+            });
+        let written = match written {
+            Ok(written) => written,
+            Err(e) => {
+                opt.errors.warn(&source, &e);
+                keep_drafts(&batch, opt.errors);
+                continue;
+            }
+        };
+
+        // Hooks always see the messages in plain text.
+        let entries: Vec<Entry> = written
+            .iter()
+            .zip(&batch)
+            .map(|(entry, line)| Entry::new(*entry.datetime(), line.trim().to_owned()))
+            .collect();
+        if let Some(command) = post_append {
+            for entry in &entries {
+                if let Err(e) = hook::post_append(command, &path, entry) {
+                    opt.errors.warn(command, &e);
+                }
+            }
+        }
+        if let Some(url) = webhook {
+            let (url, path) = (url.clone(), path.clone());
+            thread::spawn(move || {
+                for entry in &entries {
+                    if let Err(e) = hook::webhook(&url, &path, entry) {
+                        errors.warn(&url, &e);
+                    }
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
+
+// Keeps each of `lines` as a draft, so that a batch that couldn't be written
+// can be written later with `hmm --recover` rather than being lost.
+#[cfg(unix)]
+fn keep_drafts(lines: &[String], errors: ErrorFormat) {
+    use hmmcli::draft;
+
+    let dir = match draft::dir() {
+        Some(dir) => dir,
+        None => {
+            let e = Error::InvalidArgument("couldn't find where to keep drafts".to_owned());
+            errors.warn("<drafts>", &e);
+            return;
+        }
+    };
+    for line in lines {
+        match draft::save(&dir, line) {
+            Ok(draft) => eprintln!(
+                "hmmd: an entry that couldn't be written was saved to {}",
+                draft.path().display()
+            ),
+            Err(e) => errors.warn(&dir.to_string_lossy(), &e),
+        }
+    }
+}
+
+// How many lines can be waiting to be written before connections are made to
+// wait. This is only reached if lines arrive faster than they can be written.
+#[cfg(unix)]
+const CHANNEL_CAPACITY: usize = 100_000;
+
+#[cfg(unix)]
+mod unix {
+    use hmmcli::{
+        error::{Error, ErrorFormat},
+        Result,
+    };
+    use std::fs;
+    use std::io::{self, BufRead, BufReader};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::SyncSender;
+    use std::thread;
+
+    pub fn default_socket() -> PathBuf {
+        match dirs::runtime_dir() {
+            Some(dir) => dir.join("hmmd.sock"),
+            None => dirs::home_dir().unwrap().join(".hmmd.sock"),
+        }
+    }
+
+    /// Listens on the socket at `path`. A socket left behind by an hmmd that
+    /// didn't shut down cleanly is replaced, but one that another hmmd is
+    /// still listening on isn't, and nor is anything that isn't a socket.
+    pub fn bind(path: &Path) -> Result<UnixListener> {
+        let create_error = |source| Error::CreateFile {
+            path: path.to_owned(),
+            source,
+        };
+
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(Error::InvalidArgument(format!(
+                        "hmmd is already listening on {}",
+                        path.display()
+                    )));
+                }
+                fs::remove_file(path).map_err(create_error)?;
+            }
+            Ok(_) => {
+                return Err(create_error(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "file exists and isn't a socket",
+                )))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(create_error(e)),
+        }
+
+        UnixListener::bind(path).map_err(create_error)
+    }
+
+    /// Accepts connections forever, reading lines from each on its own thread
+    /// and sending them to `tx` to be written.
+    pub fn accept(listener: UnixListener, tx: SyncSender<String>, errors: ErrorFormat) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    thread::spawn(move || read_lines(stream, tx, errors));
+                }
+                Err(e) => errors.warn("<socket>", &e.into()),
+            }
+        }
+    }
+
+    // Each line is a single entry. Blank lines are skipped, rather than
+    // written as empty entries, so that clients can be a little careless
+    // about trailing newlines.
+    fn read_lines(stream: UnixStream, tx: SyncSender<String>, errors: ErrorFormat) {
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    errors.warn("<socket>", &e.into());
+                    return;
+                }
+            };
+
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            if tx.send(line.to_owned()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use chrono::prelude::*;
+    use escargot::{CargoBuild, CargoRun};
+    use hmmcli::entries::Entries;
+    use lazy_static::lazy_static;
+    use std::fs::File;
+    use std::io::{BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::{Path, PathBuf};
+    use std::process::{Child, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::{NamedTempFile, TempDir};
+
+    lazy_static! {
+        static ref HMMD: CargoRun = CargoBuild::new()
+            .bin("hmmd")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    // Kills the daemon when the test finishes, whether it passed or not.
+    struct Daemon(Child);
+
+    impl Drop for Daemon {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn start(path: &Path, socket: &Path, args: Vec<&str>) -> Daemon {
+        start_with_env(path, socket, args, vec![])
+    }
+
+    fn start_with_env(
+        path: &Path,
+        socket: &Path,
+        args: Vec<&str>,
+        env: Vec<(&str, &Path)>,
+    ) -> Daemon {
+        let child = HMMD
+            .command()
+            .arg("--path")
+            .arg(path)
+            .arg("--socket")
+            .arg(socket)
+            .args(args)
+            .envs(env)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let daemon = Daemon(child);
+
+        let start = Instant::now();
+        while UnixStream::connect(socket).is_err() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "hmmd didn't start"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        daemon
+    }
+
+    fn entries(path: &Path) -> Vec<(DateTime<FixedOffset>, String)> {
+        Entries::new(BufReader::new(File::open(path).unwrap()))
+            .map(|e| e.unwrap())
+            .map(|e| (*e.datetime(), e.message().to_owned()))
+            .collect()
+    }
+
+    fn new_tempfile_path() -> PathBuf {
+        NamedTempFile::new().unwrap().keep().unwrap().1
+    }
+
+    #[test]
+    fn test_hmmd() {
+        let path = new_tempfile_path();
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("hmmd.sock");
+        let _daemon = start(&path, &socket, vec![]);
+
+        let clients: Vec<_> = (0..8)
+            .map(|i| {
+                let socket = socket.clone();
+                thread::spawn(move || {
+                    let mut stream = UnixStream::connect(socket).unwrap();
+                    for j in 0..25 {
+                        write!(stream, "client {} line {}\r\n\n", i, j).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        let start = Instant::now();
+        let mut written = entries(&path);
+        while written.len() < 200 && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+            written = entries(&path);
+        }
+
+        assert_eq!(written.len(), 200);
+        assert!(written.windows(2).all(|w| w[0].0 <= w[1].0));
+        let mut messages: Vec<_> = written.into_iter().map(|(_, m)| m).collect();
+        messages.sort();
+        let mut expected: Vec<_> = (0..8)
+            .flat_map(|i| (0..25).map(move |j| format!("client {} line {}", i, j)))
+            .collect();
+        expected.sort();
+        assert_eq!(messages, expected);
+    }
+
+    #[test]
+    fn test_hmmd_encrypt_command() {
+        let path = new_tempfile_path();
+        let config = new_tempfile_path();
+        std::fs::write(&config, "encrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"").unwrap();
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("hmmd.sock");
+        let _daemon = start(&path, &socket, vec!["--config", config.to_str().unwrap()]);
+
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        writeln!(stream, "hello").unwrap();
+        drop(stream);
+
+        let start = Instant::now();
+        let mut written = entries(&path);
+        while written.is_empty() && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+            written = entries(&path);
+        }
+        let messages: Vec<_> = written.into_iter().map(|(_, m)| m).collect();
+        assert_eq!(messages, vec!["hmm-encrypted:dXJ5eWI="]);
+    }
+
+    #[test]
+    fn test_hmmd_hooks() {
+        let path = new_tempfile_path();
+        let appended = new_tempfile_path();
+        let config = new_tempfile_path();
+        std::fs::write(
+            &config,
+            format!(
+                "pre_append = \"test \\\"$(cat)\\\" != rejected\"\npost_append = \"cat >> {}; echo >> {}\"",
+                appended.display(),
+                appended.display()
+            ),
+        )
+        .unwrap();
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("hmmd.sock");
+        let _daemon = start(&path, &socket, vec!["--config", config.to_str().unwrap()]);
+
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        writeln!(stream, "accepted\nrejected").unwrap();
+        drop(stream);
+
+        let start = Instant::now();
+        let mut hooked = std::fs::read_to_string(&appended).unwrap();
+        while !hooked.ends_with('\n') && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+            hooked = std::fs::read_to_string(&appended).unwrap();
+        }
+        assert_eq!(hooked, "accepted\n");
+        let messages: Vec<_> = entries(&path).into_iter().map(|(_, m)| m).collect();
+        assert_eq!(messages, vec!["accepted"]);
+    }
+
+    #[test]
+    fn test_hmmd_keeps_drafts() {
+        let path = new_tempfile_path();
+        let config = new_tempfile_path();
+        std::fs::write(&config, "encrypt_command = \"exit 1\"").unwrap();
+        let data = TempDir::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("hmmd.sock");
+        let _daemon = start_with_env(
+            &path,
+            &socket,
+            vec!["--config", config.to_str().unwrap()],
+            vec![("XDG_DATA_HOME", data.path())],
+        );
+
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        writeln!(stream, "hello").unwrap();
+        drop(stream);
+
+        let drafts = data.path().join("hmm").join("drafts");
+        let start = Instant::now();
+        let read_drafts = || -> Vec<String> {
+            match std::fs::read_dir(&drafts) {
+                Ok(files) => files
+                    .map(|f| std::fs::read_to_string(f.unwrap().path()).unwrap())
+                    .collect(),
+                Err(_) => vec![],
+            }
+        };
+        let mut kept = read_drafts();
+        while kept != vec!["hello"] && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+            kept = read_drafts();
+        }
+        assert_eq!(kept, vec!["hello"]);
+        assert!(entries(&path).is_empty());
+    }
+
+    #[test]
+    fn test_hmmd_already_running() {
+        let path = new_tempfile_path();
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("hmmd.sock");
+        let _daemon = start(&path, &socket, vec![]);
+
+        let out = HMMD
+            .command()
+            .arg("--path")
+            .arg(&path)
+            .arg("--socket")
+            .arg(&socket)
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(
+            stderr.contains("hmmd is already listening on"),
+            "{}",
+            stderr
+        );
+        assert_eq!(out.status.code(), Some(64));
+    }
+
+    #[test]
+    fn test_hmmd_replaces_stale_socket() {
+        let path = new_tempfile_path();
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("hmmd.sock");
+        drop(start(&path, &socket, vec![]));
+        assert!(socket.exists());
+
+        let _daemon = start(&path, &socket, vec![]);
+    }
+
+    #[test]
+    fn test_hmmd_wont_replace_other_files() {
+        let path = new_tempfile_path();
+        let socket = new_tempfile_path();
+        std::fs::write(&socket, "important").unwrap();
+
+        let out = HMMD
+            .command()
+            .arg("--path")
+            .arg(&path)
+            .arg("--socket")
+            .arg(&socket)
+            .output()
+            .unwrap();
+        assert_eq!(out.status.code(), Some(73));
+        assert_eq!(std::fs::read_to_string(&socket).unwrap(), "important");
+    }
+}
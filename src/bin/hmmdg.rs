@@ -1,19 +1,39 @@
 use chrono::{prelude::*, Duration};
-use hmmcli::{entry::Entry, Result};
+use hmmcli::{
+    entries::Entries,
+    entry::{Entry, Metadata},
+    error::{Error, ErrorFormat},
+    journal::{self, JournalWriter},
+    Result,
+};
 use human_panic::setup_panic;
-use std::io::BufWriter;
+use lipsum::MarkovChain;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
 use std::path::PathBuf;
 use std::process::exit;
+use std::str::FromStr;
+use std::time::Instant;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "hmmdg", about = "Generate valid .hmm files for benchmarking.")]
 struct Opt {
     /// Path to write the generated .hmm file to. Specifically does not default to the
-    /// usual .hmm file location, and will refuse to run if the file already exists.
+    /// usual .hmm file location, and will refuse to run if the file already exists
+    /// unless --append is given.
     #[structopt(long = "path")]
     path: PathBuf,
 
+    /// Add entries to the end of an existing .hmm file instead of creating a new
+    /// one. The first new entry comes one step after the last entry already in
+    /// the file.
+    #[structopt(long = "append", conflicts_with = "start")]
+    append: bool,
+
     /// How many simulated entries per day you would like to write.
     #[structopt(long = "entries-per-day", default_value = "1440")]
     entries_per_day: u64,
@@ -22,66 +42,660 @@ struct Opt {
     #[structopt(long = "num-days", default_value = "3650")]
     num_days: u64,
 
+    /// The date of the first entry, in RFC3339 format. Defaults to --num-days
+    /// before now.
+    #[structopt(long = "start")]
+    start: Option<String>,
+
     /// You can optionally supply a fixed message to write for every entry. If this is not
     /// supplied, a random message is generated for you.
     #[structopt(long = "message")]
     message: Option<String>,
+
+    /// Where random messages come from. "lipsum" is lorem ipsum, "markov" is
+    /// English text generated from a built-in sample of journal entries, and
+    /// "file:<path>" generates text that reads like the contents of the given
+    /// file.
+    #[structopt(long = "corpus", default_value = "lipsum")]
+    corpus: Corpus,
+
+    /// Add #hashtags to messages, picked from this many different tags. Some
+    /// tags are used much more often than others, and each entry gets between
+    /// none and three of them.
+    #[structopt(long = "with-tags", default_value = "0")]
+    with_tags: usize,
+
+    /// The probability, between 0 and 1, that a message spans more than one
+    /// line.
+    #[structopt(long = "multiline-prob", default_value = "0")]
+    multiline_prob: f64,
+
+    /// The probability, between 0 and 1, that an entry has metadata attached
+    /// to it.
+    #[structopt(long = "metadata-prob", default_value = "0")]
+    metadata_prob: f64,
+
+    /// Seed for the random number generator. Running twice with the same seed,
+    /// --start and other options produces exactly the same file.
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// How many threads to generate entries on. The output is the same no
+    /// matter how many are used. Use 0 to have one thread per CPU.
+    #[structopt(long = "jobs", default_value = "0")]
+    jobs: usize,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+#[derive(Debug)]
+enum Corpus {
+    Lipsum,
+    Markov,
+    File(PathBuf),
+}
+
+impl FromStr for Corpus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lipsum" => Ok(Corpus::Lipsum),
+            "markov" => Ok(Corpus::Markov),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Corpus::File(PathBuf::from(path))),
+                _ => Err(Error::InvalidArgument(format!(
+                    "unknown corpus \"{}\", expected lipsum, markov or file:<path>",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+// The text the "markov" corpus learns from. It's meant to look like the sort
+// of thing people actually write in a .hmm file, so that searching and
+// formatting generated files behaves like it would on a real one.
+const JOURNAL_SAMPLE: &str = "
+Finally got the build working again after spending the whole morning on it.
+Turns out the config file was being read from the wrong directory the whole time.
+Had a good chat with the team about the roadmap for next quarter, lots of ideas.
+Feeling tired today, didn't sleep well. Going to try and finish early.
+Reminder to self: renew the car insurance before the end of the month.
+Read a really interesting article about how databases store data on disk.
+The meeting ran over again, need to push back on having so many of these.
+Went for a long walk at lunch, the weather was lovely and it cleared my head.
+Fixed the bug where the page would crash if you clicked the button twice.
+Spent the afternoon reviewing pull requests, some really nice work in there.
+Booked flights for the trip in the summer, can't wait to get away for a bit.
+Need to remember to call mum this weekend, it's been a while.
+Started reading a new book about the history of computing, slow going so far.
+The deploy failed twice before it worked, need to look at why it's so flaky.
+Tried a new recipe for dinner tonight and it actually turned out really well.
+Wrote up some notes on the design for the new search feature, needs more work.
+Learned that you can pipe the output of one command straight in to another.
+Had a bit of a rough day, nothing went to plan and I got very little done.
+Paired with a colleague on the migration, much faster than doing it alone.
+Cleaned up the garden and planted some tomatoes, hopefully they survive this time.
+Idea for later: a small tool to keep track of the things I read each week.
+Went to the gym for the first time in weeks, going to be sore tomorrow.
+Finished the first draft of the talk, it's too long and needs cutting down.
+The new laptop arrived today, spent the evening setting everything up again.
+Thinking about whether it's worth rewriting the parser or just patching it.
+";
+
+// The average number of words in a generated message. Lengths are drawn from
+// an exponential distribution, so most messages are fairly short but every so
+// often there's a much longer one, the same as in a real journal.
+const MEAN_WORDS: f64 = 20.0;
+const MAX_WORDS: usize = 200;
+
+// Tags to pick from when generating entries with --with-tags. If more are
+// asked for than there are here, the rest are numbered.
+const TAGS: &[&str] = &[
+    "work", "home", "idea", "todo", "meeting", "bug", "reading", "health", "family", "travel",
+    "cooking", "finance", "learning", "music", "garden", "review",
+];
+
+// Entries are generated in chunks of this many, each on a single thread, and
+// each thread is given this many chunks at a time before they're written out
+// in order.
+const ENTRIES_PER_CHUNK: u64 = 10_000;
+const CHUNKS_PER_THREAD: usize = 4;
+
+// The most lines a multiline message can have.
+const MAX_LINES: usize = 5;
+
+/// Where the text of generated messages comes from.
+enum Text<'a> {
+    Fixed(&'a str),
+    Lipsum,
+    Chain(MarkovChain<'a>),
+}
+
+impl<'a> Text<'a> {
+    fn new(opt: &'a Opt, corpus: &'a str) -> Result<Self> {
+        if let Some(ref message) = opt.message {
+            return Ok(Text::Fixed(message));
+        }
+
+        match opt.corpus {
+            Corpus::Lipsum => Ok(Text::Lipsum),
+            Corpus::Markov | Corpus::File(_) => {
+                let mut chain = MarkovChain::new();
+                for line in corpus.lines() {
+                    chain.learn(line);
+                }
+                if chain.is_empty() {
+                    return Err(Error::InvalidArgument(
+                        "corpus needs at least one line with 3 or more words in it".to_owned(),
+                    ));
+                }
+                Ok(Text::Chain(chain))
+            }
+        }
+    }
+
+    fn line(&self, rng: &mut impl Rng) -> String {
+        let n = message_len(rng);
+        match self {
+            Text::Fixed(message) => (*message).to_owned(),
+            Text::Lipsum => lipsum::lipsum_words_with_rng(rng, n),
+            Text::Chain(chain) => chain.generate_with_rng(rng, n),
+        }
+    }
+}
+
+/// Makes up entries.
+struct Generator<'a> {
+    text: Text<'a>,
+    tags: Vec<String>,
+    multiline_prob: f64,
+    metadata_prob: f64,
+}
+
+impl<'a> Generator<'a> {
+    fn new(opt: &'a Opt, corpus: &'a str) -> Result<Self> {
+        for (name, p) in &[
+            ("--multiline-prob", opt.multiline_prob),
+            ("--metadata-prob", opt.metadata_prob),
+        ] {
+            if !(0.0..=1.0).contains(p) {
+                return Err(Error::InvalidArgument(format!(
+                    "{} must be between 0 and 1",
+                    name
+                )));
+            }
+        }
+
+        let tags = (0..opt.with_tags)
+            .map(|i| match TAGS.get(i) {
+                Some(tag) => (*tag).to_owned(),
+                None => format!("tag{}", i + 1),
+            })
+            .collect();
+
+        Ok(Generator {
+            text: Text::new(opt, corpus)?,
+            tags,
+            multiline_prob: opt.multiline_prob,
+            metadata_prob: opt.metadata_prob,
+        })
+    }
+
+    fn entry(&self, datetime: DateTime<FixedOffset>, rng: &mut impl Rng) -> Entry {
+        let mut message = self.text.line(rng);
+        if rng.gen_bool(self.multiline_prob) {
+            for _ in 1..rng.gen_range(2..=MAX_LINES) {
+                message.push('\n');
+                message.push_str(&self.text.line(rng));
+            }
+        }
+
+        if !self.tags.is_empty() {
+            for _ in 0..rng.gen_range(0..=3) {
+                // Squaring skews the choice towards the start of the list, so a
+                // few tags are very common and the rest are rare.
+                let i = (rng.gen::<f64>().powi(2) * self.tags.len() as f64) as usize;
+                message.push_str(" #");
+                message.push_str(&self.tags[i]);
+            }
+        }
+
+        let mut metadata = Metadata::new();
+        if rng.gen_bool(self.metadata_prob) {
+            metadata.insert("mood".to_owned(), rng.gen_range(1..=5u8).into());
+        }
+
+        Entry::new(datetime, message).with_metadata(metadata)
+    }
+}
+
+fn message_len(rng: &mut impl Rng) -> usize {
+    let u: f64 = rng.gen();
+    let n = (-(1.0 - u).ln() * MEAN_WORDS).ceil() as usize;
+    n.clamp(1, MAX_WORDS)
 }
 
 fn main() {
     setup_panic!();
 
-    if let Err(e) = app(&Opt::from_args()) {
-        eprintln!("{}", e);
-        exit(1);
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
     }
 }
 
 fn app(opt: &Opt) -> Result<()> {
-    let mut fopts = std::fs::OpenOptions::new();
-    fopts.create_new(true);
-    fopts.write(true);
-
-    let f = match fopts.open(&opt.path) {
-        Ok(f) => f,
-        Err(e) => {
-            return Err(format!(
-                "error creating file at {}: {}",
-                opt.path.to_string_lossy(),
-                e
-            )
-            .into())
+    let corpus = match opt.corpus {
+        Corpus::File(ref path) => {
+            std::fs::read_to_string(path).map_err(|source| Error::OpenFile {
+                path: path.clone(),
+                source,
+            })?
         }
+        _ => JOURNAL_SAMPLE.to_owned(),
+    };
+    let generator = Generator::new(opt, &corpus)?;
+    let seed = opt.seed.unwrap_or_else(rand::random);
+
+    if opt.entries_per_day == 0 {
+        return Err(Error::InvalidArgument(
+            "--entries-per-day must be greater than 0".to_owned(),
+        ));
+    }
+    let total = opt
+        .entries_per_day
+        .checked_mul(opt.num_days)
+        .ok_or_else(|| {
+            Error::InvalidArgument(
+                "--entries-per-day times --num-days is too many entries".to_owned(),
+            )
+        })?;
+    // The step is at most a day in seconds, so it always fits in an i64.
+    let step = ((24 * 60 * 60) / opt.entries_per_day) as i64;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.jobs)
+        .build()
+        .map_err(|e| Error::InvalidArgument(format!("couldn't start threads: {}", e)))?;
+
+    // The last entry's time is worked out before anything is written, so that
+    // asking for more entries than fit before the last date hmm can store fails
+    // without leaving a half-written file behind.
+    let first = |last: Option<DateTime<FixedOffset>>| -> Result<DateTime<FixedOffset>> {
+        let start = match (last, &opt.start) {
+            (Some(last), _) => nth_time(last, step, 1)?,
+            (None, Some(start)) => DateTime::parse_from_rfc3339(start)?,
+            (None, None) => {
+                let now: DateTime<FixedOffset> = Utc::now().into();
+                i64::try_from(opt.num_days)
+                    .ok()
+                    .and_then(Duration::try_days)
+                    .and_then(|days| now.checked_sub_signed(days))
+                    .ok_or_else(|| {
+                        Error::InvalidArgument("--num-days goes back too far".to_owned())
+                    })?
+            }
+        };
+        nth_time(start, step, total.saturating_sub(1))?;
+        Ok(start)
+    };
+
+    let (f, start) = if opt.append {
+        let (f, last) = open_append(opt)?;
+        (f, first(last)?)
+    } else {
+        let start = first(None)?;
+        let f = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&opt.path)
+            .map_err(|source| Error::CreateFile {
+                path: opt.path.clone(),
+                source,
+            })?;
+        (f, start)
     };
 
     let mut w = BufWriter::new(f);
-    let now: DateTime<FixedOffset> = Utc::now().into();
-    let start = now
-        .checked_sub_signed(Duration::days(opt.num_days as i64))
-        .unwrap();
-    let step = Duration::seconds((24 * 60 * 60) / opt.entries_per_day as i64);
 
     let sty = indicatif::ProgressStyle::default_bar()
         .template("[{elapsed_precise}] {wide_bar:.cyan/blue} {pos}/{len} {percent}% {eta_precise}")
         .unwrap()
         .progress_chars("##-");
-    let pb = indicatif::ProgressBar::new(opt.entries_per_day * opt.num_days);
+    let pb = indicatif::ProgressBar::new(total);
     pb.enable_steady_tick(std::time::Duration::from_millis(100));
     pb.set_style(sty);
 
-    for i in 0..(opt.entries_per_day * opt.num_days) {
-        let t = start.checked_add_signed(step * i as i32).unwrap();
-        Entry::new(
-            t,
-            opt.message
-                .clone()
-                .unwrap_or_else(|| lipsum::lipsum_words(20)),
-        )
-        .write(&mut w)?;
-        pb.inc(1);
+    let began = Instant::now();
+    let chunks = total.div_ceil(ENTRIES_PER_CHUNK);
+    let chunks_per_round = (CHUNKS_PER_THREAD * pool.current_num_threads()) as u64;
+    let mut bytes = 0;
+
+    for round in (0..chunks).step_by(chunks_per_round as usize) {
+        let bufs: Vec<Vec<u8>> = pool.install(|| {
+            (round..chunks.min(round + chunks_per_round))
+                .into_par_iter()
+                .map(|chunk| {
+                    // Each chunk gets its own generator seeded from its
+                    // position in the file, so the output doesn't depend on
+                    // which thread generated it or how many threads there are.
+                    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(chunk));
+                    let mut w = JournalWriter::new(Vec::new());
+                    let first = chunk * ENTRIES_PER_CHUNK;
+                    for i in first..total.min(first + ENTRIES_PER_CHUNK) {
+                        let t = nth_time(start, step, i)?;
+                        w.append(&generator.entry(t, &mut rng))?;
+                    }
+                    w.finish()
+                })
+                .collect::<Result<_>>()
+        })?;
+
+        for buf in bufs {
+            w.write_all(&buf)?;
+            bytes += buf.len() as u64;
+            pb.inc(ENTRIES_PER_CHUNK.min(total - pb.position()));
+        }
     }
 
-    pb.finish();
+    w.flush()?;
+    pb.finish_and_clear();
+
+    let secs = began.elapsed().as_secs_f64().max(f64::EPSILON);
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    eprintln!(
+        "wrote {} entries ({:.1} MB) in {:.2}s: {:.0} entries/sec, {:.1} MB/sec",
+        total,
+        mb,
+        secs,
+        total as f64 / secs,
+        mb / secs
+    );
 
     Ok(())
 }
+
+/// The time of the `n`th entry after `start`, with `step` seconds between
+/// each one.
+fn nth_time(start: DateTime<FixedOffset>, step: i64, n: u64) -> Result<DateTime<FixedOffset>> {
+    i64::try_from(n)
+        .ok()
+        .and_then(|n| step.checked_mul(n))
+        .and_then(Duration::try_seconds)
+        .and_then(|offset| start.checked_add_signed(offset))
+        .ok_or_else(|| {
+            Error::InvalidArgument(
+                "that many entries would go past the last date hmm can store".to_owned(),
+            )
+        })
+}
+
+/// Opens the file at --path to add more entries to, returning it along with
+/// the time of the last entry in it, if it has any. The file is locked so that
+/// nothing else writes to it while we do.
+fn open_append(opt: &Opt) -> Result<(File, Option<DateTime<FixedOffset>>)> {
+    let f = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .open(&opt.path)
+        .map_err(|source| Error::OpenFile {
+            path: opt.path.clone(),
+            source,
+        })?;
+    journal::lock(&f, std::time::Duration::from_secs(10))?;
+
+    let last = Entries::new(BufReader::new(&f))
+        .next_back()
+        .transpose()?
+        .map(|entry| *entry.datetime());
+
+    if !journal::ends_with_newline(&f)? {
+        (&f).write_all(b"\n")?;
+    }
+
+    Ok((f, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::assert::Assert;
+    use escargot::{CargoBuild, CargoRun};
+    use hmmcli::entry::Entry;
+    use lazy_static::lazy_static;
+    use std::convert::TryInto;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMDG: CargoRun = CargoBuild::new()
+            .bin("hmmdg")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    fn generate(path: &Path, args: &[&str]) -> Assert {
+        assert_cmd::Command::from_std(HMMDG.command())
+            .arg("--path")
+            .arg(path)
+            .args(["--entries-per-day", "24", "--num-days", "2"])
+            .args(["--start", "2020-01-01T00:00:00+00:00"])
+            .args(args)
+            .assert()
+    }
+
+    fn generate_to_string(dir: &TempDir, name: &str, args: &[&str]) -> String {
+        let path = dir.path().join(name);
+        generate(&path, args).success();
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test_case(&["--corpus", "lipsum"] ; "lipsum")]
+    #[test_case(&["--corpus", "markov"] ; "markov")]
+    fn test_same_seed_same_output(args: &[&str]) {
+        let dir = TempDir::new().unwrap();
+        let args: Vec<&str> = args.iter().chain(&["--seed", "42"]).cloned().collect();
+
+        let a = generate_to_string(&dir, "a.hmm", &args);
+        let b = generate_to_string(&dir, "b.hmm", &args);
+
+        assert_eq!(a.lines().count(), 48);
+        assert!(a.starts_with("2020-01-01T00:00:00+00:00,"), "{}", a);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_jobs_same_output() {
+        let dir = TempDir::new().unwrap();
+        let run = |name: &str, jobs: &str| {
+            let path = dir.path().join(name);
+            assert_cmd::Command::from_std(HMMDG.command())
+                .arg("--path")
+                .arg(&path)
+                .args(["--entries-per-day", "50000", "--num-days", "1"])
+                .args(["--start", "2020-01-01T00:00:00+00:00"])
+                .args(["--seed", "3", "--jobs", jobs])
+                .assert()
+                .success();
+            fs::read_to_string(path).unwrap()
+        };
+
+        let a = run("a.hmm", "1");
+        let b = run("b.hmm", "4");
+        assert_eq!(a.lines().count(), 50000);
+        assert!(a == b, "output differs depending on --jobs");
+    }
+
+    #[test]
+    fn test_summary() {
+        let dir = TempDir::new().unwrap();
+        let assert = generate(&dir.path().join("out.hmm"), &[]).success();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.starts_with("wrote 48 entries"), "{}", stderr);
+        assert!(stderr.contains("entries/sec"), "{}", stderr);
+        assert!(stderr.contains("MB/sec"), "{}", stderr);
+    }
+
+    #[test]
+    fn test_different_seed_different_output() {
+        let dir = TempDir::new().unwrap();
+        let a = generate_to_string(&dir, "a.hmm", &["--seed", "1"]);
+        let b = generate_to_string(&dir, "b.hmm", &["--seed", "2"]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_file_corpus() {
+        let dir = TempDir::new().unwrap();
+        let corpus = dir.path().join("corpus.txt");
+        fs::write(&corpus, "apple banana cherry apple banana cherry apple\n").unwrap();
+
+        let out = generate_to_string(
+            &dir,
+            "out.hmm",
+            &["--corpus", &format!("file:{}", corpus.to_str().unwrap())],
+        );
+
+        for line in out.lines() {
+            let message = line.split_once(',').unwrap().1.to_lowercase();
+            for word in message.trim_matches('"').split_whitespace() {
+                let word = word.trim_end_matches('.');
+                assert!(
+                    ["apple", "banana", "cherry"].contains(&word),
+                    "unexpected word {:?} in {:?}",
+                    word,
+                    line
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tags_multiline_and_metadata() {
+        let dir = TempDir::new().unwrap();
+        let args = [
+            "--seed",
+            "7",
+            "--with-tags",
+            "3",
+            "--multiline-prob",
+            "0.5",
+            "--metadata-prob",
+            "0.5",
+        ];
+        let out = generate_to_string(&dir, "out.hmm", &args);
+        let entries: Vec<Entry> = out.lines().map(|l| l.try_into().unwrap()).collect();
+
+        assert_eq!(entries.len(), 48);
+        assert!(entries.iter().any(|e| e.message().contains('\n')));
+        assert!(entries.iter().any(|e| !e.message().contains('\n')));
+        assert!(entries.iter().any(|e| !e.metadata().is_empty()));
+        assert!(entries.iter().any(|e| e.metadata().is_empty()));
+        assert!(entries.iter().any(|e| !e.tags().is_empty()));
+        for entry in &entries {
+            for tag in entry.tags() {
+                assert!(["work", "home", "idea"].contains(&tag), "{}", tag);
+            }
+            if let Some(mood) = entry.metadata().get("mood") {
+                assert!((1..=5).contains(&mood.as_u64().unwrap()));
+            }
+        }
+
+        assert_eq!(out, generate_to_string(&dir, "again.hmm", &args));
+    }
+
+    #[test]
+    fn test_fixed_message() {
+        let dir = TempDir::new().unwrap();
+        let out = generate_to_string(&dir, "out.hmm", &["--message", "hello"]);
+        assert!(out.lines().all(|l| l.ends_with(",\"\"\"hello\"\"\"")));
+    }
+
+    #[test_case(&["--corpus", "shakespeare"] => 1  ; "unknown corpus")]
+    #[test_case(&["--corpus", "file:/this/path/does/not/exist"] => 66 ; "missing corpus file")]
+    #[test_case(&["--multiline-prob", "1.5"] => 64 ; "probability too big")]
+    fn test_bad_corpus(args: &[&str]) -> i32 {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.hmm");
+        let assert = generate(&path, args);
+        assert!(!path.exists());
+        assert.get_output().status.code().unwrap()
+    }
+
+    #[test_case(&["--entries-per-day", "0", "--num-days", "2"] ; "no entries per day")]
+    #[test_case(&["--entries-per-day", "4294967296", "--num-days", "4294967296"] ; "too many entries")]
+    #[test_case(&["--entries-per-day", "1", "--num-days", "1000000000", "--start", "2020-01-01T00:00:00+00:00"] ; "past the last date")]
+    #[test_case(&["--entries-per-day", "1", "--num-days", "1000000000000000"] ; "before the first date")]
+    fn test_bad_counts(args: &[&str]) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.hmm");
+        assert_cmd::Command::from_std(HMMDG.command())
+            .arg("--path")
+            .arg(&path)
+            .args(args)
+            .assert()
+            .code(64);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.hmm");
+        fs::write(&path, "2020-01-01T00:00:00+00:00,\"\"\"first\"\"\"").unwrap();
+
+        // --start makes no sense with --append, which the generate helper sets.
+        generate(&path, &["--append", "--message", "more"]).code(1);
+
+        assert_cmd::Command::from_std(HMMDG.command())
+            .arg("--path")
+            .arg(&path)
+            .args(["--entries-per-day", "24", "--num-days", "1"])
+            .args(["--append", "--message", "more"])
+            .assert()
+            .success();
+
+        let entries: Vec<Entry> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| l.try_into().unwrap())
+            .collect();
+        assert_eq!(entries.len(), 25);
+        assert_eq!(entries[0].message(), "first");
+        assert_eq!(
+            entries[1].datetime().to_rfc3339(),
+            "2020-01-01T01:00:00+00:00"
+        );
+        assert_eq!(
+            entries[24].datetime().to_rfc3339(),
+            "2020-01-02T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_append_missing_file() {
+        let dir = TempDir::new().unwrap();
+        assert_cmd::Command::from_std(HMMDG.command())
+            .arg("--path")
+            .arg(dir.path().join("out.hmm"))
+            .arg("--append")
+            .assert()
+            .code(66);
+    }
+
+    #[test]
+    fn test_refuses_to_overwrite() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.hmm");
+        fs::write(&path, "").unwrap();
+        generate(&path, &[]).code(73);
+    }
+}
@@ -0,0 +1,367 @@
+use hmmcli::{
+    config::Config,
+    crypt,
+    entries::Entries,
+    entry::Entry,
+    error::{Error, ErrorFormat},
+    format::Format,
+    fuzzy,
+    pins::{self, Pins},
+    remote::Remote,
+    storage::Storage,
+    text, Result,
+};
+use human_panic::setup_panic;
+use std::fs::File;
+use std::io::{BufReader, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use structopt::StructOpt;
+use termimad::crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyModifiers},
+    execute, queue,
+    style::{Print, ResetColor},
+    terminal::{self, ClearType},
+};
+
+// How entries are shown, the same as hmmq's default format.
+const DEFAULT_FORMAT: &str = "╭ {{ color \"blue\" (strftime \"%Y-%m-%d %H:%M\" datetime) }}\n{{ indent (markdown message) }}╰─────────────────";
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hmmf",
+    about = "Search your hmm file as you type, and print or copy the entry you pick"
+)]
+struct Opt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// What to start off searching for.
+    #[structopt(long = "query", default_value = "")]
+    query: String,
+
+    /// Print every entry matching this, best match first, instead of picking
+    /// one. This doesn't need a terminal, so it works in scripts.
+    #[structopt(long = "filter", conflicts_with_all = &["query", "copy"])]
+    filter: Option<String>,
+
+    /// Copy the message of the entry you pick to the clipboard instead of
+    /// printing it.
+    #[structopt(long = "copy")]
+    copy: bool,
+
+    /// The Handlebars template entries are shown and printed with, the same
+    /// as hmmq's --format.
+    #[structopt(long = "format")]
+    format: Option<String>,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+/// Lets you pick an entry by typing bits of it, showing how each one looks as
+/// you move through them, then prints it or copies it to the clipboard.
+fn app(opt: &Opt) -> Result<()> {
+    let path = opt
+        .path
+        .clone()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+    if Remote::parse(&path)?.is_some() {
+        return Err(Error::InvalidArgument(format!(
+            "{} isn't on this machine, run hmmf where it is",
+            path.display()
+        )));
+    }
+    let config = Config::load_or_default(opt.config.as_deref())?;
+    let mut format = Format::with_template(opt.format.as_deref().unwrap_or(DEFAULT_FORMAT))?;
+    format.set_color_rules(config.color_rules.clone());
+    let color = std::io::stdout().is_terminal();
+
+    // Pinned entries first, then the newest, as those are the ones most
+    // likely to be looked for.
+    let pins = pins::load_default()?;
+    let mut entries = read_all(&path)?;
+    entries.reverse();
+    entries.sort_by_cached_key(|entry| !pins.contains(&entry.id()));
+    if let Some(ref command) = config.decrypt_command {
+        for entry in entries.iter_mut() {
+            if crypt::is_encrypted(entry.message(), entry.metadata()) {
+                *entry = crypt::decrypt_entry(command, entry)?;
+            }
+        }
+    }
+
+    if let Some(query) = &opt.filter {
+        let messages: Vec<&str> = entries.iter().map(Entry::message).collect();
+        format.set_color(color);
+        for i in fuzzy::rank(query, &messages) {
+            println!("{}", format.format_entry(&entries[i])?);
+        }
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Err(Error::InvalidArgument(
+            "hmmf needs a terminal, use --filter to search without one".to_owned(),
+        ));
+    }
+    let entry = match pick(&entries, &pins, &mut format, &opt.query)? {
+        Some(i) => &entries[i],
+        None => return Ok(()),
+    };
+
+    if opt.copy {
+        let mut clipboard =
+            arboard::Clipboard::new().map_err(|e| Error::Clipboard(e.to_string()))?;
+        return clipboard
+            .set_text(entry.message())
+            .map_err(|e| Error::Clipboard(e.to_string()));
+    }
+    format.set_color(color);
+    println!("{}", format.format_entry(entry)?);
+    Ok(())
+}
+
+/// Reads every entry in the journal at `path`, without creating it if it
+/// doesn't exist.
+fn read_all(path: &Path) -> Result<Vec<Entry>> {
+    match Storage::parse(path)? {
+        Storage::File(path) => {
+            let f = File::open(&path).map_err(|source| Error::OpenFile { path, source })?;
+            Entries::new(BufReader::new(f)).collect()
+        }
+        #[cfg(feature = "object-storage")]
+        Storage::Object(store) => Entries::new(BufReader::new(store.reader()?)).collect(),
+    }
+}
+
+// Puts the terminal back the way it was however hmmf finishes.
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Screen> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            std::io::stderr(),
+            terminal::EnterAlternateScreen,
+            cursor::Show
+        )?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        execute!(std::io::stderr(), terminal::LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Runs the finder, drawing it on stderr so that what's picked can be piped
+/// somewhere. Pinned entries are marked with a pin. Returns the index of the
+/// entry that was picked, or None if it was cancelled.
+fn pick(entries: &[Entry], pins: &Pins, format: &mut Format, query: &str) -> Result<Option<usize>> {
+    let messages: Vec<&str> = entries.iter().map(Entry::message).collect();
+    let mut query = query.to_owned();
+    let mut matches = fuzzy::rank(&query, &messages);
+    let mut selected = 0;
+    let mut top = 0;
+
+    let _screen = Screen::enter()?;
+    let mut out = std::io::stderr();
+    format.set_color(true);
+    loop {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        // The matches take up the top half of the screen, under the query,
+        // and the one that's selected is shown in the bottom half.
+        let rows = (height.saturating_sub(2) / 2).max(1);
+        if selected < top {
+            top = selected;
+        } else if selected >= top + rows {
+            top = selected + 1 - rows;
+        }
+
+        let prompt = format!("> {}", query);
+        let count = format!("  {}/{}", matches.len(), entries.len());
+        queue!(
+            out,
+            terminal::Clear(ClearType::All),
+            cursor::MoveTo(0, 0),
+            Print(text::truncate(&format!("{}{}", prompt, count), width, "…"))
+        )?;
+        for (row, &i) in matches.iter().enumerate().skip(top).take(rows) {
+            let entry = &entries[i];
+            let line = format!(
+                "{} {} {}{}",
+                if row == selected { ">" } else { " " },
+                entry.datetime().format("%Y-%m-%d %H:%M"),
+                if pins.contains(&entry.id()) {
+                    "📌 "
+                } else {
+                    ""
+                },
+                entry.title()
+            );
+            queue!(
+                out,
+                cursor::MoveTo(0, (row - top + 1) as u16),
+                Print(text::truncate(&line, width, "…"))
+            )?;
+        }
+        queue!(
+            out,
+            cursor::MoveTo(0, (rows + 1) as u16),
+            Print("─".repeat(width))
+        )?;
+        if let Some(&i) = matches.get(selected) {
+            let preview = format.format_entry(&entries[i])?;
+            let lines = preview.lines().take(height.saturating_sub(rows + 2));
+            for (row, line) in lines.enumerate() {
+                queue!(
+                    out,
+                    cursor::MoveTo(0, (rows + 2 + row) as u16),
+                    Print(text::truncate(line, width, "…")),
+                    ResetColor
+                )?;
+            }
+        }
+        let column = text::display_width(&prompt).min(width.saturating_sub(1));
+        queue!(out, cursor::MoveTo(column as u16, 0))?;
+        out.flush()?;
+
+        let key = match event::read()? {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (key.code, ctrl) {
+            (KeyCode::Enter, _) => return Ok(matches.get(selected).copied()),
+            (KeyCode::Esc, _) | (KeyCode::Char('c'), true) | (KeyCode::Char('g'), true) => {
+                return Ok(None)
+            }
+            (KeyCode::Up, _) | (KeyCode::Char('p'), true) => selected = selected.saturating_sub(1),
+            (KeyCode::Down, _) | (KeyCode::Char('n'), true) => {
+                selected = (selected + 1).min(matches.len().saturating_sub(1))
+            }
+            (KeyCode::Backspace, _) | (KeyCode::Char('u'), true) | (KeyCode::Char(_), false) => {
+                match key.code {
+                    KeyCode::Char(c) if !ctrl => query.push(c),
+                    KeyCode::Backspace => {
+                        query.pop();
+                    }
+                    _ => query.clear(),
+                }
+                matches = fuzzy::rank(&query, &messages);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::prelude::*;
+    use escargot::{CargoBuild, CargoRun};
+    use hmmcli::entries::Entries;
+    use hmmcli::entry::Entry;
+    use lazy_static::lazy_static;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::PathBuf;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMF: CargoRun = CargoBuild::new()
+            .bin("hmmf")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    fn new_tempfile(content: &str) -> PathBuf {
+        let path = NamedTempFile::new().unwrap().keep().unwrap().1;
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    const TESTDATA: &str = "2024-05-12T23:00:00+00:00,\"\"\"last week\"\"\"
+2024-05-13T09:00:00+00:00,\"\"\"standup #work\\nTODO: book a room\"\"\"
+2024-05-14T12:00:00+00:00,\"\"\"lunch #friends #work\"\"\"
+2024-06-01T09:00:00+00:00,\"\"\"next month #garden\"\"\"
+";
+
+    #[test_case("work"      => "lunch #friends #work\nstandup #work\nTODO: book a room\n" ; "newest first when tied")]
+    #[test_case("wk"        => "lunch #friends #work\nstandup #work\nTODO: book a room\nlast week\n" ; "fuzzy")]
+    #[test_case("fri lunch" => "lunch #friends #work\n" ; "every word")]
+    #[test_case("xyzzy"     => "" ; "nothing")]
+    fn test_hmmf_filter(query: &str) -> String {
+        let assert = HMMF
+            .command()
+            .args(["--format", "{{ message }}", "--filter", query, "--path"])
+            .arg(new_tempfile(TESTDATA))
+            .assert();
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmmf_pinned_first() {
+        let config = tempfile::TempDir::new().unwrap();
+        let path = new_tempfile(TESTDATA);
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::create_dir(config.path().join("hmm")).unwrap();
+        std::fs::write(
+            config.path().join("hmm").join("pins"),
+            format!("{}\n{}\n", entries[2].id(), entries[0].id()),
+        )
+        .unwrap();
+
+        HMMF.command()
+            .env("XDG_CONFIG_HOME", config.path())
+            .args(["--format", "{{ message }}", "--filter", "", "--path"])
+            .arg(&path)
+            .assert()
+            .success()
+            .stdout("lunch #friends #work\nlast week\nnext month #garden\nstandup #work\nTODO: book a room\n");
+    }
+
+    #[test_case(vec![] => 64 ; "no terminal")]
+    #[test_case(vec!["--filter", "a", "--query", "b"] => 1 ; "filter and query")]
+    #[test_case(vec!["--filter", "a", "--copy"] => 1 ; "filter and copy")]
+    #[test_case(vec!["--filter", "a", "--format", "{{"] => 64 ; "bad format")]
+    #[test_case(vec!["--filter", "a", "--path", "/this/path/does/not/exist"] => 66 ; "missing journal")]
+    #[test_case(vec!["--path", "ssh://host/.hmm"] => 64 ; "remote")]
+    fn test_hmmf_errors(args: Vec<&str>) -> i32 {
+        let mut cmd = HMMF.command();
+        if !args.contains(&"--path") {
+            cmd.arg("--path").arg(new_tempfile(TESTDATA));
+        }
+        cmd.args(args).assert().get_output().status.code().unwrap()
+    }
+}
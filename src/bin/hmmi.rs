@@ -0,0 +1,585 @@
+use hmmcli::{
+    config::Config,
+    crypt,
+    entry::Entry,
+    error::{Error, ErrorFormat},
+    import::{pattern::Pattern, ImportOptions, Source},
+    journal::{self, AppendOptions},
+    lock, Result,
+};
+use human_panic::setup_panic;
+use std::fs::OpenOptions;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hmmi",
+    about = "Import entries in to your hmm file from other formats"
+)]
+struct Opt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// The format of the files being imported. "org" reads org-mode files,
+    /// including those written by org-journal, "git" reads the commits in git
+    /// repositories, "zsh", "bash" and "fish" read shell history files,
+    /// "slack" reads Slack export zip files, "irc" reads IRC logs and
+    /// "maildir" reads maildirs, or single emails.
+    #[structopt(
+        long = "from",
+        possible_values = &["org", "git", "zsh", "bash", "fish", "slack", "irc", "maildir"],
+        required_unless = "pattern"
+    )]
+    from: Option<Source>,
+
+    /// Import a line-based log, where each line matches this pattern. It's
+    /// made of strftime specifiers for the date and time and {message} for the
+    /// message, e.g. "%Y-%m-%d %H:%M | {message}". Lines that don't match are
+    /// added to the message of the line before them.
+    #[structopt(long = "pattern", conflicts_with = "from")]
+    pattern: Option<Pattern>,
+
+    /// Print the first N entries that would be imported, as they'd be written
+    /// to your hmm file, without writing anything.
+    #[structopt(long = "preview")]
+    preview: Option<usize>,
+
+    /// Only import commits by authors matching this pattern, as in
+    /// `git log --author`. Only used with --from git.
+    #[structopt(long = "author")]
+    author: Option<String>,
+
+    /// Whose messages to import, by their username, display name or ID in
+    /// Slack, or their nick in IRC. Needed for --from slack and --from irc.
+    #[structopt(long = "user")]
+    user: Option<String>,
+
+    /// How many seconds to wait for another hmm process to finish writing to
+    /// your hmm file before giving up.
+    #[structopt(
+        long = "lock-timeout",
+        env = "HMM_LOCK_TIMEOUT",
+        default_value = "10",
+        parse(try_from_str = lock::parse_timeout)
+    )]
+    lock_timeout: Duration,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+
+    /// Files to import. Use - to read from stdin. If no files are given,
+    /// stdin is read, or with --from git, the repository in the current
+    /// directory, or for shells, the shell's default history file.
+    files: Vec<PathBuf>,
+}
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+fn app(opt: &Opt) -> Result<()> {
+    if opt.author.is_some() && opt.from != Some(Source::Git) {
+        return Err(Error::InvalidArgument(
+            "--author can only be used with --from git".to_owned(),
+        ));
+    }
+
+    let messages = matches!(opt.from, Some(Source::Slack) | Some(Source::Irc));
+    if opt.user.is_some() && !messages {
+        return Err(Error::InvalidArgument(
+            "--user can only be used with --from slack or --from irc".to_owned(),
+        ));
+    }
+    if messages && opt.user.is_none() {
+        return Err(Error::InvalidArgument(
+            "--from slack and --from irc need --user to say whose messages to import".to_owned(),
+        ));
+    }
+
+    let config = Config::load_or_default(opt.config.as_deref())?;
+
+    let import_opts = ImportOptions {
+        author: opt.author.clone(),
+        user: opt.user.clone(),
+    };
+    let read = |path: &Path| match (&opt.pattern, opt.from) {
+        (Some(pattern), _) => pattern.read(path),
+        (None, Some(from)) => from.read(path, &import_opts),
+        (None, None) => Err(Error::InvalidArgument(
+            "either --from or --pattern must be given".to_owned(),
+        )),
+    };
+
+    let mut inputs = opt.files.clone();
+    if inputs.is_empty() {
+        inputs.push(match opt.from {
+            Some(from) => from.default_input(),
+            None => PathBuf::from("-"),
+        });
+    }
+
+    // Everything is read before anything is written, so that your hmm file is
+    // only rewritten once. Inputs that can't be read or parsed are skipped,
+    // with a warning, and the rest are imported.
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for path in inputs {
+        let read: Result<Vec<Entry>> =
+            read(&path).and_then(|records| records.into_iter().map(|r| r.into_entry()).collect());
+        match read {
+            Ok(read) => entries.extend(read),
+            Err(e) => {
+                let source = match path.to_string_lossy() {
+                    s if s == "-" => "<stdin>".into(),
+                    s => s,
+                };
+                opt.errors.warn(&source, &e);
+                skipped += 1;
+            }
+        }
+    }
+
+    if let Some(n) = opt.preview {
+        preview(&mut entries, n)?;
+        return match skipped {
+            0 => Ok(()),
+            skipped => Err(Error::SkippedFiles(skipped)),
+        };
+    }
+
+    let path = opt
+        .path
+        .clone()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+    let f = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(&path)
+        .map_err(|source| Error::OpenFile {
+            path: path.clone(),
+            source,
+        })?;
+
+    // Encrypting the same message twice gives different ciphertext with most
+    // tools, so encrypted entries aren't recognised as already being there if
+    // they're imported again.
+    if let Some(ref command) = config.encrypt_command {
+        entries = entries
+            .iter()
+            .map(|entry| crypt::encrypt_entry(command, entry))
+            .collect::<Result<_>>()?;
+    }
+
+    let found = entries.len();
+    let written = journal::insert(
+        &f,
+        &path,
+        entries,
+        &AppendOptions {
+            lock_timeout: opt.lock_timeout,
+            ..AppendOptions::default()
+        },
+    )?;
+
+    eprintln!(
+        "imported {} {}, {} already in {}",
+        written,
+        if written == 1 { "entry" } else { "entries" },
+        found - written,
+        path.display()
+    );
+
+    if skipped > 0 {
+        return Err(Error::SkippedFiles(skipped));
+    }
+
+    Ok(())
+}
+
+// Entries are previewed in the order they'd be written in, which is rarely
+// the order they were read in when importing more than one file.
+fn preview(entries: &mut [Entry], n: usize) -> Result<()> {
+    entries.sort_by(|a, b| a.datetime().cmp(b.datetime()));
+
+    let mut out = stdout().lock();
+    for entry in entries.iter().take(n) {
+        out.write_all(entry.to_csv_row()?.as_bytes())?;
+    }
+    out.flush()?;
+
+    eprintln!(
+        "previewed {} of {} {}, nothing was written",
+        n.min(entries.len()),
+        entries.len(),
+        if entries.len() == 1 {
+            "entry"
+        } else {
+            "entries"
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::assert::Assert;
+    use chrono::DateTime;
+    use escargot::{CargoBuild, CargoRun};
+    use hmmcli::entries::Entries;
+    use hmmcli::entry::Entry;
+    use lazy_static::lazy_static;
+    use std::fs::{self, File};
+    use std::io::BufReader;
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::TempDir;
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMI: CargoRun = CargoBuild::new()
+            .bin("hmmi")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    // Org timestamps don't have a timezone, so the tests pin the local one.
+    fn import(path: &Path, args: &[&str]) -> Assert {
+        assert_cmd::Command::from_std(HMMI.command())
+            .env("TZ", "UTC")
+            .arg("--path")
+            .arg(path)
+            .args(args)
+            .assert()
+    }
+
+    fn entries(path: &Path) -> Vec<(String, String)> {
+        Entries::new(BufReader::new(File::open(path).unwrap()))
+            .map(|e| e.unwrap())
+            .map(|e| (e.datetime().to_rfc3339(), e.message().to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_hmmi_org() {
+        let dir = TempDir::new().unwrap();
+        let hmm = dir.path().join(".hmm");
+        let existing = Entry::new(
+            DateTime::parse_from_rfc3339("2019-06-04T12:00:00+00:00").unwrap(),
+            "already here".to_owned(),
+        );
+        fs::write(&hmm, existing.to_csv_row().unwrap()).unwrap();
+
+        let org = dir.path().join("20190604");
+        fs::write(
+            &org,
+            "* 10:00 standup\nTalked about the release.\n* 13:00 lunch\n",
+        )
+        .unwrap();
+
+        import(&hmm, &["--from", "org", org.to_str().unwrap()])
+            .success()
+            .stderr(format!(
+                "imported 2 entries, 0 already in {}\n",
+                hmm.display()
+            ));
+
+        assert_eq!(
+            entries(&hmm),
+            vec![
+                (
+                    "2019-06-04T10:00:00+00:00".to_owned(),
+                    "standup\nTalked about the release.".to_owned()
+                ),
+                (
+                    "2019-06-04T12:00:00+00:00".to_owned(),
+                    "already here".to_owned()
+                ),
+                ("2019-06-04T13:00:00+00:00".to_owned(), "lunch".to_owned()),
+            ]
+        );
+
+        // Importing the same file again doesn't duplicate anything.
+        import(&hmm, &["--from", "org", org.to_str().unwrap()])
+            .success()
+            .stderr(format!(
+                "imported 0 entries, 2 already in {}\n",
+                hmm.display()
+            ));
+        assert_eq!(entries(&hmm).len(), 3);
+    }
+
+    #[test]
+    fn test_hmmi_stdin() {
+        let dir = TempDir::new().unwrap();
+        let hmm = dir.path().join(".hmm");
+
+        assert_cmd::Command::from_std(HMMI.command())
+            .env("TZ", "UTC")
+            .arg("--path")
+            .arg(&hmm)
+            .args(["--from", "org"])
+            .write_stdin("* <2020-01-02 Thu 09:15> hello\n")
+            .assert()
+            .success();
+
+        assert_eq!(
+            entries(&hmm),
+            vec![("2020-01-02T09:15:00+00:00".to_owned(), "hello".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_hmmi_skips_bad_files() {
+        let dir = TempDir::new().unwrap();
+        let hmm = dir.path().join(".hmm");
+        let good = dir.path().join("good.org");
+        let bad = dir.path().join("bad.org");
+        fs::write(&good, "* 2019-06-04\n** 10:00 fine\n").unwrap();
+        fs::write(&bad, "* no date here\n").unwrap();
+
+        let assert = import(
+            &hmm,
+            &[
+                "--from",
+                "org",
+                bad.to_str().unwrap(),
+                good.to_str().unwrap(),
+            ],
+        )
+        .code(65);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(
+            stderr.contains(&format!(
+                "warning: {}: malformed entry on line 1",
+                bad.display()
+            )),
+            "{}",
+            stderr
+        );
+        assert!(
+            stderr.ends_with("1 file couldn't be imported and was skipped\n"),
+            "{}",
+            stderr
+        );
+
+        assert_eq!(
+            entries(&hmm),
+            vec![("2019-06-04T10:00:00+00:00".to_owned(), "fine".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_hmmi_git() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path().join("repo");
+        let git = |args: &[&str], date: &str| {
+            assert!(Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(["-c", "commit.gpgsign=false"])
+                .args(args)
+                .env("GIT_AUTHOR_NAME", "me")
+                .env("GIT_AUTHOR_EMAIL", "me@example.com")
+                .env("GIT_COMMITTER_NAME", "me")
+                .env("GIT_COMMITTER_EMAIL", "me@example.com")
+                .env("GIT_COMMITTER_DATE", date)
+                .status()
+                .unwrap()
+                .success());
+        };
+        fs::create_dir(&repo).unwrap();
+        git(&["init", "-q"], "");
+        git(
+            &["commit", "-q", "--allow-empty", "-m", "subject\n\nbody"],
+            "2020-01-01T09:00:00+02:00",
+        );
+
+        let hmm = dir.path().join(".hmm");
+        import(
+            &hmm,
+            &["--from", "git", "--author", "me", repo.to_str().unwrap()],
+        )
+        .success();
+
+        let imported: Vec<Entry> = Entries::new(BufReader::new(File::open(&hmm).unwrap()))
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(imported.len(), 1);
+        assert_eq!(
+            imported[0].datetime().to_rfc3339(),
+            "2020-01-01T09:00:00+02:00"
+        );
+        assert_eq!(imported[0].message(), "subject\n\nbody");
+        assert_eq!(imported[0].metadata()["sha"].as_str().unwrap().len(), 40);
+        assert!(imported[0].metadata()["repo"]
+            .as_str()
+            .unwrap()
+            .ends_with("repo"));
+    }
+
+    #[test]
+    fn test_hmmi_author_needs_git() {
+        let dir = TempDir::new().unwrap();
+        import(
+            &dir.path().join(".hmm"),
+            &["--from", "org", "--author", "me"],
+        )
+        .code(64);
+    }
+
+    #[test]
+    fn test_hmmi_irc() {
+        let dir = TempDir::new().unwrap();
+        let hmm = dir.path().join(".hmm");
+        let log = dir.path().join("#notes.log");
+        fs::write(
+            &log,
+            "[2020-01-02 09:00:00] *** sam joined #notes\n[2020-01-02 09:00:30] <sam> renew the domain\n[2020-01-02 09:01:00] <alex> ok\n",
+        )
+        .unwrap();
+
+        import(
+            &hmm,
+            &["--from", "irc", "--user", "sam", log.to_str().unwrap()],
+        )
+        .success();
+        assert_eq!(
+            entries(&hmm),
+            vec![(
+                "2020-01-02T09:00:30+00:00".to_owned(),
+                "renew the domain #notes".to_owned()
+            )]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hmmi_encrypt_command() {
+        let dir = TempDir::new().unwrap();
+        let hmm = dir.path().join(".hmm");
+        let config = dir.path().join("config.toml");
+        fs::write(&config, "encrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"").unwrap();
+        let org = dir.path().join("20190604");
+        fs::write(&org, "* 10:00 hello\n").unwrap();
+
+        import(
+            &hmm,
+            &[
+                "--config",
+                config.to_str().unwrap(),
+                "--from",
+                "org",
+                org.to_str().unwrap(),
+            ],
+        )
+        .success();
+        assert_eq!(
+            entries(&hmm),
+            vec![(
+                "2019-06-04T10:00:00+00:00".to_owned(),
+                "hmm-encrypted:dXJ5eWI=".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_hmmi_maildir() {
+        let dir = TempDir::new().unwrap();
+        let hmm = dir.path().join(".hmm");
+        let maildir = dir.path().join("journal");
+        fs::create_dir_all(maildir.join("new")).unwrap();
+        fs::write(
+            maildir.join("new/1"),
+            "Subject: renew the domain\nDate: Thu, 2 Jan 2020 09:00:30 +0100\n\nbefore it runs out\n",
+        )
+        .unwrap();
+
+        import(&hmm, &["--from", "maildir", maildir.to_str().unwrap()]).success();
+        assert_cmd::Command::from_std(HMMI.command())
+            .arg("--path")
+            .arg(&hmm)
+            .args(["--from", "maildir"])
+            .write_stdin("Subject: lunch\nDate: Thu, 2 Jan 2020 12:00:00 +0000\n\n")
+            .assert()
+            .success();
+        assert_eq!(
+            entries(&hmm),
+            vec![
+                (
+                    "2020-01-02T09:00:30+01:00".to_owned(),
+                    "renew the domain\n\nbefore it runs out".to_owned()
+                ),
+                ("2020-01-02T12:00:00+00:00".to_owned(), "lunch".to_owned()),
+            ]
+        );
+    }
+
+    #[test_case(&["--from", "irc"]                   ; "irc without user")]
+    #[test_case(&["--from", "slack"]                 ; "slack without user")]
+    #[test_case(&["--from", "org", "--user", "sam"]  ; "user without messages")]
+    fn test_hmmi_user(args: &[&str]) {
+        let dir = TempDir::new().unwrap();
+        import(&dir.path().join(".hmm"), args).code(64);
+    }
+
+    #[test]
+    fn test_hmmi_pattern_preview() {
+        let dir = TempDir::new().unwrap();
+        let hmm = dir.path().join(".hmm");
+        let log = dir.path().join("notes.txt");
+        fs::write(
+            &log,
+            "2020-01-02 10:00 | second\n  continued\n2020-01-02 09:00 | first\n2020-01-03 09:00 | third\n",
+        )
+        .unwrap();
+
+        let args = [
+            "--pattern",
+            "%Y-%m-%d %H:%M | {message}",
+            log.to_str().unwrap(),
+        ];
+        import(&hmm, &[&args[..], &["--preview", "2"]].concat())
+            .success()
+            .stdout(
+                "2020-01-02T09:00:00+00:00,\"\"\"first\"\"\"\n2020-01-02T10:00:00+00:00,\"\"\"second\\n  continued\"\"\"\n",
+            )
+            .stderr("previewed 2 of 3 entries, nothing was written\n");
+        assert!(!hmm.exists());
+
+        import(&hmm, &args).success();
+        assert_eq!(entries(&hmm).len(), 3);
+    }
+
+    #[test]
+    fn test_hmmi_needs_a_format() {
+        let dir = TempDir::new().unwrap();
+        import(&dir.path().join(".hmm"), &["-"]).failure();
+    }
+
+    #[test]
+    fn test_hmmi_unknown_format() {
+        let dir = TempDir::new().unwrap();
+        import(&dir.path().join(".hmm"), &["--from", "nope"]).failure();
+    }
+}
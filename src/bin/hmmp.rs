@@ -1,7 +1,14 @@
-use hmmcli::{entry::Entry, format::Format, Result};
+use hmmcli::{
+    error::{Error, ErrorFormat},
+    format::{ColorMode, Format, TemplateData},
+    input::{InputFormat, LineParser},
+    Result,
+};
 use human_panic::setup_panic;
-use std::convert::TryInto;
-use std::io::{stdin, BufRead};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, IsTerminal, Write};
+use std::path::PathBuf;
 use std::process::exit;
 use structopt::StructOpt;
 
@@ -16,27 +23,424 @@ struct Opt {
     /// values "datetime" and "message" are passed in.
     #[structopt(
         long = "format",
+        env = "HMM_FORMAT",
         default_value = "╭ {{ color \"blue\" (strftime \"%Y-%m-%d %H:%M\" datetime) }}\n{{ indent (markdown message) }}╰─────────────────"
     )]
     format: String,
+
+    /// Path to a hmm file to format, instead of reading from stdin. This is
+    /// formatted before any files passed as arguments.
+    #[structopt(long = "path")]
+    path: Option<PathBuf>,
+
+    /// The format of the lines being read. "csv" is the format hmm files are
+    /// written in, "json" reads one JSON object per line with "datetime" and
+    /// "message" fields.
+    #[structopt(long = "input", default_value = "csv", possible_values = &["csv", "json"])]
+    input: InputFormat,
+
+    /// Stop at the first line that can't be parsed instead of warning about
+    /// it and carrying on.
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    /// How many threads to format entries on. Output is printed in the same
+    /// order as the input either way, but with more than one thread it's
+    /// printed in batches rather than a line at a time. Use 0 to have one
+    /// thread per CPU.
+    #[structopt(long = "jobs", default_value = "1")]
+    jobs: usize,
+
+    /// Whether to color output: "auto" colors output that's going to a
+    /// terminal, unless the NO_COLOR environment variable is set.
+    #[structopt(long = "color", env = "HMM_COLOR", default_value = "auto", possible_values = &["auto", "always", "never"])]
+    color: ColorMode,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+
+    /// Files to format, in order. Use - to read from stdin. If no files are
+    /// given, and --path isn't set, entries are read from stdin.
+    files: Vec<PathBuf>,
 }
 
 fn main() {
     setup_panic!();
 
-    if let Err(e) = app(&Opt::from_args(), stdin().lock()) {
-        eprintln!("{}", e);
-        exit(1);
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
     }
 }
 
-fn app(opt: &Opt, stdin: impl BufRead) -> Result<()> {
+fn app(opt: &Opt) -> Result<()> {
     let mut formatter = Format::with_template(&opt.format)?;
+    formatter.set_color(opt.color.enabled(stdout().is_terminal()));
+    let parser = opt.input.parser();
 
-    for line in stdin.lines() {
-        let entry: Entry = line?.try_into()?;
-        println!("{}", formatter.format_entry(&entry)?);
+    // Templates that use the entry before can't be formatted in parallel, as
+    // each thread would only see the entries in its own batches.
+    let pool = match opt.jobs {
+        _ if formatter.uses_previous() => None,
+        1 => None,
+        jobs => Some(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs)
+                .build()
+                .map_err(|e| Error::InvalidArgument(format!("couldn't start threads: {}", e)))?,
+        ),
+    };
+
+    let mut inputs: Vec<PathBuf> = opt.path.iter().chain(opt.files.iter()).cloned().collect();
+    if inputs.is_empty() {
+        inputs.push(PathBuf::from("-"));
+    }
+
+    let mut skipped = 0;
+    for path in inputs {
+        let (source, r): (String, Box<dyn BufRead>) = if path.as_os_str() == "-" {
+            ("<stdin>".to_owned(), Box::new(stdin().lock()))
+        } else {
+            let f = File::open(&path).map_err(|source| Error::OpenFile {
+                path: path.clone(),
+                source,
+            })?;
+            (
+                path.to_string_lossy().into_owned(),
+                Box::new(BufReader::new(f)),
+            )
+        };
+
+        skipped += match pool {
+            Some(ref pool) => format_lines_parallel(opt, &source, r, &*parser, &formatter, pool)?,
+            None => format_lines(opt, &source, r, &*parser, &mut formatter)?,
+        };
+    }
+
+    if skipped > 0 {
+        return Err(Error::SkippedLines(skipped));
     }
 
     Ok(())
 }
+
+/// Formats every entry read from `r`, returning how many lines were skipped
+/// because they couldn't be parsed. A warning is printed for each one, but
+/// they don't stop the rest of the input from being formatted unless --strict
+/// was passed. Blank lines are skipped without a warning.
+fn format_lines(
+    opt: &Opt,
+    source: &str,
+    mut r: impl BufRead,
+    parser: &dyn LineParser,
+    formatter: &mut Format,
+) -> Result<u64> {
+    let mut buf = String::new();
+    let mut line = 0;
+    let mut byte = 0;
+    let mut skipped = 0;
+
+    loop {
+        buf.clear();
+        let n = r.read_line(&mut buf)?;
+        if n == 0 {
+            return Ok(skipped);
+        }
+        line += 1;
+        let start = byte;
+        byte += n as u64;
+
+        if is_blank(&buf) {
+            continue;
+        }
+
+        match parser.parse_line(&buf) {
+            Ok(entry) => println!("{}", formatter.format_entry(&entry)?),
+            Err(e) => {
+                let e = e.at_line(Some(line), start, &buf);
+                if opt.strict {
+                    return Err(e);
+                }
+                opt.errors.warn(source, &e);
+                skipped += 1;
+            }
+        }
+    }
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+// How many lines each thread formats at a time when formatting in parallel,
+// and how many of those batches are read in before formatting them. Bigger
+// batches mean less coordination between threads but more memory, and a longer
+// wait before the first output appears.
+const LINES_PER_BATCH: usize = 1024;
+const BATCHES_PER_READ: usize = 16;
+
+struct Line {
+    text: String,
+    number: u64,
+    byte: u64,
+}
+
+/// The same as format_lines, but parsing and formatting batches of lines on
+/// a thread pool. Each batch is formatted in to a single buffer, which
+/// are then written out in the order the lines were read.
+fn format_lines_parallel(
+    opt: &Opt,
+    source: &str,
+    mut r: impl BufRead,
+    parser: &dyn LineParser,
+    formatter: &Format,
+    pool: &rayon::ThreadPool,
+) -> Result<u64> {
+    let read_size = LINES_PER_BATCH * BATCHES_PER_READ * pool.current_num_threads();
+    let mut lines: Vec<Line> = Vec::with_capacity(read_size);
+    let mut number = 0;
+    let mut byte = 0;
+    let mut skipped = 0;
+    let mut eof = false;
+
+    while !eof {
+        lines.clear();
+        while lines.len() < read_size {
+            let mut text = String::new();
+            let n = r.read_line(&mut text)?;
+            if n == 0 {
+                eof = true;
+                break;
+            }
+            number += 1;
+            if !is_blank(&text) {
+                lines.push(Line { text, number, byte });
+            }
+            byte += n as u64;
+        }
+
+        let batches: Vec<(Vec<u8>, Vec<Error>)> = pool.install(|| {
+            lines
+                .par_chunks(LINES_PER_BATCH)
+                .map_init(TemplateData::default, |data, batch| {
+                    let mut out = Vec::new();
+                    let mut warnings = Vec::new();
+                    for line in batch {
+                        match parser.parse_line(&line.text) {
+                            Ok(entry) => {
+                                formatter.write_entry(&entry, data, &mut out)?;
+                                out.push(b'\n');
+                            }
+                            Err(e) => {
+                                warnings.push(e.at_line(Some(line.number), line.byte, &line.text));
+                                // Nothing after the first bad line is printed
+                                // in strict mode, the same as when formatting
+                                // on one thread.
+                                if opt.strict {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Ok((out, warnings))
+                })
+                .collect::<Result<_>>()
+        })?;
+
+        let stdout = stdout();
+        let mut stdout = stdout.lock();
+        for (out, warnings) in batches {
+            stdout.write_all(&out)?;
+            for warning in warnings {
+                if opt.strict {
+                    stdout.flush()?;
+                    return Err(warning);
+                }
+                opt.errors.warn(source, &warning);
+                skipped += 1;
+            }
+        }
+        stdout.flush()?;
+    }
+
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::{assert::Assert, prelude::*};
+    use chrono::prelude::*;
+    use escargot::{CargoBuild, CargoRun};
+    use lazy_static::lazy_static;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    const TESTDATA: &str = "2020-01-01T00:01:00.899849209+00:00,\"\"\"1\"\"\"
+2020-02-12T23:08:40.987613062+00:00,\"\"\"2\"\"\"
+";
+
+    lazy_static! {
+        static ref HMMP: CargoRun = CargoBuild::new()
+            .bin("hmmp")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    fn new_tempfile(content: &str) -> PathBuf {
+        let mut f = NamedTempFile::new().unwrap();
+        f.write_all(content.as_bytes()).unwrap();
+        f.keep().unwrap().1
+    }
+
+    fn run(args: Vec<&str>, stdin: &str) -> Assert {
+        assert_cmd::Command::from_std(HMMP.command())
+            .arg("--format")
+            .arg("{{ message }}")
+            .args(args)
+            .write_stdin(stdin)
+            .assert()
+    }
+
+    fn stdout(assert: &Assert) -> String {
+        String::from_utf8(assert.get_output().stdout.clone()).unwrap()
+    }
+
+    #[test_case(vec![] => "1\n2\n" ; "stdin")]
+    #[test_case(vec!["--path", new_tempfile(TESTDATA).to_str().unwrap()] => "1\n2\n" ; "path")]
+    #[test_case(vec![new_tempfile(TESTDATA).to_str().unwrap(), new_tempfile(TESTDATA).to_str().unwrap()] => "1\n2\n1\n2\n" ; "multiple files")]
+    #[test_case(vec![new_tempfile(TESTDATA).to_str().unwrap(), "-"] => "1\n2\n1\n2\n" ; "file and stdin")]
+    fn test_hmmp(args: Vec<&str>) -> String {
+        let assert = run(args, TESTDATA);
+        let out = stdout(&assert);
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmmp_jobs() {
+        let mut content = String::new();
+        for i in 0..5000 {
+            content.push_str(&format!("2020-01-01T00:00:00+00:00,\"\"\"{}\"\"\"\n", i));
+            if i % 1000 == 0 {
+                content.push_str("nope\n");
+            }
+        }
+        let path = new_tempfile(&content);
+
+        let sequential = run(vec![path.to_str().unwrap()], "");
+        let parallel = run(vec!["--jobs", "4", path.to_str().unwrap()], "");
+
+        assert_eq!(stdout(&sequential), stdout(&parallel));
+        assert_eq!(sequential.get_output().stderr, parallel.get_output().stderr);
+        parallel.code(65);
+    }
+
+    #[test]
+    fn test_hmmp_jobs_since_prev() {
+        let mut content = String::new();
+        for i in 0..3000 {
+            content.push_str(&format!(
+                "{},\"\"\"{}\"\"\"\n",
+                (DateTime::UNIX_EPOCH + chrono::Duration::minutes(i)).to_rfc3339(),
+                i
+            ));
+        }
+        let path = new_tempfile(&content);
+
+        let assert = HMMP
+            .command()
+            .args(["--jobs", "4", "--format", "{{ since_prev }}"])
+            .arg(&path)
+            .assert();
+        let out = stdout(&assert);
+        assert.success();
+        assert_eq!(out.lines().filter(|line| *line == "1m").count(), 2999);
+    }
+
+    #[test_case("always" => "\x1b[34m1\x1b[0m\n" ; "always")]
+    #[test_case("never"  => "1\n"                  ; "never")]
+    #[test_case("auto"   => "1\n"                  ; "auto when piped")]
+    fn test_hmmp_color(color: &str) -> String {
+        let assert = assert_cmd::Command::from_std(HMMP.command())
+            .args(["--format", "{{ color \"blue\" message }}", "--color", color])
+            .write_stdin(TESTDATA.lines().next().unwrap())
+            .assert();
+        let out = stdout(&assert);
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmmp_json_input() {
+        let json = "{\"datetime\":\"2020-01-01T00:00:00Z\",\"message\":\"1\"}\n{\"datetime\":\"2020-01-02T00:00:00Z\",\"message\":\"2\"}\n";
+        let assert = run(vec!["--input", "json"], json);
+        assert_eq!(stdout(&assert), "1\n2\n");
+        assert.success();
+    }
+
+    #[test]
+    fn test_hmmp_skips_malformed_lines() {
+        let path = new_tempfile(&format!("{}nope\n{}", TESTDATA, TESTDATA));
+        let assert = run(vec![path.to_str().unwrap()], "");
+
+        assert_eq!(stdout(&assert), "1\n2\n1\n2\n");
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(
+            stderr.contains(&format!(
+                "warning: {}: malformed CSV on line 3 (byte offset 88)",
+                path.to_str().unwrap()
+            )),
+            "unexpected stderr: {}",
+            stderr
+        );
+        assert!(
+            stderr.contains("1 line couldn't be parsed and was skipped"),
+            "unexpected stderr: {}",
+            stderr
+        );
+        assert.code(65);
+    }
+
+    #[test_case(vec![]                ; "one thread")]
+    #[test_case(vec!["--jobs", "4"]   ; "several threads")]
+    fn test_hmmp_skips_blank_lines(args: Vec<&str>) {
+        let path = new_tempfile(&format!("\n{}  \n\r\n{}\n\n", TESTDATA, TESTDATA));
+        let mut args = args;
+        args.push(path.to_str().unwrap());
+        let assert = run(args, "");
+
+        assert_eq!(stdout(&assert), "1\n2\n1\n2\n");
+        assert_eq!(assert.get_output().stderr, b"");
+        assert.success();
+    }
+
+    #[test_case(vec![]                ; "one thread")]
+    #[test_case(vec!["--jobs", "4"]   ; "several threads")]
+    fn test_hmmp_strict(args: Vec<&str>) {
+        let path = new_tempfile(&format!("{}$ hmmq --last 2\n{}", TESTDATA, TESTDATA));
+        let mut args = args;
+        args.push("--strict");
+        args.push(path.to_str().unwrap());
+        let assert = run(args, "");
+
+        assert_eq!(stdout(&assert), "1\n2\n");
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(
+            stderr.starts_with("malformed CSV on line 3 (byte offset 88)"),
+            "unexpected stderr: {}",
+            stderr
+        );
+        assert.code(65);
+    }
+
+    #[test]
+    fn test_hmmp_missing_file() {
+        run(vec!["/this/path/does/not/exist"], "").code(66);
+    }
+}
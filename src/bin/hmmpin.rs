@@ -0,0 +1,243 @@
+use hmmcli::{
+    config::Config,
+    crypt,
+    entries::Entries,
+    entry::{self, Entry},
+    error::{Error, ErrorFormat},
+    format::Format,
+    pins::{self, Pins},
+    remote::Remote,
+    storage::Storage,
+    Result,
+};
+use human_panic::setup_panic;
+use std::fs::File;
+use std::io::{BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use structopt::StructOpt;
+
+// How pinned entries are printed, the same as hmmq's default format.
+const DEFAULT_FORMAT: &str = "╭ {{ color \"blue\" (strftime \"%Y-%m-%d %H:%M\" datetime) }}\n{{ indent (markdown message) }}╰─────────────────";
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hmmpin",
+    about = "Pin entries to keep them close to hand, and print the ones you've pinned"
+)]
+struct Opt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Unpin the entry instead.
+    #[structopt(long = "remove", requires = "id")]
+    remove: bool,
+
+    /// The Handlebars template pinned entries are printed with, the same as
+    /// hmmq's --format.
+    #[structopt(long = "format", conflicts_with = "id")]
+    format: Option<String>,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+
+    /// The ID of the entry to pin, like the ones hmmq --ids prints, with or
+    /// without the ^ in front of it. Without one, the pinned entries are
+    /// printed in the order they were pinned.
+    #[structopt(parse(try_from_str = entry::parse_id))]
+    id: Option<String>,
+}
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+fn app(opt: &Opt) -> Result<()> {
+    let path = opt
+        .path
+        .clone()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+    if Remote::parse(&path)?.is_some() {
+        return Err(Error::InvalidArgument(format!(
+            "{} isn't on this machine, run hmmpin where it is",
+            path.display()
+        )));
+    }
+    let mut pins = pins::load_default()?;
+
+    match opt.id {
+        Some(ref id) if opt.remove => {
+            if !pins.unpin(id) {
+                return Err(Error::InvalidArgument(format!("^{} isn't pinned", id)));
+            }
+            pins.save()
+        }
+        Some(ref id) => {
+            // IDs are short enough to mistype, so only entries that exist can
+            // be pinned.
+            if !read_all(&path)?.iter().any(|entry| entry.id() == *id) {
+                return Err(Error::InvalidArgument(format!(
+                    "there's no entry with the ID ^{} in {}",
+                    id,
+                    path.display()
+                )));
+            }
+            pins.pin(id);
+            pins.save()
+        }
+        None => list(opt, &path, &pins),
+    }
+}
+
+/// Prints the pinned entries, in the order they were pinned.
+fn list(opt: &Opt, path: &Path, pins: &Pins) -> Result<()> {
+    let config = Config::load_or_default(opt.config.as_deref())?;
+    let mut format = Format::with_template(opt.format.as_deref().unwrap_or(DEFAULT_FORMAT))?;
+    format.set_color_rules(config.color_rules.clone());
+    format.set_color(std::io::stdout().is_terminal());
+
+    let pinned = pins.select(read_all(path)?);
+    for entry in &pinned {
+        let entry = match config.decrypt_command {
+            Some(ref command) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                crypt::decrypt_entry(command, entry)?
+            }
+            _ => entry.clone(),
+        };
+        println!("{}", format.format_entry(&entry)?);
+    }
+    if pinned.len() < pins.ids().len() {
+        eprintln!(
+            "{} of your pins aren't in {}, unpin them with hmmpin --remove",
+            pins.ids().len() - pinned.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Reads every entry in the journal at `path`, without creating it if it
+/// doesn't exist.
+fn read_all(path: &Path) -> Result<Vec<Entry>> {
+    match Storage::parse(path)? {
+        Storage::File(path) => {
+            let f = File::open(&path).map_err(|source| Error::OpenFile { path, source })?;
+            Entries::new(BufReader::new(f)).collect()
+        }
+        #[cfg(feature = "object-storage")]
+        Storage::Object(store) => Entries::new(BufReader::new(store.reader()?)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::{assert::Assert, prelude::*};
+    use escargot::{CargoBuild, CargoRun};
+    use hmmcli::entries::Entries;
+    use hmmcli::entry::Entry;
+    use lazy_static::lazy_static;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::{Path, PathBuf};
+    use tempfile::{NamedTempFile, TempDir};
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMPIN: CargoRun = CargoBuild::new()
+            .bin("hmmpin")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    fn new_tempfile(content: &str) -> PathBuf {
+        let path = NamedTempFile::new().unwrap().keep().unwrap().1;
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn run_with_path(config: &Path, path: &Path, args: &[&str]) -> Assert {
+        HMMPIN
+            .command()
+            .env("XDG_CONFIG_HOME", config)
+            .arg("--path")
+            .arg(path)
+            .args(args)
+            .assert()
+    }
+
+    const TESTDATA: &str = "2024-05-12T23:00:00+00:00,\"\"\"last week\"\"\"
+2024-05-13T09:00:00+00:00,\"\"\"standup #work\\nTODO: book a room\"\"\"
+2024-05-14T12:00:00+00:00,\"\"\"lunch #friends #work\"\"\"
+2024-06-01T09:00:00+00:00,\"\"\"next month #garden\"\"\"
+";
+
+    #[test]
+    fn test_hmmpin() {
+        let config = TempDir::new().unwrap();
+        let path = new_tempfile(TESTDATA);
+        let hmmpin = |args: &[&str]| run_with_path(config.path(), &path, args);
+        let stdout = |args: &[&str]| {
+            let assert = hmmpin(args).success();
+            String::from_utf8(assert.get_output().stdout.clone()).unwrap()
+        };
+        let id = |i: usize| {
+            let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+                .collect::<Result<_, _>>()
+                .unwrap();
+            entries[i].id()
+        };
+
+        assert_eq!(stdout(&[]), "");
+        hmmpin(&[&format!("^{}", id(2))]).success();
+        hmmpin(&[&id(0)]).success();
+        hmmpin(&[&id(0)]).success();
+        assert_eq!(
+            stdout(&["--format", "{{ message }}"]),
+            "lunch #friends #work\nlast week\n"
+        );
+
+        hmmpin(&["--remove", &id(2)]).success();
+        hmmpin(&["--remove", &id(2)]).code(64);
+        assert_eq!(stdout(&["--format", "{{ message }}"]), "last week\n");
+
+        // Pins are the same whichever journal they were pinned in, so ones
+        // that aren't in this journal are left out.
+        std::fs::write(&path, "2024-05-13T09:00:00+00:00,\"\"\"other\"\"\"\n").unwrap();
+        let assert = hmmpin(&[]).success();
+        assert!(assert.get_output().stdout.is_empty());
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("1 of your pins"), "{}", stderr);
+    }
+
+    #[test_case(vec!["nope"] => 1 ; "not an id")]
+    #[test_case(vec!["^00000000"] => 64 ; "no such entry")]
+    #[test_case(vec!["--remove"] => 1 ; "remove without an id")]
+    #[test_case(vec!["--format", "{{ message }}", "^00000000"] => 1 ; "format with an id")]
+    fn test_hmmpin_errors(args: Vec<&str>) -> i32 {
+        let config = TempDir::new().unwrap();
+        let assert = run_with_path(config.path(), &new_tempfile(TESTDATA), &args);
+        assert.get_output().status.code().unwrap()
+    }
+
+    #[test]
+    fn test_hmmpin_remote() {
+        let config = TempDir::new().unwrap();
+        run_with_path(config.path(), Path::new("ssh://host/.hmm"), &[]).code(64);
+    }
+}
@@ -1,28 +1,63 @@
 use chrono::prelude::*;
-use hmmcli::{entries::Entries, format::Format, Result};
+#[cfg(feature = "caldav")]
+use hmmcli::caldav::{self, CalDav};
+#[cfg(feature = "parquet")]
+use hmmcli::export::ParquetWriter;
+#[cfg(feature = "object-storage")]
+use hmmcli::storage::object::ObjectStore;
+use hmmcli::{
+    calendar,
+    config::{Config, QueryDefaults},
+    crypt, date,
+    entries::Entries,
+    entry::{self, Entry, EntryRef},
+    error::{Error, ErrorFormat},
+    filter::Filter,
+    format::{ColorMode, Format, GroupBy, GroupTotals},
+    graph::{self, Graph},
+    ics, links, location,
+    redact::{self, Redactor},
+    remote::Remote,
+    scan, site,
+    storage::Storage,
+    table, text, twtxt, Result,
+};
 use human_panic::setup_panic;
+use rand::seq::SliceRandom;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::str::FromStr;
+use std::sync::Mutex;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "hmmq", about = "Query your hmm file")]
 struct Opt {
     /// Path to your hmm file, defaults to your default configuration directory,
-    /// ~/.config on *nix systems, %APPDATA% on Windows.
-    #[structopt(long = "path")]
+    /// ~/.config on *nix systems, %APPDATA% on Windows. A path like
+    /// ssh://host/~/.hmm queries a journal on another machine by running hmmq
+    /// there over SSH.
+    #[structopt(long = "path", env = "HMM_PATH")]
     path: Option<PathBuf>,
 
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
     /// How to format entry output. hmm uses Handlebars as a template format, see
     /// https://handlebarsjs.com/guide/ for information on how to use them. The
-    /// values "datetime" and "message" are passed in.
-    #[structopt(
-        long = "format",
-        default_value = "╭ {{ color \"blue\" (strftime \"%Y-%m-%d %H:%M\" datetime) }}\n{{ indent (markdown message) }}╰─────────────────"
-    )]
-    format: String,
+    /// values "datetime" and "message" are passed in. Overrides default_format
+    /// in your config file.
+    #[structopt(long = "format", env = "HMM_FORMAT")]
+    format: Option<String>,
 
     /// Path to a file containing a Handlebar template to use as --format. If both
     /// --format-file and --format are supplied, --format-file takes precedence.
@@ -57,12 +92,12 @@ struct Opt {
     /// local time, and can be specified using any subset of an RFC3339 date,
     /// e.g. 2012, 2012-01, 2012-01-29, 2012-01-29T14, 2012-01-29T14:30,
     /// 2012-01-29T14:30:11.
-    #[structopt(short = "s", long = "start", parse(try_from_str = parse_date_arg))]
+    #[structopt(short = "s", long = "start", parse(try_from_str = date::parse))]
     start: Option<DateTime<FixedOffset>>,
 
     /// Date to stop printing at, exclusive. Like --start, this can be any subset of an
     /// RFC3339 date. See --start for details.
-    #[structopt(short = "e", long = "end", parse(try_from_str = parse_date_arg))]
+    #[structopt(short = "e", long = "end", parse(try_from_str = date::parse))]
     end: Option<DateTime<FixedOffset>>,
 
     /// Only print entries that contain this substring exactly. Cannot be used
@@ -74,81 +109,1352 @@ struct Opt {
     /// --contains.
     #[structopt(long = "regex")]
     regex: Option<String>,
+
+    /// Print what --regex's capture groups matched in each entry instead of
+    /// the entry, formatted with this template. Groups are passed in as c1,
+    /// c2 and so on, with c0 the whole match, and named groups by their names
+    /// too. The entry's datetime, message and id are passed in as well.
+    #[structopt(long = "print-captures", requires = "regex", conflicts_with_all = &["format", "format-file", "titles-only", "count", "raw", "html-site", "ics", "table"])]
+    print_captures: Option<String>,
+
+    /// Only print entries that match this expression, like
+    /// 'tag=work and mood>=3 and message~"review"'. Fields are tag, message
+    /// or anything in an entry's metadata, compared using one of =, !=, <,
+    /// <=, >, >=, ~ (contains) and !~. Comparisons can be joined with and and
+    /// or, negated with not and grouped with brackets. Can be given more than
+    /// once, and entries have to match all of them.
+    #[structopt(long = "where", number_of_values = 1)]
+    filters: Vec<Filter>,
+
+    /// Only print entries that link to the entry with this ID, by having it
+    /// in their message like "^cbbf4083".
+    #[structopt(long = "backlinks", parse(try_from_str = entry::parse_id))]
+    backlinks: Option<String>,
+
+    /// Only print entries with at least this many words in them, to leave out
+    /// short status updates.
+    #[structopt(long = "min-words")]
+    min_words: Option<usize>,
+
+    /// Only print entries with at most this many characters in them, to see
+    /// only short status updates.
+    #[structopt(long = "max-chars")]
+    max_chars: Option<usize>,
+
+    /// Only print entries written near a place, given as a latitude,
+    /// longitude and radius like 51.5,-0.12,2km. The radius is in kilometres
+    /// unless it ends in m for metres. Entries only have a location if hmm's
+    /// location_command was set when they were written.
+    #[structopt(long = "near")]
+    near: Option<location::Near>,
+
+    /// Only print every Nth entry that matches, starting with the first, to
+    /// skim through busy stretches of time. This is applied after --first and
+    /// --last, so --last 100 --every 10 prints 10 entries.
+    #[structopt(long = "every", conflicts_with_all = &["count", "random"])]
+    every: Option<u64>,
+
+    /// Read the .hmm file through a memory map instead of buffered reads. This
+    /// is faster for queries that seek around a lot, such as --last and date
+    /// ranges, on large files.
+    #[structopt(long = "mmap")]
+    mmap: bool,
+
+    /// Write results to this file instead of stdout. The file is replaced if
+    /// it already exists, unless --append-output is also given. A summary of
+    /// what was written is printed to stderr.
+    #[structopt(short = "o", long = "output")]
+    output: Option<PathBuf>,
+
+    /// Append to the file given to --output rather than replacing it.
+    #[structopt(long = "append-output", requires = "output")]
+    append_output: bool,
+
+    /// Write the matching entries to this directory as a static website, with
+    /// a page for each month and an index.html you can search from, instead
+    /// of printing them.
+    #[structopt(long = "html-site", conflicts_with_all = &["count", "raw", "output"])]
+    html_site: Option<PathBuf>,
+
+    /// Print the matching entries as an iCalendar file, for importing in to a
+    /// calendar app. "entry" makes an event for each entry, "day" makes an
+    /// all-day event for each day listing that day's entries.
+    #[structopt(long = "ics", possible_values = &["entry", "day"], conflicts_with_all = &["count", "raw", "html-site"])]
+    ics: Option<ics::Grouping>,
+
+    /// Print the matching entries as a twtxt feed, a line per entry of its
+    /// datetime, a tab and its message, for publishing as a microblog.
+    /// Newlines in messages are written as U+2028 LINE SEPARATOR.
+    #[structopt(long = "twtxt", conflicts_with_all = &["count", "raw", "html-site", "ics", "table", "calendar", "sql", "group-by", "print-captures", "titles-only", "number", "ids", "format", "format-file"])]
+    twtxt: bool,
+
+    /// Only print the first and last of the matching entries on each day, to
+    /// see when you started and stopped each day.
+    #[structopt(long = "bookends", conflicts_with_all = &["count", "random"])]
+    bookends: bool,
+
+    /// Only print one of each message, for journals with the same short status
+    /// written lots of times. Messages are compared ignoring case and
+    /// differences in whitespace.
+    #[structopt(long = "distinct", conflicts_with_all = &["count", "random", "bookends"])]
+    distinct: bool,
+
+    /// Which of the entries with the same message --distinct prints: the
+    /// "first" or the "last" one. Defaults to "first".
+    #[structopt(long = "distinct-keep", requires = "distinct", possible_values = &["first", "last"])]
+    distinct_keep: Option<Keep>,
+
+    /// Put how many times each message was written after it, like "(×3)".
+    #[structopt(long = "distinct-count", requires = "distinct", conflicts_with_all = &["raw", "html-site", "ics", "table"])]
+    distinct_count: bool,
+
+    /// Sort the matching entries by something other than when they were
+    /// written: "length" puts the longest first, "alpha" sorts them
+    /// alphabetically and "random" shuffles them. Every matching entry is read
+    /// in to memory to sort them.
+    #[structopt(long = "sort-by", possible_values = &["length", "alpha", "random"], conflicts_with_all = &["count", "random", "bookends", "group-by"])]
+    sort_by: Option<SortBy>,
+
+    /// Put each entry's number in the results, starting from 1, before it.
+    #[structopt(long = "number", conflicts_with_all = &["count", "raw", "html-site", "ics", "table"])]
+    number: bool,
+
+    /// Put each entry's ID, like "^cbbf4083", before it. IDs can be used to
+    /// refer to entries in other commands.
+    #[structopt(long = "ids", conflicts_with_all = &["count", "raw", "html-site", "ics", "table"])]
+    ids: bool,
+
+    /// Put the title of the entry each link in a message points to after the
+    /// link, like '^cbbf4083 ("Planning the move")'. Every entry in the
+    /// journal is read to find them.
+    #[structopt(long = "link-previews", conflicts_with_all = &["count", "raw"])]
+    link_previews: bool,
+
+    /// Only print the first line of each entry, its title, after its date,
+    /// for a compact listing of entries that have a title and then a body.
+    #[structopt(long = "titles-only", conflicts_with_all = &["format", "format-file", "count", "raw", "html-site", "ics", "table"])]
+    titles_only: bool,
+
+    /// Print the matching entries as a table, with a row for each entry
+    /// showing its time, its tags and its message on a single line. The table
+    /// is made to fit the width of your terminal.
+    #[structopt(long = "table", conflicts_with_all = &["count", "raw", "html-site", "ics"])]
+    table: bool,
+
+    /// Which characters to draw --table with: "unicode" box drawing
+    /// characters, or plain "ascii". Defaults to "unicode".
+    #[structopt(long = "table-style", requires = "table", possible_values = &["unicode", "ascii"])]
+    table_style: Option<table::Style>,
+
+    /// Print a calendar of this month, like 2024-06, showing how many entries
+    /// were written on each day, instead of the entries. Counts are colored
+    /// by how busy each day was when output is colored.
+    #[structopt(long = "calendar", parse(try_from_str = calendar::parse_month), conflicts_with_all = &["count", "raw", "html-site", "ics", "table", "group-by", "random", "first", "last", "start", "end"])]
+    calendar: Option<NaiveDate>,
+
+    /// List the time and title of each entry under its day after the
+    /// --calendar.
+    #[structopt(long = "with-titles", requires = "calendar")]
+    with_titles: bool,
+
+    /// Load the matching entries in to an in-memory SQLite database and print
+    /// what this query returns as CSV. Entries are in a table called entries,
+    /// with columns id, datetime, message, title, words and metadata, and
+    /// their tags are in a table called tags, with columns id and tag.
+    #[structopt(long = "sql", conflicts_with_all = &["count", "raw", "html-site", "ics", "table", "calendar", "group-by", "print-captures", "titles-only", "number", "ids", "format", "format-file"])]
+    sql: Option<String>,
+
+    /// Write the matching entries to this Parquet file instead of printing
+    /// them, for analysis in tools like DuckDB or pandas. It has a datetime,
+    /// message and tags column, and is replaced if it already exists.
+    #[structopt(long = "parquet", conflicts_with_all = &["count", "raw", "output", "html-site", "ics", "twtxt", "table", "calendar", "sql", "group-by", "print-captures", "titles-only", "number", "ids", "format", "format-file"])]
+    parquet: Option<PathBuf>,
+
+    /// Publish an all-day event for each day with matching entries to a
+    /// calendar on a CalDAV server instead of printing them, so the days you
+    /// wrote on show up in calendars you share. Publishing a day again
+    /// replaces its event.
+    #[structopt(long = "caldav", conflicts_with_all = &["count", "raw", "output", "html-site", "ics", "twtxt", "table", "calendar", "sql", "parquet", "group-by", "print-captures", "titles-only", "number", "ids", "format", "format-file"])]
+    caldav: bool,
+
+    /// Where your calendars are on the CalDAV server, like
+    /// https://dav.example.com/calendars/sam. Defaults to url in the [caldav]
+    /// section of your config file.
+    #[structopt(long = "caldav-url", requires = "caldav")]
+    #[cfg_attr(not(feature = "caldav"), allow(dead_code))]
+    caldav_url: Option<String>,
+
+    /// The name of the calendar --caldav publishes to. Defaults to calendar in
+    /// the [caldav] section of your config file, or "journal".
+    #[structopt(long = "caldav-calendar", requires = "caldav")]
+    #[cfg_attr(not(feature = "caldav"), allow(dead_code))]
+    caldav_calendar: Option<String>,
+
+    /// Print a graph of the matching entries and the tags, mentions and links
+    /// in them instead of the entries: "dot" for Graphviz, or "json" for a
+    /// list of nodes and a list of edges.
+    #[structopt(long = "graph", possible_values = &["dot", "json"], conflicts_with_all = &["count", "raw", "html-site", "ics", "twtxt", "table", "calendar", "sql", "parquet", "caldav", "group-by", "print-captures", "titles-only", "number", "ids", "format", "format-file"])]
+    graph: Option<graph::Format>,
+
+    /// Print a heading before the entries of each day, week or month. Unless
+    /// --format is given, entries then leave out the parts of their date that
+    /// are in the heading.
+    #[structopt(long = "group-by", possible_values = &["day", "week", "month"], conflicts_with_all = &["count", "raw", "html-site", "ics", "table"])]
+    group_by: Option<GroupBy>,
+
+    /// How to format the headings printed by --group-by, as a Handlebars
+    /// template like --format. The value "datetime", the start of the day,
+    /// week or month, is passed in.
+    #[structopt(long = "group-format", requires = "group-by")]
+    group_format: Option<String>,
+
+    /// Hide @mentions or email addresses in the messages of the entries that
+    /// are printed or exported. Can be given more than once.
+    #[structopt(long = "redact", number_of_values = 1, possible_values = &["mentions", "emails"])]
+    redact: Vec<redact::Target>,
+
+    /// Hide anything matching this regular expression in the messages of the
+    /// entries that are printed or exported. Can be given more than once.
+    #[structopt(long = "redact-regex", number_of_values = 1)]
+    redact_regex: Vec<String>,
+
+    /// What to replace redacted text with: "strip" replaces it with
+    /// "[redacted]", "hash" replaces it with a short hash, so you can still
+    /// tell when two entries mention the same thing.
+    #[structopt(long = "redact-with", default_value = "strip", possible_values = &["strip", "hash"])]
+    redact_with: redact::Mode,
+
+    /// Whether to color output: "auto" colors output that's going to a
+    /// terminal, unless the NO_COLOR environment variable is set. Overrides the
+    /// color setting in your config file, defaults to "auto".
+    #[structopt(long = "color", env = "HMM_COLOR", possible_values = &["auto", "always", "never"])]
+    color: Option<ColorMode>,
+
+    /// Don't print progress bars or the summary of what --output wrote to
+    /// stderr. Progress bars are also hidden when stderr isn't a terminal.
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
 }
 
 fn main() {
     setup_panic!();
 
-    if let Err(e) = app(Opt::from_args()) {
-        eprintln!("{}", e);
-        exit(1);
+    let args = match expand_aliases() {
+        Ok(args) => args,
+        Err(e) => {
+            ErrorFormat::Text.print(&e);
+            exit(e.exit_code());
+        }
+    };
+
+    let opt = Opt::from_iter(&args);
+    let errors = opt.errors;
+    if let Err(e) = app(opt, &args[1..]) {
+        errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+/// Replaces an alias from the config file given as the first argument with the
+/// arguments it stands for. This has to happen before the arguments are parsed,
+/// so the config file is found by looking for --config by hand.
+fn expand_aliases() -> Result<Vec<OsString>> {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let program = args.remove(0);
+
+    let config_path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .and_then(|i| args.get(i + 1).map(PathBuf::from))
+        .or_else(|| {
+            args.iter()
+                .filter_map(|arg| arg.to_str()?.strip_prefix("--config="))
+                .map(PathBuf::from)
+                .next()
+        })
+        .or_else(|| env::var_os("HMM_CONFIG").map(PathBuf::from));
+
+    let config = Config::load_or_default(config_path.as_deref())?;
+    let mut args = config.expand_aliases(args)?;
+    args.insert(0, program);
+    Ok(args)
+}
+
+const DEFAULT_FORMAT: &str = "╭ {{ color \"blue\" (strftime \"%Y-%m-%d %H:%M\" datetime) }}\n{{ indent (markdown message) }}╰─────────────────";
+
+/// The format --titles-only prints entries with.
+const TITLES_FORMAT: &str =
+    "{{ color \"blue\" (strftime \"%Y-%m-%d %H:%M\" datetime) }} {{ title }}";
+
+impl Opt {
+    /// Whether any flags were given that pick entries by what's in them,
+    /// rather than by when they were written, which means every entry in the
+    /// range has to be looked at to find them.
+    fn filters_entries(&self) -> bool {
+        self.contains.is_some()
+            || self.regex.is_some()
+            || !self.filters.is_empty()
+            || self.backlinks.is_some()
+            || self.min_words.is_some()
+            || self.max_chars.is_some()
+            || self.near.is_some()
+    }
+}
+
+/// Which of the entries with the same message --distinct keeps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Keep {
+    #[default]
+    First,
+    Last,
+}
+
+impl FromStr for Keep {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(Keep::First),
+            "last" => Ok(Keep::Last),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown --distinct-keep \"{}\", expected first or last",
+                s
+            ))),
+        }
     }
 }
 
-fn app(opt: Opt) -> Result<()> {
-    let mut formatter = if let Some(path) = opt.format_file {
+/// What --sort-by sorts entries by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Length,
+    Alpha,
+    Random,
+}
+
+impl FromStr for SortBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "length" => Ok(SortBy::Length),
+            "alpha" => Ok(SortBy::Alpha),
+            "random" => Ok(SortBy::Random),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown --sort-by \"{}\", expected length, alpha or random",
+                s
+            ))),
+        }
+    }
+}
+
+impl SortBy {
+    /// Sorts `entries`, keeping entries that sort the same in the order they
+    /// were written.
+    fn sort<T>(&self, entries: &mut [(Entry, T)]) {
+        match self {
+            SortBy::Length => {
+                entries.sort_by_key(|(entry, _)| Reverse(entry.message().chars().count()))
+            }
+            SortBy::Alpha => {
+                entries.sort_by_cached_key(|(entry, _)| entry.message().to_lowercase())
+            }
+            SortBy::Random => entries.shuffle(&mut rand::thread_rng()),
+        }
+    }
+}
+
+/// Like `format`, DEFAULT_FORMAT or TITLES_FORMAT, but without the parts of
+/// the date that are already in the heading of the group each entry is in.
+fn grouped_format(format: &str, group_by: GroupBy) -> String {
+    let time = match group_by {
+        GroupBy::Day => "%H:%M",
+        GroupBy::Week => "%a %H:%M",
+        GroupBy::Month => "%a %-d %H:%M",
+    };
+    format.replace("%Y-%m-%d %H:%M", time)
+}
+
+fn app(opt: Opt, args: &[OsString]) -> Result<()> {
+    let path = opt
+        .path
+        .clone()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+
+    if let Some(remote) = Remote::parse(&path)? {
+        return query_remote(&remote, opt, args);
+    }
+
+    #[cfg(not(feature = "sql"))]
+    if opt.sql.is_some() {
+        return Err(Error::InvalidArgument(
+            "--sql isn't supported by this build of hmmq, reinstall it with `cargo install hmmcli --features sql`".to_owned(),
+        ));
+    }
+    #[cfg(not(feature = "parquet"))]
+    if opt.parquet.is_some() {
+        return Err(Error::InvalidArgument(
+            "--parquet isn't supported by this build of hmmq, reinstall it with `cargo install hmmcli --features parquet`".to_owned(),
+        ));
+    }
+    #[cfg(not(feature = "caldav"))]
+    if opt.caldav {
+        return Err(Error::InvalidArgument(
+            "--caldav isn't supported by this build of hmmq, reinstall it with `cargo install hmmcli --features caldav`".to_owned(),
+        ));
+    }
+
+    let config = Config::load_or_default(opt.config.as_deref())?;
+    let mut opt = apply_defaults(opt, config.query_defaults(&path));
+    if let Some(month) = opt.calendar {
+        opt.start = Some(date::start_of_day(month));
+        opt.end = Some(date::start_of_day(month + chrono::Months::new(1)));
+    }
+
+    let mut formatter = if let Some(ref path) = opt.format_file {
         let mut f = File::open(path)?;
         let mut contents = String::new();
         f.read_to_string(&mut contents)?;
         Format::with_template(&contents)?
+    } else if let Some(ref template) = opt.print_captures {
+        Format::with_template(template)?
+    } else if opt.titles_only {
+        match opt.group_by {
+            Some(group_by) => Format::with_template(&grouped_format(TITLES_FORMAT, group_by))?,
+            None => Format::with_template(TITLES_FORMAT)?,
+        }
     } else {
-        Format::with_template(&opt.format)?
+        match (opt.format.as_deref(), opt.group_by) {
+            (Some(format), _) => Format::with_template(format)?,
+            (None, Some(group_by)) => {
+                Format::with_template(&grouped_format(DEFAULT_FORMAT, group_by))?
+            }
+            (None, None) => Format::with_template(DEFAULT_FORMAT)?,
+        }
     };
 
-    let path = opt
-        .path
-        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+    let terminal = opt.output.is_none() && io::stdout().is_terminal();
+    let color = opt.color.unwrap_or_default().enabled(terminal);
+    formatter.set_color(color);
+    formatter.set_color_rules(config.color_rules.clone());
+
+    let storage = Storage::parse(&path)?;
+    let f = match storage {
+        Storage::File(ref path) => {
+            let mut fopts = std::fs::OpenOptions::new();
+            fopts.create(true);
+            fopts.read(true);
+            fopts.write(true);
+
+            Some(fopts.open(path).map_err(|source| Error::OpenFile {
+                path: path.clone(),
+                source,
+            })?)
+        }
+        #[cfg(feature = "object-storage")]
+        Storage::Object(_) => None,
+    };
+
+    let mut out = match opt.output {
+        Some(ref output) => {
+            let mut fopts = std::fs::OpenOptions::new();
+            fopts.create(true);
+            if opt.append_output {
+                fopts.append(true);
+            } else {
+                fopts.write(true).truncate(true);
+            }
+
+            let f = fopts.open(output).map_err(|source| Error::CreateFile {
+                path: output.clone(),
+                source,
+            })?;
+            Output::new(Box::new(f))
+        }
+        None => Output::new(Box::new(io::stdout())),
+    };
+    if opt.html_site.is_some()
+        || opt.ics.is_some()
+        || opt.twtxt
+        || opt.table
+        || opt.calendar.is_some()
+        || opt.sql.is_some()
+        || opt.parquet.is_some()
+        || opt.caldav
+        || opt.graph.is_some()
+    {
+        out.collected = Some(Vec::new());
+    }
+    if let Some(by) = opt.group_by {
+        let template = opt.group_format.as_deref().unwrap_or(by.default_heading());
+        let mut heading = Format::with_template(template)?;
+        heading.set_color(color);
+        out.groups = Some(Groups::new(by, heading));
+    }
+    if opt.bookends {
+        out.bookends = Some(Bookends::default());
+    }
+    if let Some(sort_by) = opt.sort_by {
+        // Filters like --contains still have to look at every entry, and
+        // can match all of them.
+        let bounded =
+            opt.start.is_some() || opt.end.is_some() || opt.first.is_some() || opt.last.is_some();
+        if !bounded && !opt.quiet {
+            opt.errors.warn(
+                &path.to_string_lossy(),
+                &Error::InvalidArgument(
+                    "--sort-by reads every entry in to memory, pass --start, --end, --first or --last to sort fewer of them".to_owned(),
+                ),
+            );
+        }
+        out.sort = Some(sort_by);
+    }
+    if opt.distinct {
+        out.distinct = Some(Distinct {
+            keep: opt.distinct_keep.unwrap_or_default(),
+            count: opt.distinct_count,
+            ..Distinct::default()
+        });
+    }
+    if opt.print_captures.is_some() {
+        // Only entries that match --regex get as far as being written, so it
+        // doesn't need checking again.
+        out.captures = opt.regex.as_deref().map(Regex::new).transpose()?;
+    }
+    out.every = opt.every;
+    out.number = opt.number;
+    out.ids = opt.ids;
+    out.decrypt_command = config.decrypt_command.clone();
+    if !opt.redact.is_empty() || !opt.redact_regex.is_empty() {
+        out.redactor = Some(Redactor::new(
+            &opt.redact,
+            &opt.redact_regex,
+            opt.redact_with,
+        )?);
+    }
+
+    let output = opt.output.clone();
+    let html_site = opt.html_site.clone();
+    let ics = opt.ics;
+    let feed = opt.twtxt;
+    let table_style = opt.table.then(|| opt.table_style.unwrap_or_default());
+    let calendar = opt.calendar.map(|month| (month, opt.with_titles));
+    #[cfg(feature = "sql")]
+    let sql = opt.sql.clone();
+    #[cfg(feature = "parquet")]
+    let parquet = opt.parquet.clone();
+    #[cfg(feature = "caldav")]
+    let caldav = opt
+        .caldav
+        .then(|| (opt.caldav_url.clone(), opt.caldav_calendar.clone()));
+    let graph = opt.graph;
+    let quiet = opt.quiet;
+    match (storage, f) {
+        #[cfg(feature = "object-storage")]
+        (Storage::Object(store), _) => query_object(opt, &store, formatter, &mut out)?,
+        _ if opt.mmap => query(opt, &path, formatter, Entries::from_mmap(&path)?, &mut out)?,
+        (_, f) => query(
+            opt,
+            &path,
+            formatter,
+            Entries::new(BufReader::new(f.unwrap())),
+            &mut out,
+        )?,
+    }
+    // The last group only ends once there are no entries left.
+    if let Some(group) = out
+        .groups
+        .as_mut()
+        .map(Groups::finish)
+        .transpose()?
+        .flatten()
+    {
+        write!(out, "{}", group)?;
+    }
+    if let Some(grouping) = ics {
+        let entries = out.collected.take().unwrap_or_default();
+        ics::write(&mut out, &entries, grouping)?;
+    }
+    if feed {
+        let entries = out.collected.take().unwrap_or_default();
+        twtxt::write(&mut out, &entries)?;
+    }
+    if let Some(style) = table_style {
+        let entries = out.collected.take().unwrap_or_default();
+        table::write(&mut out, &entries, style, textwrap::termwidth())?;
+    }
+    if let Some((month, titles)) = calendar {
+        let entries = out.collected.take().unwrap_or_default();
+        calendar::write(&mut out, &entries, month, titles, color)?;
+    }
+    #[cfg(feature = "sql")]
+    if let Some(sql) = sql {
+        let entries = out.collected.take().unwrap_or_default();
+        hmmcli::sql::query(&mut out, &entries, &sql)?;
+    }
+    if let Some(format) = graph {
+        let entries = out.collected.take().unwrap_or_default();
+        write!(out, "{}", Graph::build(&entries).write(format)?)?;
+    }
+    out.flush()?;
+
+    #[cfg(feature = "parquet")]
+    if let (Some(parquet), Some(entries)) = (parquet, out.collected.as_ref()) {
+        let f = File::create(&parquet).map_err(|source| Error::CreateFile {
+            path: parquet.clone(),
+            source,
+        })?;
+        let mut writer = ParquetWriter::new(f)?;
+        for entry in entries {
+            writer.write(entry)?;
+        }
+        writer.finish()?;
+        if !quiet {
+            eprintln!(
+                "wrote {} {} to {}",
+                entries.len(),
+                if entries.len() == 1 {
+                    "entry"
+                } else {
+                    "entries"
+                },
+                parquet.to_string_lossy()
+            );
+        }
+    }
+
+    #[cfg(feature = "caldav")]
+    if let (Some((url, calendar)), Some(entries)) = (caldav, out.collected.as_ref()) {
+        publish_caldav(&config, url, calendar, entries)?;
+    }
+
+    if let (Some(dir), Some(entries)) = (html_site, out.collected.as_ref()) {
+        let pages = site::write(&dir, entries)?;
+        if !quiet {
+            eprintln!(
+                "wrote {} {} to {} {} in {}",
+                entries.len(),
+                if entries.len() == 1 {
+                    "entry"
+                } else {
+                    "entries"
+                },
+                pages,
+                if pages == 1 { "page" } else { "pages" },
+                dir.to_string_lossy()
+            );
+        }
+    }
+
+    if let (Some(output), false) = (output, quiet) {
+        eprintln!(
+            "wrote {}{} {} ({} bytes) to {}",
+            if out.counted { "a count of " } else { "" },
+            out.entries,
+            if out.entries == 1 { "entry" } else { "entries" },
+            out.bytes,
+            output.to_string_lossy()
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs the query on the machine a remote journal is on, passing on `args`
+/// apart from those that only make sense here. Entries are formatted and
+/// filtered there, using the config file there.
+fn query_remote(remote: &Remote, opt: Opt, args: &[OsString]) -> Result<()> {
+    if opt.output.is_some() || opt.html_site.is_some() || opt.parquet.is_some() {
+        return Err(Error::InvalidArgument(
+            "--output, --html-site and --parquet can't be used with a remote journal, redirect hmmq's output instead"
+                .to_owned(),
+        ));
+    }
+
+    let mut remote_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_str().ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "argument {:?} isn't valid UTF-8, which is needed to pass it on to {}",
+                arg,
+                remote.host()
+            ))
+        })?;
+        let flag = arg.split_once('=').map_or(arg, |(flag, _)| flag);
+        match flag {
+            "--path" | "--config" | "--format-file" | "--color" => {
+                if flag == arg {
+                    args.next();
+                }
+            }
+            _ => remote_args.push(arg.to_owned()),
+        }
+    }
+
+    // A template file is read here, as it won't be on the other machine.
+    if let Some(ref path) = opt.format_file {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        remote_args.extend(["--format".to_owned(), contents]);
+    }
+
+    // hmmq on the other machine is writing to SSH rather than a terminal, so
+    // whether to color the output is decided here.
+    let color = opt
+        .color
+        .unwrap_or_default()
+        .enabled(io::stdout().is_terminal());
+    remote_args.extend([
+        "--color".to_owned(),
+        if color { "always" } else { "never" }.to_owned(),
+    ]);
+
+    // Errors on the other machine have already been printed there.
+    let code = remote.run("hmmq", &remote_args)?;
+    if code != 0 {
+        exit(code);
+    }
+    Ok(())
+}
+
+/// Queries a journal in object storage. Most queries only need a few parts of
+/// the journal, which are fetched as they're needed, but searching every
+/// entry for --contains or --regex is faster with the whole thing downloaded.
+#[cfg(feature = "object-storage")]
+fn query_object(opt: Opt, store: &ObjectStore, formatter: Format, out: &mut Output) -> Result<()> {
+    let scan = opt.filters_entries() && opt.first.is_none() && opt.last.is_none();
+    if scan {
+        let journal = store.download()?;
+        let f = File::open(journal.path())?;
+        return query(
+            opt,
+            journal.path(),
+            formatter,
+            Entries::new(BufReader::new(f)),
+            out,
+        );
+    }
 
-    let mut fopts = std::fs::OpenOptions::new();
-    fopts.create(true);
-    fopts.read(true);
-    fopts.write(true);
+    let entries = Entries::new(BufReader::new(store.reader()?));
+    query(opt, Path::new(&store.to_string()), formatter, entries, out)
+}
 
-    let f = fopts.open(&path).map_err(|e| {
-        format!(
-            "Couldn't open or create file at {}: {}",
-            path.to_string_lossy(),
-            e
+/// Publishes an all-day event for each day with entries to a CalDAV calendar,
+/// listing that day's entries. `url` and `calendar` are from the command line,
+/// and fall back to the [caldav] section of the config file.
+#[cfg(feature = "caldav")]
+fn publish_caldav(
+    config: &Config,
+    url: Option<String>,
+    calendar: Option<String>,
+    entries: &[Entry],
+) -> Result<()> {
+    let url = url.or_else(|| config.caldav.url.clone()).ok_or_else(|| {
+        Error::InvalidArgument(
+            "--caldav needs a --caldav-url, or a url in the [caldav] section of your config file"
+                .to_owned(),
         )
     })?;
-    let mut entries = Entries::new(BufReader::new(f));
+    let calendar = calendar
+        .or_else(|| config.caldav.calendar.clone())
+        .unwrap_or_else(|| "journal".to_owned());
+    let mut caldav = CalDav::new(&url, &calendar)?;
+    match (&config.caldav.username, &config.caldav.password_command) {
+        (Some(username), Some(command)) => {
+            caldav = caldav.with_user(username, &caldav::password(command)?);
+        }
+        (Some(_), None) => {
+            return Err(Error::InvalidArgument(
+                "the [caldav] section of your config file has a username but no password_command"
+                    .to_owned(),
+            ))
+        }
+        _ => {}
+    }
+
+    let days = caldav.publish(entries)?;
+    eprintln!(
+        "published {} {} to {}",
+        days,
+        if days == 1 { "day" } else { "days" },
+        caldav.url()
+    );
+    Ok(())
+}
+
+/// Fills in anything not given on the command line from the defaults in the
+/// config file. --last is only filled in when there are no other flags that
+/// pick which entries to show, otherwise a default_last in the config file
+/// would stop something like --start 2020 from showing everything since then.
+fn apply_defaults(mut opt: Opt, defaults: QueryDefaults) -> Opt {
+    if opt.format.is_none() {
+        opt.format = defaults.default_format;
+    }
+
+    if opt.color.is_none() {
+        opt.color = defaults.color;
+    }
+
+    let selects = opt.first.is_some()
+        || opt.last.is_some()
+        || opt.start.is_some()
+        || opt.end.is_some()
+        || opt.filters_entries()
+        || opt.random
+        || opt.count
+        || opt.calendar.is_some()
+        || opt.sql.is_some()
+        || opt.parquet.is_some()
+        || opt.caldav
+        || opt.graph.is_some();
+    if !selects {
+        opt.last = defaults.default_last;
+    }
+
+    opt
+}
+
+/// Where query results are written, keeping track of how much has been
+/// written so it can be reported when writing to a file.
+struct Output {
+    w: BufWriter<Box<dyn Write>>,
+    entries: u64,
+    bytes: u64,
+
+    /// Whether what was written is how many entries matched, for --count,
+    /// rather than the entries themselves.
+    counted: bool,
+
+    /// When set, entries are kept here rather than written, for outputs like
+    /// --html-site that need all of them before they can write anything.
+    collected: Option<Vec<Entry>>,
+
+    /// When set, encrypted messages are decrypted with this command before
+    /// they're written or collected, except in --raw output.
+    decrypt_command: Option<String>,
+
+    /// When set, links in entries' messages have the title of the entry they
+    /// point to put after them, except in --raw output.
+    links: Option<links::Graph>,
+
+    /// When set, entries' messages are redacted before they're written or
+    /// collected.
+    redactor: Option<Redactor>,
+
+    /// When set, a heading is written before the first entry of each group.
+    groups: Option<Groups>,
+
+    /// When set, only the first and last entries of each day are written.
+    bookends: Option<Bookends>,
+
+    /// When set, only one entry with each message is written, once all of
+    /// them have been seen.
+    distinct: Option<Distinct>,
+
+    /// When set, entries are held in `sorted` until all of them have been
+    /// seen, then sorted and written.
+    sort: Option<SortBy>,
+    sorted: Vec<Entry>,
+
+    /// When set, what this matched in each entry is formatted for
+    /// --print-captures rather than the entry itself.
+    captures: Option<Regex>,
+
+    /// When set, only every this many entries are kept, starting with the
+    /// first, and `skipped` counts the entries seen since the last one kept.
+    every: Option<u64>,
+    skipped: u64,
+
+    /// Whether entries are written with their number and their ID before
+    /// them.
+    number: bool,
+    ids: bool,
+}
+
+/// The day of the last entry seen for --bookends, and the last entry of that
+/// day so far, which isn't written until the next day starts.
+#[derive(Default)]
+struct Bookends {
+    day: Option<NaiveDate>,
+    last: Option<Entry>,
+}
+
+/// The entries kept for --distinct, in the order they were seen, along with
+/// how many entries had the same message as each of them.
+#[derive(Default)]
+struct Distinct {
+    keep: Keep,
+    count: bool,
+    seen: HashMap<String, usize>,
+    entries: Vec<(Entry, u64)>,
+}
+
+impl Distinct {
+    fn add(&mut self, entry: &Entry) {
+        let key = normalize(entry.message());
+        match self.seen.get(&key) {
+            Some(&i) => {
+                let (ref mut kept, ref mut count) = self.entries[i];
+                if self.keep == Keep::Last {
+                    *kept = entry.clone();
+                }
+                *count += 1;
+            }
+            None => {
+                self.seen.insert(key, self.entries.len());
+                self.entries.push((entry.clone(), 1));
+            }
+        }
+    }
+
+    /// The kept entries in the order they were written, which for
+    /// --distinct-keep last is the order of each message's last entry.
+    fn finish(&mut self) -> Vec<(Entry, u64)> {
+        self.seen.clear();
+        let mut entries = std::mem::take(&mut self.entries);
+        if self.keep == Keep::Last {
+            entries.sort_by_key(|(entry, _)| *entry.datetime());
+        }
+        entries
+    }
+}
+
+// Messages that only differ in case or whitespace count as the same message
+// for --distinct.
+fn normalize(message: &str) -> String {
+    message
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The headings written for --group-by, and the group the last entry written
+/// was in. A group's entries are held in `pending` until the group ends, so
+/// that its heading can have its totals in it.
+struct Groups {
+    by: GroupBy,
+    heading: Format<'static>,
+    current: Option<NaiveDate>,
+    totals: GroupTotals,
+    pending: String,
+    written: bool,
+}
+
+impl Groups {
+    fn new(by: GroupBy, heading: Format<'static>) -> Self {
+        Groups {
+            by,
+            heading,
+            current: None,
+            totals: GroupTotals::default(),
+            pending: String::new(),
+            written: false,
+        }
+    }
+
+    /// Adds `entry`, formatted as `formatted`, to its group, returning the
+    /// group before it with its heading if this entry ends it.
+    fn add(&mut self, entry: &Entry, formatted: &str) -> Result<Option<String>> {
+        let period = self.by.period(entry.datetime());
+        let finished = match self.current {
+            Some(current) if current != period => self.finish()?,
+            _ => None,
+        };
+        self.current = Some(period);
+        self.totals.add(entry);
+        self.pending.push_str(formatted);
+        self.pending.push('\n');
+        Ok(finished)
+    }
+
+    /// The group being added to with its heading, if it has any entries in
+    /// it. Groups after the first are separated by a blank line.
+    fn finish(&mut self) -> Result<Option<String>> {
+        let period = match self.current.take() {
+            Some(period) => period,
+            None => return Ok(None),
+        };
+        let heading = self.heading.format_heading(period, &self.totals)?;
+        let separator = if self.written { "\n" } else { "" };
+        self.written = true;
+        self.totals = GroupTotals::default();
+        let entries = std::mem::take(&mut self.pending);
+        Ok(Some(format!("{}{}\n{}", separator, heading, entries)))
+    }
+}
+
+impl Output {
+    fn new(w: Box<dyn Write>) -> Self {
+        Output {
+            w: BufWriter::new(w),
+            entries: 0,
+            bytes: 0,
+            counted: false,
+            collected: None,
+            decrypt_command: None,
+            links: None,
+            redactor: None,
+            groups: None,
+            bookends: None,
+            distinct: None,
+            sort: None,
+            sorted: Vec::new(),
+            captures: None,
+            every: None,
+            skipped: 0,
+            number: false,
+            ids: false,
+        }
+    }
+
+    /// Writes how many entries matched, for --count.
+    fn count(&mut self, count: u64) -> Result<()> {
+        writeln!(self, "{}", count)?;
+        self.entries = count;
+        self.counted = true;
+        Ok(())
+    }
+
+    /// What's written before an entry for --number and --ids.
+    fn prefix(&self, entry: &Entry) -> String {
+        let mut prefix = String::new();
+        if self.number {
+            prefix.push_str(&format!("{}. ", self.entries + 1));
+        }
+        if self.ids {
+            prefix.push_str(&format!("^{} ", entry.id()));
+        }
+        prefix
+    }
+
+    fn entry(&mut self, entry: &Entry, raw: bool, formatter: &mut Format) -> Result<()> {
+        if let Some(every) = self.every {
+            let skip = self.skipped > 0;
+            self.skipped = (self.skipped + 1) % every;
+            if skip {
+                return Ok(());
+            }
+        }
+        if let Some(ref mut distinct) = self.distinct {
+            distinct.add(entry);
+            return Ok(());
+        }
+        if self.sort.is_some() {
+            self.sorted.push(entry.clone());
+            return Ok(());
+        }
+        if let Some(ref mut bookends) = self.bookends {
+            let day = GroupBy::Day.period(entry.datetime());
+            if bookends.day == Some(day) {
+                bookends.last = Some(entry.clone());
+                return Ok(());
+            }
+            bookends.day = Some(day);
+            if let Some(last) = bookends.last.take() {
+                self.write_entry(&last, raw, formatter, None)?;
+            }
+        }
+        self.write_entry(entry, raw, formatter, None)
+    }
+
+    /// Writes anything that's been held back until all of the entries have
+    /// been seen.
+    fn finish(&mut self, raw: bool, formatter: &mut Format) -> Result<()> {
+        let mut held = match self.distinct.take() {
+            Some(mut distinct) => distinct
+                .finish()
+                .into_iter()
+                .map(|(entry, count)| (entry, distinct.count.then_some(count)))
+                .collect(),
+            None => Vec::new(),
+        };
+        if let Some(sort_by) = self.sort.take() {
+            held.extend(self.sorted.drain(..).map(|entry| (entry, None)));
+            sort_by.sort(&mut held);
+        }
+        for (entry, count) in held {
+            self.write_entry(&entry, raw, formatter, count)?;
+        }
+        match self.bookends.as_mut().and_then(|b| b.last.take()) {
+            Some(last) => self.write_entry(&last, raw, formatter, None),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes `entry`, followed by how many times its message was written if
+    /// `count` is given.
+    fn write_entry(
+        &mut self,
+        entry: &Entry,
+        raw: bool,
+        formatter: &mut Format,
+        count: Option<u64>,
+    ) -> Result<()> {
+        let decrypted;
+        let entry = match self.decrypt_command {
+            Some(ref command) if !raw && crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                decrypted = crypt::decrypt_entry(command, entry)?;
+                &decrypted
+            }
+            _ => entry,
+        };
+
+        let previewed;
+        let entry = match self.links {
+            Some(ref graph) if !raw => {
+                previewed = Entry::new(*entry.datetime(), graph.preview(entry.message()))
+                    .with_metadata(entry.metadata().clone());
+                &previewed
+            }
+            _ => entry,
+        };
+
+        let redacted;
+        let entry = match self.redactor {
+            Some(ref redactor) => {
+                redacted = redactor.entry(entry);
+                &redacted
+            }
+            None => entry,
+        };
+
+        if let Some(ref mut collected) = self.collected {
+            collected.push(entry.clone());
+        } else if raw {
+            write!(self, "{}", entry.to_csv_row()?)?;
+        } else {
+            let prefix = self.prefix(entry);
+            let formatted = match self.captures {
+                Some(ref regex) => formatter.format_data(&captures(regex, entry))?,
+                None => formatter.format_entry(entry)?,
+            };
+            let suffix = count.map_or(String::new(), |count| format!(" (×{})", count));
+            let formatted = format!("{}{}{}", prefix, formatted, suffix);
+            match self.groups {
+                Some(ref mut groups) => {
+                    if let Some(group) = groups.add(entry, &formatted)? {
+                        write!(self, "{}", group)?;
+                    }
+                }
+                None => writeln!(self, "{}", formatted)?,
+            }
+        }
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+/// The values passed in to the --print-captures template for `entry`: what
+/// each of `regex`'s groups matched the first time it matched the message,
+/// along with the entry's datetime, message and id. Groups that didn't match
+/// anything are empty.
+fn captures(regex: &Regex, entry: &Entry) -> serde_json::Map<String, serde_json::Value> {
+    let mut data = serde_json::Map::new();
+    data.insert("datetime".to_owned(), entry.datetime().to_rfc3339().into());
+    data.insert("message".to_owned(), entry.message().into());
+    data.insert("id".to_owned(), entry.id().into());
+
+    let caps = regex.captures(entry.message());
+    for (i, name) in regex.capture_names().enumerate() {
+        let matched = caps
+            .as_ref()
+            .and_then(|caps| caps.get(i))
+            .map_or("", |m| m.as_str());
+        data.insert(format!("c{}", i), matched.into());
+        if let Some(name) = name {
+            data.insert(name.to_owned(), matched.into());
+        }
+    }
+    data
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.w.write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+fn query<T: Seek + Read + BufRead>(
+    opt: Opt,
+    path: &Path,
+    mut formatter: Format,
+    mut entries: Entries<T>,
+    out: &mut Output,
+) -> Result<()> {
+    if opt.link_previews {
+        out.links = Some(link_graph(&mut entries)?);
+    }
 
     if opt.random {
         if let Some(entry) = entries.rand_entry()? {
-            println!("{}", formatter.format_entry(&entry)?);
+            out.entry(&entry, false, &mut formatter)?;
         }
         return Ok(());
     }
 
     if opt.regex.is_some() && opt.contains.is_some() {
-        return Err("You can only specify one of --contains and --regex".into());
+        return Err(Error::InvalidArgument(
+            "You can only specify one of --contains and --regex".to_owned(),
+        ));
     }
 
-    let regex = match opt.regex {
+    let regex = match opt.regex.as_deref() {
         None => None,
-        Some(s) => Some(regex::Regex::new(&s)?),
+        Some(s) => Some(Regex::new(s)?),
     };
 
     if opt.first.is_some() && opt.last.is_some() {
-        return Err("cannot specify --first and --last at the same time".into());
+        return Err(Error::InvalidArgument(
+            "cannot specify --first and --last at the same time".to_owned(),
+        ));
     }
 
     if let Some(first) = opt.first {
         if first < 1 {
-            return Err("--first must be greater than 0".into());
+            return Err(Error::InvalidArgument(
+                "--first must be greater than 0".to_owned(),
+            ));
         }
     }
 
     if let Some(last) = opt.last {
         if last < 1 {
-            return Err("--last must be greater than 0".into());
+            return Err(Error::InvalidArgument(
+                "--last must be greater than 0".to_owned(),
+            ));
+        }
+    }
+
+    if opt.every == Some(0) {
+        return Err(Error::InvalidArgument(
+            "--every must be greater than 0".to_owned(),
+        ));
+    }
+
+    let filter = Filter::All(opt.filters.clone());
+    let filtered = opt.filters_entries();
+    let contains = opt.contains.as_deref();
+
+    // Encrypted messages are decrypted before they're matched, so that
+    // searching finds what's in them. Matching can't fail, so the first error
+    // decrypting is kept here and returned once matching is done.
+    let decrypt_command = out.decrypt_command.clone();
+    let decrypt_error = Mutex::new(None);
+
+    // Lines that can't contain what --contains is looking for, or what every
+    // match of --regex starts with, are skipped before they're parsed, and
+    // entries are only copied out of the line they were read from once they've
+    // matched, which makes searching for a few of them a lot faster. There's no
+    // telling what's in an encrypted message until it's been decrypted though.
+    let prefixes = opt.regex.as_deref().and_then(entry::regex_prefixes);
+    let might_match = |line: &str| {
+        let encrypted = decrypt_command.is_some() && crypt::might_be_encrypted(line);
+        (encrypted
+            || contains.map_or(true, |s| entry::might_contain(line, s))
+                && prefixes.as_ref().map_or(true, |prefixes| {
+                    prefixes.iter().any(|p| entry::might_contain(line, p))
+                })
+                && (opt.backlinks.is_none() || entry::might_contain(line, "^")))
+            && (opt.near.is_none() || entry::might_contain(line, location::FIELD))
+    };
+    let matches = |entry: &EntryRef| {
+        let decrypted = match decrypt_command {
+            Some(ref command)
+                if filtered && crypt::is_encrypted(entry.message(), entry.metadata()) =>
+            {
+                match crypt::decrypt_entry(command, &entry.to_entry()) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        decrypt_error.lock().unwrap().get_or_insert(e);
+                        return false;
+                    }
+                }
+            }
+            _ => None,
+        };
+        let decrypted = decrypted.as_ref().map(Entry::as_entry_ref);
+        let entry = decrypted.as_ref().unwrap_or(entry);
+        contains.map_or(true, |s| entry.contains(s))
+            && regex.as_ref().map_or(true, |r| r.is_match(entry.message()))
+            && filter.matches_ref(entry)
+            && opt
+                .backlinks
+                .as_ref()
+                .map_or(true, |id| entry.links().contains(id))
+            && opt
+                .min_words
+                .map_or(true, |min| text::word_count(entry.message()) >= min)
+            && opt
+                .max_chars
+                .map_or(true, |max| entry.message().chars().count() <= max)
+            && opt
+                .near
+                .map_or(true, |near| near.contains(entry.metadata()))
+    };
+
+    // Without --first or --last every entry in the range has to be looked at to
+    // find the ones that match, so we split the range in to chunks and search
+    // them in parallel.
+    if filtered && opt.first.is_none() && opt.last.is_none() {
+        let progress = scan_progress_bar(opt.quiet);
+        let (start, end) = (opt.start.as_ref(), opt.end.as_ref());
+
+        if opt.count {
+            let count =
+                scan::par_count_with_progress(path, start, end, might_match, matches, &progress)?;
+            progress.finish_and_clear();
+            if let Some(e) = decrypt_error.into_inner().unwrap() {
+                return Err(e);
+            }
+            out.count(count)?;
+        } else {
+            // Entries are written out as they're found, stopping at the first
+            // one that couldn't be decrypted, and with the progress bar out of
+            // the way.
+            scan::par_filter_refs_for_each(
+                path,
+                start,
+                end,
+                might_match,
+                matches,
+                &progress,
+                |entry| {
+                    if let Some(e) = decrypt_error.lock().unwrap().take() {
+                        return Err(e);
+                    }
+                    progress.suspend(|| out.entry(&entry, opt.raw, &mut formatter))
+                },
+            )?;
+            progress.finish_and_clear();
+            if let Some(e) = decrypt_error.into_inner().unwrap() {
+                return Err(e);
+            }
+            out.finish(opt.raw, &mut formatter)?;
         }
+        return Ok(());
     }
 
-    if let Some(ref start_date) = opt.start {
-        entries.seek_to_first(start_date)?;
+    // Counting entries in a date range doesn't require parsing any of them, so
+    // we can skip the loop below entirely.
+    if opt.count && opt.first.is_none() && opt.last.is_none() {
+        return out.count(entries.count_between(opt.start.as_ref(), opt.end.as_ref())?);
     }
 
+    // With --last we position the cursor ourselves, so --start doesn't apply.
+    let mut start = opt.start.as_ref();
+
     if let Some(last) = opt.last {
         if let Some(ref end_date) = opt.end {
             // Because --end is exclusive, all we need to do is seek to the
@@ -159,7 +1465,7 @@ fn app(opt: Opt) -> Result<()> {
             // We read the last entry to get to the end of the file. We'll
             // end up reading the entry again later, so it's definitely not
             // the most optimal way to achieve this but it is the simplest.
-            let len = entries.len()?;
+            let len = entries.len_bytes()?;
             entries.at(len)?;
         }
 
@@ -168,90 +1474,73 @@ fn app(opt: Opt) -> Result<()> {
         for _ in 0..last {
             entries.seek_to_prev()?;
         }
+        start = None;
+    }
+
+    if let Some(date) = start {
+        entries.seek_to_first(date)?;
     }
 
     let mut count = 0;
-    loop {
-        if opt.first.is_some() && count >= opt.first.unwrap() {
+    while let Some(entry) = entries.next_entry_ref()? {
+        if opt.first.is_some_and(|first| count >= first) {
             break;
         }
 
-        match entries.next_entry()? {
-            None => break,
-            Some(entry) => {
-                // If we've found an entry that occurs on or after our given end
-                // date, break out and stop printing.
-                if opt.end.is_some() && opt.end.as_ref().unwrap() <= entry.datetime() {
-                    break;
-                }
-
-                // If we've found an entry that does not contain the specified
-                // string to search for, move to the next loop iteration.
-                if opt.contains.is_some()
-                    && !entry.message().contains(opt.contains.as_ref().unwrap())
-                {
-                    continue;
-                }
-
-                if regex.is_some() && !regex.as_ref().unwrap().is_match(entry.message()) {
-                    continue;
-                }
+        if opt.end.is_some_and(|end| entry.datetime() >= &end) {
+            break;
+        }
 
-                if !opt.count {
-                    if opt.raw {
-                        print!("{}", entry.to_csv_row()?);
-                    } else {
-                        println!("{}", formatter.format_entry(&entry)?);
-                    }
-                }
-                count += 1;
+        if !matches(&entry) {
+            match decrypt_error.lock().unwrap().take() {
+                Some(e) => return Err(e),
+                None => continue,
             }
-        };
+        }
+
+        if !opt.count {
+            out.entry(&entry.to_entry(), opt.raw, &mut formatter)?;
+        }
+        count += 1;
     }
 
     if opt.count {
-        println!("{}", count);
+        out.count(count as u64)?;
     }
 
-    Ok(())
+    out.finish(opt.raw, &mut formatter)
 }
 
-fn parse_date_arg(s: &str) -> Result<DateTime<FixedOffset>> {
-    if let Ok(d) = parse_local_datetime_str(&format!("{}-01-01T00:00:00", s), "%Y-%m-%dT%H:%M:%S") {
-        return Ok(d.into());
-    }
-    if let Ok(d) = parse_local_datetime_str(&format!("{}-01T00:00:00", s), "%Y-%m-%dT%H:%M:%S") {
-        return Ok(d.into());
-    }
-    if let Ok(d) = parse_local_datetime_str(&format!("{}T00:00:00", s), "%Y-%m-%dT%H:%M:%S") {
-        return Ok(d.into());
-    }
-    if let Ok(d) = parse_local_datetime_str(&format!("{}:00:00", s), "%Y-%m-%dT%H:%M:%S") {
-        return Ok(d.into());
-    }
-    if let Ok(d) = parse_local_datetime_str(&format!("{}:00", s), "%Y-%m-%dT%H:%M:%S") {
-        return Ok(d.into());
-    }
-    if let Ok(d) = parse_local_datetime_str(s, "%Y-%m-%dT%H:%M:%S") {
-        return Ok(d.into());
+/// Reads every entry for --link-previews, leaving `entries` back at the start.
+fn link_graph<T: Seek + Read + BufRead>(entries: &mut Entries<T>) -> Result<links::Graph> {
+    let mut graph = links::Graph::new();
+    entries.rewind()?;
+    while let Some(entry) = entries.next_entry_ref()? {
+        graph.add(&entry);
     }
-
-    Err(format!("unrecognised date format: \"{}\", accepted formats include things like:\n  - 2012\n  - 2012-01\n  - 2012-01-24\n  - 2012-01-24T16\n  - 2012-01-24T16:20\n  - 2012-01-24T16:20:30", s).into())
+    entries.rewind()?;
+    Ok(graph)
 }
 
-fn parse_local_datetime_str(s: &str, format: &str) -> Result<DateTime<Utc>> {
-    let d = NaiveDateTime::parse_from_str(s, format)?;
-    let local_result = Utc.from_local_datetime(&d);
-    Ok(local_result.earliest().unwrap_or_else(|| {
-        local_result
-            .latest()
-            .unwrap_or_else(|| local_result.unwrap())
-    }))
+/// A progress bar for scans that have to read every entry in a range, drawn on
+/// stderr. It stays hidden if asked to be quiet or if stderr isn't a terminal,
+/// so that it never ends up in a file or another program's input.
+fn scan_progress_bar(quiet: bool) -> indicatif::ProgressBar {
+    if quiet || !io::stderr().is_terminal() {
+        return indicatif::ProgressBar::hidden();
+    }
+
+    let sty = indicatif::ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {wide_bar:.cyan/blue} {bytes}/{total_bytes} {percent}% {eta_precise}")
+        .unwrap()
+        .progress_chars("##-");
+    let pb = indicatif::ProgressBar::new(0);
+    pb.set_style(sty);
+    pb
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
     use assert_cmd::{assert::Assert, prelude::*};
     use escargot::{CargoBuild, CargoRun};
     use lazy_static::lazy_static;
@@ -283,14 +1572,24 @@ mod tests {
         f.keep().unwrap().1
     }
 
-    #[test_case("2012"                => "2012-01-01T00:00:00+00:00" ; "y")]
-    #[test_case("2012-02"             => "2012-02-01T00:00:00+00:00" ; "ym")]
-    #[test_case("2012-02-02"          => "2012-02-02T00:00:00+00:00" ; "ymd")]
-    #[test_case("2012-02-02T02"       => "2012-02-02T02:00:00+00:00" ; "ymdh")]
-    #[test_case("2012-02-02T02:02"    => "2012-02-02T02:02:00+00:00" ; "ymdhm")]
-    #[test_case("2012-02-02T02:02:02" => "2012-02-02T02:02:02+00:00" ; "ymdhms")]
-    fn test_parse_date_arg(s: &str) -> String {
-        parse_date_arg(s).unwrap().to_rfc3339()
+    // Stands in for ssh by running the remote command here, or failing like
+    // ssh does when it can't connect if the host is "down".
+    #[cfg(unix)]
+    const FAKE_SSH: &str = "while [ \"$1\" != -- ]; do shift; done\n[ \"$2\" = down ] && exit 255\nexec sh -c \"$3\"\n";
+
+    #[cfg(unix)]
+    fn remote_command(bin: &CargoRun, host: &str, path: &Path) -> std::process::Command {
+        let ssh = new_tempfile(FAKE_SSH);
+        let paths = std::env::var_os("PATH").unwrap_or_default();
+        let paths = std::iter::once(bin.path().parent().unwrap().to_owned())
+            .chain(std::env::split_paths(&paths));
+
+        let mut cmd = bin.command();
+        cmd.env("HMM_SSH", format!("sh {}", ssh.display()))
+            .env("PATH", std::env::join_paths(paths).unwrap())
+            .arg("--path")
+            .arg(format!("ssh://{}{}", host, path.display()));
+        cmd
     }
 
     const TESTDATA: &str = "2020-01-01T00:01:00.899849209+00:00,\"\"\"1\"\"\"
@@ -314,11 +1613,40 @@ mod tests {
     #[test_case(vec!["--start", "2020-06-13", "--end", "2020-06-14", "--format", "{{ message }}"] => "6\n")]
     #[test_case(vec!["--contains", "1", "--format", "{{ message }}"] => "1\n")]
     #[test_case(vec!["--regex", "(1|2)", "--format", "{{ message }}"] => "1\n2\n")]
+    #[test_case(vec!["--regex", "[0-9]", "--start", "2020-02", "--end", "2020-05", "--format", "{{ message }}"] => "2\n3\n4\n")]
+    #[test_case(vec!["--contains", "3", "--start", "2020-04", "--format", "{{ message }}"] => "")]
+    #[test_case(vec!["--regex", "[0-9]", "--start", "2020-02", "--end", "2020-05", "--count"] => "3\n")]
     #[test_case(vec!["--raw"] => TESTDATA)]
+    #[test_case(vec!["--raw", "--mmap"] => TESTDATA)]
+    #[test_case(vec!["--mmap", "--last", "2", "--format", "{{ message }}"] => "5\n6\n" ; "get last two lines with mmap")]
+    #[test_case(vec!["--mmap", "--start", "2020-02", "--end", "2020-04", "--format", "{{ message }}"] => "2\n3\n" ; "date range with mmap")]
     #[test_case(vec!["--count"] => "6\n")]
     #[test_case(vec!["--first", "1", "--count"] => "1\n")]
+    #[test_case(vec!["--start", "2020-02", "--end", "2020-05", "--count"] => "3\n")]
+    #[test_case(vec!["--start", "2020-05", "--count"] => "2\n")]
+    #[test_case(vec!["--end", "2020-01", "--count"] => "0\n")]
     #[test_case(vec!["--contains", "4", "--count"] => "1\n")]
     #[test_case(vec!["--contains", "nope", "--count"] => "0\n")]
+    #[test_case(vec!["--first", "2", "--group-by", "month", "--format", "{{ message }}"] => "## January 2020\n1\n\n## February 2020\n2\n" ; "group by month")]
+    #[test_case(vec!["--start", "2020-05", "--group-by", "day", "--group-format", "{{ strftime \"%d/%m\" datetime }}", "--format", "{{ message }}"] => "12/05\n5\n\n13/06\n6\n" ; "group format")]
+    #[test_case(vec!["--start", "2020-05", "--group-by", "month", "--group-format", "{{ entries }} {{ words }} {{ span }} {{ strftime \"%d\" last }}", "--format", "{{ message }}"] => "1 1 0s 12\n5\n\n1 1 0s 13\n6\n" ; "group totals")]
+    #[test_case(vec!["--last", "1", "--group-by", "week", "--color", "never"] => "## Week of 2020-06-08\n╭ Sat 10:12\n│ 6\n╰─────────────────\n" ; "group by week")]
+    #[test_case(vec!["--first", "2", "--table", "--table-style", "ascii"] => "\
++------------------+------+---------+
+| Time             | Tags | Message |
++------------------+------+---------+
+| 2020-01-01 00:01 |      | 1       |
+| 2020-02-12 23:08 |      | 2       |
++------------------+------+---------+
+" ; "table")]
+    #[test_case(vec!["--start", "2020-05", "--number", "--format", "{{ message }}"] => "1. 5\n2. 6\n" ; "number")]
+    #[test_case(vec!["--first", "3", "--format", "[{{ since_prev }}] {{ message }}"] => "[] 1\n[42d23h7m40s] 2\n[28d51m19s] 3\n" ; "since_prev")]
+    #[test_case(vec!["--bookends", "--format", "{{ message }}"] => "1\n2\n3\n4\n5\n6\n" ; "bookends with an entry a day")]
+    #[test_case(vec!["--last", "1", "--number", "--ids", "--format", "{{ message }}"] => "1. ^682aadf1 6\n" ; "number and ids")]
+    #[test_case(vec!["--every", "2", "--format", "{{ message }}"] => "1\n3\n5\n" ; "every")]
+    #[test_case(vec!["--every", "4", "--start", "2020-02", "--number", "--format", "{{ message }}"] => "1. 2\n2. 6\n" ; "every with start")]
+    #[test_case(vec!["--every", "2", "--regex", "[2-6]", "--format", "{{ message }}"] => "2\n4\n6\n" ; "every match")]
+    #[test_case(vec!["--every", "1", "--last", "2", "--format", "{{ message }}"] => "5\n6\n" ; "every one")]
     fn test_hmmq(args: Vec<&str>) -> String {
         let path = new_tempfile(TESTDATA);
 
@@ -326,18 +1654,440 @@ mod tests {
         String::from_utf8(assert.get_output().stdout.clone()).unwrap()
     }
 
+    #[test_case(vec!["--titles-only"] => "2020-01-01 00:00 Standup\n2020-01-01 09:30 Lunch\n2020-01-02 10:00 \n" ; "titles")]
+    #[test_case(vec!["--titles-only", "--group-by", "day"] => "## 2020-01-01 (2 entries, 6 words, 00:00 to 09:30 (9h30m))\n00:00 Standup\n09:30 Lunch\n\n## 2020-01-02 (1 entry, 2 words, 10:00)\n10:00 \n" ; "grouped")]
+    #[test_case(vec!["--format", "{{ body }}", "--first", "1"] => "Talked about\nthe release\n" ; "body")]
+    fn test_hmmq_titles(args: Vec<&str>) -> String {
+        let path = new_tempfile(
+            "2020-01-01T00:00:00+00:00,\"\"\"Standup\\nTalked about\\nthe release\"\"\"
+2020-01-01T09:30:00+00:00,\"\"\"Lunch\"\"\"
+2020-01-02T10:00:00+00:00,\"\"\"\\nNo title\"\"\"
+",
+        );
+        let assert = run_with_path(&path, args);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    #[test]
+    fn test_hmmq_output() {
+        let path = new_tempfile(TESTDATA);
+        let output = new_tempfile("stale contents\n");
+        let args = vec![
+            "--first",
+            "2",
+            "--format",
+            "{{ message }}",
+            "--output",
+            output.to_str().unwrap(),
+        ];
+
+        let assert = run_with_path(&path, args.clone());
+        assert!(assert.get_output().stdout.is_empty());
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert_eq!(
+            stderr,
+            format!(
+                "wrote 2 entries (4 bytes) to {}\n",
+                output.to_str().unwrap()
+            )
+        );
+        assert.success();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "1\n2\n");
+
+        let mut append_args = args.clone();
+        append_args.push("--append-output");
+        run_with_path(&path, append_args).success();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "1\n2\n1\n2\n");
+
+        let mut quiet_args = vec!["--quiet"];
+        quiet_args.extend(args);
+        let assert = run_with_path(&path, quiet_args);
+        assert!(assert.get_output().stderr.is_empty());
+    }
+
+    #[test_case(vec![],                  "6\n" => "wrote a count of 6 entries (2 bytes)" ; "range")]
+    #[test_case(vec!["--contains", "1"], "1\n" => "wrote a count of 1 entry (2 bytes)"   ; "scan")]
+    #[test_case(vec!["--first", "2"],    "2\n" => "wrote a count of 2 entries (2 bytes)" ; "first")]
+    fn test_hmmq_output_count(args: Vec<&str>, count: &str) -> String {
+        let path = new_tempfile(TESTDATA);
+        let output = new_tempfile("");
+        let mut args = args;
+        args.extend(["--count", "--output", output.to_str().unwrap()]);
+
+        let assert = run_with_path(&path, args);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert.success();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), count);
+        stderr
+            .trim_end()
+            .strip_suffix(&format!(" to {}", output.to_str().unwrap()))
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test_case(vec![]                               => "start 1\nstop 1\nonly 2\nstart 3\nstop 3\n" ; "all days")]
+    #[test_case(vec!["--contains", "t"]              => "start 1\nstop 1\nstart 3\nstop 3\n"           ; "scan")]
+    #[test_case(vec!["--end", "2020-01-03T10:00:00"] => "start 1\nstop 1\nonly 2\nstart 3\ncoffee 3\n" ; "range")]
+    fn test_hmmq_bookends(args: Vec<&str>) -> String {
+        let path = new_tempfile(
+            "2020-01-01T09:00:00+00:00,\"\"\"start 1\"\"\"
+2020-01-01T12:00:00+00:00,\"\"\"lunch 1\"\"\"
+2020-01-01T17:00:00+00:00,\"\"\"stop 1\"\"\"
+2020-01-02T11:00:00+00:00,\"\"\"only 2\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"start 3\"\"\"
+2020-01-03T09:30:00+00:00,\"\"\"coffee 3\"\"\"
+2020-01-03T18:00:00+00:00,\"\"\"stop 3\"\"\"
+",
+        );
+        let mut args = args;
+        args.extend(["--bookends", "--format", "{{ message }}"]);
+        let assert = run_with_path(&path, args);
+        String::from_utf8(assert.get_output().stdout.clone()).unwrap()
+    }
+
+    #[test_case(vec![]                                           => "standup\nlunch\nStandup\n" ; "no distinct")]
+    #[test_case(vec!["--distinct"]                               => "standup\nlunch\n"            ; "first")]
+    #[test_case(vec!["--distinct", "--distinct-keep", "last"]    => "lunch\nStandup\n"            ; "last")]
+    #[test_case(vec!["--distinct", "--distinct-count"]           => "standup (×2)\nlunch (×1)\n"  ; "count")]
+    #[test_case(vec!["--distinct", "--contains", "up"]           => "standup\n"                    ; "scan")]
+    #[test_case(vec!["--distinct", "--last", "2"]                => "lunch\nStandup\n"            ; "last entries")]
+    fn test_hmmq_distinct(args: Vec<&str>) -> String {
+        let path = new_tempfile(
+            "2020-01-01T09:00:00+00:00,\"\"\"standup\"\"\"
+2020-01-01T12:00:00+00:00,\"\"\"lunch\"\"\"
+2020-01-02T09:00:00+00:00,\"\"\"Standup  \"\"\"
+",
+        );
+        let mut args = args;
+        args.extend(["--format", "{{ message }}"]);
+        let assert = run_with_path(&path, args);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    #[test_case(vec!["--regex", "deployed (\\S+) to (\\S+)", "--print-captures", "{{ c1 }} -> {{ c2 }}"] => "api -> prod\nweb -> staging\n" ; "numbered")]
+    #[test_case(vec!["--regex", "deployed (?P<app>\\S+)( to (?P<env>\\S+))?", "--print-captures", "{{ app }}:{{ env }}:{{ c0 }}"] => "api:prod:deployed api to prod\nweb:staging:deployed web to staging\nworker::deployed worker\n" ; "named and optional")]
+    #[test_case(vec!["--regex", "to (\\S+)", "--print-captures", "{{ strftime \"%d\" datetime }} {{ c1 }}", "--start", "2020-01-03"] => "03 staging\n" ; "datetime")]
+    fn test_hmmq_print_captures(args: Vec<&str>) -> String {
+        let path = new_tempfile(
+            "2020-01-01T09:00:00+00:00,\"\"\"deployed api to prod\"\"\"
+2020-01-02T09:00:00+00:00,\"\"\"lunch\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"deployed web to staging\"\"\"
+2020-01-04T09:00:00+00:00,\"\"\"deployed worker\"\"\"
+",
+        );
+        let assert = run_with_path(&path, args);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    const SORTS: &str = "2020-01-01T09:00:00+00:00,\"\"\"medium one\"\"\"
+2020-01-02T09:00:00+00:00,\"\"\"a short\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"the longest one of them\"\"\"
+2020-01-04T09:00:00+00:00,\"\"\"Zed\"\"\"
+2020-01-05T09:00:00+00:00,\"\"\"medium two\"\"\"
+";
+
+    #[test_case(vec!["--sort-by", "length"]                            => "the longest one of them\nmedium one\nmedium two\na short\nZed\n" ; "length")]
+    #[test_case(vec!["--sort-by", "alpha"]                             => "a short\nmedium one\nmedium two\nthe longest one of them\nZed\n" ; "alpha")]
+    #[test_case(vec!["--sort-by", "length", "--first", "2"]            => "medium one\na short\n" ; "first")]
+    #[test_case(vec!["--sort-by", "length", "--contains", "medium"]    => "medium one\nmedium two\n" ; "scan")]
+    #[test_case(vec!["--sort-by", "alpha", "--distinct", "--distinct-count", "--contains", "m"] => "medium one (×1)\nmedium two (×1)\nthe longest one of them (×1)\n" ; "distinct")]
+    fn test_hmmq_sort_by(args: Vec<&str>) -> String {
+        let path = new_tempfile(SORTS);
+        let mut args = args;
+        args.extend(["--format", "{{ message }}", "--start", "2020"]);
+        let assert = run_with_path(&path, args);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    #[test_case(vec!["--min-words", "2"]                   => "medium one\na short\nthe longest one of them\nmedium two\n" ; "min words")]
+    #[test_case(vec!["--max-chars", "7"]                   => "a short\nZed\n" ; "max chars")]
+    #[test_case(vec!["--min-words", "2", "--max-chars", "10"] => "medium one\na short\nmedium two\n" ; "both")]
+    #[test_case(vec!["--min-words", "2", "--last", "2"]    => "medium two\n" ; "last")]
+    #[test_case(vec!["--max-chars", "3", "--count"]        => "1\n" ; "count")]
+    fn test_hmmq_length_filters(args: Vec<&str>) -> String {
+        let path = new_tempfile(SORTS);
+        let mut args = args;
+        args.extend(["--format", "{{ message }}"]);
+        let assert = run_with_path(&path, args);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    #[test_case(vec!["--sort-by", "alpha"]                     => true  ; "unbounded")]
+    #[test_case(vec!["--sort-by", "alpha", "--contains", "x"]  => true  ; "filtered")]
+    #[test_case(vec!["--sort-by", "alpha", "--last", "2"]      => false ; "last")]
+    #[test_case(vec!["--sort-by", "alpha", "--start", "2020"]  => false ; "start")]
+    fn test_hmmq_sort_by_warns(args: Vec<&str>) -> bool {
+        let path = new_tempfile(SORTS);
+        let assert = run_with_path(&path, args);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert.success();
+        stderr.contains("--sort-by reads every entry")
+    }
+
+    #[test]
+    fn test_hmmq_sort_by_random() {
+        let path = new_tempfile(SORTS);
+        let assert = run_with_path(
+            &path,
+            vec!["--sort-by", "random", "--format", "{{ message }}"],
+        );
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(
+            stderr.starts_with("warning:") && stderr.contains("--sort-by reads every entry"),
+            "{}",
+            stderr
+        );
+        assert.success();
+
+        let mut shuffled: Vec<&str> = stdout.lines().collect();
+        shuffled.sort_unstable();
+        let mut expected: Vec<&str> = SORTS.lines().map(|l| &l[29..l.len() - 3]).collect();
+        expected.sort_unstable();
+        assert_eq!(shuffled, expected);
+    }
+
+    const MOODS: &str = "2020-01-01T09:00:00+00:00,\"\"\"rough\"\"\",\"{\"\"mood\"\":2}\"
+2020-01-02T09:00:00+00:00,\"\"\"no mood\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"fine\"\"\",\"{\"\"mood\"\":4}\"
+2020-01-04T09:00:00+00:00,\"\"\"great\"\"\",\"{\"\"mood\"\":5}\"
+";
+
+    #[test_case(vec!["--where", "mood>=4"]                       => "fine\ngreat\n" ; "scan")]
+    #[test_case(vec!["--where", "mood>=4", "--first", "1"]       => "fine\n"         ; "first")]
+    #[test_case(vec!["--where", "mood>1", "--where", "mood<5"]   => "rough\nfine\n" ; "all conditions")]
+    #[test_case(vec!["--where", "mood>=4", "--contains", "g"]    => "great\n"        ; "with contains")]
+    #[test_case(vec!["--where", "mood!=4", "--count"]            => "2\n"            ; "count")]
+    #[test_case(vec!["--where", "mood<3 or message~\"no\""]      => "rough\nno mood\n" ; "expression")]
+    #[test_case(vec!["--where", "not (mood>=4 or mood<3)"]       => "no mood\n"      ; "not")]
+    fn test_hmmq_where(args: Vec<&str>) -> String {
+        let path = new_tempfile(MOODS);
+        let mut args = args;
+        args.extend(["--format", "{{ message }}"]);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    const LINKS: &str = "2020-01-01T09:00:00+00:00,\"\"\"Planning the move\\nboxes\"\"\"
+2020-01-02T09:00:00+00:00,\"\"\"see ^6d30280a\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"x^6d30280a and ^b04d11ce\"\"\"
+2020-01-04T09:00:00+00:00,\"\"\"back to ^6D30280A, not ^00000000\"\"\"
+";
+
+    #[test_case(vec!["--backlinks", "^6d30280a"]                => "see ^6d30280a\nback to ^6D30280A, not ^00000000\n" ; "backlinks")]
+    #[test_case(vec!["--backlinks", "b04d11ce", "--count"]      => "1\n" ; "count")]
+    #[test_case(vec!["--backlinks", "6d30280a", "--first", "1"] => "see ^6d30280a\n" ; "first")]
+    #[test_case(vec!["--link-previews", "--last", "2"]          => "x^6d30280a and ^b04d11ce (\"see ^6d30280a\")\nback to ^6D30280A (\"Planning the move\"), not ^00000000\n" ; "previews")]
+    #[test_case(vec!["--link-previews", "--backlinks", "b04d11ce"] => "x^6d30280a and ^b04d11ce (\"see ^6d30280a\")\n" ; "previews of backlinks")]
+    fn test_hmmq_links(args: Vec<&str>) -> String {
+        let path = new_tempfile(LINKS);
+        let mut args = args;
+        args.extend(["--format", "{{ message }}"]);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test_case(vec!["--calendar", "2020-01"] => "                  January 2020
+   Mon    Tue    Wed    Thu    Fri    Sat    Sun
+               1   1  2   1  3   1  4   1  5   ·
+ 6   ·  7   ·  8   ·  9   · 10   · 11   · 12   ·
+13   · 14   · 15   · 16   · 17   · 18   · 19   ·
+20   · 21   · 22   · 23   · 24   · 25   · 26   ·
+27   · 28   · 29   · 30   · 31   ·
+" ; "calendar")]
+    #[test_case(vec!["--calendar", "2020-01-15", "--with-titles", "--where", "mood>=4"] => "                  January 2020
+   Mon    Tue    Wed    Thu    Fri    Sat    Sun
+               1   ·  2   ·  3   1  4   1  5   ·
+ 6   ·  7   ·  8   ·  9   · 10   · 11   · 12   ·
+13   · 14   · 15   · 16   · 17   · 18   · 19   ·
+20   · 21   · 22   · 23   · 24   · 25   · 26   ·
+27   · 28   · 29   · 30   · 31   ·
+
+Fri 3
+  09:00 fine
+
+Sat 4
+  09:00 great
+" ; "with titles and filters")]
+    fn test_hmmq_calendar(args: Vec<&str>) -> String {
+        let path = new_tempfile(MOODS);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    const ESCAPES: &str = "2020-01-01T09:00:00+00:00,\"\"\"line 1\\nline 2\"\"\"
+2020-01-02T09:00:00+00:00,\"\"\"say \\\"\"hi\\\"\"\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"caf\\u00e9\"\"\"
+2020-01-04T09:00:00+00:00,\"\"\"plain\"\"\"
+";
+
+    #[test_case(vec!["--contains", "line 2"]           => "line 1\nline 2\n" ; "after a newline")]
+    #[test_case(vec!["--contains", "1\nline"]          => "line 1\nline 2\n" ; "newline")]
+    #[test_case(vec!["--contains", "nline"]            => ""                 ; "escape letter")]
+    #[test_case(vec!["--contains", "\"hi\""]           => "say \"hi\"\n"     ; "quotes")]
+    #[test_case(vec!["--contains", "café"]             => "café\n"           ; "unicode escape")]
+    #[test_case(vec!["--contains", "i", "--count"]     => "3\n"              ; "count")]
+    #[test_case(vec!["--regex", "(?m)^line 2$"]        => "line 1\nline 2\n" ; "regex across lines")]
+    #[test_case(vec!["--regex", "\"\\w+\"", "--count"] => "1\n"              ; "regex quotes")]
+    fn test_hmmq_contains_escapes(args: Vec<&str>) -> String {
+        let path = new_tempfile(ESCAPES);
+        let mut args = args;
+        args.extend(["--format", "{{ message }}"]);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmmq_ics() {
+        let path = new_tempfile(TESTDATA);
+        let assert = run_with_path(&path, vec!["--ics", "entry", "--contains", "5"]);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+
+        assert!(stdout.starts_with("BEGIN:VCALENDAR\r\n"), "{}", stdout);
+        assert!(stdout.ends_with("END:VCALENDAR\r\n"), "{}", stdout);
+        assert_eq!(stdout.matches("BEGIN:VEVENT").count(), 1);
+        assert!(
+            stdout.contains("DTSTART:20200512T232848Z\r\n"),
+            "{}",
+            stdout
+        );
+        assert!(
+            stdout.contains("UID:20200512T232848.495151445Z@hmm\r\n"),
+            "{}",
+            stdout
+        );
+
+        let assert = run_with_path(&path, vec!["--ics", "day", "--start", "2020-03"]);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert_eq!(stdout.matches("BEGIN:VEVENT").count(), 4);
+    }
+
+    const LOCATIONS: &str = "2020-01-01T09:00:00+00:00,\"\"\"westminster\"\"\",\"{\"\"location\"\":{\"\"lat\"\":51.5007,\"\"lon\"\":-0.1246}}\"
+2020-01-02T09:00:00+00:00,\"\"\"nowhere\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"paris\"\"\",\"{\"\"location\"\":{\"\"lat\"\":48.8584,\"\"lon\"\":2.2945}}\"
+";
+
+    #[test_case(vec!["--near", "51.5,-0.12,2km"]               => "westminster\n"        ; "near")]
+    #[test_case(vec!["--near", "51.5,-0.12,500km"]             => "westminster\nparis\n" ; "wide")]
+    #[test_case(vec!["--near", "51.5,-0.12,2km", "--last", "5"] => "westminster\n"        ; "last")]
+    #[test_case(vec!["--near", "0,0,100km", "--count"]         => "0\n"                  ; "count")]
+    fn test_hmmq_near(args: Vec<&str>) -> String {
+        let path = new_tempfile(LOCATIONS);
+        let mut args = args;
+        args.extend(["--format", "{{ message }}"]);
+        let assert = run_with_path(&path, args);
+        String::from_utf8(assert.success().get_output().stdout.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_hmmq_twtxt() {
+        let path = new_tempfile(
+            "2020-01-01T09:00:00+00:00,\"\"\"standup #work\"\"\"\n2020-01-01T12:00:00+00:00,\"\"\"lunch with @sam\\nat noon #public\"\"\"\n",
+        );
+        let assert = run_with_path(
+            &path,
+            vec!["--twtxt", "--contains", "#public", "--redact", "mentions"],
+        );
+        assert
+            .success()
+            .stdout("2020-01-01T12:00:00+00:00\tlunch with [redacted]\u{2028}at noon #public\n");
+    }
+
+    #[test_case(vec!["--redact", "mentions"] => "lunch with [redacted] (sam@example.com)\n" ; "mentions")]
+    #[test_case(vec!["--redact", "mentions", "--redact", "emails"] => "lunch with [redacted] ([redacted])\n" ; "mentions and emails")]
+    #[test_case(vec!["--redact-regex", "l\\w+h"] => "[redacted] with @sam (sam@example.com)\n" ; "regex")]
+    #[test_case(vec!["--redact", "mentions", "--redact-with", "hash"] => "lunch with @def869c5 (sam@example.com)\n" ; "hash")]
+    #[test_case(vec!["--redact", "mentions", "--raw"] => "2020-01-01T00:00:00+00:00,\"\"\"lunch with [redacted] (sam@example.com)\"\"\"\n" ; "raw")]
+    fn test_hmmq_redact(args: Vec<&str>) -> String {
+        let path = new_tempfile(
+            "2020-01-01T00:00:00+00:00,\"\"\"lunch with @sam (sam@example.com)\"\"\"\n",
+        );
+        let assert = run_with_path(&path, [vec!["--format", "{{ message }}"], args].concat());
+        String::from_utf8(assert.success().get_output().stdout.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_hmmq_html_site() {
+        let path = new_tempfile(TESTDATA);
+        let dir = tempfile::TempDir::new().unwrap();
+        let site = dir.path().join("site");
+
+        let assert = run_with_path(
+            &path,
+            vec!["--start", "2020-04", "--html-site", site.to_str().unwrap()],
+        );
+        assert!(assert.get_output().stdout.is_empty());
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert_eq!(
+            stderr,
+            format!("wrote 3 entries to 3 pages in {}\n", site.to_str().unwrap())
+        );
+        assert.success();
+
+        let mut files: Vec<String> = std::fs::read_dir(&site)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "2020-04.html",
+                "2020-05.html",
+                "2020-06.html",
+                "entries.js",
+                "index.html"
+            ]
+        );
+    }
+
     #[test_case(vec!["--path", "/this/path/does/not/exist"],        "Couldn't open or create file at")]
     #[test_case(vec!["--path", "something", "--path", "something"], "The argument '--path <path>' was provided more than once")]
     #[test_case(vec!["--nonexistent"],                              "Found argument '--nonexistent' which wasn't expected")]
     #[test_case(vec!["--contains", "a", "--regex", "b"],            "You can only specify one of --contains and --regex")]
     #[test_case(vec!["--regex", "("],                               "regex parse error")]
+    #[test_case(vec!["--where", "mood"],                            "couldn't understand \"mood\"")]
+    #[test_case(vec!["--backlinks", "^xyz"],                        "^xyz isn't an entry ID, which looks like ^cbbf4083")]
     #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--first=-1"],                  "--first must be greater than 0")]
     #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--first", "0"],                "--first must be greater than 0")]
     #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--last=-1"],                   "--last must be greater than 0")]
     #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--last", "0"],                 "--last must be greater than 0")]
+    #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--every", "0"],                "--every must be greater than 0")]
+    #[test_case(vec!["--every", "2", "--count"],                     "cannot be used with")]
     #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--start", "nope"],             "unrecognised date format")]
     #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--end", "nope"],               "unrecognised date format")]
     #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--format", "{{"],              "invalid handlebars syntax")]
+    #[test_case(vec!["--group-by", "day", "--count"],                "cannot be used with")]
+    #[test_case(vec!["--sql", "select 1", "--table"],                "cannot be used with")]
+    #[test_case(vec!["--parquet", "out.parquet", "--count"],         "cannot be used with")]
+    #[test_case(vec!["--caldav", "--ics", "day"],                    "cannot be used with")]
+    #[test_case(vec!["--caldav-url", "http://127.0.0.1:1"],          "required arguments were not provided")]
+    #[test_case(vec!["--graph", "dot", "--format", "{{ message }}"], "cannot be used with")]
+    #[test_case(vec!["--graph", "svg"],                              "isn't a valid value for '--graph <graph>'")]
+    #[test_case(vec!["--table", "--count"],                          "cannot be used with")]
+    #[test_case(vec!["--table-style", "ascii"],                      "required arguments were not provided")]
+    #[test_case(vec!["--group-by", "year"],                          "isn't a valid value for '--group-by <group-by>'")]
+    #[test_case(vec!["--group-format", "{{ datetime }}"],            "required arguments were not provided")]
+    #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(),  "--errors", "json", "--first", "0"], "{\"code\":64,\"error\":\"invalid_argument\",\"message\":\"--first must be greater than 0\"}")]
     fn test_hmmq_errors(args: Vec<&str>, error: &str) {
         let assert = HMMQ.command().args(args).assert();
         let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
@@ -349,4 +2099,254 @@ mod tests {
             stderr
         );
     }
+
+    const GRAPH: &str = "2020-01-01T09:00:00+00:00,\"\"\"Planning the move #home\"\"\"
+2020-01-02T09:00:00+00:00,\"\"\"boxes with @sam, see ^6d30280a\"\"\"
+2020-01-03T09:00:00+00:00,\"\"\"nothing\"\"\"
+";
+
+    #[test_case(vec!["--graph", "dot"] => "digraph hmm {
+    \"^6d30280a\" [label=\"Planning the move #home\", shape=box];
+    \"#home\" [label=\"#home\", shape=ellipse];
+    \"^b04d11ce\" [label=\"boxes with @sam, see ^6d30280a\", shape=box];
+    \"@sam\" [label=\"@sam\", shape=diamond];
+    \"^6d30280a\" -> \"#home\";
+    \"^b04d11ce\" -> \"@sam\";
+    \"^b04d11ce\" -> \"^6d30280a\";
+}
+" ; "dot")]
+    #[test_case(vec!["--graph", "dot", "--start", "2020-01-02"] => "digraph hmm {
+    \"^b04d11ce\" [label=\"boxes with @sam, see ^6d30280a\", shape=box];
+    \"@sam\" [label=\"@sam\", shape=diamond];
+    \"^b04d11ce\" -> \"@sam\";
+}
+" ; "start")]
+    #[test_case(vec!["--graph", "dot", "--end", "2020-01-01"] => "digraph hmm {\n}\n" ; "empty")]
+    fn test_hmmq_graph(args: Vec<&str>) -> String {
+        let path = new_tempfile(GRAPH);
+        let assert = run_with_path(&path, args).success();
+        String::from_utf8(assert.get_output().stdout.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_hmmq_graph_json() {
+        let path = new_tempfile(GRAPH);
+        let assert = run_with_path(&path, vec!["--graph", "json"]).success();
+        let graph: serde_json::Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+        assert_eq!(graph["nodes"].as_array().unwrap().len(), 4);
+        assert_eq!(
+            graph["edges"][2],
+            serde_json::json!({"source": "^b04d11ce", "target": "^6d30280a", "type": "entry"})
+        );
+    }
+
+    // The binary tests run against is built with the default features, so
+    // this only checks that hmmq --sql says how to get it.
+    #[cfg(not(feature = "sql"))]
+    #[test]
+    fn test_hmmq_sql_without_feature() {
+        let assert = HMMQ
+            .command()
+            .arg("--path")
+            .arg(new_tempfile(TESTDATA))
+            .args(["--sql", "select count(*) from entries"])
+            .assert()
+            .code(64);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("--features sql"), "{}", stderr);
+        assert!(assert.get_output().stdout.is_empty());
+    }
+
+    // The binary tests run against is built with the default features, so
+    // this only checks that hmmq --parquet says how to get it.
+    #[cfg(not(feature = "parquet"))]
+    #[test]
+    fn test_hmmq_parquet_without_feature() {
+        let out = tempfile::TempDir::new().unwrap();
+        let assert = HMMQ
+            .command()
+            .arg("--path")
+            .arg(new_tempfile(TESTDATA))
+            .arg("--parquet")
+            .arg(out.path().join("hmm.parquet"))
+            .assert()
+            .code(64);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("--features parquet"), "{}", stderr);
+        assert!(!out.path().join("hmm.parquet").exists());
+    }
+
+    // The binary tests run against is built with the default features, so
+    // this only checks that hmmq --caldav says how to get it.
+    #[cfg(not(feature = "caldav"))]
+    #[test]
+    fn test_hmmq_caldav_without_feature() {
+        let assert = HMMQ
+            .command()
+            .arg("--path")
+            .arg(new_tempfile(TESTDATA))
+            .args(["--caldav", "--caldav-url", "http://127.0.0.1:1/calendars"])
+            .assert()
+            .code(64);
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("--features caldav"), "{}", stderr);
+    }
+
+    #[cfg(unix)]
+    #[test_case(vec!["--format", "{{ message }}", "--last", "2"]                    => "5\n6\n"   ; "query")]
+    #[test_case(vec!["--format={{ message }}", "--start", "2020-06", "--color", "always"] => "6\n" ; "equals and color")]
+    #[test_case(vec!["--config", "/does/not/exist", "--count", "--end", "2020-02"]  => "1\n"       ; "local config isn't passed on")]
+    fn test_hmmq_remote(args: Vec<&str>) -> String {
+        let path = new_tempfile(TESTDATA);
+        let assert = remote_command(&HMMQ, "box", &path).args(args).assert();
+        String::from_utf8(assert.success().get_output().stdout.clone()).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test_case("box",  vec!["--format", "{{"]            => 64 ; "remote error")]
+    #[test_case("box",  vec!["--output", "/tmp/out.txt"]  => 64 ; "output")]
+    #[test_case("down", vec![]                            => 69 ; "can't connect")]
+    fn test_hmmq_remote_errors(host: &str, args: Vec<&str>) -> i32 {
+        let path = new_tempfile(TESTDATA);
+        let assert = remote_command(&HMMQ, host, &path).args(args).assert();
+        assert.get_output().status.code().unwrap()
+    }
+
+    #[test_case("", vec![] => "1\n2\n3\n4\n5\n6\n" ; "no config")]
+    #[test_case("default_last = 2", vec![] => "5\n6\n" ; "default last")]
+    #[test_case("default_last = 2", vec!["--last", "1"] => "6\n" ; "flag overrides default last")]
+    #[test_case("default_last = 2", vec!["--start", "2020-04"] => "4\n5\n6\n" ; "default last only applies without other flags")]
+    #[test_case("default_format = \"<{{ message }}>\"", vec!["--last", "1"] => "<6>\n" ; "default format")]
+    #[test_case("color = \"always\"", vec!["--last", "1", "--format", "{{ color \"blue\" message }}"] => "\x1b[34m6\x1b[0m\n" ; "color always")]
+    #[test_case("color = \"always\"", vec!["--last", "1", "--format", "{{ color \"blue\" message }}", "--color", "never"] => "6\n" ; "flag overrides color")]
+    #[test_case("color = \"always\"\n[[color_rules]]\nweekday = \"Saturday\"\ndim = true", vec!["--last", "2", "--format", "{{ autocolor message }}"] => "5\n\x1b[2m6\x1b[0m\n" ; "color rules")]
+    #[test_case("[[journals]]\npath = \"JOURNAL\"\ndefault_last = 1", vec![] => "6\n" ; "per journal")]
+    #[test_case("default_last = 3\n[[journals]]\npath = \"/some/other.hmm\"\ndefault_last = 1", vec![] => "4\n5\n6\n" ; "other journal")]
+    fn test_hmmq_config(config: &str, args: Vec<&str>) -> String {
+        let path = new_tempfile(TESTDATA);
+        let config_path = new_tempfile(&config.replace("JOURNAL", path.to_str().unwrap()));
+
+        let mut full_args = vec!["--config", config_path.to_str().unwrap()];
+        if !args.contains(&"--format") && !config.contains("default_format") {
+            full_args.extend(&["--format", "{{ message }}"]);
+        }
+        full_args.extend(args);
+
+        let assert = run_with_path(&path, full_args);
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    #[cfg(unix)]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec![] => "hello\nplain\n" ; "decrypted")]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec!["--raw"] => "2020-01-01T00:00:00+00:00,\"\"\"hmm-encrypted:dXJ5eWI=\"\"\",\"{\"\"encrypted\"\":true}\"\n2020-01-02T00:00:00+00:00,\"\"\"plain\"\"\"\n" ; "raw stays encrypted")]
+    #[test_case("", vec![] => "hmm-encrypted:dXJ5eWI=\nplain\n" ; "no decrypt command")]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec!["--contains", "hello"] => "hello\n" ; "contains")]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec!["--regex", "^hel+o$"] => "hello\n" ; "regex")]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec!["--where", "message~ell"] => "hello\n" ; "where")]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec!["--contains", "hello", "--first", "5"] => "hello\n" ; "contains with first")]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec!["--contains", "hello", "--count"] => "1\n" ; "contains with count")]
+    #[test_case("decrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"", vec!["--contains", "hello", "--raw"] => "2020-01-01T00:00:00+00:00,\"\"\"hmm-encrypted:dXJ5eWI=\"\"\",\"{\"\"encrypted\"\":true}\"\n" ; "contains with raw")]
+    #[test_case("", vec!["--contains", "hello"] => "" ; "contains without decrypt command")]
+    fn test_hmmq_decrypt_command(config: &str, args: Vec<&str>) -> String {
+        let path = new_tempfile(
+            "2020-01-01T00:00:00+00:00,\"\"\"hmm-encrypted:dXJ5eWI=\"\"\",\"{\"\"encrypted\"\":true}\"\n2020-01-02T00:00:00+00:00,\"\"\"plain\"\"\"\n",
+        );
+        let config_path = new_tempfile(config);
+        let args = [
+            vec![
+                "--config",
+                config_path.to_str().unwrap(),
+                "--format",
+                "{{ message }}",
+            ],
+            args,
+        ]
+        .concat();
+        let assert = run_with_path(&path, args);
+        String::from_utf8(assert.success().get_output().stdout.clone()).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test_case(vec!["--contains", "hello"]               ; "scan")]
+    #[test_case(vec!["--contains", "hello", "--first", "5"] ; "first")]
+    fn test_hmmq_decrypt_command_fails(args: Vec<&str>) {
+        let path = new_tempfile(
+            "2020-01-01T00:00:00+00:00,\"\"\"hmm-encrypted:dXJ5eWI=\"\"\",\"{\"\"encrypted\"\":true}\"\n2020-01-02T00:00:00+00:00,\"\"\"hello\"\"\"\n",
+        );
+        let config_path = new_tempfile("decrypt_command = \"exit 1\"");
+        let args = [vec!["--config", config_path.to_str().unwrap()], args].concat();
+        run_with_path(&path, args).code(70).stdout("");
+    }
+
+    #[test_case(vec![("HMM_FORMAT", "<{{ message }}>")],                          vec![]                            => "<6>\n"  ; "HMM_FORMAT")]
+    #[test_case(vec![("HMM_FORMAT", "<{{ message }}>")],                          vec!["--format", "{{ message }}"] => "6\n"    ; "flag before HMM_FORMAT")]
+    #[test_case(vec![("HMM_FORMAT", "<{{ message }}>"), ("HMM_CONFIG", "CONFIG")], vec![]                            => "<6>\n"  ; "HMM_FORMAT before config")]
+    #[test_case(vec![("HMM_CONFIG", "CONFIG")],                                   vec![]                            => "[6]\n"  ; "HMM_CONFIG")]
+    #[test_case(vec![("HMM_COLOR", "always"), ("HMM_FORMAT", "{{ color \"blue\" message }}")], vec![]              => "\x1b[34m6\x1b[0m\n" ; "HMM_COLOR")]
+    fn test_hmmq_env(env: Vec<(&str, &str)>, args: Vec<&str>) -> String {
+        let path = new_tempfile(TESTDATA);
+        let config_path = new_tempfile("default_format = \"[{{ message }}]\"");
+
+        let mut cmd = HMMQ.command();
+        cmd.env("HMM_PATH", &path)
+            .env("HMM_CONFIG", "/this/path/does/not/exist")
+            .env_remove("HMM_FORMAT")
+            .env_remove("HMM_COLOR")
+            .args(["--last", "1"])
+            .args(args);
+        for (k, v) in env {
+            cmd.env(k, v.replace("CONFIG", config_path.to_str().unwrap()));
+        }
+
+        let assert = cmd.assert();
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    #[test_case(vec!["newest"]                   => "6\n"     ; "alias")]
+    #[test_case(vec!["newest", "--end", "2020-06"] => "5\n" ; "arguments after an alias")]
+    #[test_case(vec!["--last", "2", "--raw"]     => "2020-05-12T23:28:48.495151445+00:00,\"\"\"5\"\"\"\n2020-06-13T10:12:53.353050231+00:00,\"\"\"6\"\"\"\n" ; "no alias")]
+    fn test_hmmq_aliases(args: Vec<&str>) -> String {
+        let path = new_tempfile(TESTDATA);
+        let config_path = new_tempfile(
+            "[aliases]\nplain = \"--format '{{ message }}'\"\nnewest = \"plain --last 1\"",
+        );
+
+        let assert = HMMQ
+            .command()
+            .args(args)
+            .arg("--path")
+            .arg(&path)
+            .env("HMM_CONFIG", &config_path)
+            .assert();
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        stdout
+    }
+
+    #[test]
+    fn test_hmmq_alias_loop() {
+        let config_path = new_tempfile("[aliases]\na = \"b\"\nb = \"a\"");
+        let assert = HMMQ
+            .command()
+            .arg("a")
+            .env("HMM_CONFIG", &config_path)
+            .assert();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("alias loop: a -> b -> a"), "{}", stderr);
+        assert.code(64);
+    }
+
+    #[test_case(vec!["--path", "/this/path/does/not/exist"]                    => 66 ; "file can't be opened")]
+    #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(), "--first", "0"] => 64 ; "invalid argument")]
+    #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(), "--regex", "("] => 64 ; "invalid regex")]
+    #[test_case(vec!["--path", new_tempfile("nope\n").to_str().unwrap()]        => 65 ; "malformed file")]
+    #[test_case(vec!["--path", new_tempfile("").to_str().unwrap(), "--output", "/this/path/does/not/exist"] => 73 ; "output can't be created")]
+    fn test_hmmq_exit_codes(args: Vec<&str>) -> i32 {
+        let assert = HMMQ.command().args(args).assert();
+        assert.get_output().status.code().unwrap()
+    }
 }
@@ -0,0 +1,161 @@
+use hmmcli::{
+    error::{Error, ErrorFormat},
+    remind::{self, Reminder, Scheduler},
+    Result,
+};
+use human_panic::setup_panic;
+use std::process::{exit, Command};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hmmremind",
+    about = "Get a notification every so often reminding you to write in your hmm file"
+)]
+struct Opt {
+    /// How often to remind you, like 30m, 2h or 1d.
+    #[structopt(long = "every", required_unless = "remove")]
+    every: Option<String>,
+
+    /// What the reminder says.
+    #[structopt(long = "message", default_value = "What's on your mind?")]
+    message: String,
+
+    /// Remove the reminder instead of installing one.
+    #[structopt(long = "remove", conflicts_with = "every")]
+    remove: bool,
+
+    /// What runs the reminder: "systemd", "launchd" or "schtasks". Defaults to
+    /// the one for your OS.
+    #[structopt(long = "scheduler", possible_values = &["systemd", "launchd", "schtasks"])]
+    scheduler: Option<Scheduler>,
+
+    /// Print what would be written and run instead of doing it.
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    let errors = opt.errors;
+    if let Err(e) = app(opt) {
+        errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+/// Installs or removes a reminder to write in your journal.
+fn app(opt: Opt) -> Result<()> {
+    let scheduler = opt.scheduler.unwrap_or_else(Scheduler::current);
+    let dir = scheduler.dir().ok_or_else(|| {
+        Error::Scheduler("couldn't find the directory to install the reminder in".to_owned())
+    })?;
+
+    let steps = match opt.every {
+        _ if opt.remove => remind::uninstall(scheduler, &dir),
+        Some(every) => Reminder::new(remind::parse_interval(&every)?, &opt.message)?
+            .install(scheduler, &dir)?,
+        None => unreachable!("clap requires --every without --remove"),
+    };
+
+    for step in steps {
+        if opt.dry_run {
+            println!("{}", step);
+            continue;
+        }
+        match step {
+            remind::Step::Write(path, contents) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&path, contents)
+                    .map_err(|source| Error::CreateFile { path, source })?;
+            }
+            remind::Step::Remove(path) => match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+            remind::Step::Run(args) => run_scheduler(&args, false)?,
+            remind::Step::Try(args) => run_scheduler(&args, true)?,
+        }
+    }
+    Ok(())
+}
+
+fn run_scheduler(args: &[String], may_fail: bool) -> Result<()> {
+    let output = Command::new(&args[0])
+        .args(&args[1..])
+        .output()
+        .map_err(|e| Error::Scheduler(format!("couldn't run {}: {}", args[0], e)))?;
+    if output.status.success() || may_fail {
+        return Ok(());
+    }
+    Err(Error::Scheduler(format!(
+        "`{}` failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::prelude::*;
+    use escargot::{CargoBuild, CargoRun};
+    use lazy_static::lazy_static;
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMREMIND: CargoRun = CargoBuild::new()
+            .bin("hmmremind")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_hmmremind_dry_run() {
+        let config = tempfile::TempDir::new().unwrap();
+        let assert = HMMREMIND
+            .command()
+            .env("XDG_CONFIG_HOME", config.path())
+            .args(["--every", "2h", "--message", "journal?"])
+            .args(["--scheduler", "systemd", "--dry-run"])
+            .assert()
+            .success();
+        let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert!(
+            stdout.contains("ExecStart=/usr/bin/env notify-send hmm \"journal?\""),
+            "{}",
+            stdout
+        );
+        assert!(stdout.contains("OnUnitActiveSec=7200"), "{}", stdout);
+        assert!(
+            stdout.contains("run systemctl --user enable --now hmm-remind.timer"),
+            "{}",
+            stdout
+        );
+        assert_eq!(std::fs::read_dir(config.path()).unwrap().count(), 0);
+    }
+
+    #[test_case(vec![]                                       => 1  ; "no interval")]
+    #[test_case(vec!["--every", "2h", "--remove"]           => 1  ; "interval and remove")]
+    #[test_case(vec!["--every", "soon", "--dry-run"]        => 64 ; "invalid interval")]
+    #[test_case(vec!["--every", "30s", "--dry-run"]         => 64 ; "too often")]
+    fn test_hmmremind_errors(args: Vec<&str>) -> i32 {
+        HMMREMIND
+            .command()
+            .args(args)
+            .assert()
+            .get_output()
+            .status
+            .code()
+            .unwrap()
+    }
+}
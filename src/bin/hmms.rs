@@ -0,0 +1,940 @@
+use chrono::prelude::*;
+use hmmcli::{
+    config::{expand_tilde, Config},
+    crypt, date, digest,
+    entries::Entries,
+    entry::Entry,
+    error::{Error, ErrorFormat},
+    filter::Filter,
+    format,
+    format::GroupBy,
+    gaps::Gaps,
+    mood::{self, Chart},
+    pins, remind,
+    remote::Remote,
+    review::{self, Review},
+    series::{self, Series},
+    storage::Storage,
+    text,
+    track::{self, Tracker},
+    year::{self, Year},
+    Result,
+};
+use human_panic::setup_panic;
+use std::fs::File;
+use std::io::{BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "hmms", about = "Reports on what's in your hmm file")]
+struct Opt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+
+    #[structopt(subcommand)]
+    report: Report,
+}
+
+#[derive(Debug, StructOpt)]
+enum Report {
+    /// Add up the time tracked with hmm --start and hmm --stop.
+    #[structopt(name = "time")]
+    Time(TimeOpt),
+
+    /// Chart the moods written with hmm --mood, a week to a line.
+    #[structopt(name = "mood")]
+    Mood(MoodOpt),
+
+    /// List the stretches of time nothing was written in.
+    #[structopt(name = "gaps")]
+    Gaps(GapsOpt),
+
+    /// Look back over a year: how much was written, when, and about what.
+    #[structopt(name = "year")]
+    Year(YearOpt),
+
+    /// Add up what was written in each hour, day or any other length of time,
+    /// as CSV or JSON for plotting.
+    #[structopt(name = "series")]
+    Series(SeriesOpt),
+
+    /// Print a digest of what was written this week, ready to paste in to an
+    /// update.
+    #[structopt(name = "review")]
+    Review(ReviewOpt),
+
+    /// Print or email what was written yesterday, along with what was written
+    /// on the same day in years gone by.
+    #[structopt(name = "digest")]
+    Digest(DigestOpt),
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(group = structopt::clap::ArgGroup::with_name("period"))]
+struct TimeOpt {
+    /// What to add up time by: "tag" gives a total for each project and "day"
+    /// a total for each day.
+    #[structopt(long = "by", default_value = "tag", possible_values = &["tag", "day"])]
+    by: track::By,
+
+    /// Date to start counting from, inclusive. Like hmmq's --start, this can
+    /// be any subset of an RFC3339 date, like 2024-05 or 2024-05-13.
+    #[structopt(short = "s", long = "start", parse(try_from_str = date::parse), conflicts_with = "period")]
+    start: Option<DateTime<FixedOffset>>,
+
+    /// Date to stop counting at, exclusive. See --start for details.
+    #[structopt(short = "e", long = "end", parse(try_from_str = date::parse), conflicts_with = "period")]
+    end: Option<DateTime<FixedOffset>>,
+
+    /// Only count time today.
+    #[structopt(long = "today", group = "period")]
+    today: bool,
+
+    /// Only count time this week, starting on Monday.
+    #[structopt(long = "this-week", group = "period")]
+    this_week: bool,
+
+    /// Only count time this month.
+    #[structopt(long = "this-month", group = "period")]
+    this_month: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct MoodOpt {
+    /// Date to start charting from, inclusive. Like hmmq's --start, this can
+    /// be any subset of an RFC3339 date, like 2024-05 or 2024-05-13.
+    #[structopt(short = "s", long = "start", parse(try_from_str = date::parse))]
+    start: Option<DateTime<FixedOffset>>,
+
+    /// Date to stop charting at, exclusive. See --start for details.
+    #[structopt(short = "e", long = "end", parse(try_from_str = date::parse))]
+    end: Option<DateTime<FixedOffset>>,
+
+    /// Show each day's mood as a number rather than a bar, for terminals and
+    /// fonts that don't have block characters.
+    #[structopt(long = "ascii")]
+    ascii: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct GapsOpt {
+    /// Only list gaps at least this long, like 12h, 3d or 2d12h.
+    #[structopt(long = "min", default_value = "1d", parse(try_from_str = parse_min))]
+    min: chrono::Duration,
+
+    /// Date to look for gaps from, inclusive. Like hmmq's --start, this can be
+    /// any subset of an RFC3339 date, like 2024-05 or 2024-05-13.
+    #[structopt(short = "s", long = "start", parse(try_from_str = date::parse))]
+    start: Option<DateTime<FixedOffset>>,
+
+    /// Date to look for gaps until, exclusive. See --start for details. The
+    /// time from the last entry until then, or until now if this isn't given,
+    /// counts as a gap too.
+    #[structopt(short = "e", long = "end", parse(try_from_str = date::parse))]
+    end: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, StructOpt)]
+struct YearOpt {
+    /// The year to look back over, like 2024. Defaults to this year.
+    #[structopt(parse(try_from_str = parse_year))]
+    year: Option<i32>,
+
+    /// How many of the tags and mentions you used most to include.
+    #[structopt(long = "top", default_value = "5")]
+    top: usize,
+
+    /// A Handlebars template to render the report with, overriding the
+    /// year_template setting in your config file.
+    #[structopt(long = "template")]
+    template: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+struct SeriesOpt {
+    /// How long each bucket is, like 1h, 1d or 7d. Buckets start at midnight
+    /// on the day of the first entry, or at --start if it's given.
+    #[structopt(long = "bucket", default_value = "1d", parse(try_from_str = parse_bucket))]
+    bucket: chrono::Duration,
+
+    /// What to add up in each bucket: "count" is how many entries were
+    /// written and "words" how many words.
+    #[structopt(long = "value", default_value = "count", possible_values = &["count", "words"])]
+    value: series::Value,
+
+    /// What to print the series as.
+    #[structopt(long = "format", default_value = "csv", possible_values = &["csv", "json"])]
+    format: series::Format,
+
+    /// Date to start the series from, inclusive. Like hmmq's --start, this can
+    /// be any subset of an RFC3339 date, like 2024-05 or 2024-05-13.
+    #[structopt(short = "s", long = "start", parse(try_from_str = date::parse))]
+    start: Option<DateTime<FixedOffset>>,
+
+    /// Date to end the series at, exclusive. See --start for details. Empty
+    /// buckets are added after the last entry up to here.
+    #[structopt(short = "e", long = "end", parse(try_from_str = date::parse))]
+    end: Option<DateTime<FixedOffset>>,
+
+    /// Only count entries that contain this string.
+    #[structopt(long = "contains")]
+    contains: Option<String>,
+
+    /// Only count entries that match this filter, in the same form as hmmq's
+    /// --where. Can be given more than once, and entries have to match all of
+    /// them.
+    #[structopt(long = "where", number_of_values = 1)]
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, StructOpt)]
+struct ReviewOpt {
+    /// Review a week, starting on Monday. This is the default.
+    #[structopt(long = "week", conflicts_with = "month")]
+    week: bool,
+
+    /// Review a month instead of a week.
+    #[structopt(long = "month")]
+    month: bool,
+
+    /// Review the week or month before the current one.
+    #[structopt(long = "last", conflicts_with = "date")]
+    last: bool,
+
+    /// Review the week or month this date is in, rather than the current one.
+    /// Like hmmq's --start, this can be any subset of an RFC3339 date.
+    #[structopt(long = "date", parse(try_from_str = date::parse))]
+    date: Option<DateTime<FixedOffset>>,
+
+    /// How many of the tags you used most to include.
+    #[structopt(long = "top", default_value = "5")]
+    top: usize,
+
+    /// A Handlebars template to render the review with, overriding the
+    /// review_template setting in your config file.
+    #[structopt(long = "template")]
+    template: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+struct DigestOpt {
+    /// Make a daily digest, of yesterday's entries. Daily digests are the only
+    /// kind so far, so this is the default, but it makes crontabs clearer.
+    #[structopt(long = "daily")]
+    #[allow(dead_code)]
+    daily: bool,
+
+    /// Make the digest as if it was run on this date, so it's of the day
+    /// before. Like hmmq's --start, this can be any subset of an RFC3339 date.
+    #[structopt(long = "date", parse(try_from_str = date::parse))]
+    date: Option<DateTime<FixedOffset>>,
+
+    /// Email the digest to this address, using the sendmail_command from your
+    /// config file, instead of printing it. Nothing is sent if there's nothing
+    /// in the digest.
+    #[structopt(long = "email")]
+    email: Option<String>,
+
+    /// A Handlebars template to render the digest with, overriding the
+    /// digest_template setting in your config file.
+    #[structopt(long = "template")]
+    template: Option<PathBuf>,
+}
+
+fn parse_bucket(s: &str) -> Result<chrono::Duration> {
+    let bucket = remind::parse_interval(s)?;
+    if bucket.is_zero() {
+        return Err(Error::InvalidArgument(format!(
+            "--bucket {} has to be longer than 0s",
+            s
+        )));
+    }
+    chrono::Duration::from_std(bucket)
+        .map_err(|_| Error::InvalidArgument(format!("--bucket {} is too long", s)))
+}
+
+fn parse_year(s: &str) -> Result<i32> {
+    s.parse()
+        .ok()
+        .filter(|year| (1..=9998).contains(year))
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!("\"{}\" isn't a year, expected one like 2024", s))
+        })
+}
+
+fn parse_min(s: &str) -> Result<chrono::Duration> {
+    let min = remind::parse_interval(s)?;
+    chrono::Duration::from_std(min)
+        .map_err(|_| Error::InvalidArgument(format!("--min {} is too long", s)))
+}
+
+impl TimeOpt {
+    /// The times to count from and to, from --start and --end or from the
+    /// period that was asked for.
+    fn range(&self) -> (Option<DateTime<FixedOffset>>, Option<DateTime<FixedOffset>>) {
+        let period = if self.today {
+            GroupBy::Day
+        } else if self.this_week {
+            GroupBy::Week
+        } else if self.this_month {
+            GroupBy::Month
+        } else {
+            return (self.start, self.end);
+        };
+
+        let start = period.period(&Local::now().fixed_offset());
+        (
+            Some(date::start_of_day(start)),
+            Some(date::start_of_day(period.next(start))),
+        )
+    }
+}
+
+// Sessions are found by reading from a while before the time being counted
+// from, so that time on a project that was started before then is counted.
+// Anything started longer ago than this is missed.
+const LOOKBACK_DAYS: i64 = 7;
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+fn app(opt: &Opt) -> Result<()> {
+    let path = opt
+        .path
+        .clone()
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+    if Remote::parse(&path)?.is_some() || !matches!(Storage::parse(&path)?, Storage::File(_)) {
+        return Err(Error::InvalidArgument(format!(
+            "{} isn't on this machine, run hmms where it is",
+            path.display()
+        )));
+    }
+    let config = Config::load_or_default(opt.config.as_deref())?;
+
+    match opt.report {
+        Report::Time(ref time_opt) => time(&path, &config, time_opt),
+        Report::Mood(ref mood_opt) => mood(&path, mood_opt),
+        Report::Gaps(ref gaps_opt) => gaps(&path, gaps_opt),
+        Report::Year(ref year_opt) => year(&path, &config, year_opt),
+        Report::Series(ref series_opt) => series(&path, &config, series_opt),
+        Report::Review(ref review_opt) => review(&path, &config, review_opt),
+        Report::Digest(ref digest_opt) => digest(&path, &config, digest_opt),
+    }
+}
+
+fn open(path: &Path) -> Result<Entries<BufReader<File>>> {
+    let f = File::open(path).map_err(|source| Error::OpenFile {
+        path: path.to_owned(),
+        source,
+    })?;
+    Ok(Entries::new(BufReader::new(f)))
+}
+
+fn time(path: &Path, config: &Config, opt: &TimeOpt) -> Result<()> {
+    let mut entries = open(path)?;
+
+    let (start, end) = opt.range();
+    let from = start.map(|start| start - chrono::Duration::days(LOOKBACK_DAYS));
+    let mut tracker = Tracker::default();
+    for entry in entries.between(from.as_ref(), end.as_ref())? {
+        let entry = entry?;
+        match config.decrypt_command {
+            Some(ref command) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                tracker.push(&crypt::decrypt_entry(command, &entry)?)
+            }
+            _ => tracker.push(&entry),
+        }
+    }
+
+    // Projects that are still running are counted up to now.
+    let now = Local::now().fixed_offset();
+    let sessions = tracker.finish(end.map_or(now, |end| end.min(now)));
+    let totals = track::totals(&sessions, opt.by, start.as_ref(), end.as_ref());
+
+    let total = totals
+        .iter()
+        .fold(chrono::Duration::zero(), |sum, (_, d)| sum + *d);
+    let width = totals
+        .iter()
+        .map(|(key, _)| text::display_width(key))
+        .chain(std::iter::once("total".len()))
+        .max()
+        .unwrap_or_default();
+    for (key, duration) in &totals {
+        println!(
+            "{}  {}",
+            text::pad(key, width),
+            date::format_duration(*duration)
+        );
+    }
+    println!(
+        "{}  {}",
+        text::pad("total", width),
+        date::format_duration(total)
+    );
+
+    Ok(())
+}
+
+// Moods are stored as metadata, which isn't encrypted, so there's nothing to
+// decrypt here.
+fn mood(path: &Path, opt: &MoodOpt) -> Result<()> {
+    let mut chart = Chart::default();
+    for entry in open(path)?.between(opt.start.as_ref(), opt.end.as_ref())? {
+        chart.push(&entry?);
+    }
+
+    println!("week of     MTWTFSS  average");
+    for week in chart.finish() {
+        let average = week
+            .average()
+            .map_or_else(|| "-".to_owned(), |average| format!("{:.1}", average));
+        println!(
+            "{}  {}  {}",
+            week.start(),
+            mood::sparkline(week.days(), opt.ascii),
+            average
+        );
+    }
+    Ok(())
+}
+
+fn gaps(path: &Path, opt: &GapsOpt) -> Result<()> {
+    let mut gaps = Gaps::new(opt.min);
+    for entry in open(path)?.between(opt.start.as_ref(), opt.end.as_ref())? {
+        gaps.push(&entry?);
+    }
+
+    let now = Local::now().fixed_offset();
+    let end = opt.end.map_or(now, |end| end.min(now));
+    let time = |datetime: &DateTime<FixedOffset>| {
+        datetime
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    };
+    for gap in gaps.finish(end) {
+        let until = if gap.end == now {
+            "now".to_owned()
+        } else {
+            time(&gap.end)
+        };
+        println!(
+            "{} to {}  {}",
+            time(&gap.start),
+            until,
+            date::format_duration(gap.duration())
+        );
+    }
+    Ok(())
+}
+
+/// Reads the Handlebars template at `path`, or uses `default` if there isn't
+/// one.
+fn load_template(path: Option<&PathBuf>, default: &str) -> Result<String> {
+    match path.map(|path| expand_tilde(path)) {
+        Some(path) => {
+            std::fs::read_to_string(&path).map_err(|source| Error::OpenFile { path, source })
+        }
+        None => Ok(default.to_owned()),
+    }
+}
+
+fn year(path: &Path, config: &Config, opt: &YearOpt) -> Result<()> {
+    let year = opt.year.unwrap_or_else(|| Local::now().year());
+    let template = load_template(
+        opt.template.as_ref().or(config.year_template.as_ref()),
+        year::DEFAULT_TEMPLATE,
+    )?;
+
+    let start = date::start_of_day(NaiveDate::from_ymd_opt(year, 1, 1).unwrap());
+    let end = date::start_of_day(NaiveDate::from_ymd_opt(year + 1, 1, 1).unwrap());
+    let mut entries = Vec::new();
+    for entry in open(path)?.between(Some(&start), Some(&end))? {
+        let entry = entry?;
+        match config.decrypt_command {
+            Some(ref command) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                entries.push(crypt::decrypt_entry(command, &entry)?)
+            }
+            _ => entries.push(entry),
+        }
+    }
+
+    let report = Year::new(year, &entries, opt.top);
+    let color = std::io::stdout().is_terminal();
+    print!("{}", format::render(&template, &report, color)?);
+    Ok(())
+}
+
+fn series(path: &Path, config: &Config, opt: &SeriesOpt) -> Result<()> {
+    let filter = Filter::All(opt.filters.clone());
+    let matches = |entry: &Entry| {
+        opt.contains.as_deref().map_or(true, |s| entry.contains(s)) && filter.matches(entry)
+    };
+
+    let mut series = Series::new(opt.bucket, opt.value, opt.start);
+    for entry in open(path)?.between(opt.start.as_ref(), opt.end.as_ref())? {
+        let entry = entry?;
+        let entry = match config.decrypt_command {
+            Some(ref command) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                crypt::decrypt_entry(command, &entry)?
+            }
+            _ => entry,
+        };
+        if matches(&entry) {
+            series.push(&entry);
+        }
+    }
+
+    series::write(
+        std::io::stdout().lock(),
+        &series.finish(opt.end),
+        opt.value,
+        opt.format,
+    )
+}
+
+fn review(path: &Path, config: &Config, opt: &ReviewOpt) -> Result<()> {
+    // --week and --month conflict, and reviews are of a week by default.
+    let by = match (opt.week, opt.month) {
+        (false, true) => GroupBy::Month,
+        _ => GroupBy::Week,
+    };
+    let mut start = by.period(&opt.date.unwrap_or_else(|| Local::now().fixed_offset()));
+    if opt.last {
+        start = by.period(&(date::start_of_day(start) - chrono::Duration::days(1)));
+    }
+    let end = by.next(start);
+    let title = match by {
+        GroupBy::Month => start.format("%B %Y").to_string(),
+        _ => start.format("Week of %Y-%m-%d").to_string(),
+    };
+    let template = load_template(
+        opt.template.as_ref().or(config.review_template.as_ref()),
+        review::DEFAULT_TEMPLATE,
+    )?;
+
+    let (from, to) = (date::start_of_day(start), date::start_of_day(end));
+    let mut entries = Vec::new();
+    for entry in open(path)?.between(Some(&from), Some(&to))? {
+        let entry = entry?;
+        match config.decrypt_command {
+            Some(ref command) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                entries.push(crypt::decrypt_entry(command, &entry)?)
+            }
+            _ => entries.push(entry),
+        }
+    }
+
+    let review = Review::new(&title, start, end, &entries, opt.top);
+    let color = std::io::stdout().is_terminal();
+    print!("{}", format::render(&template, &review, color)?);
+    Ok(())
+}
+
+fn digest(path: &Path, config: &Config, opt: &DigestOpt) -> Result<()> {
+    let template = load_template(
+        opt.template.as_ref().or(config.digest_template.as_ref()),
+        digest::DEFAULT_TEMPLATE,
+    )?;
+
+    let today = opt
+        .date
+        .unwrap_or_else(|| Local::now().fixed_offset())
+        .with_timezone(&Local)
+        .date_naive();
+    let day = today.pred_opt().unwrap_or(today);
+    let decrypt_command = config.decrypt_command.as_deref();
+    let mut digest = digest::build(&mut open(path)?, day, decrypt_command)?;
+
+    let pins = pins::load_default()?;
+    let mut pinned = Vec::new();
+    if !pins.ids().is_empty() {
+        for entry in pins.select(open(path)?.collect::<Result<Vec<_>>>()?) {
+            match decrypt_command {
+                Some(command) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                    pinned.push(crypt::decrypt_entry(command, &entry)?)
+                }
+                _ => pinned.push(entry),
+            }
+        }
+    }
+    digest.set_pinned(&pinned);
+
+    match opt.email {
+        Some(ref to) => {
+            if digest.is_empty() {
+                return Ok(());
+            }
+            let body = format::render(&template, &digest, false)?;
+            let subject = day.format("hmm digest for %A %-d %B %Y").to_string();
+            let command = config.sendmail_command.as_deref().unwrap_or("sendmail -t");
+            digest::send(command, &digest::email(to, &subject, &body))
+        }
+        None => {
+            let color = std::io::stdout().is_terminal();
+            print!("{}", format::render(&template, &digest, color)?);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::{assert::Assert, prelude::*};
+    use escargot::{CargoBuild, CargoRun};
+    use hmmcli::entries::Entries;
+    use hmmcli::entry::Entry;
+    use lazy_static::lazy_static;
+    use std::fs::File;
+    use std::io::{BufReader, Write};
+    use std::path::{Path, PathBuf};
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMS: CargoRun = CargoBuild::new()
+            .bin("hmms")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    fn new_tempfile(content: &str) -> PathBuf {
+        let mut f = NamedTempFile::new().unwrap();
+        f.write_all(content.as_bytes()).unwrap();
+        f.keep().unwrap().1
+    }
+
+    fn run_with_path(path: &Path, args: Vec<&str>) -> Assert {
+        HMMS.command()
+            .arg("--path")
+            .arg(path.as_os_str())
+            .args(args)
+            .assert()
+    }
+
+    const TESTDATA: &str = "2024-05-12T22:00:00+00:00,\"\"\"#start #oncall\"\"\"
+2024-05-13T02:00:00+00:00,\"\"\"#stop quiet night\"\"\"
+2024-05-13T09:00:00+00:00,\"\"\"#start #work\"\"\"
+2024-05-13T10:15:00+00:00,\"\"\"standup went long\"\"\"
+2024-05-13T12:00:00+00:00,\"\"\"#start #garden\"\"\"
+2024-05-13T12:45:00+00:00,\"\"\"#start #work\"\"\"
+2024-05-13T17:30:00+00:00,\"\"\"#stop\"\"\"
+";
+
+    #[test_case(vec!["time"] => "garden  45m\noncall  4h\nwork    7h45m\ntotal   12h30m\n" ; "by tag")]
+    #[test_case(vec!["time", "--by", "day"] => "2024-05-12  2h\n2024-05-13  10h30m\ntotal       12h30m\n" ; "by day")]
+    #[test_case(vec!["time", "--start", "2024-05-13"] => "garden  45m\noncall  2h\nwork    7h45m\ntotal   10h30m\n" ; "start")]
+    #[test_case(vec!["time", "--start", "2024-05-13T12:00", "--end", "2024-05-13T13"] => "garden  45m\nwork    15m\ntotal   1h\n" ; "range")]
+    #[test_case(vec!["time", "--start", "2025"] => "total  0s\n" ; "nothing tracked")]
+    fn test_hmms_time(args: Vec<&str>) -> String {
+        let path = new_tempfile(TESTDATA);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    const MOODS: &str = "2024-05-07T09:00:00+00:00,\"\"\"meh\"\"\",\"{\"\"mood\"\":2}\"
+2024-05-07T18:00:00+00:00,\"\"\"better\"\"\",\"{\"\"mood\"\":3}\"
+2024-05-08T09:00:00+00:00,\"\"\"no mood\"\"\"
+2024-05-12T20:00:00+00:00,\"\"\"great\"\"\",\"{\"\"mood\"\":5}\"
+2024-05-22T09:00:00+00:00,\"\"\"good\"\"\",\"{\"\"mood\"\":4}\"
+";
+
+    #[test_case(vec!["mood"] => "week of     MTWTFSS  average\n2024-05-06   ▄    █  3.3\n2024-05-13           -\n2024-05-20    ▆      4.0\n" ; "chart")]
+    #[test_case(vec!["mood", "--ascii"] => "week of     MTWTFSS  average\n2024-05-06   3    5  3.3\n2024-05-13           -\n2024-05-20    4      4.0\n" ; "ascii")]
+    #[test_case(vec!["mood", "--start", "2024-05-20"] => "week of     MTWTFSS  average\n2024-05-20    ▆      4.0\n" ; "start")]
+    #[test_case(vec!["mood", "--start", "2025"] => "week of     MTWTFSS  average\n" ; "no moods")]
+    fn test_hmms_mood(args: Vec<&str>) -> String {
+        let path = new_tempfile(MOODS);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test_case(vec!["gaps", "--min", "5d", "--end", "2024-05-25"] => "2024-05-12 20:00 to 2024-05-22 09:00  9d13h\n" ; "gaps")]
+    #[test_case(vec!["gaps", "--end", "2024-05-25"] => "2024-05-08 09:00 to 2024-05-12 20:00  4d11h\n2024-05-12 20:00 to 2024-05-22 09:00  9d13h\n2024-05-22 09:00 to 2024-05-25 00:00  2d15h\n" ; "default minimum")]
+    #[test_case(vec!["gaps", "--min", "4d", "--start", "2024-05-13", "--end", "2024-06"] => "2024-05-22 09:00 to 2024-06-01 00:00  9d15h\n" ; "range")]
+    #[test_case(vec!["gaps", "--min", "30d", "--end", "2024-05-25"] => "" ; "none")]
+    fn test_hmms_gaps(args: Vec<&str>) -> String {
+        let path = new_tempfile(MOODS);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmms_gaps_until_now() {
+        let assert = run_with_path(&new_tempfile(MOODS), vec!["gaps", "--min", "30d"]).success();
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert!(out.starts_with("2024-05-22 09:00 to now  "), "{}", out);
+    }
+
+    #[test_case(vec!["year", "2024"] => "# 2024 in review
+
+5 entries and 6 words over 4 days.
+
+- Busiest day: Tuesday 7 May (2 entries)
+- Busiest week: the week of 6 May (4 entries)
+- Longest entry: no mood (2 words, 8 May)
+
+## First entry, Tuesday 7 May 09:00
+
+meh
+
+## Last entry, Wednesday 22 May 09:00
+
+good
+" ; "year")]
+    #[test_case(vec!["year", "2023"] => "# 2023 in review\n\nNothing was written.\n" ; "empty year")]
+    fn test_hmms_year(args: Vec<&str>) -> String {
+        let path = new_tempfile(MOODS);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmms_year_template() {
+        let template =
+            new_tempfile("{{ year }}: {{ count }} from {{ first.title }} to {{ last.title }}\n");
+        let assert = run_with_path(
+            &new_tempfile(MOODS),
+            vec!["year", "2024", "--template", template.to_str().unwrap()],
+        );
+        assert.success().stdout("2024: 5 from meh to good\n");
+    }
+
+    #[test_case(vec!["series", "--start", "2024-05-07", "--end", "2024-05-10"] => "start,count
+2024-05-07T00:00:00+00:00,2
+2024-05-08T00:00:00+00:00,1
+2024-05-09T00:00:00+00:00,0
+" ; "series")]
+    #[test_case(vec!["series", "--bucket", "12h", "--value", "words", "--end", "2024-05-08T12"] => "start,words
+2024-05-07T00:00:00+00:00,1
+2024-05-07T12:00:00+00:00,1
+2024-05-08T00:00:00+00:00,2
+" ; "words")]
+    #[test_case(vec!["series", "--bucket", "7d", "--start", "2024-05-06", "--end", "2024-05-27", "--where", "mood>=3", "--contains", "t"] => "start,count
+2024-05-06T00:00:00+00:00,2
+2024-05-13T00:00:00+00:00,0
+2024-05-20T00:00:00+00:00,0
+" ; "filtered")]
+    fn test_hmms_series(args: Vec<&str>) -> String {
+        let path = new_tempfile(MOODS);
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmms_series_json() {
+        let assert = run_with_path(
+            &new_tempfile(MOODS),
+            vec!["series", "--format", "json", "--bucket", "30d"],
+        )
+        .success();
+        let json: serde_json::Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{ "start": "2024-05-07T00:00:00+00:00", "count": 5 }])
+        );
+    }
+
+    const REVIEW: &str = "2024-05-12T23:00:00+00:00,\"\"\"last week\"\"\"
+2024-05-13T09:00:00+00:00,\"\"\"standup #work\\nTODO: book a room\"\"\"
+2024-05-14T12:00:00+00:00,\"\"\"lunch #friends #work\"\"\"
+2024-06-01T09:00:00+00:00,\"\"\"next month #garden\"\"\"
+";
+
+    #[test_case(vec!["--date", "2024-05-15"] => "\
+# Week of 2024-05-13
+
+2 entries over 2 days. Most written about: #work (2), #friends (1).
+
+## Monday 13 May
+
+- 09:00 standup #work
+
+## Tuesday 14 May
+
+- 12:00 lunch #friends #work
+
+## Open TODOs
+
+- [ ] book a room (Mon 09:00)
+" ; "week")]
+    #[test_case(vec!["--month", "--date", "2024-05-15", "--template", "TEMPLATE"] => "May 2024: 3 entries, #work #friends\n" ; "month with template")]
+    #[test_case(vec!["--week", "--date", "2024-05-15", "--top", "1", "--template", "TEMPLATE"] => "Week of 2024-05-13: 2 entries, #work\n" ; "top")]
+    #[test_case(vec!["--date", "2025", "--template", "TEMPLATE"] => "Week of 2024-12-30: 0 entries,\n" ; "nothing written")]
+    fn test_hmms_review(args: Vec<&str>) -> String {
+        let path = new_tempfile(REVIEW);
+        let template =
+            new_tempfile("{{ title }}: {{ count }} entries,{{#each tags}} #{{ tag }}{{/each}}\n");
+        let args: Vec<&str> = std::iter::once("review")
+            .chain(args.into_iter().map(|arg| match arg {
+                "TEMPLATE" => template.to_str().unwrap(),
+                arg => arg,
+            }))
+            .collect();
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test_case(vec!["review", "--week", "--month"] => 1 ; "week and month")]
+    #[test_case(vec!["review", "--last", "--date", "2024"] => 1 ; "last and date")]
+    #[test_case(vec!["review", "--template", "/this/path/does/not/exist"] => 66 ; "missing template")]
+    fn test_hmms_review_errors(args: Vec<&str>) -> i32 {
+        let assert = run_with_path(&new_tempfile(REVIEW), args);
+        assert.get_output().status.code().unwrap()
+    }
+
+    const DIGEST: &str = "2023-05-13T09:00:00+00:00,\"\"\"last year\"\"\"
+2024-05-13T09:00:00+00:00,\"\"\"yesterday\"\"\"
+2024-05-14T09:00:00+00:00,\"\"\"today\"\"\"
+";
+
+    #[test_case(vec!["--daily", "--date", "2024-05-14"] => "# Monday 13 May 2024\n\n- 09:00 yesterday\n\n## On this day in 2023\n\n- 09:00 last year\n" ; "daily")]
+    #[test_case(vec!["--date", "2024-05-14T23:00", "--template", "TEMPLATE"] => "yesterday (last year)\n" ; "template")]
+    #[test_case(vec!["--date", "2024-06-01"] => "# Friday 31 May 2024\n\nNothing was written.\n" ; "nothing")]
+    fn test_hmms_digest(args: Vec<&str>) -> String {
+        let path = new_tempfile(DIGEST);
+        let template = new_tempfile(
+            "{{#each entries}}{{ message }}{{/each}}{{#each flashbacks}} ({{#each entries}}{{ message }}{{/each}}){{/each}}\n",
+        );
+        let args: Vec<&str> = std::iter::once("digest")
+            .chain(args.into_iter().map(|arg| match arg {
+                "TEMPLATE" => template.to_str().unwrap(),
+                arg => arg,
+            }))
+            .collect();
+        let assert = run_with_path(&path, args);
+        let out = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+        assert.success();
+        out
+    }
+
+    #[test]
+    fn test_hmms_digest_pinned() {
+        let config = tempfile::TempDir::new().unwrap();
+        let path = new_tempfile(DIGEST);
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(&path).unwrap()))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        std::fs::create_dir(config.path().join("hmm")).unwrap();
+        std::fs::write(
+            config.path().join("hmm").join("pins"),
+            format!("{}\nnot-an-entry\n{}\n", entries[2].id(), entries[0].id()),
+        )
+        .unwrap();
+
+        HMMS.command()
+            .env("XDG_CONFIG_HOME", config.path())
+            .arg("--path")
+            .arg(&path)
+            .args(["digest", "--date", "2024-07-01"])
+            .assert()
+            .success()
+            .stdout("# Sunday 30 June 2024\n\nNothing was written.\n\n## Pinned\n\n- today\n- last year\n");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_hmms_digest_email() {
+        let path = new_tempfile(DIGEST);
+        let mail = new_tempfile("");
+        let config = new_tempfile(&format!(
+            "sendmail_command = \"cat >> {}\"\n",
+            mail.display()
+        ));
+        let run = |date: &str| {
+            HMMS.command()
+                .arg("--path")
+                .arg(&path)
+                .arg("--config")
+                .arg(&config)
+                .args(["digest", "--email", "me@example.com", "--date", date])
+                .assert()
+        };
+
+        run("2024-05-14").success();
+        // Nothing's sent when there's nothing in the digest.
+        run("2024-06-01").success();
+
+        let mail = std::fs::read_to_string(&mail).unwrap();
+        assert!(
+            mail.starts_with("To: me@example.com\nSubject: hmm digest for Monday 13 May 2024\n"),
+            "{}",
+            mail
+        );
+        assert!(mail.ends_with("\n\n# Monday 13 May 2024\n\n- 09:00 yesterday\n\n## On this day in 2023\n\n- 09:00 last year\n"), "{}", mail);
+        assert_eq!(mail.matches("To:").count(), 1);
+    }
+
+    #[test]
+    fn test_hmms_digest_email_fails() {
+        let config = new_tempfile("sendmail_command = \"exit 3\"\n");
+        HMMS.command()
+            .arg("--path")
+            .arg(new_tempfile(DIGEST))
+            .arg("--config")
+            .arg(&config)
+            .args([
+                "digest",
+                "--email",
+                "me@example.com",
+                "--date",
+                "2024-05-14",
+            ])
+            .assert()
+            .code(69);
+    }
+
+    #[test_case(vec!["series", "--bucket", "0d"] ; "empty bucket")]
+    #[test_case(vec!["series", "--value", "chars"] ; "unknown value")]
+    #[test_case(vec!["series", "--where", "mood"] ; "bad filter")]
+    #[test_case(vec!["year", "24x"] ; "bad year")]
+    #[test_case(vec!["year", "0"] ; "year too early")]
+    #[test_case(vec!["gaps", "--min", "soon"] ; "bad minimum")]
+    #[test_case(vec!["time", "--today", "--this-week"] ; "two periods")]
+    #[test_case(vec!["time", "--today", "--start", "2024"] ; "period and start")]
+    #[test_case(vec!["time", "--by", "week"] ; "unknown grouping")]
+    #[test_case(vec![] ; "no report")]
+    fn test_hmms_errors(args: Vec<&str>) {
+        run_with_path(&new_tempfile(TESTDATA), args).failure();
+    }
+
+    #[test]
+    fn test_hmms_missing_file() {
+        run_with_path(Path::new("/this/path/does/not/exist"), vec!["time"]).code(66);
+    }
+}
@@ -0,0 +1,613 @@
+use hmmcli::{
+    config::Config,
+    crypt, date,
+    entries::Entries,
+    entry::Entry,
+    error::{Error, ErrorFormat},
+    hook,
+    journal::{self, AppendOptions, SkewPolicy},
+    lock, Result,
+};
+use human_panic::setup_panic;
+use serde::Deserialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::PathBuf;
+use std::process::exit;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "hmmserve", about = "Serve your hmm file over a small HTTP API")]
+struct Opt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Path to your hmm config file, defaults to ~/.config/hmm/config.toml on
+    /// *nix systems, %APPDATA%\hmm\config.toml on Windows.
+    #[structopt(long = "config", env = "HMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// The address to listen on. Use port 0 to pick any free port.
+    #[structopt(long = "listen", env = "HMM_LISTEN", default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// The token clients have to send in an "Authorization: Bearer <token>"
+    /// header. Prefer setting HMM_TOKEN, as anything on the command line can
+    /// be seen by other users on the same machine.
+    #[structopt(long = "token", env = "HMM_TOKEN", hide_env_values = true)]
+    token: String,
+
+    /// How many seconds to wait for another hmm process to finish writing to
+    /// your hmm file before giving up on a request.
+    #[structopt(
+        long = "lock-timeout",
+        env = "HMM_LOCK_TIMEOUT",
+        default_value = "10",
+        parse(try_from_str = lock::parse_timeout)
+    )]
+    lock_timeout: Duration,
+
+    /// What to do if the last entry in your hmm file is later than the current
+    /// time, as in hmm's --skew. Overrides the skew setting in your config
+    /// file, defaults to "error".
+    #[structopt(long = "skew", env = "HMM_SKEW", possible_values = &["error", "wait", "clamp"])]
+    skew: Option<SkewPolicy>,
+
+    /// Don't run the pre_append and post_append hooks or the webhook from your
+    /// config file.
+    #[structopt(long = "no-hooks")]
+    no_hooks: bool,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+// The largest request body we'll read. Entries are usually a sentence or two,
+// so this is generous, but stops a misbehaving client from eating all of our
+// memory.
+const MAX_BODY: u64 = 1024 * 1024;
+
+// The most entries a GET will return, and how many it returns if it isn't
+// given a limit, so that listing a big journal can't tie the server up.
+const MAX_LIMIT: usize = 1000;
+
+/// Everything needed to handle a request, worked out once at startup.
+struct State {
+    path: PathBuf,
+    token: String,
+    append_opts: AppendOptions,
+    pre_append: Option<String>,
+    post_append: Option<String>,
+    webhook: Option<String>,
+    encrypt_command: Option<String>,
+    decrypt_command: Option<String>,
+    errors: ErrorFormat,
+}
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    if let Err(e) = app(&opt) {
+        opt.errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+fn app(opt: &Opt) -> Result<()> {
+    if opt.token.is_empty() {
+        return Err(Error::InvalidArgument(
+            "--token must not be empty".to_owned(),
+        ));
+    }
+    let config = Config::load_or_default(opt.config.as_deref())?;
+    let (pre_append, post_append, webhook) = match opt.no_hooks {
+        true => (None, None, None),
+        false => (config.pre_append, config.post_append, config.webhook),
+    };
+    let state = State {
+        path: opt
+            .path
+            .clone()
+            .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm")),
+        token: opt.token.clone(),
+        append_opts: AppendOptions {
+            lock: true,
+            lock_timeout: opt.lock_timeout,
+            skew: opt.skew.or(config.skew).unwrap_or_default(),
+        },
+        pre_append,
+        post_append,
+        webhook,
+        encrypt_command: config.encrypt_command,
+        decrypt_command: config.decrypt_command,
+        errors: opt.errors,
+    };
+
+    let server = Server::http(&opt.listen).map_err(|e| {
+        Error::Io(io::Error::other(format!(
+            "couldn't listen on {}: {}",
+            opt.listen, e
+        )))
+    })?;
+    eprintln!("hmmserve: listening on http://{}", server.server_addr());
+
+    for mut request in server.incoming_requests() {
+        let response = handle(&state, &mut request);
+        if let Err(e) = request.respond(response) {
+            opt.errors.warn("<http>", &e.into());
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(state: &State, request: &mut Request) -> Response<io::Cursor<Vec<u8>>> {
+    if !authorized(state, request) {
+        return json_error(401, "unauthorized", "missing or incorrect token");
+    }
+
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    if path != "/entries" {
+        return json_error(404, "not_found", "the only endpoint is /entries");
+    }
+
+    let method = request.method().clone();
+    let result = match method {
+        Method::Get => list(state, query).map(|entries| (200, entries)),
+        Method::Post => create(state, request).map(|entry| (201, entry)),
+        _ => return json_error(405, "method_not_allowed", "use GET or POST"),
+    };
+
+    match result {
+        Ok((status, body)) => json(status, body),
+        Err(e) => json_error(status_code(&e), e.kind(), &e.to_string()),
+    }
+}
+
+// Compares every byte of the token, rather than stopping at the first one
+// that differs, so that how long a request takes doesn't give away how much
+// of the token it got right.
+fn authorized(state: &State, request: &Request) -> bool {
+    let expected = format!("Bearer {}", state.token);
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str())
+        .is_some_and(|given| {
+            given.len() == expected.len()
+                && given
+                    .bytes()
+                    .zip(expected.bytes())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0
+        })
+}
+
+/// The entries matching the `start`, `end` and `q` query parameters, which
+/// work the same way as hmmq's --start, --end and --contains, up to `limit` of
+/// them. Encrypted entries are decrypted before they're matched if there's a
+/// decrypt_command.
+fn list(state: &State, query: &str) -> Result<serde_json::Value> {
+    let mut start = None;
+    let mut end = None;
+    let mut contains = None;
+    let mut limit = MAX_LIMIT;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "start" => start = Some(date::parse(&value)?),
+            "end" => end = Some(date::parse(&value)?),
+            "q" => contains = Some(value.into_owned()),
+            "limit" => {
+                limit = value
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=MAX_LIMIT).contains(n))
+                    .ok_or_else(|| {
+                        Error::InvalidArgument(format!(
+                            "limit must be a number from 1 to {}",
+                            MAX_LIMIT
+                        ))
+                    })?
+            }
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown query parameter \"{}\"",
+                    key
+                )))
+            }
+        }
+    }
+
+    let f = File::open(&state.path).map_err(|source| Error::OpenFile {
+        path: state.path.clone(),
+        source,
+    })?;
+    let mut entries = Entries::new(BufReader::new(f));
+
+    let mut matches = Vec::new();
+    for result in entries.between(start.as_ref(), end.as_ref())? {
+        let entry = match (result?, &state.decrypt_command) {
+            (entry, Some(command)) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                crypt::decrypt_entry(command, &entry)?
+            }
+            (entry, _) => entry,
+        };
+        if contains.as_ref().map_or(true, |s| entry.contains(s)) {
+            matches.push(serde_json::to_value(&entry)?);
+            if matches.len() == limit {
+                break;
+            }
+        }
+    }
+
+    Ok(serde_json::Value::Array(matches))
+}
+
+#[derive(Deserialize)]
+struct NewEntry {
+    message: String,
+}
+
+/// Appends the entry in the request body, which is either a JSON object with a
+/// "message" field or, for any other content type, the message as plain text.
+/// Once the entry is written it's there to stay, so if the post_append hook
+/// fails, the entry is returned with a "warning" field saying so, the same as
+/// hmm warns but keeps the entry. The webhook is sent in the background, as
+/// with its retries it can take the best part of a minute to give up, and
+/// requests are handled one at a time. Like hmm, the message is encrypted if
+/// there's an encrypt_command, but hooks and the response see it in plain
+/// text.
+fn create(state: &State, request: &mut Request) -> Result<serde_json::Value> {
+    let is_json = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .is_some_and(|h| h.value.as_str().starts_with("application/json"));
+
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body)?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(Error::InvalidArgument(format!(
+            "entries can't be bigger than {} bytes",
+            MAX_BODY
+        )));
+    }
+
+    // Entries are written trimmed, so trimming here means the hooks see the
+    // message as it's written, and encrypted entries can go back in plain text
+    // with it.
+    let message = match is_json {
+        true => serde_json::from_str::<NewEntry>(&body)?.message,
+        false => body,
+    };
+    let message = message.trim().to_owned();
+    if message.is_empty() {
+        return Err(Error::InvalidArgument("message can't be empty".to_owned()));
+    }
+
+    if let Some(ref command) = state.pre_append {
+        hook::pre_append(command, &state.path, &message)?;
+    }
+
+    let f = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(&state.path)
+        .map_err(|source| Error::OpenFile {
+            path: state.path.clone(),
+            source,
+        })?;
+    let (stored, metadata) = crypt::encrypt_with(state.encrypt_command.as_deref(), &message)?;
+    let written = journal::append_with_metadata(&f, &stored, &metadata, &state.append_opts)?;
+    let entry = match state.encrypt_command {
+        Some(_) => Entry::new(*written.datetime(), message),
+        None => written,
+    };
+    let mut body = serde_json::to_value(&entry)?;
+
+    if let Some(ref command) = state.post_append {
+        if let Err(e) = hook::post_append(command, &state.path, &entry) {
+            body["warning"] = e.to_string().into();
+        }
+    }
+    if let Some(ref url) = state.webhook {
+        let (url, path, errors) = (url.clone(), state.path.clone(), state.errors);
+        thread::spawn(move || {
+            if let Err(e) = hook::webhook(&url, &path, &entry) {
+                errors.warn(&url, &e);
+            }
+        });
+    }
+
+    Ok(body)
+}
+
+/// The HTTP status to respond with for an error, following the same split as
+/// exit codes between problems with the request and problems on our end.
+fn status_code(e: &Error) -> u16 {
+    match e {
+        Error::InvalidArgument(_) | Error::InvalidDate(_) | Error::SerdeJson(_) => 400,
+        Error::HookRejected(_) => 422,
+        Error::ClockSkew | Error::LockTimeout(_) | Error::Replaced => 503,
+        _ => 500,
+    }
+}
+
+fn json(status: u16, body: serde_json::Value) -> Response<io::Cursor<Vec<u8>>> {
+    let content_type: Header = "Content-Type: application/json".parse().unwrap();
+    Response::from_data(body.to_string().into_bytes())
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn json_error(status: u16, kind: &str, message: &str) -> Response<io::Cursor<Vec<u8>>> {
+    json(
+        status,
+        serde_json::json!({ "error": kind, "message": message }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use escargot::{CargoBuild, CargoRun};
+    use lazy_static::lazy_static;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::path::{Path, PathBuf};
+    use std::process::{Child, Stdio};
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    const TOKEN: &str = "s3cret";
+    const AUTH: &str = "Authorization: Bearer s3cret";
+
+    const TESTDATA: &str = "2020-01-01T00:01:00+00:00,\"\"\"new year\"\"\"
+2020-02-12T23:08:40+00:00,\"\"\"valentine's plans\"\"\"
+2020-03-12T00:00:00+00:00,\"\"\"spring\"\"\"
+";
+
+    lazy_static! {
+        static ref HMMSERVE: CargoRun = CargoBuild::new()
+            .bin("hmmserve")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    // Kills the server when the test finishes, whether it passed or not.
+    struct Server {
+        child: Child,
+        addr: String,
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    fn start(path: &Path, args: Vec<&str>) -> Server {
+        let mut child = HMMSERVE
+            .command()
+            .arg("--path")
+            .arg(path)
+            .args(["--listen", "127.0.0.1:0"])
+            .args(args)
+            .env("HMM_TOKEN", TOKEN)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut line = String::new();
+        BufReader::new(child.stderr.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("hmmserve: listening on http://")
+            .unwrap_or_else(|| panic!("unexpected stderr: {}", line))
+            .to_owned();
+        Server { child, addr }
+    }
+
+    fn new_tempfile(content: &str) -> PathBuf {
+        let mut f = NamedTempFile::new().unwrap();
+        f.write_all(content.as_bytes()).unwrap();
+        f.keep().unwrap().1
+    }
+
+    // A bare bones HTTP client, returning the status and the body.
+    fn request(server: &Server, request: &str, headers: &[&str], body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(&server.addr).unwrap();
+        let mut headers: String = headers.iter().map(|h| format!("{}\r\n", h)).collect();
+        headers.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        write!(
+            stream,
+            "{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n{}",
+            request, headers, body
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_owned();
+        (status, body)
+    }
+
+    fn messages(body: &str) -> Vec<String> {
+        let json: serde_json::Value = serde_json::from_str(body).unwrap();
+        json.as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["message"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test_case("GET /entries"                              => vec!["new year", "valentine's plans", "spring"] ; "all")]
+    #[test_case("GET /entries?start=2020-02"                => vec!["valentine's plans", "spring"]             ; "start")]
+    #[test_case("GET /entries?start=2020-01&end=2020-03"    => vec!["new year", "valentine's plans"]           ; "start and end")]
+    #[test_case("GET /entries?q=valentine%27s"              => vec!["valentine's plans"]                       ; "q")]
+    #[test_case("GET /entries?q=nope"                       => Vec::<String>::new()                            ; "no matches")]
+    #[test_case("GET /entries?limit=2"                      => vec!["new year", "valentine's plans"]           ; "limit")]
+    #[test_case("GET /entries?start=2020-02&limit=1"        => vec!["valentine's plans"]                       ; "start and limit")]
+    fn test_hmmserve_get(req: &str) -> Vec<String> {
+        let server = start(&new_tempfile(TESTDATA), vec![]);
+        let (status, body) = request(&server, req, &[AUTH], "");
+        assert_eq!(status, 200, "{}", body);
+        messages(&body)
+    }
+
+    #[test]
+    fn test_hmmserve_post() {
+        let path = new_tempfile(TESTDATA);
+        let server = start(&path, vec![]);
+
+        let (status, body) = request(&server, "POST /entries", &[AUTH], "  from my phone\n");
+        assert_eq!(status, 201, "{}", body);
+        let entry: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(entry["message"], "from my phone");
+
+        let json = "{\"message\":\"with\\nnewlines\"}";
+        let (status, body) = request(
+            &server,
+            "POST /entries",
+            &[AUTH, "Content-Type: application/json"],
+            json,
+        );
+        assert_eq!(status, 201, "{}", body);
+
+        let (_, body) = request(&server, "GET /entries?start=2021", &[AUTH], "");
+        assert_eq!(messages(&body), vec!["from my phone", "with\nnewlines"]);
+    }
+
+    #[test_case("GET /entries",            vec![],                                 ""   => 401 ; "no token")]
+    #[test_case("GET /entries",            vec!["Authorization: Bearer s3creT"],   ""   => 401 ; "wrong token")]
+    #[test_case("POST /entries",           vec!["Authorization: Bearer nope"],     "hi" => 401 ; "wrong token on post")]
+    #[test_case("GET /entries",            vec!["Authorization: s3cret"],          ""   => 401 ; "not a bearer token")]
+    #[test_case("GET /nope",               vec![AUTH],                             ""   => 404 ; "unknown path")]
+    #[test_case("DELETE /entries",         vec![AUTH],                             ""   => 405 ; "unknown method")]
+    #[test_case("GET /entries?start=nope", vec![AUTH],                             ""   => 400 ; "bad date")]
+    #[test_case("GET /entries?sort=desc",  vec![AUTH],                             ""   => 400 ; "unknown parameter")]
+    #[test_case("GET /entries?limit=0",    vec![AUTH],                             ""   => 400 ; "zero limit")]
+    #[test_case("GET /entries?limit=1001", vec![AUTH],                             ""   => 400 ; "limit too big")]
+    #[test_case("POST /entries",           vec![AUTH],                             "\n" => 400 ; "empty message")]
+    #[test_case("POST /entries",           vec![AUTH, "Content-Type: application/json"], "{}" => 400 ; "json without message")]
+    fn test_hmmserve_errors(req: &str, headers: Vec<&str>, body: &str) -> u16 {
+        let path = new_tempfile(TESTDATA);
+        let server = start(&path, vec![]);
+        let (status, _) = request(&server, req, &headers, body);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), TESTDATA);
+        status
+    }
+
+    #[test]
+    fn test_hmmserve_pre_append_hook() {
+        let path = new_tempfile(TESTDATA);
+        let config = new_tempfile("pre_append = \"false\"");
+        let server = start(&path, vec!["--config", config.to_str().unwrap()]);
+
+        let (status, body) = request(&server, "POST /entries", &[AUTH], "hi");
+        assert_eq!(status, 422, "{}", body);
+        assert!(body.contains("\"error\":\"hook_rejected\""), "{}", body);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), TESTDATA);
+    }
+
+    #[test]
+    fn test_hmmserve_post_append_hook_fails() {
+        let path = new_tempfile(TESTDATA);
+        let config = new_tempfile("post_append = \"false\"");
+        let server = start(&path, vec!["--config", config.to_str().unwrap()]);
+
+        let (status, body) = request(&server, "POST /entries", &[AUTH], "hi");
+        assert_eq!(status, 201, "{}", body);
+        let entry: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(entry["message"], "hi");
+        assert!(
+            entry["warning"].as_str().unwrap().contains("`false`"),
+            "{}",
+            body
+        );
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("\"\"\"hi\"\"\"\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hmmserve_encryption() {
+        let path = new_tempfile(TESTDATA);
+        let config = new_tempfile(
+            "encrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"\ndecrypt_command = \"tr 'A-Za-z' 'N-ZA-Mn-za-m'\"",
+        );
+        let server = start(&path, vec!["--config", config.to_str().unwrap()]);
+
+        let (status, body) = request(&server, "POST /entries", &[AUTH], "hello");
+        assert_eq!(status, 201, "{}", body);
+        let entry: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(entry["message"], "hello");
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("\"\"\"hmm-encrypted:dXJ5eWI=\"\"\",\"{\"\"encrypted\"\":true}\"\n"));
+
+        let (status, body) = request(&server, "GET /entries?q=hello", &[AUTH], "");
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(messages(&body), vec!["hello"]);
+    }
+
+    #[test]
+    fn test_hmmserve_webhook_in_background() {
+        let hook = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", hook.server_addr().to_ip().unwrap());
+        let (respond, responded) = std::sync::mpsc::channel::<()>();
+        let received = std::thread::spawn(move || {
+            let mut req = hook.recv().unwrap();
+            let mut body = String::new();
+            req.as_reader().read_to_string(&mut body).unwrap();
+            // Hold on to the webhook until the entry has been responded to,
+            // which would take curl timing out if the webhook held up the
+            // response.
+            responded.recv().unwrap();
+            req.respond(tiny_http::Response::empty(200)).unwrap();
+            body
+        });
+
+        let path = new_tempfile(TESTDATA);
+        let config = new_tempfile(&format!("webhook = \"{}\"", url));
+        let server = start(&path, vec!["--config", config.to_str().unwrap()]);
+        let started = std::time::Instant::now();
+        let (status, body) = request(&server, "POST /entries", &[AUTH], "hi");
+        assert_eq!(status, 201, "{}", body);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        respond.send(()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&received.join().unwrap()).unwrap();
+        assert_eq!(json["text"], "hi");
+    }
+
+    #[test]
+    fn test_hmmserve_requires_token() {
+        let out = HMMSERVE
+            .command()
+            .env_remove("HMM_TOKEN")
+            .args(["--listen", "127.0.0.1:0", "--token", ""])
+            .output()
+            .unwrap();
+        assert_eq!(out.status.code(), Some(64));
+    }
+}
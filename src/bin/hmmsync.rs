@@ -0,0 +1,249 @@
+use hmmcli::{
+    entries::Entries,
+    error::{Error, ErrorFormat},
+    journal::{self, AppendOptions},
+    lock,
+    remote::Remote,
+    storage::Storage,
+    Result,
+};
+use human_panic::setup_panic;
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "hmmsync",
+    about = "Merge other copies of your hmm file in to it",
+    group = structopt::clap::ArgGroup::with_name("copies").multiple(true).required(true)
+)]
+struct Opt {
+    /// Path to your hmm file, defaults to your default configuration directory,
+    /// ~/.config/.hmm on *nix systems, %APPDATA%\.hmm on Windows.
+    #[structopt(long = "path", env = "HMM_PATH")]
+    path: Option<PathBuf>,
+
+    /// Copies of your hmm file to merge in to it. Entries that are in a copy
+    /// but not in your hmm file are added to it, everything else is left as it
+    /// is, so merging the same copy twice is harmless.
+    #[structopt(long = "merge", group = "copies")]
+    merge: Vec<PathBuf>,
+
+    /// Merge in any conflicted copies of your hmm file that Dropbox, Nextcloud
+    /// or Syncthing have left next to it.
+    #[structopt(long = "conflicts", group = "copies")]
+    conflicts: bool,
+
+    /// Delete each copy once it has been merged.
+    #[structopt(long = "delete")]
+    delete: bool,
+
+    /// How many seconds to wait for another hmm process to finish writing to
+    /// your hmm file before giving up.
+    #[structopt(
+        long = "lock-timeout",
+        env = "HMM_LOCK_TIMEOUT",
+        default_value = "10",
+        parse(try_from_str = lock::parse_timeout)
+    )]
+    lock_timeout: Duration,
+
+    /// Don't lock your hmm file while writing to it.
+    #[structopt(long = "no-lock")]
+    no_lock: bool,
+
+    /// How to print errors. Use "json" for machine-readable output on stderr.
+    #[structopt(long = "errors", env = "HMM_ERRORS", default_value = "text", possible_values = &["text", "json"])]
+    errors: ErrorFormat,
+}
+
+fn main() {
+    setup_panic!();
+
+    let opt = Opt::from_args();
+    let errors = opt.errors;
+    if let Err(e) = app(opt) {
+        errors.print(&e);
+        exit(e.exit_code());
+    }
+}
+
+/// Merges copies of a journal, like the ones file syncing tools make when it's
+/// changed on two machines before they've caught up with each other, back in
+/// to it. Only journals on this machine can be merged.
+fn app(opt: Opt) -> Result<()> {
+    let path = opt
+        .path
+        .unwrap_or_else(|| dirs::home_dir().unwrap().join(".hmm"));
+    if Remote::parse(&path)?.is_some() || !matches!(Storage::parse(&path)?, Storage::File(_)) {
+        return Err(Error::InvalidArgument(format!(
+            "{} isn't on this machine, run hmmsync where it is",
+            path.display()
+        )));
+    }
+
+    let append_opts = AppendOptions {
+        lock: !opt.no_lock,
+        lock_timeout: opt.lock_timeout,
+        ..AppendOptions::default()
+    };
+
+    let mut copies = opt.merge;
+    if opt.conflicts {
+        copies.extend(journal::conflicted_copies(&path)?);
+    }
+
+    for copy in copies {
+        let entries = File::open(&copy)
+            .map_err(|source| Error::OpenFile {
+                path: copy.clone(),
+                source,
+            })
+            .and_then(|cf| Entries::new(BufReader::new(cf)).collect::<Result<Vec<_>>>())?;
+
+        // Merging can replace the journal with a new copy, so it's opened
+        // again for each one.
+        let f = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|source| Error::OpenFile {
+                path: path.clone(),
+                source,
+            })?;
+        let n = journal::merge(&f, &path, entries, &append_opts)?;
+        eprintln!(
+            "merged {} {} from {} in to {}",
+            n,
+            if n == 1 { "entry" } else { "entries" },
+            copy.display(),
+            path.display()
+        );
+
+        if opt.delete {
+            std::fs::remove_file(&copy)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_cmd::prelude::*;
+    use escargot::{CargoBuild, CargoRun};
+    use hmmcli::entries::Entries;
+    use lazy_static::lazy_static;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::{Path, PathBuf};
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    lazy_static! {
+        static ref HMMSYNC: CargoRun = CargoBuild::new()
+            .bin("hmmsync")
+            .current_release()
+            .current_target()
+            .run()
+            .unwrap();
+    }
+
+    fn new_tempfile_path() -> PathBuf {
+        NamedTempFile::new().unwrap().keep().unwrap().1
+    }
+
+    fn read_messages(path: &Path) -> Vec<String> {
+        Entries::new(BufReader::new(File::open(path).unwrap()))
+            .map(|e| e.unwrap().message().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_hmmsync_merge() {
+        let path = new_tempfile_path();
+        std::fs::write(
+            &path,
+            "2020-01-01T00:00:00+00:00,\"\"\"a\"\"\"\n2020-01-03T00:00:00+00:00,\"\"\"c\"\"\"\n",
+        )
+        .unwrap();
+        let copy = new_tempfile_path();
+        std::fs::write(
+            &copy,
+            "2020-01-01T00:00:00+00:00,\"\"\"a\"\"\"\n2020-01-02T00:00:00+00:00,\"\"\"b\"\"\"\n",
+        )
+        .unwrap();
+
+        let assert = HMMSYNC
+            .command()
+            .arg("--path")
+            .arg(&path)
+            .arg("--merge")
+            .arg(&copy)
+            .assert()
+            .success();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.starts_with("merged 1 entry from "), "{}", stderr);
+        assert_eq!(read_messages(&path), vec!["a", "b", "c"]);
+
+        let assert = HMMSYNC
+            .command()
+            .args(["--delete", "--path"])
+            .arg(&path)
+            .arg("--merge")
+            .arg(&copy)
+            .assert()
+            .success();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.starts_with("merged 0 entries from "), "{}", stderr);
+        assert_eq!(read_messages(&path), vec!["a", "b", "c"]);
+        assert!(!copy.exists());
+    }
+
+    #[test]
+    fn test_hmmsync_conflicts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("journal.hmm");
+        std::fs::write(&path, "2020-01-01T00:00:00+00:00,\"\"\"a\"\"\"\n").unwrap();
+        std::fs::write(
+            dir.path()
+                .join("journal (laptop's conflicted copy 2020-01-02).hmm"),
+            "2020-01-02T00:00:00+00:00,\"\"\"b\"\"\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path()
+                .join("journal.sync-conflict-20200103-000000-ABCDEFG.hmm"),
+            "2020-01-03T00:00:00+00:00,\"\"\"c\"\"\"\n",
+        )
+        .unwrap();
+
+        HMMSYNC
+            .command()
+            .args(["--conflicts", "--delete", "--path"])
+            .arg(&path)
+            .assert()
+            .success();
+        assert_eq!(read_messages(&path), vec!["a", "b", "c"]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test_case(vec![]                                                => 1  ; "nothing to merge")]
+    #[test_case(vec!["--merge", "/this/path/does/not/exist"]          => 66 ; "missing copy")]
+    #[test_case(vec!["--path", "ssh://box/~/.hmm", "--conflicts"]     => 64 ; "remote journal")]
+    fn test_hmmsync_errors(args: Vec<&str>) -> i32 {
+        HMMSYNC
+            .command()
+            .env("HMM_PATH", new_tempfile_path())
+            .args(args)
+            .assert()
+            .get_output()
+            .status
+            .code()
+            .unwrap()
+    }
+}
@@ -0,0 +1,265 @@
+//! Publishing a journal to a calendar on a CalDAV server, as an all-day event
+//! for each day with entries, for hmmq --caldav.
+
+use super::{curl, entry::Entry, error::Error, hook, ics, Result};
+use std::io::Write;
+use std::process::Stdio;
+use tempfile::NamedTempFile;
+
+/// A calendar on a CalDAV server. Events are put in it with curl, which needs
+/// to be installed. Credentials are a username and a password given with
+/// `with_user`, or otherwise read from ~/.netrc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalDav {
+    url: String,
+    user: Option<(String, String)>,
+}
+
+impl CalDav {
+    /// The calendar called `calendar` in the collection of calendars at
+    /// `url`, like https://dav.example.com/calendars/sam.
+    pub fn new(url: &str, calendar: &str) -> Result<CalDav> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(Error::InvalidArgument(format!(
+                "invalid CalDAV URL \"{}\", expected one starting with http:// or https://",
+                url
+            )));
+        }
+        if calendar.is_empty() || calendar.contains('/') {
+            return Err(Error::InvalidArgument(format!(
+                "invalid calendar name \"{}\"",
+                calendar
+            )));
+        }
+        Ok(CalDav {
+            url: format!("{}/{}/", url.trim_end_matches('/'), calendar),
+            user: None,
+        })
+    }
+
+    pub fn with_user(mut self, username: &str, password: &str) -> CalDav {
+        self.user = Some((username.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// The URL of the calendar, ending in a /.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Puts an all-day event for each day in `entries` in the calendar,
+    /// returning how many days there were. Each day's event is always at the
+    /// same URL, so publishing a day again replaces the event rather than
+    /// adding another one.
+    pub fn publish(&self, entries: &[Entry]) -> Result<usize> {
+        let calendars = ics::day_calendars(entries)?;
+        for (day, calendar) in &calendars {
+            self.put(&format!("hmm-{}.ics", day.format("%Y%m%d")), calendar)?;
+        }
+        Ok(calendars.len())
+    }
+
+    fn put(&self, name: &str, calendar: &[u8]) -> Result<()> {
+        let url = format!("{}{}", self.url, name);
+        let mut upload = NamedTempFile::new()?;
+        upload.write_all(calendar)?;
+        let body = NamedTempFile::new()?;
+
+        let out = curl::run(
+            curl::command()
+                .args(["--write-out", "%{http_code}", "--output"])
+                .arg(body.path())
+                .args(["--header", "Content-Type: text/calendar; charset=utf-8"])
+                .arg("--upload-file")
+                .arg(upload.path())
+                .arg(&url),
+            &self.curl_config(),
+        )
+        .map_err(|e| Error::Remote(format!("couldn't run curl: {}", e)))?;
+        if !out.status.success() {
+            return Err(Error::Remote(format!(
+                "couldn't reach {}: {}",
+                url,
+                String::from_utf8_lossy(&out.stderr).trim()
+            )));
+        }
+        let status = String::from_utf8_lossy(&out.stdout);
+        if !status.starts_with('2') {
+            let body = std::fs::read_to_string(body.path()).unwrap_or_default();
+            let mut message = format!("PUT {} failed with status {}", url, status);
+            if !body.trim().is_empty() {
+                message.push_str(": ");
+                message.push_str(&body.trim().chars().take(200).collect::<String>());
+            }
+            return Err(Error::Remote(message));
+        }
+        Ok(())
+    }
+
+    // Credentials are given to curl on stdin, rather than as arguments that
+    // anyone on the machine could see.
+    fn curl_config(&self) -> String {
+        match self.user {
+            Some((ref username, ref password)) => {
+                format!(
+                    "user = {}\n",
+                    curl::quote(&format!("{}:{}", username, password))
+                )
+            }
+            None => "netrc-optional\n".to_owned(),
+        }
+    }
+}
+
+/// Runs `command`, a shell command like `pass show caldav`, and returns the
+/// first line it prints, for reading a password from a password manager.
+pub fn password(command: &str) -> Result<String> {
+    let out = hook::shell(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| Error::Remote(format!("couldn't run `{}`: {}", command, e)))?;
+    if !out.status.success() {
+        return Err(Error::Remote(format!(
+            "`{}` failed: {}",
+            command,
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    let stdout = String::from_utf8(out.stdout)?;
+    Ok(stdout.lines().next().unwrap_or_default().to_owned())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use test_case::test_case;
+    use tiny_http::{Response, Server};
+
+    // What a request to the test server was: its method, URL, Authorization
+    // header and body.
+    type Request = (String, String, Option<String>, String);
+
+    // Runs a server that answers every request with `status`, returning its
+    // address and the requests it gets.
+    fn serve(status: u16) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let shared = requests.clone();
+        thread::spawn(move || {
+            for mut req in server.incoming_requests() {
+                let auth = req
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Authorization"))
+                    .map(|h| h.value.as_str().to_owned());
+                let mut body = String::new();
+                req.as_reader().read_to_string(&mut body).unwrap();
+                shared.lock().unwrap().push((
+                    req.method().as_str().to_owned(),
+                    req.url().to_owned(),
+                    auth,
+                    body,
+                ));
+                let res = Response::from_string(if status < 300 { "" } else { "nope" })
+                    .with_status_code(status);
+                req.respond(res).unwrap();
+            }
+        });
+
+        (addr, requests)
+    }
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry::new(
+                DateTime::parse_from_rfc3339("2024-05-13T09:00:00+00:00").unwrap(),
+                "standup".to_owned(),
+            ),
+            Entry::new(
+                DateTime::parse_from_rfc3339("2024-05-13T12:30:00+00:00").unwrap(),
+                "lunch".to_owned(),
+            ),
+            Entry::new(
+                DateTime::parse_from_rfc3339("2024-05-15T18:00:00+00:00").unwrap(),
+                "garden".to_owned(),
+            ),
+        ]
+    }
+
+    #[test_case("https://dav.example.com/calendars/sam",  "journal" => Ok("https://dav.example.com/calendars/sam/journal/".to_owned())  ; "url")]
+    #[test_case("https://dav.example.com/calendars/sam/", "journal" => Ok("https://dav.example.com/calendars/sam/journal/".to_owned())  ; "trailing slash")]
+    #[test_case("dav.example.com/calendars/sam",          "journal" => Err("invalid CalDAV URL \"dav.example.com/calendars/sam\", expected one starting with http:// or https://".to_owned()) ; "no scheme")]
+    #[test_case("https://dav.example.com",                "a/b"     => Err("invalid calendar name \"a/b\"".to_owned()) ; "slash in calendar")]
+    #[test_case("https://dav.example.com",                ""        => Err("invalid calendar name \"\"".to_owned())    ; "empty calendar")]
+    fn test_new(url: &str, calendar: &str) -> std::result::Result<String, String> {
+        CalDav::new(url, calendar)
+            .map(|caldav| caldav.url().to_owned())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_publish() {
+        let (addr, requests) = serve(201);
+        let caldav = CalDav::new(&format!("http://{}/calendars/sam", addr), "journal")
+            .unwrap()
+            .with_user("sam", "hunter2");
+        assert_eq!(caldav.publish(&entries()).unwrap(), 2);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (method, url, auth, body) = &requests[0];
+        assert_eq!(method, "PUT");
+        assert_eq!(url, "/calendars/sam/journal/hmm-20240513.ics");
+        // "sam:hunter2" in base64.
+        assert_eq!(auth.as_deref(), Some("Basic c2FtOmh1bnRlcjI="));
+        assert!(body.contains("UID:20240513@hmm\r\n"), "{}", body);
+        assert!(body.contains("SUMMARY:2 hmm entries\r\n"), "{}", body);
+        assert_eq!(requests[1].1, "/calendars/sam/journal/hmm-20240515.ics");
+    }
+
+    #[test]
+    fn test_publish_nothing() {
+        let caldav = CalDav::new("http://127.0.0.1:1", "journal").unwrap();
+        assert_eq!(caldav.publish(&[]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_publish_rejected() {
+        let (addr, _) = serve(403);
+        let caldav = CalDav::new(&format!("http://{}", addr), "journal").unwrap();
+        let err = caldav.publish(&entries()).unwrap_err();
+        assert_eq!(err.kind(), "remote");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "PUT http://{}/journal/hmm-20240513.ics failed with status 403: nope",
+                addr
+            )
+        );
+    }
+
+    #[test]
+    fn test_publish_unreachable() {
+        let caldav = CalDav::new("http://127.0.0.1:1", "journal").unwrap();
+        let err = caldav.publish(&entries()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("couldn't reach http://127.0.0.1:1/journal/hmm-20240513.ics"),
+            "{}",
+            err
+        );
+    }
+
+    #[test_case("echo hunter2"                  => Ok("hunter2".to_owned())      ; "first line")]
+    #[test_case("printf 'hunter2\\nnotes\\n'"   => Ok("hunter2".to_owned())      ; "more lines")]
+    #[test_case("echo oops >&2; exit 1"         => Err("`echo oops >&2; exit 1` failed: oops".to_owned()) ; "failure")]
+    fn test_password(command: &str) -> std::result::Result<String, String> {
+        password(command).map_err(|e| e.to_string())
+    }
+}
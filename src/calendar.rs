@@ -0,0 +1,173 @@
+//! A month calendar showing how many entries were written on each day, for
+//! hmmq --calendar.
+
+use super::{date, entry::Entry, error::Error, Result};
+use chrono::prelude::*;
+use colored::Color;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+// Each day is its number and how many entries were written on it, like
+// "13   4", and days are separated by a space.
+const CELL_WIDTH: usize = 6;
+const WIDTH: usize = CELL_WIDTH * 7 + 6;
+
+/// Parses the month given to --calendar, which is any prefix of an RFC3339
+/// date like --start, and returns its first day.
+pub fn parse_month(s: &str) -> Result<NaiveDate> {
+    let date = date::parse(s)?.with_timezone(&Local).date_naive();
+    date.with_day(1)
+        .ok_or_else(|| Error::InvalidDate(s.to_owned()))
+}
+
+/// Writes a calendar of the month starting on `month` to `w`, with weeks
+/// starting on Monday and how many of `entries` were written on each day. If
+/// `color` is true, counts are colored by how busy the day was compared to the
+/// busiest day of the month. If `titles` is true, the time and title of each
+/// entry is listed under its day after the calendar. Entries from other months
+/// are left out.
+pub fn write(
+    mut w: impl Write,
+    entries: &[Entry],
+    month: NaiveDate,
+    titles: bool,
+    color: bool,
+) -> Result<()> {
+    let mut days: BTreeMap<NaiveDate, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        let local = entry.datetime().with_timezone(&Local);
+        if local.year() == month.year() && local.month() == month.month() {
+            days.entry(local.date_naive()).or_default().push(entry);
+        }
+    }
+    let busiest = days.values().map(Vec::len).max().unwrap_or(0);
+
+    let heading = format!(
+        "{:^width$}",
+        month.format("%B %Y").to_string(),
+        width = WIDTH
+    );
+    writeln!(w, "{}", heading.trim_end())?;
+    let weekdays: Vec<String> = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+        .iter()
+        .map(|day| format!("{:>width$}", day, width = CELL_WIDTH))
+        .collect();
+    writeln!(w, "{}", weekdays.join(" "))?;
+
+    let mut cells = vec![" ".repeat(CELL_WIDTH); month.weekday().num_days_from_monday() as usize];
+    for day in month
+        .iter_days()
+        .take_while(|day| day.month() == month.month())
+    {
+        let count = days.get(&day).map_or(0, Vec::len);
+        cells.push(cell(day.day(), count, busiest, color));
+    }
+    for week in cells.chunks(7) {
+        writeln!(w, "{}", week.join(" ").trim_end())?;
+    }
+
+    if titles {
+        for (day, entries) in &days {
+            writeln!(w, "\n{}", day.format("%a %-d"))?;
+            for entry in entries {
+                let time = entry.datetime().with_timezone(&Local).format("%H:%M");
+                writeln!(w, "  {} {}", time, entry.title().trim())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A day in the calendar. Days without entries get a dot so that they can be
+// told apart from days outside of the month.
+fn cell(day: u32, count: usize, busiest: usize, color: bool) -> String {
+    if count == 0 {
+        return format!("{:>2} {:>3}", day, "·");
+    }
+    if !color {
+        return format!("{:>2} {:>3}", day, count);
+    }
+    let color = match count_level(count, busiest) {
+        1 => Color::Green,
+        2 => Color::Yellow,
+        _ => Color::Red,
+    };
+    format!("{:>2} \x1b[{}m{:>3}\x1b[0m", day, color.to_fg_str(), count)
+}
+
+// Which third of the busiest day's count `count` is in, from 1 to 3.
+fn count_level(count: usize, busiest: usize) -> usize {
+    (count * 3).div_ceil(busiest.max(1)).clamp(1, 3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn entry(day: u32, hour: u32, message: &str) -> Entry {
+        let datetime = Local
+            .with_ymd_and_hms(2024, 6, day, hour, 0, 0)
+            .unwrap()
+            .fixed_offset();
+        Entry::new(datetime, message.to_owned())
+    }
+
+    fn calendar(entries: &[Entry], titles: bool, color: bool) -> String {
+        let mut out = Vec::new();
+        let month = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        write(&mut out, entries, month, titles, color).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write() {
+        let entries = [
+            entry(3, 9, "first\nbody"),
+            entry(3, 12, "second"),
+            entry(30, 9, "last"),
+        ];
+        assert_eq!(
+            calendar(&entries, false, false),
+            "                   June 2024
+   Mon    Tue    Wed    Thu    Fri    Sat    Sun
+                                    1   ·  2   ·
+ 3   2  4   ·  5   ·  6   ·  7   ·  8   ·  9   ·
+10   · 11   · 12   · 13   · 14   · 15   · 16   ·
+17   · 18   · 19   · 20   · 21   · 22   · 23   ·
+24   · 25   · 26   · 27   · 28   · 29   · 30   1
+"
+        );
+        assert!(calendar(&entries, true, false)
+            .ends_with("30   1\n\nMon 3\n  09:00 first\n  12:00 second\n\nSun 30\n  09:00 last\n"));
+    }
+
+    #[test]
+    fn test_write_color() {
+        let entries = [
+            entry(3, 9, "a"),
+            entry(3, 10, "b"),
+            entry(3, 11, "c"),
+            entry(4, 9, "d"),
+        ];
+        let calendar = calendar(&entries, false, true);
+        assert!(calendar.contains(" 3 \x1b[31m  3\x1b[0m"), "{:?}", calendar);
+        assert!(calendar.contains(" 4 \x1b[32m  1\x1b[0m"), "{:?}", calendar);
+        assert!(calendar.contains(" 5   ·"), "{:?}", calendar);
+    }
+
+    #[test_case(1, 3  => 1 ; "low")]
+    #[test_case(2, 3  => 2 ; "middle")]
+    #[test_case(3, 3  => 3 ; "busiest")]
+    #[test_case(1, 10 => 1 ; "much less")]
+    fn test_count_level(count: usize, busiest: usize) -> usize {
+        count_level(count, busiest)
+    }
+
+    #[test_case("2024-06"    => NaiveDate::from_ymd_opt(2024, 6, 1).unwrap() ; "month")]
+    #[test_case("2024-06-13" => NaiveDate::from_ymd_opt(2024, 6, 1).unwrap() ; "day")]
+    #[test_case("2024"       => NaiveDate::from_ymd_opt(2024, 1, 1).unwrap() ; "year")]
+    fn test_parse_month(s: &str) -> NaiveDate {
+        parse_month(s).unwrap()
+    }
+}
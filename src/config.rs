@@ -0,0 +1,465 @@
+use super::{
+    entry_template::EntryTemplate,
+    error::Error,
+    format::{ColorMode, ColorRule},
+    journal::SkewPolicy,
+    Result,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Settings read from hmm's config file, a TOML file that lives at
+/// ~/.config/hmm/config.toml on *nix systems and %APPDATA%\hmm\config.toml on
+/// Windows. Everything in it is optional, and flags passed on the command line
+/// take precedence over anything set here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// What `hmm` should do when the last entry in your hmm file is later than
+    /// the current time.
+    pub skew: Option<SkewPolicy>,
+
+    /// A shell command `hmm` runs before writing an entry. If it exits
+    /// unsuccessfully the entry isn't written.
+    pub pre_append: Option<String>,
+
+    /// A shell command `hmm` runs after writing an entry, for things like
+    /// committing your hmm file to git.
+    pub post_append: Option<String>,
+
+    /// A URL `hmm` POSTs each new entry to as JSON after writing it, for
+    /// things like Slack, Discord or IFTTT.
+    pub webhook: Option<String>,
+
+    /// A shell command `hmm` runs to find where you are when you write an
+    /// entry, like CoreLocationCLI or termux-location. What it prints, a
+    /// latitude and longitude, is stored in the entry's location metadata.
+    pub location_command: Option<String>,
+
+    /// A shell command `hmm` runs on entries composed in an editor before
+    /// writing them, like a spell checker. If it exits unsuccessfully, what it
+    /// printed is shown and you can edit the entry again or write it anyway.
+    pub validate_command: Option<String>,
+
+    /// A shell command `hmm` pipes each new entry's message through to
+    /// encrypt it, leaving its timestamp in plain text.
+    pub encrypt_command: Option<String>,
+
+    /// A shell command `hmmq` pipes encrypted messages through to decrypt
+    /// them.
+    pub decrypt_command: Option<String>,
+
+    /// A file `hmm` fills your editor with when you compose an entry, like
+    /// git's commit.template. If it's a directory, one of the files in it is
+    /// picked each day. A leading ~ is expanded to your home directory.
+    pub template: Option<PathBuf>,
+
+    /// Lines starting with this are removed from entries composed from a
+    /// template, so that templates can include instructions. Defaults to "#".
+    pub comment_prefix: Option<String>,
+
+    /// A Handlebars template `hmms review` renders its digest with, instead of
+    /// the default one. A leading ~ is expanded to your home directory.
+    pub review_template: Option<PathBuf>,
+
+    /// A Handlebars template `hmms digest` renders with, instead of the default
+    /// one. A leading ~ is expanded to your home directory.
+    pub digest_template: Option<PathBuf>,
+
+    /// A Handlebars template `hmms year` renders with, instead of the default
+    /// one. A leading ~ is expanded to your home directory.
+    pub year_template: Option<PathBuf>,
+
+    /// The command `hmms digest --email` pipes emails to, which is expected to
+    /// read who to send them to from their headers. Defaults to
+    /// "sendmail -t".
+    pub sendmail_command: Option<String>,
+
+    /// Where `hmmq --caldav` publishes to, and how it logs in.
+    pub caldav: CalDavConfig,
+
+    /// Defaults for hmmq, used when the matching flag isn't given.
+    #[serde(flatten)]
+    pub query: QueryDefaults,
+
+    /// Overrides for particular journals, which take precedence over the
+    /// defaults above when hmmq is run against them.
+    pub journals: Vec<JournalConfig>,
+
+    /// How hmmq's autocolor helper styles entries, by their tags and the day
+    /// of the week they were written on.
+    pub color_rules: Vec<ColorRule>,
+
+    /// Shortcuts for hmmq flags. An alias given as the first argument to hmmq
+    /// is replaced with the flags it stands for.
+    pub aliases: BTreeMap<String, String>,
+
+    /// Templates for entries that always have the same shape, filled in from
+    /// the command line with `hmm --new`.
+    pub entry_templates: BTreeMap<String, EntryTemplate>,
+}
+
+/// Defaults for hmmq, which can be set for all journals or per journal.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct QueryDefaults {
+    /// The template to format entries with, as in --format.
+    pub default_format: Option<String>,
+
+    /// How many entries to show, as in --last, when hmmq is run without any
+    /// flags that pick which entries to show.
+    pub default_last: Option<i64>,
+
+    /// Whether to color output, as in --color.
+    pub color: Option<ColorMode>,
+}
+
+impl QueryDefaults {
+    /// Fills in any settings that aren't set in `self` from `other`.
+    pub fn or(self, other: QueryDefaults) -> QueryDefaults {
+        QueryDefaults {
+            default_format: self.default_format.or(other.default_format),
+            default_last: self.default_last.or(other.default_last),
+            color: self.color.or(other.color),
+        }
+    }
+}
+
+/// Settings for `hmmq --caldav`, given in the config file as
+///
+/// ```toml
+/// [caldav]
+/// url = "https://dav.example.com/calendars/sam"
+/// calendar = "journal"
+/// username = "sam"
+/// password_command = "pass show caldav"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CalDavConfig {
+    /// Where your calendars are, as in --url.
+    pub url: Option<String>,
+
+    /// Which calendar to publish to, as in --calendar.
+    pub calendar: Option<String>,
+
+    /// Who to log in as. Without this, credentials are read from ~/.netrc.
+    pub username: Option<String>,
+
+    /// A shell command that prints the password to log in with, like one
+    /// that reads it from a password manager. Only its first line is used.
+    pub password_command: Option<String>,
+}
+
+/// Settings for a single journal, given in the config file as
+///
+/// ```toml
+/// [[journals]]
+/// path = "~/work.hmm"
+/// default_last = 5
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    /// Path to the journal these settings apply to. A leading ~ is expanded
+    /// to your home directory.
+    pub path: PathBuf,
+
+    #[serde(flatten)]
+    pub query: QueryDefaults,
+}
+
+impl Config {
+    /// Where the config file is read from when one isn't given explicitly.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("hmm").join("config.toml"))
+    }
+
+    /// Reads the config file at `path`. It's fine for the file not to exist,
+    /// in which case every setting takes its default value.
+    pub fn load(path: &Path) -> Result<Config> {
+        let s = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => {
+                return Err(Error::Config {
+                    path: path.to_owned(),
+                    reason: e.to_string(),
+                })
+            }
+        };
+
+        toml::from_str(&s).map_err(|e| Error::Config {
+            path: path.to_owned(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// The hmmq defaults to use for the journal at `path`, with any overrides
+    /// for that journal applied.
+    pub fn query_defaults(&self, path: &Path) -> QueryDefaults {
+        let path = canonical(path);
+        let journal = self
+            .journals
+            .iter()
+            .find(|journal| canonical(&expand_tilde(&journal.path)) == path);
+
+        match journal {
+            Some(journal) => journal.query.clone().or(self.query.clone()),
+            None => self.query.clone(),
+        }
+    }
+
+    /// Expands the alias at the start of `args`, if there is one, in to the
+    /// arguments it stands for. Aliases can refer to other aliases, as long as
+    /// they don't end up referring back to themselves. `args` shouldn't include
+    /// the program name.
+    pub fn expand_aliases(&self, mut args: Vec<OsString>) -> Result<Vec<OsString>> {
+        let mut seen: Vec<String> = Vec::new();
+
+        loop {
+            let name = match args.first().and_then(|arg| arg.to_str()) {
+                Some(name) if self.aliases.contains_key(name) => name.to_owned(),
+                _ => return Ok(args),
+            };
+
+            if seen.contains(&name) {
+                seen.push(name);
+                return Err(Error::InvalidArgument(format!(
+                    "alias loop: {}",
+                    seen.join(" -> ")
+                )));
+            }
+
+            let expansion = shellwords::split(&self.aliases[&name]).map_err(|_| {
+                Error::InvalidArgument(format!("mismatched quotes in alias \"{}\"", name))
+            })?;
+            args.splice(..1, expansion.into_iter().map(OsString::from));
+            seen.push(name);
+        }
+    }
+
+    /// Reads the config file at `path` if given, otherwise the one at the
+    /// default location.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Config> {
+        match path {
+            Some(path) => Config::load(path),
+            None => match Config::default_path() {
+                Some(path) => Config::load(&path),
+                None => Ok(Config::default()),
+            },
+        }
+    }
+}
+
+/// Replaces a leading ~ in `path` with the current user's home directory.
+pub fn expand_tilde(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+// Paths are compared after resolving symlinks and relative components where
+// possible, so that the same journal matches however it's been referred to.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    fn load(s: &str) -> Result<Config> {
+        let mut f = NamedTempFile::new().unwrap();
+        f.write_all(s.as_bytes()).unwrap();
+        Config::load(f.path())
+    }
+
+    #[test_case(""                 => None                      ; "empty")]
+    #[test_case("skew = \"error\"" => Some(SkewPolicy::Error)   ; "error")]
+    #[test_case("skew = \"wait\""  => Some(SkewPolicy::Wait)    ; "wait")]
+    #[test_case("skew = \"clamp\"" => Some(SkewPolicy::Clamp)   ; "clamp")]
+    fn test_load_skew(s: &str) -> Option<SkewPolicy> {
+        load(s).unwrap().skew
+    }
+
+    #[test]
+    fn test_load_hooks() {
+        let config = load(
+            "pre_append = \"true\"\npost_append = \"git commit -am 'entry'\"\nvalidate_command = \"codespell -\"\nwebhook = \"https://example.com/hook\"\nlocation_command = \"CoreLocationCLI\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.pre_append.as_deref(), Some("true"));
+        assert_eq!(config.webhook.as_deref(), Some("https://example.com/hook"));
+        assert_eq!(config.location_command.as_deref(), Some("CoreLocationCLI"));
+        assert_eq!(config.validate_command.as_deref(), Some("codespell -"));
+        assert_eq!(
+            config.post_append.as_deref(),
+            Some("git commit -am 'entry'")
+        );
+    }
+
+    #[test]
+    fn test_load_crypt_commands() {
+        let config = load(
+            "encrypt_command = \"age -r age1xyz\"\ndecrypt_command = \"age -d -i ~/.hmm-key\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.encrypt_command.as_deref(), Some("age -r age1xyz"));
+        assert_eq!(
+            config.decrypt_command.as_deref(),
+            Some("age -d -i ~/.hmm-key")
+        );
+    }
+
+    #[test]
+    fn test_load_template() {
+        let config = load("template = \"~/.hmm-template\"\ncomment_prefix = \";\"\n").unwrap();
+        assert_eq!(config.template, Some(PathBuf::from("~/.hmm-template")));
+        assert_eq!(config.comment_prefix.as_deref(), Some(";"));
+    }
+
+    #[test]
+    fn test_load_review_template() {
+        let config = load("review_template = \"~/review.hbs\"\n").unwrap();
+        assert_eq!(config.review_template, Some(PathBuf::from("~/review.hbs")));
+    }
+
+    #[test]
+    fn test_load_digest() {
+        let config =
+            load("digest_template = \"~/digest.hbs\"\nsendmail_command = \"msmtp -t\"\n").unwrap();
+        assert_eq!(config.digest_template, Some(PathBuf::from("~/digest.hbs")));
+        assert_eq!(config.sendmail_command.as_deref(), Some("msmtp -t"));
+    }
+
+    #[test]
+    fn test_load_caldav() {
+        let config = load(
+            "[caldav]\nurl = \"https://dav.example.com/calendars/sam\"\nusername = \"sam\"\npassword_command = \"pass show caldav\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.caldav,
+            CalDavConfig {
+                url: Some("https://dav.example.com/calendars/sam".to_owned()),
+                calendar: None,
+                username: Some("sam".to_owned()),
+                password_command: Some("pass show caldav".to_owned()),
+            }
+        );
+        assert_eq!(load("").unwrap().caldav, CalDavConfig::default());
+    }
+
+    #[test]
+    fn test_load_year_template() {
+        let config = load("year_template = \"~/year.hbs\"\n").unwrap();
+        assert_eq!(config.year_template, Some(PathBuf::from("~/year.hbs")));
+    }
+
+    #[test]
+    fn test_load_color_rules() {
+        let config =
+            load("[[color_rules]]\ntag = \"bug\"\ncolor = \"red\"\n\n[[color_rules]]\nweekday = \"Sat\"\ndim = true\n")
+                .unwrap();
+        assert_eq!(config.color_rules.len(), 2);
+        assert_eq!(config.color_rules[0].tag.as_deref(), Some("bug"));
+        assert_eq!(config.color_rules[1].weekday, Some(chrono::Weekday::Sat));
+
+        let err = load("[[color_rules]]\ntag = \"bug\"\n").err().unwrap();
+        assert_eq!(err.kind(), "config");
+    }
+
+    #[test]
+    fn test_load_entry_templates() {
+        let config =
+            load("[entry_templates]\nstandup = \"today: {t}\\nblockers: {b|none}\"\n").unwrap();
+        assert_eq!(config.entry_templates["standup"].fields(), vec!["t", "b"]);
+
+        let err = load("[entry_templates]\nstandup = \"today: {t\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "config");
+    }
+
+    #[test]
+    fn test_query_defaults() {
+        let journal = NamedTempFile::new().unwrap();
+        let config = load(&format!(
+            "default_last = 20\ncolor = \"never\"\n\n[[journals]]\npath = {:?}\ndefault_last = 5\ndefault_format = \"{{{{ message }}}}\"\n",
+            journal.path()
+        ))
+        .unwrap();
+
+        assert_eq!(
+            config.query_defaults(Path::new("/some/other.hmm")),
+            QueryDefaults {
+                default_format: None,
+                default_last: Some(20),
+                color: Some(ColorMode::Never),
+            }
+        );
+        assert_eq!(
+            config.query_defaults(journal.path()),
+            QueryDefaults {
+                default_format: Some("{{ message }}".to_owned()),
+                default_last: Some(5),
+                color: Some(ColorMode::Never),
+            }
+        );
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test_case(&["--last", "1"]          => args(&["--last", "1"])                                  ; "no alias")]
+    #[test_case(&["recent"]               => args(&["--last", "10"])                                 ; "alias")]
+    #[test_case(&["recent", "--raw"]      => args(&["--last", "10", "--raw"])                        ; "extra args")]
+    #[test_case(&["plain", "--last", "1"] => args(&["--format", "{{ message }}", "--last", "1"])     ; "quoted")]
+    #[test_case(&["raw-recent"]           => args(&["--last", "10", "--raw"])                        ; "nested")]
+    #[test_case(&["--raw", "recent"]      => args(&["--raw", "recent"])                              ; "only first argument")]
+    fn test_expand_aliases(a: &[&str]) -> Vec<OsString> {
+        let config = load(
+            "[aliases]\nrecent = \"--last 10\"\nplain = \"--format '{{ message }}'\"\nraw-recent = \"recent --raw\"",
+        )
+        .unwrap();
+        config.expand_aliases(args(a)).unwrap()
+    }
+
+    #[test]
+    fn test_expand_aliases_loop() {
+        let config = load("[aliases]\na = \"b --raw\"\nb = \"a\"").unwrap();
+        let err = config.expand_aliases(args(&["a"])).err().unwrap();
+        assert_eq!(err.to_string(), "alias loop: a -> b -> a");
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_tilde(Path::new("~/a.hmm")), home.join("a.hmm"));
+        assert_eq!(expand_tilde(Path::new("/a.hmm")), Path::new("/a.hmm"));
+        assert_eq!(expand_tilde(Path::new("a~/b")), Path::new("a~/b"));
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let config = Config::load(Path::new("/this/path/does/not/exist")).unwrap();
+        assert!(config.skew.is_none());
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let err = load("skew = \"sometimes\"").err().unwrap();
+        assert_eq!(err.kind(), "config");
+        assert!(err.to_string().contains("sometimes"), "{}", err);
+    }
+}
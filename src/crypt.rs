@@ -0,0 +1,276 @@
+use super::{
+    entry::{Entry, Metadata},
+    error::Error,
+    hook, Result,
+};
+use std::io::{self, Write};
+use std::process::Stdio;
+use std::thread;
+
+/// The metadata field that marks an entry's message as encrypted, written
+/// along with every message that `encrypt` returns.
+pub const FIELD: &str = "encrypted";
+
+// Encrypted messages start with this, followed by the ciphertext in base64,
+// which makes them easy to spot in the file. It's FIELD that tells them apart
+// from messages that were written in plain text and happen to start the same
+// way.
+const PREFIX: &str = "hmm-encrypted:";
+
+/// Whether an entry with `message` and `metadata` was encrypted by `encrypt`.
+pub fn is_encrypted(message: &str, metadata: &Metadata) -> bool {
+    metadata.get(FIELD).and_then(|v| v.as_bool()) == Some(true) && message.starts_with(PREFIX)
+}
+
+/// Whether a line of a .hmm file might have an encrypted message in it, which
+/// can be checked without parsing the line. Like `entry::might_contain`, this
+/// only returns false when it definitely doesn't.
+pub fn might_be_encrypted(line: &str) -> bool {
+    line.contains(PREFIX)
+}
+
+/// Encrypts `message` by piping it through `command`, a shell command like
+/// `age -r age1...` that writes the ciphertext to stdout. hmm doesn't do any
+/// encryption itself, so that it's done by a tool you already trust. The
+/// entry it's written to needs the metadata from `metadata` to be recognised
+/// as encrypted.
+pub fn encrypt(command: &str, message: &str) -> Result<String> {
+    let ciphertext = run(command, message.as_bytes())?;
+    Ok(format!("{}{}", PREFIX, base64_encode(&ciphertext)))
+}
+
+/// The metadata that marks an entry's message as encrypted.
+pub fn metadata() -> Metadata {
+    let mut metadata = Metadata::new();
+    metadata.insert(FIELD.to_owned(), true.into());
+    metadata
+}
+
+/// Encrypts `message` with `command` if there is one, for writers that encrypt
+/// what they write when encrypt_command is set, and otherwise returns it
+/// unchanged. Either way, it's returned with the metadata to write with it,
+/// which is empty if it wasn't encrypted.
+pub fn encrypt_with(command: Option<&str>, message: &str) -> Result<(String, Metadata)> {
+    match command {
+        Some(command) => Ok((encrypt(command, message)?, metadata())),
+        None => Ok((message.to_owned(), Metadata::new())),
+    }
+}
+
+/// Returns a copy of `entry` with its message encrypted by `encrypt`.
+pub fn encrypt_entry(command: &str, entry: &Entry) -> Result<Entry> {
+    let mut metadata = entry.metadata().clone();
+    metadata.extend(self::metadata());
+    Ok(Entry::new(*entry.datetime(), encrypt(command, entry.message())?).with_metadata(metadata))
+}
+
+/// Decrypts a message written by `encrypt` by piping the ciphertext through
+/// `command`, like `age -d -i ~/.hmm-key`. Messages that don't start the way
+/// encrypted ones do are returned unchanged.
+pub fn decrypt(command: &str, message: &str) -> Result<String> {
+    let encoded = match message.strip_prefix(PREFIX) {
+        Some(encoded) => encoded,
+        None => return Ok(message.to_owned()),
+    };
+    let ciphertext = base64_decode(encoded)
+        .ok_or_else(|| Error::Crypt("encrypted message isn't valid base64".to_owned()))?;
+    let plaintext = run(command, &ciphertext)?;
+    String::from_utf8(plaintext)
+        .map_err(|_| Error::Crypt("decrypted message isn't valid UTF-8".to_owned()))
+}
+
+/// Returns a copy of `entry` with its message decrypted and the metadata
+/// marking it as encrypted taken off, if it was encrypted.
+pub fn decrypt_entry(command: &str, entry: &Entry) -> Result<Entry> {
+    if !is_encrypted(entry.message(), entry.metadata()) {
+        return Ok(entry.clone());
+    }
+    let mut metadata = entry.metadata().clone();
+    metadata.remove(FIELD);
+    Ok(Entry::new(*entry.datetime(), decrypt(command, entry.message())?).with_metadata(metadata))
+}
+
+// Input is written on another thread, as commands can start writing output
+// before they've read all of their input, and both pipes filling up would
+// leave us waiting on each other forever.
+fn run(command: &str, input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = hook::shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Crypt(format!("couldn't run `{}`: {}", command, e)))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_owned();
+    let writer = thread::spawn(move || match stdin.write_all(&input) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(()),
+    });
+
+    let out = child.wait_with_output()?;
+    writer.join().unwrap()?;
+
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(Error::Crypt(format!(
+            "`{}` failed: {}",
+            command,
+            stderr.trim()
+        )));
+    }
+    Ok(out.stdout)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    let mut n = 0u32;
+    for (i, c) in s.bytes().enumerate() {
+        let v = BASE64.iter().position(|&b| b == c)? as u32;
+        n = n << 6 | v;
+        if i % 4 == 3 {
+            bytes.extend_from_slice(&[(n >> 16) as u8, (n >> 8) as u8, n as u8]);
+            n = 0;
+        }
+    }
+    match s.len() % 4 {
+        0 => {}
+        2 => bytes.push((n >> 4) as u8),
+        3 => bytes.extend_from_slice(&[(n >> 10) as u8, (n >> 2) as u8]),
+        _ => return None,
+    }
+    Some(bytes)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use test_case::test_case;
+
+    // Not encryption, but it's reversible and every system has it.
+    const ROT13: &str = "tr 'A-Za-z' 'N-ZA-Mn-za-m'";
+
+    #[test_case(""       => ""         ; "empty")]
+    #[test_case("f"      => "Zg=="     ; "one byte")]
+    #[test_case("fo"     => "Zm8="     ; "two bytes")]
+    #[test_case("foo"    => "Zm9v"     ; "three bytes")]
+    #[test_case("foobar" => "Zm9vYmFy" ; "six bytes")]
+    fn test_base64(s: &str) -> String {
+        let encoded = base64_encode(s.as_bytes());
+        assert_eq!(base64_decode(&encoded).unwrap(), s.as_bytes());
+        encoded
+    }
+
+    #[test_case("Zm9v!" ; "bad character")]
+    #[test_case("Z"     ; "bad length")]
+    fn test_base64_invalid(s: &str) {
+        assert!(base64_decode(s).is_none());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let encrypted = encrypt(ROT13, "hello, world").unwrap();
+        assert_eq!(
+            encrypted,
+            format!("{}{}", PREFIX, base64_encode(b"uryyb, jbeyq"))
+        );
+        assert_eq!(decrypt(ROT13, &encrypted).unwrap(), "hello, world");
+    }
+
+    #[test]
+    fn test_encrypt_with() {
+        assert_eq!(
+            encrypt_with(None, "hello").unwrap(),
+            ("hello".to_owned(), Metadata::new())
+        );
+        assert_eq!(
+            encrypt_with(Some(ROT13), "hello").unwrap(),
+            (encrypt(ROT13, "hello").unwrap(), metadata())
+        );
+    }
+
+    #[test_case("2020-01-01T00:00:00+00:00,\"\"\"hmm-encrypted:dXJ5eWI=\"\"\"" => true  ; "encrypted")]
+    #[test_case("2020-01-01T00:00:00+00:00,\"\"\"hello\"\"\""                  => false ; "plain")]
+    fn test_might_be_encrypted(line: &str) -> bool {
+        might_be_encrypted(line)
+    }
+
+    #[test]
+    fn test_decrypt_plain_text() {
+        assert_eq!(decrypt("false", "not secret").unwrap(), "not secret");
+    }
+
+    #[test]
+    fn test_encrypt_entry() {
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap(),
+            "secret".to_owned(),
+        );
+        let encrypted = encrypt_entry(ROT13, &entry).unwrap();
+        assert!(is_encrypted(encrypted.message(), encrypted.metadata()));
+        assert_eq!(encrypted.datetime(), entry.datetime());
+        let decrypted = decrypt_entry(ROT13, &encrypted).unwrap();
+        assert_eq!(decrypted.message(), "secret");
+        assert!(decrypted.metadata().is_empty());
+    }
+
+    #[test]
+    fn test_decrypt_entry() {
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap(),
+            encrypt(ROT13, "secret").unwrap(),
+        )
+        .with_metadata(metadata());
+        let decrypted = decrypt_entry(ROT13, &entry).unwrap();
+        assert_eq!(decrypted.message(), "secret");
+        assert_eq!(decrypted.datetime(), entry.datetime());
+        assert!(decrypted.metadata().is_empty());
+    }
+
+    #[test_case("hmm-encrypted: not really" ; "not base64")]
+    #[test_case("hmm-encrypted:dXJ5eWI="     ; "base64")]
+    fn test_decrypt_entry_plain_text_with_prefix(message: &str) {
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap(),
+            message.to_owned(),
+        );
+        assert!(!is_encrypted(entry.message(), entry.metadata()));
+        assert_eq!(decrypt_entry("false", &entry).unwrap().message(), message);
+    }
+
+    #[test]
+    fn test_failing_command() {
+        let err = encrypt("echo no key >&2; exit 1", "secret").unwrap_err();
+        assert_eq!(err.to_string(), "`echo no key >&2; exit 1` failed: no key");
+        assert_eq!(err.exit_code(), 70);
+    }
+
+    #[test]
+    fn test_large_message() {
+        let message = "a".repeat(1 << 20);
+        let encrypted = encrypt("cat", &message).unwrap();
+        assert_eq!(decrypt("cat", &encrypted).unwrap(), message);
+    }
+}
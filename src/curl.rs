@@ -0,0 +1,56 @@
+//! Running curl, which is how hmm makes HTTP requests for webhooks, object
+//! storage and CalDAV without pulling in an HTTP client and TLS stack.
+
+use std::io::{self, Write};
+use std::process::{Command, Output, Stdio};
+
+/// A curl command that reads more options from a config file on stdin, to be
+/// run with `run`. Errors are printed to stderr, but progress isn't.
+pub fn command() -> Command {
+    let mut cmd = Command::new("curl");
+    cmd.args(["--silent", "--show-error", "--config", "-"]);
+    cmd
+}
+
+/// Runs `cmd`, made with `command`, giving it `config` on stdin and returning
+/// what it printed. Options that include secrets, like credentials and webhook
+/// URLs, go in `config` rather than in arguments, as the arguments of a
+/// process can be seen by anyone on the machine.
+pub fn run(cmd: &mut Command, config: &str) -> io::Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(config.as_bytes())?;
+    drop(stdin);
+    child.wait_with_output()
+}
+
+/// Quotes a value for a curl config file.
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("https://example.com"  => "\"https://example.com\""    ; "plain")]
+    #[test_case("say \"hi\""            => "\"say \\\"hi\\\"\""         ; "quotes")]
+    #[test_case("C:\\hmm"               => "\"C:\\\\hmm\""              ; "backslash")]
+    fn test_quote(s: &str) -> String {
+        quote(s)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_reads_config_from_stdin() {
+        // curl isn't needed to check that the config arrives on stdin.
+        let mut cmd = Command::new("cat");
+        let out = run(&mut cmd, "url = \"https://example.com\"\n").unwrap();
+        assert_eq!(out.stdout, b"url = \"https://example.com\"\n");
+    }
+}
@@ -0,0 +1,148 @@
+use super::{error::Error, Result};
+use chrono::prelude::*;
+
+/// Parses a date given as any prefix of an RFC3339 date without a timezone,
+/// like "2020", "2020-06" or "2020-06-13T10:12", filling in the rest with
+/// the earliest value it could be. This is how dates are given to hmmq's
+/// --start and --end.
+pub fn parse(s: &str) -> Result<DateTime<FixedOffset>> {
+    if let Ok(d) = parse_local_datetime_str(&format!("{}-01-01T00:00:00", s), "%Y-%m-%dT%H:%M:%S") {
+        return Ok(d.into());
+    }
+    if let Ok(d) = parse_local_datetime_str(&format!("{}-01T00:00:00", s), "%Y-%m-%dT%H:%M:%S") {
+        return Ok(d.into());
+    }
+    if let Ok(d) = parse_local_datetime_str(&format!("{}T00:00:00", s), "%Y-%m-%dT%H:%M:%S") {
+        return Ok(d.into());
+    }
+    if let Ok(d) = parse_local_datetime_str(&format!("{}:00:00", s), "%Y-%m-%dT%H:%M:%S") {
+        return Ok(d.into());
+    }
+    if let Ok(d) = parse_local_datetime_str(&format!("{}:00", s), "%Y-%m-%dT%H:%M:%S") {
+        return Ok(d.into());
+    }
+    if let Ok(d) = parse_local_datetime_str(s, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(d.into());
+    }
+
+    Err(Error::InvalidDate(s.to_owned()))
+}
+
+/// Splits a timestamp off the start of `line`, like the ones at the start of
+/// each line of most logs, returning it along with the rest of the line. RFC
+/// 3339 timestamps are understood, as are ones with a space instead of the T,
+/// and ones without a timezone are in local time.
+pub fn split_timestamp(line: &str) -> Option<(DateTime<FixedOffset>, &str)> {
+    let mut words = line.splitn(3, ' ');
+    let first = words.next()?;
+    let second = words.next();
+
+    if let Some(datetime) = parse_timestamp(first) {
+        return Some((datetime, line[first.len()..].trim_start()));
+    }
+    let second = second?;
+    let datetime = parse_timestamp(&format!("{}T{}", first, second))?;
+    Some((
+        datetime,
+        line[first.len() + 1 + second.len()..].trim_start(),
+    ))
+}
+
+/// The first moment of `day` in local time. That's midnight, unless the
+/// clocks skip it when they change, in which case it's the first hour there
+/// is.
+pub fn start_of_day(day: NaiveDate) -> DateTime<FixedOffset> {
+    (0..24)
+        .find_map(|hour| {
+            Local
+                .from_local_datetime(&day.and_hms_opt(hour, 0, 0)?)
+                .earliest()
+        })
+        .unwrap_or_else(|| Local.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap()))
+        .fixed_offset()
+}
+
+/// Formats a duration the way intervals are given on the command line, like
+/// "45s", "1h30m" or "2d3h", leaving out units that are zero. Anything less
+/// than a second is dropped.
+pub fn format_duration(d: chrono::Duration) -> String {
+    let mut secs = d.num_seconds();
+    let mut s = String::new();
+    if secs < 0 {
+        s.push('-');
+        secs = -secs;
+    } else if secs == 0 {
+        return "0s".to_owned();
+    }
+
+    for (unit, len) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)] {
+        if secs >= len {
+            s.push_str(&format!("{}{}", secs / len, unit));
+            secs %= len;
+        }
+    }
+    s
+}
+
+fn parse_timestamp(s: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Some(datetime);
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|datetime| datetime.fixed_offset())
+}
+
+fn parse_local_datetime_str(s: &str, format: &str) -> Result<DateTime<Utc>> {
+    let d = NaiveDateTime::parse_from_str(s, format)?;
+    let local_result = Utc.from_local_datetime(&d);
+    Ok(local_result.earliest().unwrap_or_else(|| {
+        local_result
+            .latest()
+            .unwrap_or_else(|| local_result.unwrap())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(0              => "0s"       ; "zero")]
+    #[test_case(45             => "45s"      ; "seconds")]
+    #[test_case(90 * 60        => "1h30m"    ; "hours and minutes")]
+    #[test_case(2 * 86400 + 61 => "2d1m1s"   ; "skips zero units")]
+    #[test_case(-600           => "-10m"     ; "negative")]
+    fn test_format_duration(secs: i64) -> String {
+        format_duration(chrono::Duration::seconds(secs))
+    }
+
+    #[test_case("2012"                => "2012-01-01T00:00:00+00:00" ; "y")]
+    #[test_case("2012-02"             => "2012-02-01T00:00:00+00:00" ; "ym")]
+    #[test_case("2012-02-02"          => "2012-02-02T00:00:00+00:00" ; "ymd")]
+    #[test_case("2012-02-02T02"       => "2012-02-02T02:00:00+00:00" ; "ymdh")]
+    #[test_case("2012-02-02T02:02"    => "2012-02-02T02:02:00+00:00" ; "ymdhm")]
+    #[test_case("2012-02-02T02:02:02" => "2012-02-02T02:02:02+00:00" ; "ymdhms")]
+    fn test_parse(s: &str) -> String {
+        parse(s).unwrap().to_rfc3339()
+    }
+
+    #[test_case("2020-01-02T03:04:05Z started"         => Some(("2020-01-02T03:04:05+00:00".to_owned(), "started")) ; "rfc3339")]
+    #[test_case("2020-01-02 03:04:05.123+01:00 a b"    => Some(("2020-01-02T03:04:05.123+01:00".to_owned(), "a b")) ; "space separated")]
+    #[test_case("2020-01-02T03:04:05+00:00"            => Some(("2020-01-02T03:04:05+00:00".to_owned(), ""))        ; "no message")]
+    #[test_case("started at 2020-01-02T03:04:05Z"      => None                                                       ; "not at the start")]
+    #[test_case("2020-01-02 hello"                     => None                                                       ; "date only")]
+    #[test_case(""                                     => None                                                       ; "empty")]
+    fn test_split_timestamp(line: &str) -> Option<(String, &str)> {
+        split_timestamp(line).map(|(datetime, rest)| (datetime.to_rfc3339(), rest))
+    }
+
+    #[test]
+    fn test_split_timestamp_local() {
+        let (datetime, rest) = split_timestamp("2020-01-02 03:04:05 hello").unwrap();
+        assert_eq!(datetime.naive_local().to_string(), "2020-01-02 03:04:05");
+        assert_eq!(rest, "hello");
+    }
+}
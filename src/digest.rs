@@ -0,0 +1,322 @@
+use super::{
+    crypt, date, entries::Entries, entry::Entry, error::Error, hook, review::Item, Result,
+};
+use chrono::prelude::*;
+use serde::Serialize;
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::process::Stdio;
+
+/// The template hmms digest renders with unless it's given another one.
+pub const DEFAULT_TEMPLATE: &str = "\
+# {{ strftime \"%A %-d %B %Y\" date }}
+
+{{#if entries}}
+{{#each entries}}
+- {{ strftime \"%H:%M\" datetime }} {{ title }}
+{{/each}}
+{{else}}
+Nothing was written.
+{{/if}}
+{{#each flashbacks}}
+
+## On this day in {{ strftime \"%Y\" date }}
+
+{{#each entries}}
+- {{ strftime \"%H:%M\" datetime }} {{ title }}
+{{/each}}
+{{/each}}
+{{#if pinned}}
+
+## Pinned
+
+{{#each pinned}}
+- {{ title }}
+{{/each}}
+{{/if}}
+";
+
+/// What was written on a day, along with what was written on the same day in
+/// previous years, to be rendered with a template. Every datetime is RFC3339,
+/// for the strftime helper.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Digest {
+    /// The start of the day.
+    date: String,
+
+    /// The entries written on the day.
+    entries: Vec<Item>,
+
+    /// The same day in previous years that have entries, most recent first.
+    flashbacks: Vec<Flashback>,
+
+    /// The entries that are pinned, in the order they were pinned.
+    pinned: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Flashback {
+    date: String,
+    years_ago: i32,
+    entries: Vec<Item>,
+}
+
+impl Digest {
+    /// Whether nothing was written on the day or on any of its flashbacks.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.flashbacks.is_empty()
+    }
+
+    /// Adds the entries that are pinned to the digest. They're shown however
+    /// long ago they were written, but don't stop the digest being empty.
+    pub fn set_pinned(&mut self, pinned: &[Entry]) {
+        self.pinned = pinned.iter().map(Item::new).collect();
+    }
+}
+
+/// Builds the digest for `day`, reading each year back to the first entry in
+/// `entries`. Encrypted messages are decrypted with `decrypt_command` if
+/// there is one. The 29th of February only has flashbacks in leap years.
+pub fn build<T: Seek + Read + BufRead>(
+    entries: &mut Entries<T>,
+    day: NaiveDate,
+    decrypt_command: Option<&str>,
+) -> Result<Digest> {
+    let items = read_day(entries, day, decrypt_command)?;
+
+    let first = match entries.at(0)? {
+        Some(first) => first.datetime().with_timezone(&Local).date_naive(),
+        None => day,
+    };
+
+    let mut flashbacks = Vec::new();
+    for years_ago in 1.. {
+        let year = day.year() - years_ago;
+        if year < first.year() {
+            break;
+        }
+        let then = match day.with_year(year) {
+            Some(then) => then,
+            None => continue,
+        };
+        let items = read_day(entries, then, decrypt_command)?;
+        if !items.is_empty() {
+            flashbacks.push(Flashback {
+                date: date::start_of_day(then).to_rfc3339(),
+                years_ago,
+                entries: items,
+            });
+        }
+    }
+
+    Ok(Digest {
+        date: date::start_of_day(day).to_rfc3339(),
+        entries: items,
+        flashbacks,
+        pinned: Vec::new(),
+    })
+}
+
+fn read_day<T: Seek + Read + BufRead>(
+    entries: &mut Entries<T>,
+    day: NaiveDate,
+    decrypt_command: Option<&str>,
+) -> Result<Vec<Item>> {
+    let start = date::start_of_day(day);
+    let end = date::start_of_day(day.succ_opt().unwrap_or(day));
+    let mut items = Vec::new();
+    for entry in entries.between(Some(&start), Some(&end))? {
+        let entry = entry?;
+        let entry = match decrypt_command {
+            Some(command) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                crypt::decrypt_entry(command, &entry)?
+            }
+            _ => entry,
+        };
+        items.push(Item::new(&entry));
+    }
+    Ok(items)
+}
+
+/// Builds an email containing `body`, ready to be piped to `sendmail -t`.
+pub fn email(to: &str, subject: &str, body: &str) -> String {
+    format!(
+        "To: {}\nSubject: {}\nMIME-Version: 1.0\nContent-Type: text/plain; charset=utf-8\n\n{}",
+        to, subject, body
+    )
+}
+
+/// Sends `email` by piping it to `command`, a shell command like
+/// `sendmail -t` that reads who to send it to from its headers.
+pub fn send(command: &str, email: &str) -> Result<()> {
+    let mut child = hook::shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Mail(format!("couldn't run `{}`: {}", command, e)))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    match stdin.write_all(email.as_bytes()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => {}
+    }
+    drop(stdin);
+
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(Error::Mail(format!(
+            "`{}` failed: {}",
+            command,
+            stderr.trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format;
+    use std::io::Cursor;
+    use tempfile::NamedTempFile;
+
+    fn journal(entries: &[(&str, &str)]) -> Entries<Cursor<Vec<u8>>> {
+        let mut buf = Vec::new();
+        for (datetime, message) in entries {
+            let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+            Entry::new(
+                Local.from_local_datetime(&datetime).unwrap().fixed_offset(),
+                message.to_string(),
+            )
+            .write(&mut buf)
+            .unwrap();
+        }
+        Entries::new(Cursor::new(buf))
+    }
+
+    // Items are only looked at by templates, so this goes through their JSON.
+    fn messages(items: &[Item]) -> Vec<String> {
+        items
+            .iter()
+            .map(|item| {
+                serde_json::to_value(item).unwrap()["message"]
+                    .as_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect()
+    }
+
+    fn day(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    fn sample() -> Entries<Cursor<Vec<u8>>> {
+        journal(&[
+            ("2021-05-13 08:00", "three years ago"),
+            ("2022-05-12 09:00", "the day before"),
+            ("2023-05-13 09:00", "last year"),
+            ("2023-05-13 22:00", "late last year"),
+            ("2024-05-12 23:59", "the night before"),
+            ("2024-05-13 09:00", "today\nwith more"),
+            ("2024-05-14 00:00", "tomorrow"),
+        ])
+    }
+
+    #[test]
+    fn test_build() {
+        let digest = build(&mut sample(), day("2024-05-13"), None).unwrap();
+        assert_eq!(messages(&digest.entries), vec!["today\nwith more"]);
+        assert_eq!(
+            digest
+                .flashbacks
+                .iter()
+                .map(|f| (f.years_ago, messages(&f.entries)))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, vec!["last year".to_owned(), "late last year".to_owned()]),
+                (3, vec!["three years ago".to_owned()]),
+            ]
+        );
+        assert!(!digest.is_empty());
+    }
+
+    #[test]
+    fn test_build_empty() {
+        let digest = build(&mut sample(), day("2024-06-01"), None).unwrap();
+        assert!(digest.is_empty());
+        let digest = build(&mut journal(&[]), day("2024-06-01"), None).unwrap();
+        assert!(digest.is_empty());
+    }
+
+    #[test]
+    fn test_build_leap_day() {
+        let mut entries = journal(&[
+            ("2020-02-29 09:00", "leap day"),
+            ("2023-02-28 09:00", "not a leap day"),
+        ]);
+        let digest = build(&mut entries, day("2024-02-29"), None).unwrap();
+        assert_eq!(digest.flashbacks.len(), 1);
+        assert_eq!(digest.flashbacks[0].years_ago, 4);
+    }
+
+    #[test]
+    fn test_default_template() {
+        let digest = build(&mut sample(), day("2024-05-13"), None).unwrap();
+        assert_eq!(
+            format::render(DEFAULT_TEMPLATE, &digest, false).unwrap(),
+            "\
+# Monday 13 May 2024
+
+- 09:00 today
+
+## On this day in 2023
+
+- 09:00 last year
+- 22:00 late last year
+
+## On this day in 2021
+
+- 08:00 three years ago
+"
+        );
+
+        let mut digest = build(&mut sample(), day("2024-06-01"), None).unwrap();
+        assert_eq!(
+            format::render(DEFAULT_TEMPLATE, &digest, false).unwrap(),
+            "# Saturday 1 June 2024\n\nNothing was written.\n"
+        );
+
+        digest.set_pinned(&[Entry::with_message("wifi password\nhunter2")]);
+        assert_eq!(
+            format::render(DEFAULT_TEMPLATE, &digest, false).unwrap(),
+            "# Saturday 1 June 2024\n\nNothing was written.\n\n## Pinned\n\n- wifi password\n"
+        );
+        assert!(digest.is_empty());
+    }
+
+    #[test]
+    fn test_email() {
+        assert_eq!(
+            email("me@example.com", "hmm digest", "hello\n"),
+            "To: me@example.com\nSubject: hmm digest\nMIME-Version: 1.0\nContent-Type: text/plain; charset=utf-8\n\nhello\n"
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_send() {
+        let out = NamedTempFile::new().unwrap();
+        let command = format!("cat > {}", out.path().display());
+        send(&command, "To: me@example.com\n\nhello\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(out.path()).unwrap(),
+            "To: me@example.com\n\nhello\n"
+        );
+
+        let err = send("echo nope >&2; exit 1", "hello").unwrap_err();
+        assert_eq!(err.kind(), "mail");
+        assert!(err.to_string().contains("nope"), "{}", err);
+    }
+}
@@ -0,0 +1,110 @@
+use super::Result;
+use chrono::prelude::*;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// An entry that was composed but couldn't be written to a journal, kept so
+/// that it can be written later rather than lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Draft {
+    path: PathBuf,
+    message: String,
+}
+
+impl Draft {
+    /// Where the draft is saved.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The draft's file name, which is how it's referred to on the command
+    /// line. Names start with the time the draft was saved, so they sort in
+    /// the order they were written.
+    pub fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Deletes the draft, once it's been written or isn't wanted any more.
+    pub fn remove(&self) -> Result<()> {
+        Ok(fs::remove_file(&self.path)?)
+    }
+}
+
+/// The directory drafts are kept in, ~/.local/share/hmm/drafts on Linux,
+/// ~/Library/Application Support/hmm/drafts on macOS and
+/// %APPDATA%\hmm\drafts on Windows.
+pub fn dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("hmm").join("drafts"))
+}
+
+/// Saves `message` as a new draft in `dir`, creating it if needed.
+pub fn save(dir: &Path, message: &str) -> Result<Draft> {
+    fs::create_dir_all(dir)?;
+    let prefix = format!("{}-", Local::now().format("%Y-%m-%d-%H%M%S"));
+    let mut f = tempfile::Builder::new()
+        .prefix(&prefix)
+        .suffix(".txt")
+        .tempfile_in(dir)?;
+    f.write_all(message.as_bytes())?;
+    let (_, path) = f.keep().map_err(|e| e.error)?;
+
+    Ok(Draft {
+        path,
+        message: message.to_owned(),
+    })
+}
+
+/// All of the drafts in `dir`, oldest first.
+pub fn list(dir: &Path) -> Result<Vec<Draft>> {
+    let files = match fs::read_dir(dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut drafts = Vec::new();
+    for file in files {
+        let path = file?.path();
+        if path.extension().is_some_and(|ext| ext == "txt") {
+            let message = fs::read_to_string(&path)?;
+            drafts.push(Draft { path, message });
+        }
+    }
+    drafts.sort_by(|a, b| a.name().cmp(b.name()));
+    Ok(drafts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_save_and_list() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("drafts");
+        assert!(list(&dir).unwrap().is_empty());
+
+        let first = save(&dir, "a long entry\nwith lines").unwrap();
+        let second = save(&dir, "another").unwrap();
+        fs::write(dir.join("notes.md"), "not a draft").unwrap();
+
+        assert!(first.name().ends_with(".txt"));
+        assert_ne!(first.name(), second.name());
+        let drafts = list(&dir).unwrap();
+        assert_eq!(drafts.len(), 2);
+        assert!(drafts.contains(&first));
+        assert!(drafts.contains(&second));
+
+        first.remove().unwrap();
+        assert_eq!(list(&dir).unwrap(), vec![second]);
+    }
+}
@@ -1,12 +1,39 @@
-use super::{entry::Entry, seek, Result};
+use super::{
+    entry::{self, Entry, EntryRef},
+    error::Error,
+    seek, sorted_file, Result,
+};
 use chrono::prelude::*;
-use rand::distributions::{Distribution, Uniform};
+use rand::{
+    distributions::{Distribution, Uniform},
+    Rng,
+};
 use std::convert::TryInto;
 use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::iter::FusedIterator;
+
+#[cfg(feature = "fs")]
+use {
+    memmap2::Mmap,
+    std::{fs::File, io::Cursor, path::Path},
+};
 
 pub struct Entries<T: Seek + Read + BufRead> {
     f: T,
     buf: String,
+
+    // State used when iterating. `back` is the byte offset of the start of the
+    // last entry returned by next_back, and `done` is set when either end of
+    // the iterator has returned None.
+    back: Option<u64>,
+    done: bool,
+
+    // The line number of the line the cursor is at the start of, if it's
+    // known. It's worked out the first time a malformed line needs reporting
+    // and kept up to date as lines are read, so that a file with lots of
+    // malformed lines isn't counted from the start for each of them. Moving the
+    // cursor anywhere else forgets it.
+    line: Option<u64>,
 }
 
 impl<T: Seek + Read + BufRead> Entries<T> {
@@ -14,22 +41,122 @@ impl<T: Seek + Read + BufRead> Entries<T> {
         Entries {
             f,
             buf: String::with_capacity(4096),
+            back: None,
+            done: false,
+            line: None,
         }
     }
 
-    pub fn len(&mut self) -> Result<u64> {
+    // Moving the cursor explicitly starts a new iteration from wherever the
+    // cursor ends up.
+    fn reset_iteration(&mut self) {
+        self.back = None;
+        self.done = false;
+        self.line = None;
+    }
+
+    /// Returns the length of the underlying file in bytes.
+    pub fn len_bytes(&mut self) -> Result<u64> {
         let prev = self.f.stream_position()?;
         let len = self.f.seek(SeekFrom::End(0))?;
         self.f.seek(SeekFrom::Start(prev))?;
         Ok(len)
     }
 
+    #[deprecated(note = "returns a length in bytes, use len_bytes or len_entries instead")]
+    pub fn len(&mut self) -> Result<u64> {
+        self.len_bytes()
+    }
+
     pub fn is_empty(&mut self) -> Result<bool> {
-        Ok(self.len()? == 0)
+        Ok(self.len_bytes()? == 0)
+    }
+
+    /// Returns the number of entries in the underlying file. This counts lines
+    /// rather than parsing entries, but it does have to read the whole file.
+    pub fn len_entries(&mut self) -> Result<u64> {
+        let prev = self.position()?;
+        let len = self.count_between(None, None)?;
+        self.f.seek(SeekFrom::Start(prev))?;
+        Ok(len)
+    }
+
+    /// Returns the byte offset of the cursor in the underlying file.
+    pub fn position(&mut self) -> Result<u64> {
+        Ok(self.f.stream_position()?)
+    }
+
+    /// Moves the cursor back to the start of the file, so that next_entry
+    /// reads the first entry.
+    pub fn rewind(&mut self) -> Result<()> {
+        self.reset_iteration();
+        self.f.seek(SeekFrom::Start(0))?;
+        self.line = Some(1);
+        Ok(())
+    }
+
+    /// Returns the entry at index `n`, counting from 0, or None if there are
+    /// fewer than `n + 1` entries. The cursor is left after the returned entry,
+    /// so calling next_entry afterwards will read entry `n + 1`.
+    pub fn nth_entry(&mut self, n: u64) -> Result<Option<Entry>> {
+        self.reset_iteration();
+        self.f.seek(SeekFrom::Start(0))?;
+        for _ in 0..n {
+            if self.seek_to_next()?.is_none() {
+                return Ok(None);
+            }
+        }
+        self.next_entry()
+    }
+
+    /// Returns the byte offset of the first entry that is on or after the given
+    /// date, or the length of the file if there is no such entry.
+    pub(crate) fn offset_of_first(&mut self, date: &DateTime<FixedOffset>) -> Result<u64> {
+        self.seek_to_first(date)?;
+        self.position()
+    }
+
+    /// Counts the entries between the `start` date, inclusive, and the `end`
+    /// date, exclusive. Either bound can be left off to count from the start or
+    /// to the end of the file.
+    ///
+    /// This finds the byte offsets of both bounds with a binary search and
+    /// counts the lines between them, so no entries are parsed. The position of
+    /// the cursor after calling this method is unspecified.
+    pub fn count_between(
+        &mut self,
+        start: Option<&DateTime<FixedOffset>>,
+        end: Option<&DateTime<FixedOffset>>,
+    ) -> Result<u64> {
+        let len = self.len_bytes()?;
+        let start_offset = match start {
+            Some(date) => self.offset_of_first(date)?,
+            None => 0,
+        };
+        let end_offset = match end {
+            Some(date) => self.offset_of_first(date)?,
+            None => len,
+        };
+
+        self.line = None;
+        if start_offset >= end_offset {
+            return Ok(0);
+        }
+
+        self.f.seek(SeekFrom::Start(start_offset))?;
+        let (mut count, last) = seek::count_newlines(&mut self.f, end_offset - start_offset)?;
+
+        // The last line of a file doesn't always end in a newline.
+        if last != Some(0x0a) {
+            count += 1;
+        }
+
+        Ok(count)
     }
 
     pub fn at(&mut self, pos: u64) -> Result<Option<Entry>> {
-        if pos > self.len()? {
+        self.reset_iteration();
+        if pos > self.len_bytes()? {
             return Ok(None);
         }
 
@@ -38,21 +165,32 @@ impl<T: Seek + Read + BufRead> Entries<T> {
         self.next_entry()
     }
 
+    /// Moves the cursor past the end of the file, so that prev_entry returns
+    /// the last entry.
     pub fn seek_to_end(&mut self) -> Result<()> {
-        let len = self.len()?;
-        self.at(len)?;
+        self.reset_iteration();
+        self.f.seek(SeekFrom::End(1))?;
         Ok(())
     }
 
     pub fn seek_to_next(&mut self) -> Result<Option<u64>> {
+        self.reset_iteration();
         seek::start_of_next_line(&mut self.f)
     }
 
     pub fn seek_to_prev(&mut self) -> Result<Option<u64>> {
+        self.reset_iteration();
         seek::start_of_prev_line(&mut self.f)
     }
 
     pub fn next_entry(&mut self) -> Result<Option<Entry>> {
+        Ok(self.next_entry_ref()?.map(|entry| entry.to_entry()))
+    }
+
+    /// Reads the next entry like next_entry, but borrows its message from the
+    /// line that was read instead of copying it. This is cheaper when most
+    /// entries are only looked at and then thrown away.
+    pub fn next_entry_ref(&mut self) -> Result<Option<EntryRef<'_>>> {
         self.buf.clear();
         self.f.read_line(&mut self.buf)?;
 
@@ -67,117 +205,339 @@ impl<T: Seek + Read + BufRead> Entries<T> {
             return Ok(None);
         }
 
-        let row = quick_csv::Csv::from_reader(self.buf.as_bytes())
-            .next()
-            .unwrap()?;
-        Ok(Some(row.try_into()?))
+        let line = self.line.take();
+        match EntryRef::parse(&self.buf) {
+            Ok(entry) => {
+                self.line = line.map(|line| line + 1);
+                Ok(Some(entry))
+            }
+            Err(e) => {
+                // We've just read the offending line, so it starts the length of
+                // the line before the cursor.
+                let byte = self.f.stream_position()? - self.buf.len() as u64;
+                let line = match line {
+                    Some(line) => line,
+                    None => seek::line_number_at(&mut self.f, byte)?,
+                };
+                self.line = Some(line + 1);
+                Err(e.at_line(Some(line), byte, &self.buf))
+            }
+        }
     }
 
+    #[cfg(feature = "fs")]
     pub fn rand_entry(&mut self) -> Result<Option<Entry>> {
-        let mut rng = rand::thread_rng();
-        let range = Uniform::new(0, self.len()?);
-        self.at(range.sample(&mut rng))
+        self.rand_entry_with(&mut rand::thread_rng())
     }
 
-    pub fn prev_entry(&mut self) -> Result<Option<Entry>> {
-        // This seek takes us to the start of the line that was just read. It
-        // will sometimes be None if we're already at the start of the file but
-        // that's fine. We don't do this seek if we've previously read past the
-        // end of the file, so that when we do read past the end of the file we
-        // can again go back and read the last line.
-        if self.f.stream_position()? <= self.len()? {
-            self.seek_to_prev()?;
+    /// Like `rand_entry`, but picks the entry with `rng`. Longer entries are
+    /// more likely to be picked, as a random byte of the file is chosen and the
+    /// entry it's in is returned.
+    pub fn rand_entry_with<R: Rng>(&mut self, rng: &mut R) -> Result<Option<Entry>> {
+        let len = self.len_bytes()?;
+        if len == 0 {
+            return Ok(None);
         }
+        let range = Uniform::new(0, len);
+        self.at(range.sample(rng))
+    }
 
-        // This seek takes us to the actual previous entry. If this one returns None
-        // it means we're trying to go past the start of the file, and there is no
-        // previous entry.
-        if self.seek_to_prev()?.is_none() {
+    /// Returns the entry before the one that was just read, or the last entry
+    /// if the cursor is past the end of the file.
+    pub fn prev_entry(&mut self) -> Result<Option<Entry>> {
+        self.reset_iteration();
+        let pos = self.position()?;
+        let len = self.len_bytes()?;
+
+        // The start of the line that was just read is where the entry we want
+        // ends. If we've read past the end of the file, it's the end of the
+        // file, so that we go back and read the last line. Working this out
+        // from the byte before the cursor means the last line is read whether
+        // or not the file ends in a newline.
+        let end = if pos > len {
+            len
+        } else if pos == 0 {
+            return Ok(None);
+        } else {
+            self.f.seek(SeekFrom::Start(pos - 1))?;
+            seek::start_of_current_line(&mut self.f)?
+        };
+
+        // If the entry we want ends at the start of the file, we're trying to
+        // go past the start of the file and there is no previous entry.
+        if end == 0 {
+            self.f.seek(SeekFrom::Start(0))?;
             return Ok(None);
         }
 
+        self.f.seek(SeekFrom::Start(end - 1))?;
+        seek::start_of_current_line(&mut self.f)?;
         self.next_entry()
     }
 
+    /// Moves the cursor to the first entry that is on or after `date`, or to the
+    /// end of the file if there isn't one.
     pub fn seek_to_first(&mut self, date: &chrono::DateTime<FixedOffset>) -> Result<()> {
-        let file_size = self.len()?;
-        let mut end = file_size;
-        let mut start = self.f.seek(SeekFrom::Start(0))?;
-
-        while start < end {
-            let cur = start + (end - start) / 2;
-
-            let entry = match self.at(cur)? {
-                Some(entry) => entry,
-                // If we get none back from at() it means we've tried to seek past
-                // the end of the file. We break out of the loop in this case and
-                // ultimately return to the caller with the file cursor at end of
-                // file. This allows people to seek backwards from the end if they
-                // want to.
-                None => break,
-            };
+        self.reset_iteration();
+        let probe = Probe::new(date);
+        sorted_file::find_first_by(&mut self.f, |line| probe.is_on_or_after(line))?;
+        Ok(())
+    }
 
-            if entry.datetime() >= date {
-                if cur == 0 {
-                    break;
-                }
-                end = cur - 1;
-            } else {
-                if cur == file_size {
-                    break;
+    /// Returns an iterator over the entries between the `start` date, inclusive,
+    /// and the `end` date, exclusive. If `start` is None iteration begins at
+    /// the current cursor position, and if `end` is None it continues to the
+    /// end of the file.
+    pub fn between(
+        &mut self,
+        start: Option<&DateTime<FixedOffset>>,
+        end: Option<&DateTime<FixedOffset>>,
+    ) -> Result<Between<'_, T>> {
+        match start {
+            Some(date) => self.seek_to_first(date)?,
+            None => self.reset_iteration(),
+        }
+
+        Ok(Between {
+            entries: self,
+            end: end.cloned(),
+            done: false,
+        })
+    }
+
+    // Reads the entry before the back cursor, without moving the front cursor.
+    // Returns None when the back cursor meets the front cursor.
+    fn next_back_entry(&mut self) -> Result<Option<Entry>> {
+        let front = self.position()?;
+        let back = match self.back {
+            Some(back) => back,
+            None => self.len_bytes()?,
+        };
+
+        if back == 0 || back <= front {
+            return Ok(None);
+        }
+
+        // The byte before the back cursor is the end of the line we want to
+        // read, so finding the start of the line it's on gets us to the entry.
+        self.f.seek(SeekFrom::Start(back - 1))?;
+        let start = seek::start_of_current_line(&mut self.f)?;
+        if start < front {
+            self.f.seek(SeekFrom::Start(front))?;
+            return Ok(None);
+        }
+        self.back = Some(start);
+
+        // Reading the entry moves the cursor away from the front and back
+        // again, so the front's line number still holds afterwards.
+        let line = self.line.take();
+        let result = self.next_entry();
+        self.f.seek(SeekFrom::Start(front))?;
+        self.line = line;
+        result
+    }
+}
+
+fn parse_line(line: &str) -> Result<Entry> {
+    // A blank line has no rows at all.
+    let row = quick_csv::Csv::from_reader(entry::trim_line(line).as_bytes())
+        .next()
+        .ok_or_else(Error::malformed)??;
+    row.try_into()
+}
+
+// A date to binary search for. Parsing every line the search looks at in to an
+// Entry means parsing its message as JSON too, so where we can we compare the
+// timestamp at the start of the line as bytes instead. That only works when
+// the line was written with the same UTC offset as the date, which is usually
+// the case for journals written in one place, and otherwise we parse.
+pub(crate) struct Probe<'a> {
+    date: &'a DateTime<FixedOffset>,
+
+    // The date as it appears in a .hmm file, split in to the local time with
+    // the fraction of a second trimmed of trailing zeros, and the UTC offset.
+    // None if the date can't be written as a plain RFC3339 timestamp.
+    raw: Option<(String, String)>,
+}
+
+impl<'a> Probe<'a> {
+    pub(crate) fn new(date: &'a DateTime<FixedOffset>) -> Self {
+        let year = date.year();
+        let raw = if (0..=9999).contains(&year) && date.offset().local_minus_utc() % 60 == 0 {
+            let local = date.format("%Y-%m-%dT%H:%M:%S%.f").to_string();
+            let local = match local.split_once('.') {
+                Some((secs, fraction)) if fraction.trim_end_matches('0').is_empty() => {
+                    secs.to_owned()
                 }
-                start = cur + 1;
+                Some(_) => local.trim_end_matches('0').to_owned(),
+                None => local,
+            };
+            Some((local, date.format("%:z").to_string()))
+        } else {
+            None
+        };
+        Probe { date, raw }
+    }
+
+    // Whether the entry on `line` is on or after the date.
+    pub(crate) fn is_on_or_after(&self, line: &str) -> Result<bool> {
+        match self.compare_raw(line) {
+            Some(ge) => Ok(ge),
+            None => Ok(parse_line(line)?.datetime() >= self.date),
+        }
+    }
+
+    // Compares the timestamp at the start of `line` with the date as bytes, or
+    // returns None if they can't be compared that way. Once the fraction of a
+    // second on the date has no trailing zeros, comparing two local times
+    // with the same offset as bytes gives the same answer as comparing them
+    // as times.
+    fn compare_raw(&self, line: &str) -> Option<bool> {
+        let (local, offset) = self.raw.as_ref()?;
+        let line = entry::trim_line(line);
+        let timestamp = &line[..line.find(',')?];
+        let line_local = timestamp.strip_suffix(offset.as_str())?;
+        if !is_plain_local_time(line_local) {
+            return None;
+        }
+        Some(line_local >= local.as_str())
+    }
+}
+
+// Whether `s` looks like "2020-01-01T00:01:00", optionally followed by a
+// fraction of a second.
+fn is_plain_local_time(s: &str) -> bool {
+    const PATTERN: &[u8] = b"dddd-dd-ddTdd:dd:dd";
+    let b = s.as_bytes();
+    if b.len() < PATTERN.len() {
+        return false;
+    }
+    let (datetime, fraction) = b.split_at(PATTERN.len());
+    let datetime_ok = PATTERN.iter().zip(datetime).all(|(p, c)| match p {
+        b'd' => c.is_ascii_digit(),
+        p => p == c,
+    });
+    let fraction_ok = match fraction.split_first() {
+        None => true,
+        Some((b'.', digits)) => !digits.is_empty() && digits.iter().all(u8::is_ascii_digit),
+        Some(_) => false,
+    };
+    datetime_ok && fraction_ok
+}
+
+#[cfg(feature = "fs")]
+impl Entries<Cursor<Mmap>> {
+    /// Opens the .hmm file at the given path as a memory map instead of going
+    /// through buffered reads. Seeking backwards through a file, which happens
+    /// a lot in reverse traversal and binary search, becomes pointer arithmetic
+    /// rather than a syscall per byte.
+    pub fn from_mmap<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let f = File::open(path)?;
+
+        // Mapping a file is unsafe because the contents can change underneath
+        // us if another process modifies it. hmm never shortens or rewrites a
+        // .hmm file in place: it either appends to it, which won't affect the
+        // part we've mapped, or writes a new copy and renames it over the old
+        // one, which leaves the old one mapped until we're done with it. That
+        // can't be promised for anything else, like editing the file by hand
+        // while it's being read, which can end a read with SIGBUS.
+        let mmap = unsafe { Mmap::map(&f)? };
+        Ok(Entries::new(Cursor::new(mmap)))
+    }
+}
+
+// Errors parsing an entry don't stop iteration, as the malformed line has
+// already been consumed and the next call will move on to the line after it.
+// Errors reading the underlying file do stop iteration.
+fn end_on_io_error(done: &mut bool, result: Result<Option<Entry>>) -> Option<Result<Entry>> {
+    match result {
+        Ok(Some(entry)) => Some(Ok(entry)),
+        Ok(None) => {
+            *done = true;
+            None
+        }
+        Err(e) => {
+            if let Error::Io(_) = e {
+                *done = true;
             }
+            Some(Err(e))
         }
+    }
+}
+
+impl<T: Seek + Read + BufRead> Iterator for Entries<T> {
+    type Item = Result<Entry>;
 
-        // When we exit the binary search loop we know that we're in one of the following
-        // states:
-        //
-        //   - We're at the very start of the file.
-        //   - We're at or past the end of the file.
-        //   - We're somewhere in the middle, potentially on the row before the row we
-        //     want to return.
-        //
-        // We need to navigate to the line that is exactly after the line before us that
-        // is less than the given time.
-
-        // If we're at the end of the file, it means that there are no lines in the file
-        // that can be less than the given date, so we return with the file cursor at the
-        // end of the file.
-        if end >= file_size {
-            return Ok(());
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
 
-        // We have to move forward one line at first, as we could have exited the binary
-        // search loop on the entry before the one that we need to return.
-        self.next_entry()?;
-
-        loop {
-            match self.prev_entry()? {
-                None => break,
-                Some(entry) => {
-                    if entry.datetime() < date {
-                        break;
-                    }
+        if let Some(back) = self.back {
+            match self.position() {
+                Ok(pos) if pos >= back => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
                 }
             }
         }
 
-        Ok(())
+        let result = self.next_entry();
+        end_on_io_error(&mut self.done, result)
     }
 }
 
-impl<T: Seek + Read + BufRead> Iterator for Entries<T> {
+impl<T: Seek + Read + BufRead> DoubleEndedIterator for Entries<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.next_back_entry();
+        end_on_io_error(&mut self.done, result)
+    }
+}
+
+impl<T: Seek + Read + BufRead> FusedIterator for Entries<T> {}
+
+/// An iterator over the entries in a date range, created by Entries::between.
+pub struct Between<'a, T: Seek + Read + BufRead> {
+    entries: &'a mut Entries<T>,
+    end: Option<DateTime<FixedOffset>>,
+    done: bool,
+}
+
+impl<'a, T: Seek + Read + BufRead> Iterator for Between<'a, T> {
     type Item = Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.next_entry() {
-            Ok(opt) => opt.map(Ok),
-            Err(e) => Some(Err(e)),
+        if self.done {
+            return None;
+        }
+
+        match self.entries.next() {
+            // The file is sorted, so the first entry on or after the end date
+            // means there are no more entries in the range.
+            Some(Ok(entry)) if self.end.is_some_and(|end| entry.datetime() >= &end) => {
+                self.done = true;
+                None
+            }
+            None => {
+                self.done = true;
+                None
+            }
+            other => other,
         }
     }
 }
 
+impl<'a, T: Seek + Read + BufRead> FusedIterator for Between<'a, T> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -191,6 +551,16 @@ mod tests {
 2020-04-12T23:28:45.726598931+00:00,\"\"\"4\"\"\"
 2020-05-12T23:28:48.495151445+00:00,\"\"\"5\"\"\"
 2020-06-13T10:12:53.353050231+00:00,\"\"\"6\"\"\"
+";
+
+    // The same entries as TESTDATA, but as they might look after being edited
+    // on Windows: a byte order mark at the start and a mix of line endings.
+    const MIXED_TESTDATA: &str = "\u{feff}2020-01-01T00:01:00.899849209+00:00,\"\"\"1\"\"\"\r
+2020-02-12T23:08:40.987613062+00:00,\"\"\"2\"\"\"\r
+2020-03-12T00:00:00.000000000+00:00,\"\"\"3\"\"\"
+2020-04-12T23:28:45.726598931+00:00,\"\"\"4\"\"\"\r
+2020-05-12T23:28:48.495151445+00:00,\"\"\"5\"\"\"
+2020-06-13T10:12:53.353050231+00:00,\"\"\"6\"\"\"\r
 ";
 
     // Clippy isn't a big fan of mathematics that can be represented simpler
@@ -254,6 +624,143 @@ mod tests {
             .map(|e| e.message().to_owned())
     }
 
+    #[test_case(None,                              None                              => 6 ; "whole file")]
+    #[test_case(Some("2020-02-12T23:08:40.987613062+00:00"), None                    => 5 ; "start only")]
+    #[test_case(None,                              Some("2020-03-12T00:00:00+00:00") => 2 ; "end only")]
+    #[test_case(Some("2020-02-01T00:00:00+00:00"), Some("2020-05-01T00:00:00+00:00") => 3 ; "start and end")]
+    #[test_case(Some("2021-01-01T00:00:00+00:00"), None                              => 0 ; "start past end of file")]
+    #[test_case(None,                              Some("2000-01-01T00:00:00+00:00") => 0 ; "end before start of file")]
+    #[test_case(Some("2020-05-01T00:00:00+00:00"), Some("2020-02-01T00:00:00+00:00") => 0 ; "start after end")]
+    fn test_count_between(start: Option<&str>, end: Option<&str>) -> u64 {
+        let start = start.map(|s| DateTime::parse_from_rfc3339(s).unwrap());
+        let end = end.map(|s| DateTime::parse_from_rfc3339(s).unwrap());
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        Entries::new(r)
+            .count_between(start.as_ref(), end.as_ref())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_count_between_no_trailing_newline() {
+        let r = Cursor::new(Vec::from(TESTDATA.trim_end().as_bytes()));
+        assert_eq!(Entries::new(r).count_between(None, None).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_count_between_empty_file() {
+        let r = Cursor::new(Vec::new());
+        assert_eq!(Entries::new(r).count_between(None, None).unwrap(), 0);
+    }
+
+    #[test_case(0 => Some("1".to_owned()))]
+    #[test_case(1 => Some("2".to_owned()))]
+    #[test_case(5 => Some("6".to_owned()))]
+    #[test_case(6 => None)]
+    #[test_case(100 => None)]
+    fn test_nth_entry(n: u64) -> Option<String> {
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        Entries::new(r)
+            .nth_entry(n)
+            .unwrap()
+            .map(|e| e.message().to_owned())
+    }
+
+    #[test]
+    fn test_paginating_entries() -> Result<()> {
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        let mut entries = Entries::new(r);
+
+        assert_eq!(entries.len_entries()?, 6);
+        assert_eq!(entries.len_bytes()?, 264);
+
+        assert_eq!(entries.nth_entry(2)?.unwrap().message(), "3");
+        assert_eq!(entries.position()?, 132);
+        assert_eq!(entries.next_entry()?.unwrap().message(), "4");
+
+        // Counting entries shouldn't move the cursor.
+        assert_eq!(entries.len_entries()?, 6);
+        assert_eq!(entries.next_entry()?.unwrap().message(), "5");
+        Ok(())
+    }
+
+    // Checks seek_to_first and count_between against reading every entry, over
+    // random journals with repeated times and fractions of a second. Some
+    // journals mix UTC offsets, so both comparing timestamps as bytes and
+    // parsing them get tested.
+    #[test]
+    fn test_seek_to_first_random_journals() -> Result<()> {
+        use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let base = DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap();
+        let offsets = [0, 3600, -5 * 3600 - 1800];
+        for _ in 0..300 {
+            let journal_offset = *offsets.choose(&mut rng).unwrap();
+            let mixed = rng.gen_bool(0.3);
+            let mut buf = Vec::new();
+            let mut datetime = base;
+            let mut written = Vec::new();
+            for i in 0..rng.gen_range(0..30) {
+                datetime += chrono::Duration::milliseconds(rng.gen_range(0..3) * 250);
+                let offset = match mixed {
+                    true => *offsets.choose(&mut rng).unwrap(),
+                    false => journal_offset,
+                };
+                let offset = FixedOffset::east_opt(offset).unwrap();
+                let entry = Entry::new(datetime.with_timezone(&offset), i.to_string());
+                entry.write(&mut buf)?;
+                written.push(entry);
+            }
+
+            let offset = FixedOffset::east_opt(*offsets.choose(&mut rng).unwrap()).unwrap();
+            let date = (base + chrono::Duration::milliseconds(rng.gen_range(-1..130) * 125))
+                .with_timezone(&offset);
+            let expected = written.iter().position(|e| e.datetime() >= &date);
+
+            let mut entries = Entries::new(Cursor::new(buf));
+            entries.seek_to_first(&date)?;
+            assert_eq!(
+                entries.next_entry()?.map(|e| e.message().to_owned()),
+                expected.map(|i| written[i].message().to_owned()),
+                "seeking to {}",
+                date
+            );
+            assert_eq!(
+                entries.count_between(Some(&date), None)?,
+                (written.len() - expected.unwrap_or(written.len())) as u64
+            );
+        }
+        Ok(())
+    }
+
+    #[test_case("2020-01-01T00:00:00+00:00",     "2020-01-01T00:00:00+00:00,\"\"" => Some(true)  ; "equal")]
+    #[test_case("2020-01-01T00:00:00.5+00:00",   "2020-01-01T00:00:00.500+00:00,\"\"" => Some(true)  ; "trailing zeros")]
+    #[test_case("2020-01-01T00:00:00.500+00:00", "2020-01-01T00:00:00.5+00:00,\"\"" => Some(true)  ; "trailing zeros on date")]
+    #[test_case("2020-01-01T00:00:00.5+00:00",   "2020-01-01T00:00:00.49+00:00,\"\"" => Some(false) ; "shorter fraction")]
+    #[test_case("2020-01-01T00:00:00.5+00:00",   "2020-01-01T00:00:00+00:00,\"\"" => Some(false) ; "no fraction")]
+    #[test_case("2020-01-01T00:00:00+00:00",     "2020-01-01T00:00:00.001+00:00,\"\"" => Some(true)  ; "fraction")]
+    #[test_case("2020-01-01T00:00:00+00:00",     "2019-12-31T23:00:00-01:00,\"\"" => None        ; "different offset")]
+    #[test_case("2020-01-01T00:00:00+00:00",     "\u{feff}2020-01-02T00:00:00+00:00,\"\"" => Some(true) ; "byte order mark")]
+    #[test_case("2020-01-01T00:00:00+00:00",     "2020-01-0xT00:00:00+00:00,\"\"" => None        ; "not a timestamp")]
+    #[test_case("2020-01-01T00:00:00+00:00",     "2020-01-01T00:00:00.+00:00,\"\"" => None       ; "empty fraction")]
+    #[test_case("2020-01-01T00:00:00+00:00",     "no comma" => None                                ; "no comma")]
+    fn test_probe_compare_raw(date: &str, line: &str) -> Option<bool> {
+        let date = DateTime::parse_from_rfc3339(date).unwrap();
+        Probe::new(&date).compare_raw(line)
+    }
+
+    #[test]
+    fn test_next_entry_ref() -> Result<()> {
+        let mut entries = Entries::new(Cursor::new(Vec::from(MIXED_TESTDATA.as_bytes())));
+        let mut messages = Vec::new();
+        while let Some(entry) = entries.next_entry_ref()? {
+            messages.push(entry.message().to_owned());
+        }
+        assert_eq!(messages, vec!["1", "2", "3", "4", "5", "6"]);
+        assert!(entries.prev_entry()?.is_some_and(|e| e.message() == "6"));
+        Ok(())
+    }
+
     #[test]
     fn test_seek_to_first_single_entry() {
         let date = DateTime::parse_from_rfc3339("2021-04-02T00:00:00Z").unwrap();
@@ -270,6 +777,19 @@ mod tests {
         assert_eq!(message, Some("Hello world".to_string()));
     }
 
+    #[test]
+    fn test_seek_to_first_blank_line() {
+        // Dates in another offset can't be compared as bytes, so the search
+        // has to parse the blank line it lands on.
+        let date = DateTime::parse_from_rfc3339("2020-03-12T01:00:00+01:00").unwrap();
+        let s = TESTDATA.replace("\n2020-03-12", "\n\n2020-03-12");
+        let mut entries = Entries::new(Cursor::new(Vec::from(s.as_bytes())));
+        match entries.seek_to_first(&date) {
+            Err(Error::Malformed { line, .. }) => assert_eq!(line, Some(3)),
+            other => panic!("expected a malformed entry, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_navigating_entries() -> Result<()> {
         let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
@@ -311,6 +831,63 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_prev_entry_reads_every_entry() -> Result<()> {
+        for s in &[
+            TESTDATA,
+            TESTDATA.trim_end(),
+            &TESTDATA.replace('\n', "\r\n"),
+        ] {
+            let mut entries = Entries::new(Cursor::new(Vec::from(s.as_bytes())));
+            entries.seek_to_end()?;
+            let mut messages = Vec::new();
+            while let Some(entry) = entries.prev_entry()? {
+                messages.push(entry.message().to_owned());
+            }
+            assert_eq!(messages, vec!["6", "5", "4", "3", "2", "1"], "{:?}", s);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_prev_entry_after_last_line_without_newline() -> Result<()> {
+        let s = TESTDATA.trim_end();
+        let mut entries = Entries::new(Cursor::new(Vec::from(s.as_bytes())));
+        while entries.next_entry()?.is_some_and(|e| e.message() != "6") {}
+        assert_eq!(entries.prev_entry()?.unwrap().message(), "5");
+        assert!(entries.next_entry()?.is_some_and(|e| e.message() == "6"));
+        assert!(entries.next_entry()?.is_none());
+        assert_eq!(entries.prev_entry()?.unwrap().message(), "6");
+        Ok(())
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_from_mmap() -> Result<()> {
+        use std::io::Write;
+
+        let mut f = tempfile::NamedTempFile::new()?;
+        f.write_all(TESTDATA.as_bytes())?;
+        let mut entries = Entries::from_mmap(f.path())?;
+
+        assert_eq!(entries.next_entry()?.unwrap().message(), "1");
+        entries.seek_to_end()?;
+        assert_eq!(entries.prev_entry()?.unwrap().message(), "6");
+        entries.seek_to_first(&DateTime::parse_from_rfc3339("2020-03-01T00:00:00Z").unwrap())?;
+        assert_eq!(entries.next_entry()?.unwrap().message(), "3");
+        Ok(())
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_from_mmap_empty_file() -> Result<()> {
+        let f = tempfile::NamedTempFile::new()?;
+        let mut entries = Entries::from_mmap(f.path())?;
+        assert!(entries.is_empty()?);
+        assert!(entries.next_entry()?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_iterator() {
         let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
@@ -324,4 +901,264 @@ mod tests {
         assert_eq!(entries.next().unwrap().unwrap().message(), "6");
         assert!(entries.next().is_none());
     }
+
+    fn messages(it: impl Iterator<Item = Result<Entry>>) -> Vec<String> {
+        it.map(|e| e.unwrap().message().to_owned()).collect()
+    }
+
+    #[test_case(None,                              None                              => vec!["1", "2", "3", "4", "5", "6"] ; "unbounded")]
+    #[test_case(Some("2020-02-12T23:08:40.987613062+00:00"), None                    => vec!["2", "3", "4", "5", "6"] ; "start only")]
+    #[test_case(None,                              Some("2020-03-12T00:00:00+00:00") => vec!["1", "2"] ; "end is exclusive")]
+    #[test_case(Some("2020-02-01T00:00:00+00:00"), Some("2020-05-01T00:00:00+00:00") => vec!["2", "3", "4"] ; "start and end")]
+    #[test_case(Some("2021-01-01T00:00:00+00:00"), None                              => Vec::<String>::new() ; "start past end of file")]
+    #[test_case(Some("2020-05-01T00:00:00+00:00"), Some("2020-02-01T00:00:00+00:00") => Vec::<String>::new() ; "start after end")]
+    fn test_between(start: Option<&str>, end: Option<&str>) -> Vec<String> {
+        let start = start.map(|s| DateTime::parse_from_rfc3339(s).unwrap());
+        let end = end.map(|s| DateTime::parse_from_rfc3339(s).unwrap());
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        let mut entries = Entries::new(r);
+        let between = entries.between(start.as_ref(), end.as_ref()).unwrap();
+        messages(between)
+    }
+
+    #[test]
+    fn test_between_from_cursor() -> Result<()> {
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        let mut entries = Entries::new(r);
+        entries.nth_entry(3)?;
+        let end = DateTime::parse_from_rfc3339("2020-06-01T00:00:00Z").unwrap();
+        assert_eq!(messages(entries.between(None, Some(&end))?), vec!["5"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reverse_iterator() {
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        assert_eq!(
+            messages(Entries::new(r).rev()),
+            vec!["6", "5", "4", "3", "2", "1"]
+        );
+    }
+
+    #[test]
+    fn test_reverse_iterator_take() {
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        assert_eq!(messages(Entries::new(r).rev().take(2)), vec!["6", "5"]);
+    }
+
+    #[test]
+    fn test_reverse_iterator_no_trailing_newline() {
+        let r = Cursor::new(Vec::from(TESTDATA.trim_end().as_bytes()));
+        assert_eq!(messages(Entries::new(r).rev().take(2)), vec!["6", "5"]);
+    }
+
+    #[test]
+    fn test_mixed_line_endings_iterator() {
+        let r = Cursor::new(Vec::from(MIXED_TESTDATA.as_bytes()));
+        assert_eq!(
+            messages(Entries::new(r)),
+            vec!["1", "2", "3", "4", "5", "6"]
+        );
+
+        let r = Cursor::new(Vec::from(MIXED_TESTDATA.as_bytes()));
+        assert_eq!(
+            messages(Entries::new(r).rev()),
+            vec!["6", "5", "4", "3", "2", "1"]
+        );
+    }
+
+    #[test]
+    fn test_mixed_line_endings_at_every_byte() -> Result<()> {
+        let mut entries = Entries::new(Cursor::new(Vec::from(MIXED_TESTDATA.as_bytes())));
+        for pos in 0..MIXED_TESTDATA.len() as u64 {
+            assert!(entries.at(pos)?.is_some(), "no entry at byte {}", pos);
+        }
+        Ok(())
+    }
+
+    #[test_case("2020-01-01T00:00:00+00:00" => Some("1".to_owned()) ; "first entry")]
+    #[test_case("2020-03-12T00:00:00+00:00" => Some("3".to_owned()) ; "after a CRLF line")]
+    #[test_case("2020-04-12T23:28:45.726598931+00:00" => Some("4".to_owned()) ; "CRLF line")]
+    #[test_case("2020-06-13T10:00:00+00:00" => Some("6".to_owned()) ; "last entry")]
+    fn test_mixed_line_endings_seek_to_first(date_str: &str) -> Option<String> {
+        let date = DateTime::parse_from_rfc3339(date_str).unwrap();
+        let mut entries = Entries::new(Cursor::new(Vec::from(MIXED_TESTDATA.as_bytes())));
+        entries.seek_to_first(&date).unwrap();
+        entries
+            .next_entry()
+            .unwrap()
+            .map(|e| e.message().to_owned())
+    }
+
+    #[test]
+    fn test_mixed_line_endings_count_between() -> Result<()> {
+        let mut entries = Entries::new(Cursor::new(Vec::from(MIXED_TESTDATA.as_bytes())));
+        let start = DateTime::parse_from_rfc3339("2020-02-01T00:00:00+00:00").unwrap();
+        assert_eq!(entries.count_between(None, None)?, 6);
+        assert_eq!(entries.count_between(Some(&start), None)?, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reverse_iterator_empty_file() {
+        let r = Cursor::new(Vec::new());
+        assert!(Entries::new(r).next_back().is_none());
+    }
+
+    #[test]
+    fn test_rand_entry_empty_file() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let r = Cursor::new(Vec::new());
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(Entries::new(r).rand_entry_with(&mut rng).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rand_entry_with() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut entries = Entries::new(Cursor::new(Vec::from(TESTDATA.as_bytes())));
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let entry = entries.rand_entry_with(&mut rng).unwrap().unwrap();
+            assert!(["1", "2", "3", "4", "5", "6"].contains(&entry.message()));
+        }
+    }
+
+    #[test]
+    fn test_iterating_from_both_ends() {
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        let mut entries = Entries::new(r);
+
+        assert_eq!(entries.next().unwrap().unwrap().message(), "1");
+        assert_eq!(entries.next_back().unwrap().unwrap().message(), "6");
+        assert_eq!(entries.next().unwrap().unwrap().message(), "2");
+        assert_eq!(entries.next_back().unwrap().unwrap().message(), "5");
+        assert_eq!(entries.next_back().unwrap().unwrap().message(), "4");
+        assert_eq!(entries.next().unwrap().unwrap().message(), "3");
+        assert!(entries.next().is_none());
+        assert!(entries.next_back().is_none());
+    }
+
+    #[test]
+    fn test_iterator_is_fused() {
+        let r = Cursor::new(Vec::from(TESTDATA.as_bytes()));
+        let mut entries = Entries::new(r);
+
+        assert_eq!(entries.by_ref().count(), 6);
+        assert!(entries.next().is_none());
+        assert!(entries.next().is_none());
+        assert!(entries.next_back().is_none());
+
+        // Explicitly moving the cursor starts a new iteration.
+        entries.nth_entry(4).unwrap();
+        assert_eq!(messages(entries), vec!["6"]);
+    }
+
+    #[test]
+    fn test_malformed_line_diagnostics() {
+        let data = format!(
+            "{}{}2020-03-12T00:00:00+00:00,\"\"\"unterminated\n",
+            &TESTDATA[..44],
+            &TESTDATA[44..88]
+        );
+        let mut entries = Entries::new(Cursor::new(Vec::from(data.as_bytes())));
+        entries.next_entry().unwrap();
+        entries.next_entry().unwrap();
+        let err = entries.next_entry().err().unwrap().to_string();
+
+        assert!(
+            err.starts_with("malformed entry on line 3 (byte offset 88): "),
+            "unexpected error: {}",
+            err
+        );
+        assert!(
+            err.ends_with("\n    2020-03-12T00:00:00+00:00,\"\"\"unterminated"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_iterator_continues_past_malformed_lines() {
+        let data = format!("{}not an entry\n{}", &TESTDATA[..44], &TESTDATA[44..88]);
+        let mut entries = Entries::new(Cursor::new(Vec::from(data.as_bytes())));
+
+        assert_eq!(entries.next().unwrap().unwrap().message(), "1");
+        assert!(entries.next().unwrap().is_err());
+        assert_eq!(entries.next().unwrap().unwrap().message(), "2");
+        assert!(entries.next().is_none());
+
+        let mut entries = Entries::new(Cursor::new(Vec::from(data.as_bytes())));
+        assert_eq!(entries.next_back().unwrap().unwrap().message(), "2");
+        assert!(entries.next_back().unwrap().is_err());
+        assert_eq!(entries.next_back().unwrap().unwrap().message(), "1");
+        assert!(entries.next_back().is_none());
+    }
+
+    #[test]
+    fn test_files_with_and_without_metadata() {
+        // Files written before entries had metadata only have two columns, and
+        // entries without metadata are still written that way, so a file can
+        // have a mix of both.
+        let data = TESTDATA.replacen("\"\"\"2\"\"\"\n", "\"\"\"2\"\"\",\"{\"\"mood\"\":4}\"\n", 1);
+        let mut entries = Entries::new(Cursor::new(Vec::from(data.as_bytes())));
+
+        let mut read = Vec::new();
+        while let Some(entry) = entries.next_entry_ref().unwrap() {
+            let entry = entry.to_entry();
+            read.push((
+                entry.message().to_owned(),
+                entry.metadata().get("mood").cloned(),
+            ));
+        }
+        let mut expected: Vec<_> = (1..=6).map(|i| (i.to_string(), None)).collect();
+        expected[1].1 = Some(4.into());
+        assert_eq!(read, expected);
+
+        entries.rewind().unwrap();
+        let read: Vec<_> = entries
+            .rev()
+            .map(|e| e.unwrap().message().to_owned())
+            .collect();
+        assert_eq!(read, vec!["6", "5", "4", "3", "2", "1"]);
+
+        let mut entries = Entries::new(Cursor::new(Vec::from(data.as_bytes())));
+        let date = DateTime::parse_from_rfc3339("2020-02-01T00:00:00Z").unwrap();
+        entries.seek_to_first(&date).unwrap();
+        let entry = entries.next_entry().unwrap().unwrap();
+        assert_eq!(entry.message(), "2");
+        assert_eq!(entry.metadata().get("mood"), Some(&4.into()));
+        assert_eq!(entries.count_between(None, None).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_malformed_line_numbers() {
+        let data = TESTDATA.replace('\n', "\nnot an entry\n");
+        let lines = |entries: &mut Entries<_>| -> Vec<Option<u64>> {
+            entries
+                .filter_map(|entry| match entry {
+                    Err(Error::Malformed { line, .. }) => Some(line),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let mut entries = Entries::new(Cursor::new(Vec::from(data.as_bytes())));
+        let expected: Vec<_> = (1..=6).map(|i| Some(i * 2)).collect();
+        assert_eq!(lines(&mut entries), expected);
+
+        entries.rewind().unwrap();
+        assert_eq!(lines(&mut entries), expected);
+
+        assert_eq!(entries.nth_entry(6).unwrap().unwrap().message(), "4");
+        assert_eq!(lines(&mut entries), expected[3..]);
+
+        entries.rewind().unwrap();
+        entries.next().unwrap().unwrap();
+        assert!(entries.next_back().unwrap().is_err());
+        assert_eq!(entries.next_back().unwrap().unwrap().message(), "6");
+        assert_eq!(lines(&mut entries), expected[..5]);
+    }
 }
@@ -1,20 +1,48 @@
-use super::{
-    error::{self, Error},
-    Result,
-};
+use super::{error::Error, Result};
 use chrono::prelude::*;
 use csv::StringRecord;
+use regex_syntax::hir::literal::Extractor;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
 use std::io::Write;
+use std::ops::Range;
 
+/// Arbitrary extra fields attached to an entry, stored as a JSON object in an
+/// optional third column of the .hmm file. Entries without any metadata are
+/// written with just the usual two columns.
+pub type Metadata = Map<String, Value>;
+
+/// What an entry is identified by when copies of a journal are merged: its
+/// timestamp and a hash of its message and metadata. Entries with the same key
+/// are the same entry, whichever copy they came from.
+pub type EntryKey = (DateTime<FixedOffset>, u64);
+
+/// An entry in a .hmm file. Entries serialize to an object with an RFC3339
+/// "datetime", a "message" and, if the entry has any, its "metadata", which is
+/// what hmm's JSON output and the library's users share:
+///
+/// ```text
+/// {"datetime":"2020-01-01T00:00:00+00:00","message":"hello"}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Entry {
+    #[serde(with = "rfc3339")]
     datetime: DateTime<FixedOffset>,
     message: String,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    metadata: Metadata,
 }
 
 impl Entry {
     pub fn new(datetime: DateTime<FixedOffset>, message: String) -> Self {
-        Entry { datetime, message }
+        Entry {
+            datetime,
+            message,
+            metadata: Metadata::new(),
+        }
     }
 
     pub fn with_message(message: &str) -> Self {
@@ -29,10 +57,89 @@ impl Entry {
         &self.message
     }
 
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// The first line of the entry's message, for entries written as a title
+    /// followed by a body.
+    pub fn title(&self) -> &str {
+        title_and_body(&self.message).0
+    }
+
+    /// Everything in the entry's message after its first line, which is empty
+    /// for messages that are only one line long.
+    pub fn body(&self) -> &str {
+        title_and_body(&self.message).1
+    }
+
+    /// The #hashtags in this entry's message, in the order they appear and
+    /// without the leading #. A tag has to start a word, so "issue#4" and
+    /// "C#" aren't tags, and is made up of letters, numbers, "_", "-" and "/".
+    pub fn tags(&self) -> Vec<&str> {
+        tags(&self.message)
+    }
+
+    /// The @mentions in this entry's message, like "@sam", without the @. A
+    /// mention has to start a word, so email addresses aren't mentions.
+    pub fn mentions(&self) -> Vec<&str> {
+        mentions(&self.message)
+    }
+
+    /// The IDs of the other entries this entry's message refers to, like
+    /// "^cbbf4083", without the ^. See `links`.
+    pub fn links(&self) -> Vec<String> {
+        links(&self.message)
+    }
+
+    /// A view of this entry that borrows its message and metadata.
+    pub fn as_entry_ref(&self) -> EntryRef<'_> {
+        EntryRef {
+            datetime: self.datetime,
+            message: Cow::Borrowed(&self.message),
+            metadata: Cow::Borrowed(&self.metadata),
+        }
+    }
+
+    /// A short ID for the entry, for referring to it on the command line. It's
+    /// made from the entry's timestamp alone, so it's the same on every
+    /// platform and doesn't change if the message is edited.
+    pub fn id(&self) -> String {
+        id(&self.datetime)
+    }
+
+    pub fn key(&self) -> EntryKey {
+        (self.datetime, self.content_hash())
+    }
+
+    /// A hash of the entry's message and metadata, which is the same on every
+    /// platform and doesn't depend on the order the metadata's fields were
+    /// written in.
+    pub fn content_hash(&self) -> u64 {
+        let mut bytes = self.message.as_bytes().to_vec();
+        if !self.metadata.is_empty() {
+            let sorted: BTreeMap<&String, &Value> = self.metadata.iter().collect();
+            bytes.push(0);
+            bytes.extend(serde_json::to_vec(&sorted).unwrap_or_default());
+        }
+        fnv1a(&bytes)
+    }
+
     pub fn contains(&self, s: &str) -> bool {
         self.message.contains(s)
     }
 
+    /// The entry as a JSON object. See `Entry` for what's in it.
+    pub fn to_json(&self) -> String {
+        // Every field is a string or a JSON value already, so this can't fail.
+        serde_json::to_string(self).unwrap()
+    }
+
     pub fn write(&self, mut w: impl Write) -> Result<()> {
         Ok(w.write_all(self.to_csv_row()?.as_bytes())?)
     }
@@ -41,13 +148,244 @@ impl Entry {
         let mut buf = Vec::new();
         {
             let mut writer = csv::Writer::from_writer(&mut buf);
-            writer.write_record(&[
-                self.datetime.to_rfc3339(),
-                serde_json::to_string(&self.message)?,
-            ])?;
+            self.write_record(&mut writer)?;
         }
         Ok(String::from_utf8(buf)?)
     }
+
+    /// Writes the entry as a row to `writer`, so that lots of entries can be
+    /// written without making a csv::Writer for each of them.
+    pub(crate) fn write_record<W: Write>(&self, writer: &mut csv::Writer<W>) -> Result<()> {
+        let mut record = vec![
+            self.datetime.to_rfc3339(),
+            serde_json::to_string(&self.message)?,
+        ];
+        if !self.metadata.is_empty() {
+            record.push(serde_json::to_string(&self.metadata)?);
+        }
+        writer.write_record(&record)?;
+        Ok(())
+    }
+}
+
+/// An entry that borrows its message from the line it was read from, where it
+/// can, rather than copying it in to a String of its own. Reading an entry
+/// this way doesn't allocate unless its message has escaped characters in it
+/// or it has metadata, which makes it much cheaper for looking at lots of
+/// entries to find the few that are wanted.
+#[derive(Debug, Clone)]
+pub struct EntryRef<'a> {
+    datetime: DateTime<FixedOffset>,
+    message: Cow<'a, str>,
+    metadata: Cow<'a, Metadata>,
+}
+
+impl<'a> EntryRef<'a> {
+    /// Parses a line of a .hmm file, with or without its line ending.
+    pub fn parse(line: &'a str) -> Result<Self> {
+        if let Some(entry) = EntryRef::parse_plain(trim_line(line)) {
+            return Ok(entry);
+        }
+
+        let entry: Entry = line.try_into()?;
+        Ok(EntryRef {
+            datetime: entry.datetime,
+            message: Cow::Owned(entry.message),
+            metadata: Cow::Owned(entry.metadata),
+        })
+    }
+
+    // Parses lines written the way hmm writes entries without metadata, whose
+    // message is a JSON string without any escapes, quoted once more for CSV:
+    //
+    //     2020-01-01T00:00:00+00:00,"""hello world"""
+    //
+    // Anything else returns None and has to be parsed properly.
+    fn parse_plain(line: &'a str) -> Option<Self> {
+        let (datetime, rest) = line.split_once(',')?;
+        let rest = rest.strip_prefix("\"\"\"")?;
+        let (message, rest) = rest.split_at(rest.find('"')?);
+        if rest != "\"\"\"" || message.bytes().any(|b| b == b'\\' || b < 0x20) {
+            return None;
+        }
+
+        Some(EntryRef {
+            datetime: DateTime::parse_from_rfc3339(datetime).ok()?,
+            message: Cow::Borrowed(message),
+            metadata: Cow::Owned(Metadata::new()),
+        })
+    }
+
+    pub fn datetime(&self) -> &DateTime<FixedOffset> {
+        &self.datetime
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// The entry's ID. See `Entry::id`.
+    pub fn id(&self) -> String {
+        id(&self.datetime)
+    }
+
+    /// The first line of the entry's message. See `Entry::title`.
+    pub fn title(&self) -> &str {
+        title_and_body(&self.message).0
+    }
+
+    /// The #hashtags in this entry's message. See `Entry::tags`.
+    pub fn tags(&self) -> Vec<&str> {
+        tags(&self.message)
+    }
+
+    /// The IDs this entry's message refers to. See `Entry::links`.
+    pub fn links(&self) -> Vec<String> {
+        links(&self.message)
+    }
+
+    pub fn contains(&self, s: &str) -> bool {
+        self.message.contains(s)
+    }
+
+    /// Copies this entry in to an Entry of its own.
+    pub fn to_entry(&self) -> Entry {
+        Entry {
+            datetime: self.datetime,
+            message: self.message.clone().into_owned(),
+            metadata: self.metadata.clone().into_owned(),
+        }
+    }
+}
+
+/// Whether the entry on `line`, a line of a .hmm file that hasn't been parsed
+/// yet, might have `s` in its message. This only returns false when it
+/// definitely doesn't, so it can rule lines out without decoding them.
+///
+/// Escaping a message for JSON and CSV leaves everything but quotes,
+/// backslashes and control characters as they are, so when `s` has none of
+/// those it appears in the line as it is. The exception is files written by
+/// something other than hmm that escape more than they need to, like "\/" or
+/// "\u00e9", so lines with those escapes in them might match too.
+pub fn might_contain(line: &str, s: &str) -> bool {
+    if s.contains(|c: char| c == '"' || c == '\\' || c.is_control()) {
+        return true;
+    }
+    line.contains(s) || line.contains("\\u") || line.contains("\\/")
+}
+
+/// What every match of the regex `pattern` starts with one of, so that lines
+/// that don't contain any of them can be ruled out with `might_contain` before
+/// the regex is run. Returns None when there's nothing to go on, like for
+/// `\w+` or a pattern that can match an empty string, and for patterns that
+/// don't parse, which `Regex::new` will report.
+///
+/// This can't be done by running the regex on the line itself: anchors, `.`
+/// and classes like `\s` behave differently once the message has been
+/// quoted and escaped, so a message can match when its line doesn't.
+pub fn regex_prefixes(pattern: &str) -> Option<Vec<String>> {
+    let hir = regex_syntax::parse(pattern).ok()?;
+    let seq = Extractor::new().extract(&hir);
+    seq.literals()?
+        .iter()
+        .map(|lit| match std::str::from_utf8(lit.as_bytes()) {
+            Ok(s) if !s.is_empty() => Some(s.to_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn title_and_body(message: &str) -> (&str, &str) {
+    let (title, body) = message.split_once('\n').unwrap_or((message, ""));
+    (title.strip_suffix('\r').unwrap_or(title), body)
+}
+
+pub(crate) fn tags(message: &str) -> Vec<&str> {
+    message
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('#'))
+        .map(|tag| {
+            let end = tag
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-' || c == '/'))
+                .unwrap_or(tag.len());
+            &tag[..end]
+        })
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Checks that `s` is an entry ID, like the ones hmmq --ids prints, with or
+/// without the ^ in front of it, and returns it without the ^.
+pub fn parse_id(s: &str) -> Result<String> {
+    let id = s.strip_prefix('^').unwrap_or(s).to_ascii_lowercase();
+    if id.len() != 8 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::InvalidArgument(format!(
+            "{} isn't an entry ID, which looks like ^cbbf4083",
+            s
+        )));
+    }
+    Ok(id)
+}
+
+fn id(datetime: &DateTime<FixedOffset>) -> String {
+    let utc = datetime
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Nanos, true);
+    let h = fnv1a(utc.as_bytes());
+    format!("{:08x}", (h ^ (h >> 32)) as u32)
+}
+
+pub(crate) fn mentions(message: &str) -> Vec<&str> {
+    message
+        .match_indices('@')
+        .filter(|&(i, _)| {
+            message[..i].chars().next_back().map_or(true, |c| {
+                !(c.is_alphanumeric() || c == '_' || c == '.' || c == '@')
+            })
+        })
+        .filter_map(|(i, _)| {
+            let rest = &message[i + 1..];
+            if !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+                return None;
+            }
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(rest.len());
+            Some(&rest[..end])
+        })
+        .collect()
+}
+
+/// The entry IDs in `message`, in the order they appear and without the ^.
+pub(crate) fn links(message: &str) -> Vec<String> {
+    link_spans(message).into_iter().map(|(_, id)| id).collect()
+}
+
+/// Where each entry ID in `message` is, including its ^, along with the ID.
+/// Like tags, a link has to start a word, so "x^12345678" isn't one, and it
+/// has to be exactly 8 hex digits long.
+pub(crate) fn link_spans(message: &str) -> Vec<(Range<usize>, String)> {
+    message
+        .match_indices('^')
+        .filter(|&(i, _)| {
+            message[..i]
+                .chars()
+                .next_back()
+                .map_or(true, |c| !c.is_alphanumeric())
+        })
+        .filter_map(|(i, _)| {
+            let rest = &message[i + 1..];
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric())
+                .unwrap_or(rest.len());
+            let id = parse_id(&rest[..end]).ok()?;
+            Some((i..i + 1 + end, id))
+        })
+        .collect()
 }
 
 impl TryFrom<quick_csv::Row> for Entry {
@@ -56,16 +394,13 @@ impl TryFrom<quick_csv::Row> for Entry {
     fn try_from(r: quick_csv::Row) -> Result<Self> {
         let mut cols = r.columns()?;
 
-        let date = cols
-            .next()
-            .ok_or_else(|| error::from_str("malformed CSV"))?;
-        let msg = cols
-            .next()
-            .ok_or_else(|| error::from_str("malformed CSV"))?;
+        let date = cols.next().ok_or_else(Error::malformed)?;
+        let msg = cols.next().ok_or_else(Error::malformed)?;
 
         Ok(Entry {
             datetime: chrono::DateTime::parse_from_rfc3339(date)?,
             message: serde_json::from_str(msg)?,
+            metadata: parse_metadata(cols.next())?,
         })
     }
 }
@@ -74,24 +409,72 @@ impl TryFrom<&StringRecord> for Entry {
     type Error = Error;
 
     fn try_from(sr: &StringRecord) -> Result<Self> {
-        let date = sr.get(0).ok_or_else(|| error::from_str("malformed CSV"))?;
-        let msg = sr.get(1).ok_or_else(|| error::from_str("malformed CSV"))?;
+        let date = sr.get(0).ok_or_else(Error::malformed)?;
+        let msg = sr.get(1).ok_or_else(Error::malformed)?;
 
         Ok(Entry {
             datetime: chrono::DateTime::parse_from_rfc3339(date)?,
             message: serde_json::from_str(msg)?,
+            metadata: parse_metadata(sr.get(2))?,
         })
     }
 }
 
+// Entries' datetimes are serialized the same way they're written in .hmm
+// files, rather than however chrono would do it.
+mod rfc3339 {
+    use chrono::{DateTime, FixedOffset};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        datetime: &DateTime<FixedOffset>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&datetime.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<DateTime<FixedOffset>, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&s).map_err(D::Error::custom)
+    }
+}
+
+// FNV-1a, which unlike the hasher in the standard library gives the same
+// hashes on every platform and Rust version.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        h ^= u64::from(b);
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+fn parse_metadata(col: Option<&str>) -> Result<Metadata> {
+    match col {
+        None | Some("") => Ok(Metadata::new()),
+        Some(col) => Ok(serde_json::from_str(col)?),
+    }
+}
+
+/// Strips the line ending from a line read out of a .hmm file, along with the
+/// UTF-8 byte order mark some Windows editors put at the start of a file.
+/// Files that have been edited on Windows often end their lines with \r\n
+/// rather than \n, so both are removed.
+pub(crate) fn trim_line(s: &str) -> &str {
+    let s = s.strip_prefix('\u{feff}').unwrap_or(s);
+    let s = s.strip_suffix('\n').unwrap_or(s);
+    s.strip_suffix('\r').unwrap_or(s)
+}
+
 impl TryFrom<&str> for Entry {
     type Error = Error;
 
     fn try_from(s: &str) -> Result<Self> {
-        let mut csv = quick_csv::Csv::from_string(s);
-        let next = csv
-            .next()
-            .ok_or_else(|| error::from_str("malformed CSV"))??;
+        let mut csv = quick_csv::Csv::from_string(trim_line(s));
+        let next = csv.next().ok_or_else(Error::malformed)??;
         next.try_into()
     }
 }
@@ -113,11 +496,300 @@ mod tests {
     #[test_case("2012-01-01T00:00:00+00:00,\"\"\"hello\\nworld\"\"\"" => ("2012-01-01T00:00:00+00:00".to_owned(), "hello\nworld".to_owned()) ; "entry with newline")]
     #[test_case("2012-01-01T01:00:00+01:00,\"\"\"hello world\"\"\""   => ("2012-01-01T01:00:00+01:00".to_owned(), "hello world".to_owned()) ; "entry with non-UTC timezone")]
     #[test_case("2012-01-01T00:00:00+00:00,\"\"\"\"\"\""              => ("2012-01-01T00:00:00+00:00".to_owned(), "".to_owned()) ; "empty entry")]
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"hello world\"\"\"\n"   => ("2012-01-01T00:00:00+00:00".to_owned(), "hello world".to_owned()) ; "trailing newline")]
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"hello world\"\"\"\r\n" => ("2012-01-01T00:00:00+00:00".to_owned(), "hello world".to_owned()) ; "trailing CRLF")]
+    #[test_case("\u{feff}2012-01-01T00:00:00+00:00,\"\"\"hello world\"\"\"\r\n" => ("2012-01-01T00:00:00+00:00".to_owned(), "hello world".to_owned()) ; "byte order mark")]
     fn test_from_str(s: &str) -> (String, String) {
         let entry: Entry = s.try_into().unwrap();
         (entry.datetime().to_rfc3339(), entry.message().to_owned())
     }
 
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"hello world\"\"\"\r\n" => true  ; "plain")]
+    #[test_case("\u{feff}2012-01-01T00:00:00+00:00,\"\"\"\"\"\""       => true  ; "empty with byte order mark")]
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"hello\\nworld\"\"\""   => false ; "escaped newline")]
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"say \\\"\"hi\\\"\"\"\"\"" => false ; "escaped quotes")]
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"hi\"\"\",\"{\"\"mood\"\":4}\"" => false ; "metadata")]
+    #[test_case("\"2012-01-01T00:00:00+00:00\",\"\"\"hi\"\"\""          => false ; "quoted timestamp")]
+    fn test_entry_ref_parse(s: &str) -> bool {
+        let entry = EntryRef::parse(s).unwrap();
+        let expected: Entry = s.try_into().unwrap();
+        assert_eq!(entry.datetime(), expected.datetime());
+        assert_eq!(entry.message(), expected.message());
+        assert_eq!(entry.metadata(), expected.metadata());
+        matches!(entry.message, Cow::Borrowed(_))
+    }
+
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"unterminated" ; "unterminated")]
+    #[test_case("2012-01-01T00:00:00+00:00,hello"               ; "not json")]
+    #[test_case("yesterday,\"\"\"hello\"\"\""                   ; "bad timestamp")]
+    #[test_case("2012-01-01T00:00:00+00:00,\"\"\"a\tb\"\"\""    ; "control character")]
+    fn test_entry_ref_parse_errors(s: &str) {
+        assert!(EntryRef::parse(s).is_err());
+        assert!(Entry::try_from(s).is_err());
+    }
+
+    #[test]
+    fn test_entry_ref_to_entry() {
+        let entry: Entry = "2012-01-01T00:00:00+00:00,\"\"\"#hi\"\"\",\"{\"\"mood\"\":4}\""
+            .try_into()
+            .unwrap();
+        let copy = entry.as_entry_ref().to_entry();
+        assert_eq!(copy.key(), entry.key());
+        assert_eq!(entry.as_entry_ref().tags(), vec!["hi"]);
+    }
+
+    #[test_case("\"\"\"hello world\"\"\"",           "lo w"    => true  ; "plain")]
+    #[test_case("\"\"\"hello world\"\"\"",           "bye"     => false ; "plain without")]
+    #[test_case("\"\"\"line 1\\nline 2\"\"\"",     "line 2"  => true  ; "after a newline")]
+    #[test_case("\"\"\"line 1\\nline 2\"\"\"",     "1\nline" => true  ; "searching for a newline")]
+    #[test_case("\"\"\"line 1\\nline 2\"\"\"",     "nline"   => true  ; "escapes can look like text")]
+    #[test_case("\"\"\"say \\\"\"hi\\\"\"\"\"\"", "\"hi\"" => true  ; "searching for quotes")]
+    #[test_case("\"\"\"say \\\"\"hi\\\"\"\"\"\"", "bye"     => false ; "quotes without")]
+    #[test_case("\"\"\"caf\\u00e9\"\"\"",          "café"    => true  ; "unicode escape")]
+    #[test_case("\"\"\"a\\/b\"\"\"",               "a/b"     => true  ; "escaped slash")]
+    fn test_might_contain(message: &str, s: &str) -> bool {
+        let line = format!("2012-01-01T00:00:00+00:00,{}\n", message);
+        let might = might_contain(&line, s);
+        if Entry::try_from(line.as_str()).unwrap().contains(s) {
+            assert!(might, "{:?} is in {:?}", s, line);
+        }
+        might
+    }
+
+    // Messages made of characters that get escaped, or are near ones that do,
+    // shouldn't ever be ruled out when they have what's being looked for.
+    #[test]
+    fn test_might_contain_random_messages() {
+        use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+        let alphabet = ['a', 'b', 'n', 'u', '/', '"', '\\', '\n', '\t', 'é', ','];
+        let mut rng = StdRng::seed_from_u64(0);
+        let random = |rng: &mut StdRng, len| -> String {
+            (0..len).map(|_| *alphabet.choose(rng).unwrap()).collect()
+        };
+        for _ in 0..2000 {
+            let len = rng.gen_range(0..12);
+            let message = random(&mut rng, len);
+            let entry = Entry::new(Utc::now().into(), message);
+            let line = entry.to_csv_row().unwrap();
+            let len = rng.gen_range(1..4);
+            let s = random(&mut rng, len);
+            if entry.contains(&s) {
+                assert!(might_contain(&line, &s), "{:?} is in {:?}", s, line);
+            }
+        }
+    }
+
+    #[test_case("deploy"        => Some(vec!["deploy".to_owned()])                      ; "literal")]
+    #[test_case("(api|web) up"  => Some(vec!["api up".to_owned(), "web up".to_owned()])   ; "alternation")]
+    #[test_case("^done\\b"      => Some(vec!["done".to_owned()])                        ; "anchors")]
+    #[test_case("\\w+"          => None                                                 ; "class")]
+    #[test_case("a*"            => None                                                 ; "empty match")]
+    #[test_case("("             => None                                                 ; "invalid")]
+    fn test_regex_prefixes(pattern: &str) -> Option<Vec<String>> {
+        regex_prefixes(pattern)
+    }
+
+    // Lines are only ever ruled out when their message doesn't match.
+    #[test]
+    fn test_regex_prefixes_random_messages() {
+        use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+        let alphabet = ['a', 'b', 'n', 'u', '/', '"', '\\', '\n', '\t', 'é', ','];
+        let patterns = [
+            "ab", "^a", "b$", "a\nb", "\"b", "(?i)AB", "n|u", "a\\sb", "é/",
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..2000 {
+            let len = rng.gen_range(0..12);
+            let message: String = (0..len)
+                .map(|_| *alphabet.choose(&mut rng).unwrap())
+                .collect();
+            let entry = Entry::new(Utc::now().into(), message);
+            let line = entry.to_csv_row().unwrap();
+            let pattern = patterns.choose(&mut rng).unwrap();
+            let prefixes = regex_prefixes(pattern).unwrap();
+            if regex::Regex::new(pattern)
+                .unwrap()
+                .is_match(entry.message())
+            {
+                assert!(
+                    prefixes.iter().any(|p| might_contain(&line, p)),
+                    "{:?} matches {:?}",
+                    pattern,
+                    line
+                );
+            }
+        }
+    }
+
+    #[test_case("#work on the thing"           => vec!["work"]               ; "start of message")]
+    #[test_case("fixed it #bug #work"          => vec!["bug", "work"]        ; "several")]
+    #[test_case("done (#work), #home."         => vec!["home"]               ; "punctuation")]
+    #[test_case("see issue#4 and C# and #"     => Vec::<&str>::new()         ; "not tags")]
+    #[test_case("line one\n#multi-line/tag_1" => vec!["multi-line/tag_1"]   ; "separators")]
+    fn test_tags(message: &str) -> Vec<String> {
+        let entry = Entry::with_message(message);
+        entry.tags().into_iter().map(str::to_owned).collect()
+    }
+
+    #[test_case("lunch with @sam and @alex-b." => vec!["sam", "alex-b"]        ; "mentions")]
+    #[test_case("(@sam), @@x @"                => vec!["sam"]                  ; "punctuation")]
+    #[test_case("sam@example.com and .@x"      => Vec::<&str>::new()           ; "not mentions")]
+    fn test_mentions(message: &str) -> Vec<String> {
+        let entry = Entry::with_message(message);
+        entry.mentions().into_iter().map(str::to_owned).collect()
+    }
+
+    #[test_case("see ^cbbf4083"                => vec!["cbbf4083"]             ; "link")]
+    #[test_case("(^CBBF4083, ^682aadf1.)"      => vec!["cbbf4083", "682aadf1"] ; "several")]
+    #[test_case("x^cbbf4083 ^cbbf408 ^cbbf40830" => Vec::<&str>::new()        ; "not links")]
+    #[test_case("2^8 ^ ^^"                     => Vec::<&str>::new()           ; "carets")]
+    fn test_links(message: &str) -> Vec<String> {
+        Entry::with_message(message).links()
+    }
+
+    #[test_case("cbbf4083"  => Some("cbbf4083".to_owned()) ; "id")]
+    #[test_case("^cbbf4083" => Some("cbbf4083".to_owned()) ; "with caret")]
+    #[test_case("^CBBF4083" => Some("cbbf4083".to_owned()) ; "upper case")]
+    #[test_case("cbbf408"   => None                        ; "too short")]
+    #[test_case("cbbf408g"  => None                        ; "not hex")]
+    #[test_case(""          => None                        ; "empty")]
+    fn test_parse_id(s: &str) -> Option<String> {
+        parse_id(s).ok()
+    }
+
+    #[test_case("hello"                  => ("hello".to_owned(), "".to_owned())                ; "one line")]
+    #[test_case("title\nbody"            => ("title".to_owned(), "body".to_owned())            ; "title and body")]
+    #[test_case("title\r\nbody\nmore"    => ("title".to_owned(), "body\nmore".to_owned())      ; "crlf and longer body")]
+    #[test_case("\nbody"                 => ("".to_owned(), "body".to_owned())                 ; "no title")]
+    fn test_title_and_body(message: &str) -> (String, String) {
+        let entry = Entry::new(Utc::now().into(), message.to_owned());
+        (entry.title().to_owned(), entry.body().to_owned())
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let mut metadata = Metadata::new();
+        metadata.insert("mood".to_owned(), Value::from(4));
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2012-01-01T00:00:00+00:00").unwrap(),
+            "hello, world".to_owned(),
+        )
+        .with_metadata(metadata);
+
+        let row = entry.to_csv_row().unwrap();
+        assert_eq!(
+            row,
+            "2012-01-01T00:00:00+00:00,\"\"\"hello, world\"\"\",\"{\"\"mood\"\":4}\"\n"
+        );
+
+        let read: Entry = row.try_into().unwrap();
+        assert_eq!(read.message(), "hello, world");
+        assert_eq!(read.metadata().get("mood"), Some(&Value::from(4)));
+    }
+
+    #[test]
+    fn test_json() {
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2012-01-01T01:00:00.5+01:00").unwrap(),
+            "hello \"world\"".to_owned(),
+        );
+        assert_eq!(
+            entry.to_json(),
+            "{\"datetime\":\"2012-01-01T01:00:00.500+01:00\",\"message\":\"hello \\\"world\\\"\"}"
+        );
+
+        let mut metadata = Metadata::new();
+        metadata.insert("mood".to_owned(), Value::from(4));
+        let entry = entry.with_metadata(metadata);
+        let read: Entry = serde_json::from_str(&entry.to_json()).unwrap();
+        assert_eq!(read.datetime(), entry.datetime());
+        assert_eq!(read.datetime().offset(), entry.datetime().offset());
+        assert_eq!(read.message(), entry.message());
+        assert_eq!(read.metadata().get("mood"), Some(&Value::from(4)));
+    }
+
+    #[test_case("{\"message\":\"hello\"}"                                   ; "missing datetime")]
+    #[test_case("{\"datetime\":\"yesterday\",\"message\":\"hello\"}"         ; "invalid datetime")]
+    #[test_case("{\"datetime\":\"2012-01-01T00:00:00Z\",\"message\":1}"      ; "message not a string")]
+    #[test_case("{\"datetime\":\"2012-01-01T00:00:00Z\",\"message\":\"\",\"metadata\":[]}" ; "metadata not an object")]
+    fn test_json_errors(s: &str) {
+        assert!(serde_json::from_str::<Entry>(s).is_err());
+    }
+
+    #[test]
+    fn test_no_metadata_two_columns() {
+        let entry: Entry = "2012-01-01T00:00:00+00:00,\"\"\"hello\"\"\""
+            .try_into()
+            .unwrap();
+        assert!(entry.metadata().is_empty());
+        assert_eq!(
+            entry.to_csv_row().unwrap(),
+            "2012-01-01T00:00:00+00:00,\"\"\"hello\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_metadata_must_be_an_object() {
+        let result: Result<Entry> = "2012-01-01T00:00:00+00:00,\"\"\"hello\"\"\",[1]".try_into();
+        assert_eq!(result.err().unwrap().kind(), "json");
+    }
+
+    #[test]
+    fn test_key() {
+        let entry = |datetime: &str, message: &str, metadata: &str| {
+            Entry::new(
+                DateTime::parse_from_rfc3339(datetime).unwrap(),
+                message.to_owned(),
+            )
+            .with_metadata(serde_json::from_str(metadata).unwrap())
+        };
+        let key = entry("2012-01-01T00:00:00+00:00", "hello", r#"{"a":1,"b":2}"#).key();
+
+        assert_eq!(
+            key,
+            entry("2012-01-01T01:00:00+01:00", "hello", r#"{"b":2,"a":1}"#).key()
+        );
+        assert_ne!(
+            key,
+            entry("2012-01-01T00:00:01+00:00", "hello", r#"{"a":1,"b":2}"#).key()
+        );
+        assert_ne!(
+            key,
+            entry("2012-01-01T00:00:00+00:00", "hello!", r#"{"a":1,"b":2}"#).key()
+        );
+        assert_ne!(
+            key,
+            entry("2012-01-01T00:00:00+00:00", "hello", r#"{"a":1}"#).key()
+        );
+    }
+
+    // Keys are compared across machines, so the hash is pinned.
+    #[test]
+    fn test_content_hash_is_stable() {
+        assert_eq!(
+            Entry::with_message("hello").content_hash(),
+            0xa430d84680aabd0b
+        );
+    }
+
+    // IDs are typed in by hand and kept in other entries, so they're pinned.
+    #[test]
+    fn test_id() {
+        let entry = |datetime: &str, message: &str| {
+            Entry::new(
+                DateTime::parse_from_rfc3339(datetime).unwrap(),
+                message.to_owned(),
+            )
+        };
+        let id = entry("2012-01-01T00:00:00+00:00", "hello").id();
+
+        assert_eq!(id, "cbbf4083");
+        assert_eq!(id, entry("2012-01-01T01:00:00+01:00", "edited").id());
+        assert_ne!(id, entry("2012-01-01T00:00:00.1+00:00", "hello").id());
+    }
+
     #[test_case("not a csv" => "malformed CSV" ; "not a csv")]
     #[test_case("." => "malformed CSV" ; "single dot")]
     #[test_case("" => "malformed CSV" ; "empty string")]
@@ -0,0 +1,253 @@
+//! Entry templates, for writing entries that always have the same shape, like
+//! standup notes, from the command line. They're set in the config file:
+//!
+//! ```toml
+//! [entry_templates]
+//! standup = "yesterday: {y}\ntoday: {t}\nblockers: {b|none}"
+//! ```
+//!
+//! and filled in with `hmm --new standup -y "..." -t "..."`. Fields are written
+//! as `{name}`, or `{name|default}` for ones that can be left out, and `{{`
+//! and `}}` stand for literal braces. Unlike formatting with Handlebars,
+//! there's nothing else to the language, so anything else is written as is.
+
+use super::{error::Error, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct EntryTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field {
+        name: String,
+        default: Option<String>,
+    },
+}
+
+/// How a field is given on the command line: -y for fields with one letter
+/// names and --name for the rest.
+pub fn flag(name: &str) -> String {
+    if name.chars().count() == 1 {
+        format!("-{}", name)
+    } else {
+        format!("--{}", name)
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        && !name.starts_with('-')
+}
+
+impl FromStr for EntryTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find(['{', '}']) {
+            text.push_str(&rest[..i]);
+            let after = &rest[i + 1..];
+            if rest[i..].starts_with("{{") || rest[i..].starts_with("}}") {
+                text.push_str(&rest[i..i + 1]);
+                rest = &after[1..];
+                continue;
+            }
+            if rest[i..].starts_with('}') {
+                return Err(Error::InvalidArgument(
+                    "a } without a { before it needs to be written as }}".to_owned(),
+                ));
+            }
+
+            let end = after.find('}').ok_or_else(|| {
+                Error::InvalidArgument(format!("{{{} is missing a closing }}", after))
+            })?;
+            let (name, default) = match after[..end].split_once('|') {
+                Some((name, default)) => (name.trim(), Some(default.to_owned())),
+                None => (after[..end].trim(), None),
+            };
+            if !valid_name(name) {
+                return Err(Error::InvalidArgument(format!(
+                    "\"{{{}}}\" isn't a field, field names can only have letters, numbers, _ and - in them",
+                    &after[..end]
+                )));
+            }
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(Part::Field {
+                name: name.to_owned(),
+                default,
+            });
+            rest = &after[end + 1..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(EntryTemplate { parts })
+    }
+}
+
+impl TryFrom<String> for EntryTemplate {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, String> {
+        s.parse().map_err(|e: Error| e.to_string())
+    }
+}
+
+impl EntryTemplate {
+    /// The names of the template's fields, in the order they first appear.
+    pub fn fields(&self) -> Vec<&str> {
+        let mut fields = Vec::new();
+        for part in &self.parts {
+            if let Part::Field { name, .. } = part {
+                if !fields.contains(&name.as_str()) {
+                    fields.push(name.as_str());
+                }
+            }
+        }
+        fields
+    }
+
+    /// Fills in the template's fields with `values`. Every field without a
+    /// default needs a value, and every value needs a field.
+    pub fn fill(&self, values: &HashMap<String, String>) -> Result<String> {
+        let fields = self.fields();
+        if let Some(name) = values.keys().find(|name| !fields.contains(&name.as_str())) {
+            return Err(Error::InvalidArgument(format!(
+                "there's no {} field in the template, it has {}",
+                flag(name),
+                list(&fields)
+            )));
+        }
+
+        let mut missing = Vec::new();
+        let mut s = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => s.push_str(text),
+                Part::Field { name, default } => match values.get(name).or(default.as_ref()) {
+                    Some(value) => s.push_str(value),
+                    None if !missing.contains(&name.as_str()) => missing.push(name),
+                    None => {}
+                },
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "the template needs {}",
+                list(&missing)
+            )));
+        }
+        Ok(s)
+    }
+}
+
+fn list(fields: &[&str]) -> String {
+    if fields.is_empty() {
+        return "no fields".to_owned();
+    }
+    let flags: Vec<String> = fields.iter().map(|name| flag(name)).collect();
+    flags.join(", ")
+}
+
+/// Reads the values for an entry template's fields from command line
+/// arguments like `-y "..." --blockers "..."` or `--blockers=...`.
+pub fn parse_values(args: &[String]) -> Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix('-') {
+            Some(flag) => flag.strip_prefix('-').unwrap_or(flag),
+            None => {
+                return Err(Error::InvalidArgument(format!(
+                    "\"{}\" needs a flag before it, like -y \"{}\"",
+                    arg, arg
+                )))
+            }
+        };
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, value.to_owned()),
+            None => match args.next() {
+                Some(value) => (flag, value.clone()),
+                None => return Err(Error::InvalidArgument(format!("{} needs a value", arg))),
+            },
+        };
+        values.insert(name.to_owned(), value);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test_case("y: {y}\nt: {t}", &[("y", "a"), ("t", "b")] => "y: a\nt: b" ; "fields")]
+    #[test_case("{b|none}", &[]                             => "none"       ; "default")]
+    #[test_case("{b|none}", &[("b", "lots")]                => "lots"       ; "default overridden")]
+    #[test_case("{ b |}", &[]                               => ""           ; "empty default")]
+    #[test_case("{x} and {x}", &[("x", "1")]                => "1 and 1"    ; "repeated")]
+    #[test_case("{{x}} {{{x}}}", &[("x", "1")]              => "{x} {1}"    ; "escaped braces")]
+    #[test_case("no fields", &[]                            => "no fields"  ; "no fields")]
+    #[test_case("{what-now}", &[("what-now", "é")]          => "é"          ; "dashes")]
+    fn test_fill(template: &str, pairs: &[(&str, &str)]) -> String {
+        let template: EntryTemplate = template.parse().unwrap();
+        template.fill(&values(pairs)).unwrap()
+    }
+
+    #[test_case("{y} {t} {b|none}", &[("t", "x")]   => "the template needs -y" ; "missing")]
+    #[test_case("{y} {t}", &[]                       => "the template needs -y, -t" ; "missing several")]
+    #[test_case("{y}", &[("y", "a"), ("today", "b")] => "there's no --today field in the template, it has -y" ; "unknown field")]
+    #[test_case("plain", &[("y", "a")]               => "there's no -y field in the template, it has no fields" ; "no fields")]
+    fn test_fill_errors(template: &str, pairs: &[(&str, &str)]) -> String {
+        let template: EntryTemplate = template.parse().unwrap();
+        template.fill(&values(pairs)).unwrap_err().to_string()
+    }
+
+    #[test_case("{y"     ; "unclosed")]
+    #[test_case("y}"     ; "lone closing brace")]
+    #[test_case("{}"     ; "empty name")]
+    #[test_case("{a b}"  ; "space in name")]
+    #[test_case("{-a}"   ; "leading dash")]
+    fn test_parse_errors(template: &str) {
+        assert!(template.parse::<EntryTemplate>().is_err());
+    }
+
+    #[test]
+    fn test_fields() {
+        let template: EntryTemplate = "{b} {a|x} {b}".parse().unwrap();
+        assert_eq!(template.fields(), vec!["b", "a"]);
+    }
+
+    #[test_case(&["-y", "a", "--today", "b c"] => Ok(values(&[("y", "a"), ("today", "b c")])) ; "flags")]
+    #[test_case(&["--b=none", "-x=-1"]         => Ok(values(&[("b", "none"), ("x", "-1")])) ; "equals")]
+    #[test_case(&["-y", "-"]                   => Ok(values(&[("y", "-")]))                 ; "value starting with a dash")]
+    #[test_case(&["-y"]                        => Err("-y needs a value".to_owned())         ; "no value")]
+    #[test_case(&["hello"]                     => Err("\"hello\" needs a flag before it, like -y \"hello\"".to_owned()) ; "no flag")]
+    fn test_parse_values(args: &[&str]) -> std::result::Result<HashMap<String, String>, String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        parse_values(&args).map_err(|e| e.to_string())
+    }
+}
@@ -1,119 +1,423 @@
-use std::{error, fmt, io};
+use std::{io, path::PathBuf, str::FromStr, time::Duration};
+use thiserror::Error;
 
-pub fn from_str(s: &str) -> Error {
-    s.to_owned().into()
-}
-
-#[derive(Debug)]
+// Some variants only exist with the features that can cause them, so code
+// outside hmm can't rely on matching all of them.
+#[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
-    Io(io::Error),
-    Csv(csv::Error),
-    QuickCsv(quick_csv::error::Error),
-    ChronoParse(chrono::format::ParseError),
-    SerdeJson(serde_json::error::Error),
+    #[error("Couldn't open or create file at {}: {source}", path.to_string_lossy())]
+    OpenFile { path: PathBuf, source: io::Error },
+
+    #[error("error creating file at {}: {source}", path.to_string_lossy())]
+    CreateFile { path: PathBuf, source: io::Error },
+
+    #[error("{}", describe_malformed(*line, *byte, snippet.as_deref(), reason.as_deref()))]
+    Malformed {
+        line: Option<u64>,
+        byte: Option<u64>,
+        snippet: Option<String>,
+        reason: Option<String>,
+    },
+
+    #[error("{0} {} couldn't be parsed and {} skipped", if *.0 == 1 { "line" } else { "lines" }, if *.0 == 1 { "was" } else { "were" })]
+    SkippedLines(u64),
+
+    #[error("{0} {} couldn't be imported and {} skipped", if *.0 == 1 { "file" } else { "files" }, if *.0 == 1 { "was" } else { "were" })]
+    SkippedFiles(u64),
+
+    #[error("error reading config file at {}: {reason}", path.to_string_lossy())]
+    Config { path: PathBuf, reason: String },
+
+    #[error("clock skew detected, writing an entry now would break the ordering of your hmm file, please try again in a moment or set skew = \"wait\" or skew = \"clamp\" in your config file")]
+    ClockSkew,
+
+    #[error("another hmm process is writing to your hmm file and didn't finish within {} seconds, please try again or pass a longer --lock-timeout", .0.as_secs_f64())]
+    LockTimeout(Duration),
+
+    #[error("another hmm process replaced your hmm file with a new copy while this one was waiting to write to it, please try again")]
+    Replaced,
+
+    #[error("Unable to find an editor, set your HMM_EDITOR or EDITOR environment variable")]
+    NoEditor,
+
+    #[error("{0}")]
+    Editor(String),
+
+    #[error("the entry is empty, so nothing was written, pass --allow-empty to write it anyway")]
+    EmptyEntry,
+
+    #[error("the pre_append hook rejected the entry: {0}")]
+    HookRejected(String),
+
+    #[error("the validate_command found problems with the entry: {0}")]
+    ValidateFailed(String),
+
+    #[error("the entry was written but the post_append hook failed: {0}")]
+    HookFailed(String),
+
+    #[error("the entry was written but the webhook failed: {0}")]
+    WebhookFailed(String),
+
+    #[error("{0}")]
+    Crypt(String),
+
+    #[error("{0}")]
+    Remote(String),
+
+    #[error("{0}")]
+    Scheduler(String),
+
+    #[error("couldn't read the clipboard: {0}")]
+    Clipboard(String),
+
+    #[error("couldn't send email: {0}")]
+    Mail(String),
+
+    #[error("couldn't find your location: {0}")]
+    Location(String),
+
+    #[error("hmm panicked, which is a bug: {0}")]
+    Panicked(String),
+
+    #[error("{0}")]
+    InvalidArgument(String),
+
+    #[error("unrecognised date format: \"{0}\", accepted formats include things like:\n  - 2012\n  - 2012-01\n  - 2012-01-24\n  - 2012-01-24T16\n  - 2012-01-24T16:20\n  - 2012-01-24T16:20:30")]
+    InvalidDate(String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    QuickCsv(#[from] quick_csv::error::Error),
+
+    #[error(transparent)]
+    ChronoParse(#[from] chrono::format::ParseError),
+
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::error::Error),
+
+    #[cfg(feature = "format")]
+    #[error(transparent)]
     Template(Box<handlebars::TemplateError>),
-    Render(handlebars::RenderError),
-    Utf8(std::string::FromUtf8Error),
-    Regex(regex::Error),
-    String(String),
+
+    #[cfg(feature = "format")]
+    #[error(transparent)]
+    Render(#[from] handlebars::RenderError),
+
+    #[cfg(feature = "parquet")]
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+
+    #[cfg(feature = "parquet")]
+    #[error(transparent)]
+    Arrow(#[from] arrow_schema::ArrowError),
+
+    #[cfg(feature = "sql")]
+    #[error(transparent)]
+    Sql(#[from] rusqlite::Error),
+
+    #[error(transparent)]
+    Utf8(#[from] std::string::FromUtf8Error),
+
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
 }
 
-impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
-        match *self {
-            Error::Io(ref err) => Some(err),
-            Error::Csv(ref err) => Some(err),
-            Error::QuickCsv(ref err) => Some(err),
-            Error::ChronoParse(ref err) => Some(err),
-            Error::SerdeJson(ref err) => Some(err),
-            Error::Template(ref err) => Some(err),
-            Error::Render(ref err) => Some(err),
-            Error::Utf8(ref err) => Some(err),
-            Error::Regex(ref err) => Some(err),
-            Error::String(_) => None,
+// Snippets of malformed lines are cut down to this many characters so that a
+// single enormous line doesn't flood the terminal.
+const MAX_SNIPPET_CHARS: usize = 80;
+
+fn describe_malformed(
+    line: Option<u64>,
+    byte: Option<u64>,
+    snippet: Option<&str>,
+    reason: Option<&str>,
+) -> String {
+    let mut s = match reason {
+        Some(_) => "malformed entry".to_owned(),
+        None => "malformed CSV".to_owned(),
+    };
+
+    match (line, byte) {
+        (Some(line), Some(byte)) => {
+            s.push_str(&format!(" on line {} (byte offset {})", line, byte))
         }
+        (Some(line), None) => s.push_str(&format!(" on line {}", line)),
+        (None, Some(byte)) => s.push_str(&format!(" at byte offset {}", byte)),
+        (None, None) => {}
     }
-}
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Error::Io(ref err) => err.fmt(f),
-            Error::Csv(ref err) => err.fmt(f),
-            Error::QuickCsv(ref err) => err.fmt(f),
-            Error::ChronoParse(ref err) => err.fmt(f),
-            Error::SerdeJson(ref err) => err.fmt(f),
-            Error::Template(ref err) => err.fmt(f),
-            Error::Render(ref err) => err.fmt(f),
-            Error::Utf8(ref err) => err.fmt(f),
-            Error::Regex(ref err) => err.fmt(f),
-            Error::String(ref s) => f.write_str(s),
-        }
+    if let Some(reason) = reason {
+        s.push_str(": ");
+        s.push_str(reason);
     }
-}
 
-impl From<&str> for Error {
-    fn from(s: &str) -> Error {
-        Error::String(s.to_owned())
+    if let Some(snippet) = snippet {
+        s.push_str("\n    ");
+        s.push_str(snippet);
     }
+
+    s
 }
 
-impl From<String> for Error {
-    fn from(s: String) -> Error {
-        Error::String(s)
+fn snippet(line: &str) -> String {
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+    if line.chars().count() <= MAX_SNIPPET_CHARS {
+        return line.to_owned();
     }
+    let mut s: String = line.chars().take(MAX_SNIPPET_CHARS).collect();
+    s.push('…');
+    s
 }
 
-impl From<regex::Error> for Error {
-    fn from(err: regex::Error) -> Error {
-        Error::Regex(err)
+impl Error {
+    pub fn malformed() -> Self {
+        Error::Malformed {
+            line: None,
+            byte: None,
+            snippet: None,
+            reason: None,
+        }
     }
-}
 
-impl From<std::string::FromUtf8Error> for Error {
-    fn from(err: std::string::FromUtf8Error) -> Error {
-        Error::Utf8(err)
+    /// Adds the location and contents of the offending line to errors that
+    /// came from parsing an entry. Other errors are returned unchanged.
+    pub fn at_line(self, line: Option<u64>, byte: u64, text: &str) -> Self {
+        let reason = match self {
+            Error::Malformed { reason, .. } => reason,
+            Error::Csv(_)
+            | Error::QuickCsv(_)
+            | Error::ChronoParse(_)
+            | Error::SerdeJson(_)
+            | Error::Utf8(_) => Some(self.to_string()),
+            other => return other,
+        };
+
+        Error::Malformed {
+            line,
+            byte: Some(byte),
+            snippet: Some(snippet(text)),
+            reason,
+        }
     }
-}
 
-impl From<handlebars::RenderError> for Error {
-    fn from(err: handlebars::RenderError) -> Error {
-        Error::Render(err)
+    /// A short, stable name for the kind of error, for use in machine-readable
+    /// output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::OpenFile { .. } => "open_file",
+            Error::CreateFile { .. } => "create_file",
+            Error::Malformed { .. } => "malformed",
+            Error::Config { .. } => "config",
+            Error::SkippedLines(_) => "skipped_lines",
+            Error::SkippedFiles(_) => "skipped_files",
+            Error::ClockSkew => "clock_skew",
+            Error::LockTimeout(_) => "lock_timeout",
+            Error::Replaced => "replaced",
+            Error::NoEditor => "no_editor",
+            Error::Editor(_) => "editor",
+            Error::EmptyEntry => "empty_entry",
+            Error::HookRejected(_) => "hook_rejected",
+            Error::ValidateFailed(_) => "validate_failed",
+            Error::HookFailed(_) => "hook_failed",
+            Error::WebhookFailed(_) => "webhook_failed",
+            Error::Crypt(_) => "crypt",
+            Error::Remote(_) => "remote",
+            Error::Scheduler(_) => "scheduler",
+            Error::Clipboard(_) => "clipboard",
+            Error::Mail(_) => "mail",
+            Error::Location(_) => "location",
+            Error::Panicked(_) => "panicked",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::InvalidDate(_) => "invalid_date",
+            Error::Io(_) => "io",
+            Error::Csv(_) | Error::QuickCsv(_) => "csv",
+            Error::ChronoParse(_) => "date_parse",
+            Error::SerdeJson(_) => "json",
+            #[cfg(feature = "format")]
+            Error::Template(_) => "template",
+            #[cfg(feature = "format")]
+            Error::Render(_) => "render",
+            #[cfg(feature = "parquet")]
+            Error::Parquet(_) | Error::Arrow(_) => "parquet",
+            #[cfg(feature = "sql")]
+            Error::Sql(_) => "sql",
+            Error::Utf8(_) => "utf8",
+            Error::Regex(_) => "regex",
+        }
+    }
+
+    /// The exit status a binary should use when exiting because of this error.
+    /// These follow the conventions in sysexits.h where one applies.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument(_) | Error::InvalidDate(_) | Error::Regex(_) => 64,
+            #[cfg(feature = "format")]
+            Error::Template(_) | Error::Render(_) => 64,
+            #[cfg(feature = "sql")]
+            Error::Sql(_) => 64,
+            Error::Malformed { .. }
+            | Error::EmptyEntry
+            | Error::SkippedLines(_)
+            | Error::SkippedFiles(_)
+            | Error::Csv(_)
+            | Error::QuickCsv(_)
+            | Error::ChronoParse(_)
+            | Error::SerdeJson(_)
+            | Error::Utf8(_) => 65,
+            Error::OpenFile { .. } => 66,
+            Error::NoEditor
+            | Error::Remote(_)
+            | Error::Scheduler(_)
+            | Error::Clipboard(_)
+            | Error::Mail(_)
+            | Error::Location(_) => 69,
+            Error::Editor(_)
+            | Error::HookFailed(_)
+            | Error::WebhookFailed(_)
+            | Error::Crypt(_)
+            | Error::Panicked(_) => 70,
+            Error::CreateFile { .. } => 73,
+            Error::Io(_) => 74,
+            #[cfg(feature = "parquet")]
+            Error::Parquet(_) | Error::Arrow(_) => 74,
+            Error::ClockSkew | Error::LockTimeout(_) | Error::Replaced => 75,
+            Error::HookRejected(_) | Error::ValidateFailed(_) => 77,
+            Error::Config { .. } => 78,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        self.json().to_string()
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": self.kind(),
+            "code": self.exit_code(),
+            "message": self.to_string(),
+        })
     }
 }
 
+#[cfg(feature = "format")]
 impl From<handlebars::TemplateError> for Error {
     fn from(err: handlebars::TemplateError) -> Error {
         Error::Template(Box::new(err))
     }
 }
 
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Error {
-        Error::Io(err)
-    }
+/// How the binaries print errors to stderr before exiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
 }
 
-impl From<csv::Error> for Error {
-    fn from(err: csv::Error) -> Error {
-        Error::Csv(err)
+impl FromStr for ErrorFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown error format \"{}\", expected text or json",
+                s
+            ))),
+        }
     }
 }
 
-impl From<quick_csv::error::Error> for Error {
-    fn from(err: quick_csv::error::Error) -> Error {
-        Error::QuickCsv(err)
+impl ErrorFormat {
+    pub fn print(&self, err: &Error) {
+        match self {
+            ErrorFormat::Text => eprintln!("{}", err),
+            ErrorFormat::Json => eprintln!("{}", err.to_json()),
+        }
     }
-}
 
-impl From<serde_json::error::Error> for Error {
-    fn from(err: serde_json::error::Error) -> Error {
-        Error::SerdeJson(err)
+    /// Prints an error that wasn't bad enough to stop for, saying which file
+    /// it came from. In JSON the object has the same fields as for fatal
+    /// errors, plus "warning" and "path".
+    pub fn warn(&self, source: &str, err: &Error) {
+        match self {
+            ErrorFormat::Text => eprintln!("warning: {}: {}", source, err),
+            ErrorFormat::Json => {
+                let mut json = err.json();
+                json["warning"] = true.into();
+                json["path"] = source.into();
+                eprintln!("{}", json)
+            }
+        }
     }
 }
 
-impl From<chrono::format::ParseError> for Error {
-    fn from(err: chrono::format::ParseError) -> Error {
-        Error::ChronoParse(err)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(None,    None     => "malformed CSV")]
+    #[test_case(Some(3), None     => "malformed CSV on line 3")]
+    #[test_case(None,    Some(10) => "malformed CSV at byte offset 10")]
+    #[test_case(Some(3), Some(10) => "malformed CSV on line 3 (byte offset 10)")]
+    fn test_malformed_display(line: Option<u64>, byte: Option<u64>) -> String {
+        Error::Malformed {
+            line,
+            byte,
+            snippet: None,
+            reason: None,
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn test_at_line() {
+        let err: Error = serde_json::from_str::<String>("nope").unwrap_err().into();
+        assert_eq!(
+            err.at_line(Some(2), 44, "2020-01-01T00:00:00+00:00,nope\n").to_string(),
+            "malformed entry on line 2 (byte offset 44): expected ident at line 1 column 2\n    2020-01-01T00:00:00+00:00,nope"
+        );
+    }
+
+    #[test]
+    fn test_at_line_truncates_long_lines() {
+        let line = "x".repeat(200);
+        let err = Error::malformed().at_line(None, 0, &line).to_string();
+        assert!(err.ends_with(&format!("\n    {}…", "x".repeat(80))));
+    }
+
+    #[test]
+    fn test_at_line_leaves_other_errors_alone() {
+        let err = Error::ClockSkew.at_line(Some(1), 0, "whatever");
+        assert_eq!(err.kind(), "clock_skew");
+    }
+
+    #[test_case(1 => "1 line couldn't be parsed and was skipped")]
+    #[test_case(3 => "3 lines couldn't be parsed and were skipped")]
+    fn test_skipped_lines_display(n: u64) -> String {
+        Error::SkippedLines(n).to_string()
+    }
+
+    #[test_case(1 => "1 file couldn't be imported and was skipped")]
+    #[test_case(2 => "2 files couldn't be imported and were skipped")]
+    fn test_skipped_files_display(n: u64) -> String {
+        Error::SkippedFiles(n).to_string()
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&Error::InvalidArgument("nope".to_owned()).to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "error": "invalid_argument", "code": 64, "message": "nope" })
+        );
     }
 }
@@ -0,0 +1,414 @@
+//! A C interface to the library, so editors and programs written in other
+//! languages can read and write journals without running hmm's binaries.
+//! Build it as a shared library with:
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! The header for it is include/hmm.h, which is generated by cbindgen. After
+//! changing anything here, regenerate it with `cbindgen --output
+//! include/hmm.h`, or the tests will fail.
+//!
+//! Functions that can fail return 0 on success and otherwise the exit code
+//! hmm's binaries would exit with for the same error, or NULL if they return
+//! a string. `hmm_last_error` describes what went wrong. Strings going in
+//! must be UTF-8 and strings coming out must be freed with `hmm_string_free`.
+
+use super::{
+    config::Config,
+    crypt, date,
+    entries::Entries,
+    error::Error,
+    journal::{self, AppendOptions},
+    Result,
+};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Appends an entry with `message` to the .hmm file at `path`, creating the
+/// file if it doesn't exist. If hmm's default config file has an
+/// encrypt_command the message is encrypted with it, as hmm would, but none of
+/// the hooks in it are run.
+///
+/// # Safety
+///
+/// `path` and `message` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn hmm_append(path: *const c_char, message: *const c_char) -> c_int {
+    status(|| {
+        let path = PathBuf::from(str_arg("path", path)?);
+        let message = str_arg("message", message)?;
+        append(&path, message, &Config::load_or_default(None)?)
+    })
+}
+
+fn append(path: &Path, message: &str, config: &Config) -> Result<()> {
+    if message.trim().is_empty() {
+        return Err(Error::InvalidArgument("message can't be empty".to_owned()));
+    }
+
+    let f = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .map_err(|source| Error::OpenFile {
+            path: path.to_owned(),
+            source,
+        })?;
+    let (stored, metadata) = crypt::encrypt_with(config.encrypt_command.as_deref(), message)?;
+    journal::append_with_metadata(&f, &stored, &metadata, &AppendOptions::default())?;
+    Ok(())
+}
+
+/// Returns the entries in the .hmm file at `path` as a JSON array of objects
+/// with "datetime" and "message" fields, and "metadata" if an entry has any,
+/// the same as hmmserve's /entries. `start`, `end` and `contains` work like
+/// hmmq's --start, --end and --contains, and any of them can be NULL. As with
+/// hmmq, encrypted messages are decrypted before they're matched if hmm's
+/// default config file has a decrypt_command. Returns NULL if there's an
+/// error.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string, and `start`, `end` and `contains`
+/// must each be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn hmm_query_json(
+    path: *const c_char,
+    start: *const c_char,
+    end: *const c_char,
+    contains: *const c_char,
+) -> *mut c_char {
+    let result = catch_panic(|| {
+        query_json(
+            str_arg("path", path)?,
+            date_arg("start", start)?,
+            date_arg("end", end)?,
+            opt_str_arg("contains", contains)?,
+            &Config::load_or_default(None)?,
+        )
+    });
+
+    match result {
+        // serde_json escapes NUL, so this can't fail.
+        Ok(json) => CString::new(json).unwrap().into_raw(),
+        Err(e) => {
+            set_last_error(&e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Counts the entries in the .hmm file at `path` between `start`, inclusive,
+/// and `end`, exclusive, either of which can be NULL, and stores the count in
+/// `count`.
+///
+/// # Safety
+///
+/// `path` must be a NUL-terminated string, `start` and `end` must each be NULL
+/// or a NUL-terminated string, and `count` must point to a uint64_t.
+#[no_mangle]
+pub unsafe extern "C" fn hmm_count(
+    path: *const c_char,
+    start: *const c_char,
+    end: *const c_char,
+    count: *mut u64,
+) -> c_int {
+    status(|| {
+        if count.is_null() {
+            return Err(Error::InvalidArgument("count can't be NULL".to_owned()));
+        }
+        let mut entries = open(str_arg("path", path)?)?;
+        let start = date_arg("start", start)?;
+        let end = date_arg("end", end)?;
+        *count = entries.count_between(start.as_ref(), end.as_ref())?;
+        Ok(())
+    })
+}
+
+/// Returns a description of the last error on this thread, or NULL if there
+/// hasn't been one. The string belongs to the library and is only valid until
+/// the next call that fails.
+#[no_mangle]
+pub extern "C" fn hmm_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Frees a string returned by the library. Does nothing if `s` is NULL.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by the library that hasn't already
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn hmm_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+// Runs `f`, turning an error in to its exit code and remembering it for
+// hmm_last_error.
+fn status<F: FnOnce() -> Result<()>>(f: F) -> c_int {
+    match catch_panic(f) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(&e);
+            e.exit_code()
+        }
+    }
+}
+
+// Unwinding out of an extern "C" function aborts the program that called it,
+// so a panic is turned in to an error like any other.
+fn catch_panic<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = match panic.downcast_ref::<&str>() {
+            Some(s) => (*s).to_owned(),
+            None => panic
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| "no message".to_owned()),
+        };
+        Err(Error::Panicked(message))
+    })
+}
+
+fn set_last_error(e: &Error) {
+    let message = CString::new(e.to_string().replace('\0', "")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+unsafe fn str_arg<'a>(name: &str, s: *const c_char) -> Result<&'a str> {
+    opt_str_arg(name, s)?.ok_or_else(|| Error::InvalidArgument(format!("{} can't be NULL", name)))
+}
+
+unsafe fn opt_str_arg<'a>(name: &str, s: *const c_char) -> Result<Option<&'a str>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(Some(s)),
+        Err(_) => Err(Error::InvalidArgument(format!(
+            "{} isn't valid UTF-8",
+            name
+        ))),
+    }
+}
+
+unsafe fn date_arg(
+    name: &str,
+    s: *const c_char,
+) -> Result<Option<chrono::DateTime<chrono::FixedOffset>>> {
+    opt_str_arg(name, s)?.map(date::parse).transpose()
+}
+
+fn query_json(
+    path: &str,
+    start: Option<chrono::DateTime<chrono::FixedOffset>>,
+    end: Option<chrono::DateTime<chrono::FixedOffset>>,
+    contains: Option<&str>,
+    config: &Config,
+) -> Result<String> {
+    let mut entries = open(path)?;
+    let mut matches = Vec::new();
+    for result in entries.between(start.as_ref(), end.as_ref())? {
+        let entry = match (result?, &config.decrypt_command) {
+            (entry, Some(command)) if crypt::is_encrypted(entry.message(), entry.metadata()) => {
+                crypt::decrypt_entry(command, &entry)?
+            }
+            (entry, _) => entry,
+        };
+        if contains.map_or(true, |s| entry.contains(s)) {
+            matches.push(serde_json::to_value(&entry)?);
+        }
+    }
+    Ok(serde_json::Value::Array(matches).to_string())
+}
+
+fn open(path: &str) -> Result<Entries<BufReader<File>>> {
+    let f = File::open(path).map_err(|source| Error::OpenFile {
+        path: Path::new(path).to_owned(),
+        source,
+    })?;
+    Ok(Entries::new(BufReader::new(f)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(hmm_last_error()) }
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    // Takes ownership of a string returned by the library.
+    fn take(s: *mut c_char) -> String {
+        assert!(!s.is_null(), "{}", last_error());
+        let owned = unsafe { CStr::from_ptr(s) }.to_str().unwrap().to_owned();
+        unsafe { hmm_string_free(s) };
+        owned
+    }
+
+    #[test]
+    fn test_append_and_query() {
+        let dir = TempDir::new().unwrap();
+        let path = c(dir.path().join("journal.hmm").to_str().unwrap());
+
+        unsafe {
+            assert_eq!(hmm_append(path.as_ptr(), c("hello #ffi").as_ptr()), 0);
+            assert_eq!(hmm_append(path.as_ptr(), c("world").as_ptr()), 0);
+        }
+
+        let json =
+            take(unsafe { hmm_query_json(path.as_ptr(), ptr::null(), ptr::null(), ptr::null()) });
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let messages: Vec<_> = entries
+            .iter()
+            .map(|e| e["message"].as_str().unwrap())
+            .collect();
+        assert_eq!(messages, vec!["hello #ffi", "world"]);
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(entries[0]["datetime"].as_str().unwrap()).is_ok()
+        );
+
+        let json = take(unsafe {
+            hmm_query_json(
+                path.as_ptr(),
+                c("2000").as_ptr(),
+                ptr::null(),
+                c("#ffi").as_ptr(),
+            )
+        });
+        assert_eq!(
+            serde_json::from_str::<Vec<serde_json::Value>>(&json)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let mut count = 0;
+        unsafe {
+            assert_eq!(
+                hmm_count(path.as_ptr(), ptr::null(), ptr::null(), &mut count),
+                0
+            );
+        }
+        assert_eq!(count, 2);
+        unsafe {
+            assert_eq!(
+                hmm_count(path.as_ptr(), ptr::null(), c("2000").as_ptr(), &mut count),
+                0
+            );
+        }
+        assert_eq!(count, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_encryption() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("journal.hmm");
+        let rot13 = Some("tr 'A-Za-z' 'N-ZA-Mn-za-m'".to_owned());
+        let config = Config {
+            encrypt_command: rot13.clone(),
+            decrypt_command: rot13,
+            ..Config::default()
+        };
+
+        append(&path, "hello", &config).unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("\"\"\"hmm-encrypted:dXJ5eWI=\"\"\",\"{\"\"encrypted\"\":true}\"\n"));
+
+        let path = path.to_str().unwrap();
+        let json = query_json(path, None, None, Some("hello"), &config).unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["message"], "hello");
+
+        let json = query_json(path, None, None, Some("hello"), &Config::default()).unwrap();
+        assert_eq!(json, "[]");
+    }
+
+    #[test]
+    fn test_header_is_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/hmm.h"));
+        let committed = include_str!("../include/hmm.h");
+        assert!(
+            generated == committed,
+            "include/hmm.h is out of date, regenerate it with `cbindgen --output include/hmm.h`"
+        );
+    }
+
+    #[test]
+    fn test_panics_are_errors() {
+        let code = status(|| panic!("oops"));
+        assert_eq!(code, 70);
+        assert_eq!(last_error(), "hmm panicked, which is a bug: oops");
+    }
+
+    #[test]
+    fn test_errors() {
+        let dir = TempDir::new().unwrap();
+        let missing = c(dir.path().join("missing.hmm").to_str().unwrap());
+        let mut count = 0;
+
+        unsafe {
+            assert_eq!(hmm_append(ptr::null(), c("hello").as_ptr()), 64);
+            assert_eq!(last_error(), "path can't be NULL");
+
+            assert_eq!(hmm_append(missing.as_ptr(), c(" \n").as_ptr()), 64);
+            assert_eq!(last_error(), "message can't be empty");
+
+            assert_eq!(
+                hmm_count(missing.as_ptr(), ptr::null(), ptr::null(), &mut count),
+                66
+            );
+            assert!(last_error().contains("missing.hmm"), "{}", last_error());
+
+            assert_eq!(
+                hmm_count(missing.as_ptr(), ptr::null(), ptr::null(), ptr::null_mut()),
+                64
+            );
+            assert_eq!(last_error(), "count can't be NULL");
+
+            let path = c(dir.path().join("journal.hmm").to_str().unwrap());
+            assert_eq!(hmm_append(path.as_ptr(), c("hello").as_ptr()), 0);
+            let json = hmm_query_json(path.as_ptr(), c("nope").as_ptr(), ptr::null(), ptr::null());
+            assert!(json.is_null());
+            assert!(
+                last_error().starts_with("unrecognised date format"),
+                "{}",
+                last_error()
+            );
+
+            // Freeing NULL is allowed.
+            hmm_string_free(ptr::null_mut());
+        }
+    }
+}
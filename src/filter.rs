@@ -0,0 +1,441 @@
+use super::{
+    entry::{Entry, EntryRef},
+    error::Error,
+    Result,
+};
+use serde_json::Value as Json;
+use std::str::FromStr;
+
+/// How a field is compared with a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// The field contains the value, written ~.
+    Contains,
+    /// The field doesn't contain the value, written !~.
+    NotContains,
+}
+
+impl Op {
+    fn compare<T: PartialOrd + ?Sized>(&self, a: &T, b: &T) -> bool {
+        match self {
+            Op::Eq => a == b,
+            Op::Ne => a != b,
+            Op::Lt => a < b,
+            Op::Le => a <= b,
+            Op::Gt => a > b,
+            Op::Ge => a >= b,
+            Op::Contains | Op::NotContains => false,
+        }
+    }
+
+    fn is_ordering(&self) -> bool {
+        matches!(self, Op::Lt | Op::Le | Op::Gt | Op::Ge)
+    }
+}
+
+// Longer operators come first so that ">=" isn't read as ">" followed by a
+// value starting with "=".
+const OPS: [(&str, Op); 9] = [
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("!~", Op::NotContains),
+    ("=", Op::Eq),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+    ("~", Op::Contains),
+];
+
+/// What part of an entry a condition looks at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// The entry's #hashtags. `tag=work` matches entries tagged #work and `~`
+    /// matches any tag containing the value.
+    Tag,
+
+    /// The entry's message.
+    Message,
+
+    /// A field in the entry's metadata, like the mood written by hmm --mood.
+    Metadata(String),
+}
+
+impl Field {
+    fn parse(name: &str) -> Field {
+        match name {
+            "tag" => Field::Tag,
+            "message" => Field::Message,
+            name => Field::Metadata(name.to_owned()),
+        }
+    }
+}
+
+/// What a field is compared with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+impl Value {
+    fn text(&self) -> String {
+        match self {
+            Value::Number(n) => n.to_string(),
+            Value::Text(s) => s.clone(),
+        }
+    }
+}
+
+/// A condition that entries either match or don't, like
+/// `tag=work and mood>=3 and message~"review"`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// A field of an entry compared with a value.
+    Compare { field: Field, op: Op, value: Value },
+
+    /// Matches when all of these filters match, or always if there are none.
+    All(Vec<Filter>),
+
+    /// Matches when any of these filters match, or never if there are none.
+    Any(Vec<Filter>),
+
+    /// Matches when this filter doesn't.
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.matches_ref(&entry.as_entry_ref())
+    }
+
+    /// The same as matches, for entries that haven't been copied out of the
+    /// line they were read from.
+    pub fn matches_ref(&self, entry: &EntryRef) -> bool {
+        match self {
+            Filter::Compare { field, op, value } => compare(entry, field, *op, value),
+            Filter::All(filters) => filters.iter().all(|f| f.matches_ref(entry)),
+            Filter::Any(filters) => filters.iter().any(|f| f.matches_ref(entry)),
+            Filter::Not(filter) => !filter.matches_ref(entry),
+        }
+    }
+}
+
+fn compare(entry: &EntryRef, field: &Field, op: Op, value: &Value) -> bool {
+    match field {
+        Field::Tag => {
+            let value = value.text();
+            let value = value.strip_prefix('#').unwrap_or(&value);
+            let tags = entry.tags();
+            match op {
+                Op::Eq => tags.contains(&value),
+                Op::Ne => !tags.contains(&value),
+                Op::Contains => tags.iter().any(|tag| tag.contains(value)),
+                Op::NotContains => !tags.iter().any(|tag| tag.contains(value)),
+                _ => false,
+            }
+        }
+        Field::Message => text(entry.message(), op, &value.text()),
+        // Entries without the field never match, whatever the comparison, so
+        // that mood!=5 doesn't match entries without a mood.
+        Field::Metadata(name) => match (entry.metadata().get(name), value) {
+            (None, _) => false,
+            (Some(field), Value::Number(n)) => {
+                field.as_f64().is_some_and(|field| op.compare(&field, n))
+            }
+            (Some(Json::String(s)), value) => text(s, op, &value.text()),
+            (Some(field), value) => text(&field.to_string(), op, &value.text()),
+        },
+    }
+}
+
+fn text(s: &str, op: Op, value: &str) -> bool {
+    match op {
+        Op::Contains => s.contains(value),
+        Op::NotContains => !s.contains(value),
+        op => op.compare(s, value),
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    /// Parses an expression made of comparisons like `mood>=4`, joined with
+    /// `and` and `or`, negated with `not` and grouped with brackets. `and`
+    /// binds tighter than `or`. Values can be numbers, words, or "quoted
+    /// strings" with \" for a quote.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            Error::InvalidArgument(format!("couldn't understand \"{}\": {}", s, reason))
+        };
+
+        let tokens = tokenize(s).map_err(|reason| invalid(&reason))?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let filter = parser.or().map_err(|reason| invalid(&reason))?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(filter),
+            Some(token) => Err(invalid(&format!("unexpected {}", token))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(w) | Token::Quoted(w) => write!(f, "\"{}\"", w),
+            Token::Op(op) => {
+                let name = OPS.iter().find(|(_, o)| o == op).map_or("?", |(n, _)| n);
+                write!(f, "\"{}\"", name)
+            }
+            Token::Open => write!(f, "\"(\""),
+            Token::Close => write!(f, "\")\""),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start();
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => return Ok(tokens),
+        };
+
+        if let Some((name, op)) = OPS.iter().find(|(name, _)| rest.starts_with(name)) {
+            tokens.push(Token::Op(*op));
+            rest = &rest[name.len()..];
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if c == '"' {
+            let mut quoted = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => quoted.push(c),
+                        None => return Err("unfinished quote".to_owned()),
+                    },
+                    Some((_, c)) => quoted.push(c),
+                    None => return Err("unfinished quote".to_owned()),
+                }
+            };
+            tokens.push(Token::Quoted(quoted));
+            rest = &rest[end..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()\"=!<>~".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected \"{}\"", c));
+            }
+            tokens.push(Token::Word(rest[..end].to_owned()));
+            rest = &rest[end..];
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> std::result::Result<Filter, String> {
+        let mut filters = vec![self.and()?];
+        while self.keyword("or") {
+            filters.push(self.and()?);
+        }
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::Any(filters),
+        })
+    }
+
+    fn and(&mut self) -> std::result::Result<Filter, String> {
+        let mut filters = vec![self.not()?];
+        while self.keyword("and") {
+            filters.push(self.not()?);
+        }
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::All(filters),
+        })
+    }
+
+    fn not(&mut self) -> std::result::Result<Filter, String> {
+        if self.keyword("not") {
+            return Ok(Filter::Not(Box::new(self.not()?)));
+        }
+        if self.tokens.get(self.pos) == Some(&Token::Open) {
+            self.pos += 1;
+            let filter = self.or()?;
+            return match self.next() {
+                Some(Token::Close) => Ok(filter),
+                _ => Err("missing \")\"".to_owned()),
+            };
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> std::result::Result<Filter, String> {
+        let field = match self.next() {
+            Some(Token::Word(w)) => w,
+            Some(token) => return Err(format!("expected a field but found {}", token)),
+            None => return Err("expected a field, like mood>=4".to_owned()),
+        };
+        let valid_field = field
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if !valid_field {
+            return Err(format!("\"{}\" isn't a field", field));
+        }
+        let field = Field::parse(&field);
+
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            _ => return Err("expected =, !=, <, <=, >, >=, ~ or !~ after a field".to_owned()),
+        };
+        if op.is_ordering() && field == Field::Tag {
+            return Err("tags can only be compared with =, !=, ~ and !~".to_owned());
+        }
+
+        // Only metadata can hold numbers, and looking for a number inside a
+        // field is looking for its digits.
+        let numeric =
+            matches!(field, Field::Metadata(_)) && !matches!(op, Op::Contains | Op::NotContains);
+        let value = match self.next() {
+            Some(Token::Quoted(s)) => Value::Text(s),
+            Some(Token::Word(w)) if numeric => match w.parse() {
+                Ok(n) => Value::Number(n),
+                Err(_) if op.is_ordering() => return Err(format!("\"{}\" isn't a number", w)),
+                Err(_) => Value::Text(w),
+            },
+            Some(Token::Word(w)) => Value::Text(w),
+            _ => return Err("expected a value after the comparison".to_owned()),
+        };
+
+        Ok(Filter::Compare { field, op, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Metadata;
+    use chrono::prelude::*;
+    use test_case::test_case;
+
+    fn entry(message: &str, metadata: &str) -> Entry {
+        let metadata: Metadata = serde_json::from_str(metadata).unwrap();
+        Entry::new(
+            Utc.with_ymd_and_hms(2024, 5, 13, 9, 0, 0).unwrap().into(),
+            message.to_owned(),
+        )
+        .with_metadata(metadata)
+    }
+
+    fn mood(op: Op, value: f64) -> Filter {
+        Filter::Compare {
+            field: Field::Metadata("mood".to_owned()),
+            op,
+            value: Value::Number(value),
+        }
+    }
+
+    #[test_case("mood>=4"          => mood(Op::Ge, 4.0)  ; "ge")]
+    #[test_case("mood = 3"         => mood(Op::Eq, 3.0)  ; "spaces")]
+    #[test_case("mood!=-1"         => mood(Op::Ne, -1.0) ; "negative")]
+    #[test_case("(mood<2)"         => mood(Op::Lt, 2.0)  ; "brackets")]
+    #[test_case("tag=work"         => Filter::Compare { field: Field::Tag, op: Op::Eq, value: Value::Text("work".to_owned()) } ; "tag")]
+    #[test_case("message~\"a \\\"b\\\"\"" => Filter::Compare { field: Field::Message, op: Op::Contains, value: Value::Text("a \"b\"".to_owned()) } ; "quoted")]
+    #[test_case("not mood>4"       => Filter::Not(Box::new(mood(Op::Gt, 4.0))) ; "not")]
+    #[test_case("mood>1 AND mood<5" => Filter::All(vec![mood(Op::Gt, 1.0), mood(Op::Lt, 5.0)]) ; "and")]
+    #[test_case("mood<2 or mood>3 and mood<5" => Filter::Any(vec![mood(Op::Lt, 2.0), Filter::All(vec![mood(Op::Gt, 3.0), mood(Op::Lt, 5.0)])]) ; "and binds tighter")]
+    #[test_case("(mood<2 or mood>3) and mood<5" => Filter::All(vec![Filter::Any(vec![mood(Op::Lt, 2.0), mood(Op::Gt, 3.0)]), mood(Op::Lt, 5.0)]) ; "grouped")]
+    fn test_parse(s: &str) -> Filter {
+        s.parse().unwrap()
+    }
+
+    #[test_case(""                    ; "empty")]
+    #[test_case("mood"                ; "no operator")]
+    #[test_case(">=4"                 ; "no field")]
+    #[test_case("mood>="              ; "no value")]
+    #[test_case("mood>=great"         ; "not a number")]
+    #[test_case("mood=>4"             ; "backwards operator")]
+    #[test_case("my mood>4"           ; "space in field")]
+    #[test_case("tag>work"            ; "ordering tags")]
+    #[test_case("mood>1 and"          ; "trailing and")]
+    #[test_case("(mood>1"             ; "unclosed bracket")]
+    #[test_case("mood>1)"             ; "extra bracket")]
+    #[test_case("message~\"review"    ; "unfinished quote")]
+    #[test_case("mood>1 mood<3"       ; "missing and")]
+    fn test_parse_errors(s: &str) {
+        assert_eq!(s.parse::<Filter>().unwrap_err().exit_code(), 64);
+    }
+
+    #[test_case("mood>=4",                   "hi",            r#"{"mood": 4}"#      => true  ; "equal")]
+    #[test_case("mood>=4",                   "hi",            r#"{"mood": 3}"#      => false ; "less")]
+    #[test_case("mood!=5",                   "hi",            r#"{}"#               => false ; "missing")]
+    #[test_case("mood>1",                    "hi",            r#"{"mood": "high"}"# => false ; "not a number")]
+    #[test_case("weather=sunny",             "hi",            r#"{"weather": "sunny"}"# => true ; "text metadata")]
+    #[test_case("weather~sun",               "hi",            r#"{"weather": "sunny"}"# => true ; "text metadata contains")]
+    #[test_case("tag=work",                  "standup #work", r#"{}"#               => true  ; "tag")]
+    #[test_case("tag=#work",                 "standup #work", r#"{}"#               => true  ; "tag with hash")]
+    #[test_case("tag=wor",                   "standup #work", r#"{}"#               => false ; "partial tag")]
+    #[test_case("tag~wor",                   "standup #work", r#"{}"#               => true  ; "tag contains")]
+    #[test_case("tag!=work",                 "standup",       r#"{}"#               => true  ; "no tag")]
+    #[test_case("message~\"code review\"",   "a code review", r#"{}"#               => true  ; "message contains")]
+    #[test_case("message!~review",           "a code review", r#"{}"#               => false ; "message doesn't contain")]
+    #[test_case("message=standup",           "standup",       r#"{}"#               => true  ; "message equals")]
+    #[test_case("not tag=work",              "standup #work", r#"{}"#               => false ; "not")]
+    #[test_case("tag=work and mood>=3 and message~\"review\"", "review #work", r#"{"mood": 3}"# => true ; "all")]
+    #[test_case("tag=work and mood>=3 and message~\"review\"", "review #work", r#"{"mood": 2}"# => false ; "all but one")]
+    #[test_case("tag=home or mood>=3",       "#work",         r#"{"mood": 3}"#      => true  ; "any")]
+    fn test_matches(filter: &str, message: &str, metadata: &str) -> bool {
+        filter
+            .parse::<Filter>()
+            .unwrap()
+            .matches(&entry(message, metadata))
+    }
+
+    #[test]
+    fn test_empty_all_and_any() {
+        assert!(Filter::All(vec![]).matches(&entry("hi", "{}")));
+        assert!(!Filter::Any(vec![]).matches(&entry("hi", "{}")));
+    }
+}
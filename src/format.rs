@@ -1,40 +1,456 @@
-use super::{entry::Entry, Result};
+use super::{
+    date,
+    entry::{self, Entry},
+    error::Error,
+    text, Result,
+};
 use chrono::prelude::*;
-use colored::*;
+use colored::Color;
 use handlebars::{
-    Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender, Output, RenderContext,
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender, Output, PathAndJson,
+    RenderContext, RenderError, ScopedJson,
 };
-use std::collections::BTreeMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
 
 pub struct Format<'a> {
     renderer: Handlebars<'a>,
-    data: BTreeMap<&'static str, String>,
+    data: TemplateData,
+    color: bool,
+    uses_previous: bool,
+}
+
+/// Whether formatted output should be colored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Color output that's going to a terminal, unless the NO_COLOR
+    /// environment variable is set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// Whether to color output, given whether it's going to a terminal.
+    pub fn enabled(&self, terminal: bool) -> bool {
+        match self {
+            ColorMode::Auto => {
+                terminal && std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty())
+            }
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown color mode \"{}\", expected auto, always or never",
+                s
+            ))),
+        }
+    }
+}
+
+/// Periods of time that entries can be grouped by, with a heading before the
+/// entries in each one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Day,
+
+    /// Weeks start on Monday.
+    Week,
+    Month,
+}
+
+impl FromStr for GroupBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "day" => Ok(GroupBy::Day),
+            "week" => Ok(GroupBy::Week),
+            "month" => Ok(GroupBy::Month),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown grouping \"{}\", expected day, week or month",
+                s
+            ))),
+        }
+    }
+}
+
+impl GroupBy {
+    /// The first day of the period `datetime` falls in, in local time.
+    pub fn period(&self, datetime: &DateTime<FixedOffset>) -> NaiveDate {
+        let date = datetime.with_timezone(&Local).date_naive();
+        match self {
+            GroupBy::Day => date,
+            GroupBy::Week => {
+                date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64)
+            }
+            GroupBy::Month => date.with_day(1).unwrap(),
+        }
+    }
+
+    /// The first day of the period after the one starting on `period`.
+    pub fn next(&self, period: NaiveDate) -> NaiveDate {
+        match self {
+            GroupBy::Day => period + chrono::Duration::days(1),
+            GroupBy::Week => period + chrono::Duration::days(7),
+            GroupBy::Month => period + chrono::Months::new(1),
+        }
+    }
+
+    /// The template headings are formatted with if no other is given.
+    pub fn default_heading(&self) -> &'static str {
+        match self {
+            GroupBy::Day => "## {{ strftime \"%Y-%m-%d\" datetime }} ({{ summary }})",
+            GroupBy::Week => "## Week of {{ strftime \"%Y-%m-%d\" datetime }}",
+            GroupBy::Month => "## {{ strftime \"%B %Y\" datetime }}",
+        }
+    }
+}
+
+/// Totals for a group of entries printed under a heading, which the heading
+/// can show. They're added up as the group's entries are seen, so the heading
+/// can only be written once the whole group has been.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupTotals {
+    pub entries: u64,
+    pub words: usize,
+    pub first: Option<DateTime<FixedOffset>>,
+    pub last: Option<DateTime<FixedOffset>>,
+}
+
+impl GroupTotals {
+    pub fn add(&mut self, entry: &Entry) {
+        let datetime = *entry.datetime();
+        self.entries += 1;
+        self.words += text::word_count(entry.message());
+        self.first = Some(self.first.map_or(datetime, |first| first.min(datetime)));
+        self.last = Some(self.last.map_or(datetime, |last| last.max(datetime)));
+    }
+
+    /// How long it was from the first entry to the last.
+    pub fn span(&self) -> chrono::Duration {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => last - first,
+            _ => chrono::Duration::zero(),
+        }
+    }
+
+    /// The totals in words, like "3 entries, 120 words, 09:00 to 17:30
+    /// (8h30m)", with the times in local time.
+    pub fn summary(&self) -> String {
+        let plural =
+            |n: u64, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+        let mut summary = format!(
+            "{}, {}",
+            plural(self.entries, "entry", "entries"),
+            plural(self.words as u64, "word", "words")
+        );
+        let time = |datetime: DateTime<FixedOffset>| {
+            datetime.with_timezone(&Local).format("%H:%M").to_string()
+        };
+        match (self.first, self.last) {
+            (Some(first), Some(last)) if first != last => summary.push_str(&format!(
+                ", {} to {} ({})",
+                time(first),
+                time(last),
+                date::format_duration(self.span())
+            )),
+            (Some(first), _) => summary.push_str(&format!(", {}", time(first))),
+            _ => {}
+        }
+        summary
+    }
+}
+
+/// A rule for the autocolor helper, which styles entries differently depending
+/// on what they are. Rules are set in the config file like:
+///
+/// ```toml
+/// [[color_rules]]
+/// tag = "bug"
+/// color = "red"
+///
+/// [[color_rules]]
+/// weekday = "Saturday"
+/// dim = true
+/// ```
+///
+/// A rule applies to entries that match everything it sets, and entries are
+/// styled by the first rule that applies to them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "RawColorRule")]
+pub struct ColorRule {
+    /// A #hashtag the entry has to have, without the #.
+    pub tag: Option<String>,
+    /// The day of the week the entry has to have been written on, in local
+    /// time.
+    pub weekday: Option<Weekday>,
+    pub color: Option<Color>,
+    pub dim: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawColorRule {
+    tag: Option<String>,
+    weekday: Option<String>,
+    color: Option<String>,
+    #[serde(default)]
+    dim: bool,
+}
+
+impl TryFrom<RawColorRule> for ColorRule {
+    type Error = String;
+
+    fn try_from(raw: RawColorRule) -> std::result::Result<Self, String> {
+        if raw.color.is_none() && !raw.dim {
+            return Err("color rules need a color or dim = true".to_owned());
+        }
+        let weekday = raw
+            .weekday
+            .map(|s| {
+                s.parse()
+                    .map_err(|_| format!("unknown weekday \"{}\" in color rule", s))
+            })
+            .transpose()?;
+        let color = raw
+            .color
+            .map(|s| {
+                s.parse()
+                    .map_err(|_| format!("unknown color \"{}\" in color rule", s))
+            })
+            .transpose()?;
+
+        Ok(ColorRule {
+            tag: raw.tag.map(|tag| tag.trim_start_matches('#').to_owned()),
+            weekday,
+            color,
+            dim: raw.dim,
+        })
+    }
+}
+
+impl ColorRule {
+    fn applies(&self, message: &str, weekday: Option<Weekday>) -> bool {
+        let tag = self
+            .tag
+            .as_ref()
+            .map_or(true, |tag| entry::tags(message).contains(&tag.as_str()));
+        let weekday = self.weekday.map_or(true, |w| weekday == Some(w));
+        tag && weekday
+    }
+
+    fn style(&self, s: &str) -> String {
+        let mut codes = Vec::new();
+        if self.dim {
+            codes.push("2".into());
+        }
+        if let Some(color) = self.color {
+            codes.push(color.to_fg_str());
+        }
+        format!("\x1b[{}m{}\x1b[0m", codes.join(";"), s)
+    }
+}
+
+/// The values passed in to a template when formatting an entry: the same
+/// fields the entry has as JSON, so templates see anything hmm adds to entries,
+/// along with a few worked out from them, like title and since_prev. Callers
+/// formatting entries on more than one thread keep one of these per thread, as
+/// it remembers the last entry it was filled from.
+#[derive(Default)]
+pub struct TemplateData {
+    values: Map<String, Value>,
+    prev: Option<DateTime<FixedOffset>>,
+}
+
+impl TemplateData {
+    fn fill(&mut self, entry: &Entry) -> Result<&Map<String, Value>> {
+        self.values = match serde_json::to_value(entry)? {
+            Value::Object(values) => values,
+            _ => unreachable!("entries serialize to objects"),
+        };
+        self.values.insert("title".into(), entry.title().into());
+        self.values.insert("body".into(), entry.body().into());
+        self.values.insert("id".into(), entry.id().into());
+        self.values
+            .insert("date".into(), date_parts(entry.datetime()));
+
+        // The first entry has nothing before it, so it gets an empty string
+        // rather than an error.
+        let since_prev = self
+            .prev
+            .map(|prev| date::format_duration(*entry.datetime() - prev))
+            .unwrap_or_default();
+        self.values.insert("since_prev".into(), since_prev.into());
+        self.prev = Some(*entry.datetime());
+        Ok(&self.values)
+    }
+
+    fn fill_period(&mut self, period: NaiveDate, totals: &GroupTotals) -> &Map<String, Value> {
+        let rfc3339 = |datetime: Option<DateTime<FixedOffset>>| {
+            datetime.map_or(Value::Null, |datetime| datetime.to_rfc3339().into())
+        };
+        self.values.clear();
+        self.values.insert(
+            "datetime".into(),
+            date::start_of_day(period).to_rfc3339().into(),
+        );
+        self.values.insert("entries".into(), totals.entries.into());
+        self.values.insert("words".into(), totals.words.into());
+        self.values.insert("first".into(), rfc3339(totals.first));
+        self.values.insert("last".into(), rfc3339(totals.last));
+        self.values
+            .insert("span".into(), date::format_duration(totals.span()).into());
+        self.values
+            .insert("summary".into(), totals.summary().into());
+        &self.values
+    }
+}
+
+// The parts of an entry's date that templates might want to lay entries out
+// differently by, in local time like the strftime helper. Numbers are numbers
+// so they can be compared with helpers like gt.
+fn date_parts(datetime: &DateTime<FixedOffset>) -> Value {
+    let local = datetime.with_timezone(&Local);
+    serde_json::json!({
+        "year": local.year(),
+        "month": local.month(),
+        "day": local.day(),
+        "weekday": local.format("%A").to_string(),
+        "hour": local.hour(),
+    })
 }
 
 impl<'a> Format<'a> {
     pub fn with_template(template: &str) -> Result<Self> {
-        let mut renderer = Handlebars::new();
-        renderer.set_strict_mode(true);
-        renderer.register_escape_fn(|s| s.trim().to_owned());
-        renderer.register_template_string("template", template)?;
-        renderer.register_helper("indent", Box::new(IndentHelper {}));
-        renderer.register_helper("strftime", Box::new(StrftimeHelper {}));
-        renderer.register_helper("color", Box::new(ColorHelper {}));
-        renderer.register_helper("markdown", Box::new(MarkdownHelper {}));
-
         Ok(Format {
-            renderer,
-            data: BTreeMap::new(),
+            renderer: renderer(template)?,
+            data: TemplateData::default(),
+            color: true,
+            uses_previous: template.contains("since_prev"),
         })
     }
 
+    /// Whether the template uses values that depend on the entry formatted
+    /// before, like since_prev, which means entries have to be formatted one
+    /// at a time and in order to come out right.
+    pub fn uses_previous(&self) -> bool {
+        self.uses_previous
+    }
+
+    /// Turns colors and other styling from the color and markdown helpers on
+    /// or off. They're on by default.
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    /// Sets the rules the autocolor helper styles entries with. Without any,
+    /// it leaves everything as it is.
+    pub fn set_color_rules(&mut self, rules: Vec<ColorRule>) {
+        self.renderer
+            .register_helper("autocolor", Box::new(AutocolorHelper { rules }));
+    }
+
     pub fn format_entry(&mut self, entry: &Entry) -> Result<String> {
-        self.data.clear();
+        let s = self.renderer.render("template", self.data.fill(entry)?)?;
+        if self.color {
+            Ok(s)
+        } else {
+            Ok(text::strip_ansi(&s))
+        }
+    }
+
+    /// Formats `data` with the template rather than an entry, for output made
+    /// from more than what's in the entry, like hmmq --print-captures.
+    pub fn format_data<T: Serialize>(&self, data: &T) -> Result<String> {
+        let s = self.renderer.render("template", data)?;
+        if self.color {
+            Ok(s)
+        } else {
+            Ok(text::strip_ansi(&s))
+        }
+    }
+
+    /// Formats the heading for a group of entries. Only "datetime" is passed
+    /// in, which is the start of the `period`.
+    pub fn format_heading(&mut self, period: NaiveDate, totals: &GroupTotals) -> Result<String> {
+        let s = self
+            .renderer
+            .render("template", self.data.fill_period(period, totals))?;
+        if self.color {
+            Ok(s)
+        } else {
+            Ok(text::strip_ansi(&s))
+        }
+    }
 
-        self.data.insert("datetime", entry.datetime().to_rfc3339());
-        self.data.insert("message", entry.message().to_owned());
+    /// Formats an entry straight in to `w`. Unlike format_entry this doesn't
+    /// need exclusive access to the Format, so a single Format can be shared
+    /// between threads as long as each one has its own `data`.
+    pub fn write_entry(
+        &self,
+        entry: &Entry,
+        data: &mut TemplateData,
+        mut w: impl Write,
+    ) -> Result<()> {
+        if self.color {
+            return Ok(self
+                .renderer
+                .render_to_write("template", data.fill(entry)?, w)?);
+        }
+
+        let s = self.renderer.render("template", data.fill(entry)?)?;
+        Ok(w.write_all(text::strip_ansi(&s).as_bytes())?)
+    }
+}
+
+fn renderer<'a>(template: &str) -> Result<Handlebars<'a>> {
+    let mut renderer = Handlebars::new();
+    renderer.set_strict_mode(true);
+    renderer.register_escape_fn(|s| s.trim().to_owned());
+    renderer.register_template_string("template", template)?;
+    renderer.register_helper("indent", Box::new(IndentHelper {}));
+    renderer.register_helper("wrap", Box::new(WrapHelper {}));
+    renderer.register_helper("strftime", Box::new(StrftimeHelper {}));
+    renderer.register_helper("color", Box::new(ColorHelper {}));
+    renderer.register_helper("markdown", Box::new(MarkdownHelper {}));
+    renderer.register_helper("emojify", Box::new(EmojifyHelper {}));
+    renderer.register_helper("autocolor", Box::new(AutocolorHelper { rules: vec![] }));
+    renderer.register_helper("wordcount", Box::new(WordCountHelper {}));
+    renderer.register_helper("readingtime", Box::new(ReadingTimeHelper {}));
+    renderer.register_helper("contains", Box::new(ContainsHelper {}));
+    renderer.register_helper("matches", Box::new(MatchesHelper::default()));
+    Ok(renderer)
+}
 
-        Ok(self.renderer.render("template", &self.data)?)
+/// Renders `template` with `data`, for things made from more than one entry,
+/// like hmms review's digest. The same helpers are available as when
+/// formatting entries.
+pub fn render<T: Serialize>(template: &str, data: &T, color: bool) -> Result<String> {
+    let s = renderer(template)?.render("template", data)?;
+    if color {
+        Ok(s)
+    } else {
+        Ok(text::strip_ansi(&s))
     }
 }
 
@@ -50,7 +466,35 @@ impl HelperDef for IndentHelper {
         out: &mut dyn Output,
     ) -> HelperResult {
         let param = h.param(0).unwrap();
-        Ok(out.write(&textwrap::indent(&param.value().render(), "│ "))?)
+        let width = width_param(h.hash_get("width"))?;
+        Ok(out.write(&text::indent(&param.value().render(), "│ ", width))?)
+    }
+}
+
+struct WrapHelper {}
+
+impl HelperDef for WrapHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let s = h.param(0).unwrap().value().render();
+        let width = width_param(h.param(1))?.unwrap_or_else(textwrap::termwidth);
+        Ok(out.write(&text::wrap(&s, width).join("\n"))?)
+    }
+}
+
+fn width_param(param: Option<&PathAndJson>) -> std::result::Result<Option<usize>, RenderError> {
+    match param {
+        None => Ok(None),
+        Some(p) => match p.value().as_u64() {
+            Some(width) => Ok(Some(width as usize)),
+            None => Err(RenderError::new("width must be a positive number")),
+        },
     }
 }
 
@@ -67,7 +511,7 @@ impl HelperDef for StrftimeHelper {
     ) -> HelperResult {
         let date_str = h.param(1).unwrap().value().render();
         let date = DateTime::parse_from_rfc3339(&date_str)
-            .map_err(|_| handlebars::RenderError::new("couldn't parse date"))?;
+            .map_err(|_| RenderError::new("couldn't parse date"))?;
         let local_date = date.with_timezone(&Local);
 
         let format_str = h.param(0).unwrap().value().render();
@@ -87,9 +531,13 @@ impl HelperDef for ColorHelper {
         _: &mut RenderContext,
         out: &mut dyn Output,
     ) -> HelperResult {
-        let color = h.param(0).unwrap().value().render();
+        // We write the escape codes ourselves rather than going through
+        // colored's Display impl, which decides whether to color things based
+        // on whether stdout is a terminal. Whether output is colored is up to
+        // the Format, which strips them out again if needed.
+        let color = Color::from(h.param(0).unwrap().value().render());
         let s = h.param(1).unwrap().value().render();
-        Ok(out.write(&format!("{}", s.color(color)))?)
+        Ok(out.write(&format!("\x1b[{}m{}\x1b[0m", color.to_fg_str(), s))?)
     }
 }
 
@@ -109,15 +557,181 @@ impl HelperDef for MarkdownHelper {
     }
 }
 
+struct EmojifyHelper {}
+
+impl HelperDef for EmojifyHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let s = h.param(0).unwrap().value().render();
+        Ok(out.write(&text::emojify(&s))?)
+    }
+}
+
+// Styles its argument with the first of the color rules that applies to the
+// entry being formatted.
+struct AutocolorHelper {
+    rules: Vec<ColorRule>,
+}
+
+impl HelperDef for AutocolorHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper,
+        _: &Handlebars,
+        ctx: &Context,
+        _: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let s = h.param(0).unwrap().value().render();
+        let data = ctx.data();
+        let message = data.get("message").and_then(Value::as_str).unwrap_or("");
+        let weekday = data
+            .pointer("/date/weekday")
+            .and_then(Value::as_str)
+            .and_then(|w| w.parse().ok());
+
+        match self.rules.iter().find(|r| r.applies(message, weekday)) {
+            Some(rule) => Ok(out.write(&rule.style(&s))?),
+            None => Ok(out.write(&s)?),
+        }
+    }
+}
+
+// Returns a number rather than writing one, so that it can be compared with
+// helpers like gt as well as printed.
+struct WordCountHelper {}
+
+impl HelperDef for WordCountHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> std::result::Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let s = h
+            .param(0)
+            .map(|p| p.value().render())
+            .ok_or_else(|| RenderError::new("wordcount needs an argument"))?;
+        Ok(ScopedJson::Derived(Value::from(text::word_count(&s))))
+    }
+}
+
+struct ReadingTimeHelper {}
+
+impl HelperDef for ReadingTimeHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let s = h
+            .param(0)
+            .map(|p| p.value().render())
+            .ok_or_else(|| RenderError::new("readingtime needs an argument"))?;
+        Ok(out.write(&date::format_duration(text::reading_time(&s)))?)
+    }
+}
+
+// Renders the `i`th parameter passed to helper `name`, which the conditional
+// helpers use so that leaving one out is an error rather than a panic.
+fn string_param(h: &Helper, name: &str, i: usize) -> std::result::Result<String, RenderError> {
+    h.param(i)
+        .map(|p| p.value().render())
+        .ok_or_else(|| RenderError::new(format!("{} needs 2 arguments", name)))
+}
+
+// Whether the first argument contains the second, for conditions like
+// {{#if (contains message "TODO")}}.
+struct ContainsHelper {}
+
+impl HelperDef for ContainsHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> std::result::Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let s = string_param(h, "contains", 0)?;
+        let sub = string_param(h, "contains", 1)?;
+        Ok(ScopedJson::Derived(Value::Bool(s.contains(&sub))))
+    }
+}
+
+// Whether the first argument matches the regex in the second. Templates are
+// rendered once per entry, so each regex is only compiled the first time it's
+// used.
+#[derive(Default)]
+struct MatchesHelper {
+    regexes: Mutex<HashMap<String, Regex>>,
+}
+
+impl HelperDef for MatchesHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> std::result::Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let s = string_param(h, "matches", 0)?;
+        let pattern = string_param(h, "matches", 1)?;
+
+        let regex = {
+            let mut regexes = self.regexes.lock().unwrap();
+            match regexes.get(&pattern) {
+                Some(regex) => regex.clone(),
+                None => {
+                    let regex = Regex::new(&pattern).map_err(|e| {
+                        RenderError::new(format!("invalid regex \"{}\": {}", pattern, e))
+                    })?;
+                    regexes.insert(pattern, regex.clone());
+                    regex
+                }
+            }
+        };
+        Ok(ScopedJson::Derived(Value::Bool(regex.is_match(&s))))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use test_case::test_case;
 
     #[test_case("{{ message }}" => "hello world")]
-    #[test_case("{{ color \"blue\" message }}" => "hello world".blue().to_string())]
+    #[test_case("{{ color \"blue\" message }}" => "\x1b[34mhello world\x1b[0m")]
     #[test_case("{{ indent message }}" => "│ hello world")]
+    #[test_case("{{ indent message width=9 }}" => "│ hello\n│ world")]
+    #[test_case("{{ wrap message 5 }}" => "hello\nworld")]
     #[test_case("{{ strftime \"%Y-%m-%d %H:%M:%S\" datetime }}" => "2020-01-02 03:04:05")]
+    #[test_case("{{ title }}|{{ body }}" => "hello world|" ; "title and body")]
+    #[test_case("{{ id }}" => "83887bb8")]
+    #[test_case("{{ emojify \"hello :wave:\" }}" => "hello 👋")]
+    #[test_case("{{ wordcount message }}" => "2")]
+    #[test_case("{{#if (gt (wordcount message) 1) }}long{{/if}}" => "long" ; "comparing wordcount")]
+    #[test_case("{{ readingtime message }}" => "1m")]
+    #[test_case("[{{ since_prev }}]" => "[]" ; "since_prev of the first entry")]
+    #[test_case("{{ date.year }}-{{ date.month }}-{{ date.day }} {{ date.hour }}" => "2020-1-2 3")]
+    #[test_case("{{ date.weekday }}" => "Thursday")]
+    #[test_case("{{#if (eq date.weekday \"Thursday\") }}weekday{{/if}}" => "weekday" ; "comparing weekday")]
+    #[test_case("{{#if (lt date.hour 9) }}early{{else}}late{{/if}}" => "early" ; "comparing hour")]
+    #[test_case("{{#if (contains message \"world\") }}yes{{else}}no{{/if}}" => "yes" ; "contains")]
+    #[test_case("{{#if (contains message \"TODO\") }}yes{{else}}no{{/if}}" => "no" ; "doesn't contain")]
+    #[test_case("{{#if (matches message \"^h.*d$\") }}yes{{else}}no{{/if}}" => "yes" ; "matches")]
+    #[test_case("{{#if (matches message \"^world\") }}yes{{else}}no{{/if}}" => "no" ; "doesn't match")]
+    #[test_case("{{#if (eq (strftime \"%a\" datetime) \"Thu\") }}yes{{else}}no{{/if}}" => "yes" ; "eq strftime")]
+    #[test_case("{{#if metadata }}yes{{else}}no{{/if}}" => "no" ; "no metadata")]
     fn test_format(template: &str) -> String {
         Format::with_template(template)
             .unwrap()
@@ -127,4 +741,234 @@ mod tests {
             ))
             .unwrap()
     }
+
+    // Templates get whatever entries have as JSON, so metadata comes along
+    // without format knowing about any of its fields.
+    #[test_case("{{ metadata.mood }}" => "4")]
+    #[test_case("{{#if metadata }}yes{{else}}no{{/if}}" => "yes" ; "has metadata")]
+    fn test_format_metadata(template: &str) -> String {
+        let metadata = serde_json::json!({ "mood": 4 });
+        Format::with_template(template)
+            .unwrap()
+            .format_entry(
+                &Entry::new(
+                    DateTime::parse_from_rfc3339("2020-01-02T03:04:05Z").unwrap(),
+                    "hello world".to_owned(),
+                )
+                .with_metadata(metadata.as_object().unwrap().clone()),
+            )
+            .unwrap()
+    }
+
+    #[test_case("{{ contains message }}"                  ; "contains missing an argument")]
+    #[test_case("{{ matches message }}"                   ; "matches missing an argument")]
+    #[test_case("{{ readingtime }}"                       ; "readingtime missing an argument")]
+    #[test_case("{{#if (matches message \"(\") }}{{/if}}" ; "invalid regex")]
+    fn test_format_errors(template: &str) {
+        let result = Format::with_template(template)
+            .unwrap()
+            .format_entry(&Entry::new(
+                DateTime::parse_from_rfc3339("2020-01-02T03:04:05Z").unwrap(),
+                "hello world".to_owned(),
+            ));
+        assert_eq!(result.err().unwrap().kind(), "render");
+    }
+
+    #[test_case("{{ color \"blue\" message }}")]
+    #[test_case("{{ markdown \"**hello** world\" }}")]
+    fn test_format_without_color(template: &str) {
+        let mut format = Format::with_template(template).unwrap();
+        format.set_color(false);
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-02T03:04:05Z").unwrap(),
+            "hello world".to_owned(),
+        );
+
+        assert_eq!(format.format_entry(&entry).unwrap().trim(), "hello world");
+
+        let mut out = Vec::new();
+        format
+            .write_entry(&entry, &mut TemplateData::default(), &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().trim(), "hello world");
+    }
+
+    #[test_case("auto"   => ColorMode::Auto)]
+    #[test_case("always" => ColorMode::Always)]
+    #[test_case("never"  => ColorMode::Never)]
+    fn test_color_mode_from_str(s: &str) -> ColorMode {
+        s.parse().unwrap()
+    }
+
+    fn color_rules(json: &str) -> std::result::Result<Vec<ColorRule>, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    // 2020-01-04 was a Saturday.
+    #[test_case("2020-01-04T12:00:00Z", "fixed #bug"    => "\x1b[31mfixed #bug\x1b[0m"   ; "tag")]
+    #[test_case("2020-01-04T12:00:00Z", "lunch"         => "\x1b[2mlunch\x1b[0m"         ; "weekday")]
+    #[test_case("2020-01-06T12:00:00Z", "lunch #food"   => "\x1b[2;32mlunch #food\x1b[0m" ; "tag and weekday")]
+    #[test_case("2020-01-06T12:00:00Z", "lunch"         => "lunch"                       ; "no rule applies")]
+    fn test_autocolor(datetime: &str, message: &str) -> String {
+        let rules = color_rules(
+            r##"[
+                {"tag": "#bug", "color": "red"},
+                {"weekday": "Sat", "dim": true},
+                {"tag": "food", "weekday": "monday", "color": "green", "dim": true}
+            ]"##,
+        )
+        .unwrap();
+        let mut format = Format::with_template("{{ autocolor message }}").unwrap();
+        format.set_color_rules(rules);
+        format
+            .format_entry(&Entry::new(
+                DateTime::parse_from_rfc3339(datetime).unwrap(),
+                message.to_owned(),
+            ))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_autocolor_without_rules() {
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-04T12:00:00Z").unwrap(),
+            "hello #bug".to_owned(),
+        );
+        let mut format = Format::with_template("{{ autocolor message }}").unwrap();
+        assert_eq!(format.format_entry(&entry).unwrap(), "hello #bug");
+    }
+
+    #[test_case(r#"[{"tag": "bug"}]"#                       => "color rules need a color or dim = true" ; "no style")]
+    #[test_case(r#"[{"weekday": "Caturday", "dim": true}]"# => "unknown weekday \"Caturday\" in color rule" ; "weekday")]
+    #[test_case(r#"[{"color": "bluish"}]"#                  => "unknown color \"bluish\" in color rule" ; "color")]
+    fn test_color_rule_errors(json: &str) -> String {
+        let err = color_rules(json).err().unwrap();
+        err.split(" at line").next().unwrap().to_owned()
+    }
+
+    #[test_case(GroupBy::Day,   "2024-05-15 10:00:00" => "2024-05-15" ; "day")]
+    #[test_case(GroupBy::Week,  "2024-05-15 10:00:00" => "2024-05-13" ; "week")]
+    #[test_case(GroupBy::Week,  "2024-05-13 00:00:00" => "2024-05-13" ; "monday")]
+    #[test_case(GroupBy::Week,  "2024-05-19 23:59:59" => "2024-05-13" ; "sunday")]
+    #[test_case(GroupBy::Month, "2024-05-15 10:00:00" => "2024-05-01" ; "month")]
+    fn test_group_by_period(group_by: GroupBy, local: &str) -> String {
+        let local = NaiveDateTime::parse_from_str(local, "%Y-%m-%d %H:%M:%S").unwrap();
+        let datetime = Local.from_local_datetime(&local).unwrap().fixed_offset();
+        group_by.period(&datetime).to_string()
+    }
+
+    #[test_case(GroupBy::Day,   "2024-02-29" => "2024-03-01" ; "day")]
+    #[test_case(GroupBy::Week,  "2024-05-13" => "2024-05-20" ; "week")]
+    #[test_case(GroupBy::Month, "2024-12-01" => "2025-01-01" ; "month")]
+    fn test_group_by_next(group_by: GroupBy, period: &str) -> String {
+        group_by.next(period.parse().unwrap()).to_string()
+    }
+
+    #[test]
+    fn test_format_since_prev() {
+        let mut format = Format::with_template("{{ since_prev }}").unwrap();
+        assert!(format.uses_previous());
+        let formatted: Vec<String> = [
+            "2020-01-02T09:00:00Z",
+            "2020-01-02T10:30:00Z",
+            "2020-01-03T10:30:05Z",
+        ]
+        .iter()
+        .map(|datetime| {
+            let entry = Entry::new(
+                DateTime::parse_from_rfc3339(datetime).unwrap(),
+                "hello world".to_owned(),
+            );
+            format.format_entry(&entry).unwrap()
+        })
+        .collect();
+        assert_eq!(formatted, vec!["", "1h30m", "1d5s"]);
+        assert!(!Format::with_template("{{ message }}")
+            .unwrap()
+            .uses_previous());
+    }
+
+    #[test_case(GroupBy::Day   => "## 2024-05-12 (2 entries, 3 words, 09:00 to 17:30 (8h30m))")]
+    #[test_case(GroupBy::Week  => "## Week of 2024-05-12")]
+    #[test_case(GroupBy::Month => "## May 2024")]
+    fn test_format_heading(group_by: GroupBy) -> String {
+        let mut totals = GroupTotals::default();
+        for (time, message) in [("17:30", "hello world"), ("09:00", "hi")] {
+            let datetime =
+                NaiveDateTime::parse_from_str(&format!("2024-05-12 {}", time), "%Y-%m-%d %H:%M")
+                    .unwrap();
+            let datetime = Local.from_local_datetime(&datetime).unwrap().fixed_offset();
+            totals.add(&Entry::new(datetime, message.to_owned()));
+        }
+        Format::with_template(group_by.default_heading())
+            .unwrap()
+            .format_heading(NaiveDate::from_ymd_opt(2024, 5, 12).unwrap(), &totals)
+            .unwrap()
+    }
+
+    #[test_case(&[]                            => "0 entries, 0 words"                     ; "nothing")]
+    #[test_case(&[("09:00", "one")]            => "1 entry, 1 word, 09:00"                 ; "one entry")]
+    #[test_case(&[("09:00", ""), ("09:00", "")] => "2 entries, 0 words, 09:00"             ; "same time")]
+    #[test_case(&[("09:00", "a b"), ("10:15", "c")] => "2 entries, 3 words, 09:00 to 10:15 (1h15m)" ; "span")]
+    fn test_group_totals_summary(entries: &[(&str, &str)]) -> String {
+        let mut totals = GroupTotals::default();
+        for (time, message) in entries {
+            let datetime =
+                NaiveDateTime::parse_from_str(&format!("2024-05-12 {}", time), "%Y-%m-%d %H:%M")
+                    .unwrap();
+            let datetime = Local.from_local_datetime(&datetime).unwrap().fixed_offset();
+            totals.add(&Entry::new(datetime, message.to_string()));
+        }
+        totals.summary()
+    }
+
+    #[test]
+    fn test_format_data() {
+        let mut format = Format::with_template("{{ color \"blue\" c1 }}").unwrap();
+        let data = serde_json::json!({ "c1": "hello" });
+        assert_eq!(format.format_data(&data).unwrap(), "\x1b[34mhello\x1b[0m");
+        format.set_color(false);
+        assert_eq!(format.format_data(&data).unwrap(), "hello");
+        assert!(Format::with_template("{{ c1 }}")
+            .unwrap()
+            .format_data(&serde_json::json!({}))
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_entry() {
+        let format = Format::with_template("{{ datetime }} {{ message }}").unwrap();
+        let mut data = TemplateData::default();
+        let mut out = Vec::new();
+        for message in &["hello", "world"] {
+            let entry = Entry::new(
+                DateTime::parse_from_rfc3339("2020-01-02T03:04:05Z").unwrap(),
+                message.to_string(),
+            );
+            format.write_entry(&entry, &mut data, &mut out).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2020-01-02T03:04:05+00:00 hello2020-01-02T03:04:05+00:00 world"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let data = serde_json::json!({
+            "start": "2024-05-13T00:00:00+00:00",
+            "tags": [{"tag": "work", "count": 3}, {"tag": "home", "count": 1}],
+        });
+        let template =
+            "{{ strftime \"%d %b\" start }}:{{#each tags}} #{{ tag }} {{ count }}{{/each}}";
+        assert_eq!(
+            render(template, &data, true).unwrap(),
+            "13 May: #work 3 #home 1"
+        );
+        assert_eq!(
+            render("{{ color \"blue\" start }}", &data, false).unwrap(),
+            "2024-05-13T00:00:00+00:00"
+        );
+        assert!(render("{{ missing }}", &data, true).is_err());
+    }
 }
@@ -0,0 +1,216 @@
+//! Front matter at the top of entries composed in an editor, which sets
+//! things about the entry rather than being part of its message. It's either
+//! a Markdown style block:
+//!
+//! ```text
+//! ---
+//! tags: work, ideas
+//! date: 2024-05-13T09:00
+//! mood: 4
+//! ---
+//! ```
+//!
+//! or org-mode style keywords, like `#+tags: work ideas`, on the first lines.
+
+use super::{date, entry, entry::Metadata, error::Error, mood, Result};
+use chrono::prelude::*;
+
+/// What front matter says about an entry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrontMatter {
+    /// Tags to add to the message, from `tags:`, without their #s.
+    pub tags: Vec<String>,
+
+    /// When the entry should be written at, from `date:`, which is any prefix
+    /// of an RFC3339 date like hmmq's --start.
+    pub date: Option<DateTime<FixedOffset>>,
+
+    /// `mood:`, which has to be between 1 and 5, and anything else, as
+    /// strings, to store with the entry.
+    pub metadata: Metadata,
+}
+
+/// Splits front matter off the top of `s`, returning it along with the rest
+/// of `s`. Text without any front matter comes back as it is. A Markdown
+/// style block is only front matter if every line in it is a `key: value`
+/// pair, so that entries starting with a horizontal rule are left alone.
+pub fn split(s: &str) -> Result<(FrontMatter, &str)> {
+    let (pairs, rest) = match markdown(s).or_else(|| org(s)) {
+        Some(found) => found,
+        None => return Ok((FrontMatter::default(), s)),
+    };
+
+    let mut front_matter = FrontMatter::default();
+    for (key, value) in pairs {
+        let key = key.to_lowercase();
+        match key.as_str() {
+            "tags" => {
+                for tag in value.split(|c: char| c == ',' || c.is_whitespace()) {
+                    let tag = tag.strip_prefix('#').unwrap_or(tag);
+                    if tag.is_empty() {
+                        continue;
+                    }
+                    if entry::tags(&format!("#{}", tag)) != [tag] {
+                        return Err(Error::InvalidArgument(format!(
+                            "\"{}\" in the front matter isn't a tag, tags are made of letters, numbers, \"_\", \"-\" and \"/\"",
+                            tag
+                        )));
+                    }
+                    front_matter.tags.push(tag.to_owned());
+                }
+            }
+            "date" => front_matter.date = Some(date::parse(value)?),
+            "mood" => {
+                let mood = value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|mood| (mood::MIN..=mood::MAX).contains(mood))
+                    .ok_or_else(|| {
+                        Error::InvalidArgument(format!(
+                            "the mood in the front matter has to be between {} and {}, not \"{}\"",
+                            mood::MIN,
+                            mood::MAX,
+                            value
+                        ))
+                    })?;
+                front_matter.metadata.insert(key, mood.into());
+            }
+            _ => {
+                front_matter.metadata.insert(key, value.into());
+            }
+        }
+    }
+    Ok((front_matter, rest))
+}
+
+impl FrontMatter {
+    /// Adds the tags to the end of `message`, on the same line if it only has
+    /// one and after a blank line otherwise. Tags that are already in the
+    /// message aren't added again.
+    pub fn tag(&self, message: &str) -> String {
+        let message = message.trim();
+        let existing = entry::tags(message);
+        let mut tags: Vec<String> = Vec::new();
+        for tag in &self.tags {
+            if !existing.contains(&tag.as_str()) && !tags.contains(tag) {
+                tags.push(format!("#{}", tag));
+            }
+        }
+        let tags = tags.join(" ");
+        match (tags.as_str(), message) {
+            ("", _) => message.to_owned(),
+            (tags, "") => tags.to_owned(),
+            (tags, message) if message.contains('\n') => format!("{}\n\n{}", message, tags),
+            (tags, message) => format!("{} {}", message, tags),
+        }
+    }
+}
+
+fn pair(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    let key = key.trim();
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    valid.then(|| (key, value.trim()))
+}
+
+type Found<'a> = (Vec<(&'a str, &'a str)>, &'a str);
+
+fn markdown(s: &str) -> Option<Found<'_>> {
+    let mut lines = s.split_inclusive('\n');
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+    let mut pairs = Vec::new();
+    let mut end = 0;
+    for line in lines {
+        end += line.len();
+        match line.trim_end() {
+            "---" => {
+                let start = s.find('\n')? + 1;
+                return Some((pairs, &s[start + end..]));
+            }
+            "" => {}
+            line => pairs.push(pair(line)?),
+        }
+    }
+    None
+}
+
+fn org(s: &str) -> Option<Found<'_>> {
+    let mut pairs = Vec::new();
+    let mut end = 0;
+    for line in s.split_inclusive('\n') {
+        match line.strip_prefix("#+").and_then(pair) {
+            Some(pair) => pairs.push(pair),
+            None => break,
+        }
+        end += line.len();
+    }
+    if pairs.is_empty() {
+        return None;
+    }
+    Some((pairs, &s[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("---\ntags: work, #ideas\nmood: 4\n---\nhello\n", "work ideas", Some(4), "hello\n" ; "markdown")]
+    #[test_case("---\r\ntags: work\r\n\r\n---\r\nhello", "work", None, "hello" ; "crlf and blank lines")]
+    #[test_case("#+TAGS: work ideas\n#+mood: 2\nhello\n#+tags: not front matter", "work ideas", Some(2), "hello\n#+tags: not front matter" ; "org")]
+    #[test_case("---\nnot: front\nmatter\n---\nhello", "", None, "---\nnot: front\nmatter\n---\nhello" ; "not all pairs")]
+    #[test_case("---\ntags: work\nhello", "", None, "---\ntags: work\nhello" ; "unclosed")]
+    #[test_case("hello\n---\ntags: work\n---\n", "", None, "hello\n---\ntags: work\n---\n" ; "not at the top")]
+    #[test_case("# heading\nhello", "", None, "# heading\nhello" ; "comment")]
+    #[test_case("", "", None, "" ; "empty")]
+    fn test_split(s: &str, tags: &str, mood: Option<u64>, rest: &str) {
+        let (front_matter, after) = split(s).unwrap();
+        assert_eq!(front_matter.tags.join(" "), tags);
+        assert_eq!(
+            front_matter.metadata.get("mood").and_then(|v| v.as_u64()),
+            mood
+        );
+        assert_eq!(after, rest);
+    }
+
+    #[test]
+    fn test_split_date_and_metadata() {
+        let (front_matter, rest) =
+            split("---\ndate: 2024-05-13T09:30\nWeather: sunny: warm\n---\nhello").unwrap();
+        assert_eq!(rest, "hello");
+        assert_eq!(
+            front_matter.date.unwrap().naive_local(),
+            NaiveDate::from_ymd_opt(2024, 5, 13)
+                .unwrap()
+                .and_hms_opt(9, 30, 0)
+                .unwrap()
+        );
+        assert_eq!(front_matter.metadata["weather"], "sunny: warm");
+    }
+
+    #[test_case("---\ndate: someday\n---\n" ; "bad date")]
+    #[test_case("---\nmood: 6\n---\n"       ; "mood too high")]
+    #[test_case("#+mood: great\n"          ; "mood not a number")]
+    #[test_case("---\ntags: a.b\n---\n"     ; "bad tag")]
+    fn test_split_errors(s: &str) {
+        assert!(split(s).is_err());
+    }
+
+    #[test_case(&["work"], "hello"                 => "hello #work"                ; "one line")]
+    #[test_case(&["work", "ideas"], "a\nb\n"        => "a\nb\n\n#work #ideas"       ; "several lines")]
+    #[test_case(&["work", "work"], "hello #work"    => "hello #work"                ; "already tagged")]
+    #[test_case(&["work"], ""                      => "#work"                      ; "empty message")]
+    #[test_case(&[], "hello\n"                     => "hello"                      ; "no tags")]
+    fn test_tag(tags: &[&str], message: &str) -> String {
+        let front_matter = FrontMatter {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..FrontMatter::default()
+        };
+        front_matter.tag(message)
+    }
+}
@@ -0,0 +1,105 @@
+//! Fuzzy matching, for picking entries out of a journal by typing a few
+//! letters from them, like hmmf does.
+
+// Matching the first letter of a word, or the letter after the last one that
+// matched, makes a match look much more like what was meant.
+const MATCH: i64 = 16;
+const WORD_START: i64 = 8;
+const CONSECUTIVE: i64 = 8;
+
+/// How well `query` matches `candidate`, or None if it doesn't. Each word in
+/// `query` has to appear in `candidate` with its letters in order, though not
+/// necessarily next to each other. Words are matched ignoring case unless they
+/// have an upper case letter in them. Higher scores are better matches, and an
+/// empty query matches everything with a score of 0.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let chars: Vec<char> = candidate.chars().collect();
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    query.split_whitespace().try_fold(0, |total, term| {
+        let term: Vec<char> = term.chars().collect();
+        // Lower casing can change how many characters there are, in which
+        // case only case sensitive matching lines up with the candidate.
+        let haystack = if term.iter().any(|c| c.is_uppercase()) || lower.len() != chars.len() {
+            &chars
+        } else {
+            &lower
+        };
+        best_match(&term, haystack, &chars).map(|score| total + score)
+    })
+}
+
+/// The indexes of the `candidates` that match `query`, best match first.
+/// Candidates that match equally well stay in the order they were given in.
+pub fn rank<S: AsRef<str>>(query: &str, candidates: &[S]) -> Vec<usize> {
+    let mut matches: Vec<(usize, i64)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, candidate)| score(query, candidate.as_ref()).map(|score| (i, score)))
+        .collect();
+    matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(i, _)| i).collect()
+}
+
+// Tries matching `term` starting from each place its first letter appears,
+// taking the letters after that greedily, and keeps the best score.
+fn best_match(term: &[char], haystack: &[char], original: &[char]) -> Option<i64> {
+    let first = *term.first()?;
+    let mut best = None;
+    for start in (0..haystack.len()).filter(|&i| haystack[i] == first) {
+        let mut score = 0;
+        let mut last = None;
+        let mut next = start;
+        for &c in term {
+            let i = next + haystack[next..].iter().position(|&h| h == c)?;
+            score += MATCH;
+            if i == 0 || !original[i - 1].is_alphanumeric() {
+                score += WORD_START;
+            }
+            match last {
+                Some(last) if i == last + 1 => score += CONSECUTIVE,
+                // Every letter skipped over makes it a little worse.
+                Some(last) => score -= (i - last - 1) as i64,
+                None => {}
+            }
+            last = Some(i);
+            next = i + 1;
+        }
+        best = best.max(Some(score));
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("", "anything"                  => true  ; "empty query")]
+    #[test_case("hlo", "hello"                  => true  ; "subsequence")]
+    #[test_case("olh", "hello"                  => false ; "out of order")]
+    #[test_case("HELLO", "hello"                => false ; "upper case is case sensitive")]
+    #[test_case("hello", "HELLO"                => true  ; "lower case ignores case")]
+    #[test_case("wor hel", "hello world"        => true  ; "words in any order")]
+    #[test_case("hel xyz", "hello world"        => false ; "every word has to match")]
+    #[test_case("café", "CAFÉ au lait"          => true  ; "non-ascii")]
+    #[test_case("hello", ""                     => false ; "empty candidate")]
+    fn test_matches(query: &str, candidate: &str) -> bool {
+        score(query, candidate).is_some()
+    }
+
+    #[test_case("meet", "meeting notes", "make everything easier to test" ; "consecutive letters")]
+    #[test_case("bs", "bug squashing", "absent" ; "word starts")]
+    #[test_case("ab", "a b", "a    b" ; "smaller gaps")]
+    fn test_better(query: &str, better: &str, worse: &str) {
+        let better = score(query, better).unwrap();
+        let worse = score(query, worse).unwrap();
+        assert!(better > worse, "{} <= {}", better, worse);
+    }
+
+    #[test_case("", vec!["a", "b", "c"]                             => vec![0, 1, 2] ; "empty query keeps order")]
+    #[test_case("fix", vec!["first xylophone", "fix bug", "nope"]   => vec![1, 0]    ; "best first")]
+    #[test_case("lunch", vec!["lunch", "dinner", "lunch"]           => vec![0, 2]    ; "ties keep order")]
+    fn test_rank(query: &str, candidates: Vec<&str>) -> Vec<usize> {
+        rank(query, &candidates)
+    }
+}
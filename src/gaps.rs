@@ -0,0 +1,94 @@
+//! Finding the stretches of time nothing was written in, for hmms gaps.
+
+use super::entry::Entry;
+use chrono::prelude::*;
+use chrono::Duration;
+
+/// A stretch of time with no entries in it, from the entry before it to the
+/// entry after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+}
+
+impl Gap {
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+}
+
+/// Collects the gaps between entries that are at least as long as a minimum.
+/// Entries have to be pushed in the order they were written.
+#[derive(Debug)]
+pub struct Gaps {
+    min: Duration,
+    last: Option<DateTime<FixedOffset>>,
+    gaps: Vec<Gap>,
+}
+
+impl Gaps {
+    pub fn new(min: Duration) -> Self {
+        Gaps {
+            min,
+            last: None,
+            gaps: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: &Entry) {
+        let datetime = *entry.datetime();
+        if let Some(last) = self.last {
+            self.add(last, datetime);
+        }
+        self.last = Some(datetime);
+    }
+
+    /// The gaps found, oldest first, counting the time from the last entry to
+    /// `end` as a gap too, so that one that hasn't ended yet is included.
+    pub fn finish(mut self, end: DateTime<FixedOffset>) -> Vec<Gap> {
+        if let Some(last) = self.last {
+            self.add(last, end);
+        }
+        self.gaps
+    }
+
+    fn add(&mut self, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) {
+        let gap = Gap { start, end };
+        if gap.duration() >= self.min {
+            self.gaps.push(gap);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn datetime(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(&format!("{}:00+00:00", s)).unwrap()
+    }
+
+    #[test_case(&["2024-05-01T09:00", "2024-05-02T09:00", "2024-05-06T09:00"], 3, "2024-05-07T09:00" => vec!["2024-05-02T09:00/2024-05-06T09:00"] ; "between entries")]
+    #[test_case(&["2024-05-01T09:00", "2024-05-04T09:00"], 3, "2024-05-04T10:00" => vec!["2024-05-01T09:00/2024-05-04T09:00"] ; "exactly the minimum")]
+    #[test_case(&["2024-05-01T09:00", "2024-05-02T09:00"], 3, "2024-05-09T09:00" => vec!["2024-05-02T09:00/2024-05-09T09:00"] ; "not ended yet")]
+    #[test_case(&["2024-05-01T09:00", "2024-05-02T09:00"], 3, "2024-05-03T09:00" => Vec::<String>::new() ; "no gaps")]
+    #[test_case(&[], 3, "2024-05-03T09:00" => Vec::<String>::new() ; "no entries")]
+    fn test_gaps(entries: &[&str], days: i64, end: &str) -> Vec<String> {
+        let mut gaps = Gaps::new(Duration::days(days));
+        for s in entries {
+            gaps.push(&Entry::new(datetime(s), String::new()));
+        }
+        gaps.finish(datetime(end))
+            .into_iter()
+            .map(|gap| {
+                format!(
+                    "{}/{}",
+                    gap.start.format("%Y-%m-%dT%H:%M"),
+                    gap.end.format("%Y-%m-%dT%H:%M")
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,269 @@
+//! A graph of the entries in a journal and the #tags, @mentions and links to
+//! other entries in them, for hmmq --graph to export to tools like Graphviz.
+
+use super::{entry::Entry, error::Error, text, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// How many columns of an entry's title are used as its label.
+const LABEL_WIDTH: usize = 40;
+
+/// What a graph is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Graphviz's DOT language.
+    Dot,
+    /// A JSON object with a list of nodes and a list of edges.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown graph format \"{}\", expected dot or json",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Entry,
+    Tag,
+    Mention,
+}
+
+/// An entry, tag or mention. IDs are what they'd be written as in a message,
+/// like "^cbbf4083", "#work" and "@sam", so they can't clash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: Kind,
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+}
+
+/// An entry tagging, mentioning or linking to another node, whose kind says
+/// which.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    #[serde(rename = "type")]
+    pub kind: Kind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// Builds the graph of `entries`. Entries that don't tag, mention or link
+    /// to anything, and aren't linked to, are left out, as are links to
+    /// entries that aren't in `entries`. Nodes and edges are in the order
+    /// they first appear.
+    pub fn build(entries: &[Entry]) -> Graph {
+        let ids: HashSet<String> = entries.iter().map(Entry::id).collect();
+        let linked: HashSet<String> = entries
+            .iter()
+            .flat_map(Entry::links)
+            .filter(|id| ids.contains(id))
+            .collect();
+
+        let mut graph = Graph::default();
+        let mut seen = HashSet::new();
+        for entry in entries {
+            let id = entry.id();
+            let mut edges = Vec::new();
+            for tag in entry.tags() {
+                edges.push((format!("#{}", tag), Kind::Tag));
+            }
+            for mention in entry.mentions() {
+                edges.push((format!("@{}", mention), Kind::Mention));
+            }
+            for link in entry.links() {
+                if ids.contains(&link) {
+                    edges.push((format!("^{}", link), Kind::Entry));
+                }
+            }
+            if edges.is_empty() && !linked.contains(&id) {
+                continue;
+            }
+
+            let source = format!("^{}", id);
+            if seen.insert(source.clone()) {
+                graph.nodes.push(Node {
+                    id: source.clone(),
+                    kind: Kind::Entry,
+                    label: text::truncate(entry.title().trim(), LABEL_WIDTH, "…"),
+                    datetime: Some(entry.datetime().to_rfc3339()),
+                });
+            }
+            let mut targets = HashSet::new();
+            for (target, kind) in edges {
+                if !targets.insert(target.clone()) {
+                    continue;
+                }
+                // Entries get their node when they're reached in the loop,
+                // so that it has their title.
+                if kind != Kind::Entry && seen.insert(target.clone()) {
+                    graph.nodes.push(Node {
+                        id: target.clone(),
+                        kind,
+                        label: target.clone(),
+                        datetime: None,
+                    });
+                }
+                graph.edges.push(Edge {
+                    source: source.clone(),
+                    target,
+                    kind,
+                });
+            }
+        }
+        graph
+    }
+
+    pub fn write(&self, format: Format) -> Result<String> {
+        match format {
+            Format::Dot => Ok(self.to_dot()),
+            Format::Json => Ok(serde_json::to_string_pretty(self)? + "\n"),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph hmm {\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                Kind::Entry => "box",
+                Kind::Tag => "ellipse",
+                Kind::Mention => "diamond",
+            };
+            dot.push_str(&format!(
+                "    {} [label={}, shape={}];\n",
+                quote(&node.id),
+                quote(&node.label),
+                shape
+            ));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!(
+                "    {} -> {};\n",
+                quote(&edge.source),
+                quote(&edge.target)
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// A DOT string, which can have anything in it but quotes, which are escaped
+// with a backslash. Backslashes are escaped too so that they aren't read as
+// escapes like \n.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+    use test_case::test_case;
+
+    fn entry(minute: u32, message: &str) -> Entry {
+        let datetime = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2024, 6, 1, 9, minute, 0)
+            .unwrap();
+        Entry::new(datetime, message.to_owned())
+    }
+
+    #[test]
+    fn test_build() {
+        let first = entry(0, "Planning the \"move\" #home\nwith @sam #home");
+        let second = entry(1, &format!("see ^{} and ^00000000 #home", first.id()));
+        let lonely = entry(2, "nothing to see here");
+        let graph = Graph::build(&[first.clone(), second.clone(), lonely]);
+
+        let nodes: Vec<(&str, Kind, &str)> = graph
+            .nodes
+            .iter()
+            .map(|n| (n.id.as_str(), n.kind, n.label.as_str()))
+            .collect();
+        let first_id = format!("^{}", first.id());
+        let second_id = format!("^{}", second.id());
+        assert_eq!(
+            nodes,
+            vec![
+                (
+                    first_id.as_str(),
+                    Kind::Entry,
+                    "Planning the \"move\" #home"
+                ),
+                ("#home", Kind::Tag, "#home"),
+                ("@sam", Kind::Mention, "@sam"),
+                (second_id.as_str(), Kind::Entry, second.title()),
+            ]
+        );
+
+        let edges: Vec<(&str, &str, Kind)> = graph
+            .edges
+            .iter()
+            .map(|e| (e.source.as_str(), e.target.as_str(), e.kind))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                (first_id.as_str(), "#home", Kind::Tag),
+                (first_id.as_str(), "@sam", Kind::Mention),
+                (second_id.as_str(), "#home", Kind::Tag),
+                (second_id.as_str(), first_id.as_str(), Kind::Entry),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let graph = Graph::build(&[entry(0, "a \\ \"b\" #x")]);
+        let id = format!("^{}", entry(0, "").id());
+        assert_eq!(
+            graph.write(Format::Dot).unwrap(),
+            format!(
+                "digraph hmm {{\n    \"{id}\" [label=\"a \\\\ \\\"b\\\" #x\", shape=box];\n    \"#x\" [label=\"#x\", shape=ellipse];\n    \"{id}\" -> \"#x\";\n}}\n",
+                id = id
+            )
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let graph = Graph::build(&[entry(0, "@sam")]);
+        let json: serde_json::Value =
+            serde_json::from_str(&graph.write(Format::Json).unwrap()).unwrap();
+        assert_eq!(json["nodes"][0]["type"], "entry");
+        assert_eq!(json["nodes"][0]["datetime"], "2024-06-01T09:00:00+00:00");
+        assert_eq!(json["nodes"][1]["id"], "@sam");
+        assert!(json["nodes"][1].get("datetime").is_none());
+        assert_eq!(json["edges"][0]["type"], "mention");
+    }
+
+    #[test_case("dot"  => Ok(Format::Dot)  ; "dot")]
+    #[test_case("json" => Ok(Format::Json) ; "json")]
+    #[test_case("png"  => Err("unknown graph format \"png\", expected dot or json".to_owned()) ; "unknown")]
+    fn test_format(s: &str) -> std::result::Result<Format, String> {
+        s.parse().map_err(|e: Error| e.to_string())
+    }
+}
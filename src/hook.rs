@@ -0,0 +1,305 @@
+use super::{curl, entry::Entry, error::Error, Result};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Output, Stdio};
+
+/// The points at which `hmm` runs a command from the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    /// Run before an entry is written. Exiting unsuccessfully stops the entry
+    /// from being written.
+    PreAppend,
+
+    /// Run after an entry has been written.
+    PostAppend,
+
+    /// Run on an entry composed in an editor before it's written. Exiting
+    /// unsuccessfully gives the chance to edit it again.
+    Validate,
+}
+
+impl Hook {
+    /// The name of the hook, as used in the config file and passed to hooks in
+    /// the HMM_HOOK environment variable.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreAppend => "pre_append",
+            Hook::PostAppend => "post_append",
+            Hook::Validate => "validate",
+        }
+    }
+}
+
+/// Runs `command` before `message` is written to the hmm file at `path`,
+/// returning Error::HookRejected if it exits unsuccessfully.
+pub fn pre_append(command: &str, path: &Path, message: &str) -> Result<()> {
+    let status = run(Hook::PreAppend, command, path, message, None, false)
+        .map_err(|e| Error::HookRejected(format!("couldn't run `{}`: {}", command, e)))?
+        .status;
+
+    if !status.success() {
+        return Err(Error::HookRejected(describe(command, status)));
+    }
+    Ok(())
+}
+
+/// Runs `command` after `entry` has been written to the hmm file at `path`,
+/// returning Error::HookFailed if it exits unsuccessfully.
+pub fn post_append(command: &str, path: &Path, entry: &Entry) -> Result<()> {
+    let datetime = entry.datetime().to_rfc3339();
+    let status = run(
+        Hook::PostAppend,
+        command,
+        path,
+        entry.message(),
+        Some(&datetime),
+        false,
+    )
+    .map_err(|e| Error::HookFailed(format!("couldn't run `{}`: {}", command, e)))?
+    .status;
+
+    if !status.success() {
+        return Err(Error::HookFailed(describe(command, status)));
+    }
+    Ok(())
+}
+
+// How long a webhook gets to respond, each time it's tried, before giving up.
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+// How many more times a webhook is tried after failing in a way that might
+// not happen again, like timing out or responding with a 5xx status.
+const WEBHOOK_RETRIES: u32 = 3;
+
+/// POSTs `entry` as JSON to `url` after it's been written to the hmm file at
+/// `path`, returning Error::WebhookFailed if it still fails after retrying.
+/// The JSON is the entry's, with "path" and a "text" field with the message,
+/// which is what Slack and Slack-compatible webhooks like Discord's show.
+///
+/// Requests are made with curl, which needs to be installed. The URL and the
+/// entry are given to curl on stdin, as webhook URLs are often secrets and
+/// entries are private, and arguments can be seen by anyone on the machine.
+pub fn webhook(url: &str, path: &Path, entry: &Entry) -> Result<()> {
+    let mut body = serde_json::to_value(entry)?;
+    body["path"] = path.to_string_lossy().into();
+    body["text"] = entry.message().into();
+    let config = format!(
+        "url = {}\nheader = \"Content-Type: application/json\"\ndata-binary = {}\n",
+        curl::quote(url),
+        curl::quote(&body.to_string())
+    );
+
+    let out = curl::run(
+        curl::command()
+            .arg("--fail")
+            .args(["--max-time", &WEBHOOK_TIMEOUT_SECS.to_string()])
+            .args(["--retry", &WEBHOOK_RETRIES.to_string()]),
+        &config,
+    )
+    .map_err(|e| Error::WebhookFailed(format!("couldn't run curl: {}", e)))?;
+    if !out.status.success() {
+        return Err(Error::WebhookFailed(
+            String::from_utf8_lossy(&out.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `command`, the validate_command from the config file, on `message`,
+/// an entry that's been composed but not yet written to the hmm file at
+/// `path`. Returns None if it exits successfully, and otherwise what it
+/// printed, so that whoever wrote the entry can see what's wrong with it.
+pub fn validate(command: &str, path: &Path, message: &str) -> Result<Option<String>> {
+    let out = run(Hook::Validate, command, path, message, None, true)
+        .map_err(|e| Error::ValidateFailed(format!("couldn't run `{}`: {}", command, e)))?;
+    if out.status.success() {
+        return Ok(None);
+    }
+
+    let mut printed = String::from_utf8_lossy(&out.stdout).into_owned();
+    printed.push_str(&String::from_utf8_lossy(&out.stderr));
+    if printed.trim().is_empty() {
+        printed = describe(command, out.status);
+    }
+    Ok(Some(printed))
+}
+
+// Hooks are run through the shell, so that they can use pipes, ~ and so on
+// the same way they would if you typed them in yourself. The entry's message
+// is passed on stdin as well as in HMM_ENTRY_MESSAGE, because messages can be
+// longer than some systems allow environment variables to be. What they print
+// goes straight to the terminal unless it's captured.
+fn run(
+    hook: Hook,
+    command: &str,
+    path: &Path,
+    message: &str,
+    datetime: Option<&str>,
+    capture: bool,
+) -> io::Result<Output> {
+    let mut cmd = shell(command);
+    cmd.env("HMM_HOOK", hook.name())
+        .env("HMM_PATH", path)
+        .env("HMM_ENTRY_MESSAGE", message)
+        .stdin(Stdio::piped());
+    match datetime {
+        Some(datetime) => cmd.env("HMM_ENTRY_DATETIME", datetime),
+        None => cmd.env_remove("HMM_ENTRY_DATETIME"),
+    };
+    if capture {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    let mut child = cmd.spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    match stdin.write_all(message.as_bytes()) {
+        // Hooks don't have to read their stdin, and may well exit before
+        // we've finished writing to it.
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+        _ => {}
+    }
+    drop(stdin);
+
+    child.wait_with_output()
+}
+
+#[cfg(windows)]
+pub(crate) fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+pub(crate) fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+fn describe(command: &str, status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("`{}` exited with status {}", command, code),
+        None => format!("`{}` was killed by a signal", command),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+    use tiny_http::{Response, Server};
+
+    #[test_case("true"   => None                                            ; "success")]
+    #[test_case("exit 3" => Some("`exit 3` exited with status 3".to_owned()) ; "failure")]
+    #[test_case("test \"$HMM_ENTRY_MESSAGE\" = hello -a \"$HMM_HOOK\" = pre_append -a -z \"$HMM_ENTRY_DATETIME\"" => None ; "environment")]
+    #[test_case("test \"$(cat)\" = hello" => None                            ; "stdin")]
+    fn test_pre_append(command: &str) -> Option<String> {
+        match pre_append(command, Path::new("/tmp/hmm"), "hello") {
+            Ok(()) => None,
+            Err(Error::HookRejected(reason)) => Some(reason),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test_case("true"                          => None                                          ; "success")]
+    #[test_case("echo \"$(cat): spelling\"; exit 1" => Some("hello: spelling\n".to_owned())        ; "prints")]
+    #[test_case("echo oops >&2; exit 1"         => Some("oops\n".to_owned())                     ; "stderr")]
+    #[test_case("exit 2"                        => Some("`exit 2` exited with status 2".to_owned()) ; "silent")]
+    #[test_case("test \"$HMM_HOOK\" = validate"  => None                                          ; "environment")]
+    fn test_validate(command: &str) -> Option<String> {
+        validate(command, Path::new("/tmp/hmm"), "hello").unwrap()
+    }
+
+    #[test]
+    fn test_post_append() {
+        let out = NamedTempFile::new().unwrap();
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap(),
+            "hello\nworld".to_owned(),
+        );
+        let command = format!(
+            "printf '%s %s %s|' \"$HMM_HOOK\" \"$HMM_PATH\" \"$HMM_ENTRY_DATETIME\" > {0}; cat >> {0}",
+            out.path().display()
+        );
+
+        post_append(&command, Path::new("/tmp/hmm"), &entry).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(out.path()).unwrap(),
+            "post_append /tmp/hmm 2020-01-01T00:00:00+00:00|hello\nworld"
+        );
+    }
+
+    #[test]
+    fn test_post_append_failure() {
+        let entry = Entry::with_message("hello");
+        let err = post_append("false", Path::new("/tmp/hmm"), &entry)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "hook_failed");
+        assert_eq!(err.exit_code(), 70);
+    }
+
+    // Runs a server that answers every request with `status`, returning its
+    // URL and the bodies of the requests it gets.
+    fn serve(status: u16) -> (String, Arc<Mutex<Vec<String>>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr().to_ip().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+
+        let shared = bodies.clone();
+        thread::spawn(move || {
+            for mut req in server.incoming_requests() {
+                let mut body = String::new();
+                req.as_reader().read_to_string(&mut body).unwrap();
+                shared.lock().unwrap().push(body);
+                req.respond(Response::empty(status)).unwrap();
+            }
+        });
+
+        (url, bodies)
+    }
+
+    #[test]
+    fn test_webhook() {
+        let (url, bodies) = serve(200);
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap(),
+            "hello \"world\"\\\nagain ünïcödé".to_owned(),
+        );
+
+        webhook(&url, Path::new("/tmp/hmm"), &entry).unwrap();
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "datetime": "2020-01-01T00:00:00+00:00",
+                "message": "hello \"world\"\\\nagain ünïcödé",
+                "path": "/tmp/hmm",
+                "text": "hello \"world\"\\\nagain ünïcödé",
+            })
+        );
+    }
+
+    #[test_case(400, 1 ; "client error")]
+    #[test_case(503, 1 + WEBHOOK_RETRIES as usize ; "retries server errors")]
+    fn test_webhook_failure(status: u16, tries: usize) {
+        let (url, bodies) = serve(status);
+        let err = webhook(&url, Path::new("/tmp/hmm"), &Entry::with_message("hello"))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "webhook_failed");
+        assert_eq!(err.exit_code(), 70);
+        assert!(err.to_string().contains(&status.to_string()), "{}", err);
+        assert_eq!(bodies.lock().unwrap().len(), tries);
+    }
+}
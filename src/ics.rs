@@ -0,0 +1,293 @@
+use super::{entry::Entry, error::Error, Result};
+use chrono::prelude::*;
+use std::io::Write;
+use std::str::FromStr;
+
+/// How entries are turned in to calendar events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    /// An event at the time of each entry.
+    Entry,
+
+    /// An all-day event for each day with entries, listing all of them.
+    Day,
+}
+
+impl FromStr for Grouping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "entry" => Ok(Grouping::Entry),
+            "day" => Ok(Grouping::Day),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown grouping \"{}\", expected entry or day",
+                s
+            ))),
+        }
+    }
+}
+
+/// Writes `entries` to `w` as an iCalendar (RFC 5545) file that calendar apps
+/// can import. Each event's UID is derived from its entry, or its day, so
+/// importing the same entries again updates the events rather than
+/// duplicating them.
+pub fn write(mut w: impl Write, entries: &[Entry], grouping: Grouping) -> Result<()> {
+    begin(&mut w)?;
+    match grouping {
+        Grouping::Entry => {
+            for entry in entries {
+                let start = utc(entry.datetime());
+                let summary = entry.message().lines().next().unwrap_or_default();
+                line(&mut w, "BEGIN:VEVENT")?;
+                line(&mut w, &format!("UID:{}@hmm", uid(entry.datetime())))?;
+                line(&mut w, &format!("DTSTAMP:{}", start))?;
+                line(&mut w, &format!("DTSTART:{}", start))?;
+                line(&mut w, &format!("DTEND:{}", start))?;
+                line(&mut w, &format!("SUMMARY:{}", escape(summary)))?;
+                line(&mut w, &format!("DESCRIPTION:{}", escape(entry.message())))?;
+                line(&mut w, "END:VEVENT")?;
+            }
+        }
+        Grouping::Day => {
+            for day in days(entries) {
+                day_event(&mut w, day)?;
+            }
+        }
+    }
+    line(&mut w, "END:VCALENDAR")
+}
+
+/// The all-day event for each day with entries, the same as `Grouping::Day`
+/// writes, but each in an iCalendar file of its own along with its day. This
+/// is what CalDAV servers want, as they keep every event separately.
+pub fn day_calendars(entries: &[Entry]) -> Result<Vec<(NaiveDate, Vec<u8>)>> {
+    days(entries)
+        .into_iter()
+        .map(|day| {
+            let mut w = Vec::new();
+            begin(&mut w)?;
+            day_event(&mut w, day)?;
+            line(&mut w, "END:VCALENDAR")?;
+            Ok((day[0].datetime().date_naive(), w))
+        })
+        .collect()
+}
+
+fn begin(w: &mut impl Write) -> Result<()> {
+    line(w, "BEGIN:VCALENDAR")?;
+    line(w, "VERSION:2.0")?;
+    line(w, "PRODID:-//samwho//hmm//EN")
+}
+
+fn day_event(w: &mut impl Write, day: &[Entry]) -> Result<()> {
+    let date = day[0].datetime().date_naive();
+    let description: Vec<String> = day
+        .iter()
+        .map(|e| format!("{} {}", e.datetime().format("%H:%M"), e.message()))
+        .collect();
+    line(w, "BEGIN:VEVENT")?;
+    line(w, &format!("UID:{}@hmm", date.format("%Y%m%d")))?;
+    line(w, &format!("DTSTAMP:{}", utc(day[0].datetime())))?;
+    line(w, &format!("DTSTART;VALUE=DATE:{}", date.format("%Y%m%d")))?;
+    line(
+        w,
+        &format!(
+            "DTEND;VALUE=DATE:{}",
+            (date + chrono::Duration::days(1)).format("%Y%m%d")
+        ),
+    )?;
+    line(
+        w,
+        &format!(
+            "SUMMARY:{} hmm {}",
+            day.len(),
+            if day.len() == 1 { "entry" } else { "entries" }
+        ),
+    )?;
+    line(
+        w,
+        &format!("DESCRIPTION:{}", escape(&description.join("\n\n"))),
+    )?;
+    line(w, "END:VEVENT")
+}
+
+// Entries are grouped by the day they were written on, in the timezone they
+// were written in. Entries are in order, so each day's entries are together.
+fn days(entries: &[Entry]) -> Vec<&[Entry]> {
+    let mut days = Vec::new();
+    let mut start = 0;
+    for i in 1..=entries.len() {
+        if i == entries.len()
+            || entries[i].datetime().date_naive() != entries[start].datetime().date_naive()
+        {
+            days.push(&entries[start..i]);
+            start = i;
+        }
+    }
+    days
+}
+
+// Entries can be less than a second apart, so unlike DTSTART the UID keeps the
+// fractional part of the timestamp.
+fn uid(datetime: &DateTime<FixedOffset>) -> String {
+    datetime
+        .with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%S%.fZ")
+        .to_string()
+}
+
+fn utc(datetime: &DateTime<FixedOffset>) -> String {
+    datetime
+        .with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+// Escapes the characters that have special meaning in TEXT values.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// The longest a line can be, in bytes, before it has to be folded on to the
+// next line.
+const MAX_LINE: usize = 75;
+
+// Writes a content line, folding it so that no line is longer than MAX_LINE
+// bytes. Folds never fall in the middle of a character, and the continuation
+// lines start with a space, which takes up one of their bytes.
+fn line(w: &mut impl Write, s: &str) -> Result<()> {
+    let mut rest = s;
+    let mut max = MAX_LINE;
+    loop {
+        let mut end = rest.len().min(max);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (head, tail) = rest.split_at(end);
+        w.write_all(head.as_bytes())?;
+        w.write_all(b"\r\n")?;
+        if tail.is_empty() {
+            return Ok(());
+        }
+        w.write_all(b" ")?;
+        rest = tail;
+        max = MAX_LINE - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        Entry::new(
+            DateTime::parse_from_rfc3339(datetime).unwrap(),
+            message.to_owned(),
+        )
+    }
+
+    fn ics(entries: &[Entry], grouping: Grouping) -> String {
+        let mut buf = Vec::new();
+        write(&mut buf, entries, grouping).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test_case("hello"            => "hello"              ; "plain")]
+    #[test_case("a, b; c\\d"       => "a\\, b\\; c\\\\d"   ; "special characters")]
+    #[test_case("one\r\ntwo\nthree" => "one\\ntwo\\nthree" ; "newlines")]
+    fn test_escape(s: &str) -> String {
+        escape(s)
+    }
+
+    #[test_case("short" => "short\r\n" ; "short")]
+    #[test_case(&"a".repeat(80) => format!("{}\r\n {}\r\n", "a".repeat(75), "a".repeat(5)) ; "folded")]
+    #[test_case(&format!("{}é", "a".repeat(74)) => format!("{}\r\n é\r\n", "a".repeat(74)) ; "doesn't split characters")]
+    fn test_line(s: &str) -> String {
+        let mut buf = Vec::new();
+        line(&mut buf, s).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_write_entries() {
+        let entries = vec![entry(
+            "2020-01-01T09:30:00+01:00",
+            "hello, world\nsecond line",
+        )];
+        assert_eq!(
+            ics(&entries, Grouping::Entry),
+            "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//samwho//hmm//EN\r
+BEGIN:VEVENT\r
+UID:20200101T083000Z@hmm\r
+DTSTAMP:20200101T083000Z\r
+DTSTART:20200101T083000Z\r
+DTEND:20200101T083000Z\r
+SUMMARY:hello\\, world\r
+DESCRIPTION:hello\\, world\\nsecond line\r
+END:VEVENT\r
+END:VCALENDAR\r
+"
+        );
+    }
+
+    #[test]
+    fn test_write_days() {
+        let entries = vec![
+            entry("2020-01-01T09:30:00+00:00", "one"),
+            entry("2020-01-01T17:00:00+00:00", "two"),
+            entry("2020-01-03T08:00:00+00:00", "three"),
+        ];
+        let out = ics(&entries, Grouping::Day);
+
+        assert_eq!(out.matches("BEGIN:VEVENT").count(), 2);
+        assert!(out.contains("DTSTART;VALUE=DATE:20200101\r\nDTEND;VALUE=DATE:20200102\r\n"));
+        assert!(out.contains("SUMMARY:2 hmm entries\r\nDESCRIPTION:09:30 one\\n\\n17:00 two\r\n"));
+        assert!(out.contains("SUMMARY:1 hmm entry\r\n"));
+    }
+
+    #[test]
+    fn test_day_calendars() {
+        let entries = vec![
+            entry("2020-01-01T09:30:00+00:00", "one"),
+            entry("2020-01-01T17:00:00+00:00", "two"),
+            entry("2020-01-03T08:00:00+00:00", "three"),
+        ];
+        let calendars = day_calendars(&entries).unwrap();
+        assert_eq!(
+            calendars.iter().map(|(day, _)| *day).collect::<Vec<_>>(),
+            vec![
+                NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2020, 1, 3).unwrap()
+            ]
+        );
+
+        // Each calendar is the same event that writing all of them by day
+        // gives.
+        let first = String::from_utf8(calendars[0].1.clone()).unwrap();
+        assert_eq!(first, ics(&entries[..2], Grouping::Day));
+        assert!(first.contains("UID:20200101@hmm\r\n"));
+    }
+
+    #[test]
+    fn test_write_nothing() {
+        assert_eq!(
+            ics(&[], Grouping::Day),
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//samwho//hmm//EN\r\nEND:VCALENDAR\r\n"
+        );
+    }
+}
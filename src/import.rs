@@ -0,0 +1,185 @@
+use super::{
+    entry::{Entry, Metadata},
+    error::Error,
+    Result,
+};
+use chrono::prelude::*;
+use std::fs;
+use std::io::{stdin, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Each format has its own module that turns its input in to Records, which
+// hmmi then writes to your hmm file with journal::insert.
+pub mod git;
+pub mod irc;
+pub mod mail;
+pub mod org;
+pub mod pattern;
+pub mod shell;
+pub mod slack;
+
+/// An entry read from another format, along with where it came from so that
+/// problems can be reported against the right line. Formats that aren't made
+/// of lines number their records in the order they were read instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub line: u64,
+    pub datetime: Timestamp,
+    pub message: String,
+    pub metadata: Metadata,
+}
+
+/// When a record was written. Plenty of formats don't say what timezone their
+/// times are in, in which case they're assumed to be in local time when
+/// they're turned in to entries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timestamp {
+    Local(NaiveDateTime),
+    Fixed(DateTime<FixedOffset>),
+}
+
+impl Record {
+    pub fn into_entry(self) -> Result<Entry> {
+        let datetime = match self.datetime {
+            Timestamp::Fixed(datetime) => datetime,
+            Timestamp::Local(naive) => match Local.from_local_datetime(&naive).earliest() {
+                Some(datetime) => datetime.fixed_offset(),
+                None => {
+                    return Err(Error::InvalidDate(format!(
+                        "{} doesn't exist in your local timezone",
+                        naive
+                    )))
+                }
+            },
+        };
+        Ok(Entry::new(datetime, self.message).with_metadata(self.metadata))
+    }
+}
+
+/// The formats hmmi knows how to import from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Org,
+    Git,
+    Zsh,
+    Bash,
+    Fish,
+    Slack,
+    Irc,
+    Maildir,
+}
+
+/// Settings that only some sources use.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Only import git commits by authors matching this pattern.
+    pub author: Option<String>,
+    /// Whose messages to import from Slack exports and IRC logs.
+    pub user: Option<String>,
+}
+
+impl Source {
+    /// Reads records from `path`. For sources that read files, a path of "-"
+    /// reads from stdin, and for git it's the repository to read commits from.
+    pub fn read(&self, path: &Path, opts: &ImportOptions) -> Result<Vec<Record>> {
+        match self {
+            Source::Git => git::log(path, opts.author.as_deref()),
+            Source::Org => {
+                let (path, contents) = read_file(path)?;
+                org::parse(path, &String::from_utf8(contents)?)
+            }
+            Source::Zsh => shell::parse_zsh(&read_file(path)?.1),
+            Source::Bash => shell::parse_bash(&String::from_utf8(read_file(path)?.1)?),
+            Source::Fish => shell::parse_fish(&String::from_utf8(read_file(path)?.1)?),
+            Source::Slack => slack::parse(Cursor::new(read_file(path)?.1), user(opts)?),
+            Source::Irc => {
+                let (path, contents) = read_file(path)?;
+                // Logs are usually saved one file per channel, like
+                // "#rust.log", so that's what they're tagged with.
+                let channel = path
+                    .and_then(Path::file_stem)
+                    .and_then(|stem| stem.to_str())
+                    .map(|stem| stem.trim_start_matches('#'))
+                    .filter(|stem| !stem.is_empty());
+                irc::parse(&String::from_utf8(contents)?, user(opts)?, channel)
+            }
+            Source::Maildir if path.is_dir() => mail::read_maildir(path),
+            Source::Maildir => Ok(mail::parse(&read_file(path)?.1)?.into_iter().collect()),
+        }
+    }
+
+    /// What to import if nothing is given: stdin for files, the repository in
+    /// the current directory for git, and the history file each shell uses by
+    /// default.
+    pub fn default_input(&self) -> PathBuf {
+        let home = || dirs::home_dir().unwrap();
+        match self {
+            Source::Org | Source::Slack | Source::Irc | Source::Maildir => PathBuf::from("-"),
+            Source::Git => PathBuf::from("."),
+            Source::Zsh => home().join(".zsh_history"),
+            Source::Bash => home().join(".bash_history"),
+            Source::Fish => dirs::data_dir()
+                .unwrap_or_else(|| home().join(".local/share"))
+                .join("fish/fish_history"),
+        }
+    }
+}
+
+fn user(opts: &ImportOptions) -> Result<&str> {
+    opts.user.as_deref().ok_or_else(|| {
+        Error::InvalidArgument("importing messages needs --user to say whose to import".to_owned())
+    })
+}
+
+// Turns a channel name in to a tag, replacing anything that can't be in one
+// with a -, so that "sam's-notes" becomes #sam-s-notes.
+fn channel_tag(channel: &str) -> String {
+    channel
+        .trim_start_matches('#')
+        .chars()
+        .map(
+            |c| match c.is_alphanumeric() || c == '_' || c == '-' || c == '/' {
+                true => c,
+                false => '-',
+            },
+        )
+        .collect()
+}
+
+// Reads the whole of `path`, or stdin if it's "-", in which case there's no
+// path to give the parser.
+fn read_file(path: &Path) -> Result<(Option<&Path>, Vec<u8>)> {
+    if path.as_os_str() == "-" {
+        let mut contents = Vec::new();
+        stdin().read_to_end(&mut contents)?;
+        return Ok((None, contents));
+    }
+
+    let contents = fs::read(path).map_err(|source| Error::OpenFile {
+        path: path.to_owned(),
+        source,
+    })?;
+    Ok((Some(path), contents))
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "org" => Ok(Source::Org),
+            "git" => Ok(Source::Git),
+            "zsh" => Ok(Source::Zsh),
+            "bash" => Ok(Source::Bash),
+            "fish" => Ok(Source::Fish),
+            "slack" => Ok(Source::Slack),
+            "irc" => Ok(Source::Irc),
+            "maildir" => Ok(Source::Maildir),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown import format \"{}\", expected org, git, zsh, bash, fish, slack, irc or maildir",
+                s
+            ))),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use super::{Record, Timestamp};
+use crate::{entry::Metadata, error::Error, Result};
+use chrono::DateTime;
+use std::path::Path;
+use std::process::Command;
+
+// Each commit is its hash, commit date and message on their own lines, and
+// with -z commits are separated by NUL bytes, which can't appear in messages.
+const FORMAT: &str = "--format=%H%n%cI%n%B";
+
+/// Reads the commits in the git repository at `path`, optionally only those by
+/// authors matching `author`, which is passed to `git log --author`. Each
+/// commit becomes a record at its commit date, with the commit message as its
+/// message and the repository and commit hash in its metadata. Merge commits
+/// are left out.
+pub fn log(path: &Path, author: Option<&str>) -> Result<Vec<Record>> {
+    let repo = git(path, &["rev-parse", "--show-toplevel"])?;
+    let repo = repo.trim_end_matches('\n');
+
+    let mut args = vec!["log", "-z", "--no-merges", "--no-color", FORMAT];
+    let author = author.map(|a| format!("--author={}", a));
+    if let Some(author) = &author {
+        args.push(author);
+    }
+    let out = git(path, &args)?;
+
+    parse(repo, &out)
+}
+
+fn git(path: &Path, args: &[&str]) -> Result<String> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()
+        .map_err(|e| Error::InvalidArgument(format!("couldn't run git: {}", e)))?;
+
+    if !out.status.success() {
+        return Err(Error::InvalidArgument(format!(
+            "couldn't read commits from {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+fn parse(repo: &str, out: &str) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for (i, commit) in out.split('\0').enumerate() {
+        let commit = commit.trim_start_matches('\n');
+        if commit.is_empty() {
+            continue;
+        }
+
+        let mut parts = commit.splitn(3, '\n');
+        let (sha, date, message) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(sha), Some(date), message) => (sha, date, message.unwrap_or("").trim_end()),
+            _ => {
+                return Err(Error::Malformed {
+                    line: Some(i as u64 + 1),
+                    byte: None,
+                    snippet: Some(commit.to_owned()),
+                    reason: Some("unexpected output from git log".to_owned()),
+                })
+            }
+        };
+
+        // Commits can have empty messages, but entries can't.
+        if message.is_empty() {
+            continue;
+        }
+
+        let mut metadata = Metadata::new();
+        metadata.insert("repo".to_owned(), repo.into());
+        metadata.insert("sha".to_owned(), sha.into());
+
+        records.push(Record {
+            line: i as u64 + 1,
+            datetime: Timestamp::Fixed(DateTime::parse_from_rfc3339(date)?),
+            message: message.to_owned(),
+            metadata,
+        });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse() {
+        let out = "aaaa\n2020-01-02T09:15:00+01:00\nfix the thing\n\nit was broken\n\n\0\
+                   bbbb\n2020-01-01T10:00:00+00:00\n\0\
+                   cccc\n2020-01-01T09:00:00+00:00\ninitial commit\n\0";
+        let records = parse("/src/hmm", out).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].message, "fix the thing\n\nit was broken");
+        assert_eq!(
+            records[0].datetime,
+            Timestamp::Fixed(DateTime::parse_from_rfc3339("2020-01-02T09:15:00+01:00").unwrap())
+        );
+        assert_eq!(records[0].metadata["repo"], "/src/hmm");
+        assert_eq!(records[0].metadata["sha"], "aaaa");
+        assert_eq!(records[1].message, "initial commit");
+        assert_eq!(records[1].line, 3);
+    }
+
+    #[test]
+    fn test_parse_bad_date() {
+        assert!(parse("/src/hmm", "aaaa\nyesterday\nhello\n\0").is_err());
+    }
+
+    #[test]
+    fn test_log() {
+        let dir = TempDir::new().unwrap();
+        let commit = |name: &str, date: &str, message: &str| {
+            fs::write(dir.path().join("file"), message).unwrap();
+            for args in [&["add", "file"][..], &["commit", "-q", "-m", message]] {
+                let status = Command::new("git")
+                    .arg("-C")
+                    .arg(dir.path())
+                    .args(["-c", "commit.gpgsign=false"])
+                    .args(args)
+                    .env("GIT_AUTHOR_NAME", name)
+                    .env("GIT_AUTHOR_EMAIL", format!("{}@example.com", name))
+                    .env("GIT_COMMITTER_NAME", name)
+                    .env("GIT_COMMITTER_EMAIL", format!("{}@example.com", name))
+                    .env("GIT_COMMITTER_DATE", date)
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            }
+        };
+
+        assert!(Command::new("git")
+            .args(["init", "-q"])
+            .arg(dir.path())
+            .status()
+            .unwrap()
+            .success());
+        commit("alice", "2020-01-01T09:00:00+00:00", "first");
+        commit("bob", "2020-01-01T10:00:00+00:00", "second");
+        commit("alice", "2020-01-01T11:00:00+00:00", "third");
+
+        let messages = |author| -> Vec<String> {
+            log(dir.path(), author)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.message)
+                .collect()
+        };
+        assert_eq!(messages(None), vec!["third", "second", "first"]);
+        assert_eq!(messages(Some("alice")), vec!["third", "first"]);
+
+        let records = log(dir.path(), None).unwrap();
+        assert_eq!(
+            records[0].datetime,
+            Timestamp::Fixed(DateTime::parse_from_rfc3339("2020-01-01T11:00:00+00:00").unwrap())
+        );
+        assert_eq!(records[0].metadata["sha"].as_str().unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_log_not_a_repo() {
+        let dir = TempDir::new().unwrap();
+        let err = log(dir.path(), None).unwrap_err();
+        assert_eq!(err.exit_code(), 64);
+    }
+}
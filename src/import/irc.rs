@@ -0,0 +1,129 @@
+use super::{channel_tag, Record, Timestamp};
+use crate::{entry::Metadata, Result};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // Lines like "[2024-05-13 09:00:12] <sam> morning", which is what most
+    // clients and bouncers write, with or without the seconds.
+    static ref MESSAGE: Regex = Regex::new(
+        r"^\[(\d{4}-\d{2}-\d{2}[ T]\d{2}:\d{2}(?::\d{2})?)\]\s+<[@+%~&]?([^>\s]+)>\s?(.*)$"
+    )
+    .unwrap();
+}
+
+/// Parses an IRC log, keeping the messages `nick` sent. Each message becomes a
+/// record tagged with `channel`, if there is one, and with the channel in its
+/// metadata. Lines that aren't messages, like joins, parts and topic changes,
+/// are skipped. Times in IRC logs are in local time.
+pub fn parse(contents: &str, nick: &str, channel: Option<&str>) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let caps = match MESSAGE.captures(line) {
+            Some(caps) => caps,
+            None => continue,
+        };
+        let message = caps[3].trim();
+        if !caps[2].eq_ignore_ascii_case(nick) || message.is_empty() {
+            continue;
+        }
+        let datetime = match parse_datetime(&caps[1]) {
+            Some(datetime) => datetime,
+            None => continue,
+        };
+
+        let mut metadata = Metadata::new();
+        let message = match channel {
+            Some(channel) => {
+                metadata.insert("channel".to_owned(), channel.into());
+                format!("{} #{}", message, channel_tag(channel))
+            }
+            None => message.to_owned(),
+        };
+        records.push(Record {
+            line: i as u64 + 1,
+            datetime: Timestamp::Local(datetime),
+            message,
+            metadata,
+        });
+    }
+    Ok(records)
+}
+
+fn parse_datetime(s: &str) -> Option<NaiveDateTime> {
+    let s = s.replacen('T', " ", 1);
+    NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const LOG: &str = "\
+[2024-05-13 09:00:12] *** sam joined #rust
+[2024-05-13 09:00:30] <sam> morning all
+[2024-05-13 09:01] <@alex> hi sam
+[2024-05-13 09:02:00] <@Sam> remember to   renew the domain
+[2024-05-13 09:03:00] <sam>
+not a message
+[2024-02-30 09:04:00] <sam> no such day
+";
+
+    fn messages(records: Vec<Record>) -> Vec<(u64, String, String)> {
+        records
+            .into_iter()
+            .map(|r| match r.datetime {
+                Timestamp::Local(datetime) => (r.line, datetime.to_string(), r.message),
+                Timestamp::Fixed(_) => panic!("IRC logs are in local time"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            messages(parse(LOG, "sam", Some("rust")).unwrap()),
+            vec![
+                (
+                    2,
+                    "2024-05-13 09:00:30".to_owned(),
+                    "morning all #rust".to_owned()
+                ),
+                (
+                    4,
+                    "2024-05-13 09:02:00".to_owned(),
+                    "remember to   renew the domain #rust".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_without_channel() {
+        let records = parse(LOG, "alex", None).unwrap();
+        assert_eq!(records[0].metadata, Metadata::new());
+        assert_eq!(
+            messages(records),
+            vec![(3, "2024-05-13 09:01:00".to_owned(), "hi sam".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let records = parse(LOG, "sam", Some("#rust")).unwrap();
+        assert_eq!(records[0].metadata["channel"], "#rust");
+        assert!(records[0].message.ends_with(" #rust"));
+    }
+
+    #[test_case("2024-05-13 09:00:12" => Some("2024-05-13 09:00:12".to_owned()) ; "seconds")]
+    #[test_case("2024-05-13 09:00"    => Some("2024-05-13 09:00:00".to_owned()) ; "no seconds")]
+    #[test_case("2024-05-13T09:00"    => Some("2024-05-13 09:00:00".to_owned()) ; "iso")]
+    #[test_case("2024-13-13 09:00"    => None                                    ; "invalid")]
+    fn test_parse_datetime(s: &str) -> Option<String> {
+        parse_datetime(s).map(|datetime| datetime.to_string())
+    }
+}
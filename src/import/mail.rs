@@ -0,0 +1,261 @@
+use super::{Record, Timestamp};
+use crate::{entry::Metadata, error::Error, Result};
+use chrono::prelude::*;
+use mail_parser::MessageParser;
+use std::fs;
+use std::path::Path;
+
+/// Parses an email, as it would be saved in a maildir or piped to a command
+/// by procmail, in to a record written at the time in its Date header. The
+/// subject is the first line of the message, followed by a blank line and the
+/// plain text of the body, without any signature. Emails with neither a
+/// subject nor a body are skipped.
+pub fn parse(contents: &[u8]) -> Result<Option<Record>> {
+    let email = MessageParser::default()
+        .parse(contents)
+        .ok_or_else(|| malformed("this doesn't look like an email"))?;
+    let datetime = email
+        .date()
+        .filter(|date| date.is_valid())
+        .and_then(|date| DateTime::parse_from_rfc3339(&date.to_rfc3339()).ok())
+        .ok_or_else(|| malformed("no Date header, or one that couldn't be read"))?;
+
+    let subject = email.subject().unwrap_or_default().trim();
+    let body = email.body_text(0).unwrap_or_default();
+    let body = without_signature(&body);
+    let message = match (subject.is_empty(), body.is_empty()) {
+        (true, true) => return Ok(None),
+        (false, true) => subject.to_owned(),
+        (true, false) => body.to_owned(),
+        (false, false) => format!("{}\n\n{}", subject, body),
+    };
+
+    let mut metadata = Metadata::new();
+    if let Some(id) = email.message_id() {
+        metadata.insert("message_id".to_owned(), id.into());
+    }
+    Ok(Some(Record {
+        line: 1,
+        datetime: Timestamp::Fixed(datetime),
+        message,
+        metadata,
+    }))
+}
+
+/// Reads every email in the maildir at `dir`, both the ones that have been
+/// seen, in cur, and the ones that haven't, in new. Records are numbered in
+/// the order the emails were read.
+pub fn read_maildir(dir: &Path) -> Result<Vec<Record>> {
+    if !dir.join("cur").is_dir() && !dir.join("new").is_dir() {
+        return Err(Error::InvalidArgument(format!(
+            "{} isn't a maildir, it has no cur or new directory",
+            dir.display()
+        )));
+    }
+
+    let mut paths = Vec::new();
+    for sub in ["cur", "new"] {
+        let sub = dir.join(sub);
+        if !sub.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&sub)? {
+            let path = file?.path();
+            let hidden = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(true, |name| name.starts_with('.'));
+            if path.is_file() && !hidden {
+                paths.push(path);
+            }
+        }
+    }
+    // Delivered emails are named after when they arrived, so this is roughly
+    // the order they were received in.
+    paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+    let mut records = Vec::new();
+    for path in paths {
+        let contents = fs::read(&path).map_err(|source| Error::OpenFile {
+            path: path.clone(),
+            source,
+        })?;
+        let record = parse(&contents).map_err(|e| match e {
+            Error::Malformed { reason, .. } => Error::Malformed {
+                line: None,
+                byte: None,
+                snippet: Some(path.display().to_string()),
+                reason,
+            },
+            e => e,
+        })?;
+        if let Some(mut record) = record {
+            record.line = records.len() as u64 + 1;
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+// Everything after a line of "-- " is a signature, by convention, though
+// plenty of mail clients drop the space.
+fn without_signature(body: &str) -> &str {
+    let end = body
+        .lines()
+        .position(|line| line.trim_end() == "--")
+        .map(|i| body.split_inclusive('\n').take(i).map(str::len).sum())
+        .unwrap_or(body.len());
+    body[..end].trim()
+}
+
+fn malformed(reason: &str) -> Error {
+    Error::Malformed {
+        line: None,
+        byte: None,
+        snippet: None,
+        reason: Some(reason.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use test_case::test_case;
+
+    const EMAIL: &str = "\
+From: Sam <sam@example.com>
+To: journal@example.com
+Subject: Renew the domain #admin
+Date: Mon, 13 May 2024 09:00:12 +0100
+Message-ID: <1234@example.com>
+Content-Type: text/plain; charset=utf-8
+
+It runs out next month.
+
+--
+Sent from my phone
+";
+
+    fn message(record: Option<Record>) -> Option<(String, String)> {
+        record.map(|r| match r.datetime {
+            Timestamp::Fixed(datetime) => (datetime.to_rfc3339(), r.message),
+            Timestamp::Local(_) => panic!("emails have a timezone"),
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let record = parse(EMAIL.as_bytes()).unwrap();
+        assert_eq!(
+            record.as_ref().unwrap().metadata["message_id"],
+            "1234@example.com"
+        );
+        assert_eq!(
+            message(record),
+            Some((
+                "2024-05-13T09:00:12+01:00".to_owned(),
+                "Renew the domain #admin\n\nIt runs out next month.".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_mime() {
+        let email = "\
+Subject: =?utf-8?q?caf=C3=A9?=
+Date: Mon, 13 May 2024 09:00:12 -0700
+MIME-Version: 1.0
+Content-Type: multipart/alternative; boundary=\"b\"
+
+--b
+Content-Type: text/plain; charset=utf-8
+Content-Transfer-Encoding: quoted-printable
+
+na=C3=AFve
+--b
+Content-Type: text/html; charset=utf-8
+
+<p>na&iuml;ve</p>
+--b--
+";
+        assert_eq!(
+            message(parse(email.as_bytes()).unwrap()),
+            Some((
+                "2024-05-13T09:00:12-07:00".to_owned(),
+                "café\n\nnaïve".to_owned()
+            ))
+        );
+    }
+
+    #[test_case("Subject: hi\nDate: Mon, 13 May 2024 09:00:12 +0000\n\n"  => Some("hi".to_owned())   ; "subject only")]
+    #[test_case("Date: Mon, 13 May 2024 09:00:12 +0000\n\nhello\n"       => Some("hello".to_owned()) ; "body only")]
+    #[test_case("Date: Mon, 13 May 2024 09:00:12 +0000\n\n\n-- \nsam\n"  => None                     ; "empty")]
+    fn test_parse_parts(email: &str) -> Option<String> {
+        parse(email.as_bytes()).unwrap().map(|r| r.message)
+    }
+
+    #[test]
+    fn test_parse_no_date() {
+        let err = parse(b"Subject: hi\n\nhello\n").unwrap_err();
+        assert_eq!(err.kind(), "malformed");
+    }
+
+    #[test]
+    fn test_read_maildir() {
+        let dir = TempDir::new().unwrap();
+        for sub in ["cur", "new", "tmp"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        let email = |subject: &str, time: &str| {
+            format!(
+                "Subject: {}\nDate: Mon, 13 May 2024 {} +0000\n\n",
+                subject, time
+            )
+        };
+        fs::write(
+            dir.path().join("cur/1715590800.1.host"),
+            email("seen", "09:00:00"),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("new/1715594400.2.host"),
+            email("unseen", "10:00:00"),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("tmp/1715598000.3.host"),
+            email("partial", "11:00:00"),
+        )
+        .unwrap();
+        fs::write(dir.path().join("new/.hidden"), email("hidden", "12:00:00")).unwrap();
+
+        let records = read_maildir(dir.path()).unwrap();
+        let messages: Vec<(u64, &str)> = records
+            .iter()
+            .map(|r| (r.line, r.message.as_str()))
+            .collect();
+        assert_eq!(messages, vec![(1, "seen"), (2, "unseen")]);
+    }
+
+    #[test]
+    fn test_read_maildir_not_a_maildir() {
+        let dir = TempDir::new().unwrap();
+        let err = read_maildir(dir.path()).unwrap_err();
+        assert_eq!(err.kind(), "invalid_argument");
+    }
+
+    #[test]
+    fn test_read_maildir_bad_email() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("new")).unwrap();
+        let bad = dir.path().join("new/1");
+        fs::write(&bad, "Subject: no date\n\n").unwrap();
+        match read_maildir(dir.path()).unwrap_err() {
+            Error::Malformed { snippet, .. } => {
+                assert_eq!(snippet, Some(bad.display().to_string()))
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use super::{Record, Timestamp};
+use crate::{entry::Metadata, error::Error, Result};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::path::Path;
+
+lazy_static! {
+    static ref HEADING: Regex = Regex::new(r"^(\*+)\s+(.*?)\s*$").unwrap();
+
+    // Headings that are nothing but a date, like the ones org-journal starts
+    // each day with. The date can be in brackets and have a weekday before or
+    // after it, e.g. "Tuesday, 2019-06-04", "<2019-06-04 Tue>" or, using
+    // org-journal's default format, "Tuesday, 06/04/19".
+    static ref ISO_DAY: Regex =
+        Regex::new(r"^(?:\p{L}+,?\s+)?[<\[]?(\d{4})-(\d{2})-(\d{2})(?:\s+\p{L}+\.?)?[>\]]?$").unwrap();
+    static ref US_DAY: Regex =
+        Regex::new(r"^(?:\p{L}+,?\s+)?(\d{1,2})/(\d{1,2})/(\d{4}|\d{2})$").unwrap();
+
+    // An active or inactive org timestamp anywhere in a heading, with an
+    // optional time. Anything after the time, like a repeater or the end of a
+    // range, is ignored.
+    static ref TIMESTAMP: Regex =
+        Regex::new(r"[<\[](\d{4})-(\d{2})-(\d{2})(?:\s+\p{L}+\.?)?(?:\s+(\d{1,2}):(\d{2}))?[^>\]]*[>\]]").unwrap();
+
+    // A time at the start of a heading, which is how org-journal titles
+    // entries under a day heading.
+    static ref TIME: Regex = Regex::new(r"^(\d{1,2}):(\d{2})(?::(\d{2}))?(?:\s+(.*))?$").unwrap();
+
+    static ref DRAWER: Regex = Regex::new(r"^\s*:[\w-]+:\s*$").unwrap();
+    static ref DRAWER_END: Regex = Regex::new(r"^\s*:END:\s*$").unwrap();
+}
+
+/// Parses an org-mode file, such as one written by org-journal, in to records.
+///
+/// Headings that are only a date set the date for the headings below them,
+/// which become entries at the time they start with, or at midnight if they
+/// don't start with one. Headings with an org timestamp become entries at that
+/// time wherever they are. Files named after a date, like org-journal's
+/// default of one file per day, use that date until a heading says otherwise.
+///
+/// An entry's message is its heading, without the time, followed by the text
+/// under it, including any subheadings. Property drawers are left out, and
+/// text under a date heading becomes an entry of its own at midnight.
+pub fn parse(path: Option<&Path>, contents: &str) -> Result<Vec<Record>> {
+    let mut date = path.and_then(file_date);
+    let mut records = Vec::new();
+    let mut current: Option<Section> = None;
+
+    for (i, line) in contents.lines().enumerate() {
+        let n = i as u64 + 1;
+
+        let caps = match HEADING.captures(line) {
+            Some(caps) => caps,
+            None => {
+                if let Some(section) = current.as_mut() {
+                    section.body.push(line);
+                }
+                continue;
+            }
+        };
+        let level = caps[1].len();
+        let title = &caps[2];
+
+        if let Some(section) = current.as_mut() {
+            if section.level.is_some_and(|l| level > l) {
+                section.body.push(line);
+                continue;
+            }
+        }
+        records.extend(current.take().and_then(Section::into_record));
+
+        if let Some(day) = day(title) {
+            date = Some(day);
+            current = Some(Section {
+                line: n,
+                level: None,
+                datetime: midnight(day),
+                title: String::new(),
+                body: Vec::new(),
+            });
+            continue;
+        }
+
+        let (datetime, title) = entry(title, date).ok_or_else(|| Error::Malformed {
+            line: Some(n),
+            byte: None,
+            snippet: Some(line.to_owned()),
+            reason: Some(
+                "couldn't find a date for this heading, it needs a timestamp or to be under a heading that's a date"
+                    .to_owned(),
+            ),
+        })?;
+        current = Some(Section {
+            line: n,
+            level: Some(level),
+            datetime,
+            title,
+            body: Vec::new(),
+        });
+    }
+
+    records.extend(current.and_then(Section::into_record));
+    Ok(records)
+}
+
+struct Section<'a> {
+    line: u64,
+    // Headings deeper than this are part of the section rather than sections
+    // of their own. Date headings don't have a level, as the headings under
+    // them are the entries.
+    level: Option<usize>,
+    datetime: NaiveDateTime,
+    title: String,
+    body: Vec<&'a str>,
+}
+
+impl Section<'_> {
+    fn into_record(self) -> Option<Record> {
+        let mut body = Vec::with_capacity(self.body.len());
+        let mut in_drawer = false;
+        for line in self.body {
+            if in_drawer {
+                in_drawer = !DRAWER_END.is_match(line);
+            } else if DRAWER.is_match(line) {
+                in_drawer = true;
+            } else {
+                body.push(line);
+            }
+        }
+
+        while body.first().is_some_and(|l| l.trim().is_empty()) {
+            body.remove(0);
+        }
+        while body.last().is_some_and(|l| l.trim().is_empty()) {
+            body.pop();
+        }
+
+        // Org files are often indented to line up with their headings, which
+        // would look odd anywhere else.
+        let leading = |l: &str| l.len() - l.trim_start().len();
+        let indent = body
+            .iter()
+            .filter(|l| !l.trim().is_empty() && !HEADING.is_match(l))
+            .map(|l| leading(l))
+            .min()
+            .unwrap_or(0);
+        let body: Vec<&str> = body
+            .iter()
+            .map(|l| l[leading(l).min(indent)..].trim_end())
+            .collect();
+
+        let message = match (self.title.is_empty(), body.is_empty()) {
+            (true, true) => return None,
+            (true, false) => body.join("\n"),
+            (false, true) => self.title,
+            (false, false) => format!("{}\n{}", self.title, body.join("\n")),
+        };
+
+        Some(Record {
+            line: self.line,
+            datetime: Timestamp::Local(self.datetime),
+            message,
+            metadata: Metadata::new(),
+        })
+    }
+}
+
+fn file_date(path: &Path) -> Option<NaiveDate> {
+    let stem = path.file_stem()?.to_str()?;
+    NaiveDate::parse_from_str(stem, "%Y%m%d")
+        .or_else(|_| NaiveDate::parse_from_str(stem, "%Y-%m-%d"))
+        .ok()
+}
+
+fn day(title: &str) -> Option<NaiveDate> {
+    if let Some(caps) = ISO_DAY.captures(title) {
+        return NaiveDate::from_ymd_opt(number(&caps, 1)?, number(&caps, 2)?, number(&caps, 3)?);
+    }
+    let caps = US_DAY.captures(title)?;
+    let mut year = number(&caps, 3)?;
+    if caps[3].len() == 2 {
+        year += if year < 69 { 2000 } else { 1900 };
+    }
+    NaiveDate::from_ymd_opt(year, number(&caps, 1)?, number(&caps, 2)?)
+}
+
+fn entry(title: &str, date: Option<NaiveDate>) -> Option<(NaiveDateTime, String)> {
+    if let Some(caps) = TIMESTAMP.captures(title) {
+        let date =
+            NaiveDate::from_ymd_opt(number(&caps, 1)?, number(&caps, 2)?, number(&caps, 3)?)?;
+        let datetime = match caps.get(4) {
+            Some(_) => date.and_hms_opt(number(&caps, 4)?, number(&caps, 5)?, 0)?,
+            None => midnight(date),
+        };
+        let ts = caps.get(0)?;
+        let rest: Vec<&str> = [title[..ts.start()].trim(), title[ts.end()..].trim()]
+            .iter()
+            .copied()
+            .filter(|s| !s.is_empty())
+            .collect();
+        return Some((datetime, rest.join(" ")));
+    }
+
+    let date = date?;
+    if let Some(caps) = TIME.captures(title) {
+        let seconds = caps.get(3).map_or(Some(0), |_| number(&caps, 3))?;
+        if let Some(datetime) = date.and_hms_opt(number(&caps, 1)?, number(&caps, 2)?, seconds) {
+            let rest = caps.get(4).map_or("", |m| m.as_str());
+            return Some((datetime, rest.to_owned()));
+        }
+    }
+    Some((midnight(date), title.to_owned()))
+}
+
+fn number<T: std::str::FromStr>(caps: &Captures, i: usize) -> Option<T> {
+    caps.get(i)?.as_str().parse().ok()
+}
+
+fn midnight(date: NaiveDate) -> NaiveDateTime {
+    date.and_hms_opt(0, 0, 0).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn naive(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn records(path: Option<&str>, contents: &str) -> Vec<(NaiveDateTime, String)> {
+        parse(path.map(Path::new), contents)
+            .unwrap()
+            .into_iter()
+            .map(|r| match r.datetime {
+                Timestamp::Local(datetime) => (datetime, r.message),
+                Timestamp::Fixed(_) => panic!("org timestamps don't have a timezone"),
+            })
+            .collect()
+    }
+
+    #[test_case("2019-06-04"             => Some("2019-06-04".to_owned()) ; "iso")]
+    #[test_case("<2019-06-04 Tue>"       => Some("2019-06-04".to_owned()) ; "active timestamp")]
+    #[test_case("[2019-06-04 Tue]"       => Some("2019-06-04".to_owned()) ; "inactive timestamp")]
+    #[test_case("Tuesday, 2019-06-04"    => Some("2019-06-04".to_owned()) ; "weekday then iso")]
+    #[test_case("Tuesday, 06/04/19"      => Some("2019-06-04".to_owned()) ; "org-journal default")]
+    #[test_case("06/04/2019"             => Some("2019-06-04".to_owned()) ; "us four digit year")]
+    #[test_case("12/31/99"               => Some("1999-12-31".to_owned()) ; "last century")]
+    #[test_case("2019-02-30"             => None                          ; "no such day")]
+    #[test_case("<2019-06-04 Tue 10:00>" => None                          ; "has a time")]
+    #[test_case("notes for 2019-06-04"   => None                          ; "not just a date")]
+    fn test_day(title: &str) -> Option<String> {
+        day(title).map(|d| d.to_string())
+    }
+
+    #[test_case("10:30 standup", Some("2019-06-04")            => Some(("2019-06-04 10:30:00".to_owned(), "standup".to_owned())) ; "time under a day")]
+    #[test_case("10:30:15 standup", Some("2019-06-04")         => Some(("2019-06-04 10:30:15".to_owned(), "standup".to_owned())) ; "seconds")]
+    #[test_case("no time", Some("2019-06-04")                  => Some(("2019-06-04 00:00:00".to_owned(), "no time".to_owned())) ; "no time under a day")]
+    #[test_case("<2020-01-02 Thu 09:15> call bob", None        => Some(("2020-01-02 09:15:00".to_owned(), "call bob".to_owned())) ; "timestamp first")]
+    #[test_case("call bob [2020-01-02 Thu 09:15]", None        => Some(("2020-01-02 09:15:00".to_owned(), "call bob".to_owned())) ; "timestamp last")]
+    #[test_case("gym <2020-01-02 Thu 18:00 +1w>", None         => Some(("2020-01-02 18:00:00".to_owned(), "gym".to_owned())) ; "repeater")]
+    #[test_case("holiday <2020-01-02 Thu>", Some("2019-06-04") => Some(("2020-01-02 00:00:00".to_owned(), "holiday".to_owned())) ; "timestamp beats the day")]
+    #[test_case("25:00 nope", Some("2019-06-04")               => Some(("2019-06-04 00:00:00".to_owned(), "25:00 nope".to_owned())) ; "not a time")]
+    #[test_case("no date", None                                => None ; "no date")]
+    fn test_entry(title: &str, date: Option<&str>) -> Option<(String, String)> {
+        let date = date.map(|d| d.parse().unwrap());
+        entry(title, date).map(|(datetime, rest)| (datetime.to_string(), rest))
+    }
+
+    #[test]
+    fn test_parse_org_journal() {
+        let contents = "#+TITLE: journal
+
+* Tuesday, 06/04/19
+Woke up early.
+
+** 10:00 standup
+   :PROPERTIES:
+   :CREATED:  20190604
+   :END:
+   Talked about the release.
+
+   Nobody had questions.
+*** action items
+    - ship it
+** 14:30
+Something with no title.
+* Wednesday, 06/05/19
+** 09:00 coffee
+";
+        assert_eq!(
+            records(None, contents),
+            vec![
+                (naive("2019-06-04 00:00"), "Woke up early.".to_owned()),
+                (
+                    naive("2019-06-04 10:00"),
+                    "standup\nTalked about the release.\n\nNobody had questions.\n*** action items\n - ship it"
+                        .to_owned()
+                ),
+                (naive("2019-06-04 14:30"), "Something with no title.".to_owned()),
+                (naive("2019-06-05 09:00"), "coffee".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_date_from_file_name() {
+        assert_eq!(
+            records(Some("journal/20190604"), "* 10:00 standup\n* 11:00 lunch\n"),
+            vec![
+                (naive("2019-06-04 10:00"), "standup".to_owned()),
+                (naive("2019-06-04 11:00"), "lunch".to_owned()),
+            ]
+        );
+        assert_eq!(
+            records(Some("2019-06-04.org"), "* 10:00 standup\n"),
+            vec![(naive("2019-06-04 10:00"), "standup".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamps() {
+        assert_eq!(
+            records(
+                Some("notes.org"),
+                "* TODO write docs <2020-01-02 Thu 09:15>\nfor the importer\n* [2020-01-01 Wed 23:59] new year's eve\n"
+            ),
+            vec![
+                (
+                    naive("2020-01-02 09:15"),
+                    "TODO write docs\nfor the importer".to_owned()
+                ),
+                (naive("2020-01-01 23:59"), "new year's eve".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_empty_sections_are_skipped() {
+        assert_eq!(
+            records(None, "* 2019-06-04\n\n** 10:00\n\n* 2019-06-05\n"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_parse_no_date() {
+        assert_eq!(
+            records(
+                None,
+                "text before any heading is ignored\n* 2019-06-04\n** fine\n"
+            ),
+            vec![(naive("2019-06-04 00:00"), "fine".to_owned())]
+        );
+
+        let err = parse(Some(Path::new("notes.org")), "* ideas\n").unwrap_err();
+        assert!(matches!(err, Error::Malformed { line: Some(1), .. }));
+        assert_eq!(err.exit_code(), 65);
+    }
+}
@@ -0,0 +1,242 @@
+use super::{read_file, Record, Timestamp};
+use crate::{entry::Metadata, error::Error, Result};
+use chrono::format::{self, Parsed, StrftimeItems};
+use chrono::Local;
+use regex::Regex;
+use std::path::Path;
+use std::str::FromStr;
+
+// Stands for the message in a pattern. Everything else is strftime.
+const MESSAGE: &str = "{message}";
+
+/// A description of how the lines of a log are laid out, made of strftime
+/// specifiers for the date and time and "{message}" for the message, like
+/// "%Y-%m-%d %H:%M | {message}".
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    before: String,
+    after: String,
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (before, after) = s.split_once(MESSAGE).ok_or_else(|| {
+            Error::InvalidArgument(format!("pattern \"{}\" doesn't contain {}", s, MESSAGE))
+        })?;
+        if after.contains(MESSAGE) {
+            return Err(Error::InvalidArgument(format!(
+                "pattern \"{}\" contains {} more than once",
+                s, MESSAGE
+            )));
+        }
+
+        let regex = format!("^({})(.*?)({})$", to_regex(before)?, to_regex(after)?);
+        Ok(Pattern {
+            regex: Regex::new(&regex)?,
+            before: before.to_owned(),
+            after: after.to_owned(),
+        })
+    }
+}
+
+impl Pattern {
+    /// Reads records from the file at `path`, or stdin if it's "-".
+    pub fn read(&self, path: &Path) -> Result<Vec<Record>> {
+        let (_, contents) = read_file(path)?;
+        self.parse(&String::from_utf8(contents)?)
+    }
+
+    /// Parses each line that matches the pattern in to a record. Lines that
+    /// don't match are added to the message of the record before them, so
+    /// that messages can span multiple lines.
+    pub fn parse(&self, contents: &str) -> Result<Vec<Record>> {
+        let mut records: Vec<Record> = Vec::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let record = self.regex.captures(line).and_then(|caps| {
+                let datetime = self.datetime(&caps[1], &caps[3])?;
+                Some(Record {
+                    line: i as u64 + 1,
+                    datetime,
+                    message: caps[2].to_owned(),
+                    metadata: Metadata::new(),
+                })
+            });
+
+            match (record, records.last_mut()) {
+                (Some(record), _) => records.push(record),
+                (None, Some(last)) => {
+                    last.message.push('\n');
+                    last.message.push_str(line);
+                }
+                (None, None) if line.trim().is_empty() => {}
+                (None, None) => {
+                    return Err(Error::Malformed {
+                        line: Some(i as u64 + 1),
+                        byte: None,
+                        snippet: Some(line.to_owned()),
+                        reason: Some("line doesn't match the pattern".to_owned()),
+                    })
+                }
+            }
+        }
+
+        for record in &mut records {
+            let len = record.message.trim_end().len();
+            record.message.truncate(len);
+        }
+        records.retain(|r| !r.message.is_empty());
+        Ok(records)
+    }
+
+    // Both sides of the message are parsed in to the same Parsed, so the date
+    // and time can be split up around it. Times without a timezone are in
+    // local time, as are Unix timestamps once they've been converted, and
+    // dates without a time are at midnight.
+    fn datetime(&self, before: &str, after: &str) -> Option<Timestamp> {
+        let mut parsed = Parsed::new();
+        format::parse(&mut parsed, before, StrftimeItems::new(&self.before)).ok()?;
+        format::parse(&mut parsed, after, StrftimeItems::new(&self.after)).ok()?;
+
+        if let Ok(datetime) = parsed.to_datetime() {
+            return Some(Timestamp::Fixed(match parsed.offset() {
+                Some(_) => datetime,
+                None => datetime.with_timezone(&Local).fixed_offset(),
+            }));
+        }
+        let date = parsed.to_naive_date().ok()?;
+        let time = parsed.to_naive_time().unwrap_or_default();
+        Some(Timestamp::Local(date.and_time(time)))
+    }
+}
+
+// Turns the strftime part of a pattern in to a regex that matches what it
+// would format, so that the pattern can be found in a line before parsing it.
+fn to_regex(pattern: &str) -> Result<String> {
+    let mut regex = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            regex.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+
+        let mut spec = String::new();
+        for c in chars.by_ref() {
+            spec.push(c);
+            if c.is_ascii_alphabetic() || c == '%' {
+                break;
+            }
+        }
+
+        regex.push_str(match spec.as_str() {
+            "Y" => r"[+-]?\d{4,}",
+            "C" | "y" | "M" | "S" => r"\d{2}",
+            "m" | "d" | "H" | "I" => r"\d{1,2}",
+            "e" | "k" | "l" => r"\s?\d{1,2}",
+            "j" => r"\d{1,3}",
+            "b" | "h" | "B" | "a" | "A" => r"\p{L}+",
+            "p" | "P" => r"[AaPp][Mm]",
+            "F" => r"\d{4}-\d{1,2}-\d{1,2}",
+            "D" | "x" => r"\d{1,2}/\d{1,2}/\d{2}",
+            "T" | "X" => r"\d{1,2}:\d{2}:\d{2}",
+            "R" => r"\d{1,2}:\d{2}",
+            "s" => r"-?\d+",
+            "f" => r"\d+",
+            ".f" | ".3f" | ".6f" | ".9f" => r"\.\d+",
+            "z" => r"[+-]\d{4}",
+            ":z" => r"[+-]\d{2}:\d{2}",
+            "Z" => r"[A-Za-z]+",
+            "%" => "%",
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unsupported strftime specifier \"%{}\" in pattern \"{}\"",
+                    spec, pattern
+                )))
+            }
+        });
+    }
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDateTime};
+    use test_case::test_case;
+
+    fn local(s: &str) -> Timestamp {
+        Timestamp::Local(NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap())
+    }
+
+    fn parse(pattern: &str, contents: &str) -> Vec<(Timestamp, String)> {
+        pattern
+            .parse::<Pattern>()
+            .unwrap()
+            .parse(contents)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.datetime, r.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(
+                "%Y-%m-%d %H:%M | {message}",
+                "2020-01-02 09:15 | hello\n2020-01-02 10:00 | multi\nline\n\n2020-01-03 08:00 | world\n"
+            ),
+            vec![
+                (local("2020-01-02 09:15:00"), "hello".to_owned()),
+                (local("2020-01-02 10:00:00"), "multi\nline".to_owned()),
+                (local("2020-01-03 08:00:00"), "world".to_owned()),
+            ]
+        );
+    }
+
+    #[test_case("[%d/%b/%Y:%H:%M:%S %z] {message}", "[02/Jan/2020:09:15:30 +0100] GET /"   ; "timezone")]
+    #[test_case("{message} (%s)",                   "GET / (1577952930)"                    ; "message first")]
+    #[test_case("%F {message} %T%:z",               "2020-01-02 GET / 09:15:30+01:00"       ; "split around message")]
+    fn test_parse_fixed(pattern: &str, line: &str) {
+        assert_eq!(
+            parse(pattern, line),
+            vec![(
+                Timestamp::Fixed(
+                    DateTime::parse_from_rfc3339("2020-01-02T09:15:30+01:00").unwrap()
+                ),
+                "GET /".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_date_only() {
+        assert_eq!(
+            parse("%Y-%m-%d: {message}", "2020-01-02: a day"),
+            vec![(local("2020-01-02 00:00:00"), "a day".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_parse_no_match() {
+        let pattern: Pattern = "%Y-%m-%d {message}".parse().unwrap();
+        let err = pattern
+            .parse("\nnot a date\n2020-01-02 hello\n")
+            .unwrap_err();
+        assert!(matches!(err, Error::Malformed { line: Some(2), .. }));
+    }
+
+    #[test_case("%Y-%m-%d"                    ; "no message")]
+    #[test_case("{message} {message}"         ; "two messages")]
+    #[test_case("%Q {message}"                ; "unknown specifier")]
+    fn test_invalid_pattern(pattern: &str) {
+        assert!(matches!(
+            pattern.parse::<Pattern>(),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
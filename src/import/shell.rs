@@ -0,0 +1,265 @@
+use super::{Record, Timestamp};
+use crate::{entry::Metadata, error::Error, Result};
+use chrono::prelude::*;
+
+// Added to the end of every command, so that they're easy to find, or leave
+// out, with hmmq.
+const TAG: &str = "#shell";
+
+/// Parses a zsh history file written with the EXTENDED_HISTORY option, which
+/// looks like ": 1560000000:0;ls -l". zsh escapes some bytes in its history
+/// file, so `contents` should be the file as it is on disk.
+pub fn parse_zsh(contents: &[u8]) -> Result<Vec<Record>> {
+    let contents = String::from_utf8(unmetafy(contents))?;
+    let mut commands = Commands::default();
+    let mut lines = contents.lines().enumerate();
+
+    while let Some((i, line)) = lines.next() {
+        let (time, command) = line
+            .strip_prefix(": ")
+            .and_then(|rest| rest.split_once(';'))
+            .and_then(|(stamp, command)| Some((stamp.split_once(':')?.0, command)))
+            .and_then(|(time, command)| Some((time.parse::<i64>().ok()?, command)))
+            .ok_or_else(|| {
+                malformed(
+                    i,
+                    line,
+                    "no timestamp, zsh only saves them with the EXTENDED_HISTORY option",
+                )
+            })?;
+
+        // Commands that span multiple lines have a backslash at the end of
+        // every line but the last.
+        let mut command = command.to_owned();
+        while command.ends_with('\\') {
+            command.pop();
+            command.push('\n');
+            match lines.next() {
+                Some((_, next)) => command.push_str(next),
+                None => break,
+            }
+        }
+
+        commands.push(i, time, line, command.trim_end())?;
+    }
+
+    Ok(commands.records)
+}
+
+/// Parses a bash history file written with HISTTIMEFORMAT set, where each
+/// command comes after a comment holding its timestamp, like "#1560000000".
+/// Lines without a timestamp of their own are part of the command before
+/// them, which is how bash saves multi-line commands with the lithist option.
+pub fn parse_bash(contents: &str) -> Result<Vec<Record>> {
+    let mut commands = Commands::default();
+    let mut current: Option<(usize, i64, String)> = None;
+
+    for (i, line) in contents.lines().enumerate() {
+        if let Some(time) = line.strip_prefix('#').and_then(|t| t.parse().ok()) {
+            if let Some((start, time, command)) = current.take() {
+                commands.push(start, time, line, command.trim_end())?;
+            }
+            current = Some((i, time, String::new()));
+            continue;
+        }
+
+        match current.as_mut() {
+            Some((_, _, command)) if command.is_empty() => command.push_str(line),
+            Some((_, _, command)) => {
+                command.push('\n');
+                command.push_str(line);
+            }
+            None => {
+                return Err(malformed(
+                    i,
+                    line,
+                    "no timestamp, bash only saves them when HISTTIMEFORMAT is set",
+                ))
+            }
+        }
+    }
+
+    if let Some((start, time, command)) = current {
+        commands.push(start, time, "", command.trim_end())?;
+    }
+
+    Ok(commands.records)
+}
+
+/// Parses a fish history file, which is a list of commands with "cmd" and
+/// "when" fields in a subset of YAML.
+pub fn parse_fish(contents: &str) -> Result<Vec<Record>> {
+    let mut commands = Commands::default();
+    let mut current: Option<(usize, String)> = None;
+
+    for (i, line) in contents.lines().enumerate() {
+        if let Some(command) = line.strip_prefix("- cmd: ") {
+            current = Some((i, unescape_fish(command)));
+        } else if let Some(time) = line.trim_start().strip_prefix("when: ") {
+            let (start, command) = current
+                .take()
+                .ok_or_else(|| malformed(i, line, "\"when\" without a \"cmd\" before it"))?;
+            let time = time
+                .parse()
+                .map_err(|_| malformed(i, line, "timestamp isn't a number"))?;
+            commands.push(start, time, line, &command)?;
+        }
+    }
+
+    Ok(commands.records)
+}
+
+// Builds up records from commands, skipping commands that are the same as the
+// one before them, as running the same thing over and over isn't worth more
+// than one entry.
+#[derive(Default)]
+struct Commands {
+    records: Vec<Record>,
+    last: Option<String>,
+}
+
+impl Commands {
+    fn push(&mut self, i: usize, time: i64, line: &str, command: &str) -> Result<()> {
+        if command.trim().is_empty() || self.last.as_deref() == Some(command) {
+            return Ok(());
+        }
+        self.last = Some(command.to_owned());
+
+        let datetime = Local
+            .timestamp_opt(time, 0)
+            .single()
+            .ok_or_else(|| malformed(i, line, "timestamp is out of range"))?;
+
+        self.records.push(Record {
+            line: i as u64 + 1,
+            datetime: Timestamp::Fixed(datetime.fixed_offset()),
+            message: format!("{} {}", command, TAG),
+            metadata: Metadata::new(),
+        });
+        Ok(())
+    }
+}
+
+fn malformed(i: usize, line: &str, reason: &str) -> Error {
+    Error::Malformed {
+        line: Some(i as u64 + 1),
+        byte: None,
+        snippet: Some(line.to_owned()),
+        reason: Some(reason.to_owned()),
+    }
+}
+
+// zsh writes some bytes, including some of the ones non-ASCII characters are
+// made of in UTF-8, as 0x83 followed by the byte XORed with 32.
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            0x83 => out.extend(bytes.next().map(|b| b ^ 32)),
+            b => out.push(b),
+        }
+    }
+    out
+}
+
+fn unescape_fish(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                out.push('\\');
+                chars.next();
+            }
+            (c, _) => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn commands(records: Vec<Record>) -> Vec<(i64, String)> {
+        records
+            .into_iter()
+            .map(|r| match r.datetime {
+                Timestamp::Fixed(datetime) => (datetime.timestamp(), r.message),
+                Timestamp::Local(_) => panic!("shell history timestamps are in UTC"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_zsh() {
+        let mut contents = b": 1560000000:0;ls -l\n: 1560000001:0;ls -l\n: 1560000002:3;for f in *; do\\\n  echo $f\\\ndone\n: 1560000003:0;echo ".to_vec();
+        contents.extend_from_slice(&[0xc4, 0x83, 0x85 ^ 32, b'\n']);
+
+        assert_eq!(
+            commands(parse_zsh(&contents).unwrap()),
+            vec![
+                (1560000000, "ls -l #shell".to_owned()),
+                (
+                    1560000002,
+                    "for f in *; do\n  echo $f\ndone #shell".to_owned()
+                ),
+                (1560000003, "echo ą #shell".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_zsh_without_timestamps() {
+        let err = parse_zsh(b"ls -l\n").unwrap_err();
+        assert!(err.to_string().contains("EXTENDED_HISTORY"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_bash() {
+        let contents = "#1560000000\nls -l\n#1560000001\nls -l\n#1560000002\ncd /tmp\n#1560000003\nfor f in *; do\n  echo $f\ndone\n#1560000004\nls -l\n";
+        assert_eq!(
+            commands(parse_bash(contents).unwrap()),
+            vec![
+                (1560000000, "ls -l #shell".to_owned()),
+                (1560000002, "cd /tmp #shell".to_owned()),
+                (
+                    1560000003,
+                    "for f in *; do\n  echo $f\ndone #shell".to_owned()
+                ),
+                (1560000004, "ls -l #shell".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_bash_without_timestamps() {
+        let err = parse_bash("ls -l\n").unwrap_err();
+        assert!(matches!(err, Error::Malformed { line: Some(1), .. }));
+    }
+
+    #[test]
+    fn test_parse_fish() {
+        let contents = "- cmd: ls -l\n  when: 1560000000\n- cmd: ls -l\n  when: 1560000001\n- cmd: cd /tmp\n  when: 1560000002\n  paths:\n    - /tmp\n- cmd: echo a\\nb\\\\c\n  when: 1560000003\n";
+        assert_eq!(
+            commands(parse_fish(contents).unwrap()),
+            vec![
+                (1560000000, "ls -l #shell".to_owned()),
+                (1560000002, "cd /tmp #shell".to_owned()),
+                (1560000003, "echo a\nb\\c #shell".to_owned()),
+            ]
+        );
+    }
+
+    #[test_case("- cmd: ls\n  when: yesterday\n" ; "bad timestamp")]
+    #[test_case("  when: 1560000000\n"           ; "no command")]
+    fn test_parse_fish_malformed(contents: &str) {
+        assert!(matches!(parse_fish(contents), Err(Error::Malformed { .. })));
+    }
+}
@@ -0,0 +1,329 @@
+use super::{channel_tag, Record, Timestamp};
+use crate::{entry::Metadata, error::Error, Result};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek};
+use zip::ZipArchive;
+
+// Messages with any other subtype are things like people joining channels or
+// changing their topic, rather than something someone wrote.
+const SUBTYPES: &[&str] = &["me_message", "thread_broadcast", "file_share"];
+
+// What direct messages are tagged with, as their folders are named after IDs
+// rather than anything readable.
+const DM_TAG: &str = "dm";
+
+lazy_static! {
+    // Slack writes links, mentions and channels like <https://x|label>,
+    // <@U123> and <#C123|general>.
+    static ref LINK: Regex = Regex::new(r"<([^<>|]+)(?:\|([^<>]*))?>").unwrap();
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    real_name: String,
+    #[serde(default)]
+    profile: Profile,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Profile {
+    #[serde(default)]
+    display_name: String,
+}
+
+impl User {
+    fn is(&self, user: &str) -> bool {
+        self.id == user
+            || [&self.name, &self.real_name, &self.profile.display_name]
+                .iter()
+                .any(|name| !name.is_empty() && name.eq_ignore_ascii_case(user))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Conversation {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    subtype: Option<String>,
+    #[serde(default)]
+    text: String,
+    ts: String,
+}
+
+/// Reads the messages sent by `user` from a Slack export, the zip file Slack
+/// makes from a workspace's settings. `user` can be someone's ID, username,
+/// display name or full name. Each message becomes a record tagged with the
+/// channel it was sent in, or #dm for direct messages, with the channel and
+/// Slack's ID for the message in its metadata. Mentions and channels are
+/// written out by name and links as they are.
+pub fn parse(r: impl Read + Seek, user: &str) -> Result<Vec<Record>> {
+    let mut zip = ZipArchive::new(r).map_err(malformed)?;
+
+    let users: Vec<User> = read_json(&mut zip, "users.json")?.unwrap_or_default();
+    let me = users
+        .iter()
+        .find(|u| u.is(user))
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "there's no one called {} in the Slack export",
+                user
+            ))
+        })?
+        .id
+        .clone();
+    let names: HashMap<&str, &str> = users
+        .iter()
+        .map(|u| (u.id.as_str(), u.name.as_str()))
+        .collect();
+
+    let mut dms = HashSet::new();
+    for list in ["dms.json", "mpims.json"] {
+        let conversations: Vec<Conversation> = read_json(&mut zip, list)?.unwrap_or_default();
+        dms.extend(conversations.into_iter().map(|c| c.id));
+    }
+
+    // Every other JSON file in a folder is a day of a channel's messages, like
+    // general/2024-05-13.json.
+    let mut days: Vec<String> = zip
+        .file_names()
+        .filter(|name| name.contains('/') && name.ends_with(".json"))
+        .map(str::to_owned)
+        .collect();
+    days.sort();
+
+    let mut records = Vec::new();
+    for day in days {
+        let channel = day.split('/').next().unwrap_or_default().to_owned();
+        let messages: Vec<Message> = read_json(&mut zip, &day)?.unwrap_or_default();
+        for message in messages {
+            let subtype_ok = message
+                .subtype
+                .as_deref()
+                .map_or(true, |s| SUBTYPES.contains(&s));
+            if message.user.as_deref() != Some(me.as_str())
+                || !subtype_ok
+                || message.text.trim().is_empty()
+            {
+                continue;
+            }
+
+            let datetime = timestamp(&message.ts).ok_or_else(|| Error::Malformed {
+                line: Some(records.len() as u64 + 1),
+                byte: None,
+                snippet: Some(format!("{}: {}", day, message.ts)),
+                reason: Some("timestamp isn't a number of seconds".to_owned()),
+            })?;
+            let tag = match dms.contains(&channel) {
+                true => DM_TAG.to_owned(),
+                false => channel_tag(&channel),
+            };
+
+            let mut metadata = Metadata::new();
+            metadata.insert("channel".to_owned(), channel.clone().into());
+            metadata.insert("ts".to_owned(), message.ts.clone().into());
+            records.push(Record {
+                line: records.len() as u64 + 1,
+                datetime: Timestamp::Fixed(datetime),
+                message: format!("{} #{}", text(&message.text, &names).trim_end(), tag),
+                metadata,
+            });
+        }
+    }
+    Ok(records)
+}
+
+// Reads a JSON file from the export, or None if it isn't in there. Not every
+// export has every list of conversations.
+fn read_json<R: Read + Seek, T: for<'de> Deserialize<'de>>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Option<T>> {
+    let mut file = match zip.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(malformed(e)),
+    };
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|e| Error::Malformed {
+            line: None,
+            byte: None,
+            snippet: None,
+            reason: Some(format!("{}: {}", name, e)),
+        })
+}
+
+// Slack's timestamps are seconds since the epoch with microseconds after a
+// dot, like "1715590800.000200", and double as IDs for messages.
+fn timestamp(ts: &str) -> Option<DateTime<FixedOffset>> {
+    let (secs, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+    let nanos = format!("{:0<9}", micros).get(..9)?.parse().ok()?;
+    Local
+        .timestamp_opt(secs.parse().ok()?, nanos)
+        .single()
+        .map(|datetime| datetime.fixed_offset())
+}
+
+// Turns Slack's markup back in to what was typed.
+fn text(s: &str, names: &HashMap<&str, &str>) -> String {
+    let s = LINK.replace_all(s, |caps: &Captures| {
+        let (target, label) = (&caps[1], caps.get(2).map(|m| m.as_str()));
+        if let Some(id) = target.strip_prefix('@') {
+            return format!("@{}", names.get(id).copied().unwrap_or(id));
+        }
+        if let Some(id) = target.strip_prefix('#') {
+            return format!("#{}", label.unwrap_or(id));
+        }
+        if let Some(special) = target.strip_prefix('!') {
+            return format!("@{}", special);
+        }
+        match label {
+            Some(label) if label != target => format!("{} ({})", label, target),
+            _ => target.to_owned(),
+        }
+    });
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn malformed(e: zip::result::ZipError) -> Error {
+    Error::Malformed {
+        line: None,
+        byte: None,
+        snippet: None,
+        reason: Some(format!("this doesn't look like a Slack export: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use test_case::test_case;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    fn export(files: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        let mut w = zip.finish().unwrap();
+        w.set_position(0);
+        w
+    }
+
+    const USERS: &str = r#"[
+        {"id": "U1", "name": "sam", "real_name": "Sam Rose", "profile": {"display_name": "samwho"}},
+        {"id": "U2", "name": "alex", "real_name": "Alex"}
+    ]"#;
+
+    fn messages(user: &str) -> Vec<(i64, String)> {
+        let zip = export(&[
+            ("users.json", USERS),
+            ("channels.json", r#"[{"id": "C1", "name": "general"}]"#),
+            ("dms.json", r#"[{"id": "D1", "members": ["U1"]}]"#),
+            (
+                "general/2024-05-13.json",
+                r#"[
+                    {"type": "message", "user": "U1", "text": "morning <@U2> &amp; all", "ts": "1715590800.000200"},
+                    {"type": "message", "user": "U2", "text": "hi", "ts": "1715590860.000100"},
+                    {"type": "message", "subtype": "channel_join", "user": "U1", "text": "<@U1> has joined the channel", "ts": "1715590700.000000"},
+                    {"type": "message", "subtype": "me_message", "user": "U1", "text": "waves", "ts": "1715590900.000000"}
+                ]"#,
+            ),
+            (
+                "D1/2024-05-14.json",
+                r#"[{"type": "message", "user": "U1", "text": "note to self: <https://samwho.dev|my site>", "ts": "1715677200.000000"}]"#,
+            ),
+        ]);
+        parse(zip, user)
+            .unwrap()
+            .into_iter()
+            .map(|r| match r.datetime {
+                Timestamp::Fixed(datetime) => (datetime.timestamp(), r.message),
+                Timestamp::Local(_) => panic!("Slack timestamps are in UTC"),
+            })
+            .collect()
+    }
+
+    #[test_case("sam"      ; "username")]
+    #[test_case("U1"       ; "id")]
+    #[test_case("samwho"   ; "display name")]
+    #[test_case("sam rose" ; "real name")]
+    fn test_parse(user: &str) {
+        assert_eq!(
+            messages(user),
+            vec![
+                (
+                    1715677200,
+                    "note to self: my site (https://samwho.dev) #dm".to_owned()
+                ),
+                (1715590800, "morning @alex & all #general".to_owned()),
+                (1715590900, "waves #general".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let zip = export(&[
+            ("users.json", USERS),
+            (
+                "random/2024-05-13.json",
+                r#"[{"user": "U2", "text": "hello", "ts": "1715590800.000200"}]"#,
+            ),
+        ]);
+        let records = parse(zip, "alex").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].metadata["channel"], "random");
+        assert_eq!(records[0].metadata["ts"], "1715590800.000200");
+        match records[0].datetime {
+            Timestamp::Fixed(datetime) => {
+                assert_eq!(datetime.timestamp_subsec_micros(), 200)
+            }
+            Timestamp::Local(_) => panic!("Slack timestamps are in UTC"),
+        }
+    }
+
+    #[test]
+    fn test_parse_unknown_user() {
+        let err = parse(export(&[("users.json", USERS)]), "bob").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "there's no one called bob in the Slack export"
+        );
+    }
+
+    #[test]
+    fn test_parse_not_a_zip() {
+        let err = parse(Cursor::new(b"nope".to_vec()), "sam").unwrap_err();
+        assert_eq!(err.kind(), "malformed");
+    }
+
+    #[test_case("<#C1|general> and <#C2>"       => "#general and #C2"       ; "channels")]
+    #[test_case("<!here> look"                   => "@here look"             ; "special mentions")]
+    #[test_case("<https://samwho.dev>"           => "https://samwho.dev"     ; "bare link")]
+    #[test_case("<mailto:a@b.com|a@b.com>"       => "a@b.com (mailto:a@b.com)" ; "labelled link")]
+    #[test_case("<@U9> &lt;3"                    => "@U9 <3"                 ; "unknown user")]
+    fn test_text(s: &str) -> String {
+        text(s, &HashMap::new())
+    }
+}
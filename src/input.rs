@@ -0,0 +1,105 @@
+use super::{
+    entry::{self, Entry},
+    error::Error,
+    Result,
+};
+use serde::Deserialize;
+use std::convert::TryInto;
+use std::str::FromStr;
+
+/// Turns a single line of input in to an entry. Implement this to teach hmmp
+/// to read a new input format.
+pub trait LineParser: Send + Sync {
+    fn parse_line(&self, line: &str) -> Result<Entry>;
+}
+
+/// Parses lines in the same CSV format used by .hmm files.
+pub struct CsvParser {}
+
+impl LineParser for CsvParser {
+    fn parse_line(&self, line: &str) -> Result<Entry> {
+        line.try_into()
+    }
+}
+
+/// Parses lines that each hold a JSON object of the form
+/// `{"datetime": "2020-01-01T00:00:00+00:00", "message": "hello"}`, where the
+/// datetime is in RFC3339 format. Any other fields are ignored.
+pub struct JsonParser {}
+
+#[derive(Deserialize)]
+struct JsonEntry {
+    datetime: String,
+    message: String,
+}
+
+impl LineParser for JsonParser {
+    fn parse_line(&self, line: &str) -> Result<Entry> {
+        let json: JsonEntry = serde_json::from_str(entry::trim_line(line))?;
+        Ok(Entry::new(
+            chrono::DateTime::parse_from_rfc3339(&json.datetime)?,
+            json.message,
+        ))
+    }
+}
+
+/// The input formats hmmp knows how to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    Json,
+}
+
+impl InputFormat {
+    pub fn parser(&self) -> Box<dyn LineParser> {
+        match self {
+            InputFormat::Csv => Box::new(CsvParser {}),
+            InputFormat::Json => Box::new(JsonParser {}),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown input format \"{}\", expected csv or json",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("{\"datetime\":\"2020-01-01T00:00:00+00:00\",\"message\":\"hello\"}"                 => ("2020-01-01T00:00:00+00:00".to_owned(), "hello".to_owned()) ; "basic")]
+    #[test_case("{\"message\":\"hello\\nworld\",\"datetime\":\"2020-01-01T01:00:00+01:00\"}\n"        => ("2020-01-01T01:00:00+01:00".to_owned(), "hello\nworld".to_owned()) ; "field order and newlines")]
+    #[test_case("{\"datetime\":\"2020-01-01T00:00:00Z\",\"message\":\"hello\",\"tags\":[\"a\"]}\r\n" => ("2020-01-01T00:00:00+00:00".to_owned(), "hello".to_owned()) ; "extra fields")]
+    fn test_json_parser(line: &str) -> (String, String) {
+        let entry = JsonParser {}.parse_line(line).unwrap();
+        (entry.datetime().to_rfc3339(), entry.message().to_owned())
+    }
+
+    #[test_case("not json"                                          => "json"       ; "not json")]
+    #[test_case("{\"message\":\"hello\"}"                           => "json"       ; "missing datetime")]
+    #[test_case("{\"datetime\":\"yesterday\",\"message\":\"hello\"}" => "date_parse" ; "invalid datetime")]
+    fn test_json_parser_errors(line: &str) -> &'static str {
+        JsonParser {}.parse_line(line).err().unwrap().kind()
+    }
+
+    #[test]
+    fn test_csv_parser() {
+        let entry = InputFormat::Csv
+            .parser()
+            .parse_line("2020-01-01T00:00:00+00:00,\"\"\"hello\"\"\"\n")
+            .unwrap();
+        assert_eq!(entry.message(), "hello");
+    }
+}
@@ -0,0 +1,1121 @@
+use super::{
+    entries::Entries,
+    entry::{Entry, EntryKey, Metadata},
+    error::Error,
+    lock, Result,
+};
+use chrono::prelude::*;
+use fs2::FileExt;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+// The longest SkewPolicy::Wait will wait for the clock to catch up with the
+// last entry. Anything more than this is more likely to be a badly wrong clock
+// than a small step backwards, and waiting would look like hmm had hung.
+const MAX_SKEW_WAIT: Duration = Duration::from_secs(5);
+
+/// What to do when the last entry in a .hmm file is later than the current
+/// time, which usually means the system clock has been stepped backwards.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkewPolicy {
+    /// Refuse to write the entry, returning Error::ClockSkew.
+    #[default]
+    Error,
+
+    /// Wait for the clock to catch up with the last entry, as long as that
+    /// won't take more than a few seconds.
+    Wait,
+
+    /// Write the entry with a timestamp one nanosecond after the last entry.
+    Clamp,
+}
+
+impl SkewPolicy {
+    /// The name of the policy, as used in the config file and by --skew.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkewPolicy::Error => "error",
+            SkewPolicy::Wait => "wait",
+            SkewPolicy::Clamp => "clamp",
+        }
+    }
+}
+
+impl FromStr for SkewPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(SkewPolicy::Error),
+            "wait" => Ok(SkewPolicy::Wait),
+            "clamp" => Ok(SkewPolicy::Clamp),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown skew policy \"{}\", expected error, wait or clamp",
+                s
+            ))),
+        }
+    }
+}
+
+/// Controls how `append` writes to a .hmm file.
+#[derive(Debug, Clone)]
+pub struct AppendOptions {
+    /// Whether to take an exclusive lock on the file while writing. Turning
+    /// this off is only safe if nothing else writes to the file at the same
+    /// time.
+    pub lock: bool,
+
+    /// How long to wait for another process to release its lock before giving
+    /// up with Error::LockTimeout.
+    pub lock_timeout: Duration,
+
+    /// What to do if the last entry in the file is later than the current
+    /// time.
+    pub skew: SkewPolicy,
+}
+
+impl Default for AppendOptions {
+    fn default() -> Self {
+        AppendOptions {
+            lock: true,
+            lock_timeout: Duration::from_secs(10),
+            skew: SkewPolicy::default(),
+        }
+    }
+}
+
+/// Appends a new entry with the given message to the end of `f`, returning the
+/// entry that was written.
+///
+/// `f` must have been opened for reading and appending. Everything that
+/// depends on the current contents of the file happens while holding the
+/// lock: the entry's timestamp is only taken once the lock is held, and it's
+/// checked against the last entry in the file at that moment, so concurrent
+/// writers can't interleave their rows or write them out of order.
+pub fn append(f: &File, message: &str, opts: &AppendOptions) -> Result<Entry> {
+    append_with_metadata(f, message, &Metadata::new(), opts)
+}
+
+/// The same as `append`, but attaching `metadata` to the entry that's written.
+pub fn append_with_metadata(
+    f: &File,
+    message: &str,
+    metadata: &Metadata,
+    opts: &AppendOptions,
+) -> Result<Entry> {
+    let mut entries = append_entries(f, &[message], metadata, opts)?;
+    Ok(entries.remove(0))
+}
+
+/// The same as `append`, but writing an entry for each of `messages` in order
+/// while only taking the lock once. Either all of the entries are written or,
+/// if there's an error, none of them are.
+pub fn append_all<S: AsRef<str>>(
+    f: &File,
+    messages: &[S],
+    opts: &AppendOptions,
+) -> Result<Vec<Entry>> {
+    append_entries(f, messages, &Metadata::new(), opts)
+}
+
+/// The same as `append_all`, but attaching `metadata` to every entry that's
+/// written.
+pub fn append_all_with_metadata<S: AsRef<str>>(
+    f: &File,
+    messages: &[S],
+    metadata: &Metadata,
+    opts: &AppendOptions,
+) -> Result<Vec<Entry>> {
+    append_entries(f, messages, metadata, opts)
+}
+
+fn append_entries<S: AsRef<str>>(
+    f: &File,
+    messages: &[S],
+    metadata: &Metadata,
+    opts: &AppendOptions,
+) -> Result<Vec<Entry>> {
+    let mut skew = opts.skew;
+    loop {
+        if opts.lock {
+            lock(f, opts.lock_timeout)?;
+        }
+
+        let res = append_locked(f, messages, metadata, skew);
+
+        if opts.lock {
+            FileExt::unlock(f)?;
+        }
+
+        // The clock is waited for without holding the lock, so that other
+        // writers aren't held up, and then the last entry is checked again as
+        // it might have changed in the meantime. If the clock still hasn't
+        // caught up by then, it isn't going to soon.
+        match res? {
+            Appended::Written(entries) => return Ok(entries),
+            Appended::Behind(behind) => {
+                thread::sleep(behind);
+                skew = SkewPolicy::Error;
+            }
+        }
+    }
+}
+
+// What append_locked did: either wrote the entries, or found that the clock is
+// a little behind the last entry and wrote nothing, so it can be waited for.
+enum Appended {
+    Written(Vec<Entry>),
+    Behind(Duration),
+}
+
+fn append_locked<S: AsRef<str>>(
+    mut f: &File,
+    messages: &[S],
+    metadata: &Metadata,
+    skew: SkewPolicy,
+) -> Result<Appended> {
+    let mut last = Entries::new(BufReader::new(f))
+        .next_back()
+        .transpose()?
+        .map(|entry| *entry.datetime());
+
+    // The rows, including the newline that ends a previous entry if someone
+    // edited the file by hand and left it off, go out in a single write so
+    // that a reader never sees half of them.
+    let mut rows = String::new();
+    if !ends_with_newline(f)? {
+        rows.push('\n');
+    }
+
+    let mut entries = Vec::with_capacity(messages.len());
+    for message in messages {
+        let mut entry = Entry::with_message(message.as_ref());
+        if let Some(last) = last {
+            if &last > entry.datetime() {
+                entry = match skew {
+                    SkewPolicy::Error => return Err(Error::ClockSkew),
+                    SkewPolicy::Wait => return Ok(Appended::Behind(time_to_wait(&last, &entry)?)),
+                    SkewPolicy::Clamp => clamp(&last, entry),
+                };
+            }
+        }
+        let entry = entry.with_metadata(metadata.clone());
+        last = Some(*entry.datetime());
+        rows.push_str(&entry.to_csv_row()?);
+        entries.push(entry);
+    }
+
+    f.write_all(rows.as_bytes())?;
+    f.sync_data()?;
+
+    Ok(Appended::Written(entries))
+}
+
+/// Writes `entries`, which can have any timestamps, in to the journal at
+/// `path` so that every entry in it stays in order. If they're all later than
+/// the last entry in the journal they're simply appended, and otherwise a new
+/// copy of the journal with them in place is written next to it and renamed
+/// over it, so a crash part way through leaves the journal as it was. Entries
+/// with the same key as one already in the journal are skipped, so inserting
+/// the same entries twice doesn't duplicate them. Returns the number of
+/// entries written.
+///
+/// `f` must have been opened from `path` for reading and appending. Anyone
+/// else who has the journal open when it's replaced is left holding the old
+/// copy, and gets Error::Replaced the next time they try to write to it, so
+/// this is meant for occasional bulk changes like imports rather than everyday
+/// writes.
+pub fn insert(f: &File, path: &Path, entries: Vec<Entry>, opts: &AppendOptions) -> Result<usize> {
+    insert_sorted(f, path, entries, true, opts)
+}
+
+/// The same as `insert`, but writing every one of `entries`, even if there's
+/// already an entry with the same key, for entries that were written at the
+/// same time with the same message rather than being the same entry twice,
+/// like two identical lines of a log.
+pub fn insert_keeping_duplicates(
+    f: &File,
+    path: &Path,
+    entries: Vec<Entry>,
+    opts: &AppendOptions,
+) -> Result<usize> {
+    insert_sorted(f, path, entries, false, opts)
+}
+
+fn insert_sorted(
+    f: &File,
+    path: &Path,
+    mut entries: Vec<Entry>,
+    skip_duplicates: bool,
+    opts: &AppendOptions,
+) -> Result<usize> {
+    entries.sort_by(|a, b| a.datetime().cmp(b.datetime()));
+
+    if opts.lock {
+        lock(f, opts.lock_timeout)?;
+    }
+
+    let res = insert_locked(f, path, entries, skip_duplicates);
+
+    if opts.lock {
+        FileExt::unlock(f)?;
+    }
+
+    res
+}
+
+fn insert_locked(
+    mut f: &File,
+    path: &Path,
+    entries: Vec<Entry>,
+    skip_duplicates: bool,
+) -> Result<usize> {
+    let first = match entries.first() {
+        Some(entry) => *entry.datetime(),
+        None => return Ok(0),
+    };
+
+    // Everything from the first entry at or after the earliest new one gets
+    // merged with the new entries and written back out. Existing entries go
+    // first when timestamps are equal, so they never move relative to each
+    // other.
+    let mut existing = Entries::new(BufReader::new(f));
+    existing.seek_to_first(&first)?;
+    let pos = existing.position()?;
+    let tail: Vec<Entry> = existing.between(None, None)?.collect::<Result<_>>()?;
+
+    let mut merged = Vec::with_capacity(tail.len() + entries.len());
+    let mut written = 0;
+    let mut tail = tail.into_iter().peekable();
+    for entry in entries {
+        while let Some(old) = tail.next_if(|old| old.datetime() <= entry.datetime()) {
+            merged.push(old);
+        }
+        let duplicate = skip_duplicates
+            && merged
+                .iter()
+                .rev()
+                .take_while(|old| old.datetime() == entry.datetime())
+                .any(|old| old.key() == entry.key());
+        if !duplicate {
+            merged.push(entry);
+            written += 1;
+        }
+    }
+    merged.extend(tail);
+
+    if written == 0 {
+        return Ok(0);
+    }
+
+    // When nothing already in the journal has to move, appending can't lose
+    // any of it.
+    if pos == f.seek(SeekFrom::End(0))? {
+        if !ends_with_newline(f)? {
+            f.write_all(b"\n")?;
+        }
+        let mut w = JournalWriter::new(f);
+        w.append_many(&merged)?;
+        w.finish()?;
+        return Ok(written);
+    }
+
+    replace(f, path, pos, &merged)?;
+    Ok(written)
+}
+
+// Writes a new copy of the journal at `path`, made of the first `pos` bytes of
+// `f` followed by `tail`, and renames it over the journal. The new copy goes in
+// the same directory as the journal, following symlinks, so that the rename
+// doesn't cross filesystems and leaves a link to the journal where it was.
+fn replace(mut f: &File, path: &Path, pos: u64, tail: &[Entry]) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut tmp = tempfile::Builder::new()
+        .prefix(".hmm-")
+        .tempfile_in(dir)
+        .map_err(|source| Error::CreateFile {
+            path: dir.to_owned(),
+            source,
+        })?;
+    tmp.as_file().set_permissions(f.metadata()?.permissions())?;
+
+    f.seek(SeekFrom::Start(0))?;
+    io::copy(&mut f.take(pos), &mut tmp)?;
+    let mut w = JournalWriter::new(tmp.as_file());
+    w.append_many(tail)?;
+    w.finish()?;
+
+    tmp.persist(&path).map_err(|e| Error::Io(e.error))?;
+    Ok(())
+}
+
+/// Takes an exclusive lock on `f`, an open journal, like
+/// `lock::lock_exclusive`, then checks that it's still the journal rather
+/// than an old copy that `insert` or `merge` replaced while we waited, giving
+/// up with Error::Replaced if it isn't. Old copies can only be told apart on
+/// Unix, where they have no links left.
+pub fn lock(f: &File, timeout: Duration) -> Result<()> {
+    lock::lock_exclusive(f, timeout)?;
+    if replaced(f)? {
+        FileExt::unlock(f)?;
+        return Err(Error::Replaced);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn replaced(f: &File) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(f.metadata()?.nlink() == 0)
+}
+
+#[cfg(not(unix))]
+fn replaced(_: &File) -> Result<bool> {
+    Ok(false)
+}
+
+/// Merges `entries`, usually from another copy of the same journal, in to `f`.
+/// Both are treated as sets of entries identified by `Entry::key`, so anything
+/// in `entries` that isn't already in `f` is inserted where it belongs and
+/// everything else is left alone. Merging the same entries twice changes
+/// nothing, and merging two copies in to each other leaves them with the same
+/// entries, though ones with the same timestamp can be in a different order.
+/// Returns the number of entries added.
+///
+/// Like `insert`, this can replace the journal with a new copy, so it's meant
+/// for occasional bulk changes. `f` must have been opened from `path` for
+/// reading and appending.
+pub fn merge(f: &File, path: &Path, entries: Vec<Entry>, opts: &AppendOptions) -> Result<usize> {
+    if opts.lock {
+        lock(f, opts.lock_timeout)?;
+    }
+
+    let res = merge_locked(f, path, entries);
+
+    if opts.lock {
+        FileExt::unlock(f)?;
+    }
+
+    res
+}
+
+fn merge_locked(f: &File, path: &Path, entries: Vec<Entry>) -> Result<usize> {
+    let mut seen: HashSet<EntryKey> = Entries::new(BufReader::new(f))
+        .map(|entry| entry.map(|e| e.key()))
+        .collect::<Result<_>>()?;
+
+    let mut new: Vec<Entry> = entries
+        .into_iter()
+        .filter(|entry| seen.insert(entry.key()))
+        .collect();
+    new.sort_by(|a, b| a.datetime().cmp(b.datetime()));
+
+    insert_locked(f, path, new, true)
+}
+
+/// Finds copies of the journal at `path` that file syncing tools have made
+/// after it was changed in two places at once, like Dropbox's
+/// "journal (Sam's conflicted copy 2024-01-01).hmm" and Syncthing's
+/// "journal.sync-conflict-20240101-120000-ABCDEFG.hmm", sorted by name.
+pub fn conflicted_copies(path: &Path) -> Result<Vec<PathBuf>> {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return Ok(Vec::new()),
+    };
+    // Hidden files like .hmm don't have an extension, as far as these tools
+    // are concerned.
+    let (stem, ext) = match name.rfind('.') {
+        Some(0) | None => (name, ""),
+        Some(i) => name.split_at(i),
+    };
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut copies = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let file = file?;
+        let copy = match file.file_name().to_str() {
+            Some(copy) => copy.to_owned(),
+            None => continue,
+        };
+        let middle = match copy
+            .strip_prefix(stem)
+            .and_then(|rest| rest.strip_suffix(ext))
+        {
+            Some(middle) => middle,
+            None => continue,
+        };
+
+        let dropbox =
+            middle.starts_with(" (") && middle.ends_with(')') && middle.contains("conflicted copy");
+        let syncthing = middle.starts_with(".sync-conflict-");
+        if dropbox || syncthing {
+            copies.push(file.path());
+        }
+    }
+    copies.sort();
+    Ok(copies)
+}
+
+// How long to wait for the clock to catch up with `last`, the last entry in
+// the file, before writing `entry`.
+fn time_to_wait(last: &DateTime<FixedOffset>, entry: &Entry) -> Result<Duration> {
+    let behind = last
+        .signed_duration_since(*entry.datetime())
+        .to_std()
+        .map_err(|_| Error::ClockSkew)?;
+    if behind > MAX_SKEW_WAIT {
+        return Err(Error::ClockSkew);
+    }
+    Ok(behind)
+}
+
+// Moves `entry` to one nanosecond after `last`, the last entry in the file.
+fn clamp(last: &DateTime<FixedOffset>, entry: Entry) -> Entry {
+    let datetime = last.with_timezone(&Utc) + chrono::Duration::nanoseconds(1);
+    Entry::new(datetime.into(), entry.message().to_owned())
+}
+
+/// When a `JournalWriter` makes sure that what it has written is on disk, and
+/// not just handed to the operating system.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Never. This is the fastest, for files that could be written again if
+    /// they were lost, like generated test data.
+    Never,
+
+    /// Whenever the writer is flushed or finished.
+    #[default]
+    OnFlush,
+
+    /// After every this many entries, as well as whenever the writer is
+    /// flushed or finished.
+    Every(usize),
+}
+
+/// Controls how a `JournalWriter` buffers and syncs what it writes.
+#[derive(Debug, Clone)]
+pub struct WriterOptions {
+    /// How many bytes to buffer before writing them out.
+    pub buffer_capacity: usize,
+
+    /// When to sync what's been written to disk.
+    pub sync: SyncPolicy,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        WriterOptions {
+            buffer_capacity: 64 * 1024,
+            sync: SyncPolicy::default(),
+        }
+    }
+}
+
+/// Something a `JournalWriter` can write to. Files are synced to disk as the
+/// writer's `SyncPolicy` says, and anything else has nothing to sync.
+pub trait Sink: Write {
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sink for File {
+    fn sync(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+}
+
+impl Sink for &File {
+    fn sync(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+}
+
+impl Sink for Vec<u8> {}
+
+/// Writes lots of entries through one buffer, rather than building a row for
+/// each of them like `Entry::write` does, for imports and generating test
+/// data.
+///
+/// Unlike `append`, this doesn't lock the file, check that entries are in
+/// order or add a newline to the end of a file that's missing one. Callers
+/// that need any of that have to do it themselves.
+pub struct JournalWriter<W: Sink> {
+    csv: csv::Writer<W>,
+    sync: SyncPolicy,
+    unsynced: usize,
+}
+
+impl<W: Sink> JournalWriter<W> {
+    pub fn new(w: W) -> Self {
+        JournalWriter::with_options(w, &WriterOptions::default())
+    }
+
+    pub fn with_options(w: W, opts: &WriterOptions) -> Self {
+        JournalWriter {
+            // Entries with metadata have a column more than those without.
+            csv: csv::WriterBuilder::new()
+                .flexible(true)
+                .buffer_capacity(opts.buffer_capacity)
+                .from_writer(w),
+            sync: opts.sync,
+            unsynced: 0,
+        }
+    }
+
+    pub fn append(&mut self, entry: &Entry) -> Result<()> {
+        entry.write_record(&mut self.csv)?;
+        self.unsynced += 1;
+        if let SyncPolicy::Every(n) = self.sync {
+            if self.unsynced >= n {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends each of `entries` in order, returning how many there were.
+    pub fn append_many<'a, I>(&mut self, entries: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = &'a Entry>,
+    {
+        let mut n = 0;
+        for entry in entries {
+            self.append(entry)?;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Writes out everything that's been buffered, syncing it to disk unless
+    /// the policy is `SyncPolicy::Never`.
+    pub fn flush(&mut self) -> Result<()> {
+        self.csv.flush()?;
+        if self.sync != SyncPolicy::Never {
+            self.csv.get_ref().sync()?;
+        }
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Flushes the writer and gives back what it was writing to.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush()?;
+        self.csv.into_inner().map_err(|e| Error::Io(e.into_error()))
+    }
+}
+
+/// Whether `f` is empty or ends with a newline, meaning a new row can be
+/// written straight on to the end of it. This moves the file's cursor.
+pub fn ends_with_newline(mut f: &File) -> Result<bool> {
+    let len = f.seek(SeekFrom::End(0))?;
+    if len == 0 {
+        return Ok(true);
+    }
+
+    let mut last = [0; 1];
+    f.seek(SeekFrom::Start(len - 1))?;
+    f.read_exact(&mut last)?;
+    Ok(last[0] == b'\n')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::path::Path;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    fn open(path: &Path) -> File {
+        OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+    }
+
+    fn messages(path: &Path) -> Vec<String> {
+        Entries::new(BufReader::new(File::open(path).unwrap()))
+            .map(|e| e.unwrap().message().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_append() {
+        let tmp = NamedTempFile::new().unwrap();
+        let f = open(tmp.path());
+
+        append(&f, "hello", &AppendOptions::default()).unwrap();
+        append(&f, "world", &AppendOptions::default()).unwrap();
+
+        assert_eq!(messages(tmp.path()), vec!["hello", "world"]);
+    }
+
+    #[test]
+    fn test_append_with_metadata() {
+        let tmp = NamedTempFile::new().unwrap();
+        let f = open(tmp.path());
+
+        let mut metadata = Metadata::new();
+        metadata.insert("mood".to_owned(), 4.into());
+        let entry =
+            append_with_metadata(&f, "hello", &metadata, &AppendOptions::default()).unwrap();
+        assert_eq!(entry.metadata(), &metadata);
+
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(tmp.path()).unwrap()))
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].message(), "hello");
+        assert_eq!(entries[0].metadata(), &metadata);
+    }
+
+    #[test]
+    fn test_append_missing_trailing_newline() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        write!(tmp, "2020-01-01T00:00:00+00:00,\"\"\"hello\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        append(&f, "world", &AppendOptions::default()).unwrap();
+
+        assert_eq!(messages(tmp.path()), vec!["hello", "world"]);
+    }
+
+    #[test]
+    fn test_append_clock_skew() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        writeln!(tmp, "9999-01-01T00:00:00+00:00,\"\"\"future\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        let err = append(&f, "now", &AppendOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), "clock_skew");
+        assert_eq!(messages(tmp.path()), vec!["future"]);
+
+        // The lock must have been released despite the error.
+        f.try_lock_exclusive().unwrap();
+    }
+
+    #[test]
+    fn test_append_wait_gives_up() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        writeln!(tmp, "9999-01-01T00:00:00+00:00,\"\"\"future\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        // Waiting only helps if the clock will catch up soon, which it won't
+        // here, so this should fail straight away rather than sleep.
+        let opts = AppendOptions {
+            skew: SkewPolicy::Wait,
+            ..AppendOptions::default()
+        };
+        let err = append(&f, "now", &opts).err().unwrap();
+        assert_eq!(err.kind(), "clock_skew");
+        assert_eq!(messages(tmp.path()), vec!["future"]);
+    }
+
+    #[test]
+    fn test_append_clamp_timestamp() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        writeln!(tmp, "9999-01-01T00:00:00+00:00,\"\"\"future\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        let opts = AppendOptions {
+            skew: SkewPolicy::Clamp,
+            ..AppendOptions::default()
+        };
+        let entry = append(&f, "now", &opts).unwrap();
+        assert_eq!(
+            entry.datetime().to_rfc3339(),
+            "9999-01-01T00:00:00.000000001+00:00"
+        );
+    }
+
+    #[test]
+    fn test_append_wait() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        let soon = Utc::now() + chrono::Duration::milliseconds(50);
+        writeln!(tmp, "{},\"\"\"soon\"\"\"", soon.to_rfc3339()).unwrap();
+        let f = open(tmp.path());
+
+        let opts = AppendOptions {
+            skew: SkewPolicy::Wait,
+            ..AppendOptions::default()
+        };
+        let entry = append(&f, "now", &opts).unwrap();
+        assert!(entry.datetime() >= &soon);
+        assert_eq!(messages(tmp.path()), vec!["soon", "now"]);
+    }
+
+    #[test]
+    fn test_append_wait_releases_lock() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        let soon = Utc::now() + chrono::Duration::milliseconds(500);
+        writeln!(tmp, "{},\"\"\"soon\"\"\"", soon.to_rfc3339()).unwrap();
+        let path = tmp.path().to_owned();
+
+        let waiting = thread::spawn(move || {
+            let opts = AppendOptions {
+                skew: SkewPolicy::Wait,
+                ..AppendOptions::default()
+            };
+            append(&open(&path), "now", &opts).unwrap()
+        });
+
+        // Another writer can take the lock while the first waits for the
+        // clock, and what it writes is taken in to account afterwards.
+        thread::sleep(Duration::from_millis(100));
+        let other = open(tmp.path());
+        other.try_lock_exclusive().unwrap();
+        writeln!(&other, "{},\"\"\"other\"\"\"", soon.to_rfc3339()).unwrap();
+        FileExt::unlock(&other).unwrap();
+
+        let entry = waiting.join().unwrap();
+        assert!(entry.datetime() >= &soon);
+        assert_eq!(messages(tmp.path()), vec!["soon", "other", "now"]);
+    }
+
+    #[test]
+    fn test_append_all() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        write!(tmp, "2020-01-01T00:00:00+00:00,\"\"\"hello\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        let entries = append_all(&f, &["a", "b", "c"], &AppendOptions::default()).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert!(entries
+            .windows(2)
+            .all(|w| w[0].datetime() <= w[1].datetime()));
+        assert_eq!(messages(tmp.path()), vec!["hello", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_append_all_clock_skew_writes_nothing() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        writeln!(tmp, "9999-01-01T00:00:00+00:00,\"\"\"future\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        let err = append_all(&f, &["a", "b"], &AppendOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), "clock_skew");
+        assert_eq!(messages(tmp.path()), vec!["future"]);
+    }
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        Entry::new(
+            DateTime::parse_from_rfc3339(datetime).unwrap(),
+            message.to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_journal_writer() {
+        let mut metadata = Metadata::new();
+        metadata.insert("mood".to_owned(), 3.into());
+        let entries = vec![
+            entry("2020-01-01T00:00:00+00:00", "hello, \"world\""),
+            entry("2020-01-02T00:00:00+00:00", "two\nlines").with_metadata(metadata),
+        ];
+
+        let mut w = JournalWriter::new(Vec::new());
+        assert_eq!(w.append_many(&entries).unwrap(), 2);
+        let written = String::from_utf8(w.finish().unwrap()).unwrap();
+
+        let rows: Vec<String> = entries.iter().map(|e| e.to_csv_row().unwrap()).collect();
+        assert_eq!(written, rows.concat());
+    }
+
+    #[test_case(SyncPolicy::Never    => 0 ; "never")]
+    #[test_case(SyncPolicy::OnFlush  => 1 ; "on flush")]
+    #[test_case(SyncPolicy::Every(2) => 3 ; "every two")]
+    fn test_journal_writer_sync(sync: SyncPolicy) -> usize {
+        #[derive(Default)]
+        struct Counter {
+            syncs: std::cell::Cell<usize>,
+        }
+
+        impl Write for Counter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl Sink for Counter {
+            fn sync(&self) -> io::Result<()> {
+                self.syncs.set(self.syncs.get() + 1);
+                Ok(())
+            }
+        }
+
+        let opts = WriterOptions {
+            sync,
+            ..WriterOptions::default()
+        };
+        let mut w = JournalWriter::with_options(Counter::default(), &opts);
+        for i in 0..5 {
+            w.append(&entry("2020-01-01T00:00:00+00:00", &i.to_string()))
+                .unwrap();
+        }
+        w.finish().unwrap().syncs.get()
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        write!(
+            tmp,
+            "2020-01-01T00:00:00+00:00,\"\"\"a\"\"\"\n2020-03-01T00:00:00+00:00,\"\"\"c\"\"\"\n2020-05-01T00:00:00+00:00,\"\"\"e\"\"\""
+        )
+        .unwrap();
+        let f = open(tmp.path());
+
+        let entry = |datetime: &str, message: &str| {
+            Entry::new(
+                DateTime::parse_from_rfc3339(datetime).unwrap(),
+                message.to_owned(),
+            )
+        };
+        let new = vec![
+            entry("2020-06-01T00:00:00+00:00", "f"),
+            entry("2020-02-01T00:00:00+00:00", "b"),
+            entry("2020-03-01T00:00:00+00:00", "c"),
+            entry("2020-03-01T00:00:00+00:00", "c2"),
+            entry("2020-04-01T00:00:00+02:00", "d"),
+        ];
+
+        assert_eq!(
+            insert(&f, tmp.path(), new, &AppendOptions::default()).unwrap(),
+            4
+        );
+        assert_eq!(
+            messages(tmp.path()),
+            vec!["a", "b", "c", "c2", "d", "e", "f"]
+        );
+    }
+
+    #[test_case(vec![]                                => 0 ; "nothing")]
+    #[test_case(vec!["2021-01-01T00:00:00+00:00"]     => 1 ; "after everything")]
+    #[test_case(vec!["2019-01-01T00:00:00+00:00"]     => 1 ; "before everything")]
+    #[test_case(vec!["2020-01-01T00:00:00+00:00"]     => 0 ; "duplicate")]
+    fn test_insert_count(datetimes: Vec<&str>) -> usize {
+        let mut tmp = NamedTempFile::new().unwrap();
+        writeln!(tmp, "2020-01-01T00:00:00+00:00,\"\"\"x\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        let entries = datetimes
+            .into_iter()
+            .map(|d| Entry::new(DateTime::parse_from_rfc3339(d).unwrap(), "x".to_owned()))
+            .collect();
+        let n = insert(&f, tmp.path(), entries, &AppendOptions::default()).unwrap();
+        assert_eq!(messages(tmp.path()).len(), 1 + n);
+        n
+    }
+
+    #[test]
+    fn test_insert_skips_same_key() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        writeln!(
+            tmp,
+            "2020-01-01T00:00:00+00:00,\"\"\"x\"\"\",\"{{\"\"a\"\":1}}\""
+        )
+        .unwrap();
+        let f = open(tmp.path());
+
+        let datetime = DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap();
+        let mut metadata = crate::entry::Metadata::new();
+        metadata.insert("a".to_owned(), 1.into());
+        let entries = vec![
+            Entry::new(datetime, "x".to_owned()).with_metadata(metadata),
+            Entry::new(datetime, "x".to_owned()),
+        ];
+
+        assert_eq!(
+            insert(&f, tmp.path(), entries, &AppendOptions::default()).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_insert_keeping_duplicates() {
+        let mut tmp = NamedTempFile::new().unwrap();
+        writeln!(tmp, "2020-01-01T00:00:00+00:00,\"\"\"x\"\"\"").unwrap();
+        let f = open(tmp.path());
+
+        let datetime = DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap();
+        let entries = vec![
+            Entry::new(datetime, "x".to_owned()),
+            Entry::new(datetime, "x".to_owned()),
+        ];
+        let written =
+            insert_keeping_duplicates(&f, tmp.path(), entries, &AppendOptions::default()).unwrap();
+        assert_eq!(written, 2);
+        assert_eq!(
+            std::fs::read_to_string(tmp.path()).unwrap(),
+            "2020-01-01T00:00:00+00:00,\"\"\"x\"\"\"\n".repeat(3)
+        );
+    }
+
+    #[test]
+    fn test_insert_replaces_journal() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("journal.hmm");
+        std::fs::write(&path, "2020-03-01T00:00:00+00:00,\"\"\"b\"\"\"\n").unwrap();
+        let f = open(&path);
+        let old = open(&path);
+
+        let entry = Entry::new(
+            DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap(),
+            "a".to_owned(),
+        );
+        insert(&f, &path, vec![entry], &AppendOptions::default()).unwrap();
+        assert_eq!(messages(&path), vec!["a", "b"]);
+
+        // Nothing is left behind, and anyone who had the journal open finds
+        // out that it's been replaced rather than writing to the old copy.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        #[cfg(unix)]
+        assert_eq!(
+            append(&old, "c", &AppendOptions::default())
+                .err()
+                .unwrap()
+                .kind(),
+            "replaced"
+        );
+        append(&open(&path), "c", &AppendOptions::default()).unwrap();
+        assert_eq!(messages(&path), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let row = |minute: u32, message: &str| {
+            Entry::new(
+                Utc.with_ymd_and_hms(2020, 1, 1, 0, minute, 0)
+                    .unwrap()
+                    .into(),
+                message.to_owned(),
+            )
+            .to_csv_row()
+            .unwrap()
+        };
+        let ours = [row(1, "a"), row(2, "b"), row(4, "d")].concat();
+        let theirs = [row(1, "a"), row(3, "c"), row(4, "d2"), row(5, "e")].concat();
+
+        let merge_in_to = |to: &str, from: &str| {
+            let mut tmp = NamedTempFile::new().unwrap();
+            write!(tmp, "{}", from).unwrap();
+            let from: Vec<Entry> = Entries::new(BufReader::new(File::open(tmp.path()).unwrap()))
+                .collect::<Result<_>>()
+                .unwrap();
+
+            let mut tmp = NamedTempFile::new().unwrap();
+            write!(tmp, "{}", to).unwrap();
+            let n = merge(
+                &open(tmp.path()),
+                tmp.path(),
+                from,
+                &AppendOptions::default(),
+            )
+            .unwrap();
+            (
+                n,
+                std::fs::read_to_string(tmp.path()).unwrap(),
+                messages(tmp.path()),
+            )
+        };
+
+        let (n, merged, messages) = merge_in_to(&ours, &theirs);
+        assert_eq!(n, 3);
+        assert_eq!(messages, vec!["a", "b", "c", "d", "d2", "e"]);
+        let (n, _, mut messages) = merge_in_to(&theirs, &ours);
+        messages.sort();
+        assert_eq!(n, 2);
+        assert_eq!(messages, vec!["a", "b", "c", "d", "d2", "e"]);
+        assert_eq!(merge_in_to(&merged, &theirs).0, 0);
+    }
+
+    #[test]
+    fn test_conflicted_copies() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in [
+            "journal.hmm",
+            "journal (Sam's conflicted copy 2024-01-01).hmm",
+            "journal.sync-conflict-20240101-120000-ABCDEFG.hmm",
+            "journal (copy).hmm",
+            "journal.hmm.bak",
+            "other (conflicted copy).hmm",
+            ".hmm",
+            ".hmm (conflicted copy 2024-01-01)",
+        ] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+
+        let names = |journal: &str| -> Vec<String> {
+            conflicted_copies(&dir.path().join(journal))
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(
+            names("journal.hmm"),
+            vec![
+                "journal (Sam's conflicted copy 2024-01-01).hmm",
+                "journal.sync-conflict-20240101-120000-ABCDEFG.hmm",
+            ]
+        );
+        assert_eq!(names(".hmm"), vec![".hmm (conflicted copy 2024-01-01)"]);
+    }
+
+    #[test_case("error" => SkewPolicy::Error)]
+    #[test_case("wait"  => SkewPolicy::Wait)]
+    #[test_case("clamp" => SkewPolicy::Clamp)]
+    fn test_skew_policy_from_str(s: &str) -> SkewPolicy {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_append_concurrent() {
+        let tmp = NamedTempFile::new().unwrap();
+
+        let threads: Vec<_> = (0..16)
+            .map(|i| {
+                let path = tmp.path().to_owned();
+                thread::spawn(move || {
+                    let f = open(&path);
+                    for j in 0..10 {
+                        append(&f, &format!("{}-{}", i, j), &AppendOptions::default()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let entries: Vec<Entry> = Entries::new(BufReader::new(File::open(tmp.path()).unwrap()))
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(entries.len(), 160);
+        assert!(entries
+            .windows(2)
+            .all(|w| w[0].datetime() <= w[1].datetime()));
+    }
+}
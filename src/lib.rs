@@ -1,7 +1,77 @@
+#[cfg(feature = "caldav")]
+pub mod caldav;
+#[cfg(feature = "format")]
+pub mod calendar;
+#[cfg(feature = "cli")]
+pub mod config;
+pub mod crypt;
+mod curl;
+pub mod date;
+#[cfg(feature = "format")]
+pub mod digest;
+#[cfg(feature = "fs")]
+pub mod draft;
 pub mod entries;
 pub mod entry;
+pub mod entry_template;
 pub mod error;
+#[cfg(feature = "parquet")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filter;
+#[cfg(feature = "format")]
 pub mod format;
+#[cfg(feature = "format")]
+pub mod front_matter;
+pub mod fuzzy;
+pub mod gaps;
+#[cfg(feature = "format")]
+pub mod graph;
+pub mod hook;
+pub mod ics;
+#[cfg(feature = "fs")]
+pub mod import;
+pub mod input;
+#[cfg(feature = "fs")]
+pub mod journal;
+#[cfg(feature = "format")]
+pub mod links;
+pub mod location;
+#[cfg(feature = "fs")]
+pub mod lock;
+#[cfg(feature = "format")]
+pub mod mood;
+#[cfg(feature = "tokio")]
+pub mod nonblocking;
+#[cfg(feature = "fs")]
+pub mod pins;
+#[cfg(test)]
+mod proptests;
+pub mod redact;
+#[cfg(feature = "fs")]
+pub mod remind;
+pub mod remote;
+#[cfg(feature = "format")]
+pub mod review;
+#[cfg(feature = "fs")]
+pub mod scan;
 pub mod seek;
+#[cfg(feature = "format")]
+pub mod series;
+#[cfg(feature = "format")]
+pub mod site;
+pub mod sorted_file;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod storage;
+#[cfg(feature = "format")]
+pub mod table;
+#[cfg(feature = "format")]
+pub mod text;
+pub mod track;
+pub mod twtxt;
+#[cfg(feature = "format")]
+pub mod year;
 
 pub type Result<T> = std::result::Result<T, error::Error>;
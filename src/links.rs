@@ -0,0 +1,148 @@
+//! Links between entries, made by putting another entry's ID, like
+//! "^cbbf4083", in a message. hmmq --backlinks finds the entries linking to
+//! one, and hmmq --link-previews shows what each link points to.
+
+use super::{crypt, entry, entry::EntryRef, text};
+use std::collections::HashMap;
+
+/// How many columns of a linked entry's title are shown after a link.
+const PREVIEW_WIDTH: usize = 40;
+
+/// The entries in a journal and the links between them. It only keeps each
+/// entry's title, so building one for a whole journal is cheap.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Graph {
+    titles: HashMap<String, String>,
+    backlinks: HashMap<String, Vec<String>>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph::default()
+    }
+
+    /// Adds `entry` and the links in its message. Encrypted entries are
+    /// added without a title, and the links in them can't be seen.
+    pub fn add(&mut self, entry: &EntryRef) {
+        let id = entry.id();
+        if crypt::is_encrypted(entry.message(), entry.metadata()) {
+            self.titles.insert(id, String::new());
+            return;
+        }
+        for link in entry::links(entry.message()) {
+            let from = self.backlinks.entry(link).or_default();
+            if !from.contains(&id) {
+                from.push(id.clone());
+            }
+        }
+        self.titles.insert(id, entry.title().trim().to_owned());
+    }
+
+    /// Whether there's an entry with the ID `id` in the graph.
+    pub fn contains(&self, id: &str) -> bool {
+        self.titles.contains_key(id)
+    }
+
+    /// The title of the entry with the ID `id`, if it's in the graph and has
+    /// one.
+    pub fn title(&self, id: &str) -> Option<&str> {
+        self.titles
+            .get(id)
+            .map(String::as_str)
+            .filter(|title| !title.is_empty())
+    }
+
+    /// The IDs of the entries linking to `id`, in the order they were added.
+    pub fn backlinks(&self, id: &str) -> &[String] {
+        self.backlinks.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    /// `message` with the title of the entry each link points to after it,
+    /// like `^cbbf4083 ("Planning the move")`. Links to entries that aren't
+    /// in the graph, or that don't have a title, are left as they are.
+    pub fn preview(&self, message: &str) -> String {
+        let mut previewed = String::with_capacity(message.len());
+        let mut last = 0;
+        for (span, id) in entry::link_spans(message) {
+            if let Some(title) = self.title(&id) {
+                previewed.push_str(&message[last..span.end]);
+                previewed.push_str(&format!(
+                    " (\"{}\")",
+                    text::truncate(title, PREVIEW_WIDTH, "…")
+                ));
+                last = span.end;
+            }
+        }
+        previewed.push_str(&message[last..]);
+        previewed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Entry;
+    use chrono::prelude::*;
+    use test_case::test_case;
+
+    fn entry(minute: u32, message: &str) -> Entry {
+        let datetime = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2024, 6, 1, 9, minute, 0)
+            .unwrap();
+        Entry::new(datetime, message.to_owned())
+    }
+
+    fn graph(entries: &[Entry]) -> Graph {
+        let mut graph = Graph::new();
+        for entry in entries {
+            graph.add(&entry.as_entry_ref());
+        }
+        graph
+    }
+
+    #[test]
+    fn test_backlinks() {
+        let first = entry(0, "Planning the move\nboxes");
+        let id = first.id();
+        let second = entry(1, &format!("see ^{} and ^{}", id, id));
+        let third = entry(2, &format!("more on ^{}", id.to_uppercase()));
+        let graph = graph(&[first, second.clone(), third.clone()]);
+
+        assert_eq!(graph.backlinks(&id), &[second.id(), third.id()]);
+        assert!(graph.backlinks(&second.id()).is_empty());
+        assert!(graph.contains(&id));
+        assert!(!graph.contains("00000000"));
+    }
+
+    #[test_case("see ^{}."                 => "see ^{} (\"Planning the move\")." ; "link")]
+    #[test_case("^{} ^00000000"            => "^{} (\"Planning the move\") ^00000000" ; "unknown link")]
+    #[test_case("no links"                 => "no links"                          ; "no links")]
+    fn test_preview(message: &str) -> String {
+        let first = entry(0, "Planning the move\nboxes");
+        let id = first.id();
+        let graph = graph(&[first]);
+        graph
+            .preview(&message.replace("{}", &id))
+            .replace(&id, "{}")
+    }
+
+    #[test]
+    fn test_preview_truncates_and_skips_encrypted() {
+        let long = entry(0, &"a".repeat(50));
+        let encrypted = entry(1, "hmm-encrypted:c2VjcmV0").with_metadata(crypt::metadata());
+        let message = format!("^{} ^{}", long.id(), encrypted.id());
+        let graph = graph(&[long.clone(), encrypted.clone()]);
+
+        assert!(graph.contains(&encrypted.id()));
+        assert_eq!(
+            graph.preview(&message),
+            format!(
+                "^{} (\"{}…\") ^{}",
+                long.id(),
+                "a".repeat(39),
+                encrypted.id()
+            )
+        );
+    }
+}
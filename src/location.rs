@@ -0,0 +1,283 @@
+use super::{entry::Metadata, error::Error, hook, Result};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// The metadata field locations are stored in, written by hmm when
+/// location_command is set.
+pub const FIELD: &str = "location";
+
+// How long location_command gets to print a location. Finding one can take a
+// while with GPS, but an entry shouldn't wait on it forever.
+const LOCATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The mean radius of the Earth.
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// A point on the Earth, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Location {
+    pub fn new(lat: f64, lon: f64) -> Result<Location> {
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(Error::InvalidArgument(format!(
+                "{},{} isn't a latitude and longitude",
+                lat, lon
+            )));
+        }
+        Ok(Location { lat, lon })
+    }
+
+    /// The location stored in `metadata`, if it has one.
+    pub fn of(metadata: &Metadata) -> Option<Location> {
+        let location = metadata.get(FIELD)?;
+        Location::new(location["lat"].as_f64()?, location["lon"].as_f64()?).ok()
+    }
+
+    /// How the location is stored in an entry's metadata, as an object like
+    /// {"lat": 51.5007, "lon": -0.1246}.
+    pub fn to_json(self) -> Value {
+        json!({ "lat": self.lat, "lon": self.lon })
+    }
+
+    /// How far apart two locations are as the crow flies, in kilometres.
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+
+    /// Reads a location from what a command like CoreLocationCLI or
+    /// termux-location prints: either a latitude and longitude separated by a
+    /// comma or whitespace, or a JSON object with latitude and longitude
+    /// fields.
+    pub fn parse_output(s: &str) -> Result<Location> {
+        let s = s.trim();
+        if let Ok(Value::Object(object)) = serde_json::from_str(s) {
+            let field = |names: &[&str]| names.iter().find_map(|name| object.get(*name)?.as_f64());
+            return match (field(&["latitude", "lat"]), field(&["longitude", "lon"])) {
+                (Some(lat), Some(lon)) => Location::new(lat, lon),
+                _ => Err(Error::Location(
+                    "its JSON doesn't have a latitude and longitude".to_owned(),
+                )),
+            };
+        }
+
+        let mut numbers = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(f64::from_str);
+        match (numbers.next(), numbers.next()) {
+            (Some(Ok(lat)), Some(Ok(lon))) => Location::new(lat, lon),
+            _ => Err(Error::Location(format!(
+                "expected a latitude and longitude, got \"{}\"",
+                s.lines().next().unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+/// Runs `command`, a shell command like `CoreLocationCLI`, and reads where you
+/// are from what it prints. If it hasn't finished after 10 seconds it's killed
+/// and an error is returned.
+pub fn locate(command: &str) -> Result<Location> {
+    locate_within(command, LOCATE_TIMEOUT)
+}
+
+fn locate_within(command: &str, timeout: Duration) -> Result<Location> {
+    let mut child = hook::shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Location(format!("couldn't run `{}`: {}", command, e)))?;
+
+    // What it prints is read on another thread, so that we can stop waiting
+    // for it. Both have to be read at once, in case it fills up one pipe
+    // while we're waiting on the other.
+    let (mut stdout, mut stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let err = thread::spawn(move || {
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).map(|_| buf)
+        });
+        let mut out = Vec::new();
+        let res = stdout.read_to_end(&mut out);
+        let err = err.join().unwrap_or_else(|_| Ok(Vec::new()));
+        let _ = tx.send(res.and(err).map(|err| (out, err)));
+    });
+
+    let (out, err) = match rx.recv_timeout(timeout) {
+        Ok(output) => output?,
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::Location(format!(
+                "`{}` didn't finish within {} seconds",
+                command,
+                timeout.as_secs_f64()
+            )));
+        }
+    };
+    if !child.wait()?.success() {
+        return Err(Error::Location(format!(
+            "`{}` failed: {}",
+            command,
+            String::from_utf8_lossy(&err).trim()
+        )));
+    }
+    Location::parse_output(&String::from_utf8(out)?)
+}
+
+/// An area around a location, for hmmq --near, given like "51.5,-0.12,2km".
+/// Radiuses are in kilometres unless they end in m for metres.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Near {
+    center: Location,
+    radius_km: f64,
+}
+
+impl Near {
+    /// Whether `metadata` has a location inside the area. Entries without a
+    /// location are never near anywhere.
+    pub fn contains(&self, metadata: &Metadata) -> bool {
+        Location::of(metadata).is_some_and(|l| self.center.distance_km(&l) <= self.radius_km)
+    }
+}
+
+impl FromStr for Near {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            Error::InvalidArgument(format!(
+                "invalid --near \"{}\", expected a latitude, longitude and radius like 51.5,-0.12,2km",
+                s
+            ))
+        };
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let (lat, lon, radius) = match parts[..] {
+            [lat, lon, radius] => (lat, lon, radius),
+            _ => return Err(invalid()),
+        };
+        let radius_km = match radius.strip_suffix("km") {
+            Some(km) => km.parse::<f64>().ok(),
+            None => match radius.strip_suffix('m') {
+                Some(m) => m.parse::<f64>().ok().map(|m| m / 1000.0),
+                None => radius.parse().ok(),
+            },
+        }
+        .filter(|r| *r >= 0.0 && r.is_finite())
+        .ok_or_else(invalid)?;
+        let center = Location::new(
+            lat.parse().map_err(|_| invalid())?,
+            lon.parse().map_err(|_| invalid())?,
+        )?;
+        Ok(Near { center, radius_km })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const BIG_BEN: Location = Location {
+        lat: 51.5007,
+        lon: -0.1246,
+    };
+
+    fn metadata(location: Location) -> Metadata {
+        let mut metadata = Metadata::new();
+        metadata.insert(FIELD.to_owned(), location.to_json());
+        metadata
+    }
+
+    #[test_case("51.5007,-0.1246"                                   => Ok((51.5007, -0.1246)) ; "comma")]
+    #[test_case("51.5007 -0.1246\n"                                  => Ok((51.5007, -0.1246)) ; "whitespace")]
+    #[test_case("51.5007, -0.1246, 12.0"                             => Ok((51.5007, -0.1246)) ; "altitude")]
+    #[test_case("{\"latitude\": 51.5007, \"longitude\": -0.1246, \"accuracy\": 20}" => Ok((51.5007, -0.1246)) ; "termux json")]
+    #[test_case("{\"accuracy\": 20}"                                 => Err("couldn't find your location: its JSON doesn't have a latitude and longitude".to_owned()) ; "json without location")]
+    #[test_case("unknown"                                            => Err("couldn't find your location: expected a latitude and longitude, got \"unknown\"".to_owned()) ; "not a location")]
+    #[test_case("95,0"                                               => Err("95,0 isn't a latitude and longitude".to_owned()) ; "out of range")]
+    fn test_parse_output(s: &str) -> std::result::Result<(f64, f64), String> {
+        Location::parse_output(s)
+            .map(|l| (l.lat, l.lon))
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_of() {
+        assert_eq!(Location::of(&metadata(BIG_BEN)), Some(BIG_BEN));
+        assert_eq!(Location::of(&Metadata::new()), None);
+        let mut metadata = Metadata::new();
+        metadata.insert(FIELD.to_owned(), "somewhere".into());
+        assert_eq!(Location::of(&metadata), None);
+    }
+
+    #[test]
+    fn test_distance_km() {
+        let eiffel_tower = Location::new(48.8584, 2.2945).unwrap();
+        let distance = BIG_BEN.distance_km(&eiffel_tower);
+        assert!((distance - 340.6).abs() < 0.5, "{}", distance);
+        assert_eq!(BIG_BEN.distance_km(&BIG_BEN), 0.0);
+    }
+
+    #[test_case("51.5,-0.12,2km"  => true  ; "km")]
+    #[test_case("51.5,-0.12,2"    => true  ; "no unit")]
+    #[test_case("51.5,-0.12,300m" => false ; "metres")]
+    #[test_case("51.5,-0.12,900m" => true  ; "more metres")]
+    #[test_case("48.86,2.29,10km" => false ; "elsewhere")]
+    fn test_near(near: &str) -> bool {
+        near.parse::<Near>().unwrap().contains(&metadata(BIG_BEN))
+    }
+
+    #[test]
+    fn test_near_without_location() {
+        let near: Near = "51.5,-0.12,2km".parse().unwrap();
+        assert!(!near.contains(&Metadata::new()));
+    }
+
+    #[test_case("51.5,-0.12"        ; "no radius")]
+    #[test_case("51.5,-0.12,far"    ; "bad radius")]
+    #[test_case("51.5,-0.12,-1km"   ; "negative radius")]
+    #[test_case("north,-0.12,1km"   ; "bad latitude")]
+    #[test_case("91,-0.12,1km"      ; "latitude out of range")]
+    fn test_near_invalid(near: &str) {
+        assert!(near.parse::<Near>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_locate() {
+        assert_eq!(locate("echo 51.5007,-0.1246").unwrap(), BIG_BEN);
+        assert_eq!(
+            locate("echo nope >&2; exit 1").unwrap_err().kind(),
+            "location"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_locate_times_out() {
+        let start = std::time::Instant::now();
+        let err =
+            locate_within("sleep 5; echo 51.5007,-0.1246", Duration::from_millis(100)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            err.to_string(),
+            "couldn't find your location: `sleep 5; echo 51.5007,-0.1246` didn't finish within 0.1 seconds"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use super::{error::Error, Result};
+use fs2::FileExt;
+use std::fs::File;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The longest we'll sleep between attempts to take a lock. Backoff doubles the
+// wait each time, so without a cap we could end up sleeping well past the
+// point the lock became free.
+const MAX_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Takes an exclusive lock on the given file, retrying with exponential backoff
+/// if another process holds it. Gives up with Error::LockTimeout if the lock
+/// couldn't be taken within `timeout`.
+pub fn lock_exclusive(f: &File, timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    let mut wait = Duration::from_millis(1);
+
+    loop {
+        match f.try_lock_exclusive() {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return Err(Error::LockTimeout(timeout));
+        }
+
+        thread::sleep(wait.min(timeout - elapsed));
+        wait = (wait * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Parses a --lock-timeout, a number of seconds that can have a fraction, like
+/// 0.5. Zero means giving up straight away if the lock is taken.
+pub fn parse_timeout(s: &str) -> Result<Duration> {
+    s.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| {
+            Error::InvalidArgument(
+                "--lock-timeout must be a non-negative number of seconds".to_owned(),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    #[test]
+    fn test_lock_uncontended() {
+        let f = NamedTempFile::new().unwrap();
+        lock_exclusive(f.as_file(), Duration::from_millis(10)).unwrap();
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let f = NamedTempFile::new().unwrap();
+        let holder = File::open(f.path()).unwrap();
+        holder.lock_exclusive().unwrap();
+
+        let start = Instant::now();
+        let err = lock_exclusive(f.as_file(), Duration::from_millis(50))
+            .err()
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(err.kind(), "lock_timeout");
+    }
+
+    #[test]
+    fn test_lock_released_while_waiting() {
+        let f = NamedTempFile::new().unwrap();
+        let holder = File::open(f.path()).unwrap();
+        holder.lock_exclusive().unwrap();
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(holder);
+        });
+
+        lock_exclusive(f.as_file(), Duration::from_secs(5)).unwrap();
+        t.join().unwrap();
+    }
+
+    #[test_case("10"   => Some(Duration::from_secs(10))    ; "whole seconds")]
+    #[test_case("0.5"  => Some(Duration::from_millis(500)) ; "fraction")]
+    #[test_case("0"    => Some(Duration::ZERO)             ; "zero")]
+    #[test_case("-1"   => None                             ; "negative")]
+    #[test_case("inf"  => None                             ; "infinite")]
+    #[test_case("NaN"  => None                             ; "not a number")]
+    #[test_case("soon" => None                             ; "not a number at all")]
+    fn test_parse_timeout(s: &str) -> Option<Duration> {
+        parse_timeout(s).ok()
+    }
+}
@@ -0,0 +1,193 @@
+use super::{entry::Entry, format::GroupBy};
+use chrono::prelude::*;
+use std::collections::BTreeMap;
+
+/// The metadata field moods are stored in, written by hmm --mood.
+pub const FIELD: &str = "mood";
+
+/// The lowest and highest moods.
+pub const MIN: u8 = 1;
+pub const MAX: u8 = 5;
+
+/// The mood stored with `entry`, if it has one.
+pub fn of(entry: &Entry) -> Option<f64> {
+    entry.metadata().get(FIELD).and_then(|v| v.as_f64())
+}
+
+/// Moods over a week, starting on Monday.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Week {
+    start: NaiveDate,
+    days: [Option<f64>; 7],
+    average: Option<f64>,
+}
+
+impl Week {
+    /// The Monday the week starts on.
+    pub fn start(&self) -> NaiveDate {
+        self.start
+    }
+
+    /// The average mood on each day of the week, Monday first, or None for
+    /// days without any.
+    pub fn days(&self) -> &[Option<f64>; 7] {
+        &self.days
+    }
+
+    /// The average of every mood in the week, or None if there weren't any.
+    pub fn average(&self) -> Option<f64> {
+        self.average
+    }
+}
+
+/// Collects the moods of entries in to weeks, in local time.
+#[derive(Debug, Default)]
+pub struct Chart {
+    days: BTreeMap<NaiveDate, Vec<f64>>,
+}
+
+impl Chart {
+    /// Adds `entry`'s mood to the chart. Entries without one are ignored.
+    pub fn push(&mut self, entry: &Entry) {
+        if let Some(mood) = of(entry) {
+            let day = entry.datetime().with_timezone(&Local).date_naive();
+            self.days.entry(day).or_default().push(mood);
+        }
+    }
+
+    /// Every week from the first mood to the last, including weeks in between
+    /// without any.
+    pub fn finish(self) -> Vec<Week> {
+        let (first, last) = match (self.days.keys().next(), self.days.keys().next_back()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Vec::new(),
+        };
+
+        let mut weeks = Vec::new();
+        let mut start = week_of(first);
+        while start <= last {
+            let mut days = [None; 7];
+            let mut all = Vec::new();
+            for (i, day) in days.iter_mut().enumerate() {
+                let moods = start
+                    .checked_add_days(chrono::Days::new(i as u64))
+                    .and_then(|date| self.days.get(&date));
+                if let Some(moods) = moods {
+                    *day = mean(moods);
+                    all.extend(moods);
+                }
+            }
+            weeks.push(Week {
+                start,
+                days,
+                average: mean(&all),
+            });
+            start = GroupBy::Week.next(start);
+        }
+        weeks
+    }
+}
+
+fn week_of(day: NaiveDate) -> NaiveDate {
+    day - chrono::Duration::days(day.weekday().num_days_from_monday() as i64)
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    match values.len() {
+        0 => None,
+        n => Some(values.iter().sum::<f64>() / n as f64),
+    }
+}
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// A sparkline of `moods`, with a bar for each mood from ▁ for the lowest to
+/// █ for the highest and a space where there isn't one. With `ascii`, moods
+/// are shown as the nearest whole number instead.
+pub fn sparkline(moods: &[Option<f64>], ascii: bool) -> String {
+    let (min, max) = (MIN as f64, MAX as f64);
+    moods
+        .iter()
+        .map(|mood| match mood {
+            None => ' ',
+            Some(mood) => {
+                let mood = mood.clamp(min, max);
+                if ascii {
+                    char::from_digit(mood.round() as u32, 10).unwrap_or('?')
+                } else {
+                    let i = ((mood - min) / (max - min) * (BARS.len() - 1) as f64).round();
+                    BARS[i as usize]
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Metadata;
+    use test_case::test_case;
+
+    fn entry(datetime: &str, mood: Option<u8>) -> Entry {
+        let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+        let mut metadata = Metadata::new();
+        if let Some(mood) = mood {
+            metadata.insert(FIELD.to_owned(), mood.into());
+        }
+        Entry::new(
+            Local.from_local_datetime(&datetime).unwrap().fixed_offset(),
+            "hello".to_owned(),
+        )
+        .with_metadata(metadata)
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_chart() {
+        let mut chart = Chart::default();
+        for entry in [
+            entry("2024-05-07 09:00", Some(2)),
+            entry("2024-05-07 18:00", Some(3)),
+            entry("2024-05-08 09:00", None),
+            entry("2024-05-12 23:59", Some(5)),
+            entry("2024-05-22 09:00", Some(4)),
+        ] {
+            chart.push(&entry);
+        }
+
+        let weeks = chart.finish();
+        assert_eq!(
+            weeks
+                .iter()
+                .map(|week| (week.start(), week.average()))
+                .collect::<Vec<_>>(),
+            vec![
+                (date("2024-05-06"), Some(10.0 / 3.0)),
+                (date("2024-05-13"), None),
+                (date("2024-05-20"), Some(4.0)),
+            ]
+        );
+        assert_eq!(
+            weeks[0].days(),
+            &[None, Some(2.5), None, None, None, None, Some(5.0)]
+        );
+    }
+
+    #[test]
+    fn test_chart_empty() {
+        let mut chart = Chart::default();
+        chart.push(&entry("2024-05-07 09:00", None));
+        assert!(chart.finish().is_empty());
+    }
+
+    #[test_case(&[Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)], false => "▁▃▅▆█" ; "bars")]
+    #[test_case(&[Some(2.5), None, Some(9.0)], false                             => "▄ █"   ; "gaps and out of range")]
+    #[test_case(&[Some(2.5), None, Some(4.4)], true                              => "3 4"   ; "ascii")]
+    fn test_sparkline(moods: &[Option<f64>], ascii: bool) -> String {
+        sparkline(moods, ascii)
+    }
+}
@@ -0,0 +1,498 @@
+//! Reading and writing .hmm files through tokio's async I/O traits, so that
+//! servers and other async callers don't have to move every query on to a
+//! blocking thread.
+//!
+//! `AsyncEntries` mirrors the parts of `Entries` that don't need to read the
+//! whole file, and `AsyncJournal` mirrors `JournalWriter`.
+
+use super::{
+    entries::Probe,
+    entry::{trim_line, Entry, EntryRef},
+    seek,
+    sorted_file::Search,
+    Result,
+};
+use chrono::prelude::*;
+use std::io::SeekFrom;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite,
+    AsyncWriteExt,
+};
+
+// How many bytes to read at a time when looking backwards for the start of a
+// line. Seeking in an async reader throws its buffer away, so reading a byte
+// at a time like the seek module does would mean a read per byte.
+const CHUNK_SIZE: u64 = 4096;
+
+pub struct AsyncEntries<T> {
+    f: T,
+    buf: String,
+}
+
+impl<T: AsyncBufRead + AsyncSeek + Unpin> AsyncEntries<T> {
+    pub fn new(f: T) -> Self {
+        AsyncEntries {
+            f,
+            buf: String::with_capacity(4096),
+        }
+    }
+
+    /// Returns the length of the underlying file in bytes.
+    pub async fn len_bytes(&mut self) -> Result<u64> {
+        let prev = self.f.stream_position().await?;
+        let len = self.f.seek(SeekFrom::End(0)).await?;
+        self.f.seek(SeekFrom::Start(prev)).await?;
+        Ok(len)
+    }
+
+    pub async fn is_empty(&mut self) -> Result<bool> {
+        Ok(self.len_bytes().await? == 0)
+    }
+
+    /// Returns the byte offset of the cursor in the underlying file.
+    pub async fn position(&mut self) -> Result<u64> {
+        Ok(self.f.stream_position().await?)
+    }
+
+    pub async fn next_entry(&mut self) -> Result<Option<Entry>> {
+        self.buf.clear();
+        self.f.read_line(&mut self.buf).await?;
+
+        // As in Entries::next_entry, reading past the end leaves the cursor
+        // past the end of the file so prev_entry knows to read the last line.
+        if self.buf.is_empty() {
+            self.f.seek(SeekFrom::End(1)).await?;
+            return Ok(None);
+        }
+
+        match EntryRef::parse(&self.buf) {
+            Ok(entry) => Ok(Some(entry.to_entry())),
+            Err(e) => {
+                let byte = self.f.stream_position().await? - self.buf.len() as u64;
+                let line = line_number_at(&mut self.f, byte).await?;
+                Err(e.at_line(Some(line), byte, &self.buf))
+            }
+        }
+    }
+
+    /// Returns the entry before the one that was just read, or the last entry
+    /// if the cursor is past the end of the file, like Entries::prev_entry.
+    pub async fn prev_entry(&mut self) -> Result<Option<Entry>> {
+        let pos = self.position().await?;
+        let len = self.len_bytes().await?;
+
+        // The start of the line that was just read, which is where the entry
+        // we want ends. This is the same walk back as Entries::prev_entry.
+        let end = if pos > len {
+            len
+        } else if pos == 0 {
+            return Ok(None);
+        } else {
+            start_of_line_at(&mut self.f, pos - 1).await?
+        };
+
+        if end == 0 {
+            self.f.seek(SeekFrom::Start(0)).await?;
+            return Ok(None);
+        }
+
+        start_of_line_at(&mut self.f, end - 1).await?;
+        self.next_entry().await
+    }
+
+    pub async fn at(&mut self, pos: u64) -> Result<Option<Entry>> {
+        if pos > self.len_bytes().await? {
+            return Ok(None);
+        }
+
+        start_of_line_at(&mut self.f, pos).await?;
+        self.next_entry().await
+    }
+
+    /// Moves the cursor past the end of the file, so that prev_entry returns
+    /// the last entry.
+    pub async fn seek_to_end(&mut self) -> Result<()> {
+        self.f.seek(SeekFrom::End(1)).await?;
+        Ok(())
+    }
+
+    /// Moves the cursor to the first entry that is on or after `date`, or to the
+    /// end of the file if there isn't one.
+    pub async fn seek_to_first(&mut self, date: &DateTime<FixedOffset>) -> Result<()> {
+        let probe = Probe::new(date);
+        let mut search = Search::new(self.f.seek(SeekFrom::End(0)).await?);
+        let mut line = String::new();
+
+        // The same search as sorted_file::find_first_by.
+        while let Some(pos) = search.next() {
+            let start = start_of_line_at(&mut self.f, pos).await?;
+            line.clear();
+            let end = start + self.f.read_line(&mut line).await? as u64;
+
+            match probe.is_on_or_after(trim_line(&line)) {
+                Ok(is_ge) => search.narrow(start, end, is_ge),
+                Err(e) => {
+                    let number = line_number_at(&mut self.f, start).await?;
+                    return Err(e.at_line(Some(number), start, &line));
+                }
+            }
+        }
+
+        self.f.seek(SeekFrom::Start(search.found())).await?;
+        Ok(())
+    }
+
+    /// Returns the entries between the `start` date, inclusive, and the `end`
+    /// date, exclusive, one at a time. If `start` is None reading begins at the
+    /// current cursor position, and if `end` is None it continues to the end
+    /// of the file.
+    pub async fn between(
+        &mut self,
+        start: Option<&DateTime<FixedOffset>>,
+        end: Option<&DateTime<FixedOffset>>,
+    ) -> Result<AsyncBetween<'_, T>> {
+        if let Some(date) = start {
+            self.seek_to_first(date).await?;
+        }
+
+        Ok(AsyncBetween {
+            entries: self,
+            end: end.cloned(),
+            done: false,
+        })
+    }
+}
+
+pub struct AsyncBetween<'a, T> {
+    entries: &'a mut AsyncEntries<T>,
+    end: Option<DateTime<FixedOffset>>,
+    done: bool,
+}
+
+impl<'a, T: AsyncBufRead + AsyncSeek + Unpin> AsyncBetween<'a, T> {
+    /// Returns the next entry in the range, or None once the range has been
+    /// read. A malformed entry returns an error, and the next call carries on
+    /// from the line after it.
+    pub async fn next(&mut self) -> Result<Option<Entry>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        match self.entries.next_entry().await? {
+            Some(entry) if self.end.map_or(true, |end| entry.datetime() < &end) => Ok(Some(entry)),
+            _ => {
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Writes entries to `W` through a buffer, like `JournalWriter`. It doesn't
+/// lock the file or check that entries are in order, so it's up to the caller
+/// to only append entries that are later than the last one in the file.
+pub struct AsyncJournal<W> {
+    w: W,
+    buf: Vec<u8>,
+    capacity: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncJournal<W> {
+    pub fn new(w: W) -> Self {
+        Self::with_capacity(w, 64 * 1024)
+    }
+
+    /// Creates a journal that writes to `w` whenever `capacity` bytes of
+    /// entries have been appended.
+    pub fn with_capacity(w: W, capacity: usize) -> Self {
+        AsyncJournal {
+            w,
+            buf: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub async fn append(&mut self, entry: &Entry) -> Result<()> {
+        entry.write(&mut self.buf)?;
+        if self.buf.len() >= self.capacity {
+            self.write_buf().await?;
+        }
+        Ok(())
+    }
+
+    /// Appends every entry in `entries`, returning how many there were.
+    pub async fn append_many<'a, I>(&mut self, entries: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = &'a Entry>,
+    {
+        let mut count = 0;
+        for entry in entries {
+            self.append(entry).await?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Writes out everything that's been appended and flushes `W`.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.write_buf().await?;
+        self.w.flush().await?;
+        Ok(())
+    }
+
+    /// Flushes the journal and gives back the writer underneath it.
+    pub async fn finish(mut self) -> Result<W> {
+        self.flush().await?;
+        Ok(self.w)
+    }
+
+    async fn write_buf(&mut self) -> Result<()> {
+        self.w.write_all(&self.buf).await?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+// Moves the cursor to the start of the line that the byte at `pos` is on, and
+// returns it. Like seek::start_of_current_line, a newline is part of the line
+// it ends, and a `pos` past the end of the file is on the last line.
+async fn start_of_line_at<T: AsyncRead + AsyncSeek + Unpin>(f: &mut T, pos: u64) -> Result<u64> {
+    let mut end = pos.min(f.seek(SeekFrom::End(0)).await?);
+    let mut chunk = [0; CHUNK_SIZE as usize];
+
+    let start = loop {
+        if end == 0 {
+            break 0;
+        }
+
+        let begin = end.saturating_sub(CHUNK_SIZE);
+        let chunk = &mut chunk[..(end - begin) as usize];
+        f.seek(SeekFrom::Start(begin)).await?;
+        f.read_exact(chunk).await?;
+
+        if let Some(i) = chunk.iter().rposition(|&b| b == 0x0a) {
+            break begin + i as u64 + 1;
+        }
+        end = begin;
+    };
+
+    f.seek(SeekFrom::Start(start)).await?;
+    Ok(start)
+}
+
+// The async version of seek::line_number_at.
+async fn line_number_at<T: AsyncRead + AsyncSeek + Unpin>(f: &mut T, pos: u64) -> Result<u64> {
+    let prev = f.stream_position().await?;
+    f.seek(SeekFrom::Start(0)).await?;
+
+    let mut r = (&mut *f).take(pos);
+    let mut buf = [0; 64 * 1024];
+    let mut count = 0;
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        count += seek::newlines_in(&buf[..n]);
+    }
+
+    f.seek(SeekFrom::Start(prev)).await?;
+    Ok(count + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entries::Entries, error::Error};
+    use std::io::Cursor;
+    use tokio::io::BufReader;
+
+    const TESTDATA: &str = "2020-01-01T00:01:00.899849209+00:00,\"\"\"1\"\"\"
+2020-02-12T23:08:40.987613062+00:00,\"\"\"2\"\"\"
+2020-03-12T00:00:00.000000000+00:00,\"\"\"3\"\"\"
+2020-04-12T23:28:45.726598931+00:00,\"\"\"4\"\"\"
+2020-05-12T23:28:48.495151445+00:00,\"\"\"5\"\"\"
+2020-06-13T10:12:53.353050231+00:00,\"\"\"6\"\"\"
+";
+
+    fn entries(s: &str) -> AsyncEntries<BufReader<Cursor<Vec<u8>>>> {
+        AsyncEntries::new(BufReader::new(Cursor::new(s.as_bytes().to_vec())))
+    }
+
+    fn date(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn message(entry: Result<Option<Entry>>) -> Option<String> {
+        entry.unwrap().map(|e| e.message().to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_next_and_prev_entry() {
+        let mut entries = entries(TESTDATA);
+        assert_eq!(message(entries.next_entry().await).unwrap(), "1");
+        assert_eq!(message(entries.next_entry().await).unwrap(), "2");
+        assert_eq!(message(entries.prev_entry().await).unwrap(), "1");
+        assert_eq!(message(entries.prev_entry().await), None);
+
+        entries.seek_to_end().await.unwrap();
+        assert_eq!(message(entries.prev_entry().await).unwrap(), "6");
+        assert_eq!(message(entries.prev_entry().await).unwrap(), "5");
+    }
+
+    #[tokio::test]
+    async fn test_no_trailing_newline() {
+        let mut entries = entries(TESTDATA.trim_end());
+        entries.seek_to_end().await.unwrap();
+        assert_eq!(message(entries.prev_entry().await).unwrap(), "6");
+    }
+
+    #[tokio::test]
+    async fn test_prev_entry_after_last_line_without_newline() {
+        let mut entries = entries(TESTDATA.trim_end());
+        while message(entries.next_entry().await).is_some_and(|m| m != "6") {}
+        assert_eq!(message(entries.prev_entry().await).unwrap(), "5");
+        assert_eq!(message(entries.next_entry().await).unwrap(), "6");
+        assert_eq!(message(entries.next_entry().await), None);
+        assert_eq!(message(entries.prev_entry().await).unwrap(), "6");
+    }
+
+    #[tokio::test]
+    async fn test_prev_entry_reads_every_entry() {
+        for s in &[
+            TESTDATA,
+            TESTDATA.trim_end(),
+            &TESTDATA.replace('\n', "\r\n"),
+        ] {
+            let mut entries = entries(s);
+            entries.seek_to_end().await.unwrap();
+            let mut messages = Vec::new();
+            while let Some(message) = message(entries.prev_entry().await) {
+                messages.push(message);
+            }
+            assert_eq!(messages, vec!["6", "5", "4", "3", "2", "1"], "{:?}", s);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_file() {
+        let mut entries = entries("");
+        assert!(entries.is_empty().await.unwrap());
+        assert_eq!(message(entries.next_entry().await), None);
+        assert_eq!(message(entries.prev_entry().await), None);
+        entries
+            .seek_to_first(&date("2020-01-01T00:00:00Z"))
+            .await
+            .unwrap();
+        assert_eq!(entries.position().await.unwrap(), 0);
+    }
+
+    // The cursor should end up in the same place as it does with Entries, which
+    // is tested more thoroughly.
+    #[tokio::test]
+    async fn test_seek_to_first_matches_entries() {
+        let mut sync = Entries::new(std::io::Cursor::new(TESTDATA.as_bytes()));
+        let mut entries = entries(TESTDATA);
+
+        for s in &[
+            "2019-01-01T00:00:00Z",
+            "2020-01-01T00:01:00.899849209Z",
+            "2020-03-12T00:00:00Z",
+            "2020-03-12T01:00:00+01:00",
+            "2020-03-12T00:00:00.000000001Z",
+            "2020-06-13T10:12:53.353050231Z",
+            "2021-01-01T00:00:00Z",
+        ] {
+            let date = date(s);
+            sync.seek_to_first(&date).unwrap();
+            entries.seek_to_first(&date).await.unwrap();
+            assert_eq!(
+                entries.position().await.unwrap(),
+                sync.position().unwrap(),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_long_lines() {
+        // Lines longer than a chunk mean looking back more than one chunk for
+        // the start of a line.
+        let mut s = String::new();
+        for (i, month) in ["01", "02", "03"].iter().enumerate() {
+            let message = i.to_string().repeat(CHUNK_SIZE as usize * 2);
+            s.push_str(&format!(
+                "2020-{}-01T00:00:00+00:00,\"\"\"{}\"\"\"\n",
+                month, message
+            ));
+        }
+
+        let mut entries = entries(&s);
+        entries
+            .seek_to_first(&date("2020-02-01T00:00:00Z"))
+            .await
+            .unwrap();
+        assert!(message(entries.next_entry().await)
+            .unwrap()
+            .starts_with('1'));
+        entries.seek_to_end().await.unwrap();
+        assert!(message(entries.prev_entry().await)
+            .unwrap()
+            .starts_with('2'));
+        assert!(message(entries.prev_entry().await)
+            .unwrap()
+            .starts_with('1'));
+    }
+
+    #[tokio::test]
+    async fn test_between() {
+        let mut entries = entries(TESTDATA);
+        let start = date("2020-02-01T00:00:00Z");
+        let end = date("2020-05-01T00:00:00Z");
+        let mut between = entries.between(Some(&start), Some(&end)).await.unwrap();
+
+        let mut messages = Vec::new();
+        while let Some(entry) = between.next().await.unwrap() {
+            messages.push(entry.message().to_owned());
+        }
+        assert_eq!(messages, vec!["2", "3", "4"]);
+        assert!(between.next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_line() {
+        let s = TESTDATA.replace("\"\"\"3\"\"\"", "nope");
+        let mut entries = entries(&s);
+        entries.next_entry().await.unwrap();
+        entries.next_entry().await.unwrap();
+        match entries.next_entry().await {
+            Err(Error::Malformed { line, .. }) => assert_eq!(line, Some(3)),
+            Err(other) => panic!("unexpected error: {:?}", other),
+            Ok(_) => panic!("expected an error"),
+        }
+        assert_eq!(message(entries.next_entry().await).unwrap(), "4");
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let written: Vec<Entry> = (0..100)
+            .map(|i| {
+                Entry::new(
+                    date("2020-01-01T00:00:00Z") + chrono::Duration::minutes(i),
+                    format!("entry {}", i),
+                )
+            })
+            .collect();
+
+        let mut expected = Vec::new();
+        for entry in &written {
+            entry.write(&mut expected).unwrap();
+        }
+
+        // A small buffer means appending writes through to `W` some of the time.
+        let mut journal = AsyncJournal::with_capacity(Vec::new(), 256);
+        assert_eq!(journal.append_many(&written).await.unwrap(), 100);
+        assert_eq!(journal.finish().await.unwrap(), expected);
+    }
+}
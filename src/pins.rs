@@ -0,0 +1,141 @@
+use super::{entry::Entry, error::Error, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A short list of entries kept close to hand, like reference notes, by their
+/// IDs. hmmpin prints them, and hmmf and hmms digest show them too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pins {
+    path: PathBuf,
+    ids: Vec<String>,
+}
+
+/// Where pins are kept, ~/.config/hmm/pins on Linux,
+/// ~/Library/Application Support/hmm/pins on macOS and %APPDATA%\hmm\pins on
+/// Windows.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("hmm").join("pins"))
+}
+
+/// Reads the pins kept at [`default_path`].
+pub fn load_default() -> Result<Pins> {
+    let path = default_path()
+        .ok_or_else(|| Error::InvalidArgument("couldn't find where pins are kept".to_owned()))?;
+    Pins::load(&path)
+}
+
+impl Pins {
+    /// Reads the pins kept at `path`, one ID per line. It's fine for the file
+    /// not to exist, in which case nothing is pinned.
+    pub fn load(path: &Path) -> Result<Pins> {
+        let ids = match fs::read_to_string(path) {
+            Ok(s) => s
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Pins {
+            path: path.to_owned(),
+            ids,
+        })
+    }
+
+    /// The IDs of the pinned entries, in the order they were pinned.
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.iter().any(|pinned| pinned == id)
+    }
+
+    /// The entries out of `entries` that are pinned, in the order they were
+    /// pinned.
+    pub fn select(&self, entries: impl IntoIterator<Item = Entry>) -> Vec<Entry> {
+        let mut pinned: Vec<Entry> = entries
+            .into_iter()
+            .filter(|entry| self.contains(&entry.id()))
+            .collect();
+        pinned.sort_by_cached_key(|entry| self.ids.iter().position(|id| *id == entry.id()));
+        pinned
+    }
+
+    /// Pins `id`, returning false if it was already pinned.
+    pub fn pin(&mut self, id: &str) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        self.ids.push(id.to_owned());
+        true
+    }
+
+    /// Unpins `id`, returning false if it wasn't pinned.
+    pub fn unpin(&mut self, id: &str) -> bool {
+        let len = self.ids.len();
+        self.ids.retain(|pinned| pinned != id);
+        self.ids.len() != len
+    }
+
+    /// Writes the pins back to where they were loaded from, creating the
+    /// directory they go in if needed.
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut s = self.ids.join("\n");
+        if !s.is_empty() {
+            s.push('\n');
+        }
+        Ok(fs::write(&self.path, s)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pins() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("hmm").join("pins");
+        let mut pins = Pins::load(&path).unwrap();
+        assert!(pins.ids().is_empty());
+
+        assert!(pins.pin("cbbf4083"));
+        assert!(pins.pin("682aadf1"));
+        assert!(!pins.pin("cbbf4083"));
+        pins.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "cbbf4083\n682aadf1\n");
+
+        let mut pins = Pins::load(&path).unwrap();
+        assert_eq!(pins.ids(), &["cbbf4083", "682aadf1"]);
+        assert!(pins.unpin("cbbf4083"));
+        assert!(!pins.unpin("cbbf4083"));
+        assert!(!pins.contains("cbbf4083"));
+        pins.save().unwrap();
+        assert_eq!(Pins::load(&path).unwrap().ids(), &["682aadf1"]);
+    }
+
+    #[test]
+    fn test_select() {
+        let tmp = TempDir::new().unwrap();
+        let mut pins = Pins::load(&tmp.path().join("pins")).unwrap();
+        let entries: Vec<Entry> = ["first", "second", "third"]
+            .iter()
+            .map(|message| Entry::new(chrono::Utc::now().fixed_offset(), message.to_string()))
+            .collect();
+        assert!(pins.select(entries.clone()).is_empty());
+
+        pins.pin(&entries[2].id());
+        pins.pin(&entries[0].id());
+        let pinned = pins.select(entries);
+        let messages: Vec<&str> = pinned.iter().map(Entry::message).collect();
+        assert_eq!(messages, ["third", "first"]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Property tests for reading .hmm files. Files made of arbitrary bytes check
+//! that nothing panics or loops forever however broken a file is, and
+//! arbitrary valid journals check that whatever is written reads back the
+//! same, in both directions and when seeking.
+
+use super::{
+    entries::Entries,
+    entry::{Entry, EntryRef, Metadata},
+    input::InputFormat,
+};
+use chrono::prelude::*;
+use proptest::prelude::*;
+use std::io::Cursor;
+
+// Pieces of .hmm files that broken files are made from, so that they get
+// close enough to valid to exercise more than the first check the parser
+// makes.
+const FRAGMENTS: &[&str] = &[
+    "2020-01-01T00:00:00+00:00",
+    "2020-01-01T00:00:00.123456789+05:30",
+    "2020-13-45T99:99:99+00:00",
+    "+10000-01-01T00:00:00+00:00",
+    "2020-01-01T00:00:00",
+    ",",
+    "\"",
+    "\"\"\"",
+    "\"\"\"hello\"\"\"",
+    "\"\"\"\\u00e9\\\"\\n\"\"\"",
+    "\"{\"\"k\"\":1}\"",
+    "\"{\"\"k\"\":",
+    "\\",
+    "\\u",
+    "\\ud800",
+    "\n",
+    "\r\n",
+    "\r",
+    " ",
+    "é",
+    "\u{0}",
+];
+
+fn soup() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(prop::sample::select(FRAGMENTS), 0..40)
+        .prop_map(|parts| parts.concat().into_bytes())
+}
+
+fn broken_file() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..200),
+        soup(),
+        // A valid journal with some bytes flipped.
+        (
+            journal(),
+            prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..5)
+        )
+            .prop_map(|(entries, flips)| {
+                let mut buf = write(&entries);
+                if !buf.is_empty() {
+                    for (i, b) in flips {
+                        let i = i.index(buf.len());
+                        buf[i] = b;
+                    }
+                }
+                buf
+            }),
+    ]
+}
+
+fn datetime() -> impl Strategy<Value = DateTime<FixedOffset>> {
+    // Offsets are whole minutes, as they are in RFC 3339.
+    (
+        -50_000_000_000i64..50_000_000_000,
+        0u32..1_000_000_000,
+        -(14 * 60)..=(14 * 60),
+    )
+        .prop_map(|(secs, nanos, minutes)| {
+            let offset = FixedOffset::east_opt(minutes * 60).unwrap();
+            DateTime::from_timestamp(secs, nanos)
+                .unwrap()
+                .with_timezone(&offset)
+        })
+}
+
+fn metadata() -> impl Strategy<Value = Metadata> {
+    prop::collection::btree_map(any::<String>(), any::<String>(), 0..3).prop_map(|map| {
+        map.into_iter()
+            .map(|(k, v)| (k, serde_json::Value::String(v)))
+            .collect()
+    })
+}
+
+// Entries in the order they'd be in a file, with the odd duplicate time and
+// different offsets from one entry to the next.
+fn journal() -> impl Strategy<Value = Vec<Entry>> {
+    prop::collection::vec((datetime(), any::<String>(), metadata()), 0..20).prop_map(|rows| {
+        let mut rows = rows;
+        rows.sort_by_key(|(datetime, _, _)| *datetime);
+        rows.into_iter()
+            .map(|(datetime, message, metadata)| {
+                Entry::new(datetime, message).with_metadata(metadata)
+            })
+            .collect()
+    })
+}
+
+fn write(entries: &[Entry]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for entry in entries {
+        entry.write(&mut buf).unwrap();
+    }
+    buf
+}
+
+fn entries(buf: &[u8]) -> Entries<Cursor<&[u8]>> {
+    Entries::new(Cursor::new(buf))
+}
+
+// Reading a file can't give back more results than it has lines, so going
+// past that means something is stuck.
+fn max_results(buf: &[u8]) -> usize {
+    buf.iter().filter(|&&b| b == b'\n').count() + 2
+}
+
+fn assert_same(read: &Entry, written: &Entry) {
+    assert_eq!(read.datetime(), written.datetime());
+    assert_eq!(read.datetime().offset(), written.datetime().offset());
+    assert_eq!(read.message(), written.message());
+    assert_eq!(read.metadata(), written.metadata());
+}
+
+proptest! {
+    #[test]
+    fn broken_files_iterate(buf in broken_file()) {
+        let max = max_results(&buf);
+        prop_assert!(entries(&buf).take(max).count() < max);
+        prop_assert!(entries(&buf).rev().take(max).count() < max);
+
+        let mut e = entries(&buf);
+        e.seek_to_end().ok();
+        let mut steps = 0;
+        while let Ok(Some(_)) = e.prev_entry() {
+            steps += 1;
+            prop_assert!(steps < max);
+        }
+    }
+
+    #[test]
+    fn broken_files_seek(
+        buf in broken_file(),
+        date in datetime(),
+        end in datetime(),
+        pos in 0u64..250,
+        n in 0u64..30,
+    ) {
+        let max = max_results(&buf);
+        let len = buf.len() as u64;
+
+        let mut e = entries(&buf);
+        if e.seek_to_first(&date).is_ok() {
+            prop_assert!(e.position().unwrap() <= len);
+        }
+        if let Ok(between) = entries(&buf).between(Some(&date), Some(&end)) {
+            prop_assert!(between.take(max).count() < max);
+        }
+        entries(&buf).count_between(Some(&date), Some(&end)).ok();
+        entries(&buf).count_between(None, None).ok();
+        entries(&buf).len_entries().ok();
+        entries(&buf).at(pos).ok();
+        entries(&buf).nth_entry(n).ok();
+        let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(pos);
+        entries(&buf).rand_entry_with(&mut rng).ok();
+    }
+
+    #[test]
+    fn broken_lines_parse(buf in broken_file()) {
+        let text = String::from_utf8_lossy(&buf);
+        for line in text.split('\n') {
+            EntryRef::parse(line).ok();
+            for format in &[InputFormat::Csv, InputFormat::Json] {
+                format.parser().parse_line(line).ok();
+            }
+        }
+    }
+
+    #[test]
+    fn journals_round_trip(written in journal()) {
+        let buf = write(&written);
+
+        let read: Vec<Entry> = entries(&buf).collect::<Result<_, _>>().unwrap();
+        prop_assert_eq!(read.len(), written.len());
+        for (read, written) in read.iter().zip(&written) {
+            assert_same(read, written);
+        }
+
+        let read: Vec<Entry> = entries(&buf).rev().collect::<Result<_, _>>().unwrap();
+        for (read, written) in read.iter().zip(written.iter().rev()) {
+            assert_same(read, written);
+        }
+
+        let mut e = entries(&buf);
+        while let Some(entry) = e.next_entry_ref().unwrap() {
+            prop_assert!(written.iter().any(|w| w.message() == entry.message()));
+        }
+
+        prop_assert_eq!(entries(&buf).count_between(None, None).unwrap(), written.len() as u64);
+    }
+
+    #[test]
+    fn journals_seek(written in journal(), date in datetime()) {
+        let buf = write(&written);
+
+        // Seeking to each entry's own time, and to a time that's probably
+        // between entries, lands on the first entry at or after it.
+        let dates = written.iter().map(|e| *e.datetime()).chain(std::iter::once(date));
+        for date in dates {
+            let mut e = entries(&buf);
+            e.seek_to_first(&date).unwrap();
+            let expected = written.iter().find(|w| w.datetime() >= &date);
+            match (e.next_entry().unwrap(), expected) {
+                (Some(read), Some(expected)) => assert_same(&read, expected),
+                (None, None) => {}
+                (read, expected) => prop_assert!(
+                    false,
+                    "seeking to {} read {:?} but expected {:?}",
+                    date,
+                    read.map(|e| e.message().to_owned()),
+                    expected.map(|e| e.message().to_owned())
+                ),
+            }
+
+            let count = written.iter().filter(|w| w.datetime() >= &date).count();
+            prop_assert_eq!(entries(&buf).count_between(Some(&date), None).unwrap(), count as u64);
+        }
+    }
+}
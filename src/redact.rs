@@ -0,0 +1,181 @@
+use super::{
+    entry::{fnv1a, Entry},
+    error::Error,
+    Result,
+};
+use regex::{Captures, Regex};
+use std::str::FromStr;
+
+/// Kinds of things in a message that can be redacted without having to write
+/// a regex for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// @mentions, like "@sam".
+    Mentions,
+
+    /// Email addresses.
+    Emails,
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mentions" => Ok(Target::Mentions),
+            "emails" => Ok(Target::Emails),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown thing to redact \"{}\", expected mentions or emails",
+                s
+            ))),
+        }
+    }
+}
+
+/// What redacted text is replaced with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Replace it with "[redacted]".
+    Strip,
+
+    /// Replace it with a short hash of itself, so that you can still tell when
+    /// two entries mention the same thing. Mentions keep their "@".
+    Hash,
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strip" => Ok(Mode::Strip),
+            "hash" => Ok(Mode::Hash),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown redaction mode \"{}\", expected strip or hash",
+                s
+            ))),
+        }
+    }
+}
+
+// Emails are redacted before mentions, so that the domain of an email doesn't
+// look like a mention once the part before it is gone. Only the "redact"
+// group is replaced, which lets mentions check what comes before them without
+// replacing it.
+const EMAILS: &str = r"(?P<redact>[\w.+-]+@[\w-]+(?:\.[\w-]+)+)";
+const MENTIONS: &str = r"(?:^|[^\w.@])(?P<redact>@\w[\w-]*)";
+
+/// Hides parts of messages, for sharing entries without sharing the names or
+/// addresses in them.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    mode: Mode,
+}
+
+impl Redactor {
+    /// Creates a redactor for `targets` and anything matching `regexes`.
+    pub fn new(targets: &[Target], regexes: &[String], mode: Mode) -> Result<Self> {
+        let mut patterns = Vec::new();
+        if targets.contains(&Target::Emails) {
+            patterns.push(Regex::new(EMAILS)?);
+        }
+        if targets.contains(&Target::Mentions) {
+            patterns.push(Regex::new(MENTIONS)?);
+        }
+        for regex in regexes {
+            patterns.push(Regex::new(regex)?);
+        }
+        Ok(Redactor { patterns, mode })
+    }
+
+    pub fn redact(&self, message: &str) -> String {
+        let mut message = message.to_owned();
+        for pattern in &self.patterns {
+            message = pattern
+                .replace_all(&message, |caps: &Captures| {
+                    let whole = caps.get(0).unwrap();
+                    let redact = caps.name("redact").unwrap_or(whole);
+                    format!(
+                        "{}{}{}",
+                        &whole.as_str()[..redact.start() - whole.start()],
+                        self.replacement(redact.as_str()),
+                        &whole.as_str()[redact.end() - whole.start()..]
+                    )
+                })
+                .into_owned();
+        }
+        message
+    }
+
+    /// Returns a copy of `entry` with its message redacted.
+    pub fn entry(&self, entry: &Entry) -> Entry {
+        Entry::new(*entry.datetime(), self.redact(entry.message()))
+            .with_metadata(entry.metadata().clone())
+    }
+
+    fn replacement(&self, s: &str) -> String {
+        match self.mode {
+            Mode::Strip => "[redacted]".to_owned(),
+            Mode::Hash => match s.strip_prefix('@') {
+                Some(name) => format!("@{}", hash(name)),
+                None => hash(s),
+            },
+        }
+    }
+}
+
+// The hash is the same on every platform and Rust version, so redacted
+// excerpts shared at different times can be compared. It's not meant to be
+// hard to reverse.
+fn hash(s: &str) -> String {
+    let h = fnv1a(s.as_bytes());
+    format!("{:08x}", (h ^ (h >> 32)) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn redact(targets: &[Target], regexes: &[&str], mode: Mode, message: &str) -> String {
+        let regexes: Vec<String> = regexes.iter().map(|s| s.to_string()).collect();
+        Redactor::new(targets, &regexes, mode)
+            .unwrap()
+            .redact(message)
+    }
+
+    #[test_case(&[Target::Mentions], &[], "lunch with @sam and @alex-b." => "lunch with [redacted] and [redacted]." ; "mentions")]
+    #[test_case(&[Target::Mentions], &[], "@sam, email sam@example.com" => "[redacted], email sam@example.com" ; "mentions aren't emails")]
+    #[test_case(&[Target::Emails], &[], "email sam.w+hmm@mail.example.com now" => "email [redacted] now" ; "emails")]
+    #[test_case(&[Target::Emails, Target::Mentions], &[], "@sam <sam@example.com>" => "[redacted] <[redacted]>" ; "both")]
+    #[test_case(&[], &["(?i)project \\w+"], "Project Falcon is late" => "[redacted] is late" ; "regex")]
+    #[test_case(&[], &[], "nothing to see" => "nothing to see" ; "nothing")]
+    fn test_strip(targets: &[Target], regexes: &[&str], message: &str) -> String {
+        redact(targets, regexes, Mode::Strip, message)
+    }
+
+    #[test]
+    fn test_hash() {
+        let redacted = redact(
+            &[Target::Emails, Target::Mentions],
+            &[],
+            Mode::Hash,
+            "@sam then @alex then @sam at sam@example.com",
+        );
+        let words: Vec<&str> = redacted.split(' ').collect();
+        assert_eq!(words[0], words[4]);
+        assert_ne!(words[0], words[2]);
+        assert!(words[0].starts_with('@'));
+        assert_eq!(words[0].len(), 9);
+        assert_eq!(words[6].len(), 8);
+        assert!(!redacted.contains("sam"));
+    }
+
+    // Changing the hash would stop redacted excerpts from matching ones that
+    // were shared before, so it's pinned here.
+    #[test]
+    fn test_hash_is_stable() {
+        assert_eq!(hash("sam"), "def869c5");
+    }
+}
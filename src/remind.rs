@@ -0,0 +1,424 @@
+use super::{error::Error, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// The name reminders are installed under, so that installing one again
+// replaces it rather than adding another.
+const NAME: &str = "hmm-remind";
+const LAUNCHD_LABEL: &str = "com.github.samwho.hmm.remind";
+
+/// The services that can run a reminder on a timer, one for each OS that hmm
+/// supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    /// A systemd user timer, on Linux.
+    Systemd,
+
+    /// A launchd user agent, on macOS.
+    Launchd,
+
+    /// A scheduled task, on Windows.
+    TaskScheduler,
+}
+
+impl Scheduler {
+    /// The scheduler for the OS hmm was built for.
+    pub fn current() -> Scheduler {
+        if cfg!(target_os = "macos") {
+            Scheduler::Launchd
+        } else if cfg!(windows) {
+            Scheduler::TaskScheduler
+        } else {
+            Scheduler::Systemd
+        }
+    }
+
+    /// The directory the scheduler looks for user timers in. Task Scheduler
+    /// keeps its own, so it doesn't have one.
+    pub fn dir(&self) -> Option<PathBuf> {
+        match self {
+            Scheduler::Systemd => dirs::config_dir().map(|d| d.join("systemd").join("user")),
+            Scheduler::Launchd => dirs::home_dir().map(|d| d.join("Library").join("LaunchAgents")),
+            Scheduler::TaskScheduler => Some(PathBuf::new()),
+        }
+    }
+}
+
+impl FromStr for Scheduler {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "systemd" => Ok(Scheduler::Systemd),
+            "launchd" => Ok(Scheduler::Launchd),
+            "schtasks" => Ok(Scheduler::TaskScheduler),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown scheduler \"{}\", expected systemd, launchd or schtasks",
+                s
+            ))),
+        }
+    }
+}
+
+/// One step of installing or removing a reminder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Write a file, creating the directory it's in if needed.
+    Write(PathBuf, String),
+
+    /// Remove a file, if it exists.
+    Remove(PathBuf),
+
+    /// Run a command, failing if it exits unsuccessfully.
+    Run(Vec<String>),
+
+    /// Run a command, carrying on if it exits unsuccessfully. Used for things
+    /// like unloading a reminder that might not be loaded.
+    Try(Vec<String>),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Write(path, contents) => {
+                write!(f, "write {}:\n{}", path.display(), contents)
+            }
+            Step::Remove(path) => write!(f, "remove {}", path.display()),
+            Step::Run(args) => write!(f, "run {}", args.join(" ")),
+            Step::Try(args) => write!(f, "run {} (failure is ignored)", args.join(" ")),
+        }
+    }
+}
+
+/// A notification, shown every so often, to remind you to write in your
+/// journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reminder {
+    every: Duration,
+    message: String,
+}
+
+impl Reminder {
+    /// Creates a reminder that shows `message` every `every`, which has to be
+    /// at least a minute.
+    pub fn new(every: Duration, message: &str) -> Result<Self> {
+        if every < Duration::from_secs(60) {
+            return Err(Error::InvalidArgument(
+                "reminders can't be more often than once a minute".to_owned(),
+            ));
+        }
+        Ok(Reminder {
+            every,
+            // Notifications are a single line everywhere.
+            message: message.split_whitespace().collect::<Vec<_>>().join(" "),
+        })
+    }
+
+    /// The steps to install this reminder with `scheduler`, replacing any
+    /// reminder that was already installed. `dir` is where the scheduler's
+    /// files go, usually `Scheduler::dir`.
+    pub fn install(&self, scheduler: Scheduler, dir: &Path) -> Result<Vec<Step>> {
+        let secs = self.every.as_secs();
+        match scheduler {
+            Scheduler::Systemd => {
+                let service = format!(
+                    "[Unit]\nDescription=Remind you to write in your hmm journal\n\n\
+                     [Service]\nType=oneshot\nExecStart=/usr/bin/env notify-send hmm {}\n",
+                    systemd_quote(&self.message)
+                );
+                let timer = format!(
+                    "[Unit]\nDescription=Remind you to write in your hmm journal\n\n\
+                     [Timer]\nOnActiveSec={0}\nOnUnitActiveSec={0}\n\n\
+                     [Install]\nWantedBy=timers.target\n",
+                    secs
+                );
+                Ok(vec![
+                    Step::Write(dir.join(format!("{}.service", NAME)), service),
+                    Step::Write(dir.join(format!("{}.timer", NAME)), timer),
+                    Step::Run(systemctl(&["daemon-reload"])),
+                    Step::Run(systemctl(&["enable", "--now", &format!("{}.timer", NAME)])),
+                    // enable doesn't restart a timer that's already running,
+                    // which it needs to pick up a new interval.
+                    Step::Run(systemctl(&["restart", &format!("{}.timer", NAME)])),
+                ])
+            }
+            Scheduler::Launchd => {
+                let script = format!(
+                    "display notification {} with title \"hmm\"",
+                    applescript_quote(&self.message)
+                );
+                let plist = format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+                     <plist version=\"1.0\">\n\
+                     <dict>\n\
+                     \t<key>Label</key>\n\
+                     \t<string>{}</string>\n\
+                     \t<key>ProgramArguments</key>\n\
+                     \t<array>\n\
+                     \t\t<string>/usr/bin/osascript</string>\n\
+                     \t\t<string>-e</string>\n\
+                     \t\t<string>{}</string>\n\
+                     \t</array>\n\
+                     \t<key>StartInterval</key>\n\
+                     \t<integer>{}</integer>\n\
+                     </dict>\n\
+                     </plist>\n",
+                    LAUNCHD_LABEL,
+                    xml_escape(&script),
+                    secs
+                );
+                let path = launchd_plist(dir);
+                Ok(vec![
+                    Step::Try(launchctl("unload", &path)),
+                    Step::Write(path.clone(), plist),
+                    Step::Run(launchctl("load", &path)),
+                ])
+            }
+            Scheduler::TaskScheduler => {
+                let (schedule, modifier) = schtasks_schedule(secs)?;
+                let command = format!("msg * \"{}\"", self.message.replace('"', "'"));
+                Ok(vec![Step::Run(
+                    [
+                        "schtasks", "/Create", "/F", "/TN", NAME, "/SC", schedule, "/MO",
+                        &modifier, "/TR", &command,
+                    ]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+                )])
+            }
+        }
+    }
+}
+
+/// The steps to remove a reminder installed with `scheduler`. It's not an
+/// error if there isn't one.
+pub fn uninstall(scheduler: Scheduler, dir: &Path) -> Vec<Step> {
+    match scheduler {
+        Scheduler::Systemd => vec![
+            Step::Try(systemctl(&["disable", "--now", &format!("{}.timer", NAME)])),
+            Step::Remove(dir.join(format!("{}.service", NAME))),
+            Step::Remove(dir.join(format!("{}.timer", NAME))),
+            Step::Run(systemctl(&["daemon-reload"])),
+        ],
+        Scheduler::Launchd => {
+            let path = launchd_plist(dir);
+            vec![Step::Try(launchctl("unload", &path)), Step::Remove(path)]
+        }
+        Scheduler::TaskScheduler => vec![Step::Try(
+            ["schtasks", "/Delete", "/F", "/TN", NAME]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        )],
+    }
+}
+
+/// Parses an interval like "2h", "30m" or "1h30m". Units are s, m, h and d.
+pub fn parse_interval(s: &str) -> Result<Duration> {
+    let invalid = || {
+        Error::InvalidArgument(format!(
+            "invalid interval \"{}\", expected something like 30m, 2h or 1d",
+            s
+        ))
+    };
+
+    let mut secs: u64 = 0;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let n: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = match rest[digits..].chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        secs = n
+            .checked_mul(unit)
+            .and_then(|n| secs.checked_add(n))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn systemctl(args: &[&str]) -> Vec<String> {
+    ["systemctl", "--user"]
+        .iter()
+        .chain(args)
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn launchctl(command: &str, plist: &Path) -> Vec<String> {
+    vec![
+        "launchctl".to_owned(),
+        command.to_owned(),
+        plist.to_string_lossy().into_owned(),
+    ]
+}
+
+fn launchd_plist(dir: &Path) -> PathBuf {
+    dir.join(format!("{}.plist", LAUNCHD_LABEL))
+}
+
+// Task Scheduler counts in minutes, hours or days, and only up to a point in
+// each of them.
+fn schtasks_schedule(secs: u64) -> Result<(&'static str, String)> {
+    let minutes = secs / 60;
+    let schedule = match minutes {
+        _ if secs % 60 != 0 => None,
+        m if m % (24 * 60) == 0 && m / (24 * 60) <= 365 => Some(("DAILY", m / (24 * 60))),
+        m if m % 60 == 0 && m / 60 <= 23 => Some(("HOURLY", m / 60)),
+        m if m <= 1439 => Some(("MINUTE", m)),
+        _ => None,
+    };
+    schedule
+        .map(|(schedule, n)| (schedule, n.to_string()))
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "Task Scheduler can't remind you every {} seconds, try a whole number of minutes, hours up to 23 or days",
+                secs
+            ))
+        })
+}
+
+// systemd splits ExecStart on spaces unless they're quoted, and expands % and
+// $ itself.
+fn systemd_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+fn applescript_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("90s"   => Some(90)    ; "seconds")]
+    #[test_case("30m"   => Some(1800)  ; "minutes")]
+    #[test_case("2h"    => Some(7200)  ; "hours")]
+    #[test_case("1d"    => Some(86400) ; "days")]
+    #[test_case("1h30m" => Some(5400)  ; "combined")]
+    #[test_case("2"     => None        ; "no unit")]
+    #[test_case("h"     => None        ; "no number")]
+    #[test_case("2w"    => None        ; "unknown unit")]
+    #[test_case(""      => None        ; "empty")]
+    #[test_case("-2h"   => None        ; "negative")]
+    fn test_parse_interval(s: &str) -> Option<u64> {
+        parse_interval(s).ok().map(|d| d.as_secs())
+    }
+
+    #[test]
+    fn test_too_often() {
+        let err = Reminder::new(Duration::from_secs(30), "hi").unwrap_err();
+        assert_eq!(err.exit_code(), 64);
+    }
+
+    #[test]
+    fn test_systemd() {
+        let reminder = Reminder::new(Duration::from_secs(7200), "50% \"done\"?\n$HOME").unwrap();
+        let steps = reminder
+            .install(Scheduler::Systemd, Path::new("/units"))
+            .unwrap();
+
+        match &steps[0] {
+            Step::Write(path, service) => {
+                assert_eq!(path, Path::new("/units/hmm-remind.service"));
+                assert!(service.contains(
+                    "ExecStart=/usr/bin/env notify-send hmm \"50%% \\\"done\\\"? $$HOME\"\n"
+                ));
+            }
+            step => panic!("unexpected step {:?}", step),
+        }
+        match &steps[1] {
+            Step::Write(path, timer) => {
+                assert_eq!(path, Path::new("/units/hmm-remind.timer"));
+                assert!(timer.contains("OnUnitActiveSec=7200\n"), "{}", timer);
+            }
+            step => panic!("unexpected step {:?}", step),
+        }
+        assert_eq!(
+            steps[3].to_string(),
+            "run systemctl --user enable --now hmm-remind.timer"
+        );
+    }
+
+    #[test]
+    fn test_launchd() {
+        let reminder = Reminder::new(Duration::from_secs(1800), "<journal> \"now\"").unwrap();
+        let steps = reminder
+            .install(Scheduler::Launchd, Path::new("/agents"))
+            .unwrap();
+
+        assert_eq!(
+            steps[0],
+            Step::Try(vec![
+                "launchctl".to_owned(),
+                "unload".to_owned(),
+                "/agents/com.github.samwho.hmm.remind.plist".to_owned(),
+            ])
+        );
+        match &steps[1] {
+            Step::Write(_, plist) => {
+                assert!(plist.contains("<string>display notification &quot;&lt;journal&gt; \\&quot;now\\&quot;&quot; with title &quot;hmm&quot;</string>"), "{}", plist);
+                assert!(plist.contains("<integer>1800</integer>"), "{}", plist);
+            }
+            step => panic!("unexpected step {:?}", step),
+        }
+    }
+
+    #[test_case(60         => Some("MINUTE 1".to_owned()) ; "a minute")]
+    #[test_case(90 * 60    => Some("MINUTE 90".to_owned()) ; "ninety minutes")]
+    #[test_case(2 * 3600   => Some("HOURLY 2".to_owned()) ; "hours")]
+    #[test_case(2 * 86400  => Some("DAILY 2".to_owned())  ; "days")]
+    #[test_case(90         => None                         ; "part of a minute")]
+    #[test_case(25 * 3600  => None                         ; "more than a day")]
+    fn test_schtasks(secs: u64) -> Option<String> {
+        let steps = Reminder::new(Duration::from_secs(secs), "journal?")
+            .unwrap()
+            .install(Scheduler::TaskScheduler, Path::new(""))
+            .ok()?;
+        match &steps[..] {
+            [Step::Run(args)] => {
+                assert_eq!(args[args.len() - 1], "msg * \"journal?\"");
+                Some(format!("{} {}", args[6], args[8]))
+            }
+            steps => panic!("unexpected steps {:?}", steps),
+        }
+    }
+
+    #[test]
+    fn test_uninstall() {
+        let steps = uninstall(Scheduler::Systemd, Path::new("/units"));
+        assert_eq!(
+            steps[1],
+            Step::Remove(PathBuf::from("/units/hmm-remind.service"))
+        );
+    }
+}
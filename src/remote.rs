@@ -0,0 +1,174 @@
+use super::{error::Error, Result};
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+const SCHEME: &str = "ssh://";
+
+// Where the journal is on the remote machine when the URL doesn't say.
+const DEFAULT_PATH: &str = "~/.hmm";
+
+/// A journal on another machine, given as a path like "ssh://host/~/.hmm".
+/// Rather than reading and writing the file over the network, hmm runs itself
+/// on the other machine over SSH, so that a remote journal is locked and gets
+/// its hooks run the same way a local one does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    host: String,
+    port: Option<u16>,
+    path: String,
+}
+
+impl Remote {
+    /// Parses `path` if it's an ssh:// URL, returning None if it's a local
+    /// path. Paths on the remote machine start at / unless they start with ~,
+    /// so "ssh://host/var/hmm" is /var/hmm and "ssh://host/~/.hmm" is .hmm in
+    /// your home directory.
+    pub fn parse(path: &Path) -> Result<Option<Remote>> {
+        let rest = match path.to_str().and_then(|p| p.strip_prefix(SCHEME)) {
+            Some(rest) => rest,
+            None => return Ok(None),
+        };
+
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, "")) => (authority, DEFAULT_PATH.to_owned()),
+            Some((authority, path)) if path.starts_with('~') => (authority, path.to_owned()),
+            Some((authority, path)) => (authority, format!("/{}", path)),
+            None => (rest, DEFAULT_PATH.to_owned()),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port.parse().map_err(|_| {
+                    Error::InvalidArgument(format!("invalid port \"{}\" in {}", port, SCHEME))
+                })?;
+                (host, Some(port))
+            }
+            None => (authority, None),
+        };
+        if host.is_empty() || host.starts_with('-') {
+            return Err(Error::InvalidArgument(format!(
+                "invalid host \"{}\" in {}{}",
+                host, SCHEME, rest
+            )));
+        }
+
+        Ok(Some(Remote {
+            host: host.to_owned(),
+            port,
+            path,
+        }))
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Runs `program`, which should be hmm or one of its other binaries, on
+    /// the remote machine with `--path` and then `args`, connected to this
+    /// process's stdin, stdout and stderr. Returns the status it exited with,
+    /// so that it can be passed on.
+    ///
+    /// SSH is run as `ssh`, or as whatever is in the HMM_SSH environment
+    /// variable, which can include flags like `ssh -i ~/.ssh/hmm`.
+    pub fn run(&self, program: &str, args: &[String]) -> Result<i32> {
+        let ssh = env::var("HMM_SSH").unwrap_or_else(|_| "ssh".to_owned());
+        let mut ssh_args = ssh.split_whitespace();
+        let ssh_program = ssh_args
+            .next()
+            .ok_or_else(|| Error::InvalidArgument("HMM_SSH is empty".to_owned()))?;
+
+        let mut cmd = Command::new(ssh_program);
+        cmd.args(ssh_args);
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        cmd.arg("--")
+            .arg(&self.host)
+            .arg(self.command(program, args));
+
+        let status = cmd
+            .status()
+            .map_err(|e| Error::Remote(format!("couldn't run `{}`: {}", ssh, e)))?;
+
+        // ssh exits with 255 when it can't connect, which hmm never does, and
+        // the remote shell with 127 when it can't find the program.
+        match status.code() {
+            Some(255) => Err(Error::Remote(format!(
+                "couldn't connect to {} with `{}`",
+                self.host, ssh
+            ))),
+            Some(127) => Err(Error::Remote(format!(
+                "couldn't run {} on {}, is it installed and in your PATH there?",
+                program, self.host
+            ))),
+            Some(code) => Ok(code),
+            None => Err(Error::Remote(format!("`{}` was killed by a signal", ssh))),
+        }
+    }
+
+    // SSH runs commands through the remote user's shell, so everything is
+    // quoted. A leading ~/ is left outside of the quotes so that it's still
+    // expanded to the remote home directory.
+    fn command(&self, program: &str, args: &[String]) -> String {
+        let path = match self.path.strip_prefix("~/") {
+            Some(rest) => format!("~/{}", quote(rest)),
+            None => quote(&self.path),
+        };
+
+        let mut command = format!("{} --path {}", program, path);
+        for arg in args {
+            command.push(' ');
+            command.push_str(&quote(arg));
+        }
+        command
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn remote(host: &str, port: Option<u16>, path: &str) -> Option<Remote> {
+        Some(Remote {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+
+    #[test_case("/home/sam/.hmm"                => None                                              ; "local")]
+    #[test_case("ssh://box/~/.hmm"              => remote("box", None, "~/.hmm")                     ; "home")]
+    #[test_case("ssh://sam@box/var/journal.hmm" => remote("sam@box", None, "/var/journal.hmm")       ; "absolute")]
+    #[test_case("ssh://box:2222/~/j.hmm"        => remote("box", Some(2222), "~/j.hmm")              ; "port")]
+    #[test_case("ssh://box"                     => remote("box", None, "~/.hmm")                     ; "no path")]
+    #[test_case("ssh://box/"                    => remote("box", None, "~/.hmm")                     ; "empty path")]
+    fn test_parse(path: &str) -> Option<Remote> {
+        Remote::parse(Path::new(path)).unwrap()
+    }
+
+    #[test_case("ssh:///.hmm"        ; "no host")]
+    #[test_case("ssh://-oProxy/.hmm" ; "option as host")]
+    #[test_case("ssh://box:ssh/.hmm" ; "bad port")]
+    fn test_parse_invalid(path: &str) {
+        let err = Remote::parse(Path::new(path)).unwrap_err();
+        assert_eq!(err.exit_code(), 64);
+    }
+
+    #[test_case("~/.hmm",          &[]                      => "hmm --path ~/'.hmm'"                        ; "home")]
+    #[test_case("/var/j.hmm",      &["--last", "1"]         => "hmm --path '/var/j.hmm' '--last' '1'"       ; "args")]
+    #[test_case("~/my journal",    &["--", "it's $HOME"]    => r"hmm --path ~/'my journal' '--' 'it'\''s $HOME'" ; "quoting")]
+    fn test_command(path: &str, args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        remote("box", None, path).unwrap().command("hmm", &args)
+    }
+}
@@ -0,0 +1,281 @@
+use super::{date, entry::Entry};
+use chrono::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// The template hmms review renders with unless it's given another one.
+pub const DEFAULT_TEMPLATE: &str = "\
+# {{ title }}
+
+{{ count }} entries over {{ day_count }} days.{{#if tags}} Most written about: {{#each tags}}#{{ tag }} ({{ count }}){{#unless @last}}, {{/unless}}{{/each}}.{{/if}}
+
+{{#each days}}
+## {{ strftime \"%A %-d %B\" datetime }}
+
+{{#each entries}}
+- {{ strftime \"%H:%M\" datetime }} {{ title }}
+{{/each}}
+
+{{/each}}
+{{#if todos}}
+## Open TODOs
+
+{{#each todos}}
+- [ ] {{ text }} ({{ strftime \"%a %H:%M\" datetime }})
+{{/each}}
+{{/if}}
+";
+
+/// A digest of the entries written over a period, to be rendered with a
+/// template. Every datetime is RFC3339, for the strftime helper.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Review {
+    /// A title for the period, like "Week of 2024-05-13".
+    title: String,
+
+    /// The start of the period, inclusive.
+    start: String,
+
+    /// The end of the period, exclusive.
+    end: String,
+
+    /// How many entries were written.
+    count: usize,
+
+    /// The days that have entries, in order.
+    days: Vec<Day>,
+
+    /// How many days have entries.
+    day_count: usize,
+
+    /// The most used tags, most used first.
+    tags: Vec<TagCount>,
+
+    /// TODOs written in entries that haven't been ticked off.
+    todos: Vec<Todo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Day {
+    datetime: String,
+    count: usize,
+    entries: Vec<Item>,
+}
+
+/// An entry as it's passed to review and digest templates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Item {
+    datetime: String,
+    id: String,
+    message: String,
+
+    /// The first line of the message.
+    title: String,
+}
+
+impl Item {
+    pub(crate) fn new(entry: &Entry) -> Item {
+        Item {
+            datetime: entry.datetime().to_rfc3339(),
+            id: entry.id(),
+            message: entry.message().to_owned(),
+            title: entry.title().to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Todo {
+    datetime: String,
+    id: String,
+    text: String,
+}
+
+impl Review {
+    /// Reviews `entries`, which should all be from `start` up to `end` and in
+    /// order. Only the `top` most used tags are kept.
+    pub fn new(
+        title: &str,
+        start: NaiveDate,
+        end: NaiveDate,
+        entries: &[Entry],
+        top: usize,
+    ) -> Review {
+        let mut days: Vec<Day> = Vec::new();
+        let mut tags: HashMap<&str, usize> = HashMap::new();
+        let mut todos = Vec::new();
+
+        for entry in entries {
+            let datetime = entry.datetime().to_rfc3339();
+            let day = entry.datetime().with_timezone(&Local).date_naive();
+            let day_start = date::start_of_day(day).to_rfc3339();
+            if days.last().map_or(true, |last| last.datetime != day_start) {
+                days.push(Day {
+                    datetime: day_start,
+                    count: 0,
+                    entries: Vec::new(),
+                });
+            }
+            let current = days.last_mut().unwrap();
+            current.count += 1;
+            current.entries.push(Item::new(entry));
+
+            let mut entry_tags = entry.tags();
+            entry_tags.sort_unstable();
+            entry_tags.dedup();
+            for tag in entry_tags {
+                *tags.entry(tag).or_default() += 1;
+            }
+
+            todos.extend(open_todos(entry.message()).into_iter().map(|text| Todo {
+                datetime: datetime.clone(),
+                id: entry.id(),
+                text: text.to_owned(),
+            }));
+        }
+
+        let mut tags: Vec<TagCount> = tags
+            .into_iter()
+            .map(|(tag, count)| TagCount {
+                tag: tag.to_owned(),
+                count,
+            })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        tags.truncate(top);
+
+        Review {
+            title: title.to_owned(),
+            start: date::start_of_day(start).to_rfc3339(),
+            end: date::start_of_day(end).to_rfc3339(),
+            count: entries.len(),
+            day_count: days.len(),
+            days,
+            tags,
+            todos,
+        }
+    }
+}
+
+/// The TODOs in `message` that haven't been done: lines that are unticked
+/// Markdown tasks, like "- [ ] write tests", or that start with "TODO".
+pub fn open_todos(message: &str) -> Vec<&str> {
+    message
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            ["- [ ]", "* [ ]"]
+                .iter()
+                .find_map(|task| line.strip_prefix(task))
+                .or_else(|| {
+                    line.strip_prefix("TODO")
+                        .filter(|rest| rest.is_empty() || rest.starts_with([':', ' ']))
+                        .map(|rest| rest.trim_start_matches(':'))
+                })
+        })
+        .map(str::trim)
+        .filter(|todo| !todo.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format;
+    use test_case::test_case;
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+        Entry::new(
+            Local.from_local_datetime(&datetime).unwrap().fixed_offset(),
+            message.to_owned(),
+        )
+    }
+
+    fn review() -> Review {
+        let entries = vec![
+            entry("2024-05-13 09:00", "standup #work\nTODO: book room"),
+            entry("2024-05-13 12:30", "lunch with #friends #work #work"),
+            entry("2024-05-15 18:00", "- [x] mow lawn\n- [ ] water #garden"),
+        ];
+        Review::new(
+            "Week of 2024-05-13",
+            NaiveDate::from_ymd_opt(2024, 5, 13).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 5, 20).unwrap(),
+            &entries,
+            2,
+        )
+    }
+
+    #[test]
+    fn test_review() {
+        let review = review();
+        assert_eq!(review.count, 3);
+        assert_eq!(
+            review
+                .days
+                .iter()
+                .map(|day| (&day.datetime[..10], day.count))
+                .collect::<Vec<_>>(),
+            vec![("2024-05-13", 2), ("2024-05-15", 1)]
+        );
+        assert_eq!(
+            review
+                .tags
+                .iter()
+                .map(|t| (t.tag.as_str(), t.count))
+                .collect::<Vec<_>>(),
+            vec![("work", 2), ("friends", 1)]
+        );
+        assert_eq!(
+            review
+                .todos
+                .iter()
+                .map(|t| t.text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["book room", "water #garden"]
+        );
+    }
+
+    #[test]
+    fn test_default_template() {
+        assert_eq!(
+            format::render(DEFAULT_TEMPLATE, &review(), false).unwrap(),
+            "\
+# Week of 2024-05-13
+
+3 entries over 2 days. Most written about: #work (2), #friends (1).
+
+## Monday 13 May
+
+- 09:00 standup #work
+- 12:30 lunch with #friends #work #work
+
+## Wednesday 15 May
+
+- 18:00 - [x] mow lawn
+
+## Open TODOs
+
+- [ ] book room (Mon 09:00)
+- [ ] water #garden (Wed 18:00)
+"
+        );
+    }
+
+    #[test_case("TODO: book a room"  => vec!["book a room"]  ; "todo")]
+    #[test_case("TODO call mum"      => vec!["call mum"]     ; "no colon")]
+    #[test_case("TODOS are fun"      => Vec::<&str>::new()   ; "not a todo")]
+    #[test_case("  - [ ] indented"   => vec!["indented"]     ; "task")]
+    #[test_case("* [ ] star\n- [x] done\n- [ ]" => vec!["star"] ; "done and empty tasks")]
+    #[test_case("nothing to do"      => Vec::<&str>::new()   ; "none")]
+    fn test_open_todos(message: &str) -> Vec<&str> {
+        open_todos(message)
+    }
+}
@@ -0,0 +1,575 @@
+use super::{
+    entries::Entries,
+    entry::{Entry, EntryRef},
+    seek, Result,
+};
+use chrono::prelude::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Splits the byte range `start..end` of a .hmm file in to at most `n` chunks,
+/// making sure that every chunk begins at the start of a line. Chunks are
+/// returned in file order as `(start, end)` pairs, with `end` being exclusive.
+pub fn chunk_boundaries<T: Seek + Read>(
+    f: &mut T,
+    start: u64,
+    end: u64,
+    n: u64,
+) -> Result<Vec<(u64, u64)>> {
+    if start >= end {
+        return Ok(vec![]);
+    }
+
+    let mut boundaries = vec![start];
+    for i in 1..n.max(1) {
+        let pos = start + (end - start) * i / n;
+        f.seek(SeekFrom::Start(pos))?;
+        let line_start = seek::start_of_current_line(f)?;
+
+        // Small ranges with lots of chunks, or ranges with very long lines, can
+        // end up with multiple chunks starting on the same line. We only keep
+        // the first of them.
+        if line_start > *boundaries.last().unwrap() && line_start < end {
+            boundaries.push(line_start);
+        }
+    }
+    boundaries.push(end);
+
+    Ok(boundaries.windows(2).map(|w| (w[0], w[1])).collect())
+}
+
+// How many bytes a chunk scans between progress updates. Updating after every
+// line would have every thread contending on the progress bar.
+const PROGRESS_INTERVAL: u64 = 64 * 1024;
+
+/// Receives updates on how far through a scan has got, for example to draw a
+/// progress bar. Updates come from many threads at once.
+pub trait Progress: Sync {
+    /// Called once before the scan starts, with the number of bytes that will
+    /// be scanned.
+    fn start(&self, total: u64);
+
+    /// Called as the scan goes with the number of bytes scanned since the last
+    /// update.
+    fn advance(&self, bytes: u64);
+}
+
+impl Progress for () {
+    fn start(&self, _: u64) {}
+    fn advance(&self, _: u64) {}
+}
+
+#[cfg(feature = "cli")]
+impl Progress for indicatif::ProgressBar {
+    fn start(&self, total: u64) {
+        self.set_length(total);
+    }
+
+    fn advance(&self, bytes: u64) {
+        self.inc(bytes);
+    }
+}
+
+/// Scans all entries in the .hmm file at `path` between the `start` date,
+/// inclusive, and the `end` date, exclusive, returning the ones that match the
+/// given predicate.
+///
+/// The range is split in to chunks on line boundaries and each chunk is read
+/// and parsed on its own thread. The returned entries are in the same order
+/// they appear in the file.
+pub fn par_filter<F>(
+    path: &Path,
+    start: Option<&DateTime<FixedOffset>>,
+    end: Option<&DateTime<FixedOffset>>,
+    predicate: F,
+) -> Result<Vec<Entry>>
+where
+    F: Fn(&Entry) -> bool + Sync,
+{
+    par_filter_with_progress(path, start, end, predicate, &())
+}
+
+/// The same as par_filter, but reporting how far through the scan it is to
+/// `progress`.
+pub fn par_filter_with_progress<F, P>(
+    path: &Path,
+    start: Option<&DateTime<FixedOffset>>,
+    end: Option<&DateTime<FixedOffset>>,
+    predicate: F,
+    progress: &P,
+) -> Result<Vec<Entry>>
+where
+    F: Fn(&Entry) -> bool + Sync,
+    P: Progress + ?Sized,
+{
+    let mut matches = Vec::new();
+    par_scan(
+        path,
+        start,
+        end,
+        |_| true,
+        |entry| {
+            let entry = entry.to_entry();
+            predicate(&entry).then_some(entry)
+        },
+        progress,
+        |entry| {
+            matches.push(entry);
+            Ok(())
+        },
+    )?;
+    Ok(matches)
+}
+
+/// The same as par_filter_with_progress, but the predicate is given entries
+/// that borrow from the line they were read from. Only the entries that match
+/// are copied, so this is much faster when few of them do.
+///
+/// Before a line is parsed at all its raw text is given to `might_match`, and
+/// lines it returns false for are skipped. It must never return false for a
+/// line the predicate would match, but it's fine for it to return true for
+/// lines the predicate won't. Lines that are skipped aren't checked for being
+/// malformed.
+pub fn par_filter_refs_with_progress<L, F, P>(
+    path: &Path,
+    start: Option<&DateTime<FixedOffset>>,
+    end: Option<&DateTime<FixedOffset>>,
+    might_match: L,
+    predicate: F,
+    progress: &P,
+) -> Result<Vec<Entry>>
+where
+    L: Fn(&str) -> bool + Sync,
+    F: Fn(&EntryRef) -> bool + Sync,
+    P: Progress + ?Sized,
+{
+    let mut matches = Vec::new();
+    par_filter_refs_for_each(
+        path,
+        start,
+        end,
+        might_match,
+        predicate,
+        progress,
+        |entry| {
+            matches.push(entry);
+            Ok(())
+        },
+    )?;
+    Ok(matches)
+}
+
+/// The same as par_filter_refs_with_progress, but rather than collecting the
+/// entries that match, each of them is given to `each` in file order while the
+/// scan carries on, so they can be written out as they're found without
+/// keeping them all in memory. An error from `each` stops the scan.
+pub fn par_filter_refs_for_each<L, F, P, E>(
+    path: &Path,
+    start: Option<&DateTime<FixedOffset>>,
+    end: Option<&DateTime<FixedOffset>>,
+    might_match: L,
+    predicate: F,
+    progress: &P,
+    each: E,
+) -> Result<()>
+where
+    L: Fn(&str) -> bool + Sync,
+    F: Fn(&EntryRef) -> bool + Sync,
+    P: Progress + ?Sized,
+    E: FnMut(Entry) -> Result<()>,
+{
+    par_scan(
+        path,
+        start,
+        end,
+        might_match,
+        |entry| predicate(entry).then(|| entry.to_entry()),
+        progress,
+        each,
+    )
+}
+
+/// Counts the entries that match the predicate in the same way that
+/// par_filter_refs_with_progress finds them, without copying any of them.
+pub fn par_count_with_progress<L, F, P>(
+    path: &Path,
+    start: Option<&DateTime<FixedOffset>>,
+    end: Option<&DateTime<FixedOffset>>,
+    might_match: L,
+    predicate: F,
+    progress: &P,
+) -> Result<u64>
+where
+    L: Fn(&str) -> bool + Sync,
+    F: Fn(&EntryRef) -> bool + Sync,
+    P: Progress + ?Sized,
+{
+    let mut count = 0;
+    par_scan(
+        path,
+        start,
+        end,
+        might_match,
+        |entry| predicate(entry).then_some(()),
+        progress,
+        |()| {
+            count += 1;
+            Ok(())
+        },
+    )?;
+    Ok(count)
+}
+
+// How many results a chunk can have waiting to be handed out before the thread
+// scanning it waits too, so that a chunk that gets ahead of the one being
+// handed out doesn't pile up every match in it.
+const CHUNK_BUFFER: usize = 1024;
+
+// Splits the range in to chunks, scans them in parallel and hands the results
+// of `f` to `each` in file order as they come in.
+//
+// There's a thread for each of rayon's threads, and they take chunks in file
+// order, each sending its results down a bounded channel of its own. Results
+// are handed out from one chunk's channel at a time, and that chunk has
+// always been taken by a thread before any later one, so the scan can always
+// make progress.
+fn par_scan<L, F, R, P, E>(
+    path: &Path,
+    start: Option<&DateTime<FixedOffset>>,
+    end: Option<&DateTime<FixedOffset>>,
+    might_match: L,
+    f: F,
+    progress: &P,
+    mut each: E,
+) -> Result<()>
+where
+    L: Fn(&str) -> bool + Sync,
+    F: Fn(&EntryRef) -> Option<R> + Sync,
+    R: Send,
+    P: Progress + ?Sized,
+    E: FnMut(R) -> Result<()>,
+{
+    let mut entries = Entries::new(BufReader::new(File::open(path)?));
+    let start_offset = match start {
+        Some(date) => entries.offset_of_first(date)?,
+        None => 0,
+    };
+    let end_offset = match end {
+        Some(date) => entries.offset_of_first(date)?,
+        None => entries.len_bytes()?,
+    };
+
+    let mut file = File::open(path)?;
+    let num_threads = rayon::current_num_threads();
+    let chunks = chunk_boundaries(&mut file, start_offset, end_offset, num_threads as u64 * 4)?;
+    progress.start(end_offset.saturating_sub(start_offset));
+
+    let (senders, receivers): (Vec<_>, Vec<_>) = chunks
+        .iter()
+        .map(|_| mpsc::sync_channel(CHUNK_BUFFER))
+        .unzip();
+    let work = Mutex::new(chunks.into_iter().zip(senders));
+    let stopped = AtomicBool::new(false);
+
+    let next_chunk = || work.lock().unwrap().next();
+
+    thread::scope(|s| {
+        for _ in 0..num_threads {
+            s.spawn(|| {
+                while let Some(((start, end), tx)) = next_chunk() {
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let send = |r| tx.send(Ok(r)).is_ok();
+                    if let Err(e) = scan_chunk(path, start, end, &might_match, &f, progress, send) {
+                        let _ = tx.send(Err(e));
+                    }
+                }
+            });
+        }
+
+        // Returning early drops the channels still to be read from, which
+        // stops the threads sending to them, and the rest don't start.
+        let res = receivers
+            .into_iter()
+            .flatten()
+            .try_for_each(|r| r.and_then(&mut each));
+        stopped.store(true, Ordering::Relaxed);
+        res
+    })
+}
+
+// Scans the lines from `start` to `end`, giving `send` what `f` returns for
+// each of them until it returns false.
+fn scan_chunk<L, F, R, P, S>(
+    path: &Path,
+    start: u64,
+    end: u64,
+    might_match: &L,
+    f: &F,
+    progress: &P,
+    send: S,
+) -> Result<()>
+where
+    L: Fn(&str) -> bool,
+    F: Fn(&EntryRef) -> Option<R>,
+    P: Progress + ?Sized,
+    S: Fn(R) -> bool,
+{
+    let mut r = BufReader::new(File::open(path)?);
+    r.seek(SeekFrom::Start(start))?;
+
+    let mut buf = String::with_capacity(4096);
+    let mut pos = start;
+    let mut reported = start;
+
+    while pos < end {
+        buf.clear();
+        let n = r.read_line(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        let line_start = pos;
+        pos += n as u64;
+        if pos - reported >= PROGRESS_INTERVAL {
+            progress.advance(pos - reported);
+            reported = pos;
+        }
+
+        if !might_match(&buf) {
+            continue;
+        }
+
+        let entry = match EntryRef::parse(&buf) {
+            Ok(entry) => entry,
+            Err(e) => {
+                let line = seek::line_number_at(&mut r, line_start)?;
+                return Err(e.at_line(Some(line), line_start, &buf));
+            }
+        };
+        if let Some(result) = f(&entry) {
+            if !send(result) {
+                break;
+            }
+        }
+    }
+
+    progress.advance(pos - reported);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+
+    // Each TESTDATA line is 43 characters long, 44 if you count the newline.
+    const TESTDATA: &str = "2020-01-01T00:01:00.899849209+00:00,\"\"\"1\"\"\"
+2020-02-12T23:08:40.987613062+00:00,\"\"\"2\"\"\"
+2020-03-12T00:00:00.000000000+00:00,\"\"\"3\"\"\"
+2020-04-12T23:28:45.726598931+00:00,\"\"\"4\"\"\"
+2020-05-12T23:28:48.495151445+00:00,\"\"\"5\"\"\"
+2020-06-13T10:12:53.353050231+00:00,\"\"\"6\"\"\"
+";
+
+    #[test_case(0,  264, 1  => vec![(0, 264)] ; "single chunk")]
+    #[test_case(0,  264, 2  => vec![(0, 132), (132, 264)] ; "two chunks")]
+    #[test_case(0,  264, 4  => vec![(0, 44), (44, 132), (132, 176), (176, 264)] ; "four chunks")]
+    #[test_case(0,  264, 64 => vec![(0, 44), (44, 88), (88, 132), (132, 176), (176, 220), (220, 264)] ; "more chunks than lines")]
+    #[test_case(44, 132, 2  => vec![(44, 88), (88, 132)] ; "sub range")]
+    #[test_case(44, 44,  2  => Vec::<(u64, u64)>::new() ; "empty range")]
+    fn test_chunk_boundaries(start: u64, end: u64, n: u64) -> Vec<(u64, u64)> {
+        let mut r = Cursor::new(TESTDATA.as_bytes());
+        chunk_boundaries(&mut r, start, end, n).unwrap()
+    }
+
+    fn messages(
+        start: Option<&str>,
+        end: Option<&str>,
+        f: impl Fn(&Entry) -> bool + Sync,
+    ) -> Vec<String> {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(TESTDATA.as_bytes()).unwrap();
+
+        let start = start.map(|s| DateTime::parse_from_rfc3339(s).unwrap());
+        let end = end.map(|s| DateTime::parse_from_rfc3339(s).unwrap());
+
+        par_filter(file.path(), start.as_ref(), end.as_ref(), f)
+            .unwrap()
+            .iter()
+            .map(|e| e.message().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_par_filter_everything() {
+        assert_eq!(
+            messages(None, None, |_| true),
+            vec!["1", "2", "3", "4", "5", "6"]
+        );
+    }
+
+    #[test]
+    fn test_par_filter_predicate() {
+        assert_eq!(
+            messages(None, None, |e| e.message() != "3"),
+            vec!["1", "2", "4", "5", "6"]
+        );
+    }
+
+    #[test]
+    fn test_par_filter_date_range() {
+        assert_eq!(
+            messages(
+                Some("2020-02-12T23:08:40.987613062+00:00"),
+                Some("2020-05-12T23:28:48.495151445+00:00"),
+                |_| true
+            ),
+            vec!["2", "3", "4"]
+        );
+    }
+
+    #[test]
+    fn test_par_filter_progress() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        struct Counter {
+            total: AtomicU64,
+            scanned: AtomicU64,
+        }
+
+        impl Progress for Counter {
+            fn start(&self, total: u64) {
+                self.total.store(total, Ordering::SeqCst);
+            }
+
+            fn advance(&self, bytes: u64) {
+                self.scanned.fetch_add(bytes, Ordering::SeqCst);
+            }
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(TESTDATA.as_bytes()).unwrap();
+        let counter = Counter {
+            total: AtomicU64::new(0),
+            scanned: AtomicU64::new(0),
+        };
+
+        par_filter_with_progress(file.path(), None, None, |_| true, &counter).unwrap();
+        assert_eq!(counter.total.load(Ordering::SeqCst), 264);
+        assert_eq!(counter.scanned.load(Ordering::SeqCst), 264);
+    }
+
+    #[test]
+    fn test_par_filter_range_past_end() {
+        assert!(messages(Some("2021-01-01T00:00:00+00:00"), None, |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_par_filter_refs_and_count() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(TESTDATA.as_bytes()).unwrap();
+        let start = DateTime::parse_from_rfc3339("2020-02-01T00:00:00+00:00").unwrap();
+        let odd = |e: &EntryRef| e.message().parse::<u32>().unwrap() % 2 == 1;
+
+        let matches =
+            par_filter_refs_with_progress(file.path(), Some(&start), None, |_| true, odd, &())
+                .unwrap();
+        assert_eq!(
+            matches.iter().map(|e| e.message()).collect::<Vec<_>>(),
+            vec!["3", "5"]
+        );
+        assert_eq!(
+            par_count_with_progress(file.path(), Some(&start), None, |_| true, odd, &()).unwrap(),
+            2
+        );
+        assert_eq!(
+            par_count_with_progress(file.path(), None, None, |_| true, |_| true, &()).unwrap(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_par_filter_refs_for_each() {
+        // Enough entries for every chunk to fill its channel, so that chunks
+        // have to wait for the ones before them.
+        let mut file = NamedTempFile::new().unwrap();
+        let start = DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap();
+        for i in 0..20_000 {
+            let entry = Entry::new(start + chrono::Duration::seconds(i), i.to_string());
+            entry.write(&mut file).unwrap();
+        }
+
+        let mut messages = Vec::new();
+        par_filter_refs_for_each(
+            file.path(),
+            None,
+            None,
+            |_| true,
+            |_| true,
+            &(),
+            |e| {
+                messages.push(e.message().to_owned());
+                Ok(())
+            },
+        )
+        .unwrap();
+        let expected: Vec<_> = (0..20_000).map(|i| i.to_string()).collect();
+        assert_eq!(messages, expected);
+
+        let mut seen = 0;
+        let err = par_filter_refs_for_each(
+            file.path(),
+            None,
+            None,
+            |_| true,
+            |_| true,
+            &(),
+            |_| {
+                seen += 1;
+                match seen {
+                    10 => Err(crate::error::Error::InvalidArgument("stop".to_owned())),
+                    _ => Ok(()),
+                }
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "stop");
+        assert_eq!(seen, 10);
+    }
+
+    #[test]
+    fn test_par_count_might_match() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(TESTDATA.as_bytes()).unwrap();
+        file.write_all(b"not an entry\n").unwrap();
+        let count = par_count_with_progress(
+            file.path(),
+            None,
+            None,
+            |line| line.contains('3'),
+            |e| e.message() == "3",
+            &(),
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_par_count_malformed() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(TESTDATA.as_bytes()).unwrap();
+        file.write_all(b"not an entry\n").unwrap();
+        let err =
+            par_count_with_progress(file.path(), None, None, |_| true, |_| true, &()).unwrap_err();
+        assert_eq!(err.kind(), "malformed");
+        assert!(err.to_string().contains("line 7"), "{}", err);
+    }
+}
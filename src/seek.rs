@@ -103,6 +103,42 @@ pub fn start_of_current_line<T: Seek + Read>(f: &mut T) -> Result<u64> {
     }
 }
 
+/// Counts the newline characters in the next `len` bytes of `f`, returning the
+/// count along with the last byte read, if any bytes were read.
+pub fn count_newlines<T: Read>(f: &mut T, len: u64) -> Result<(u64, Option<u8>)> {
+    let mut r = f.take(len);
+    let mut buf = [0; 64 * 1024];
+    let mut count = 0;
+    let mut last = None;
+
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        count += newlines_in(&buf[..n]);
+        last = Some(buf[n - 1]);
+    }
+
+    Ok((count, last))
+}
+
+/// Counts the newline characters in `buf`.
+pub fn newlines_in(buf: &[u8]) -> u64 {
+    buf.iter().filter(|&&b| b == 0x0a).count() as u64
+}
+
+/// Returns the line number, counting from 1, of the line that the byte at
+/// `pos` is on. This has to read everything before `pos`, so it's intended for
+/// reporting errors rather than navigation. The cursor is left where it was.
+pub fn line_number_at<T: Seek + Read>(f: &mut T, pos: u64) -> Result<u64> {
+    let prev = f.stream_position()?;
+    f.seek(SeekFrom::Start(0))?;
+    let (count, _) = count_newlines(f, pos)?;
+    f.seek(SeekFrom::Start(prev))?;
+    Ok(count + 1)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -127,6 +163,10 @@ mod tests {
     #[test_case("line 1\nline 2\nline 3",   19 => "line 3"   ; "end of third line")]
     #[test_case("line 1\nline 2\nline 3",   26 => "line 3"   ; "past eof")]
     #[test_case("line 1\nline 2\nline 3\n", 20 => "line 3\n" ; "last line when line ends with eof")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 6  => "line 1\r\n" ; "carriage return of first line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 7  => "line 1\r\n" ; "line feed of first line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 8  => "line 2\r\n" ; "start of second CRLF line")]
+    #[test_case("line 1\r\nline 2\nline 3",   15 => "line 3"       ; "mixed line endings")]
     fn test_start_of_current_line(s: &str, pos: u64) -> String {
         let mut r = Cursor::new(s.as_bytes());
         r.seek(SeekFrom::Start(pos)).unwrap();
@@ -143,6 +183,9 @@ mod tests {
     #[test_case("line 1\nline 2\nline 3", 14 => None     ; "start of last line")]
     #[test_case("line 1\nline 2\nline 3", 16 => None     ; "middle of last line")]
     #[test_case("line 1\nline 2\nline 3", 19 => None     ; "end of last line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 6  => Some(8)  ; "carriage return of first line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 7  => Some(8)  ; "line feed of first line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 8  => Some(16) ; "start of second CRLF line")]
     fn test_start_of_next_line(s: &str, pos: u64) -> Option<u64> {
         let mut r = Cursor::new(s.as_bytes());
         r.seek(SeekFrom::Start(pos)).unwrap();
@@ -159,9 +202,26 @@ mod tests {
     #[test_case("line 1\nline 2\nline 3", 14 => Some(7)  ; "start of last line")]
     #[test_case("line 1\nline 2\nline 3", 16 => Some(7)  ; "middle of last line")]
     #[test_case("line 1\nline 2\nline 3", 19 => Some(7)  ; "end of last line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 8  => Some(0)  ; "start of second CRLF line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 15 => Some(0)  ; "line feed of second line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 16 => Some(8)  ; "start of last CRLF line")]
     fn test_start_of_prev_line(s: &str, pos: u64) -> Option<u64> {
         let mut r = Cursor::new(s.as_bytes());
         r.seek(SeekFrom::Start(pos)).unwrap();
         start_of_prev_line(&mut r).unwrap()
     }
+
+    #[test_case("",                       0  => 1 ; "empty file")]
+    #[test_case("line 1\nline 2\nline 3", 0  => 1 ; "start of first line")]
+    #[test_case("line 1\nline 2\nline 3", 6  => 1 ; "end of first line")]
+    #[test_case("line 1\nline 2\nline 3", 7  => 2 ; "start of second line")]
+    #[test_case("line 1\nline 2\nline 3", 19 => 3 ; "end of last line")]
+    #[test_case("line 1\r\nline 2\r\nline 3", 8  => 2 ; "start of second CRLF line")]
+    fn test_line_number_at(s: &str, pos: u64) -> u64 {
+        let mut r = Cursor::new(s.as_bytes());
+        r.seek(SeekFrom::Start(3)).unwrap();
+        let line = line_number_at(&mut r, pos).unwrap();
+        assert_eq!(r.stream_position().unwrap(), 3);
+        line
+    }
 }
@@ -0,0 +1,251 @@
+//! How much was written in each stretch of time of a fixed length, for hmms
+//! series to export to tools like gnuplot.
+
+use super::{date, entry::Entry, error::Error, text, Result};
+use chrono::prelude::*;
+use chrono::Duration;
+use serde_json::json;
+use std::io::Write;
+use std::str::FromStr;
+
+/// What's added up in each bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    /// How many entries were written.
+    Count,
+    /// How many words were written, across every entry.
+    Words,
+}
+
+impl Value {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Value::Count => "count",
+            Value::Words => "words",
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "count" => Ok(Value::Count),
+            "words" => Ok(Value::Words),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown value \"{}\", expected count or words",
+                s
+            ))),
+        }
+    }
+}
+
+/// What a series is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A header line, then a line per bucket of its start and value.
+    Csv,
+    /// An array with an object per bucket.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown series format \"{}\", expected csv or json",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bucket {
+    pub start: DateTime<FixedOffset>,
+    pub value: u64,
+}
+
+/// Adds entries up in to buckets of a fixed length. Buckets are counted from
+/// the start given, or from the start of the day the first entry was written
+/// on, and buckets nothing was written in are kept so that there are no holes
+/// in the series. Entries have to be pushed in the order they were written.
+#[derive(Debug)]
+pub struct Series {
+    length: Duration,
+    value: Value,
+    start: Option<DateTime<FixedOffset>>,
+    buckets: Vec<Bucket>,
+}
+
+impl Series {
+    /// Panics if `length` isn't longer than zero.
+    pub fn new(length: Duration, value: Value, start: Option<DateTime<FixedOffset>>) -> Self {
+        assert!(length > Duration::zero(), "buckets have to have a length");
+        Series {
+            length,
+            value,
+            start,
+            buckets: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: &Entry) {
+        let datetime = *entry.datetime();
+        let start = *self
+            .start
+            .get_or_insert_with(|| date::start_of_day(datetime.with_timezone(&Local).date_naive()));
+        if datetime < start {
+            return;
+        }
+        let i = ((datetime - start).num_seconds() / self.length.num_seconds()) as usize;
+        self.fill(i + 1);
+        self.buckets[i].value += match self.value {
+            Value::Count => 1,
+            Value::Words => text::word_count(entry.message()) as u64,
+        };
+    }
+
+    /// The buckets, oldest first, carrying on with empty ones up to `end` if
+    /// it's after the last entry.
+    pub fn finish(mut self, end: Option<DateTime<FixedOffset>>) -> Vec<Bucket> {
+        if let (Some(start), Some(end)) = (self.start, end) {
+            if end > start {
+                let seconds = (end - start).num_seconds();
+                let length = self.length.num_seconds();
+                self.fill(((seconds + length - 1) / length) as usize);
+            }
+        }
+        self.buckets
+    }
+
+    // Adds empty buckets until there are `len` of them.
+    fn fill(&mut self, len: usize) {
+        let start = self.start.unwrap();
+        while self.buckets.len() < len {
+            self.buckets.push(Bucket {
+                start: start + self.length * self.buckets.len() as i32,
+                value: 0,
+            });
+        }
+    }
+}
+
+/// Writes `buckets` to `w` in `format`, with their value called whatever
+/// `value` is. Times are RFC3339, in the local timezone.
+pub fn write(mut w: impl Write, buckets: &[Bucket], value: Value, format: Format) -> Result<()> {
+    let start = |bucket: &Bucket| bucket.start.with_timezone(&Local).to_rfc3339();
+    match format {
+        Format::Csv => {
+            writeln!(w, "start,{}", value.name())?;
+            for bucket in buckets {
+                writeln!(w, "{},{}", start(bucket), bucket.value)?;
+            }
+        }
+        Format::Json => {
+            let buckets: Vec<_> = buckets
+                .iter()
+                .map(|bucket| json!({ "start": start(bucket), value.name(): bucket.value }))
+                .collect();
+            writeln!(w, "{}", serde_json::to_string_pretty(&buckets)?)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn datetime(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(&format!("{}:00+00:00", s)).unwrap()
+    }
+
+    fn series(
+        entries: &[(&str, &str)],
+        hours: i64,
+        value: Value,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> Vec<String> {
+        let mut series = Series::new(Duration::hours(hours), value, start.map(datetime));
+        for (at, message) in entries {
+            series.push(&Entry::new(datetime(at), message.to_string()));
+        }
+        series
+            .finish(end.map(datetime))
+            .into_iter()
+            .map(|bucket| format!("{} {}", bucket.start.format("%d %H:%M"), bucket.value))
+            .collect()
+    }
+
+    const ENTRIES: &[(&str, &str)] = &[
+        ("2024-05-13T09:00", "standup"),
+        ("2024-05-13T09:30", "two words"),
+        ("2024-05-13T14:00", "lunch was late"),
+        ("2024-05-14T01:00", "can't sleep"),
+    ];
+
+    #[test_case(ENTRIES, 12, Value::Count, None, None => vec!["13 00:00 2", "13 12:00 1", "14 00:00 1"] ; "count")]
+    #[test_case(ENTRIES, 12, Value::Words, None, None => vec!["13 00:00 3", "13 12:00 3", "14 00:00 2"] ; "words")]
+    #[test_case(ENTRIES, 24, Value::Count, Some("2024-05-12T00:00"), Some("2024-05-15T12:00") => vec!["12 00:00 0", "13 00:00 3", "14 00:00 1", "15 00:00 0"] ; "start and end")]
+    #[test_case(ENTRIES, 6, Value::Count, Some("2024-05-13T09:00"), None => vec!["13 09:00 3", "13 15:00 0", "13 21:00 1"] ; "from start")]
+    #[test_case(&[], 24, Value::Count, Some("2024-05-12T00:00"), Some("2024-05-14T00:00") => vec!["12 00:00 0", "13 00:00 0"] ; "no entries")]
+    #[test_case(&[], 24, Value::Count, None, Some("2024-05-14T00:00") => Vec::<String>::new() ; "nothing at all")]
+    fn test_series(
+        entries: &[(&str, &str)],
+        hours: i64,
+        value: Value,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> Vec<String> {
+        series(entries, hours, value, start, end)
+    }
+
+    #[test]
+    fn test_write() {
+        let buckets = [
+            Bucket {
+                start: datetime("2024-05-13T00:00"),
+                value: 3,
+            },
+            Bucket {
+                start: datetime("2024-05-14T00:00"),
+                value: 0,
+            },
+        ];
+        let mut out = Vec::new();
+        write(&mut out, &buckets, Value::Words, Format::Csv).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "start,words\n2024-05-13T00:00:00+00:00,3\n2024-05-14T00:00:00+00:00,0\n"
+        );
+
+        let mut out = Vec::new();
+        write(&mut out, &buckets, Value::Count, Format::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json[0]["start"], "2024-05-13T00:00:00+00:00");
+        assert_eq!(json[0]["count"], 3);
+        assert_eq!(json[1]["count"], 0);
+    }
+
+    #[test_case("count" => Ok(Value::Count) ; "count")]
+    #[test_case("words" => Ok(Value::Words) ; "words")]
+    #[test_case("chars" => Err("unknown value \"chars\", expected count or words".to_owned()) ; "unknown")]
+    fn test_value(s: &str) -> std::result::Result<Value, String> {
+        s.parse().map_err(|e: Error| e.to_string())
+    }
+
+    #[test_case("csv"  => Ok(Format::Csv)  ; "csv")]
+    #[test_case("json" => Ok(Format::Json) ; "json")]
+    #[test_case("xml"  => Err("unknown series format \"xml\", expected csv or json".to_owned()) ; "unknown")]
+    fn test_format(s: &str) -> std::result::Result<Format, String> {
+        s.parse().map_err(|e: Error| e.to_string())
+    }
+}
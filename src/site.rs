@@ -0,0 +1,252 @@
+use super::{entry::Entry, error::Error, Result};
+use handlebars::html_escape;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Writes a small static website for browsing `entries` to `dir`, creating it
+/// if it doesn't exist. There's a page for each month with entries in it, and
+/// an index.html that links to them all and lets you search every entry. The
+/// pages don't depend on anything outside of `dir`, so they work straight
+/// from disk. Returns the number of month pages written.
+pub fn write(dir: &Path, entries: &[Entry]) -> Result<usize> {
+    fs::create_dir_all(dir).map_err(|source| Error::CreateFile {
+        path: dir.to_owned(),
+        source,
+    })?;
+
+    let months = months(entries);
+    let mut search = Vec::with_capacity(entries.len());
+
+    for (i, month) in months.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|i| &months[i]);
+        let next = months.get(i + 1);
+
+        let mut body = String::new();
+        body.push_str(&nav(prev, next));
+        body.push_str(&format!("<h1>{}</h1>\n", html_escape(&month.title)));
+        for (n, entry) in month.entries.iter().enumerate() {
+            body.push_str(&article(entry, n));
+            search.push(serde_json::json!({
+                "d": entry.datetime().format("%Y-%m-%d %H:%M").to_string(),
+                "m": entry.message(),
+                "u": format!("{}#e{}", month.file(), n),
+            }));
+        }
+        body.push_str(&nav(prev, next));
+
+        write_file(&dir.join(month.file()), &page(&month.title, &body))?;
+    }
+
+    let mut body = String::from(INDEX_HEADER);
+    for month in months.iter().rev() {
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> <span class=\"count\">{}</span></li>\n",
+            month.file(),
+            html_escape(&month.title),
+            month.entries.len()
+        ));
+    }
+    body.push_str("</ul>\n<script src=\"entries.js\"></script>\n");
+    body.push_str(SEARCH_SCRIPT);
+    write_file(&dir.join("index.html"), &page("hmm", &body))?;
+
+    // The search index is a script rather than a JSON file so that it can be
+    // loaded when the pages are opened straight from disk, where browsers
+    // don't allow fetching files.
+    let index = format!("var HMM_ENTRIES = {};\n", serde_json::Value::Array(search));
+    write_file(&dir.join("entries.js"), &index)?;
+
+    Ok(months.len())
+}
+
+struct Month<'a> {
+    key: String,
+    title: String,
+    entries: Vec<&'a Entry>,
+}
+
+impl Month<'_> {
+    fn file(&self) -> String {
+        format!("{}.html", self.key)
+    }
+}
+
+// Entries are grouped by the month they were written in, in the timezone they
+// were written in, which is what their author would expect to see. That means
+// entries either side of the start of a month can end up out of order if they
+// were written in different timezones, so the months are sorted by name.
+fn months(entries: &[Entry]) -> Vec<Month<'_>> {
+    let mut months: BTreeMap<String, Month> = BTreeMap::new();
+    for entry in entries {
+        let key = entry.datetime().format("%Y-%m").to_string();
+        months
+            .entry(key.clone())
+            .or_insert_with(|| Month {
+                key,
+                title: entry.datetime().format("%B %Y").to_string(),
+                entries: Vec::new(),
+            })
+            .entries
+            .push(entry);
+    }
+    months.into_values().collect()
+}
+
+fn nav(prev: Option<&Month>, next: Option<&Month>) -> String {
+    let link = |month: Option<&Month>, text: &str| match month {
+        Some(month) => format!("<a href=\"{}\">{}</a>", month.file(), text),
+        None => format!("<span>{}</span>", text),
+    };
+    format!(
+        "<nav>{} <a href=\"index.html\">index</a> {}</nav>\n",
+        link(prev, "&larr; previous"),
+        link(next, "next &rarr;")
+    )
+}
+
+fn article(entry: &Entry, n: usize) -> String {
+    format!(
+        "<article id=\"e{}\"><time datetime=\"{}\">{}</time><div class=\"message\">{}</div></article>\n",
+        n,
+        entry.datetime().to_rfc3339(),
+        entry.datetime().format("%Y-%m-%d %H:%M"),
+        html_escape(entry.message())
+    )
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        html_escape(title),
+        STYLE,
+        body
+    )
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    fs::write(path, contents).map_err(|source| Error::CreateFile {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 40em; margin: 0 auto; padding: 1em; line-height: 1.4; }
+nav { display: flex; justify-content: space-between; margin: 1em 0; }
+nav span { color: #aaa; }
+article { border-left: 3px solid #4a7ebb; padding-left: 0.8em; margin: 1.5em 0; }
+time { color: #4a7ebb; font-size: 0.9em; }
+.message { white-space: pre-wrap; }
+.count { color: #888; }
+input { width: 100%; font-size: 1em; padding: 0.4em; box-sizing: border-box; }";
+
+const INDEX_HEADER: &str = "<h1>hmm</h1>
+<input id=\"search\" type=\"search\" placeholder=\"Search entries\" autofocus>
+<div id=\"results\"></div>
+<ul id=\"months\">
+";
+
+// Shows up to 100 entries containing every word typed in to the search box,
+// ignoring case, in place of the list of months.
+const SEARCH_SCRIPT: &str = "<script>
+var search = document.getElementById('search');
+var results = document.getElementById('results');
+var months = document.getElementById('months');
+search.addEventListener('input', function () {
+  var words = search.value.toLowerCase().split(/\\s+/).filter(Boolean);
+  results.textContent = '';
+  months.hidden = words.length > 0;
+  if (!words.length) return;
+  var shown = 0;
+  for (var i = HMM_ENTRIES.length - 1; i >= 0 && shown < 100; i--) {
+    var e = HMM_ENTRIES[i];
+    var m = e.m.toLowerCase();
+    if (!words.every(function (w) { return m.indexOf(w) !== -1; })) continue;
+    var article = document.createElement('article');
+    var link = document.createElement('a');
+    link.href = e.u;
+    link.textContent = e.d;
+    var message = document.createElement('div');
+    message.className = 'message';
+    message.textContent = e.m;
+    article.appendChild(link);
+    article.appendChild(message);
+    results.appendChild(article);
+    shown++;
+  }
+  if (!shown) results.textContent = 'No entries found.';
+});
+</script>
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use tempfile::TempDir;
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        Entry::new(
+            DateTime::parse_from_rfc3339(datetime).unwrap(),
+            message.to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = TempDir::new().unwrap();
+        let entries = vec![
+            entry("2020-01-01T09:00:00+00:00", "new year"),
+            entry("2020-01-20T09:00:00+00:00", "<b>not bold</b> & such"),
+            entry("2020-03-01T09:00:00+00:00", "spring"),
+        ];
+
+        assert_eq!(write(dir.path(), &entries).unwrap(), 2);
+
+        let mut files: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["2020-01.html", "2020-03.html", "entries.js", "index.html"]
+        );
+
+        let january = fs::read_to_string(dir.path().join("2020-01.html")).unwrap();
+        assert!(january.contains("<h1>January 2020</h1>"));
+        assert!(january.contains("&lt;b&gt;not bold&lt;/b&gt; &amp; such"));
+        assert!(january.contains("<a href=\"2020-03.html\">next &rarr;</a>"));
+        assert!(january.contains("<span>&larr; previous</span>"));
+
+        let index = fs::read_to_string(dir.path().join("index.html")).unwrap();
+        let march = index.find("2020-03.html").unwrap();
+        assert!(march < index.find("2020-01.html").unwrap());
+
+        let search = fs::read_to_string(dir.path().join("entries.js")).unwrap();
+        assert!(search.contains("\"u\":\"2020-01.html#e1\""));
+        assert!(search.contains("\"m\":\"<b>not bold</b> & such\""));
+    }
+
+    #[test]
+    fn test_write_timezones() {
+        let dir = TempDir::new().unwrap();
+        let entries = vec![
+            entry("2020-01-31T23:00:00-02:00", "january"),
+            entry("2020-02-01T08:00:00+10:00", "february"),
+            entry("2020-01-31T20:00:00-08:00", "still january"),
+        ];
+
+        assert_eq!(write(dir.path(), &entries).unwrap(), 2);
+        let january = fs::read_to_string(dir.path().join("2020-01.html")).unwrap();
+        assert!(january.contains("still january"));
+    }
+
+    #[test]
+    fn test_write_no_entries() {
+        let dir = TempDir::new().unwrap();
+        let site = dir.path().join("site");
+        assert_eq!(write(&site, &[]).unwrap(), 0);
+        assert!(site.join("index.html").exists());
+    }
+}
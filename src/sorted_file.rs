@@ -0,0 +1,193 @@
+//! Binary search over files whose lines are in sorted order, like .hmm files.
+//!
+//! Lines can end in "\n" or "\r\n", and the last line doesn't need to end in
+//! either. Functions here leave the cursor at the offset they return, so that
+//! reading from the file carries on from the line that was found.
+
+use super::{entry::trim_line, seek, Result};
+use std::io::{BufRead, Seek, SeekFrom};
+
+/// Returns the byte offset of the first line that `is_ge` returns true for,
+/// or the length of the file if there isn't one. `is_ge` is given each line it
+/// needs to look at without its line ending, and must return false for every
+/// line before the one being looked for and true for every line after it, as
+/// "is this line on or after X" does for a sorted file.
+///
+/// Errors returned by `is_ge` have the line they came from attached to them
+/// with `Error::at_line`.
+pub fn find_first_by<T, F>(f: &mut T, mut is_ge: F) -> Result<u64>
+where
+    T: Seek + BufRead,
+    F: FnMut(&str) -> Result<bool>,
+{
+    let mut search = Search::new(f.seek(SeekFrom::End(0))?);
+    let mut line = String::new();
+
+    while let Some(pos) = search.next() {
+        f.seek(SeekFrom::Start(pos))?;
+        let start = seek::start_of_current_line(f)?;
+        line.clear();
+        let end = start + f.read_line(&mut line)? as u64;
+
+        match is_ge(trim_line(&line)) {
+            Ok(is_ge) => search.narrow(start, end, is_ge),
+            Err(e) => {
+                let number = seek::line_number_at(f, start)?;
+                return Err(e.at_line(Some(number), start, &line));
+            }
+        }
+    }
+
+    f.seek(SeekFrom::Start(search.found()))?;
+    Ok(search.found())
+}
+
+/// The bookkeeping for the binary search `find_first_by` does, kept apart from
+/// its reads so that the nonblocking module can do the same search with async
+/// I/O.
+pub(crate) struct Search {
+    // Every line starting before `lo` is less than what we're looking for, and
+    // every line starting at or after `hi` isn't. Both are always the start of
+    // a line, or the end of the file.
+    lo: u64,
+    hi: u64,
+}
+
+impl Search {
+    /// Starts a search over a file that's `len` bytes long.
+    pub(crate) fn new(len: u64) -> Self {
+        Search { lo: 0, hi: len }
+    }
+
+    /// Returns a byte whose line should be looked at next, or None once the
+    /// search has finished.
+    pub(crate) fn next(&self) -> Option<u64> {
+        if self.lo < self.hi {
+            Some(self.lo + (self.hi - self.lo) / 2)
+        } else {
+            None
+        }
+    }
+
+    /// Narrows the search down given that the line from `start` up to `end`
+    /// was or wasn't on or after what's being looked for.
+    pub(crate) fn narrow(&mut self, start: u64, end: u64, is_ge: bool) {
+        if is_ge {
+            self.hi = start;
+        } else {
+            self.lo = end;
+        }
+    }
+
+    /// The offset of the line that was found, once `next` has returned None.
+    pub(crate) fn found(&self) -> u64 {
+        self.lo
+    }
+}
+
+/// Returns the byte offset of the first line that sorts on or after `prefix`,
+/// comparing bytes, or the length of the file if there isn't one. If any line
+/// starts with `prefix`, this is the first of them.
+pub fn find_first_ge<T: Seek + BufRead>(f: &mut T, prefix: &str) -> Result<u64> {
+    find_first_by(f, |line| Ok(line >= prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::io::Cursor;
+    use test_case::test_case;
+
+    #[test_case("",                 "b" => 0  ; "empty file")]
+    #[test_case("a\nb\nc\n",        "a" => 0  ; "first line")]
+    #[test_case("a\nb\nc\n",        "b" => 2  ; "middle line")]
+    #[test_case("a\nb\nc\n",        "bb" => 4 ; "between lines")]
+    #[test_case("a\nb\nc\n",        "d" => 6  ; "after every line")]
+    #[test_case("a\nb\nc",          "c" => 4  ; "no trailing newline")]
+    #[test_case("a\r\nb\r\nc\r\n",  "b" => 3  ; "crlf")]
+    #[test_case("a\nb\nb\nb\nc\n",  "b" => 2  ; "first of duplicates")]
+    #[test_case("ab\nbc\ncd\n",     "b" => 3  ; "prefix")]
+    fn test_find_first_ge(s: &str, prefix: &str) -> u64 {
+        let mut f = Cursor::new(s.as_bytes());
+        let offset = find_first_ge(&mut f, prefix).unwrap();
+        assert_eq!(f.stream_position().unwrap(), offset);
+        offset
+    }
+
+    #[test]
+    fn test_find_first_by_error() {
+        let mut f = Cursor::new("a\nb\nc\n".as_bytes());
+        let err = find_first_by(&mut f, |line| {
+            if line == "b" {
+                Err(Error::Malformed {
+                    line: None,
+                    byte: None,
+                    snippet: None,
+                    reason: Some("bad line".to_owned()),
+                })
+            } else {
+                Ok(line > "b")
+            }
+        })
+        .unwrap_err();
+
+        match err {
+            Error::Malformed { line, byte, .. } => {
+                assert_eq!(line, Some(2));
+                assert_eq!(byte, Some(2));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    // Builds a file of sorted, zero-padded numbers with some repeated, mixed
+    // line endings and sometimes no newline at the end.
+    fn random_file(rng: &mut StdRng) -> (String, Vec<u64>) {
+        let mut lines = Vec::new();
+        let mut n = 0;
+        for _ in 0..rng.gen_range(0..50) {
+            n += rng.gen_range(0..3);
+            lines.push(n);
+        }
+
+        let mut s = String::new();
+        let mut offsets = Vec::new();
+        for (i, n) in lines.iter().enumerate() {
+            offsets.push(s.len() as u64);
+            s.push_str(&format!("{:04}", n));
+            if i + 1 < lines.len() || rng.gen_bool(0.5) {
+                s.push_str(if rng.gen_bool(0.2) { "\r\n" } else { "\n" });
+            }
+        }
+        (s, offsets)
+    }
+
+    #[test]
+    fn test_find_first_ge_matches_linear_search() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..500 {
+            let (s, offsets) = random_file(&mut rng);
+            let prefix = format!("{:04}", rng.gen_range(0..110));
+
+            let expected = offsets
+                .iter()
+                .copied()
+                .find(|&offset| s[offset as usize..].lines().next().unwrap() >= &prefix[..])
+                .unwrap_or(s.len() as u64);
+
+            // Where the cursor starts shouldn't matter.
+            let mut f = Cursor::new(s.as_bytes());
+            f.seek(SeekFrom::Start(rng.gen_range(0..=s.len() as u64)))
+                .unwrap();
+            assert_eq!(
+                find_first_ge(&mut f, &prefix).unwrap(),
+                expected,
+                "searching for {:?} in {:?}",
+                prefix,
+                s
+            );
+        }
+    }
+}
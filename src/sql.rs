@@ -0,0 +1,151 @@
+//! Running SQL over entries, for hmmq --sql, by loading them in to an
+//! in-memory SQLite database. There are two tables:
+//!
+//! ```sql
+//! create table entries (
+//!     id text,        -- like "cbbf4083"
+//!     datetime text,  -- local time, like "2024-05-13 09:00:00"
+//!     message text,
+//!     title text,     -- the first line of the message
+//!     words integer,
+//!     metadata text   -- a JSON object, for json_extract
+//! );
+//! create table tags (id text, tag text);  -- tags without their #s
+//! ```
+//!
+//! Datetimes are in the form SQLite's date and time functions expect, so
+//! something like `strftime('%Y-%m', datetime)` works.
+
+use super::{entry::Entry, text, Result};
+use chrono::prelude::*;
+use rusqlite::{params, types::ValueRef, Connection};
+use std::io::Write;
+
+const SCHEMA: &str = "
+create table entries (
+    id text not null,
+    datetime text not null,
+    message text not null,
+    title text not null,
+    words integer not null,
+    metadata text not null
+);
+create table tags (id text not null, tag text not null);
+";
+
+/// Loads `entries` in to a new in-memory database.
+pub fn load(entries: &[Entry]) -> Result<Connection> {
+    let mut db = Connection::open_in_memory()?;
+    db.execute_batch(SCHEMA)?;
+
+    let tx = db.transaction()?;
+    {
+        let mut insert_entry = tx.prepare("insert into entries values (?, ?, ?, ?, ?, ?)")?;
+        let mut insert_tag = tx.prepare("insert into tags values (?, ?)")?;
+        for entry in entries {
+            let id = entry.id();
+            insert_entry.execute(params![
+                id,
+                entry
+                    .datetime()
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                entry.message(),
+                entry.title(),
+                text::word_count(entry.message()) as i64,
+                serde_json::to_string(entry.metadata())?,
+            ])?;
+            let mut tags = entry.tags();
+            tags.sort_unstable();
+            tags.dedup();
+            for tag in tags {
+                insert_tag.execute(params![id, tag])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(db)
+}
+
+/// Runs `query` against `entries` and writes what it returns to `w` as CSV,
+/// with a header row of the column names. Nulls are written as empty cells.
+pub fn query(w: impl Write, entries: &[Entry], query: &str) -> Result<()> {
+    let db = load(entries)?;
+    let mut statement = db.prepare(query)?;
+    let mut out = csv::Writer::from_writer(w);
+    out.write_record(statement.column_names())?;
+
+    let columns = statement.column_count();
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut record = Vec::with_capacity(columns);
+        for i in 0..columns {
+            record.push(match row.get_ref(i)? {
+                ValueRef::Null => String::new(),
+                ValueRef::Integer(n) => n.to_string(),
+                ValueRef::Real(n) => n.to_string(),
+                ValueRef::Text(s) | ValueRef::Blob(s) => String::from_utf8_lossy(s).into_owned(),
+            });
+        }
+        out.write_record(&record)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Metadata;
+    use test_case::test_case;
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+        Entry::new(
+            Local.from_local_datetime(&datetime).unwrap().fixed_offset(),
+            message.to_owned(),
+        )
+    }
+
+    fn entries() -> Vec<Entry> {
+        let mut metadata = Metadata::new();
+        metadata.insert("mood".to_owned(), 4.into());
+        vec![
+            entry("2024-04-30 09:00", "standup #work"),
+            entry("2024-05-13 09:00", "standup, again #work #work #meetings"),
+            entry("2024-05-13 12:30", "lunch\nwith sam").with_metadata(metadata),
+        ]
+    }
+
+    fn run(sql: &str) -> String {
+        let mut out = Vec::new();
+        query(&mut out, &entries(), sql).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test_case("select strftime('%Y-%m', datetime) m, count(*) n from entries group by m" => "m,n\n2024-04,1\n2024-05,2\n" ; "group by month")]
+    #[test_case("select tag, count(*) from tags group by tag order by tag" => "tag,count(*)\nmeetings,1\nwork,2\n" ; "tags")]
+    #[test_case("select title, words from entries where message like 'lunch%'" => "title,words\nlunch,3\n" ; "title and words")]
+    #[test_case("select json_extract(metadata, '$.mood') mood from entries" => "mood\n\"\"\n\"\"\n4\n" ; "metadata")]
+    #[test_case("select avg(words), null from entries" => "avg(words),null\n3.3333333333333335,\n" ; "real and null")]
+    #[test_case("select * from entries where 0" => "id,datetime,message,title,words,metadata\n" ; "no rows")]
+    fn test_query(sql: &str) -> String {
+        run(sql)
+    }
+
+    #[test]
+    fn test_query_quotes_messages() {
+        assert_eq!(
+            run("select datetime, message from entries where title = 'lunch'"),
+            "datetime,message\n2024-05-13 12:30:00,\"lunch\nwith sam\"\n"
+        );
+    }
+
+    #[test]
+    fn test_query_error() {
+        let err = query(Vec::new(), &entries(), "select nope from entries").unwrap_err();
+        assert_eq!(err.kind(), "sql");
+        assert!(err.to_string().contains("no such column: nope"), "{}", err);
+    }
+}
@@ -0,0 +1,65 @@
+use super::Result;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "object-storage")]
+pub mod object;
+
+// Paths starting with these are journals in object storage, which needs hmm
+// to be built with the object-storage feature.
+const OBJECT_SCHEMES: &[&str] = &["s3://", "dav://", "davs://"];
+
+/// Where a journal is kept, worked out from the path it was given as.
+#[derive(Debug, Clone)]
+pub enum Storage {
+    /// A file on this machine.
+    File(PathBuf),
+
+    /// An object in S3 or on a WebDAV server, given as a path like
+    /// "s3://bucket/journal.hmm" or "davs://example.com/journal.hmm".
+    #[cfg(feature = "object-storage")]
+    Object(object::ObjectStore),
+}
+
+impl Storage {
+    pub fn parse(path: &Path) -> Result<Storage> {
+        let url = match path.to_str() {
+            Some(url) if OBJECT_SCHEMES.iter().any(|s| url.starts_with(s)) => url,
+            _ => return Ok(Storage::File(path.to_owned())),
+        };
+
+        object(url)
+    }
+}
+
+#[cfg(feature = "object-storage")]
+fn object(url: &str) -> Result<Storage> {
+    Ok(Storage::Object(url.parse()?))
+}
+
+#[cfg(not(feature = "object-storage"))]
+fn object(url: &str) -> Result<Storage> {
+    Err(super::error::Error::InvalidArgument(format!(
+        "{} is in object storage, which this build of hmm doesn't support, reinstall it with `cargo install hmmcli --features object-storage`",
+        url
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file() {
+        assert!(matches!(
+            Storage::parse(Path::new("/home/sam/.hmm")),
+            Ok(Storage::File(path)) if path == Path::new("/home/sam/.hmm")
+        ));
+    }
+
+    #[cfg(not(feature = "object-storage"))]
+    #[test]
+    fn test_parse_object_without_feature() {
+        let err = Storage::parse(Path::new("s3://bucket/journal.hmm")).unwrap_err();
+        assert_eq!(err.exit_code(), 64);
+    }
+}
@@ -0,0 +1,708 @@
+use crate::{
+    curl,
+    entry::{Entry, Metadata},
+    error::Error,
+    journal::{self, AppendOptions},
+    Result,
+};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+
+// Journals are read a block of this many bytes at a time, so that seeking
+// around a journal to find entries by date only fetches the parts of it that
+// are looked at.
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+// How long to wait before trying again when something else wrote to the
+// journal between us reading it and writing it back.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Used when neither AWS_REGION nor AWS_DEFAULT_REGION is set, like the AWS
+// CLI does.
+const DEFAULT_REGION: &str = "us-east-1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Service {
+    S3 {
+        bucket: String,
+        key: String,
+        region: String,
+        endpoint: Option<String>,
+    },
+    WebDav {
+        url: String,
+    },
+}
+
+/// A journal kept in S3 or on a WebDAV server. There's no way to append to an
+/// object, so entries are written by reading the whole journal, adding them
+/// to the end, and writing it back only if nothing else has changed it in the
+/// meantime. Reads fetch only the parts of the journal they need.
+///
+/// Requests are made with curl, which needs to be installed. S3 credentials
+/// are read from AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and
+/// AWS_SESSION_TOKEN, and the region from AWS_REGION. Setting AWS_ENDPOINT_URL
+/// uses an S3-compatible service other than AWS. WebDAV credentials are read
+/// from ~/.netrc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectStore {
+    name: String,
+    service: Service,
+}
+
+impl FromStr for ObjectStore {
+    type Err = Error;
+
+    /// Parses "s3://bucket/key", "dav://host/path" or "davs://host/path",
+    /// where dav is WebDAV over HTTP and davs is WebDAV over HTTPS.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidArgument(format!("invalid object storage URL \"{}\"", s));
+
+        let service = if let Some(rest) = s.strip_prefix("s3://") {
+            let (bucket, key) = rest.split_once('/').ok_or_else(invalid)?;
+            if bucket.is_empty() || key.is_empty() {
+                return Err(invalid());
+            }
+            Service::S3 {
+                bucket: bucket.to_owned(),
+                key: key.to_owned(),
+                region: env::var("AWS_REGION")
+                    .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+                    .unwrap_or_else(|_| DEFAULT_REGION.to_owned()),
+                endpoint: env::var("AWS_ENDPOINT_URL_S3")
+                    .or_else(|_| env::var("AWS_ENDPOINT_URL"))
+                    .ok(),
+            }
+        } else {
+            let url = match (s.strip_prefix("dav://"), s.strip_prefix("davs://")) {
+                (Some(rest), _) => format!("http://{}", rest),
+                (_, Some(rest)) => format!("https://{}", rest),
+                _ => return Err(invalid()),
+            };
+            match url.split_once("://").unwrap().1.split_once('/') {
+                Some((host, path)) if !host.is_empty() && !path.is_empty() => {}
+                _ => return Err(invalid()),
+            }
+            Service::WebDav { url }
+        };
+
+        Ok(ObjectStore {
+            name: s.to_owned(),
+            service,
+        })
+    }
+}
+
+impl fmt::Display for ObjectStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl ObjectStore {
+    /// Appends a new entry with the given message to the end of the journal,
+    /// returning the entry that was written. See `journal::append`.
+    pub fn append(&self, message: &str, opts: &AppendOptions) -> Result<Entry> {
+        self.append_with_metadata(message, &Metadata::new(), opts)
+    }
+
+    /// The same as `append`, but attaching `metadata` to the entry that's
+    /// written.
+    pub fn append_with_metadata(
+        &self,
+        message: &str,
+        metadata: &Metadata,
+        opts: &AppendOptions,
+    ) -> Result<Entry> {
+        let mut entries = self.write(opts, |f, local| {
+            journal::append_with_metadata(f, message, metadata, local).map(|entry| vec![entry])
+        })?;
+        Ok(entries.remove(0))
+    }
+
+    /// The same as `append`, but writing an entry for each of `messages`.
+    ///
+    /// If something else writes to the journal at the same time, this tries
+    /// again with what it wrote until `opts.lock_timeout` runs out. Without
+    /// `opts.lock` the journal is written back regardless, which is only safe
+    /// if nothing else writes to it.
+    pub fn append_all<S: AsRef<str>>(
+        &self,
+        messages: &[S],
+        opts: &AppendOptions,
+    ) -> Result<Vec<Entry>> {
+        self.write(opts, |f, local| journal::append_all(f, messages, local))
+    }
+
+    // Fetches the journal, has `append` write to the local copy of it, and
+    // writes it back, trying again as append_all says.
+    fn write<F>(&self, opts: &AppendOptions, append: F) -> Result<Vec<Entry>>
+    where
+        F: Fn(&fs::File, &AppendOptions) -> Result<Vec<Entry>>,
+    {
+        let deadline = Instant::now() + opts.lock_timeout;
+        let local = AppendOptions {
+            lock: false,
+            ..opts.clone()
+        };
+
+        loop {
+            let (journal, etag) = self.fetch()?;
+            let f = OpenOptions::new()
+                .read(true)
+                .append(true)
+                .open(journal.path())?;
+            let entries = append(&f, &local)?;
+
+            let condition = match (opts.lock, etag) {
+                (false, _) => None,
+                (true, Version::Missing) => Some("If-None-Match: *".to_owned()),
+                (true, Version::ETag(etag)) => Some(format!("If-Match: {}", etag)),
+                (true, Version::Unknown) => {
+                    return Err(Error::Remote(format!(
+                    "{} doesn't support conditional writes, pass --no-lock to write to it anyway",
+                    self
+                )))
+                }
+            };
+            if self.put(journal.path(), condition)? {
+                return Ok(entries);
+            }
+
+            if Instant::now() >= deadline {
+                return Err(Error::LockTimeout(opts.lock_timeout));
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+    }
+
+    /// Downloads the whole journal to a temporary file, which is empty if the
+    /// journal doesn't exist yet.
+    pub fn download(&self) -> Result<NamedTempFile> {
+        Ok(self.fetch()?.0)
+    }
+
+    /// Opens the journal for reading a block at a time, for passing to
+    /// `Entries::new` in a `BufReader`.
+    pub fn reader(&self) -> Result<ObjectReader> {
+        let mut reader = ObjectReader {
+            store: self.clone(),
+            etag: None,
+            len: 0,
+            pos: 0,
+            blocks: HashMap::new(),
+        };
+        reader.fetch_block(0)?;
+        Ok(reader)
+    }
+
+    fn fetch(&self) -> Result<(NamedTempFile, Version)> {
+        let res = self.request("GET", &[], None)?;
+        let version = match res.status {
+            404 => Version::Missing,
+            200 => res.etag(),
+            _ => return Err(res.error("GET", self)),
+        };
+
+        let mut journal = NamedTempFile::new()?;
+        if version != Version::Missing {
+            journal.write_all(&res.body)?;
+        }
+        Ok((journal, version))
+    }
+
+    // Returns whether the journal was written, which it isn't if `condition`
+    // didn't hold.
+    fn put(&self, journal: &Path, condition: Option<String>) -> Result<bool> {
+        let headers: Vec<String> = condition.into_iter().collect();
+        let res = self.request("PUT", &headers, Some(journal))?;
+        match res.status {
+            200..=299 => Ok(true),
+            // S3 returns 409 when a conditional write races with another.
+            409 | 412 => Ok(false),
+            _ => Err(res.error("PUT", self)),
+        }
+    }
+
+    fn url(&self) -> String {
+        match &self.service {
+            Service::S3 {
+                bucket,
+                key,
+                endpoint: Some(endpoint),
+                ..
+            } => format!("{}/{}/{}", endpoint.trim_end_matches('/'), bucket, key),
+            Service::S3 {
+                bucket,
+                key,
+                region,
+                endpoint: None,
+            } => format!("https://{}.s3.{}.amazonaws.com/{}", bucket, region, key),
+            Service::WebDav { url } => url.clone(),
+        }
+    }
+
+    // Options that include credentials are given to curl on stdin, rather than
+    // as arguments that anyone on the machine could see.
+    fn curl_config(&self) -> Result<String> {
+        match &self.service {
+            Service::S3 { region, .. } => {
+                let var = |name| {
+                    env::var(name).map_err(|_| {
+                        Error::InvalidArgument(format!("{} needs to be set to use {}", name, self))
+                    })
+                };
+                let mut config = format!(
+                    "aws-sigv4 = {}\nuser = {}\n",
+                    curl::quote(&format!("aws:amz:{}:s3", region)),
+                    curl::quote(&format!(
+                        "{}:{}",
+                        var("AWS_ACCESS_KEY_ID")?,
+                        var("AWS_SECRET_ACCESS_KEY")?
+                    ))
+                );
+                if let Ok(token) = env::var("AWS_SESSION_TOKEN") {
+                    config.push_str(&format!(
+                        "header = {}\n",
+                        curl::quote(&format!("x-amz-security-token: {}", token))
+                    ));
+                }
+                Ok(config)
+            }
+            Service::WebDav { .. } => Ok("netrc-optional\n".to_owned()),
+        }
+    }
+
+    fn request(&self, method: &str, headers: &[String], upload: Option<&Path>) -> Result<Response> {
+        let body = NamedTempFile::new()?;
+
+        let mut cmd = curl::command();
+        cmd.args(["--dump-header", "-", "--output"])
+            .arg(body.path())
+            .args(["--request", method]);
+        for header in headers {
+            cmd.arg("--header").arg(header);
+        }
+        if let Some(upload) = upload {
+            cmd.arg("--upload-file").arg(upload);
+        }
+        cmd.arg(self.url());
+
+        let out = curl::run(&mut cmd, &self.curl_config()?)
+            .map_err(|e| Error::Remote(format!("couldn't run curl: {}", e)))?;
+        if !out.status.success() {
+            return Err(Error::Remote(format!(
+                "couldn't reach {}: {}",
+                self,
+                String::from_utf8_lossy(&out.stderr).trim()
+            )));
+        }
+
+        let mut res = Response::parse(&String::from_utf8_lossy(&out.stdout)).ok_or_else(|| {
+            Error::Remote(format!("couldn't understand the response from {}", self))
+        })?;
+        res.body = fs::read(body.path())?;
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Version {
+    Missing,
+    ETag(String),
+    Unknown,
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    // curl writes the headers of every response it gets, including any
+    // "100 Continue" before the real one, so only the last set is kept.
+    fn parse(headers: &str) -> Option<Response> {
+        let mut res = None;
+        for line in headers.lines() {
+            if line.starts_with("HTTP/") {
+                let status = line.split_whitespace().nth(1)?.parse().ok()?;
+                res = Some(Response {
+                    status,
+                    headers: Vec::new(),
+                    body: Vec::new(),
+                });
+            } else if let (Some(res), Some((name, value))) = (res.as_mut(), line.split_once(':')) {
+                res.headers
+                    .push((name.trim().to_lowercase(), value.trim().to_owned()));
+            }
+        }
+        res
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn etag(&self) -> Version {
+        match self.header("etag") {
+            Some(etag) => Version::ETag(etag.to_owned()),
+            None => Version::Unknown,
+        }
+    }
+
+    // The total length of the object from a header like "bytes 0-99/1234".
+    fn total_len(&self) -> Option<u64> {
+        self.header("content-range")?
+            .rsplit_once('/')?
+            .1
+            .parse()
+            .ok()
+    }
+
+    fn error(&self, method: &str, store: &ObjectStore) -> Error {
+        let body = String::from_utf8_lossy(&self.body);
+        let mut message = format!("{} {} failed with status {}", method, store, self.status);
+        if !body.trim().is_empty() {
+            message.push_str(": ");
+            message.push_str(&body.trim().chars().take(200).collect::<String>());
+        }
+        Error::Remote(message)
+    }
+}
+
+/// Reads a journal in object storage with range requests, fetching each block
+/// of it the first time it's needed and keeping it for any later reads. If the
+/// journal changes part way through reading it, reads fail rather than mixing
+/// up the old and new versions.
+pub struct ObjectReader {
+    store: ObjectStore,
+    etag: Option<String>,
+    len: u64,
+    pos: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+}
+
+impl ObjectReader {
+    fn fetch_block(&mut self, block: u64) -> Result<()> {
+        let start = block * BLOCK_SIZE;
+        let mut headers = vec![format!("Range: bytes={}-{}", start, start + BLOCK_SIZE - 1)];
+        if let Some(ref etag) = self.etag {
+            headers.push(format!("If-Match: {}", etag));
+        }
+
+        let res = self.store.request("GET", &headers, None)?;
+        match res.status {
+            206 => {
+                if self.etag.is_none() {
+                    self.len = res.total_len().ok_or_else(|| {
+                        Error::Remote(format!("{} didn't say how long the journal is", self.store))
+                    })?;
+                    self.etag = res.header("etag").map(str::to_owned);
+                }
+                self.blocks.insert(block, res.body);
+            }
+            // Servers that don't support ranges send the whole thing.
+            200 => {
+                self.len = res.body.len() as u64;
+                self.etag = res.header("etag").map(str::to_owned);
+                for (i, chunk) in res.body.chunks(BLOCK_SIZE as usize).enumerate() {
+                    self.blocks.insert(i as u64, chunk.to_vec());
+                }
+            }
+            404 | 416 if self.etag.is_none() => self.len = 0,
+            412 => {
+                return Err(Error::Remote(format!(
+                    "{} changed while it was being read, please try again",
+                    self.store
+                )))
+            }
+            _ => return Err(res.error("GET", &self.store)),
+        }
+        Ok(())
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block = self.pos / BLOCK_SIZE;
+        if !self.blocks.contains_key(&block) {
+            self.fetch_block(block)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+        }
+
+        let data = self.blocks.get(&block).map_or(&[][..], |b| &b[..]);
+        let offset = (self.pos - block * BLOCK_SIZE) as usize;
+        let n = data.len().saturating_sub(offset).min(buf.len());
+        buf[..n].copy_from_slice(&data[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ObjectReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::entries::Entries;
+    use chrono::DateTime;
+    use std::io::BufReader;
+    use std::sync::{Arc, Mutex};
+    use test_case::test_case;
+    use tiny_http::{Header, Response as HttpResponse, Server};
+
+    #[derive(Default)]
+    struct Object {
+        contents: Option<Vec<u8>>,
+        version: u64,
+
+        // How many of the next writes to reject as if something else had
+        // written first.
+        conflicts: u64,
+
+        // How many bytes of the object have been sent.
+        sent: u64,
+    }
+
+    // Runs a server that stores a single object, enough like S3 and WebDAV
+    // servers to test against, returning its address and the object.
+    fn serve(contents: Option<&str>) -> (String, Arc<Mutex<Object>>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap().to_string();
+        let object = Arc::new(Mutex::new(Object {
+            contents: contents.map(|c| c.as_bytes().to_vec()),
+            ..Object::default()
+        }));
+
+        let shared = object.clone();
+        thread::spawn(move || {
+            for mut req in server.incoming_requests() {
+                let header = |name: &'static str| {
+                    req.headers()
+                        .iter()
+                        .find(|h| h.field.equiv(name))
+                        .map(|h| h.value.as_str().to_owned())
+                };
+                let (if_match, if_none_match, range) =
+                    (header("If-Match"), header("If-None-Match"), header("Range"));
+
+                let mut object = shared.lock().unwrap();
+                let etag = format!("\"{}\"", object.version);
+                let put = req.method().as_str() == "PUT";
+                let conflict = put && object.conflicts > 0;
+                let precondition_failed = conflict
+                    || match (&object.contents, &if_match, &if_none_match) {
+                        (Some(_), _, Some(_)) | (None, Some(_), _) => true,
+                        (_, Some(m), _) => *m != etag,
+                        _ => false,
+                    };
+
+                let res = if precondition_failed {
+                    if conflict {
+                        object.conflicts -= 1;
+                    }
+                    HttpResponse::from_data(Vec::new()).with_status_code(412)
+                } else if put {
+                    let mut body = Vec::new();
+                    req.as_reader().read_to_end(&mut body).unwrap();
+                    object.contents = Some(body);
+                    object.version += 1;
+                    HttpResponse::from_data(Vec::new())
+                } else if let Some(data) = object.contents.clone() {
+                    let (start, end) = match range.as_deref().and_then(parse_range) {
+                        Some((start, end)) => (start, (end + 1).min(data.len())),
+                        None => (0, data.len()),
+                    };
+                    let etag = Header::from_bytes("ETag", etag.as_bytes()).unwrap();
+                    if range.is_none() {
+                        object.sent += data.len() as u64;
+                        HttpResponse::from_data(data).with_header(etag)
+                    } else if start >= data.len() {
+                        HttpResponse::from_data(Vec::new()).with_status_code(416)
+                    } else {
+                        object.sent += (end - start) as u64;
+                        let range = format!("bytes {}-{}/{}", start, end - 1, data.len());
+                        HttpResponse::from_data(data[start..end].to_vec())
+                            .with_status_code(206)
+                            .with_header(etag)
+                            .with_header(
+                                Header::from_bytes("Content-Range", range.as_bytes()).unwrap(),
+                            )
+                    }
+                } else {
+                    HttpResponse::from_data(Vec::new()).with_status_code(404)
+                };
+                drop(object);
+                req.respond(res).unwrap();
+            }
+        });
+
+        (addr, object)
+    }
+
+    fn parse_range(range: &str) -> Option<(usize, usize)> {
+        let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+        Some((start.parse().ok()?, end.parse().ok()?))
+    }
+
+    fn dav(addr: &str) -> ObjectStore {
+        format!("dav://{}/journal.hmm", addr).parse().unwrap()
+    }
+
+    fn messages(store: &ObjectStore) -> Vec<String> {
+        Entries::new(BufReader::new(store.reader().unwrap()))
+            .map(|e| e.unwrap().message().to_owned())
+            .collect()
+    }
+
+    fn journal(n: i64) -> String {
+        let start = DateTime::parse_from_rfc3339("2020-01-01T00:00:00+00:00").unwrap();
+        (0..n)
+            .map(|i| {
+                Entry::new(start + chrono::Duration::minutes(i), format!("entry {}", i))
+                    .to_csv_row()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test_case("s3://bucket/journal.hmm"       => "s3 bucket journal.hmm"          ; "s3")]
+    #[test_case("s3://bucket/dir/journal.hmm"   => "s3 bucket dir/journal.hmm"      ; "s3 with dirs")]
+    #[test_case("dav://example.com/j.hmm"       => "http://example.com/j.hmm"       ; "dav")]
+    #[test_case("davs://example.com:8443/j.hmm" => "https://example.com:8443/j.hmm" ; "davs")]
+    fn test_parse(s: &str) -> String {
+        match s.parse::<ObjectStore>().unwrap().service {
+            Service::S3 { bucket, key, .. } => format!("s3 {} {}", bucket, key),
+            Service::WebDav { url } => url,
+        }
+    }
+
+    #[test_case("s3://bucket"        ; "no key")]
+    #[test_case("s3:///journal.hmm"  ; "no bucket")]
+    #[test_case("dav://example.com"  ; "no path")]
+    #[test_case("ftp://example.com/" ; "unknown scheme")]
+    fn test_parse_invalid(s: &str) {
+        assert!(s.parse::<ObjectStore>().is_err());
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let (addr, object) = serve(None);
+        let store = dav(&addr);
+        assert!(messages(&store).is_empty());
+
+        store.append("hello", &AppendOptions::default()).unwrap();
+        store.append("world", &AppendOptions::default()).unwrap();
+
+        assert_eq!(messages(&store), vec!["hello", "world"]);
+        assert_eq!(object.lock().unwrap().version, 2);
+    }
+
+    #[test]
+    fn test_append_retries_on_conflict() {
+        let (addr, object) = serve(Some(""));
+        object.lock().unwrap().conflicts = 3;
+        let store = dav(&addr);
+
+        store.append("hello", &AppendOptions::default()).unwrap();
+
+        assert_eq!(messages(&store), vec!["hello"]);
+        assert_eq!(object.lock().unwrap().conflicts, 0);
+    }
+
+    #[test]
+    fn test_append_gives_up() {
+        let (addr, object) = serve(Some(""));
+        object.lock().unwrap().conflicts = u64::MAX;
+        let opts = AppendOptions {
+            lock_timeout: Duration::from_millis(250),
+            ..AppendOptions::default()
+        };
+
+        assert!(matches!(
+            dav(&addr).append("hello", &opts),
+            Err(Error::LockTimeout(_))
+        ));
+    }
+
+    #[test]
+    fn test_reads_only_what_it_needs() {
+        let contents = journal(20_000);
+        let (addr, object) = serve(Some(&contents));
+
+        let mut entries = Entries::new(BufReader::new(dav(&addr).reader().unwrap()));
+        let last = entries.next_back().unwrap().unwrap();
+        assert_eq!(last.message(), "entry 19999");
+
+        let sent = object.lock().unwrap().sent;
+        assert!(sent <= 2 * BLOCK_SIZE, "{} of {}", sent, contents.len());
+    }
+
+    #[test]
+    fn test_read_fails_if_changed() {
+        let (addr, object) = serve(Some(&journal(20_000)));
+        let mut reader = dav(&addr).reader().unwrap();
+
+        object.lock().unwrap().version += 1;
+        reader.seek(SeekFrom::End(-1)).unwrap();
+        let err = reader.read(&mut [0; 1]).unwrap_err();
+        assert!(err.to_string().contains("changed"), "{}", err);
+    }
+
+    #[test]
+    fn test_s3() {
+        let (addr, _) = serve(None);
+        let store = ObjectStore {
+            name: "s3://bucket/journal.hmm".to_owned(),
+            service: Service::S3 {
+                bucket: "bucket".to_owned(),
+                key: "journal.hmm".to_owned(),
+                region: "eu-west-1".to_owned(),
+                endpoint: Some(format!("http://{}", addr)),
+            },
+        };
+        assert_eq!(store.url(), format!("http://{}/bucket/journal.hmm", addr));
+
+        env::set_var("AWS_ACCESS_KEY_ID", "key");
+        env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+        store.append("hello", &AppendOptions::default()).unwrap();
+        assert_eq!(messages(&store), vec!["hello"]);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let err = dav("127.0.0.1:1").download().unwrap_err();
+        assert_eq!(err.exit_code(), 69);
+    }
+}
@@ -0,0 +1,227 @@
+use super::{entry::Entry, error::Error, text, Result};
+use chrono::prelude::*;
+use std::io::Write;
+use std::str::FromStr;
+
+/// The characters a table's borders are drawn with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// Unicode box drawing characters.
+    #[default]
+    Unicode,
+
+    /// Plain ASCII, for terminals and fonts that don't have box drawing
+    /// characters.
+    Ascii,
+}
+
+impl FromStr for Style {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "unicode" => Ok(Style::Unicode),
+            "ascii" => Ok(Style::Ascii),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown table style \"{}\", expected unicode or ascii",
+                s
+            ))),
+        }
+    }
+}
+
+// The pieces a border is made of: a horizontal line, the left, middle and
+// right joints of the top, middle and bottom borders, and a vertical line.
+struct Borders {
+    line: &'static str,
+    top: [&'static str; 3],
+    middle: [&'static str; 3],
+    bottom: [&'static str; 3],
+    side: &'static str,
+    ellipsis: &'static str,
+}
+
+impl Style {
+    fn borders(&self) -> Borders {
+        match self {
+            Style::Unicode => Borders {
+                line: "─",
+                top: ["┌", "┬", "┐"],
+                middle: ["├", "┼", "┤"],
+                bottom: ["└", "┴", "┘"],
+                side: "│",
+                ellipsis: "…",
+            },
+            Style::Ascii => Borders {
+                line: "-",
+                top: ["+", "+", "+"],
+                middle: ["+", "+", "+"],
+                bottom: ["+", "+", "+"],
+                side: "|",
+                ellipsis: "...",
+            },
+        }
+    }
+}
+
+const HEADINGS: [&str; 3] = ["Time", "Tags", "Message"];
+
+// Tags never take up more than this fraction of the table, so that there's
+// always room left for messages.
+const MAX_TAGS_FRACTION: usize = 4;
+
+/// Writes `entries` to `w` as a table with a row for each entry, showing its
+/// time, its tags and its message. The table is made to fit in `width`
+/// columns, cutting off messages and tags that don't fit, and messages are
+/// put on a single line.
+pub fn write(mut w: impl Write, entries: &[Entry], style: Style, width: usize) -> Result<()> {
+    let borders = style.borders();
+    let rows: Vec<[String; 3]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry
+                    .datetime()
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+                entry
+                    .tags()
+                    .iter()
+                    .map(|tag| format!("#{}", tag))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+                entry
+                    .message()
+                    .split_whitespace()
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            ]
+        })
+        .collect();
+
+    let widths = column_widths(&rows, width);
+    let rule = |joints: [&str; 3]| {
+        let lines: Vec<String> = widths.iter().map(|w| borders.line.repeat(w + 2)).collect();
+        format!("{}{}{}", joints[0], lines.join(joints[1]), joints[2])
+    };
+    let row = |cells: &[String; 3]| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| text::pad(&text::truncate(cell, width, borders.ellipsis), width))
+            .collect();
+        let separator = format!(" {} ", borders.side);
+        format!(
+            "{} {} {}",
+            borders.side,
+            cells.join(&separator),
+            borders.side
+        )
+    };
+
+    writeln!(w, "{}", rule(borders.top))?;
+    writeln!(w, "{}", row(&HEADINGS.map(str::to_owned)))?;
+    writeln!(w, "{}", rule(borders.middle))?;
+    for cells in &rows {
+        writeln!(w, "{}", row(cells))?;
+    }
+    writeln!(w, "{}", rule(borders.bottom))?;
+    Ok(())
+}
+
+// Each column is as wide as the widest thing in it, but the tags and message
+// columns are narrowed until the whole table, borders and all, fits in
+// `width`. Times are never cut off, and no column is narrower than its
+// heading, so very narrow terminals get a table that's wider than they are.
+fn column_widths(rows: &[[String; 3]], width: usize) -> [usize; 3] {
+    let min = HEADINGS.map(text::display_width);
+    let mut widths = min;
+    for cells in rows {
+        for (w, cell) in widths.iter_mut().zip(cells.iter()) {
+            *w = (*w).max(text::display_width(cell));
+        }
+    }
+
+    // Each column has a space either side of it and a border after it, and
+    // there's a border at the very start.
+    let available = width.saturating_sub(widths[0] + 3 * HEADINGS.len() + 1);
+    widths[1] = widths[1]
+        .min((width / MAX_TAGS_FRACTION).max(min[1]))
+        .min(available.saturating_sub(min[2]).max(min[1]));
+    widths[2] = widths[2].min(available.saturating_sub(widths[1]).max(min[2]));
+    widths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+        Entry::new(
+            Local.from_local_datetime(&datetime).unwrap().fixed_offset(),
+            message.to_owned(),
+        )
+    }
+
+    fn table(style: Style, width: usize) -> String {
+        let entries = vec![
+            entry("2024-05-12 09:00", "standup #work"),
+            entry(
+                "2024-05-12 12:30",
+                "lunch with #friends at the new place\nit was good",
+            ),
+        ];
+        let mut out = Vec::new();
+        write(&mut out, &entries, style, width).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write() {
+        assert_eq!(
+            table(Style::Unicode, 80),
+            "\
+┌──────────────────┬──────────┬────────────────────────────────────────────────┐
+│ Time             │ Tags     │ Message                                        │
+├──────────────────┼──────────┼────────────────────────────────────────────────┤
+│ 2024-05-12 09:00 │ #work    │ standup #work                                  │
+│ 2024-05-12 12:30 │ #friends │ lunch with #friends at the new place it was g… │
+└──────────────────┴──────────┴────────────────────────────────────────────────┘
+"
+        );
+    }
+
+    #[test]
+    fn test_write_ascii() {
+        assert_eq!(
+            table(Style::Ascii, 50),
+            "\
++------------------+----------+------------------+
+| Time             | Tags     | Message          |
++------------------+----------+------------------+
+| 2024-05-12 09:00 | #work    | standup #work    |
+| 2024-05-12 12:30 | #friends | lunch with #f... |
++------------------+----------+------------------+
+"
+        );
+    }
+
+    #[test_case(20  ; "narrower than the times")]
+    #[test_case(60  ; "narrow")]
+    #[test_case(200 ; "wide")]
+    fn test_write_fits(width: usize) {
+        let fits = table(Style::Unicode, width)
+            .lines()
+            .all(|line| text::display_width(line) <= width.max(37));
+        assert!(fits);
+    }
+
+    #[test_case("unicode" => Style::Unicode)]
+    #[test_case("ascii"   => Style::Ascii)]
+    fn test_style_from_str(s: &str) -> Style {
+        s.parse().unwrap()
+    }
+}
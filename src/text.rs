@@ -0,0 +1,323 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Splits `s` in to the smallest pieces that can be measured and moved around
+/// on their own, along with how many terminal columns each one takes up.
+/// Pieces are either grapheme clusters, so that things like flags, emoji with
+/// skin tones and letters with combining accents are never split apart, or
+/// ANSI escape sequences, which don't take up any columns at all.
+fn units(s: &str) -> Vec<(&str, usize)> {
+    let mut units = Vec::new();
+    let mut rest = s;
+
+    while !rest.is_empty() {
+        let len = match escape_len(rest) {
+            Some(len) => len,
+            None => rest.graphemes(true).next().map_or(rest.len(), str::len),
+        };
+        let (unit, tail) = rest.split_at(len);
+        units.push((unit, unit_width(unit)));
+        rest = tail;
+    }
+
+    units
+}
+
+// The length in bytes of the ANSI escape sequence at the start of `s`, if
+// there is one. We only care about CSI sequences, which is what the color and
+// markdown helpers produce.
+fn escape_len(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 2 || bytes[0] != 0x1b || bytes[1] != b'[' {
+        return None;
+    }
+
+    bytes[2..]
+        .iter()
+        .position(|b| (0x40..=0x7e).contains(b))
+        .map(|i| i + 3)
+}
+
+fn unit_width(unit: &str) -> usize {
+    if unit.starts_with('\x1b') {
+        return 0;
+    }
+
+    // A grapheme cluster is drawn as a single character, so however many code
+    // points went in to it it can't be wider than a double-width character.
+    // Summing the widths of its code points would count an emoji ZWJ sequence
+    // like 👩‍💻 as 4 columns, for example.
+    unit.width().min(2)
+}
+
+/// The number of terminal columns `s` takes up when printed. CJK characters
+/// and most emoji take up two columns, combining characters take up none, and
+/// ANSI escape sequences are ignored.
+pub fn display_width(s: &str) -> usize {
+    units(s).iter().map(|(_, w)| w).sum()
+}
+
+/// Wraps `s` so that no line is wider than `width` columns, measuring each
+/// line by its display width rather than its length in bytes. Existing line
+/// breaks are kept and lines that already fit are left alone. Words longer
+/// than `width` are broken between grapheme clusters.
+pub fn wrap(s: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+
+    for paragraph in s.split('\n') {
+        if display_width(paragraph) <= width {
+            lines.push(paragraph.to_owned());
+            continue;
+        }
+
+        let mut line = String::new();
+        let mut line_width = 0;
+
+        for word in paragraph.split_whitespace() {
+            let word_width = display_width(word);
+
+            if line_width > 0 && line_width + 1 + word_width > width {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0;
+            }
+
+            if line_width > 0 {
+                line.push(' ');
+                line_width += 1;
+            }
+
+            if word_width <= width - line_width {
+                line.push_str(word);
+                line_width += word_width;
+                continue;
+            }
+
+            for (unit, unit_width) in units(word) {
+                if line_width > 0 && line_width + unit_width > width {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                }
+                line.push_str(unit);
+                line_width += unit_width;
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Puts `prefix` at the start of every line in `s`, the same way
+/// textwrap::indent does. If a `width` is given, the lines are wrapped first
+/// so that they still fit within `width` columns once the prefix has been
+/// added.
+pub fn indent(s: &str, prefix: &str, width: Option<usize>) -> String {
+    let width = match width {
+        Some(width) => width,
+        None => return textwrap::indent(s, prefix),
+    };
+
+    let body = s.strip_suffix('\n').unwrap_or(s);
+    let mut wrapped = wrap(body, width.saturating_sub(display_width(prefix))).join("\n");
+    if s.ends_with('\n') {
+        wrapped.push('\n');
+    }
+    textwrap::indent(&wrapped, prefix)
+}
+
+/// Removes ANSI escape sequences from `s`, leaving just the text that would be
+/// visible in a terminal.
+pub fn strip_ansi(s: &str) -> String {
+    units(s)
+        .into_iter()
+        .filter(|(unit, _)| !unit.starts_with('\x1b'))
+        .map(|(unit, _)| unit)
+        .collect()
+}
+
+/// Pads `s` with spaces on the right until it's `width` columns wide. Strings
+/// that are already at least that wide are returned unchanged.
+pub fn pad(s: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(s));
+    format!("{}{}", s, " ".repeat(padding))
+}
+
+/// Shortens `s` so that it's no wider than `width` columns, ending it with
+/// `ellipsis` if anything had to be cut off. Strings that already fit are
+/// returned unchanged.
+pub fn truncate(s: &str, width: usize, ellipsis: &str) -> String {
+    if display_width(s) <= width {
+        return s.to_owned();
+    }
+
+    let width = width.saturating_sub(display_width(ellipsis));
+    let mut truncated = String::new();
+    let mut truncated_width = 0;
+    for (unit, unit_width) in units(s) {
+        if truncated_width + unit_width > width {
+            break;
+        }
+        truncated.push_str(unit);
+        truncated_width += unit_width;
+    }
+    truncated.push_str(ellipsis);
+    truncated
+}
+
+/// Removes lines starting with `prefix` from `s`, like git does with commit
+/// messages, along with any blank lines left at the start or end.
+pub fn strip_comments(s: &str, prefix: &str) -> String {
+    let lines: Vec<&str> = s.lines().filter(|line| !line.starts_with(prefix)).collect();
+    lines.join("\n").trim_matches(&['\r', '\n'][..]).to_owned()
+}
+
+/// How many words a minute reading_time assumes people read at.
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// The number of words in `s`, counting anything separated by whitespace as a
+/// word.
+pub fn word_count(s: &str) -> usize {
+    s.split_whitespace().count()
+}
+
+/// Roughly how long `s` takes to read, rounded up to the next whole minute.
+/// Anything with words in it takes at least a minute.
+pub fn reading_time(s: &str) -> chrono::Duration {
+    let minutes = word_count(s).div_ceil(WORDS_PER_MINUTE);
+    chrono::Duration::minutes(minutes as i64)
+}
+
+/// Replaces GitHub and Slack style shortcodes like `:rocket:` in `s` with the
+/// emoji they stand for. Anything between colons that isn't a shortcode, like
+/// the time in "meet at 10:30:", is left alone.
+pub fn emojify(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(':') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let emoji = after.find(':').and_then(|end| {
+            let code = &after[..end];
+            let valid = !code.is_empty()
+                && code
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+');
+            let emoji = emojis::get_by_shortcode(code).filter(|_| valid)?;
+            Some((emoji, end))
+        });
+        match emoji {
+            Some((emoji, end)) => {
+                out.push_str(emoji.as_str());
+                rest = &after[end + 1..];
+            }
+            // The closing colon might be the start of a real shortcode, so
+            // carry on from it.
+            None => {
+                out.push(':');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("hello"          => 5  ; "ascii")]
+    #[test_case("héllo"          => 5  ; "precomposed accent")]
+    #[test_case("he\u{301}llo"   => 5  ; "combining accent")]
+    #[test_case("日本語"         => 6  ; "cjk")]
+    #[test_case("🎉"             => 2  ; "emoji")]
+    #[test_case("👩‍💻"             => 2  ; "emoji zwj sequence")]
+    #[test_case("🇬🇧"             => 2  ; "flag")]
+    #[test_case("\x1b[34mhi\x1b[0m" => 2 ; "ansi escapes")]
+    #[test_case(""               => 0  ; "empty")]
+    fn test_display_width(s: &str) -> usize {
+        display_width(s)
+    }
+
+    #[test_case("hello world", 5      => vec!["hello", "world"]        ; "ascii")]
+    #[test_case("hello world", 20     => vec!["hello world"]           ; "fits")]
+    #[test_case("日本語 日本語", 6    => vec!["日本語", "日本語"]      ; "cjk words")]
+    #[test_case("日本語日本語", 5     => vec!["日本", "語日", "本語"]  ; "cjk without spaces")]
+    #[test_case("🎉🎉🎉", 4           => vec!["🎉🎉", "🎉"]            ; "emoji")]
+    #[test_case("👩‍💻👩‍💻", 2             => vec!["👩‍💻", "👩‍💻"]             ; "zwj sequences aren't split")]
+    #[test_case("he\u{301}llo", 2     => vec!["he\u{301}", "ll", "o"]  ; "combining accents aren't split")]
+    #[test_case("a\nb c", 10          => vec!["a", "b c"]              ; "keeps line breaks")]
+    #[test_case("  a  b", 10          => vec!["  a  b"]                ; "leaves lines that fit alone")]
+    #[test_case("", 10                => vec![""]                      ; "empty")]
+    fn test_wrap(s: &str, width: usize) -> Vec<String> {
+        wrap(s, width)
+    }
+
+    #[test_case("hello\nworld", None      => "│ hello\n│ world"       ; "no width")]
+    #[test_case("hello world\n", Some(9)  => "│ hello\n│ world\n"    ; "wraps to fit prefix")]
+    #[test_case("日本語 日本語", Some(8)  => "│ 日本語\n│ 日本語"   ; "cjk")]
+    fn test_indent(s: &str, width: Option<usize>) -> String {
+        indent(s, "│ ", width)
+    }
+
+    #[test_case("\x1b[34mhi\x1b[0m"      => "hi"            ; "color")]
+    #[test_case("\x1b[1;4m日本\x1b[0m語"  => "日本語"        ; "bold underline")]
+    #[test_case("plain"                  => "plain"         ; "plain")]
+    fn test_strip_ansi(s: &str) -> String {
+        strip_ansi(s)
+    }
+
+    #[test_case("# hi\nhello\n# there\nworld\n", "#"  => "hello\nworld"        ; "comments")]
+    #[test_case("What went well?\n\n#\n\n", "#"       => "What went well?"     ; "trailing blank lines")]
+    #[test_case("  # not a comment\n", "#"             => "  # not a comment"   ; "indented")]
+    #[test_case("## heading\n; note\n", ";"           => "## heading"          ; "other prefix")]
+    #[test_case("# only comments\n", "#"               => ""                    ; "only comments")]
+    fn test_strip_comments(s: &str, prefix: &str) -> String {
+        strip_comments(s, prefix)
+    }
+
+    #[test_case("ab", 4     => "ab  "   ; "ascii")]
+    #[test_case("日本", 5   => "日本 "  ; "cjk")]
+    #[test_case("hello", 2  => "hello"  ; "already too wide")]
+    fn test_pad(s: &str, width: usize) -> String {
+        pad(s, width)
+    }
+
+    #[test_case("hello", 5, "…"     => "hello"  ; "fits")]
+    #[test_case("hello world", 8, "…" => "hello w…" ; "unicode ellipsis")]
+    #[test_case("hello world", 8, "..." => "hello..." ; "ascii ellipsis")]
+    #[test_case("日本語です", 6, "…"  => "日本…"  ; "cjk")]
+    #[test_case("hello", 0, "…"     => "…"      ; "no room")]
+    fn test_truncate(s: &str, width: usize, ellipsis: &str) -> String {
+        truncate(s, width, ellipsis)
+    }
+
+    #[test_case(""                      => 0 ; "empty")]
+    #[test_case("  hello \n\tworld  "   => 2 ; "whitespace")]
+    #[test_case("#work don't forget"    => 3 ; "punctuation")]
+    fn test_word_count(s: &str) -> usize {
+        word_count(s)
+    }
+
+    #[test_case(0   => 0 ; "empty")]
+    #[test_case(1   => 1 ; "one word")]
+    #[test_case(200 => 1 ; "a minute")]
+    #[test_case(201 => 2 ; "rounds up")]
+    fn test_reading_time(words: usize) -> i64 {
+        reading_time(&"word ".repeat(words)).num_minutes()
+    }
+
+    #[test_case("shipped it :rocket:"         => "shipped it 🚀"        ; "shortcode")]
+    #[test_case(":+1::tada:"                  => "👍🎉"                  ; "adjacent")]
+    #[test_case("meet at 10:30 :coffee:"      => "meet at 10:30 ☕"     ; "colon before a shortcode")]
+    #[test_case(":not_an_emoji: :smile:"      => ":not_an_emoji: 😄"    ; "unknown shortcode")]
+    #[test_case("a: b :c d:"                  => "a: b :c d:"           ; "not shortcodes")]
+    #[test_case("::"                          => "::"                   ; "empty")]
+    #[test_case("ends with :rocket"           => "ends with :rocket"    ; "unclosed")]
+    fn test_emojify(s: &str) -> String {
+        emojify(s)
+    }
+}
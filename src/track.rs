@@ -0,0 +1,312 @@
+use super::{date, entry::Entry, error::Error, Result};
+use chrono::prelude::*;
+use chrono::Duration;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The tags that entries written by hmm --start and hmm --stop begin with.
+pub const START: &str = "#start";
+pub const STOP: &str = "#stop";
+
+/// The message of an entry that starts tracking time on `project`, like
+/// "#start #garden weeding". The project can be given with or without its #.
+pub fn start_message(project: &str, note: &str) -> Result<String> {
+    let project = project.strip_prefix('#').unwrap_or(project);
+    let tag = format!("#{}", project);
+    if Entry::with_message(&tag).tags() != [project] || tag == START || tag == STOP {
+        return Err(Error::InvalidArgument(format!(
+            "\"{}\" can't be used as a project, projects are tags made of letters, numbers, \"_\", \"-\" and \"/\"",
+            project
+        )));
+    }
+    Ok(with_note(&format!("{} {}", START, tag), note))
+}
+
+/// The message of an entry that stops tracking time on whatever project was
+/// started last.
+pub fn stop_message(note: &str) -> String {
+    with_note(STOP, note)
+}
+
+fn with_note(message: &str, note: &str) -> String {
+    match note.trim() {
+        "" => message.to_owned(),
+        note => format!("{} {}", message, note),
+    }
+}
+
+/// Time spent on a project, from the entry that started it to the one that
+/// stopped it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    project: String,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    running: bool,
+}
+
+impl Session {
+    pub fn project(&self) -> &str {
+        &self.project
+    }
+
+    pub fn start(&self) -> &DateTime<FixedOffset> {
+        &self.start
+    }
+
+    pub fn end(&self) -> &DateTime<FixedOffset> {
+        &self.end
+    }
+
+    /// Whether the session hadn't been stopped yet, in which case its end is
+    /// the time it was tracked until.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
+
+/// Pairs up start and stop entries in to sessions, reading entries in order.
+/// Starting a project stops the one that's running, if there is one, and stop
+/// entries are ignored when nothing's running.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    running: Option<(String, DateTime<FixedOffset>)>,
+    sessions: Vec<Session>,
+}
+
+impl Tracker {
+    pub fn push(&mut self, entry: &Entry) {
+        let mut words = entry.message().split_whitespace();
+        match words.next() {
+            Some(START) => {
+                let project = match words.next().and_then(|tag| tag.strip_prefix('#')) {
+                    Some(project) if !project.is_empty() => project,
+                    _ => return,
+                };
+                if matches!(self.running, Some((ref running, _)) if running == project) {
+                    return;
+                }
+                self.stop(*entry.datetime(), false);
+                self.running = Some((project.to_owned(), *entry.datetime()));
+            }
+            Some(STOP) => self.stop(*entry.datetime(), false),
+            _ => {}
+        }
+    }
+
+    /// The sessions that were found. A session that's still running is
+    /// counted up to `until`.
+    pub fn finish(mut self, until: DateTime<FixedOffset>) -> Vec<Session> {
+        self.stop(until, true);
+        self.sessions
+    }
+
+    fn stop(&mut self, end: DateTime<FixedOffset>, running: bool) {
+        if let Some((project, start)) = self.running.take() {
+            if end > start {
+                self.sessions.push(Session {
+                    project,
+                    start,
+                    end,
+                    running,
+                });
+            }
+        }
+    }
+}
+
+/// What time is added up by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum By {
+    /// Each project's tag.
+    Tag,
+
+    /// Each day, in local time. Sessions that go past midnight count towards
+    /// both days.
+    Day,
+}
+
+impl FromStr for By {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tag" => Ok(By::Tag),
+            "day" => Ok(By::Day),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown grouping \"{}\", expected tag or day",
+                s
+            ))),
+        }
+    }
+}
+
+/// Adds up the time spent in `sessions`, leaving out anything before `start`
+/// or from `end` onwards. Totals are sorted by the tag or day they're for.
+pub fn totals(
+    sessions: &[Session],
+    by: By,
+    start: Option<&DateTime<FixedOffset>>,
+    end: Option<&DateTime<FixedOffset>>,
+) -> Vec<(String, Duration)> {
+    let mut totals: BTreeMap<String, Duration> = BTreeMap::new();
+    let mut add = |key: String, from: DateTime<FixedOffset>, to: DateTime<FixedOffset>| {
+        let from = start.map_or(from, |start| from.max(*start));
+        let to = end.map_or(to, |end| to.min(*end));
+        if to > from {
+            *totals.entry(key).or_insert_with(Duration::zero) += to - from;
+        }
+    };
+
+    for session in sessions {
+        match by {
+            By::Tag => add(session.project.clone(), session.start, session.end),
+            By::Day => {
+                let mut from = session.start;
+                while from < session.end {
+                    let day = from.with_timezone(&Local).date_naive();
+                    let to = day.succ_opt().map_or(session.end, |next| {
+                        date::start_of_day(next).min(session.end)
+                    });
+                    add(day.to_string(), from, to);
+                    from = to;
+                }
+            }
+        }
+    }
+
+    totals.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn at(datetime: &str) -> DateTime<FixedOffset> {
+        let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+        Local.from_local_datetime(&datetime).unwrap().fixed_offset()
+    }
+
+    fn sessions(entries: &[(&str, &str)], until: &str) -> Vec<Session> {
+        let mut tracker = Tracker::default();
+        for (datetime, message) in entries {
+            tracker.push(&Entry::new(at(datetime), message.to_string()));
+        }
+        tracker.finish(at(until))
+    }
+
+    fn minutes(totals: Vec<(String, Duration)>) -> Vec<(String, i64)> {
+        totals
+            .into_iter()
+            .map(|(key, total)| (key, total.num_minutes()))
+            .collect()
+    }
+
+    #[test_case("garden", "" => "#start #garden" ; "project")]
+    #[test_case("#garden", "weeding" => "#start #garden weeding" ; "tag and note")]
+    #[test_case("work/hmm", " " => "#start #work/hmm" ; "blank note")]
+    fn test_start_message(project: &str, note: &str) -> String {
+        start_message(project, note).unwrap()
+    }
+
+    #[test_case("two words" ; "not a tag")]
+    #[test_case("" ; "empty")]
+    #[test_case("stop" ; "stop")]
+    fn test_start_message_errors(project: &str) {
+        assert_eq!(start_message(project, "").unwrap_err().exit_code(), 64);
+    }
+
+    #[test]
+    fn test_stop_message() {
+        assert_eq!(stop_message(""), "#stop");
+        assert_eq!(stop_message("done for the day"), "#stop done for the day");
+    }
+
+    #[test]
+    fn test_tracker() {
+        let sessions = sessions(
+            &[
+                ("2024-05-13 08:00", "#stop nothing running"),
+                ("2024-05-13 09:00", "#start #work"),
+                ("2024-05-13 09:30", "a note in the middle"),
+                ("2024-05-13 10:00", "#start #work again"),
+                ("2024-05-13 11:00", "#start #garden"),
+                ("2024-05-13 11:45", "#stop"),
+                ("2024-05-13 12:00", "#start without a project"),
+                ("2024-05-13 13:00", "I'll #start #work later"),
+                ("2024-05-13 14:00", "#start #work"),
+            ],
+            "2024-05-13 15:30",
+        );
+
+        let summary: Vec<(&str, i64, bool)> = sessions
+            .iter()
+            .map(|s| {
+                (
+                    s.project(),
+                    (*s.end() - *s.start()).num_minutes(),
+                    s.is_running(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("work", 120, false),
+                ("garden", 45, false),
+                ("work", 90, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_totals_by_tag() {
+        let sessions = sessions(
+            &[
+                ("2024-05-13 09:00", "#start #work"),
+                ("2024-05-13 11:00", "#start #garden"),
+                ("2024-05-13 12:00", "#start #work"),
+                ("2024-05-13 12:30", "#stop"),
+            ],
+            "2024-05-14 00:00",
+        );
+        assert_eq!(
+            minutes(totals(&sessions, By::Tag, None, None)),
+            vec![("garden".to_owned(), 60), ("work".to_owned(), 150)]
+        );
+        assert_eq!(
+            minutes(totals(
+                &sessions,
+                By::Tag,
+                Some(&at("2024-05-13 10:00")),
+                Some(&at("2024-05-13 12:15"))
+            )),
+            vec![("garden".to_owned(), 60), ("work".to_owned(), 75)]
+        );
+    }
+
+    #[test]
+    fn test_totals_by_day() {
+        let sessions = sessions(
+            &[
+                ("2024-05-13 22:00", "#start #oncall"),
+                ("2024-05-14 02:00", "#stop"),
+            ],
+            "2024-05-15 00:00",
+        );
+        assert_eq!(
+            minutes(totals(&sessions, By::Day, None, None)),
+            vec![
+                ("2024-05-13".to_owned(), 120),
+                ("2024-05-14".to_owned(), 120)
+            ]
+        );
+    }
+
+    #[test_case("tag" => By::Tag)]
+    #[test_case("day" => By::Day)]
+    fn test_by_from_str(s: &str) -> By {
+        s.parse().unwrap()
+    }
+}
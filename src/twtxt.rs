@@ -0,0 +1,74 @@
+use super::{entry::Entry, Result};
+use std::io::Write;
+
+// twtxt only allows a line per post, so newlines in messages are written as
+// Unicode's line separator, which is how clients that support multi-line
+// posts expect them.
+const LINE_SEPARATOR: char = '\u{2028}';
+
+/// Writes `entries` to `w` as a twtxt feed (https://twtxt.readthedocs.io), a
+/// line per entry of its RFC 3339 datetime, a tab, and its message. Feeds are
+/// read oldest first, so that's how they're written, whatever order `entries`
+/// are in.
+pub fn write(mut w: impl Write, entries: &[Entry]) -> Result<()> {
+    let mut entries: Vec<&Entry> = entries.iter().collect();
+    entries.sort_by_key(|entry| *entry.datetime());
+    for entry in entries {
+        writeln!(
+            w,
+            "{}\t{}",
+            entry.datetime().to_rfc3339(),
+            escape(entry.message())
+        )?;
+    }
+    Ok(())
+}
+
+fn escape(message: &str) -> String {
+    let mut lines = message.trim_end().lines().map(str::trim_end);
+    let mut out = lines.next().unwrap_or_default().to_owned();
+    for line in lines {
+        out.push(LINE_SEPARATOR);
+        out.push_str(line);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+    use test_case::test_case;
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        Entry::new(
+            DateTime::parse_from_rfc3339(datetime).unwrap(),
+            message.to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_write() {
+        let mut out = Vec::new();
+        write(
+            &mut out,
+            &[
+                entry("2024-05-13T12:30:00+01:00", "lunch\nwith sam\n"),
+                entry("2024-05-13T09:00:00+01:00", "standup #work"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2024-05-13T09:00:00+01:00\tstandup #work\n2024-05-13T12:30:00+01:00\tlunch\u{2028}with sam\n"
+        );
+    }
+
+    #[test_case("hello"                 => "hello"                      ; "one line")]
+    #[test_case("a\nb\r\nc"             => "a\u{2028}b\u{2028}c"        ; "newlines")]
+    #[test_case("a  \n\nb\n\n"          => "a\u{2028}\u{2028}b"         ; "trailing whitespace")]
+    #[test_case("tab\tseparated"        => "tab\tseparated"             ; "tabs")]
+    fn test_escape(message: &str) -> String {
+        escape(message)
+    }
+}
@@ -0,0 +1,289 @@
+//! A look back over everything written in a year, for hmms year.
+
+use super::{date, entry::Entry, format::GroupBy, review::Item, text};
+use chrono::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// The template hmms year renders with unless it's given another one.
+pub const DEFAULT_TEMPLATE: &str = "\
+# {{ year }} in review
+
+{{#if count}}
+{{ count }} entries and {{ words }} words over {{ day_count }} days.
+
+- Busiest day: {{ strftime \"%A %-d %B\" busiest_day.datetime }} ({{ busiest_day.count }} entries)
+- Busiest week: the week of {{ strftime \"%-d %B\" busiest_week.datetime }} ({{ busiest_week.count }} entries)
+{{#if tags}}
+- Most written about: {{#each tags}}#{{ tag }} ({{ count }}){{#unless @last}}, {{/unless}}{{/each}}
+{{/if}}
+{{#if mentions}}
+- Most mentioned: {{#each mentions}}@{{ mention }} ({{ count }}){{#unless @last}}, {{/unless}}{{/each}}
+{{/if}}
+- Longest entry: {{ longest.title }} ({{ longest.words }} words, {{ strftime \"%-d %B\" longest.datetime }})
+
+## First entry, {{ strftime \"%A %-d %B %H:%M\" first.datetime }}
+
+{{ first.message }}
+
+## Last entry, {{ strftime \"%A %-d %B %H:%M\" last.datetime }}
+
+{{ last.message }}
+{{else}}
+Nothing was written.
+{{/if}}
+";
+
+/// The totals and highlights of a year of entries, to be rendered with a
+/// template. Every datetime is RFC3339, for the strftime helper.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Year {
+    year: i32,
+
+    /// The start of the year, inclusive.
+    start: String,
+
+    /// The start of the next year, exclusive.
+    end: String,
+
+    /// How many entries were written.
+    count: usize,
+
+    /// How many words were written, across every entry.
+    words: usize,
+
+    /// How many days have entries.
+    day_count: usize,
+
+    /// The day with the most entries, the earliest if there's a tie.
+    busiest_day: Option<Period>,
+
+    /// The week, starting on Monday, with the most entries, the earliest if
+    /// there's a tie.
+    busiest_week: Option<Period>,
+
+    /// The most used tags, most used first.
+    tags: Vec<TagCount>,
+
+    /// The most mentioned people, most mentioned first.
+    mentions: Vec<MentionCount>,
+
+    /// The entry with the most words, the earliest if there's a tie.
+    longest: Option<Longest>,
+
+    first: Option<Item>,
+    last: Option<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Period {
+    datetime: String,
+    count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct MentionCount {
+    mention: String,
+    count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Longest {
+    words: usize,
+    #[serde(flatten)]
+    entry: Item,
+}
+
+impl Year {
+    /// Looks back over `entries`, which should all be from `year` and in
+    /// order. Only the `top` most used tags and mentions are kept. Panics if
+    /// `year` is before 1 or after 9998, which dates can't be written for.
+    pub fn new(year: i32, entries: &[Entry], top: usize) -> Year {
+        let mut days: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+        let mut weeks: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+        let mut tags: HashMap<&str, usize> = HashMap::new();
+        let mut mentions: HashMap<&str, usize> = HashMap::new();
+        let mut words = 0;
+        let mut longest: Option<Longest> = None;
+
+        for entry in entries {
+            *days
+                .entry(GroupBy::Day.period(entry.datetime()))
+                .or_default() += 1;
+            *weeks
+                .entry(GroupBy::Week.period(entry.datetime()))
+                .or_default() += 1;
+
+            // Entries are only counted once for each tag or mention in them.
+            let mut entry_tags = entry.tags();
+            entry_tags.sort_unstable();
+            entry_tags.dedup();
+            for tag in entry_tags {
+                *tags.entry(tag).or_default() += 1;
+            }
+            let mut entry_mentions = entry.mentions();
+            entry_mentions.sort_unstable();
+            entry_mentions.dedup();
+            for mention in entry_mentions {
+                *mentions.entry(mention).or_default() += 1;
+            }
+
+            let entry_words = text::word_count(entry.message());
+            words += entry_words;
+            if longest.as_ref().map_or(true, |l| entry_words > l.words) {
+                longest = Some(Longest {
+                    words: entry_words,
+                    entry: Item::new(entry),
+                });
+            }
+        }
+
+        let tags = top_counts(tags, top)
+            .into_iter()
+            .map(|(tag, count)| TagCount { tag, count })
+            .collect();
+        let mentions = top_counts(mentions, top)
+            .into_iter()
+            .map(|(mention, count)| MentionCount { mention, count })
+            .collect();
+
+        Year {
+            year,
+            start: date::start_of_day(first_day(year)).to_rfc3339(),
+            end: date::start_of_day(first_day(year + 1)).to_rfc3339(),
+            count: entries.len(),
+            words,
+            day_count: days.len(),
+            busiest_day: busiest(&days),
+            busiest_week: busiest(&weeks),
+            tags,
+            mentions,
+            longest,
+            first: entries.first().map(Item::new),
+            last: entries.last().map(Item::new),
+        }
+    }
+}
+
+fn first_day(year: i32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, 1, 1).unwrap()
+}
+
+// The period with the most entries. Periods are in order, so the earliest
+// wins ties.
+fn busiest(periods: &BTreeMap<NaiveDate, usize>) -> Option<Period> {
+    periods
+        .iter()
+        .rev()
+        .max_by_key(|(_, count)| **count)
+        .map(|(start, count)| Period {
+            datetime: date::start_of_day(*start).to_rfc3339(),
+            count: *count,
+        })
+}
+
+fn top_counts(counts: HashMap<&str, usize>, top: usize) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts
+        .into_iter()
+        .map(|(name, count)| (name.to_owned(), count))
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(top);
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format;
+
+    fn entry(datetime: &str, message: &str) -> Entry {
+        let datetime = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+        Entry::new(
+            Local.from_local_datetime(&datetime).unwrap().fixed_offset(),
+            message.to_owned(),
+        )
+    }
+
+    fn year() -> Year {
+        let entries = vec![
+            entry("2024-01-02 09:00", "new year, new journal #habits"),
+            entry("2024-05-13 09:00", "standup with @sam #work"),
+            entry("2024-05-13 12:30", "lunch with @sam and @alex #work #work"),
+            entry("2024-05-15 18:00", "garden #garden"),
+            entry("2024-12-30 21:00", "one more #habits"),
+        ];
+        Year::new(2024, &entries, 2)
+    }
+
+    #[test]
+    fn test_year() {
+        let year = year();
+        assert_eq!(year.count, 5);
+        assert_eq!(year.words, 21);
+        assert_eq!(year.day_count, 4);
+        assert_eq!(
+            year.busiest_day,
+            Some(Period {
+                datetime: date::start_of_day(NaiveDate::from_ymd_opt(2024, 5, 13).unwrap())
+                    .to_rfc3339(),
+                count: 2
+            })
+        );
+        assert_eq!(year.busiest_week.as_ref().map(|w| w.count), Some(3));
+        assert_eq!(
+            year.tags
+                .iter()
+                .map(|t| (t.tag.as_str(), t.count))
+                .collect::<Vec<_>>(),
+            vec![("habits", 2), ("work", 2)]
+        );
+        assert_eq!(
+            year.mentions
+                .iter()
+                .map(|m| (m.mention.as_str(), m.count))
+                .collect::<Vec<_>>(),
+            vec![("sam", 2), ("alex", 1)]
+        );
+        assert_eq!(year.longest.as_ref().map(|l| l.words), Some(7));
+        assert!(year.start.starts_with("2024-01-01T00:00:00"));
+        assert!(year.end.starts_with("2025-01-01T00:00:00"));
+    }
+
+    #[test]
+    fn test_default_template() {
+        assert_eq!(
+            format::render(DEFAULT_TEMPLATE, &year(), false).unwrap(),
+            "\
+# 2024 in review
+
+5 entries and 21 words over 4 days.
+
+- Busiest day: Monday 13 May (2 entries)
+- Busiest week: the week of 13 May (3 entries)
+- Most written about: #habits (2), #work (2)
+- Most mentioned: @sam (2), @alex (1)
+- Longest entry: lunch with @sam and @alex #work #work (7 words, 13 May)
+
+## First entry, Tuesday 2 January 09:00
+
+new year, new journal #habits
+
+## Last entry, Monday 30 December 21:00
+
+one more #habits
+"
+        );
+
+        assert_eq!(
+            format::render(DEFAULT_TEMPLATE, &Year::new(2023, &[], 5), false).unwrap(),
+            "# 2023 in review\n\nNothing was written.\n"
+        );
+    }
+}